@@ -0,0 +1,91 @@
+//! Generates the shell completion scripts and the JSON help of the CLI in
+//! `OUT_DIR`, from the definition of the commands in `src/cli`: they're
+//! embedded in the executable instead of being generated at runtime.
+
+use std::{env, fs, io, path::PathBuf};
+
+use clap::{Arg, Command, ValueEnum};
+use clap_complete::{generate_to, Shell};
+use serde_json::{json, Value};
+
+#[path = "src/cli/command.rs"]
+mod command;
+// Only the definitions of the options are used
+#[allow(dead_code)]
+#[path = "src/cli/pulsar.rs"]
+mod pulsar;
+#[allow(dead_code)]
+#[path = "src/cli/pulsard.rs"]
+mod pulsard;
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+
+    // Read by `pulsar completions`
+    let completions = out_dir.join("completions");
+    fs::create_dir_all(&completions)?;
+    let mut cli = command::pulsar_command();
+    let name = cli.get_name().to_string();
+    for shell in Shell::value_variants() {
+        generate_to(*shell, &mut cli, &name, &completions)?;
+    }
+
+    // Read by `--help --format json`
+    let launcher = command::launcher(
+        env!("CARGO_PKG_VERSION"),
+        command::pulsard_command(),
+        command::pulsar_command(),
+    );
+    fs::write(
+        out_dir.join("help.json"),
+        command_json(&launcher).to_string(),
+    )
+}
+
+/// Description of a command and its subcommands, printed by
+/// `--help --format json`.
+fn command_json(command: &Command) -> Value {
+    json!({
+        "name": command.get_name(),
+        "about": command.get_about().map(ToString::to_string),
+        "args": command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .map(arg_json)
+            .collect::<Vec<_>>(),
+        "subcommands": command
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(command_json)
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn arg_json(arg: &Arg) -> Value {
+    let takes_value = arg.get_action().takes_values();
+    // Flags accept only true and false
+    let possible_values: Vec<String> = if takes_value {
+        arg.get_possible_values()
+            .iter()
+            .map(|value| value.get_name().to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    json!({
+        "name": arg.get_id().as_str(),
+        "long": arg.get_long(),
+        "short": arg.get_short().map(String::from),
+        "help": arg.get_help().map(ToString::to_string),
+        "positional": arg.is_positional(),
+        "required": arg.is_required_set(),
+        "takes_value": takes_value,
+        "default": arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect::<Vec<_>>(),
+        "possible_values": possible_values,
+    })
+}
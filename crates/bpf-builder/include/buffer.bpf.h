@@ -89,6 +89,33 @@ static void buffer_append_str(struct buffer *buffer, struct buffer_index *index,
   buffer->len += r - 1;
 }
 
+// Like buffer_append_str, with source pointing to user memory.
+static void buffer_append_user_str(struct buffer *buffer,
+                                   struct buffer_index *index,
+                                   const char *source, int len) {
+  int pos = (index->start + index->len);
+  if (pos >= HALF_BUFFER_MASK) {
+    LOG_ERROR("trying to write over half: %d+%d", index->start, index->len);
+    return;
+  }
+  if (len > HALF_BUFFER_MASK) {
+    len = HALF_BUFFER_MASK;
+  } else {
+    // include space for terminating 0
+    len = (len + 1) & HALF_BUFFER_MASK;
+  }
+
+  int r = bpf_probe_read_user_str(&((char *)buffer->buffer)[pos], len, source);
+  if (r <= 0) {
+    LOG_ERROR("reading failure: %d", r);
+    return;
+  }
+
+  // Update counters, ignoring the final 0.
+  index->len += r - 1;
+  buffer->len += r - 1;
+}
+
 // Copy up to len bytes from source to the buffer pointed by index.
 // On success, update index and buffer length.
 // Source must point to user memory.
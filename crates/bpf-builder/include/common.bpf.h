@@ -30,11 +30,12 @@ const volatile int LINUX_KERNEL_VERSION;
   })
 
 // --------------- PULSAR_LSM_HOOK MACRO DEFINITION --------------
-// This macro makes it easy to hook into LSM attach points, keeping a kprobe
-// fallback.
-// PULSAR_LSM_HOOK(hook_point, args) will attach to `lsm/<hook_point>` and
-// `kprobe/security_<hook_point>`. It calls function `on_<hook_point>`, which
-// must be defined by the user and accept the specified args args.
+// This macro makes it easy to hook into LSM attach points, keeping fentry and
+// kprobe fallbacks.
+// PULSAR_LSM_HOOK(hook_point, args) will attach to `lsm/<hook_point>`,
+// `fentry/security_<hook_point>` and `kprobe/security_<hook_point>`. It calls
+// function `on_<hook_point>`, which must be defined by the user and accept the
+// specified args args.
 //
 // Example:
 // PULSAR_LSM_HOOK(file_open, struct file *, file);
@@ -47,6 +48,12 @@ const volatile int LINUX_KERNEL_VERSION;
 //   return ret;
 // }
 //
+// SEC("fentry/security_file_open")
+// int BPF_PROG(fentry_security_file_open, struct file *file) {
+//   on_file_open(ctx, file);
+//   return 0;
+// }
+//
 // SEC("kprobe/security_file_open")
 // int BPF_KPROBE(security_file_open, struct file *file) {
 //   on_file_open(ctx, file);
@@ -77,6 +84,12 @@ const volatile int LINUX_KERNEL_VERSION;
     return ret;                                                                \
   }                                                                            \
                                                                                \
+  SEC("fentry/security_" #hook_point)                                          \
+  int BPF_PROG(fentry_security_##hook_point, TYPED_ARGS(args)) {               \
+    on_##hook_point(ctx, UNTYPED_ARGS(args));                                  \
+    return 0;                                                                  \
+  }                                                                            \
+                                                                               \
   SEC("kprobe/security_" #hook_point)                                          \
   int BPF_KPROBE(security_##hook_point, TYPED_ARGS(args)) {                    \
     on_##hook_point(ctx, UNTYPED_ARGS(args));                                  \
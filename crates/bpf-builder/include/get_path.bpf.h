@@ -63,11 +63,26 @@ struct {
   __uint(max_entries, 1);
 } components_map SEC(".maps");
 
+// Identifies the first ancestor which is missing from a truncated path.
+// Paths are truncated when they have more components than the loop iterations
+// available, or when the walk can't reach the global root, for example when
+// the mount point is unknown. Userspace can rebuild the full path by joining
+// the ancestor path, if known, with the truncated one.
+struct path_truncation {
+  bool truncated;
+  // Device of the ancestor super block, in the kernel dev_t encoding
+  u32 dev;
+  u64 ino;
+};
+
 struct ctx_get_path {
   // Output of get_path_str
   struct buffer *buffer;
   struct buffer_index *index;
 
+  // Set when loop_get_dentry_name reaches the global root
+  bool complete;
+
   // Current dentry and vfsmount being iterated by loop_get_dentry_name.
   struct path current;
 
@@ -108,6 +123,7 @@ static __always_inline long loop_get_dentry_name(u32 i, void *callback_ctx) {
     struct mount *parent_mount = BPF_CORE_READ(current_mount, mnt_parent);
     if (current_mount == parent_mount) {
       // Global root - path fully parsed
+      c->complete = true;
       return LOOP_STOP;
     } else {
       // We reached root, but not global root - continue with mount point.
@@ -148,8 +164,11 @@ static __always_inline long loop_append_path_component(u32 i,
 }
 
 // Copy to buffer/index the path of the file pointed by dentry/path.
-static void get_path_str(struct path *path, struct buffer *buffer,
-                         struct buffer_index *index) {
+// If truncation is not NULL, it's filled with the first ancestor missing from
+// the path.
+static void get_path_str_truncation(struct path *path, struct buffer *buffer,
+                                    struct buffer_index *index,
+                                    struct path_truncation *truncation) {
 
   u32 key = 0;
   struct ctx_get_path c;
@@ -164,7 +183,23 @@ static void get_path_str(struct path *path, struct buffer *buffer,
   c.buffer = buffer;
   c.current.dentry = path->dentry;
   c.current.mnt = path->mnt;
+  c.complete = false;
   buffer_index_init(buffer, index);
   LOOP(MAX_PATH_COMPONENTS, MAX_PATH_UNROLL, loop_get_dentry_name, &c);
   LOOP(MAX_PATH_COMPONENTS, MAX_PATH_UNROLL, loop_append_path_component, &c);
+  if (truncation) {
+    truncation->truncated = !c.complete;
+    if (!c.complete) {
+      // The current dentry is the first one not added to the path
+      struct dentry *ancestor = c.current.dentry;
+      truncation->dev = BPF_CORE_READ(ancestor, d_sb, s_dev);
+      truncation->ino = BPF_CORE_READ(ancestor, d_inode, i_ino);
+    }
+  }
+}
+
+// Copy to buffer/index the path of the file pointed by dentry/path.
+static void get_path_str(struct path *path, struct buffer *buffer,
+                         struct buffer_index *index) {
+  get_path_str_truncation(path, buffer, index, NULL);
 }
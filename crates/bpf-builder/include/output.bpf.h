@@ -101,6 +101,12 @@
     return event;                                                              \
   }                                                                            \
                                                                                \
+  /* Release an event obtained with init without emitting it */                \
+  static __always_inline void discard_##struct_name(                           \
+      struct struct_name *event) {                                             \
+    decrease_nesting_##struct_name();                                          \
+  }                                                                            \
+                                                                               \
   /* Output map definition */                                                  \
   struct {                                                                     \
     __uint(type, BPF_MAP_TYPE_PERF_EVENT_ARRAY);                               \
@@ -112,7 +118,7 @@
   static __always_inline void output_##struct_name(                            \
       void *ctx, struct struct_name *event) {                                  \
     decrease_nesting_##struct_name();                                          \
-    if (is_initialized()) {                                                    \
+    if (is_initialized() && !is_muted(event->pid, event->event_type)) {        \
       if (event->buffer.len >= BUFFER_MAX) {                                   \
         LOG_ERROR("invalid buffer.len = %d, skipping event",                   \
                   event->buffer.len);                                          \
@@ -149,6 +155,32 @@ static __always_inline int is_initialized() {
   return initialization_status && *initialization_status;
 }
 
+// Events of a process and type muted by the userspace storm control, because
+// they exceeded the rate threshold. The value is the monotonic time at which
+// the mute expires. See bpf_common::storm.
+struct storm_key {
+  pid_t pid;
+  u32 event_type;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, struct storm_key);
+  __type(value, u64);
+  __uint(max_entries, 1024);
+} map_storm_mute SEC(".maps");
+
+static __always_inline int is_muted(pid_t pid, u32 event_type) {
+  struct storm_key key = {.pid = pid, .event_type = event_type};
+  u64 *until = bpf_map_lookup_elem(&map_storm_mute, &key);
+  if (!until)
+    return 0;
+  if (bpf_ktime_get_ns() < *until)
+    return 1;
+  bpf_map_delete_elem(&map_storm_mute, &key);
+  return 0;
+}
+
 // Get a value and increment it by one
 static __always_inline u64 sync_increment(u64 *value) {
 #ifdef FEATURE_ATOMICS
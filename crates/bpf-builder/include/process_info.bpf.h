@@ -0,0 +1,120 @@
+/* SPDX-License-Identifier: GPL-2.0-only */
+#pragma once
+
+#include "common.bpf.h"
+#include "interest_tracking.bpf.h"
+#include "vmlinux.h"
+
+// This header-only library gives access to a shared map with the metadata of
+// every process on the system, so that probes don't need to keep their own
+// per-process state.
+//
+// The map is pinned, so every probe declaring it with
+// PROCESS_INFO_MAP_DECLARATION uses the same instance. It's written only by
+// process-monitor, on fork, exec and exit, and initialized by userspace from
+// procfs on startup. Other probes should only read it with process_info_get.
+//
+// Userspace resizes it after the host limits with
+// bpf_filtering::maps::process_info_max_entries, and it's not preallocated.
+
+// The process was started from a terminal
+#define PROCESS_INFO_TTY 1
+
+struct process_info {
+  // Parent process id
+  pid_t ppid;
+  // Mount and pid namespace inodes
+  u32 mnt_ns;
+  u32 pid_ns;
+  // Bitmap of PROCESS_INFO_ flags
+  u32 flags;
+  // Executable path, truncated to MAX_IMAGE_LEN
+  char image[MAX_IMAGE_LEN];
+};
+
+#define PROCESS_INFO_MAP m_process_info
+#define PROCESS_INFO_MAP_DECLARATION                                           \
+  struct bpf_map_def_aya SEC("maps/m_process_info") PROCESS_INFO_MAP = {       \
+      .type = BPF_MAP_TYPE_HASH,                                               \
+      .key_size = sizeof(pid_t),                                               \
+      .value_size = sizeof(struct process_info),                               \
+      .max_entries = 16384,                                                    \
+      .map_flags = BPF_F_NO_PREALLOC,                                          \
+      .pinning = PINNING_ENABLED,                                              \
+  };
+
+// Return the metadata of a process, NULL if it's unknown.
+static __always_inline struct process_info *process_info_get(pid_t tgid) {
+  return bpf_map_lookup_elem(&PROCESS_INFO_MAP, &tgid);
+}
+
+// Inode of the pid namespace the task runs in, the one of /proc/<pid>/ns/pid.
+// nsproxy->pid_ns_for_children is the namespace of its future children
+// instead, which differs after unshare(CLONE_NEWPID).
+static __always_inline u32 task_active_pid_ns(struct task_struct *task) {
+  struct pid *pid = BPF_CORE_READ(task, thread_pid);
+  unsigned int level = BPF_CORE_READ(pid, level);
+  return BPF_CORE_READ(pid, numbers[level].ns, ns.inum);
+}
+
+static __always_inline void process_info_set_task(struct process_info *info,
+                                                  struct task_struct *task) {
+  info->mnt_ns = BPF_CORE_READ(task, nsproxy, mnt_ns, ns.inum);
+  info->pid_ns = task_active_pid_ns(task);
+  if (BPF_CORE_READ(task, signal, tty) != NULL) {
+    info->flags |= PROCESS_INFO_TTY;
+  } else {
+    info->flags &= ~PROCESS_INFO_TTY;
+  }
+}
+
+// A new process inherits the image of its parent.
+static __always_inline void process_info_fork(struct task_struct *parent,
+                                              struct task_struct *child) {
+  pid_t parent_tgid = BPF_CORE_READ(parent, tgid);
+  pid_t child_tgid = BPF_CORE_READ(child, tgid);
+  struct process_info info;
+  __builtin_memset(&info, 0, sizeof(info));
+  struct process_info *parent_info = process_info_get(parent_tgid);
+  if (parent_info) {
+    __builtin_memcpy(info.image, parent_info->image, MAX_IMAGE_LEN);
+  }
+  info.ppid = parent_tgid;
+  process_info_set_task(&info, child);
+  long res = bpf_map_update_elem(&PROCESS_INFO_MAP, &child_tgid, &info, BPF_ANY);
+  if (res != 0) {
+    LOG_ERROR("error adding process info for %d (%d)", child_tgid, res);
+  }
+}
+
+static __always_inline void process_info_exec(struct task_struct *p,
+                                              const char *image) {
+  pid_t tgid = BPF_CORE_READ(p, tgid);
+  struct process_info info;
+  __builtin_memset(&info, 0, sizeof(info));
+  struct process_info *old_info = process_info_get(tgid);
+  if (old_info) {
+    info.ppid = old_info->ppid;
+    info.flags = old_info->flags;
+  } else {
+    info.ppid = BPF_CORE_READ(p, real_parent, tgid);
+  }
+  bpf_probe_read_kernel_str(info.image, MAX_IMAGE_LEN, image);
+  process_info_set_task(&info, p);
+  long res = bpf_map_update_elem(&PROCESS_INFO_MAP, &tgid, &info, BPF_ANY);
+  if (res != 0) {
+    LOG_ERROR("error updating process info for %d (%d)", tgid, res);
+  }
+}
+
+static __always_inline void process_info_change_parent(pid_t tgid,
+                                                       pid_t ppid) {
+  struct process_info *info = process_info_get(tgid);
+  if (info) {
+    info->ppid = ppid;
+  }
+}
+
+static __always_inline void process_info_exit(pid_t tgid) {
+  bpf_map_delete_elem(&PROCESS_INFO_MAP, &tgid);
+}
@@ -3,7 +3,7 @@ use std::{env, path::PathBuf, process::Command, string::String};
 use anyhow::{bail, Context};
 
 static CLANG_DEFAULT: &str = "clang";
-static LLVM_STRIP: &str = "llvm-strip";
+static LLVM_STRIP_DEFAULT: &str = "llvm-strip";
 static INCLUDE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/include");
 
 // Given a probe name and the eBPF program source code path, compile it to OUT_DIR.
@@ -11,6 +11,10 @@ static INCLUDE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/include");
 // - `${OUT_DIR}/{name}.5_13.bpf.o`: will contain the full version
 // - `${OUT_DIR}/{name}.5_5.bpf.o`: will contain a version with the FEATURE_5_5 constant
 //   defined. This version should be loaded on kernel < 5.13, see ../include/compatibility.bpf.h
+//
+// The programs are built for the cargo target, so cross-compiling the agent
+// builds the probes of the target architecture. See the README for the
+// environment variables overriding the tools and headers used.
 pub fn build(name: &str, source: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed={source}");
     println!("cargo:rerun-if-changed={INCLUDE_PATH}/common.bpf.h");
@@ -21,59 +25,133 @@ pub fn build(name: &str, source: &str) -> Result<(), Box<dyn std::error::Error>>
     println!("cargo:rerun-if-changed={INCLUDE_PATH}/get_path.bpf.h");
     println!("cargo:rerun-if-changed={INCLUDE_PATH}/compatibility.bpf.h");
 
+    let target = Target::from_env()?;
+    println!(
+        "cargo:rerun-if-changed={}",
+        target.vmlinux_dir.join("vmlinux.h").display()
+    );
+
     let out_file = PathBuf::from(env::var("OUT_DIR")?).join(name);
 
     compile(
+        &target,
         source,
         out_file.with_extension("5_13.bpf.o"),
         &["-DVERSION_5_13"],
     )
     .context("Error compiling 5.13 version")?;
-    compile(source, out_file.with_extension("5_5.bpf.o"), &[])
+    compile(&target, source, out_file.with_extension("5_5.bpf.o"), &[])
         .context("Error compiling 5.5 version")?;
 
     Ok(())
 }
 
-fn compile(probe: &str, out_object: PathBuf, extra_args: &[&str]) -> anyhow::Result<()> {
-    let clang = env::var("CLANG").unwrap_or_else(|_| String::from(CLANG_DEFAULT));
-    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+/// Toolchain and headers used to build the probes of a cargo target.
+#[derive(Debug)]
+struct Target {
+    /// Clang target with the byte order of the target, `bpfel` or `bpfeb`
+    clang_target: &'static str,
+    /// Architecture name used by `bpf_tracing.h`, like `arm64`
+    bpf_arch: &'static str,
+    /// Directory with the `vmlinux.h` of the architecture
+    vmlinux_dir: PathBuf,
+    clang: String,
+    llvm_strip: String,
+    sysroot: Option<PathBuf>,
+}
+
+impl Target {
+    /// Target of the build script, from the variables set by cargo.
+    fn from_env() -> anyhow::Result<Self> {
+        let triple = env::var("TARGET").context("TARGET not set")?;
+        let arch = env::var("CARGO_CFG_TARGET_ARCH").context("CARGO_CFG_TARGET_ARCH not set")?;
+        let endian =
+            env::var("CARGO_CFG_TARGET_ENDIAN").context("CARGO_CFG_TARGET_ENDIAN not set")?;
+        let var = |name: &str| target_var(name, &triple);
+        Ok(Self {
+            clang_target: match endian.as_str() {
+                "big" => "bpfeb",
+                _ => "bpfel",
+            },
+            bpf_arch: bpf_arch(&arch)?,
+            vmlinux_dir: var("BPF_VMLINUX_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(INCLUDE_PATH).join(&arch)),
+            clang: var("CLANG").unwrap_or_else(|| String::from(CLANG_DEFAULT)),
+            llvm_strip: var("LLVM_STRIP").unwrap_or_else(|| String::from(LLVM_STRIP_DEFAULT)),
+            sysroot: var("BPF_SYSROOT").map(PathBuf::from),
+        })
+    }
+}
+
+/// Architecture name of `bpf_tracing.h` for a cargo target architecture.
+fn bpf_arch(arch: &str) -> anyhow::Result<&'static str> {
+    Ok(match arch {
+        "x86_64" => "x86",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        "riscv64" => "riscv",
+        _ => bail!("eBPF probes are not supported on {arch}"),
+    })
+}
+
+/// Value of the environment variable `name`, preferring the one specific to
+/// the target triple, like `BPF_SYSROOT_aarch64_unknown_linux_gnu`.
+fn target_var(name: &str, triple: &str) -> Option<String> {
+    let names = [
+        format!("{name}_{triple}"),
+        format!("{name}_{}", triple.replace('-', "_")),
+        name.to_string(),
+    ];
+    let mut value = None;
+    for name in names {
+        println!("cargo:rerun-if-env-changed={name}");
+        if value.is_none() {
+            value = env::var(name).ok();
+        }
+    }
+    value
+}
+
+fn compile(
+    target: &Target,
+    probe: &str,
+    out_object: PathBuf,
+    extra_args: &[&str],
+) -> anyhow::Result<()> {
     let include_path = PathBuf::from(INCLUDE_PATH);
-    let status = Command::new(clang)
+    let mut command = Command::new(&target.clang);
+    command
         .arg(format!("-I{}", include_path.to_string_lossy()))
-        .arg(format!("-I{}", include_path.join(&arch).to_string_lossy()))
+        .arg(format!("-I{}", target.vmlinux_dir.to_string_lossy()))
         .arg("-g")
         .arg("-O2")
-        .args(["-target", "bpf"])
+        .args(["-target", target.clang_target])
         .arg("-c")
         .arg("-Werror")
         .arg("-fno-stack-protector")
-        .arg(format!(
-            "-D__TARGET_ARCH_{}",
-            match arch.as_str() {
-                "x86_64" => "x86".to_string(),
-                "aarch64" => "arm64".to_string(),
-                "riscv64" => "riscv".to_string(),
-                _ => arch.clone(),
-            }
-        ))
+        .arg(format!("-D__TARGET_ARCH_{}", target.bpf_arch));
+    if let Some(sysroot) = &target.sysroot {
+        command.arg(format!("--sysroot={}", sysroot.to_string_lossy()));
+    }
+    let status = command
         .args(extra_args)
         .arg(probe)
         .arg("-o")
         .arg(&out_object)
         .status()
-        .context("Failed to execute clang")?;
+        .with_context(|| format!("Failed to execute {}", target.clang))?;
 
     if !status.success() {
         bail!("Failed to compile eBPF program");
     }
 
     // Strip debug symbols
-    let status = Command::new(LLVM_STRIP)
+    let status = Command::new(&target.llvm_strip)
         .arg("-g")
         .arg(out_object)
         .status()
-        .context("Failed to execute llvm-strip")?;
+        .with_context(|| format!("Failed to execute {}", target.llvm_strip))?;
 
     if !status.success() {
         bail!("Failed strip eBPF program");
@@ -81,3 +159,37 @@ fn compile(probe: &str, out_object: PathBuf, extra_args: &[&str]) -> anyhow::Res
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn architectures() {
+        assert_eq!(bpf_arch("x86_64").unwrap(), "x86");
+        assert_eq!(bpf_arch("aarch64").unwrap(), "arm64");
+        assert_eq!(bpf_arch("riscv64").unwrap(), "riscv");
+        assert!(bpf_arch("mips").is_err());
+    }
+
+    #[test]
+    fn target_variables() {
+        env::set_var("BPF_TEST_SYSROOT", "/");
+        assert_eq!(
+            target_var("BPF_TEST_SYSROOT", "aarch64-unknown-linux-gnu").as_deref(),
+            Some("/")
+        );
+        env::set_var(
+            "BPF_TEST_SYSROOT_aarch64_unknown_linux_gnu",
+            "/usr/aarch64-linux-gnu",
+        );
+        assert_eq!(
+            target_var("BPF_TEST_SYSROOT", "aarch64-unknown-linux-gnu").as_deref(),
+            Some("/usr/aarch64-linux-gnu")
+        );
+        assert_eq!(
+            target_var("BPF_TEST_SYSROOT", "riscv64gc-unknown-linux-gnu").as_deref(),
+            Some("/")
+        );
+    }
+}
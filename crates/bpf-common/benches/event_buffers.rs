@@ -0,0 +1,94 @@
+//! Copy the data of 1M events out of the perf buffers, allocating a buffer per
+//! event like `Program::read_events` used to do, and with a `BufferArena`.
+//!
+//! The number of allocations of both strategies is printed before running
+//! the benchmarks.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::VecDeque,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bpf_common::parsing::BufferArena;
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const EVENTS: usize = 1_000_000;
+/// Size of the buffers read from the perf event array: the event, the perf
+/// header and the maximum size of its dynamic data.
+const BUFFER_SIZE: usize = 128 + 4 + 16384;
+/// Events kept alive at the same time, like the ones waiting in the bus.
+const IN_FLIGHT: usize = 1000;
+
+fn buffer_per_event(data: &[u8]) -> Bytes {
+    let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
+    buffer.extend_from_slice(data);
+    buffer.freeze()
+}
+
+fn copy_events(payloads: &[Vec<u8>], mut copy: impl FnMut(&[u8]) -> Bytes) {
+    let mut in_flight = VecDeque::with_capacity(IN_FLIGHT);
+    for i in 0..EVENTS {
+        if in_flight.len() == IN_FLIGHT {
+            in_flight.pop_front();
+        }
+        in_flight.push_back(copy(black_box(&payloads[i % payloads.len()])));
+    }
+}
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn event_buffers(c: &mut Criterion) {
+    // Most events have no dynamic data or just a path, few carry big messages
+    let payloads: Vec<Vec<u8>> = [0, 0, 32, 64, 128, 256, 4096]
+        .into_iter()
+        .map(|len| vec![0xab; len])
+        .collect();
+
+    let per_event = allocations(|| copy_events(&payloads, buffer_per_event));
+    let arena = allocations(|| {
+        let mut arena = BufferArena::default();
+        copy_events(&payloads, |data| arena.copy(data))
+    });
+    println!("allocations per {EVENTS} events: buffer per event {per_event}, arena {arena}");
+
+    let mut group = c.benchmark_group("event_buffers");
+    group.throughput(Throughput::Elements(EVENTS as u64));
+    group.sample_size(10);
+    group.bench_function("buffer_per_event", |b| {
+        b.iter(|| copy_events(&payloads, buffer_per_event))
+    });
+    group.bench_function("arena", |b| {
+        b.iter(|| {
+            let mut arena = BufferArena::default();
+            copy_events(&payloads, |data| arena.copy(data))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, event_buffers);
+criterion_main!(benches);
@@ -1,3 +1,4 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    bpf_builder::build("test_lsm", "src/feature_autodetect/test_lsm.bpf.c")
+    bpf_builder::build("test_lsm", "src/feature_autodetect/test_lsm.bpf.c")?;
+    bpf_builder::build("test_fentry", "src/feature_autodetect/test_fentry.bpf.c")
 }
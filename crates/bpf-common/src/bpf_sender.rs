@@ -4,11 +4,23 @@
 
 use tokio::sync::mpsc;
 
-use crate::{program::BpfEvent, ProgramError};
+use crate::{program::BpfEvent, storm::EventStorm, ProgramError};
 
 pub trait BpfSender<T>: Clone + Send + 'static {
     /// Must not block since it can be used in async contexts
     fn send(&mut self, data: Result<BpfEvent<T>, ProgramError>);
+
+    /// Send the event which made its process and type exceed the storm
+    /// control threshold, see [`crate::storm`].
+    fn send_storm(&mut self, event: BpfEvent<T>, storm: EventStorm) {
+        log::warn!(
+            "Event storm of pid {} type {}: muted for {:?}",
+            storm.pid,
+            storm.event_type,
+            storm.muted_for
+        );
+        self.send(Ok(event))
+    }
 }
 
 /// Simple implementation for tokio::mpsc bounded channels.
@@ -21,9 +33,24 @@ impl<T: 'static + Send> BpfSender<T> for mpsc::Sender<Result<BpfEvent<T>, Progra
     }
 }
 
+/// Implementation for tokio::mpsc unbounded channels, which never drops
+/// messages: for low rate events which must all be handled.
+impl<T: 'static + Send> BpfSender<T> for mpsc::UnboundedSender<Result<BpfEvent<T>, ProgramError>> {
+    fn send(&mut self, data: Result<BpfEvent<T>, ProgramError>) {
+        if mpsc::UnboundedSender::send(self, data).is_err() {
+            log::warn!("dropping msg, receiver closed");
+        }
+    }
+}
+
 /// BpfSenderWrapper wraps a BpfSender with a new one which calls
 /// a callback on every event generated. This is useful for modules
-/// which want to take some actions when sending events.
+/// which want to take some actions when sending events. Expensive
+/// processing should be moved to a [`crate::worker_pool::WorkerPool`].
+///
+/// The callback sees the eBPF event before it's converted and sent on the
+/// bus, unlike the middlewares of the bus: the process-monitor uses it to
+/// update the process tracker, which the events of a process wait for.
 #[derive(Clone)]
 pub struct BpfSenderWrapper<S, F> {
     cb: F,
@@ -47,4 +74,9 @@ where
         }
         self.inner.send(data)
     }
+
+    fn send_storm(&mut self, event: BpfEvent<E>, storm: EventStorm) {
+        (self.cb)(&event);
+        self.inner.send_storm(event, storm)
+    }
 }
@@ -0,0 +1,127 @@
+//! Explain why eBPF programs fail to load, pointing at the kernel
+//! configuration most likely missing.
+
+use std::{io, path::Path};
+
+const BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+const LSM_PATH: &str = "/sys/kernel/security/lsm";
+
+/// Error of the kernel for operations not supported, like BPF trampolines
+/// on some architectures. Not in libc, since it's never seen by userspace
+/// except from the bpf syscall.
+const ENOTSUPP: i32 = 524;
+
+/// Kind of eBPF program, as far as the hints are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramKind {
+    TracePoint,
+    RawTracePoint,
+    Kprobe,
+    Lsm,
+    /// fentry and fexit
+    Tracing,
+}
+
+/// Kernel features the probes depend on, checked without loading anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelFeatures {
+    /// The kernel exposes its BTF in `/sys/kernel/btf/vmlinux`
+    pub btf: bool,
+    /// `bpf` is one of the active LSMs in `/sys/kernel/security/lsm`
+    pub bpf_lsm: bool,
+}
+
+impl KernelFeatures {
+    pub fn autodetect() -> Self {
+        Self {
+            btf: Path::new(BTF_PATH).exists(),
+            bpf_lsm: std::fs::read_to_string(LSM_PATH)
+                .map(|lsms| lsms.trim().split(',').any(|lsm| lsm == "bpf"))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Likely cause of the failed load of a program, from the error of the
+/// `BPF_PROG_LOAD` syscall and the output of the verifier.
+pub fn load_hint(
+    kind: ProgramKind,
+    io_error: Option<&io::Error>,
+    verifier_log: &str,
+    features: &KernelFeatures,
+) -> Option<&'static str> {
+    if !features.btf {
+        return Some("the kernel exposes no BTF, it must be built with CONFIG_DEBUG_INFO_BTF=y");
+    }
+    if kind == ProgramKind::Lsm && !features.bpf_lsm {
+        return Some(
+            "eBPF LSM programs need CONFIG_BPF_LSM=y and `bpf` in the `lsm=` boot parameter",
+        );
+    }
+    if verifier_log.contains("unknown func") || verifier_log.contains("invalid func") {
+        return Some("the program uses an eBPF helper missing from this kernel, it may be too old");
+    }
+    if verifier_log.contains("too large") || verifier_log.contains("too complex") {
+        return Some("the program exceeds the complexity limit of the verifier of this kernel");
+    }
+    match io_error.and_then(io::Error::raw_os_error) {
+        Some(libc::EPERM) | Some(libc::EACCES) => {
+            Some("missing CAP_BPF and CAP_PERFMON, or CAP_SYS_ADMIN on kernels before 5.8")
+        }
+        Some(libc::EINVAL) if kind == ProgramKind::Kprobe => {
+            Some("kprobes need a kernel built with CONFIG_KPROBES=y and CONFIG_BPF_EVENTS=y")
+        }
+        Some(libc::EINVAL) | Some(ENOTSUPP) if kind == ProgramKind::Tracing => {
+            Some("fentry and fexit need CONFIG_FUNCTION_TRACER and Linux 5.5, or 6.0 on aarch64")
+        }
+        Some(libc::EINVAL) if kind == ProgramKind::RawTracePoint => {
+            Some("raw tracepoints need Linux 4.17 or later")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEATURES: KernelFeatures = KernelFeatures {
+        btf: true,
+        bpf_lsm: true,
+    };
+
+    #[test]
+    fn hints() {
+        let no_lsm = KernelFeatures {
+            bpf_lsm: false,
+            ..FEATURES
+        };
+        assert!(load_hint(ProgramKind::Lsm, None, "", &no_lsm)
+            .unwrap()
+            .contains("CONFIG_BPF_LSM"));
+        assert_eq!(load_hint(ProgramKind::Kprobe, None, "", &no_lsm), None);
+
+        let log = "0: R1=ctx(off=0,imm=0) R10=fp0\n0: (85) call bpf_loop#181\nunknown func bpf_loop#181\n";
+        assert!(load_hint(ProgramKind::TracePoint, None, log, &FEATURES)
+            .unwrap()
+            .contains("helper"));
+
+        let eperm = io::Error::from_raw_os_error(libc::EPERM);
+        assert!(
+            load_hint(ProgramKind::TracePoint, Some(&eperm), "", &FEATURES)
+                .unwrap()
+                .contains("CAP_BPF")
+        );
+        let einval = io::Error::from_raw_os_error(libc::EINVAL);
+        assert_eq!(
+            load_hint(ProgramKind::TracePoint, Some(&einval), "", &FEATURES),
+            None
+        );
+        let enotsupp = io::Error::from_raw_os_error(ENOTSUPP);
+        assert!(
+            load_hint(ProgramKind::Tracing, Some(&enotsupp), "", &FEATURES)
+                .unwrap()
+                .contains("aarch64")
+        );
+    }
+}
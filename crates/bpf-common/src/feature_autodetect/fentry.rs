@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use aya::{include_bytes_aligned, programs::FEntry, BpfLoader, Btf};
+
+/// Check if the system supports fentry and fexit programs, which attach to
+/// kernel functions through BPF trampolines with a lower overhead than
+/// kprobes. They're available since 5.5 on x86_64 and 6.0 on aarch64, on
+/// kernels built with CONFIG_DEBUG_INFO_BTF and CONFIG_FUNCTION_TRACER.
+///
+/// We try to load and attach a test program to `tcp_set_state`.
+///
+/// NOTE: this function is blocking.
+pub fn fentry_supported() -> bool {
+    match try_load() {
+        Ok(()) => true,
+        Err(err) => {
+            if log::log_enabled!(log::Level::Debug) {
+                log::warn!("fentry not supported: {err:?}");
+            } else {
+                log::warn!("fentry not supported: {err}");
+            }
+
+            false
+        }
+    }
+}
+
+static TEST_FENTRY_PROBE: &[u8] =
+    include_bytes_aligned!(concat!(env!("OUT_DIR"), "/test_fentry.5_13.bpf.o"));
+
+fn try_load() -> Result<()> {
+    let btf = Btf::from_sys_fs().context("Loading Btf failed")?;
+    let mut bpf = BpfLoader::new()
+        .btf(Some(&btf))
+        .load(TEST_FENTRY_PROBE)
+        .context("Initial loading failed")?;
+    let program: &mut FEntry = bpf
+        .program_mut("fentry_tcp_set_state")
+        .context("fentry program not found")?
+        .try_into()
+        .context("fentry program of the wrong type")?;
+    program.load("tcp_set_state", &btf).context("Load failed")?;
+    program.attach().context("Attach failed")?;
+    Ok(())
+}
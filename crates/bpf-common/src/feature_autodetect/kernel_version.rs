@@ -1,7 +1,7 @@
 //! Extract kernel version from the currently running system.
 //! Code ported from libbpf.
 
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use anyhow::{Context, Result};
 use nix::fcntl::AtFlags;
@@ -88,6 +88,12 @@ impl KernelVersion {
     }
 }
 
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 fn parse_u32_skipping_suffix(input: &str) -> Result<u32, <u32 as FromStr>::Err> {
     let i = input.find(|c: char| !c.is_numeric()).unwrap_or(input.len());
     input[..i].parse::<u32>()
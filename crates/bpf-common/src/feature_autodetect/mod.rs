@@ -1,4 +1,6 @@
 //! This module checks what features are supported by the runnig kernel
+pub mod diagnostics;
+pub mod fentry;
 pub mod kernel_version;
 pub mod lsm;
 
@@ -6,12 +8,12 @@ pub mod lsm;
 pub mod test_suite {
     use crate::test_runner::{TestCase, TestReport, TestSuite};
 
-    use super::lsm::lsm_supported;
+    use super::{fentry::fentry_supported, lsm::lsm_supported};
 
     pub fn tests() -> TestSuite {
         TestSuite {
             name: "feature_autodetect",
-            tests: vec![lsm()],
+            tests: vec![lsm(), fentry()],
         }
     }
 
@@ -23,4 +25,13 @@ pub mod test_suite {
             }
         })
     }
+
+    fn fentry() -> TestCase {
+        TestCase::new("fentry", async {
+            TestReport {
+                success: tokio::task::spawn_blocking(fentry_supported).await.unwrap(),
+                lines: vec![],
+            }
+        })
+    }
 }
@@ -0,0 +1,186 @@
+//! Recorded events, used to test the userspace side of the modules without
+//! root privileges or eBPF support.
+//!
+//! When [`RECORD_ENV`] is set to a directory, every program appends the records
+//! read from its perf buffer to `<directory>/<program>.fixture`. A record is
+//! the raw event followed by the data of its buffer, hex encoded on a single
+//! line. Empty lines and lines starting with `#` are ignored, so fixtures can
+//! be annotated.
+//!
+//! The modules load them back with [`load`] in their integration tests, which
+//! run with a plain `cargo test`. Records are only valid for the event type
+//! of the probes which wrote them: fixtures must be recorded again when the
+//! layout of the event changes.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::program::{BpfEvent, RawBpfEvent};
+
+/// Environment variable with the directory where the events are recorded.
+pub const RECORD_ENV: &str = "PULSAR_RECORD_FIXTURES";
+
+const EXTENSION: &str = "fixture";
+
+#[derive(Error, Debug)]
+pub enum FixtureError {
+    #[error("error reading fixture {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+    #[error("line {line}: invalid hex encoding")]
+    Hex {
+        line: usize,
+        #[source]
+        error: hex::FromHexError,
+    },
+    #[error(
+        "line {line}: record of {len} bytes doesn't match the event layout ({expected} bytes)"
+    )]
+    Layout {
+        line: usize,
+        len: usize,
+        expected: usize,
+    },
+}
+
+/// Load the events of a fixture file.
+pub fn load<P>(path: impl AsRef<Path>) -> Result<Vec<BpfEvent<P>>, FixtureError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(|error| FixtureError::Read {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    parse(&content)
+}
+
+/// Parse the events of a fixture. Every record must have the size of the
+/// event, followed by the data of its buffer.
+pub fn parse<P>(content: &str) -> Result<Vec<BpfEvent<P>>, FixtureError> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, record)| {
+            let record = hex::decode(record).map_err(|error| FixtureError::Hex { line, error })?;
+            let layout_error = |expected| FixtureError::Layout {
+                line,
+                len: record.len(),
+                expected,
+            };
+            let (raw, data) = RawBpfEvent::<P>::parse(&record)
+                .ok_or_else(|| layout_error(RawBpfEvent::<P>::SIZE))?;
+            if RawBpfEvent::<P>::SIZE + data.len() != record.len() {
+                return Err(layout_error(RawBpfEvent::<P>::SIZE + data.len()));
+            }
+            let data = Bytes::copy_from_slice(data);
+            Ok(raw.into_event(data))
+        })
+        .collect()
+}
+
+/// Writer of the records of a program, shared by the tasks reading its
+/// perf buffers.
+pub(crate) struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Create the recorder of `program` if [`RECORD_ENV`] is set.
+    pub(crate) fn from_env(program: &str) -> Option<Arc<Self>> {
+        let dir = PathBuf::from(std::env::var_os(RECORD_ENV)?);
+        let path = dir.join(program).with_extension(EXTENSION);
+        let file = fs::create_dir_all(&dir)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+        match file {
+            Ok(file) => {
+                log::info!("{program}: recording events to {}", path.display());
+                Some(Arc::new(Self {
+                    file: Mutex::new(file),
+                }))
+            }
+            Err(err) => {
+                log::warn!("{program}: error opening {}: {err}", path.display());
+                None
+            }
+        }
+    }
+
+    pub(crate) fn record(&self, record: &[u8]) {
+        // A single write per line, so lines are not interleaved with the
+        // ones of other readers of the same file
+        let line = format!("{}\n", hex::encode(record));
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            log::warn!("Error recording event: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct TestEvent {
+        value: u32,
+    }
+
+    fn record(value: u32, data: &[u8]) -> String {
+        // timestamp, pid, payload padded to the buffer length, aligned to 8
+        let mut record = Vec::new();
+        record.extend_from_slice(&1234_u64.to_ne_bytes());
+        record.extend_from_slice(&42_i32.to_ne_bytes());
+        record.extend_from_slice(&value.to_ne_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        record.extend_from_slice(&[0; 4]);
+        record.extend_from_slice(data);
+        hex::encode(&record)
+    }
+
+    #[test]
+    fn parse_records() {
+        let content = format!(
+            "# two events\n{}\n\n{}\n",
+            record(7, b"hello"),
+            record(8, b"")
+        );
+        let events = parse::<TestEvent>(&content).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].pid.as_raw(), 42);
+        assert_eq!(events[0].timestamp.raw(), 1234);
+        assert_eq!(events[0].payload.value, 7);
+        assert_eq!(&events[0].buffer[..], b"hello");
+        assert_eq!(events[1].payload.value, 8);
+        assert!(events[1].buffer.is_empty());
+    }
+
+    #[test]
+    fn invalid_records() {
+        assert!(matches!(
+            parse::<TestEvent>("0011zz"),
+            Err(FixtureError::Hex { line: 1, .. })
+        ));
+        // A record of a larger event type
+        let record = record(7, b"hello");
+        assert!(matches!(
+            parse::<TestEvent>(&format!("\n{}00", &record[..32])),
+            Err(FixtureError::Layout { line: 2, .. })
+        ));
+        // Data longer than the buffer length
+        assert!(matches!(
+            parse::<TestEvent>(&format!("{record}00")),
+            Err(FixtureError::Layout { line: 1, .. })
+        ));
+    }
+}
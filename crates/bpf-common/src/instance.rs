@@ -0,0 +1,231 @@
+//! Detection of concurrent daemon instances.
+//!
+//! Two daemons attaching the same probes report every event twice and remove
+//! each other's pinned maps. The first instance takes an exclusive lock on the
+//! pinning folder in bpffs, which is shared even by instances running in
+//! containers with their own `/run`, and the next ones exit with
+//! [`InstanceError::AlreadyRunning`].
+//!
+//! A copy which doesn't take the lock, like an older version, is detected
+//! by [`duplicate_programs`], checked periodically by the daemon: the kernel
+//! lists every loaded program with the hash of its instructions, which is the
+//! same for the programs of the same probes.
+
+use std::{
+    fs::{self, File},
+    io,
+    mem::size_of,
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    },
+    path::Path,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+    sys::stat::{major, minor},
+};
+use thiserror::Error;
+
+use crate::{
+    program::PINNED_MAPS_PATH,
+    program_stats::{loaded_programs, prog_info},
+};
+
+const BPF_PROG_GET_NEXT_ID: libc::c_long = 11;
+const BPF_PROG_GET_FD_BY_ID: libc::c_long = 13;
+
+const PROC_LOCKS: &str = "/proc/locks";
+
+#[derive(Error, Debug)]
+pub enum InstanceError {
+    #[error("error locking {path}")]
+    Locking {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+    #[error(
+        "another pulsard instance is running{}: stop it before starting a new one",
+        .pid.map(|pid| format!(" with pid {pid}")).unwrap_or_default()
+    )]
+    AlreadyRunning {
+        /// Process holding the lock, in the pid namespace of this one
+        pid: Option<u32>,
+    },
+}
+
+/// Lock held by the running daemon until it exits.
+pub struct InstanceLock {
+    _dir: File,
+}
+
+impl InstanceLock {
+    /// Take the lock of the pinning folder, failing if another instance holds
+    /// it. The lock is released when the process exits, even on upgrades
+    /// leaving the probes attached.
+    pub fn acquire() -> Result<Self, InstanceError> {
+        Self::acquire_path(Path::new(PINNED_MAPS_PATH))
+    }
+
+    fn acquire_path(path: &Path) -> Result<Self, InstanceError> {
+        let locking = |error| InstanceError::Locking {
+            path: path.display().to_string(),
+            error,
+        };
+        fs::create_dir_all(path).map_err(locking)?;
+        let dir = File::open(path).map_err(locking)?;
+        match flock(dir.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => Ok(Self { _dir: dir }),
+            Err(Errno::EWOULDBLOCK) => Err(InstanceError::AlreadyRunning {
+                pid: lock_holder(&dir),
+            }),
+            Err(errno) => Err(locking(errno.into())),
+        }
+    }
+}
+
+/// Process holding the flock of `file`, from `/proc/locks`.
+fn lock_holder(file: &File) -> Option<u32> {
+    let metadata = file.metadata().ok()?;
+    let locks = fs::read_to_string(PROC_LOCKS).ok()?;
+    let device = (major(metadata.dev()), minor(metadata.dev()));
+    find_lock_holder(&locks, device, metadata.ino())
+}
+
+/// Parse lines like `1: FLOCK  ADVISORY  WRITE 1234 00:1f:5678 0 EOF`,
+/// skipping the `->` lines of the processes waiting for a lock.
+fn find_lock_holder(locks: &str, device: (u64, u64), inode: u64) -> Option<u32> {
+    locks.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, "FLOCK", _, _, pid, file, ..] = fields.as_slice() else {
+            return None;
+        };
+        let mut file = file.split(':');
+        let major = u64::from_str_radix(file.next()?, 16).ok()?;
+        let minor = u64::from_str_radix(file.next()?, 16).ok()?;
+        let ino = file.next()?.parse::<u64>().ok()?;
+        if (major, minor) == device && ino == inode {
+            pid.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Names of the programs loaded by other processes with the same instructions
+/// as the programs of the running probes. Programs left attached by a
+/// previous instance are reported until they're released.
+pub fn duplicate_programs() -> io::Result<Vec<String>> {
+    let ours: Vec<_> = loaded_programs()
+        .into_iter()
+        .filter_map(|fd| prog_info(fd).ok())
+        .collect();
+    let mut duplicates = Vec::new();
+    let mut id = 0;
+    while let Some(next_id) = next_program_id(id)? {
+        id = next_id;
+        if ours.iter().any(|program| program.id == id) {
+            continue;
+        }
+        // The program may have been unloaded in the meantime
+        let Ok(info) = program_fd(id).and_then(|fd| prog_info(fd.as_raw_fd())) else {
+            continue;
+        };
+        if ours
+            .iter()
+            .any(|program| program.tag == info.tag && program.name == info.name)
+        {
+            let name = info.name.split(|byte| *byte == 0).next().unwrap_or(&[]);
+            duplicates.push(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+    duplicates.sort();
+    duplicates.dedup();
+    Ok(duplicates)
+}
+
+#[repr(C)]
+struct GetIdAttr {
+    id: u32,
+    next_id: u32,
+    open_flags: u32,
+}
+
+/// Id of the loaded program following `id`, `None` after the last one.
+fn next_program_id(id: u32) -> io::Result<Option<u32>> {
+    let mut attr = GetIdAttr {
+        id,
+        next_id: 0,
+        open_flags: 0,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_GET_NEXT_ID,
+            &mut attr as *mut GetIdAttr,
+            size_of::<GetIdAttr>(),
+        )
+    };
+    if ret < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENOENT) => Ok(None),
+            _ => Err(error),
+        };
+    }
+    Ok(Some(attr.next_id))
+}
+
+fn program_fd(id: u32) -> io::Result<OwnedFd> {
+    let attr = GetIdAttr {
+        id,
+        next_id: 0,
+        open_flags: 0,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_GET_FD_BY_ID,
+            &attr as *const GetIdAttr,
+            size_of::<GetIdAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use super::*;
+
+    #[test]
+    fn second_instance() {
+        let path = temp_dir().join(format!("pulsar-instance-lock-{}", std::process::id()));
+        let first = InstanceLock::acquire_path(&path).unwrap();
+        // flock locks belong to the open file, not to the process
+        assert!(matches!(
+            InstanceLock::acquire_path(&path),
+            Err(InstanceError::AlreadyRunning { .. })
+        ));
+        drop(first);
+        InstanceLock::acquire_path(&path).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn lock_holders() {
+        let locks = "1: POSIX  ADVISORY  WRITE 812 00:1a:1042 0 EOF\n\
+                     2: FLOCK  ADVISORY  WRITE 4242 00:1f:5678 0 EOF\n\
+                     2: -> FLOCK  ADVISORY  WRITE 4343 00:1f:5678 0 EOF\n";
+        assert_eq!(find_lock_holder(locks, (0, 0x1f), 5678), Some(4242));
+        assert_eq!(find_lock_holder(locks, (0, 0x1a), 1042), None);
+        assert_eq!(find_lock_holder(locks, (0, 0x1f), 5679), None);
+    }
+}
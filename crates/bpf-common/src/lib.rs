@@ -1,5 +1,6 @@
 mod bpf_sender;
 pub mod program;
+pub mod program_stats;
 #[cfg(feature = "test-utils")]
 pub mod test_runner;
 #[cfg(feature = "test-utils")]
@@ -9,8 +10,12 @@ pub mod test_utils;
 pub mod trace_pipe;
 
 mod bump_memlock_rlimit;
+pub mod fixtures;
+pub mod instance;
 pub mod parsing;
+pub mod storm;
 pub mod time;
+pub mod worker_pool;
 
 pub use bpf_sender::{BpfSender, BpfSenderWrapper};
 pub use bump_memlock_rlimit::bump_memlock_rlimit;
@@ -0,0 +1,84 @@
+//! `BufferArena` copies the dynamically sized part of eBPF events out of the
+//! perf buffers, which are reused for every read.
+//!
+//! Allocating a new buffer for every event is expensive, since it must be big
+//! enough for the largest event. The arena appends the data of every event to
+//! a shared chunk and splits it off: events share the chunk allocation, which
+//! is reused once all of them have been dropped. Modules use it for the data
+//! they build from events too, like messages joined from several chunks.
+
+use bytes::{Bytes, BytesMut};
+
+/// Default size of the arena chunks, fitting many small events.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct BufferArena {
+    chunk: BytesMut,
+    chunk_size: usize,
+}
+
+impl BufferArena {
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk: BytesMut::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Copy `data` to the arena, returning a buffer which shares its allocation.
+    /// Empty data doesn't use the arena at all.
+    pub fn copy(&mut self, data: &[u8]) -> Bytes {
+        self.concat(&[data])
+    }
+
+    /// Copy the concatenation of `parts` to the arena, like [`Self::copy`].
+    pub fn concat<B: AsRef<[u8]>>(&mut self, parts: &[B]) -> Bytes {
+        let len = parts.iter().map(|part| part.as_ref().len()).sum();
+        if len == 0 {
+            return Bytes::new();
+        }
+        if self.chunk.capacity() < len {
+            // Reclaims the current chunk if every buffer split from it has
+            // been dropped, otherwise allocates a new one.
+            self.chunk.reserve(self.chunk_size.max(len));
+        }
+        for part in parts {
+            self.chunk.extend_from_slice(part.as_ref());
+        }
+        self.chunk.split().freeze()
+    }
+}
+
+impl Default for BufferArena {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy() {
+        let mut arena = BufferArena::new(8);
+        let a = arena.copy(b"abc");
+        let b = arena.copy(b"defgh");
+        // doesn't fit the current chunk
+        let c = arena.copy(b"0123456789");
+        assert_eq!(&a[..], b"abc");
+        assert_eq!(&b[..], b"defgh");
+        assert_eq!(&c[..], b"0123456789");
+        assert!(arena.copy(b"").is_empty());
+        assert_eq!(&arena.concat(&[&b"ab"[..], b"", b"cd"])[..], b"abcd");
+    }
+
+    #[test]
+    fn reuse_chunk() {
+        let mut arena = BufferArena::new(16);
+        let ptr = arena.copy(&[1; 16]).as_ptr();
+        // the previous buffer has been dropped, so its chunk can be reused
+        assert_eq!(arena.copy(&[2; 16]).as_ptr(), ptr);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod procfs;
 
+mod buffer_arena;
 mod buffer_index;
+mod path_truncation;
 
+pub use buffer_arena::BufferArena;
 pub use buffer_index::{BufferIndex, IndexError};
+pub use path_truncation::PathTruncation;
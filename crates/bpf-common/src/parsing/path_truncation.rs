@@ -0,0 +1,13 @@
+/// Mirror of `struct path_truncation` filled by `get_path_str_truncation` in
+/// get_path.bpf.h.
+///
+/// When `truncated` is set, the path emitted by the probe is missing its first
+/// components: `dev` and `ino` identify the first ancestor which is not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct PathTruncation {
+    pub truncated: bool,
+    /// Device of the ancestor, in the kernel `dev_t` encoding
+    pub dev: u32,
+    pub ino: u64,
+}
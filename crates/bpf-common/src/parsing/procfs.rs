@@ -25,6 +25,8 @@ pub enum ProcfsError {
     ParentNotFound(Pid),
     #[error("user id for process {0} not found")]
     UserNotFound(Pid),
+    #[error("terminal for process {0} not found")]
+    TtyNotFound(Pid),
 
     #[error("globbing running processes")]
     GlobbingError(#[from] glob::PatternError),
@@ -107,6 +109,21 @@ pub fn get_process_parent_pid(pid: Pid) -> Result<Pid, ProcfsError> {
     Err(ProcfsError::ParentNotFound(pid))
 }
 
+/// Returns true if the given process has a controlling terminal.
+pub fn get_process_has_tty(pid: Pid) -> Result<bool, ProcfsError> {
+    let path = format!("/proc/{pid}/stat");
+    let data =
+        fs::read_to_string(&path).map_err(|source| ProcfsError::ReadFile { source, path })?;
+
+    // The command name can contain spaces, so we skip it by looking for the last ')'.
+    // The fields after it are: state, ppid, pgrp, session, tty_nr.
+    let tty_nr = data
+        .rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(4))
+        .ok_or(ProcfsError::TtyNotFound(pid))?;
+    Ok(tty_nr.parse::<i32>()? != 0)
+}
+
 /// Returns the user id of a given process.
 pub fn get_process_user_id(pid: Pid) -> Result<Uid, ProcfsError> {
     let path = format!("/proc/{pid}/status");
@@ -2,9 +2,34 @@
 //! - runs background thread which sets up the probe and waits for a shutdown signal
 //! - allows to read events events.
 //!
+//! When pinning is enabled, programs can be checkpointed on exit with
+//! [`request_checkpoint`]: their links are pinned to bpffs, so the hooks stay
+//! attached until the next daemon instance attaches its own programs and
+//! releases them. Meanwhile they keep the pinned maps up to date, like the
+//! interest map and the process info map following forks and execs, so the
+//! next instance doesn't start from stale state. Their events are lost
+//! though: the perf buffers are closed with the old instance, and the kernel
+//! clears the perf event array when the file descriptor which filled it is
+//! closed, so pinning it wouldn't keep them.
+//!
 use core::fmt;
 use std::{
-    collections::HashSet, convert::TryFrom, fmt::Display, mem::size_of, sync::Arc, time::Duration,
+    collections::HashSet,
+    convert::TryFrom,
+    ffi::CString,
+    fmt::Display,
+    io,
+    mem::size_of,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use aya::{
@@ -12,18 +37,68 @@ use aya::{
         perf::{AsyncPerfEventArray, PerfBufferError},
         Array, HashMap, Map, MapData,
     },
-    programs::{KProbe, Lsm, RawTracePoint, TracePoint},
+    programs::{
+        links::{FdLink, PinnedLink},
+        FEntry, FExit, KProbe, Lsm, RawTracePoint, TracePoint,
+    },
     util::online_cpus,
     Bpf, BpfLoader, Btf, BtfError, Pod,
 };
-use bytes::{Buf, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use thiserror::Error;
 use tokio::{sync::watch, task::JoinError};
 
-use crate::{feature_autodetect::kernel_version::KernelVersion, time::Timestamp, BpfSender, Pid};
+use crate::{
+    feature_autodetect::{
+        diagnostics::{load_hint, KernelFeatures, ProgramKind},
+        kernel_version::KernelVersion,
+    },
+    fixtures::Recorder,
+    parsing::BufferArena,
+    program_stats,
+    storm::{StormConfig, StormDetector, StormKey, STORM_MUTE_MAP},
+    time::Timestamp,
+    BpfSender, Pid,
+};
 
 const PERF_HEADER_SIZE: usize = 4;
-const PINNED_MAPS_PATH: &str = "/sys/fs/bpf/pulsar";
+pub(crate) const PINNED_MAPS_PATH: &str = "/sys/fs/bpf/pulsar";
+/// Subfolder of the pinning path containing the checkpointed links
+const PINNED_LINKS_DIR: &str = "links";
+/// Subfolder of the pinning path containing the maps pinned with
+/// [`ProgramBuilder::pin_map`]
+const PINNED_MAPS_DIR: &str = "maps";
+
+/// When checkpointing, events are read from the buffers until they're idle for
+/// this long.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+static CHECKPOINT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Keep the programs attached after exiting, pinning their links, so that the
+/// pinned maps are kept up to date until the next daemon instance takes them
+/// over. The events in between are lost.
+pub fn request_checkpoint() {
+    CHECKPOINT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Events lost by the programs with a full perf buffer, or dropped by a
+/// full [`crate::worker_pool::WorkerPool`].
+static LOST_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of events lost by the programs with a full perf buffer, or
+/// dropped by a full [`crate::worker_pool::WorkerPool`] before being sent.
+pub fn lost_events() -> u64 {
+    LOST_EVENTS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn count_lost_events(lost: u64) {
+    LOST_EVENTS.fetch_add(lost, Ordering::Relaxed);
+}
+
+fn checkpoint_requested() -> bool {
+    CHECKPOINT_REQUESTED.load(Ordering::Relaxed)
+}
 
 pub const PERF_PAGES_DEFAULT: usize = 4096;
 
@@ -50,6 +125,10 @@ pub struct BpfContext {
     kernel_version: KernelVersion,
     /// LSM support
     lsm_supported: bool,
+    /// fentry and fexit support, see [`ProgramBuilder::fentry_or_kprobe`]
+    fentry_supported: bool,
+    /// Storm control settings, disabled if missing
+    storm_control: Option<StormConfig>,
 }
 
 #[derive(Clone)]
@@ -104,16 +183,157 @@ impl BpfContext {
             log_level,
             kernel_version,
             lsm_supported,
+            fentry_supported: false,
+            storm_control: None,
         })
     }
 
+    /// Mute the events of processes exceeding the storm control threshold,
+    /// see [`crate::storm`].
+    pub fn with_storm_control(mut self, config: StormConfig) -> Self {
+        self.storm_control = Some(config);
+        self
+    }
+
+    /// Attach to kernel functions with fentry and fexit programs when
+    /// possible, see [`crate::feature_autodetect::fentry::fentry_supported`].
+    pub fn with_fentry_support(mut self, fentry_supported: bool) -> Self {
+        self.fentry_supported = fentry_supported;
+        self
+    }
+
     pub fn lsm_supported(&self) -> bool {
         self.lsm_supported
     }
 
+    pub fn fentry_supported(&self) -> bool {
+        self.fentry_supported
+    }
+
     pub fn kernel_version(&self) -> &KernelVersion {
         &self.kernel_version
     }
+
+    /// Detach the programs left attached by a checkpoint of the previous daemon
+    /// instance and not taken over by this one, like the ones of modules which
+    /// are now disabled.
+    pub fn release_checkpoint(&self) {
+        if matches!(self.pinning, Pinning::Disabled) {
+            return;
+        }
+        let links_path = Path::new(&self.pinning_path).join(PINNED_LINKS_DIR);
+        let probes = match std::fs::read_dir(&links_path) {
+            Ok(probes) => probes,
+            Err(_) => return,
+        };
+        for link_path in probes
+            .flatten()
+            .filter_map(|probe| std::fs::read_dir(probe.path()).ok())
+            .flatten()
+            .flatten()
+        {
+            log::info!(
+                "Detaching {} left by the previous daemon",
+                link_path.path().display()
+            );
+            release_pinned_link(&link_path.path());
+        }
+        let _ = std::fs::remove_dir_all(links_path);
+    }
+
+    /// Since kernel 5.15 kprobes and tracepoints are attached with bpf links,
+    /// which can be pinned. Raw tracepoints and LSM programs always can.
+    fn pinnable_perf_links(&self) -> bool {
+        self.kernel_version.as_i32()
+            >= (KernelVersion {
+                major: 5,
+                minor: 15,
+                patch: 0,
+            })
+            .as_i32()
+    }
+
+    fn links_path(&self, probe: &str) -> PathBuf {
+        Path::new(&self.pinning_path)
+            .join(PINNED_LINKS_DIR)
+            .join(probe)
+    }
+
+    /// Path of a map pinned by [`ProgramBuilder::pin_map`]:
+    /// `/sys/fs/bpf/pulsar/maps/<probe>/<map>`.
+    pub fn pinned_map_path(&self, probe: &str, map_name: &str) -> PathBuf {
+        Path::new(&self.pinning_path)
+            .join(PINNED_MAPS_DIR)
+            .join(probe)
+            .join(map_name)
+    }
+
+    /// Open a map pinned by [`ProgramBuilder::pin_map`], possibly by another
+    /// module or a previous daemon instance. The result must be wrapped in the
+    /// [`Map`] variant of its type to be converted into a typed map, for example
+    /// `HashMap::try_from(Map::HashMap(map_data))`.
+    pub fn open_pinned_map(&self, probe: &str, map_name: &str) -> Result<MapData, ProgramError> {
+        let path = self.pinned_map_path(probe, map_name);
+        if !path.exists() {
+            return Err(ProgramError::MapNotFound(path.display().to_string()));
+        }
+        Ok(MapData::from_pin(path)?)
+    }
+}
+
+/// Pin the bpf object `fd` to `path` with the `BPF_OBJ_PIN` command.
+fn pin_object(fd: RawFd, path: &Path) -> io::Result<()> {
+    const BPF_OBJ_PIN: libc::c_long = 6;
+    #[repr(C)]
+    struct ObjPinAttr {
+        pathname: u64,
+        bpf_fd: u32,
+        file_flags: u32,
+    }
+    let pathname = CString::new(path.as_os_str().as_bytes())?;
+    let attr = ObjPinAttr {
+        pathname: pathname.as_ptr() as u64,
+        bpf_fd: fd as u32,
+        file_flags: 0,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_PIN,
+            &attr as *const ObjPinAttr,
+            size_of::<ObjPinAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// File descriptor of the map, for the map types which can be pinned.
+fn map_fd(map: &Map) -> Option<RawFd> {
+    let map_data = match map {
+        Map::Array(map_data)
+        | Map::PerCpuArray(map_data)
+        | Map::HashMap(map_data)
+        | Map::PerCpuHashMap(map_data)
+        | Map::LruHashMap(map_data)
+        | Map::PerCpuLruHashMap(map_data)
+        | Map::LpmTrie(map_data)
+        | Map::Queue(map_data)
+        | Map::Stack(map_data) => map_data,
+        _ => return None,
+    };
+    Some(map_data.as_raw_fd())
+}
+
+/// Unpin a link, detaching its program.
+fn release_pinned_link(path: &Path) {
+    match PinnedLink::from_pin(path).map(PinnedLink::unpin) {
+        Ok(Ok(_link)) => {}
+        Ok(Err(err)) => log::warn!("Error unpinning link {}: {err}", path.display()),
+        Err(err) => log::warn!("Error loading pinned link {}: {err}", path.display()),
+    }
 }
 
 /// Return the correct version of the eBPF binary to load.
@@ -154,9 +374,17 @@ pub enum ProgramError {
     ProgramNotFound(String),
     #[error("incorrect program type {0}")]
     ProgramTypeError(String),
-    #[error("failed program load {program}")]
+    #[error(
+        "failed program load {program} on kernel {kernel_version}{}",
+        load_details(.hint, .verifier_log)
+    )]
     ProgramLoadError {
         program: String,
+        kernel_version: String,
+        /// Likely cause of the failure, see [`load_hint`]
+        hint: Option<&'static str>,
+        /// Output of the verifier, empty if the program was rejected before it
+        verifier_log: String,
         #[source]
         program_error: Box<aya::programs::ProgramError>,
     },
@@ -178,6 +406,40 @@ pub enum ProgramError {
     BtfError(#[from] BtfError),
     #[error("running background aya task {0}")]
     JoinError(#[from] JoinError),
+    #[error("link of {0} can't be pinned")]
+    LinkNotPinnable(String),
+    #[error("map {0} can't be pinned")]
+    MapNotPinnable(String),
+    #[error("pinning map {map} to {path}")]
+    MapPinError {
+        map: String,
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+}
+
+/// Lines at the end of the verifier log kept in the error message, where
+/// the verifier explains why it rejected the program.
+const VERIFIER_LOG_LINES: usize = 20;
+
+fn load_details(hint: &Option<&'static str>, verifier_log: &str) -> String {
+    let mut details = String::new();
+    if let Some(hint) = hint {
+        details.push_str(&format!(": {hint}"));
+    }
+    let lines: Vec<&str> = verifier_log.trim_end().lines().collect();
+    if !lines.is_empty() {
+        details.push_str("\nverifier log:");
+        if lines.len() > VERIFIER_LOG_LINES {
+            details.push_str("\n...");
+        }
+        for line in &lines[lines.len().saturating_sub(VERIFIER_LOG_LINES)..] {
+            details.push('\n');
+            details.push_str(line);
+        }
+    }
+    details
 }
 
 pub struct ProgramBuilder {
@@ -187,6 +449,14 @@ pub struct ProgramBuilder {
     ctx: BpfContext,
     probe: Vec<u8>,
     programs: Vec<ProgramType>,
+    /// Maps to pin to the file system
+    pinned_maps: Vec<String>,
+    /// Maps resized with [`ProgramBuilder::max_entries`]
+    max_entries: Vec<(String, u32)>,
+    /// Hooks of the programs not to attach, see [`ProgramBuilder::disable`]
+    disabled: HashSet<String>,
+    /// Event types which storm control can mute, see [`ProgramBuilder::storm_events`]
+    storm_events: HashSet<u32>,
 }
 
 impl ProgramBuilder {
@@ -196,9 +466,49 @@ impl ProgramBuilder {
             name,
             probe,
             programs: Vec::new(),
+            pinned_maps: Vec::new(),
+            max_entries: Vec::new(),
+            disabled: HashSet::new(),
+            storm_events: HashSet::new(),
         }
     }
 
+    /// Pin a map to [`BpfContext::pinned_map_path`] when the program starts,
+    /// replacing the map pinned by a previous instance. Other modules and
+    /// external tools can open it with [`BpfContext::open_pinned_map`].
+    /// The map stays pinned until the program is dropped, or after it when
+    /// checkpointing for upgrades.
+    pub fn pin_map(mut self, map_name: &str) -> Self {
+        self.pinned_maps.push(map_name.to_string());
+        self
+    }
+
+    /// Override the `max_entries` of a map declared by the probe, for maps
+    /// sized after the host. Pinned maps shared by several probes must be
+    /// given the same size by all of them: the first one loaded creates it.
+    pub fn max_entries(mut self, map_name: &str, max_entries: u32) -> Self {
+        self.max_entries.push((map_name.to_string(), max_entries));
+        self
+    }
+
+    /// Don't load and attach the programs of the given hooks: tracepoint,
+    /// LSM hook or kernel function names, like `sys_exit_read`,
+    /// `socket_bind` or `tcp_set_state`. Used to turn off from the module
+    /// configuration the hooks with too much overhead on some workloads.
+    pub fn disable(mut self, hooks: &[String]) -> Self {
+        self.disabled.extend(hooks.iter().cloned());
+        self
+    }
+
+    /// Let storm control mute these event types, the variants of the event
+    /// enum of the probe, when enabled with [`BpfContext::with_storm_control`].
+    /// Only high volume events which no detection depends on should be
+    /// listed: the other types, like the process lifecycle, are never muted.
+    pub fn storm_events(mut self, event_types: &[u32]) -> Self {
+        self.storm_events.extend(event_types);
+        self
+    }
+
     pub fn tracepoint(mut self, section: &str, tracepoint: &str) -> Self {
         self.programs.push(ProgramType::TracePoint(
             section.to_string(),
@@ -228,6 +538,35 @@ impl ProgramBuilder {
         self
     }
 
+    /// Attach the `fentry_<function>` program to the entry of a kernel function.
+    pub fn fentry(mut self, function: &str) -> Self {
+        self.programs.push(ProgramType::FEntry(
+            function.to_string(),
+            format!("fentry_{function}"),
+        ));
+        self
+    }
+
+    /// Attach the `fexit_<function>` program to the exit of a kernel function.
+    pub fn fexit(mut self, function: &str) -> Self {
+        self.programs.push(ProgramType::FExit(
+            function.to_string(),
+            format!("fexit_{function}"),
+        ));
+        self
+    }
+
+    /// Attach to a kernel function with [`Self::fentry`] when the kernel
+    /// supports it, falling back to [`Self::kprobe`]. The probe must define
+    /// both programs, like the ones of `PULSAR_LSM_HOOK`.
+    pub fn fentry_or_kprobe(self, function: &str) -> Self {
+        if self.ctx.fentry_supported() {
+            self.fentry(function)
+        } else {
+            self.kprobe(function)
+        }
+    }
+
     pub async fn start(self) -> Result<Program, ProgramError> {
         // We need to notify background tasks reading from maps that we're shutting down.
         // We must use oneshot::Receiver as the main shut down machanism because it has
@@ -240,10 +579,12 @@ impl ProgramBuilder {
         let btf = self.ctx.btf.clone();
         let ctx = self.ctx.clone();
         let name = self.name.to_string();
+        let storm_events = self.storm_events.clone();
 
-        let bpf = tokio::task::spawn_blocking(move || {
+        let (bpf, links, pinned_maps) = tokio::task::spawn_blocking(move || {
             let _ = std::fs::create_dir(&self.ctx.pinning_path);
-            let mut bpf = BpfLoader::new()
+            let mut loader = BpfLoader::new();
+            loader
                 .map_pin_path(&self.ctx.pinning_path)
                 .btf(Some(btf.as_ref()))
                 .set_global("log_level", &(self.ctx.log_level as i32), true)
@@ -251,22 +592,71 @@ impl ProgramBuilder {
                     "LINUX_KERNEL_VERSION",
                     &self.ctx.kernel_version.as_i32(),
                     true,
-                )
-                .load(&self.probe)?;
+                );
+            for (map_name, max_entries) in &self.max_entries {
+                loader.set_max_entries(map_name, *max_entries);
+            }
+            let mut bpf = loader.load(&self.probe)?;
+            let mut pinned_maps = Vec::new();
+            for map_name in &self.pinned_maps {
+                let map = bpf
+                    .take_map(map_name)
+                    .ok_or_else(|| ProgramError::MapNotFound(map_name.to_string()))?;
+                let fd = map_fd(&map)
+                    .ok_or_else(|| ProgramError::MapNotPinnable(map_name.to_string()))?;
+                let path = self.ctx.pinned_map_path(self.name, map_name);
+                let pin_err = |error| ProgramError::MapPinError {
+                    map: map_name.to_string(),
+                    path: path.display().to_string(),
+                    error,
+                };
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(pin_err)?;
+                }
+                let _ = std::fs::remove_file(&path);
+                pin_object(fd, &path).map_err(pin_err)?;
+                pinned_maps.push((map_name.to_string(), map));
+            }
+            let links_path = self.ctx.links_path(self.name);
+            let mut links = Vec::new();
+            for hook in &self.disabled {
+                if !self.programs.iter().any(|program| program.hook() == hook) {
+                    log::warn!("Can't disable {hook}: {} has no such hook", self.name);
+                }
+            }
             for program in self.programs {
-                program.attach(&mut bpf, &btf)?;
+                if self.disabled.contains(program.hook()) {
+                    log::info!("Not attaching disabled {program} of {}", self.name);
+                    continue;
+                }
+                let pin = matches!(self.ctx.pinning, Pinning::Enabled)
+                    && (program.has_fd_link() || self.ctx.pinnable_perf_links());
+                if let Some(link) = program.attach(&mut bpf, &btf, &self.ctx.kernel_version, pin)? {
+                    // Take over the program left attached by the previous
+                    // daemon, now that ours is running.
+                    let link_path = links_path.join(program.name());
+                    if link_path.exists() {
+                        log::debug!("Replacing checkpointed {program}");
+                        release_pinned_link(&link_path);
+                    }
+                    links.push((program.name().to_string(), link));
+                }
             }
-            Result::<Bpf, ProgramError>::Ok(bpf)
+            Result::<_, ProgramError>::Ok((bpf, links, pinned_maps))
         })
         .await
         .expect("join error")?;
+        program_stats::register(&name, &bpf);
 
         Ok(Program {
             tx_exit,
             name,
             ctx,
             bpf,
+            links,
+            pinned_maps,
             used_maps: Default::default(),
+            storm_events,
         })
     }
 }
@@ -277,6 +667,9 @@ enum ProgramType {
     Kprobe(String),
     Kretprobe(String),
     Lsm(String),
+    /// Kernel function and program name
+    FEntry(String, String),
+    FExit(String, String),
 }
 
 impl Display for ProgramType {
@@ -289,43 +682,156 @@ impl Display for ProgramType {
             ProgramType::Kprobe(kprobe) => write!(f, "kprobe {kprobe}"),
             ProgramType::Kretprobe(kretprobe) => write!(f, "kretprobe {kretprobe}"),
             ProgramType::Lsm(lsm) => write!(f, "lsm {lsm}"),
+            ProgramType::FEntry(function, _) => write!(f, "fentry {function}"),
+            ProgramType::FExit(function, _) => write!(f, "fexit {function}"),
         }
     }
 }
 
 impl ProgramType {
-    fn attach(&self, bpf: &mut Bpf, btf: &Btf) -> Result<(), ProgramError> {
-        let load_err = |program_error| ProgramError::ProgramLoadError {
-            program: self.to_string(),
-            program_error: Box::new(program_error),
+    /// Name of the eBPF program
+    fn name(&self) -> &str {
+        match self {
+            ProgramType::TracePoint(_, name)
+            | ProgramType::RawTracePoint(name)
+            | ProgramType::Kprobe(name)
+            | ProgramType::Kretprobe(name)
+            | ProgramType::Lsm(name)
+            | ProgramType::FEntry(_, name)
+            | ProgramType::FExit(_, name) => name,
+        }
+    }
+
+    /// Tracepoint, LSM hook or kernel function the program is attached to
+    fn hook(&self) -> &str {
+        match self {
+            ProgramType::TracePoint(_, hook)
+            | ProgramType::RawTracePoint(hook)
+            | ProgramType::Kprobe(hook)
+            | ProgramType::Kretprobe(hook)
+            | ProgramType::Lsm(hook)
+            | ProgramType::FEntry(hook, _)
+            | ProgramType::FExit(hook, _) => hook,
+        }
+    }
+
+    fn kind(&self) -> ProgramKind {
+        match self {
+            ProgramType::TracePoint(..) => ProgramKind::TracePoint,
+            ProgramType::RawTracePoint(_) => ProgramKind::RawTracePoint,
+            ProgramType::Kprobe(_) | ProgramType::Kretprobe(_) => ProgramKind::Kprobe,
+            ProgramType::Lsm(_) => ProgramKind::Lsm,
+            ProgramType::FEntry(..) | ProgramType::FExit(..) => ProgramKind::Tracing,
+        }
+    }
+
+    /// Raw tracepoints, LSM, fentry and fexit programs are always attached
+    /// with a bpf link
+    fn has_fd_link(&self) -> bool {
+        matches!(
+            self,
+            ProgramType::RawTracePoint(_)
+                | ProgramType::Lsm(_)
+                | ProgramType::FEntry(..)
+                | ProgramType::FExit(..)
+        )
+    }
+
+    /// Load and attach the program. With `take_link`, the link is returned
+    /// to be pinned on checkpoints, otherwise it's owned by the program.
+    fn attach(
+        &self,
+        bpf: &mut Bpf,
+        btf: &Btf,
+        kernel_version: &KernelVersion,
+        take_link: bool,
+    ) -> Result<Option<FdLink>, ProgramError> {
+        let load_err = |program_error: aya::programs::ProgramError| {
+            let (io_error, verifier_log) = match &program_error {
+                aya::programs::ProgramError::LoadError {
+                    io_error,
+                    verifier_log,
+                } => (Some(io_error), verifier_log.to_string()),
+                _ => (None, String::new()),
+            };
+            let hint = load_hint(
+                self.kind(),
+                io_error,
+                &verifier_log,
+                &KernelFeatures::autodetect(),
+            );
+            ProgramError::ProgramLoadError {
+                program: self.to_string(),
+                kernel_version: kernel_version.to_string(),
+                hint,
+                verifier_log,
+                program_error: Box::new(program_error),
+            }
         };
         let attach_err = |program_error| ProgramError::ProgramAttachError {
             program: self.to_string(),
             program_error: Box::new(program_error),
         };
-        match self {
+        let not_pinnable = || ProgramError::LinkNotPinnable(self.to_string());
+        let link = match self {
             ProgramType::TracePoint(section, tracepoint) => {
                 let program: &mut TracePoint = extract_program(bpf, tracepoint)?;
                 program.load().map_err(load_err)?;
-                program.attach(section, tracepoint).map_err(attach_err)?;
+                let link_id = program.attach(section, tracepoint).map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                let link = program.take_link(link_id).map_err(attach_err)?;
+                FdLink::try_from(link).map_err(|_| not_pinnable())?
             }
             ProgramType::RawTracePoint(tracepoint) => {
                 let program: &mut RawTracePoint = extract_program(bpf, tracepoint)?;
                 program.load().map_err(load_err)?;
-                program.attach(tracepoint).map_err(attach_err)?;
+                let link_id = program.attach(tracepoint).map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                FdLink::from(program.take_link(link_id).map_err(attach_err)?)
             }
             ProgramType::Kretprobe(kprobe) | ProgramType::Kprobe(kprobe) => {
                 let program: &mut KProbe = extract_program(bpf, kprobe)?;
                 program.load().map_err(load_err)?;
-                program.attach(kprobe, 0).map_err(attach_err)?;
+                let link_id = program.attach(kprobe, 0).map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                let link = program.take_link(link_id).map_err(attach_err)?;
+                FdLink::try_from(link).map_err(|_| not_pinnable())?
             }
             ProgramType::Lsm(lsm) => {
                 let program: &mut Lsm = extract_program(bpf, lsm)?;
                 program.load(lsm, btf).map_err(load_err)?;
-                program.attach().map_err(attach_err)?;
+                let link_id = program.attach().map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                FdLink::from(program.take_link(link_id).map_err(attach_err)?)
             }
-        }
-        Ok(())
+            ProgramType::FEntry(function, name) => {
+                let program: &mut FEntry = extract_program(bpf, name)?;
+                program.load(function, btf).map_err(load_err)?;
+                let link_id = program.attach().map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                FdLink::from(program.take_link(link_id).map_err(attach_err)?)
+            }
+            ProgramType::FExit(function, name) => {
+                let program: &mut FExit = extract_program(bpf, name)?;
+                program.load(function, btf).map_err(load_err)?;
+                let link_id = program.attach().map_err(attach_err)?;
+                if !take_link {
+                    return Ok(None);
+                }
+                FdLink::from(program.take_link(link_id).map_err(attach_err)?)
+            }
+        };
+        Ok(Some(link))
     }
 }
 
@@ -347,13 +853,29 @@ pub struct Program {
     ctx: BpfContext,
     name: String,
     bpf: Bpf,
+    /// Links taken from the programs to be pinned on checkpoints, with the
+    /// name of their program. Dropping them detaches the programs.
+    links: Vec<(String, FdLink)>,
+    /// Maps pinned with [`ProgramBuilder::pin_map`], taken from `bpf` until
+    /// they're used.
+    pinned_maps: Vec<(String, Map)>,
     used_maps: HashSet<String>,
+    storm_events: HashSet<u32>,
 }
 
 impl Drop for Program {
     fn drop(&mut self) {
+        program_stats::unregister(&self.bpf);
         if matches!(self.ctx.pinning, Pinning::Disabled) {
             let _ = std::fs::remove_dir_all(&self.ctx.pinning_path);
+        } else if checkpoint_requested() {
+            self.pin_links();
+        } else {
+            let _ = std::fs::remove_dir_all(
+                Path::new(&self.ctx.pinning_path)
+                    .join(PINNED_MAPS_DIR)
+                    .join(&self.name),
+            );
         }
     }
 }
@@ -362,6 +884,26 @@ impl Program {
     pub fn bpf(&mut self) -> &mut Bpf {
         &mut self.bpf
     }
+
+    /// Pin the links to keep the programs attached after exiting.
+    fn pin_links(&mut self) {
+        let links_path = self.ctx.links_path(&self.name);
+        if let Err(err) = std::fs::create_dir_all(&links_path) {
+            log::warn!(
+                "{}: error creating {}: {err}",
+                self.name,
+                links_path.display()
+            );
+            return;
+        }
+        for (program, link) in self.links.drain(..) {
+            if let Err(err) = link.pin(links_path.join(&program)) {
+                log::warn!("{}: error pinning link of {program}: {err}", self.name);
+            }
+        }
+        log::info!("{}: probes kept attached for the next daemon", self.name);
+    }
+
     /// Poll a BPF_MAP_TYPE_HASH with a certain interval
     pub async fn poll<F, K, V>(
         &mut self,
@@ -407,14 +949,38 @@ impl Program {
                 .ok_or_else(|| ProgramError::MapNotFound("init_map".to_string()))?,
         )?;
 
+        let storm_control = self
+            .ctx
+            .storm_control
+            .filter(|_| !self.storm_events.is_empty());
+        let storm_mute = match storm_control {
+            Some(_) => match self.take_map(STORM_MUTE_MAP) {
+                Ok(map) => Some(Arc::new(Mutex::new(HashMap::<_, StormKey, u64>::try_from(
+                    map,
+                )?))),
+                Err(err) => {
+                    log::warn!("{}: storm control disabled: {err}", self.name);
+                    None
+                }
+            },
+            None => None,
+        };
+
         let buffers = online_cpus()
             .unwrap()
             .into_iter()
             .map(|cpu_id| perf_array.open(cpu_id, Some(self.ctx.perf_pages)))
             .collect::<Result<Vec<_>, PerfBufferError>>()?;
+        let recorder = Recorder::from_env(&self.name);
         for mut buf in buffers {
             let name = self.name.clone();
+            let recorder = recorder.clone();
             let mut sender = sender.clone();
+            let storm_mute = storm_mute.clone();
+            let storm_events = self.storm_events.clone();
+            let mut storm_detector = storm_control
+                .filter(|_| storm_mute.is_some())
+                .map(StormDetector::new);
             let mut rx_exit = self.tx_exit.subscribe();
             let event_size: usize = size_of::<RawBpfEvent<T>>();
             let buffer_size: usize = event_size + PERF_HEADER_SIZE + BUFFER_MAX;
@@ -422,14 +988,34 @@ impl Program {
                 let mut buffers = (0..10)
                     .map(|_| BytesMut::with_capacity(buffer_size))
                     .collect::<Vec<_>>();
+                let mut arena = BufferArena::default();
+                let mut draining = false;
                 loop {
-                    let events = tokio::select! {
-                        Err(_) = rx_exit.changed() => return,
-                        events = buf.read_events(&mut buffers) => events,
+                    let events = if draining {
+                        match tokio::time::timeout(DRAIN_TIMEOUT, buf.read_events(&mut buffers))
+                            .await
+                        {
+                            Ok(events) => events,
+                            Err(_) => return,
+                        }
+                    } else {
+                        tokio::select! {
+                            Err(_) = rx_exit.changed() => {
+                                // On checkpoints the probes stay attached:
+                                // forward the events left in the buffer.
+                                draining = checkpoint_requested();
+                                if !draining {
+                                    return;
+                                }
+                                continue;
+                            }
+                            events = buf.read_events(&mut buffers) => events,
+                        }
                     };
                     match events {
                         Ok(events) => {
                             if events.lost > 0 {
+                                LOST_EVENTS.fetch_add(events.lost as u64, Ordering::Relaxed);
                                 log::warn!(
                                     "{}: Lost {} events (read {})",
                                     name,
@@ -438,31 +1024,48 @@ impl Program {
                                 );
                             }
                             for buffer in buffers.iter_mut().take(events.read) {
-                                if buffer.len() < event_size {
+                                let Some((raw, data)) = RawBpfEvent::<T>::parse(buffer) else {
                                     log::error!("sizeof T: {}", size_of::<T>());
                                     log::error!(
                                         "sizeof RawBpfEvent<T>: {}",
                                         size_of::<RawBpfEvent<T>>()
                                     );
                                     panic!("Buffer too short. buffer.len() = {}", buffer.len(),);
+                                };
+                                if let Some(recorder) = &recorder {
+                                    recorder.record(&buffer[..event_size + data.len()]);
+                                }
+                                // The payload is a C enum, starting with the event type
+                                let event_type = unsafe {
+                                    let ptr = buffer.as_ptr() as *const RawBpfEvent<T>;
+                                    (std::ptr::addr_of!((*ptr).payload) as *const u32)
+                                        .read_unaligned()
+                                };
+                                // The perf buffers are reused by the next read
+                                let data = arena.copy(data);
+                                buffer.clear();
+                                let event = raw.into_event(data);
+                                let key = StormKey {
+                                    pid: event.pid.as_raw(),
+                                    event_type,
+                                };
+                                let storm = storm_detector
+                                    .as_mut()
+                                    .filter(|_| storm_events.contains(&event_type))
+                                    .and_then(|detector| detector.observe(key, event.timestamp));
+                                match (storm, &storm_mute) {
+                                    (Some(storm), Some(storm_mute)) => {
+                                        let until = Timestamp::now().raw()
+                                            + storm.muted_for.as_nanos() as u64;
+                                        if let Err(err) =
+                                            storm_mute.lock().unwrap().insert(key, until, 0)
+                                        {
+                                            log::warn!("{name}: error muting event storm: {err}");
+                                        }
+                                        sender.send_storm(event, storm)
+                                    }
+                                    _ => sender.send(Ok(event)),
                                 }
-                                let mut buffer =
-                                    std::mem::replace(buffer, BytesMut::with_capacity(buffer_size));
-                                let ptr = buffer.as_ptr() as *const RawBpfEvent<T>;
-                                let raw = unsafe { ptr.read_unaligned() };
-                                buffer.advance(event_size);
-                                // NOTE: read buffer will be padded. Eg. if the eBPF program
-                                // writes 3 bytes, we'll read 4, with the forth being a 0.
-                                // This is why we need buffer_len and can't rely on the
-                                // received buffer alone.
-                                buffer.truncate(raw.buffer.buffer_len as usize);
-                                let buffer = buffer.freeze();
-                                sender.send(Ok(BpfEvent {
-                                    timestamp: raw.timestamp,
-                                    pid: raw.pid,
-                                    payload: raw.payload,
-                                    buffer,
-                                }))
                             }
                         }
                         Err(e) => return sender.send(Err(e.into())),
@@ -487,10 +1090,17 @@ impl Program {
             return Err(ProgramError::MapAlreadyUsed(map_name.to_string()));
         };
 
-        let map_resource = self
-            .bpf
-            .take_map(map_name)
-            .ok_or_else(|| ProgramError::MapNotFound(map_name.to_string()))?;
+        let map_resource = match self
+            .pinned_maps
+            .iter()
+            .position(|(name, _)| name == map_name)
+        {
+            Some(index) => self.pinned_maps.swap_remove(index).1,
+            None => self
+                .bpf
+                .take_map(map_name)
+                .ok_or_else(|| ProgramError::MapNotFound(map_name.to_string()))?,
+        };
 
         self.used_maps.insert(map_name.to_string());
         Ok(map_resource)
@@ -518,6 +1128,37 @@ struct Buffer {
     pub buffer_len: u32,
 }
 
+impl<P> RawBpfEvent<P> {
+    pub(crate) const SIZE: usize = size_of::<Self>();
+
+    /// Read the event at the start of a record of the perf buffer, returning
+    /// it with the data of its buffer. Returns `None` if the record is shorter
+    /// than the event.
+    pub(crate) fn parse(record: &[u8]) -> Option<(Self, &[u8])> {
+        if record.len() < Self::SIZE {
+            return None;
+        }
+        let ptr = record.as_ptr() as *const Self;
+        let raw = unsafe { ptr.read_unaligned() };
+        // NOTE: read buffer will be padded. Eg. if the eBPF program
+        // writes 3 bytes, we'll read 4, with the forth being a 0.
+        // This is why we need buffer_len and can't rely on the
+        // received buffer alone.
+        let data = &record[Self::SIZE..];
+        let data = &data[..data.len().min(raw.buffer.buffer_len as usize)];
+        Some((raw, data))
+    }
+
+    pub(crate) fn into_event(self, buffer: Bytes) -> BpfEvent<P> {
+        BpfEvent {
+            timestamp: self.timestamp,
+            pid: self.pid,
+            payload: self.payload,
+            buffer,
+        }
+    }
+}
+
 impl<P: fmt::Display> fmt::Display for BpfEvent<P> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} {} {}", self.timestamp, self.pid, self.payload)
@@ -0,0 +1,176 @@
+//! Run time statistics of the eBPF programs, to measure the overhead of
+//! every hook on the workload of the host.
+//!
+//! The kernel counts the invocations and the time spent in the programs only
+//! while the statistics are enabled, with [`enable_stats`] or the
+//! `kernel.bpf_stats_enabled` sysctl, since the measure itself has a cost.
+
+use std::{
+    io,
+    mem::size_of,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::Mutex,
+};
+
+use aya::Bpf;
+
+const BPF_OBJ_GET_INFO_BY_FD: libc::c_long = 15;
+const BPF_ENABLE_STATS: libc::c_long = 32;
+const BPF_STATS_RUN_TIME: u32 = 0;
+
+const STATS_ENABLED_PATH: &str = "/proc/sys/kernel/bpf_stats_enabled";
+
+/// Programs of the running probes, see [`register`].
+static PROGRAMS: Mutex<Vec<LoadedProgram>> = Mutex::new(Vec::new());
+
+struct LoadedProgram {
+    probe: String,
+    program: String,
+    fd: RawFd,
+}
+
+/// Invocations and run time of an eBPF program since the statistics were
+/// enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub probe: String,
+    pub program: String,
+    pub run_count: u64,
+    pub run_time_ns: u64,
+}
+
+/// Enable the run time statistics of all the eBPF programs until the
+/// returned file descriptor is closed.
+pub fn enable_stats() -> io::Result<OwnedFd> {
+    #[repr(C)]
+    struct EnableStatsAttr {
+        stats_type: u32,
+    }
+    let attr = EnableStatsAttr {
+        stats_type: BPF_STATS_RUN_TIME,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_ENABLE_STATS,
+            &attr as *const EnableStatsAttr,
+            size_of::<EnableStatsAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+}
+
+/// The kernel is collecting the run time statistics.
+pub fn stats_enabled() -> bool {
+    std::fs::read_to_string(STATS_ENABLED_PATH)
+        .map(|value| value.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Statistics of the programs of all the running probes.
+pub fn program_stats() -> Vec<ProgramStats> {
+    PROGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|loaded| match prog_info(loaded.fd) {
+            Ok(info) => Some(ProgramStats {
+                probe: loaded.probe.clone(),
+                program: loaded.program.clone(),
+                run_count: info.run_cnt,
+                run_time_ns: info.run_time_ns,
+            }),
+            Err(err) => {
+                log::warn!("Error reading stats of {}: {err}", loaded.program);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Track the loaded programs of a probe. The file descriptors are owned by
+/// `bpf`: they must be unregistered before dropping it.
+pub(crate) fn register(probe: &str, bpf: &Bpf) {
+    let mut programs = PROGRAMS.lock().unwrap();
+    for (name, program) in bpf.programs() {
+        if let Some(fd) = program.fd() {
+            programs.push(LoadedProgram {
+                probe: probe.to_string(),
+                program: name.to_string(),
+                fd: fd.as_raw_fd(),
+            });
+        }
+    }
+}
+
+pub(crate) fn unregister(bpf: &Bpf) {
+    let fds: Vec<RawFd> = bpf
+        .programs()
+        .filter_map(|(_, program)| program.fd())
+        .map(|fd| fd.as_raw_fd())
+        .collect();
+    PROGRAMS
+        .lock()
+        .unwrap()
+        .retain(|loaded| !fds.contains(&loaded.fd));
+}
+
+/// Prefix of `struct bpf_prog_info` up to the statistics, available since
+/// 5.1. The kernel only fills the fields it knows about.
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct ProgInfo {
+    _prog_type: u32,
+    pub(crate) id: u32,
+    /// Hash of the instructions, without the map file descriptors
+    pub(crate) tag: [u8; 8],
+    /// From `jited_prog_len` to `map_ids`
+    _fields: [u64; 6],
+    /// Program name, truncated to 15 bytes and NUL terminated
+    pub(crate) name: [u8; 16],
+    /// From `ifindex` to `prog_tags`
+    _more_fields: [u64; 14],
+    pub(crate) run_time_ns: u64,
+    pub(crate) run_cnt: u64,
+}
+
+/// File descriptors of the programs of all the running probes.
+pub(crate) fn loaded_programs() -> Vec<RawFd> {
+    PROGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|loaded| loaded.fd)
+        .collect()
+}
+
+/// Read the `struct bpf_prog_info` of a program.
+pub(crate) fn prog_info(fd: RawFd) -> io::Result<ProgInfo> {
+    #[repr(C)]
+    struct InfoAttr {
+        bpf_fd: u32,
+        info_len: u32,
+        info: u64,
+    }
+    let mut info = ProgInfo::default();
+    let attr = InfoAttr {
+        bpf_fd: fd as u32,
+        info_len: size_of::<ProgInfo>() as u32,
+        info: &mut info as *mut ProgInfo as u64,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_GET_INFO_BY_FD,
+            &attr as *const InfoAttr,
+            size_of::<InfoAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(info)
+}
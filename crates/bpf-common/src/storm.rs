@@ -0,0 +1,135 @@
+//! Storm control protects the event pipeline from processes generating a
+//! pathological amount of events, like a UDP flood.
+//!
+//! The events read from every CPU are counted by process and event type. When
+//! a pair exceeds the threshold within a second, it's muted in the eBPF probe
+//! for a while, using the `map_storm_mute` map of `output.bpf.h`, and the
+//! [`crate::BpfSender`] is notified with an [`EventStorm`].
+//!
+//! Only the event types listed by the module with
+//! [`crate::ProgramBuilder::storm_events`] are counted and muted, so that the
+//! process lifecycle and the events detections depend on are always reported.
+
+use std::{collections::HashMap, time::Duration};
+
+use aya::Pod;
+
+use crate::{time::Timestamp, Pid};
+
+/// Name of the eBPF map with the muted processes and event types.
+pub const STORM_MUTE_MAP: &str = "map_storm_mute";
+
+/// Interval in which the events are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Storm control settings, disabled unless `storm_threshold` is configured.
+/// The default values apply to the settings left unset.
+#[derive(Debug, Clone, Copy)]
+pub struct StormConfig {
+    /// Events per second of a process and event type, on a single CPU, after
+    /// which they are muted
+    pub threshold: u64,
+    /// How long the events are muted
+    pub mute_duration: Duration,
+}
+
+impl Default for StormConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 10_000,
+            mute_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Key of `map_storm_mute`, the value is the mute expiration as a monotonic
+/// timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct StormKey {
+    pub pid: i32,
+    /// Variant of the probe event enum
+    pub event_type: u32,
+}
+
+unsafe impl Pod for StormKey {}
+
+/// Process and event type which exceeded the threshold.
+#[derive(Debug, Clone, Copy)]
+pub struct EventStorm {
+    pub pid: Pid,
+    pub event_type: u32,
+    /// Events counted in the last second
+    pub events: u64,
+    pub muted_for: Duration,
+}
+
+pub(crate) struct StormDetector {
+    config: StormConfig,
+    window_start: Timestamp,
+    counts: HashMap<StormKey, u64>,
+}
+
+impl StormDetector {
+    pub(crate) fn new(config: StormConfig) -> Self {
+        Self {
+            config,
+            window_start: Timestamp::from(0),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count an event, returning a storm when its process and type reach the
+    /// threshold: this happens only once per window.
+    pub(crate) fn observe(&mut self, key: StormKey, timestamp: Timestamp) -> Option<EventStorm> {
+        if timestamp.raw().saturating_sub(self.window_start.raw()) >= WINDOW.as_nanos() as u64 {
+            self.window_start = timestamp;
+            self.counts.clear();
+        }
+        let count = self.counts.entry(key).or_default();
+        *count += 1;
+        (*count == self.config.threshold).then(|| EventStorm {
+            pid: Pid::from_raw(key.pid),
+            event_type: key.event_type,
+            events: *count,
+            muted_for: self.config.mute_duration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let mut detector = StormDetector::new(StormConfig {
+            threshold: 3,
+            mute_duration: Duration::from_secs(1),
+        });
+        let noisy = StormKey {
+            pid: 42,
+            event_type: 4,
+        };
+        let other = StormKey {
+            pid: 42,
+            event_type: 5,
+        };
+        let start = 1_000_000_000;
+        assert!(detector.observe(noisy, start.into()).is_none());
+        assert!(detector.observe(other, (start + 1).into()).is_none());
+        assert!(detector.observe(noisy, (start + 2).into()).is_none());
+        let storm = detector.observe(noisy, (start + 3).into()).unwrap();
+        assert_eq!(storm.pid.as_raw(), 42);
+        assert_eq!(storm.event_type, 4);
+        assert_eq!(storm.events, 3);
+        // reported once per window
+        assert!(detector.observe(noisy, (start + 4).into()).is_none());
+
+        // the count restarts in the next window
+        let next = start + 1_000_000_000;
+        assert!(detector.observe(noisy, next.into()).is_none());
+        assert!(detector.observe(noisy, (next + 1).into()).is_none());
+        assert!(detector.observe(noisy, (next + 2).into()).is_some());
+    }
+}
@@ -32,15 +32,25 @@
 //!     }
 //! }
 //! ```
+//!
+//! Tests using fixed ports or paths can use `run_sandboxed` instead of `run`,
+//! so that they don't conflict with services running on the host.
+//!
+//! Besides `expect_event`, tests can check with `expect_no_event` that events
+//! are filtered out, and with `expect_sequence` that events happen in a given
+//! order.
 
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{future::Future, pin::Pin, sync::OnceLock, time::Duration};
 
 use anyhow::Context;
 use bytes::Bytes;
+use nix::sched::{unshare, CloneFlags};
 use tokio::sync::mpsc;
 
-use crate::feature_autodetect::lsm::lsm_supported;
+use crate::feature_autodetect::{fentry::fentry_supported, lsm::lsm_supported};
 use crate::{
     program::{BpfContext, BpfEvent, BpfLogLevel, Pinning},
     time::Timestamp,
@@ -107,7 +117,9 @@ impl<T: Debug> TestRunner<T> {
         static BPF_CONTEXT: OnceLock<BpfContext> = OnceLock::new();
 
         let ctx = BPF_CONTEXT.get_or_init(|| {
-            BpfContext::new(Pinning::Disabled, 512, BpfLogLevel::Debug, lsm_supported()).unwrap()
+            BpfContext::new(Pinning::Disabled, 512, BpfLogLevel::Debug, lsm_supported())
+                .unwrap()
+                .with_fentry_support(fentry_supported())
         });
 
         Self {
@@ -137,6 +149,81 @@ impl<T: Debug> TestRunner<T> {
             expectations: Vec::new(),
         }
     }
+
+    /// Like [`TestRunner::run`], but the triggering code runs in a thread with
+    /// a new network namespace, where only the loopback interface is up, and
+    /// with an empty temporary directory as working directory. Threads and
+    /// processes spawned by the triggering code inherit the sandbox.
+    pub async fn run_sandboxed<F>(self, trigger_program: F) -> TestResult<T>
+    where
+        F: FnOnce() + Send,
+    {
+        let dir = sandbox_dir();
+        std::fs::create_dir_all(&dir)
+            .context("creating sandbox directory")
+            .unwrap();
+        let result = self
+            .run(|| {
+                std::thread::scope(|scope| {
+                    scope
+                        .spawn(|| {
+                            enter_sandbox(&dir).context("entering sandbox").unwrap();
+                            trigger_program();
+                        })
+                        .join()
+                        .unwrap()
+                })
+            })
+            .await;
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+}
+
+/// Return a new temporary directory path for a sandboxed test run.
+fn sandbox_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "pulsar-test-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Move the current thread into a new network namespace and to its own
+/// working directory.
+fn enter_sandbox(dir: &Path) -> anyhow::Result<()> {
+    unshare(CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_FS)
+        .context("creating network namespace")?;
+    std::env::set_current_dir(dir).context("changing working directory")?;
+    loopback_up().context("setting up loopback interface")
+}
+
+/// Bring up the loopback interface of the current network namespace, which
+/// starts down.
+fn loopback_up() -> std::io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if socket < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in request.ifr_name.iter_mut().zip(b"lo\0") {
+        *dst = *src as libc::c_char;
+    }
+    let result = unsafe {
+        if libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request as *mut libc::ifreq) < 0 {
+            -1
+        } else {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            libc::ioctl(socket, libc::SIOCSIFFLAGS, &request as *const libc::ifreq)
+        }
+    };
+    let error = std::io::Error::last_os_error();
+    unsafe { libc::close(socket) };
+    if result < 0 {
+        return Err(error);
+    }
+    Ok(())
 }
 
 /// Simple BpfSender used to collect `bpf_common::program::Program` events.
@@ -186,6 +273,18 @@ enum Expectation<T> {
         /// List of checks on the event properties
         checks: Vec<Check<T>>,
     },
+    /// No event must match all provided constraints
+    NoEvent {
+        pid: Option<Pid>,
+        time_bound: bool,
+        checks: Vec<Check<T>>,
+    },
+    /// Every step must be matched by an event, in order of timestamp
+    Sequence {
+        pid: Option<Pid>,
+        time_bound: bool,
+        steps: Vec<Vec<Check<T>>>,
+    },
 }
 
 /// A `Predicate<T>` is a function which takes a `BpfEvent<T>` and returns if
@@ -241,6 +340,31 @@ impl<T: Debug> TestResult<T> {
         self
     }
 
+    /// Make sure there's no event matching all expectations, produced during
+    /// the collection interval by the current process. Useful to verify
+    /// filtering.
+    pub fn expect_no_event(mut self, checks: Vec<Check<T>>) -> Self {
+        self.expectations.push(Expectation::NoEvent {
+            pid: Some(Pid::from_raw(std::process::id() as i32)),
+            time_bound: true,
+            checks,
+        });
+        self
+    }
+
+    /// Make sure there's an event matching each list of checks, produced during
+    /// the collection interval by the current process, in the given order.
+    /// Events are ordered by timestamp, since events from different CPUs may
+    /// be collected out of order.
+    pub fn expect_sequence(mut self, steps: Vec<Vec<Check<T>>>) -> Self {
+        self.expectations.push(Expectation::Sequence {
+            pid: Some(Pid::from_raw(std::process::id() as i32)),
+            time_bound: true,
+            steps,
+        });
+        self
+    }
+
     /// Search among the produced events one which satisfies all expectations.
     pub fn report(self) -> TestReport {
         let events = self.events;
@@ -273,12 +397,101 @@ impl<T: Debug> TestResult<T> {
                     }
                     success = success && run_checks(&events, checks, &mut lines);
                 }
+                Expectation::NoEvent {
+                    pid,
+                    time_bound,
+                    mut checks,
+                } => {
+                    if let Some(pid) = pid {
+                        checks.push(pid_check(pid));
+                    }
+                    if time_bound {
+                        checks.push(timestamp_check(self.start_time, self.end_time));
+                    }
+                    success = run_no_event_checks(&events, checks, &mut lines) && success;
+                }
+                Expectation::Sequence {
+                    pid,
+                    time_bound,
+                    steps,
+                } => {
+                    let steps = steps
+                        .into_iter()
+                        .map(|mut checks| {
+                            if let Some(pid) = pid {
+                                checks.push(pid_check(pid));
+                            }
+                            if time_bound {
+                                checks.push(timestamp_check(self.start_time, self.end_time));
+                            }
+                            checks
+                        })
+                        .collect();
+                    success = run_sequence_checks(&events, steps, &mut lines) && success;
+                }
             }
         }
         TestReport { success, lines }
     }
 }
 
+fn matches_all<T>(event: &BpfEvent<T>, checks: &[Check<T>]) -> bool {
+    checks.iter().all(|check| (check.check_fn)(event).success)
+}
+
+/// Make sure the eBPF program produced no event matching all checks.
+pub fn run_no_event_checks<T: std::fmt::Debug>(
+    events: &[BpfEvent<T>],
+    checks: Vec<Check<T>>,
+    lines: &mut Vec<String>,
+) -> bool {
+    let unexpected: Vec<_> = events
+        .iter()
+        .filter(|event| matches_all(event, &checks))
+        .collect();
+    for event in &unexpected {
+        lines.push(format!("* Unexpected event \"{event:?}\""));
+    }
+    unexpected.is_empty()
+}
+
+/// Make sure the eBPF program produced events matching each list of checks,
+/// in order of timestamp.
+pub fn run_sequence_checks<T: std::fmt::Debug>(
+    events: &[BpfEvent<T>],
+    steps: Vec<Vec<Check<T>>>,
+    lines: &mut Vec<String>,
+) -> bool {
+    let mut events: Vec<&BpfEvent<T>> = events.iter().collect();
+    events.sort_by_key(|event| event.timestamp);
+    let mut remaining = &events[..];
+    for (index, checks) in steps.iter().enumerate() {
+        match remaining
+            .iter()
+            .position(|event| matches_all(event, checks))
+        {
+            Some(position) => remaining = &remaining[position + 1..],
+            None => {
+                let matched_before = events
+                    .iter()
+                    .filter(|event| matches_all(event, checks))
+                    .count();
+                if matched_before > 0 {
+                    lines.push(format!(
+                        "* Step {} of the sequence matched only by events preceding step {}",
+                        index + 1,
+                        index
+                    ));
+                } else {
+                    lines.push(format!("* Step {} of the sequence not found", index + 1));
+                }
+                return false;
+            }
+        }
+    }
+    true
+}
+
 /// Make sure the eBPF program produced at least one event maching all checks.
 pub fn run_checks<T: std::fmt::Debug>(
     events: &[BpfEvent<T>],
@@ -1,6 +1,10 @@
 use std::{
     fmt, ops,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use nix::{
@@ -25,6 +29,17 @@ impl Timestamp {
     pub fn raw(&self) -> u64 {
         self.0
     }
+
+    /// Convert to a SystemTime with the wall clock before the last clock step,
+    /// when the step may have happened after the event.
+    ///
+    /// Events changing the clock are usually converted after the step, so they
+    /// would get a time of the new clock.
+    pub fn wall_clock_before_step(self) -> SystemTime {
+        let clock = clock();
+        let offset = offset_before_step(self.0, clock.offset(), *clock.last_step.lock().unwrap());
+        to_system_time(self.0, offset)
+    }
 }
 
 impl std::fmt::Debug for Timestamp {
@@ -55,18 +70,166 @@ impl ops::Sub for Timestamp {
     }
 }
 
+/// Interval after which the offset between the wall clock and the monotonic
+/// clock is measured again, to follow NTP adjustments.
+const RESYNC_INTERVAL: u64 = Duration::from_secs(1).as_nanos() as u64;
+
+/// Changes of the offset bigger than this are reported as clock steps, like
+/// the ones made by NTP clients, a manual clock change or a suspend.
+const STEP_THRESHOLD: Duration = Duration::from_millis(128);
+
+/// Offset between the wall clock and the monotonic clock, measured on the
+/// first conversion and again every [`RESYNC_INTERVAL`].
+static CLOCK: OnceLock<ClockSync> = OnceLock::new();
+
+fn clock() -> &'static ClockSync {
+    CLOCK.get_or_init(ClockSync::new)
+}
+
+struct ClockSync {
+    /// Nanoseconds to add to a monotonic timestamp to get the wall clock time
+    /// since the UNIX epoch
+    offset: AtomicI64,
+    /// Monotonic timestamp of the last synchronization
+    last_sync: AtomicU64,
+    /// Last clock step, with the offset before it. Events between the
+    /// synchronizations around the step may precede it.
+    last_step: Mutex<Option<Step>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    /// Monotonic timestamp of the last synchronization before the step
+    since: u64,
+    /// Monotonic timestamp of the synchronization which detected the step
+    until: u64,
+    previous_offset: i64,
+}
+
+impl ClockSync {
+    /// Measure the offset right away: threads converting timestamps
+    /// concurrently wait for it in [`OnceLock::get_or_init`].
+    fn new() -> Self {
+        Self {
+            offset: AtomicI64::new(measure_offset()),
+            last_sync: AtomicU64::new(Timestamp::now().raw()),
+            last_step: Mutex::new(None),
+        }
+    }
+
+    /// Return the offset, measuring it again if it's older than
+    /// [`RESYNC_INTERVAL`].
+    fn offset(&self) -> i64 {
+        let now = Timestamp::now().raw();
+        let last_sync = self.last_sync.load(Ordering::Relaxed);
+        // Only one thread measures the offset, the others use the current one
+        if now.saturating_sub(last_sync) >= RESYNC_INTERVAL
+            && self
+                .last_sync
+                .compare_exchange(last_sync, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let offset = measure_offset();
+            if let Some(step) = self.update_offset(offset) {
+                log::warn!(
+                    "Wall clock changed by {:.3}s, event times follow the new clock",
+                    step as f64 / 1e9
+                );
+                *self.last_step.lock().unwrap() = Some(Step {
+                    since: last_sync,
+                    until: now,
+                    previous_offset: offset - step,
+                });
+            }
+        }
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// Store a new offset, returning the difference from the previous one
+    /// when it's a clock step.
+    fn update_offset(&self, offset: i64) -> Option<i64> {
+        let previous = self.offset.swap(offset, Ordering::Relaxed);
+        let step = offset - previous;
+        (step.unsigned_abs() > STEP_THRESHOLD.as_nanos() as u64).then_some(step)
+    }
+}
+
+/// Read the wall clock between two reads of the monotonic clock.
+fn measure_offset() -> i64 {
+    let before = Timestamp::now().raw();
+    let realtime = clock_gettime(ClockId::CLOCK_REALTIME)
+        .map(|t| t.num_nanoseconds())
+        .unwrap_or(0);
+    let after = Timestamp::now().raw();
+    realtime - (before + (after - before) / 2) as i64
+}
+
 /// Convert a eBPF timestamp (nanos since boot) to a SystemTime.
+///
+/// The offset between the clocks is measured again every second: the monotonic
+/// clock stops during a suspend and NTP adjusts the wall clock.
 impl From<Timestamp> for SystemTime {
     fn from(event_timestamp: Timestamp) -> Self {
-        // The monotonic clock will stop in case of a suspend, so we must check it
-        // every time and compare it to the event time.
-        let elapsed_since_event = Timestamp::now().raw() - event_timestamp.raw();
-        SystemTime::now() - Duration::from_nanos(elapsed_since_event)
+        to_system_time(event_timestamp.raw(), clock().offset())
+    }
+}
+
+fn offset_before_step(timestamp: u64, offset: i64, step: Option<Step>) -> i64 {
+    match step {
+        Some(step) if (step.since..=step.until).contains(&timestamp) => step.previous_offset,
+        _ => offset,
     }
 }
 
+fn to_system_time(timestamp: u64, offset: i64) -> SystemTime {
+    let nanos = timestamp as i64 + offset;
+    UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock() {
+        let converted = SystemTime::from(Timestamp::now());
+        let now = SystemTime::now();
+        let difference = now
+            .duration_since(converted)
+            .unwrap_or_else(|err| err.duration());
+        assert!(difference < Duration::from_millis(10), "{difference:?}");
+    }
+
+    #[test]
+    fn clock_steps() {
+        // Not the global clock, used by the other tests
+        let clock = ClockSync::new();
+        let offset = clock.offset.load(Ordering::Relaxed);
+        assert_ne!(offset, 0);
+        // Small adjustments are not steps
+        assert_eq!(clock.update_offset(offset + 1_000_000), None);
+        let step = Duration::from_secs(2).as_nanos() as i64;
+        assert_eq!(clock.update_offset(offset + step), Some(step - 1_000_000));
+    }
+
+    #[test]
+    fn offset_before_a_step() {
+        let step = Some(Step {
+            since: 1_000,
+            until: 2_000,
+            previous_offset: 10,
+        });
+        assert_eq!(offset_before_step(1_500, 20, step), 10);
+        assert_eq!(offset_before_step(2_000, 20, step), 10);
+        // Events after the detection of the step follow the new clock
+        assert_eq!(offset_before_step(2_500, 20, step), 20);
+        assert_eq!(offset_before_step(500, 20, step), 20);
+        assert_eq!(offset_before_step(1_500, 20, None), 20);
+    }
+}
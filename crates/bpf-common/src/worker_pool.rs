@@ -0,0 +1,176 @@
+//! Pool of workers for the expensive processing of events, like parsing DNS or
+//! TLS messages or hashing files.
+//!
+//! [`crate::BpfSender::send`] runs on the task reading the perf buffer of a
+//! CPU: slow processing there delays the following events and lets the buffer
+//! overflow. A [`WorkerPool`] moves it to a fixed number of Tokio tasks, each
+//! with a bounded queue. Items are assigned to a worker by key, so the ones
+//! with the same key, like the events of a socket, are processed in the order
+//! they were dispatched: a worker awaits the processing of an item, like
+//! sending its events, before starting the next one.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::mpsc;
+
+/// Items dropped because the queue of their worker was full, on every pool.
+/// They're counted in [`crate::program::lost_events`] too, since they hold
+/// the events of the probes.
+static DROPPED_ITEMS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of items dropped by the worker pools.
+pub fn dropped_items() -> u64 {
+    DROPPED_ITEMS.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerPoolConfig {
+    /// Number of workers
+    pub workers: usize,
+    /// Items waiting in the queue of every worker after which new ones are
+    /// dropped
+    pub queue_size: usize,
+}
+
+impl Default for WorkerPoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_size: 1024,
+        }
+    }
+}
+
+pub struct WorkerPool<T> {
+    queues: Arc<[mpsc::Sender<T>]>,
+}
+
+impl<T> Clone for WorkerPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Start the workers, each calling its clone of `process` on the items
+    /// assigned to it and awaiting the returned future. Must be called within
+    /// a Tokio runtime. The workers exit when every clone of the pool is
+    /// dropped.
+    pub fn start<F, Fut>(config: WorkerPoolConfig, process: F) -> Self
+    where
+        F: FnMut(T) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let queues: Vec<_> = (0..config.workers.max(1))
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel(config.queue_size.max(1));
+                let mut process = process.clone();
+                tokio::spawn(async move {
+                    while let Some(item) = rx.recv().await {
+                        process(item).await;
+                    }
+                });
+                tx
+            })
+            .collect();
+        Self {
+            queues: queues.into(),
+        }
+    }
+
+    /// Queue the item on the worker of `key`. Never blocks: returns false if
+    /// the item was dropped because the queue is full.
+    pub fn dispatch(&self, key: impl Hash, item: T) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let queue = &self.queues[hasher.finish() as usize % self.queues.len()];
+        match queue.try_send(item) {
+            Ok(()) => true,
+            Err(_) => {
+                crate::program::count_lost_events(1);
+                if DROPPED_ITEMS.fetch_add(1, Ordering::Relaxed) == 0 {
+                    log::warn!("Worker queue full: dropping events");
+                }
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ordered_by_key() {
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let pool = WorkerPool::start(
+            WorkerPoolConfig {
+                workers: 3,
+                queue_size: 100,
+            },
+            {
+                let processed = processed.clone();
+                move |(key, value): (u32, u32)| {
+                    let processed = processed.clone();
+                    let done_tx = done_tx.clone();
+                    async move {
+                        // Items of other keys may run meanwhile on other workers
+                        tokio::task::yield_now().await;
+                        processed.lock().unwrap().push((key, value));
+                        let _ = done_tx.send(());
+                    }
+                }
+            },
+        );
+        for value in 0..20 {
+            for key in 0..5 {
+                assert!(pool.dispatch(key, (key, value)));
+            }
+        }
+        for _ in 0..100 {
+            done_rx.recv().await.unwrap();
+        }
+
+        let processed = processed.lock().unwrap();
+        for key in 0..5 {
+            let values: Vec<u32> = processed
+                .iter()
+                .filter(|(item_key, _)| *item_key == key)
+                .map(|(_, value)| *value)
+                .collect();
+            assert_eq!(values, (0..20).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue() {
+        // The worker doesn't run before the test yields
+        let pool = WorkerPool::start(
+            WorkerPoolConfig {
+                workers: 1,
+                queue_size: 2,
+            },
+            |_: u32| async {},
+        );
+        assert!(pool.dispatch(0, 1));
+        assert!(pool.dispatch(0, 2));
+        let lost = crate::program::lost_events();
+        assert!(!pool.dispatch(0, 3));
+        assert!(dropped_items() >= 1);
+        assert!(crate::program::lost_events() > lost);
+    }
+}
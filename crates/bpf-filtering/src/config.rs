@@ -1,7 +1,7 @@
 use bpf_common::Pid;
-use pulsar_core::pdk::{ConfigError, ModuleConfig};
+use pulsar_core::pdk::{ConfigError, ConfigField, ConfigKind, ModuleConfig};
 
-use crate::maps::{DEFAULT_CGROUP_RULES, DEFAULT_INTEREST, DEFAULT_RULES};
+use crate::maps::{DEFAULT_CGROUP_RULES, DEFAULT_INTEREST, DEFAULT_PROCESS_INFO, DEFAULT_RULES};
 
 use super::maps::Image;
 
@@ -22,6 +22,8 @@ pub struct Config {
     /// Map name of the rules map
     pub rule_map_name: String,
     pub cgroup_rule_map_name: String,
+    /// Map name of the shared process metadata map
+    pub process_info_map_name: String,
     /// Sets the default tracking status for Pid 1 and when finding missing entries.
     pub track_by_default: bool,
     /// Whitelist the current process
@@ -49,6 +51,49 @@ pub struct PidRule {
 pub const MAX_IMAGE_LEN: usize = 100;
 pub const MAX_CGROUP_LEN: usize = 300;
 
+impl Config {
+    /// Schema of the filtering fields, to be included in the module configuration schema.
+    pub fn schema_fields() -> Vec<ConfigField> {
+        vec![
+            ConfigField::new("pid_targets", ConfigKind::List, "Process ids to track"),
+            ConfigField::new(
+                "pid_targets_children",
+                ConfigKind::List,
+                "Process ids to track together with their children",
+            ),
+            ConfigField::new("targets", ConfigKind::List, "Images to track"),
+            ConfigField::new(
+                "targets_children",
+                ConfigKind::List,
+                "Images to track together with their children",
+            ),
+            ConfigField::new("whitelist", ConfigKind::List, "Images to ignore"),
+            ConfigField::new(
+                "whitelist_children",
+                ConfigKind::List,
+                "Images to ignore together with their children",
+            ),
+            ConfigField::new(
+                "cgroup_targets",
+                ConfigKind::List,
+                "Cgroup paths whose processes are always tracked",
+            ),
+            ConfigField::new(
+                "track_by_default",
+                ConfigKind::Bool,
+                "Track processes not matched by any rule",
+            )
+            .default_value(true),
+            ConfigField::new(
+                "ignore_self",
+                ConfigKind::Bool,
+                "Ignore the pulsar process itself",
+            )
+            .default_value(true),
+        ]
+    }
+}
+
 /// Extract Config from configuration file
 impl TryFrom<&ModuleConfig> for Config {
     type Error = ConfigError;
@@ -89,6 +134,7 @@ impl TryFrom<&ModuleConfig> for Config {
             interest_map_name: DEFAULT_INTEREST.to_string(),
             rule_map_name: DEFAULT_RULES.to_string(),
             cgroup_rule_map_name: DEFAULT_CGROUP_RULES.to_string(),
+            process_info_map_name: DEFAULT_PROCESS_INFO.to_string(),
             track_by_default: config.with_default("track_by_default", true)?,
             ignore_self: config.with_default("ignore_self", true)?,
         })
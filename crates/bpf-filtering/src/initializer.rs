@@ -1,17 +1,21 @@
-use std::{os::unix::prelude::OsStringExt, time::Duration};
+use std::{
+    fs,
+    os::unix::prelude::{MetadataExt, OsStringExt},
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
-use bpf_common::{aya::Bpf, Pid};
+use bpf_common::{aya::Bpf, parsing::procfs, Pid};
 use pulsar_core::{
-    pdk::process_tracker::{ProcessTrackerHandle, TrackerUpdate},
+    pdk::process_tracker::{ProcessTrackerHandle, StdinKind, TerminalInfo, TrackerUpdate},
     Timestamp,
 };
 use tokio::sync::mpsc;
 
-use crate::maps::{Cgroup, Map};
+use crate::maps::{Cgroup, Map, ProcessInfo, ProcessInfoMap, PROCESS_INFO_TTY};
 
 use super::{
-    config::{Config, Rule},
+    config::{Config, Rule, MAX_IMAGE_LEN},
     maps::InterestMap,
     maps::{Image, PolicyDecision, RuleMap},
     process_tree::{ProcessData, ProcessTree, PID_0},
@@ -82,6 +86,7 @@ pub async fn setup_events_filter(
             timestamp: Timestamp::from(0),
             argv: Vec::new(),
             namespaces: process.namespaces,
+            terminal: terminal_info(process.pid),
         });
     }
 
@@ -111,9 +116,20 @@ pub async fn setup_events_filter(
     Ok(())
 }
 
-/// Initializer of map_interest
+/// Read the terminal details of a running process from procfs.
+fn terminal_info(pid: Pid) -> TerminalInfo {
+    TerminalInfo {
+        has_tty: procfs::get_process_has_tty(pid).unwrap_or_default(),
+        stdin: fs::metadata(format!("/proc/{pid}/fd/0"))
+            .map(|metadata| StdinKind::from_mode(metadata.mode()))
+            .unwrap_or_default(),
+    }
+}
+
+/// Initializer of map_interest and of the shared process info map
 struct Initializer {
     interest_map: InterestMap,
+    process_info_map: ProcessInfoMap,
     cache: std::collections::HashMap<Pid, PolicyDecision>,
     config: Config,
 }
@@ -123,16 +139,23 @@ impl Initializer {
         let mut interest_map = InterestMap::load(bpf, &config.interest_map_name)?;
         // clear interest map
         interest_map.clear()?;
+        let mut process_info_map = ProcessInfoMap::load(bpf, &config.process_info_map_name)?;
+        process_info_map.clear()?;
         let cache = Default::default();
 
         Ok(Self {
             interest_map,
+            process_info_map,
             cache,
             config,
         })
     }
 
     fn update(&mut self, process: &ProcessData) -> Result<()> {
+        // A missing entry only loses the metadata of that process
+        if let Err(err) = self.update_process_info(process) {
+            log::warn!("Process info of {} not initialized: {:?}", process.pid, err);
+        }
         // If we're already tracking a process, we don't want to override that decision and stop
         // tracking it. This is useful for cgroups, which are checked before process hierarchy
         if matches!(
@@ -189,6 +212,21 @@ impl Initializer {
         self.set_policy(process.pid, decision)
     }
 
+    fn update_process_info(&mut self, process: &ProcessData) -> Result<()> {
+        let image = process.image.as_bytes();
+        let mut info = ProcessInfo {
+            ppid: process.parent.as_raw(),
+            mnt_ns: process.namespaces.mnt,
+            pid_ns: process.namespaces.pid,
+            flags: 0,
+            image: Image::try_from(image[..image.len().min(MAX_IMAGE_LEN - 1)].to_vec())?,
+        };
+        if terminal_info(process.pid).has_tty {
+            info.flags |= PROCESS_INFO_TTY;
+        }
+        self.process_info_map.set(process.pid, info)
+    }
+
     fn set_policy(&mut self, pid: Pid, policy: PolicyDecision) -> Result<()> {
         log::trace!("Set policy for {}: {}", pid, policy.as_raw());
         self.cache.insert(pid, policy);
@@ -44,6 +44,12 @@
 //!   - If exec image is in target/whitelist we set `interesting[my pid].interesting = track`
 //!   - if exec image is in target/whitelist and extended to children, we set `interesting[my pid].children_interesting = track`
 //!
+//! **`(pinned) process_info: HashMap<Pid, struct { ppid, mnt_ns, pid_ns, flags, image }>`**
+//! - Contains the metadata of all processes, shared by the probes of all modules
+//! - It's initialized by userspace on startup by checking `procfs`
+//! - It's updated by `process_monitor` on fork/exec/exit events
+//! - It's sized with `maps::process_info_max_entries` by every probe declaring it
+//!
 //! ## Startup procedure
 //!
 //! We empty the `interesting` map.
@@ -13,6 +13,9 @@ pub struct InterestMap(pub(crate) Map<i32, u8>);
 /// Default name for map interest
 pub const DEFAULT_INTEREST: &str = "m_interest";
 
+/// Default name for the shared process metadata map
+pub const DEFAULT_PROCESS_INFO: &str = "m_process_info";
+
 /// Default name for rules map
 pub const DEFAULT_RULES: &str = "m_rules";
 pub const DEFAULT_CGROUP_RULES: &str = "m_cgroup_rules";
@@ -38,6 +41,66 @@ impl InterestMap {
     }
 }
 
+/// Size of the process info map used when the kernel limits can't be read
+const DEFAULT_PROCESS_INFO_ENTRIES: u32 = 16384;
+
+/// Size of the process info map, to be passed to
+/// `ProgramBuilder::max_entries` by every probe declaring it: room for as
+/// many processes as the host can run, which is the lowest of `pid_max` and
+/// `threads-max`. The map isn't preallocated, so only the running processes
+/// use memory.
+pub fn process_info_max_entries() -> u32 {
+    let read_limit =
+        |path: &str| -> Option<u32> { std::fs::read_to_string(path).ok()?.trim().parse().ok() };
+    ["/proc/sys/kernel/pid_max", "/proc/sys/kernel/threads-max"]
+        .into_iter()
+        .filter_map(read_limit)
+        .min()
+        .unwrap_or(DEFAULT_PROCESS_INFO_ENTRIES)
+}
+
+/// Process has a controlling terminal, see [`ProcessInfo::flags`]
+pub const PROCESS_INFO_TTY: u32 = 1;
+
+/// Metadata of a process shared with all the eBPF probes, see
+/// `process_info.bpf.h`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessInfo {
+    pub ppid: i32,
+    pub mnt_ns: u32,
+    pub pid_ns: u32,
+    pub flags: u32,
+    pub image: Image,
+}
+
+unsafe impl aya::Pod for ProcessInfo {}
+
+/// This map contains the metadata of every running process. It's kept up to
+/// date by `process_monitor` and read by the other probes.
+pub struct ProcessInfoMap(pub(crate) Map<i32, ProcessInfo>);
+
+impl ProcessInfoMap {
+    /// Try to load the map from eBPF
+    pub fn load(bpf: &mut aya::Bpf, name: &str) -> Result<Self> {
+        Map::load(bpf, name).map(Self)
+    }
+
+    /// Clear the map
+    pub fn clear(&mut self) -> Result<()> {
+        self.0.clear()
+    }
+
+    /// Set the metadata of a given process
+    pub fn set(&mut self, pid: Pid, info: ProcessInfo) -> Result<()> {
+        self.0
+            .map
+            .insert(pid.as_raw(), info, 0)
+            .context("Error inserting entry in process info map")?;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct PolicyDecision {
     pub interesting: bool,
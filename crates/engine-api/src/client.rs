@@ -4,12 +4,19 @@ use anyhow::{anyhow, bail, ensure, Context, Result};
 use futures::{Stream, StreamExt};
 use hyper::{Body, Method, Request, StatusCode, Uri};
 use hyperlocal::{UnixClientExt, UnixConnector};
-use pulsar_core::pdk::{Event, ModuleOverview};
-use serde::de::DeserializeOwned;
+use pulsar_core::{
+    acknowledgments::Acknowledgment,
+    history::EventFilter,
+    listeners::Listener,
+    loadgen::{LoadgenConfig, LoadgenReport},
+    pdk::{Event, ModuleOverview},
+    rule_stats::RuleProfile,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use tokio_tungstenite::{client_async, tungstenite::Message};
 
 use crate::{
-    dto::{ConfigKV, ModuleConfigKVs},
+    dto::{AcknowledgeRequest, ConfigKV, ModuleConfigKVs, ProbeStats},
     error::WebsocketError,
 };
 
@@ -81,6 +88,35 @@ impl EngineApiClient {
         Ok(output)
     }
 
+    async fn post<B: Serialize, T: DeserializeOwned>(&self, uri: Uri, body: &B) -> Result<T> {
+        let body_string = serde_json::to_string(body)
+            .map_err(|err| anyhow!("Error during object serialization. Reason: {err}"))?;
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body_string))
+            .map_err(|err| anyhow!("Error building the request. Reason: {}", err))?;
+
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|err| anyhow!("Error during the http request. Reason: {}", err))?;
+
+        let status = res.status();
+        let buf = hyper::body::to_bytes(res).await?;
+        match status {
+            StatusCode::OK => Ok(serde_json::from_slice(&buf)?),
+            _ => {
+                let error = std::str::from_utf8(&buf)
+                    .map_err(|err| anyhow!("Cannot parse error str. Reason: {}", err))?;
+                Err(anyhow!("Error during request. {error}"))
+            }
+        }
+    }
+
     pub async fn list_modules(&self) -> Result<Vec<ModuleOverview>> {
         let url = self.uri("/modules");
         self.get(url).await
@@ -106,11 +142,72 @@ impl EngineApiClient {
         self.empty_post(url).await
     }
 
+    pub async fn boot_complete(&self) -> Result<()> {
+        let url = self.uri("/boot/complete");
+        self.empty_post(url).await
+    }
+
     pub async fn get_module_config(&self, module_name: &str) -> Result<Vec<ConfigKV>> {
         let url = self.uri(format!("/modules/{module_name}/config"));
         self.get(url).await
     }
 
+    /// Get the events kept in the history of the daemon.
+    pub async fn get_events(&self, filter: &EventFilter) -> Result<Vec<Event>> {
+        let query: Vec<_> = [
+            ("pid", filter.pid.map(|pid| pid.to_string())),
+            ("since", filter.since.map(|since| since.to_string())),
+            ("until", filter.until.map(|until| until.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(format!("{key}={}", value?)))
+        .collect();
+        let url = self.uri(format!("/events?{}", query.join("&")));
+        self.get(url).await
+    }
+
+    /// Get the sockets listening on the host, see [`pulsar_core::listeners`].
+    pub async fn listeners(&self) -> Result<Vec<Listener>> {
+        let url = self.uri("/listeners");
+        self.get(url).await
+    }
+
+    /// Send synthetic events on the bus of the daemon, see [`pulsar_core::loadgen`].
+    pub async fn loadgen(&self, config: &LoadgenConfig) -> Result<LoadgenReport> {
+        let url = self.uri("/loadgen");
+        self.post(url, config).await
+    }
+
+    /// Get the run time statistics of the eBPF programs of the daemon.
+    pub async fn probe_stats(&self) -> Result<ProbeStats> {
+        let url = self.uri("/probe-stats");
+        self.get(url).await
+    }
+
+    /// Get the evaluation statistics of the rules, see [`pulsar_core::rule_stats`].
+    pub async fn rules_stats(&self) -> Result<Vec<RuleProfile>> {
+        let url = self.uri("/rules/stats");
+        self.get(url).await
+    }
+
+    /// Get the threats acknowledged on the daemon, see [`pulsar_core::acknowledgments`].
+    pub async fn threat_acknowledgments(&self) -> Result<Vec<Acknowledgment>> {
+        let url = self.uri("/threats/acknowledgments");
+        self.get(url).await
+    }
+
+    /// Silence the threats with the given id for a while.
+    pub async fn acknowledge_threat(&self, request: &AcknowledgeRequest) -> Result<Acknowledgment> {
+        let url = self.uri("/threats/acknowledgments");
+        self.post(url, request).await
+    }
+
+    /// Notify again the threats with the given id.
+    pub async fn unacknowledge_threat(&self, id: &str) -> Result<()> {
+        let url = self.uri(format!("/threats/acknowledgments/{id}"));
+        self.empty_request(Method::DELETE, url).await
+    }
+
     pub async fn set_module_config(
         &self,
         module_name: &str,
@@ -154,8 +251,12 @@ impl EngineApiClient {
     }
 
     async fn empty_post(&self, uri: Uri) -> Result<()> {
+        self.empty_request(Method::POST, uri).await
+    }
+
+    async fn empty_request(&self, method: Method, uri: Uri) -> Result<()> {
         let req = Request::builder()
-            .method(Method::POST)
+            .method(method)
             .uri(uri)
             .body(Body::empty())
             .map_err(|err| anyhow!("Error building the request. Reason: {}", err))?;
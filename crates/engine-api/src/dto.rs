@@ -11,3 +11,28 @@ pub struct ModuleConfigKVs {
     pub module: String,
     pub config: Vec<ConfigKV>,
 }
+
+/// Run time statistics of the eBPF programs, see [`bpf_common::program_stats`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeStats {
+    /// The kernel is collecting the statistics
+    pub enabled: bool,
+    pub programs: Vec<ProgramStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProgramStats {
+    pub probe: String,
+    pub program: String,
+    pub run_count: u64,
+    pub run_time_ns: u64,
+}
+
+/// Acknowledge the threats with `id`, see [`pulsar_core::acknowledgments`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AcknowledgeRequest {
+    pub id: String,
+    pub ttl_secs: u64,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
@@ -60,6 +60,8 @@ impl From<PulsarDaemonError> for EngineApiError {
         match &error {
             PulsarDaemonError::ModuleNotFound(_) => Self::BadRequest(error.to_string()),
             PulsarDaemonError::StopError(_) => Self::BadRequest(error.to_string()),
+            PulsarDaemonError::InvalidConfiguration(_) => Self::BadRequest(error.to_string()),
+            PulsarDaemonError::DependencyNotRunning { .. } => Self::BadRequest(error.to_string()),
             PulsarDaemonError::ConfigurationUpdateError(_) => {
                 log::error!("Unexpected Error {}", error.to_string());
                 Self::InternalServerError
@@ -1,23 +1,31 @@
 use std::{
+    os::unix::io::{FromRawFd, RawFd},
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use anyhow::{anyhow, Result};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        Path, Query, State,
     },
     response::Response,
-    routing::{get, patch, post},
+    routing::{delete, get, patch, post},
     BoxError, Json, Router,
 };
+use bpf_common::program_stats;
 use futures::ready;
 use hyper::server::accept::Accept;
 use pulsar_core::{
+    acknowledgments::{self, Acknowledgment},
     bus::Bus,
-    pdk::{ModuleOverview, PulsarDaemonHandle},
+    history::{EventFilter, EventHistory},
+    listeners::{Listener, ListenerTable},
+    loadgen::{self, LoadgenConfig, LoadgenReport},
+    pdk::{Event, ModuleOverview, PulsarDaemonHandle},
+    rule_stats::{self, RuleProfile},
 };
 use tokio::{
     net::{UnixListener, UnixStream},
@@ -26,10 +34,13 @@ use tokio::{
 };
 
 use crate::{
-    dto::{ConfigKV, ModuleConfigKVs},
+    dto::{AcknowledgeRequest, ConfigKV, ModuleConfigKVs, ProbeStats, ProgramStats},
     error::EngineApiError,
 };
 
+/// First file descriptor passed by systemd socket activation, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
 pub struct ServerHandle {
     tx_shutdown: oneshot::Sender<()>,
     server_join_handle: JoinHandle<()>,
@@ -46,6 +57,8 @@ impl ServerHandle {
 pub struct EngineAPIContext {
     pub bus: Bus,
     pub pulsar_daemon: PulsarDaemonHandle,
+    pub history: EventHistory,
+    pub listeners: ListenerTable,
 }
 
 pub fn run_api_server(
@@ -64,13 +77,32 @@ pub fn run_api_server(
         .nest("/modules", modules)
         .route("/configs", get(configs))
         .route("/monitor", get(event_monitor_handler))
+        .route("/events", get(events))
+        .route("/listeners", get(listeners))
+        .route("/loadgen", post(run_loadgen))
+        .route("/probe-stats", get(probe_stats))
+        .route("/rules/stats", get(rules_stats))
+        .route("/threats/acknowledgments", get(threat_acknowledgments))
+        .route("/threats/acknowledgments", post(acknowledge_threat))
+        .route("/threats/acknowledgments/:id", delete(unacknowledge_threat))
+        .route("/boot/complete", post(boot_complete))
         .with_state(engine_api_ctx);
 
-    let socket_path = custom_socket_path.unwrap_or(super::DEFAULT_UDS).to_string();
-
-    let uds =
-        UnixListener::bind(&socket_path).map_err(|err| anyhow!("Cannot bind to socket: {err}"))?;
-    log::debug!("listening on {}", socket_path);
+    // The socket is owned by systemd when it's passed with socket activation,
+    // otherwise we create it and remove it on exit.
+    let (uds, socket_path) = match activated_socket()? {
+        Some(uds) => {
+            log::debug!("listening on socket passed by systemd");
+            (uds, None)
+        }
+        None => {
+            let socket_path = custom_socket_path.unwrap_or(super::DEFAULT_UDS).to_string();
+            let uds = UnixListener::bind(&socket_path)
+                .map_err(|err| anyhow!("Cannot bind to socket: {err}"))?;
+            log::debug!("listening on {}", socket_path);
+            (uds, Some(socket_path))
+        }
+    };
 
     let (tx_shutdown, rx_shutdown) = oneshot::channel();
 
@@ -84,9 +116,11 @@ pub fn run_api_server(
         if let Err(e) = server.await {
             log::error!("Engine Api server error: {}", e);
         }
-        if let Err(e) = tokio::fs::remove_file(socket_path).await {
-            log::error!("Error removing unix socket: {}", e);
-        };
+        if let Some(socket_path) = socket_path {
+            if let Err(e) = tokio::fs::remove_file(socket_path).await {
+                log::error!("Error removing unix socket: {}", e);
+            };
+        }
     });
 
     let server_handle = ServerHandle {
@@ -97,6 +131,39 @@ pub fn run_api_server(
     Ok(server_handle)
 }
 
+/// Get the listening socket passed by systemd socket activation, if any.
+fn activated_socket() -> Result<Option<UnixListener>> {
+    let listen_pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    let listen_fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+    if listen_pid != Some(std::process::id()) || listen_fds == 0 {
+        return Ok(None);
+    }
+    // Don't pass the socket to child processes
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    if unsafe { libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(anyhow!(
+            "Invalid socket passed by systemd: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    // SAFETY: systemd passes the listening sockets starting from SD_LISTEN_FDS_START
+    // and we checked they're meant for this process.
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(UnixListener::from_std(listener)?))
+}
+
+async fn boot_complete(State(ctx): State<EngineAPIContext>) {
+    ctx.pulsar_daemon.boot_complete().await;
+}
+
 async fn module_start(
     State(ctx): State<EngineAPIContext>,
     Path(module_name): Path<String>,
@@ -143,6 +210,45 @@ async fn configs(State(ctx): State<EngineAPIContext>) -> Json<Vec<ModuleConfigKV
     Json(cfgs_key_value)
 }
 
+async fn events(
+    State(ctx): State<EngineAPIContext>,
+    Query(filter): Query<EventFilter>,
+) -> Json<Vec<Event>> {
+    let events = ctx.history.query(&filter);
+    Json(events.iter().map(|event| (**event).clone()).collect())
+}
+
+async fn listeners(State(ctx): State<EngineAPIContext>) -> Json<Vec<Listener>> {
+    Json(ctx.listeners.snapshot())
+}
+
+async fn run_loadgen(
+    State(ctx): State<EngineAPIContext>,
+    Json(config): Json<LoadgenConfig>,
+) -> Json<LoadgenReport> {
+    Json(loadgen::run(&ctx.bus, &config).await)
+}
+
+async fn probe_stats() -> Json<ProbeStats> {
+    let programs = program_stats::program_stats()
+        .into_iter()
+        .map(|stats| ProgramStats {
+            probe: stats.probe,
+            program: stats.program,
+            run_count: stats.run_count,
+            run_time_ns: stats.run_time_ns,
+        })
+        .collect();
+    Json(ProbeStats {
+        enabled: program_stats::stats_enabled(),
+        programs,
+    })
+}
+
+async fn rules_stats() -> Json<Vec<RuleProfile>> {
+    Json(rule_stats::rule_profiles())
+}
+
 async fn get_module_cfg(
     State(ctx): State<EngineAPIContext>,
     Path(module_name): Path<String>,
@@ -172,7 +278,7 @@ async fn event_monitor_handler(
     State(ctx): State<EngineAPIContext>,
     ws: WebSocketUpgrade,
 ) -> Response {
-    let mut bus_receiver = ctx.bus.get_receiver();
+    let mut bus_receiver = ctx.bus.get_output_receiver();
 
     // This closure reads events from the bus receiver and sends them into the socket
     let handle_socket = |mut socket: WebSocket| async move {
@@ -217,3 +323,46 @@ impl Accept for ServerAccept {
         Poll::Ready(Some(Ok(stream)))
     }
 }
+
+async fn threat_acknowledgments() -> Json<Vec<Acknowledgment>> {
+    Json(acknowledgments::acknowledgments())
+}
+
+async fn acknowledge_threat(
+    Json(request): Json<AcknowledgeRequest>,
+) -> Result<Json<Acknowledgment>, EngineApiError> {
+    if request.id.is_empty() {
+        return Err(EngineApiError::BadRequest("empty threat id".to_string()));
+    }
+    if request.ttl_secs == 0 {
+        return Err(EngineApiError::BadRequest(
+            "the ttl must be at least one second".to_string(),
+        ));
+    }
+    log::info!(
+        "Threat {} acknowledged for {}s{}",
+        request.id,
+        request.ttl_secs,
+        request
+            .reason
+            .as_ref()
+            .map(|reason| format!(": {reason}"))
+            .unwrap_or_default()
+    );
+    Ok(Json(acknowledgments::acknowledge(
+        &request.id,
+        Duration::from_secs(request.ttl_secs),
+        request.reason,
+    )))
+}
+
+async fn unacknowledge_threat(Path(id): Path<String>) -> Result<(), EngineApiError> {
+    if acknowledgments::unacknowledge(&id) {
+        log::info!("Threat {id} no longer acknowledged");
+        Ok(())
+    } else {
+        Err(EngineApiError::BadRequest(format!(
+            "threat {id} is not acknowledged"
+        )))
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{
+    event::{Severity, Value},
+    pdk::{
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+        ModuleError, PulsarModule, ShutdownSignal, Version,
+    },
+};
+
+pub mod token;
+pub mod watcher;
+
+use token::CanaryToken;
+use watcher::Watcher;
+
+const MODULE_NAME: &str = "canary";
+const DEFAULT_TOKEN_PATH: &str = "/var/lib/pulsar/canary_token";
+const DEFAULT_CORRELATION_WINDOW: u64 = 60;
+/// Interval between the cleanups of the processes followed by the watcher.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        canary_task,
+    )
+    // Accesses to the files and connections of their readers
+    .depends_on("file-system-monitor")
+    .depends_on("network-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(ConfigField::new(
+                "files",
+                ConfigKind::List,
+                "Canary files created with fake credentials, raising a threat when accessed",
+            ))
+            .field(ConfigField::new(
+                "env_file",
+                ConfigKind::Path,
+                "File with the fake credentials as environment variables, to load in services",
+            ))
+            .field(
+                ConfigField::new(
+                    "token_path",
+                    ConfigKind::Path,
+                    "File where the generated fake credentials are saved",
+                )
+                .default_value(DEFAULT_TOKEN_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "correlation_window",
+                    ConfigKind::Integer,
+                    "Seconds after opening a canary file in which contacting a remote host is an exfiltration",
+                )
+                .default_value(DEFAULT_CORRELATION_WINDOW)
+                .range(0, 86400),
+            )
+            .field(ConfigField::new(
+                "ignored_images",
+                ConfigKind::List,
+                "Executables allowed to read the canary files, like backup agents",
+            )),
+    )
+}
+
+async fn canary_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    let mut watcher = deploy(&rx_config.read()?)?;
+    let mut expire_interval = tokio::time::interval(EXPIRE_INTERVAL);
+    // The files are written and read by the daemon itself
+    let own_pid = std::process::id() as i32;
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                watcher = deploy(&rx_config.read()?)?;
+            }
+            _ = expire_interval.tick() => watcher.expire(SystemTime::now()),
+            event = receiver.recv() => {
+                let event = event?;
+                if event.header().threat.is_some() || event.header().pid == own_pid {
+                    continue;
+                }
+                for alert in watcher.check(&event) {
+                    sender.send_threat_derived(
+                        &event,
+                        alert.description(),
+                        Severity::Critical,
+                        Value::try_from(alert).ok(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Deploy the canaries of the configuration and watch them.
+fn deploy(config: &Config) -> Result<Watcher, ModuleError> {
+    let token = CanaryToken::load_or_create(&config.token_path)?;
+    let files = token::deploy(&config.files, &token);
+    if let Some(env_file) = &config.env_file {
+        write_env_file(env_file, &token);
+    }
+    log::info!("Watching {} canary files", files.len());
+    Ok(Watcher::new(
+        &files,
+        token,
+        Duration::from_secs(config.correlation_window),
+        config.ignored_images.clone(),
+    ))
+}
+
+/// Write the environment file, which services load on startup: unlike the
+/// canary files, reading it is not a threat.
+fn write_env_file(path: &Path, token: &CanaryToken) {
+    if let Err(err) = token::write_private(path, &token.env()) {
+        log::warn!(
+            "Error writing canary environment file {}: {err}",
+            path.display()
+        );
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    files: Vec<PathBuf>,
+    env_file: Option<PathBuf>,
+    token_path: PathBuf,
+    correlation_window: u64,
+    ignored_images: Vec<String>,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            files: config.get_list("files")?,
+            env_file: config
+                .get_raw("env_file")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            token_path: config.with_default("token_path", PathBuf::from(DEFAULT_TOKEN_PATH))?,
+            correlation_window: config
+                .with_default("correlation_window", DEFAULT_CORRELATION_WINDOW)?,
+            ignored_images: config.get_list("ignored_images")?,
+        })
+    }
+}
@@ -0,0 +1,398 @@
+//! Detection of the accesses to the canaries.
+//!
+//! The file-system-monitor events tell which process opened, renamed or
+//! deleted a canary file. A process which opened one is then followed for
+//! `correlation_window` seconds, along with its descendants and the processes
+//! reading the pipes it writes, like `curl` in `cat credentials | curl`:
+//! connecting or sending data to a remote host in that time is reported as an
+//! exfiltration. The canary credentials are looked for in the command line and
+//! environment of new processes and in DNS queries.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{
+    event::Host,
+    pdk::{Event, Payload},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::token::CanaryToken;
+
+/// Access to a canary, reported as a threat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Alert {
+    /// A canary file was opened
+    Opened { path: String },
+    /// A canary file was renamed, linked or deleted
+    Tampered { path: String },
+    /// A process which opened a canary file contacted a remote host
+    Exfiltrated { path: String, destination: String },
+    /// The canary credentials were used, `location` tells where they were found
+    CredentialUsed { location: String },
+}
+
+impl Alert {
+    pub fn description(&self) -> String {
+        match self {
+            Alert::Opened { path } => format!("Canary file {path} opened"),
+            Alert::Tampered { path } => format!("Canary file {path} tampered with"),
+            Alert::Exfiltrated { path, destination } => {
+                format!("Canary file {path} possibly exfiltrated to {destination}")
+            }
+            Alert::CredentialUsed { location } => {
+                format!("Canary credentials used in {location}")
+            }
+        }
+    }
+}
+
+/// Canary file opened by a process, or passed to it by an ancestor or through
+/// a pipe, followed until `deadline`.
+#[derive(Debug, Clone)]
+struct Reader {
+    path: String,
+    deadline: SystemTime,
+}
+
+pub struct Watcher {
+    files: Vec<String>,
+    token: CanaryToken,
+    window: Duration,
+    /// Images of the processes allowed to read the canary files
+    ignored_images: Vec<String>,
+    /// Canary files read by every followed process
+    readers: HashMap<i32, Vec<Reader>>,
+}
+
+impl Watcher {
+    pub fn new(
+        files: &[PathBuf],
+        token: CanaryToken,
+        window: Duration,
+        ignored_images: Vec<String>,
+    ) -> Self {
+        Self {
+            files: files
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+            token,
+            window,
+            ignored_images,
+            readers: HashMap::new(),
+        }
+    }
+
+    /// Check the event, returning the accesses to the canaries it shows.
+    pub fn check(&mut self, event: &Event) -> Vec<Alert> {
+        let header = event.header();
+        if self.ignored_images.contains(&header.image) {
+            return Vec::new();
+        }
+        let mut alerts = Vec::new();
+        match event.payload() {
+            Payload::FileOpened { resolved_path, .. } => {
+                if let Some(path) = self.canary(resolved_path) {
+                    self.follow(
+                        header.pid,
+                        Reader {
+                            path: path.clone(),
+                            deadline: header.timestamp + self.window,
+                        },
+                    );
+                    alerts.push(Alert::Opened { path });
+                }
+            }
+            Payload::Fork { ppid, .. } => self.inherit(*ppid, header.pid),
+            Payload::PipeTransfer {
+                writer_pid,
+                reader_pid,
+                ..
+            } => self.inherit(*writer_pid, *reader_pid),
+            Payload::FileDeleted { filename } => {
+                alerts.extend(self.canary(filename).map(|path| Alert::Tampered { path }));
+            }
+            Payload::FileRename { source, .. } | Payload::FileLink { source, .. } => {
+                alerts.extend(self.canary(source).map(|path| Alert::Tampered { path }));
+            }
+            Payload::Connect { destination, .. } | Payload::Send { destination, .. } => {
+                alerts.extend(self.exfiltrations(header.pid, header.timestamp, destination));
+            }
+            Payload::Exec { argv, env, .. } => {
+                if argv.into_iter().any(|arg| self.token.found_in(arg)) {
+                    alerts.push(Alert::CredentialUsed {
+                        location: "command line".to_string(),
+                    });
+                }
+                if env.iter().any(|variable| self.token.found_in(variable)) {
+                    alerts.push(Alert::CredentialUsed {
+                        location: "environment".to_string(),
+                    });
+                }
+            }
+            Payload::DnsQuery { questions }
+                if questions
+                    .iter()
+                    .any(|question| self.token.found_in(&question.name)) =>
+            {
+                alerts.push(Alert::CredentialUsed {
+                    location: "DNS query".to_string(),
+                });
+            }
+            _ => {}
+        }
+        alerts
+    }
+
+    /// Forget the processes followed for longer than the correlation window.
+    pub fn expire(&mut self, now: SystemTime) {
+        self.readers.retain(|_, readers| {
+            readers.retain(|reader| reader.deadline > now);
+            !readers.is_empty()
+        });
+    }
+
+    /// Follow a process for a canary file, until the latest deadline if it
+    /// already read it.
+    fn follow(&mut self, pid: i32, reader: Reader) {
+        let readers = self.readers.entry(pid).or_default();
+        match readers.iter_mut().find(|known| known.path == reader.path) {
+            Some(known) => known.deadline = known.deadline.max(reader.deadline),
+            None => readers.push(reader),
+        }
+    }
+
+    /// Follow a process for the canary files read by another one, its parent
+    /// or the writer of a pipe it reads. The source isn't forgotten when it
+    /// exits, since the data may still be in the pipe.
+    fn inherit(&mut self, source: i32, pid: i32) {
+        if source == pid {
+            return;
+        }
+        let Some(readers) = self.readers.get(&source).cloned() else {
+            return;
+        };
+        for reader in readers {
+            self.follow(pid, reader);
+        }
+    }
+
+    fn canary(&self, path: &str) -> Option<String> {
+        self.files
+            .iter()
+            .find(|canary| canary.as_str() == path)
+            .cloned()
+    }
+
+    /// Report the first remote host contacted by a reader of canary files,
+    /// once for each of them.
+    fn exfiltrations(&mut self, pid: i32, time: SystemTime, destination: &Host) -> Vec<Alert> {
+        if is_local(destination.ip) {
+            return Vec::new();
+        }
+        let readers = self.readers.remove(&pid).unwrap_or_default();
+        readers
+            .into_iter()
+            .filter(|reader| time <= reader.deadline)
+            .map(|reader| Alert::Exfiltrated {
+                path: reader.path,
+                destination: format!("{}:{}", destination.ip, destination.port),
+            })
+            .collect()
+    }
+}
+
+fn is_local(ip: IpAddr) -> bool {
+    ip.is_loopback() || ip.is_unspecified()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use pulsar_core::event::{Argv, FileFlags, Header};
+
+    use super::*;
+
+    fn event(pid: i32, seconds: u64, payload: Payload) -> Event {
+        event_of("/usr/bin/python3", pid, seconds, payload)
+    }
+
+    fn event_of(image: &str, pid: i32, seconds: u64, payload: Payload) -> Event {
+        Event::new(
+            Header {
+                id: 1,
+                image: image.to_string(),
+                pid,
+                parent_pid: 1,
+                timestamp: UNIX_EPOCH + Duration::from_secs(seconds),
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    fn opened(path: &str) -> Payload {
+        Payload::FileOpened {
+            filename: path.to_string(),
+            flags: FileFlags::from_raw_unchecked(0),
+            mode: 0o100600,
+            fd: 3,
+            resolved_path: path.to_string(),
+        }
+    }
+
+    fn connect(ip: &str) -> Payload {
+        Payload::Connect {
+            destination: Host::from(std::net::SocketAddr::new(ip.parse().unwrap(), 443)),
+            is_tcp: true,
+        }
+    }
+
+    fn watcher() -> Watcher {
+        Watcher::new(
+            &[PathBuf::from("/root/.aws/credentials")],
+            CanaryToken {
+                key_id: "AKIA0123456789ABCDEF".to_string(),
+                secret: "s3cr3t".repeat(6),
+            },
+            Duration::from_secs(60),
+            vec!["/usr/bin/backup".to_string()],
+        )
+    }
+
+    #[test]
+    fn exfiltration() {
+        let mut watcher = watcher();
+        let path = "/root/.aws/credentials".to_string();
+        assert!(watcher
+            .check(&event(42, 0, opened("/etc/hosts")))
+            .is_empty());
+        assert_eq!(
+            watcher.check(&event(42, 0, opened(&path))),
+            [Alert::Opened { path: path.clone() }]
+        );
+        // Local connections are not exfiltrations
+        assert!(watcher
+            .check(&event(42, 1, connect("127.0.0.1")))
+            .is_empty());
+        assert!(watcher.check(&event(43, 1, connect("1.2.3.4"))).is_empty());
+        assert_eq!(
+            watcher.check(&event(42, 2, connect("1.2.3.4"))),
+            [Alert::Exfiltrated {
+                path: path.clone(),
+                destination: "1.2.3.4:443".to_string()
+            }]
+        );
+        // Reported once
+        assert!(watcher.check(&event(42, 3, connect("1.2.3.4"))).is_empty());
+
+        // After the correlation window
+        watcher.check(&event(44, 10, opened(&path)));
+        assert!(watcher
+            .check(&event(44, 100, connect("1.2.3.4")))
+            .is_empty());
+        watcher.check(&event(45, 10, opened(&path)));
+        watcher.expire(UNIX_EPOCH + Duration::from_secs(100));
+        assert!(watcher.readers.is_empty());
+    }
+
+    #[test]
+    fn followed_processes() {
+        let aws = "/root/.aws/credentials".to_string();
+        let ssh = "/root/.ssh/id_backup".to_string();
+        let mut watcher = Watcher::new(
+            &[PathBuf::from(&aws), PathBuf::from(&ssh)],
+            watcher().token,
+            Duration::from_secs(60),
+            Vec::new(),
+        );
+        let exfiltrated = |path: &str| Alert::Exfiltrated {
+            path: path.to_string(),
+            destination: "1.2.3.4:443".to_string(),
+        };
+
+        // A second canary doesn't replace the first one
+        watcher.check(&event(42, 0, opened(&aws)));
+        watcher.check(&event(42, 1, opened(&ssh)));
+        // Children of the reader are followed
+        watcher.check(&event(
+            43,
+            2,
+            Payload::Fork {
+                ppid: 42,
+                namespaces: Default::default(),
+            },
+        ));
+        assert_eq!(
+            watcher.check(&event(43, 3, connect("1.2.3.4"))),
+            [exfiltrated(&aws), exfiltrated(&ssh)]
+        );
+
+        // cat credentials | curl, reported even after cat exits
+        watcher.check(&event(50, 10, opened(&aws)));
+        watcher.check(&event(50, 10, Payload::Exit { exit_code: 0 }));
+        let transfer = Payload::PipeTransfer {
+            filename: String::new(),
+            inode: 1234,
+            writer_pid: 50,
+            reader_pid: 51,
+            anonymous: true,
+        };
+        watcher.check(&event(51, 11, transfer));
+        assert_eq!(
+            watcher.check(&event(51, 12, connect("1.2.3.4"))),
+            [exfiltrated(&aws)]
+        );
+        assert!(watcher.check(&event(52, 12, connect("1.2.3.4"))).is_empty());
+    }
+
+    #[test]
+    fn credentials() {
+        let mut watcher = watcher();
+        let exec = |argv: &[&str], env: Vec<String>| Payload::Exec {
+            filename: argv[0].to_string(),
+            argc: argv.len(),
+            argv: Argv::from(argv.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()),
+            namespaces: Default::default(),
+            script: String::new(),
+            shell: Default::default(),
+            env,
+            cwd: String::new(),
+            chrooted: false,
+            package: String::new(),
+            package_version: String::new(),
+            verified: false,
+            package_checked: false,
+        };
+        assert_eq!(
+            watcher.check(&event(
+                42,
+                0,
+                exec(
+                    &["curl", "-u", "AKIA0123456789ABCDEF:x", "https://x"],
+                    Vec::new()
+                )
+            )),
+            [Alert::CredentialUsed {
+                location: "command line".to_string()
+            }]
+        );
+        assert!(watcher
+            .check(&event(42, 0, exec(&["ls"], vec!["HOME=/root".to_string()])))
+            .is_empty());
+    }
+
+    #[test]
+    fn ignored_images() {
+        let mut watcher = watcher();
+        let backup = event_of("/usr/bin/backup", 42, 0, opened("/root/.aws/credentials"));
+        assert!(watcher.check(&backup).is_empty());
+    }
+}
@@ -8,8 +8,8 @@ use anyhow::{Context, Result};
 use pulsar_core::{
     event::Threat,
     pdk::{
-        CleanExit, ConfigError, Event, ModuleConfig, ModuleContext, ModuleError, PulsarModule,
-        ShutdownSignal, Version,
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+        ModuleContext, ModuleError, PulsarModule, ShutdownSignal, Version,
     },
 };
 
@@ -22,13 +22,44 @@ pub fn module() -> PulsarModule {
         false,
         desktop_nitifier_task,
     )
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "user_id",
+                    ConfigKind::Integer,
+                    "User running the notification",
+                )
+                .default_value(1000)
+                .range(0, u32::MAX.into()),
+            )
+            .field(
+                ConfigField::new("display", ConfigKind::String, "X11 display").default_value(":0"),
+            )
+            .field(
+                ConfigField::new(
+                    "notify_send_executable",
+                    ConfigKind::Path,
+                    "Executable used to send notifications",
+                )
+                .default_value("notify-send"),
+            )
+            .field(
+                ConfigField::new(
+                    "bus_address",
+                    ConfigKind::String,
+                    "DBus session bus address",
+                )
+                .default_value("unix:path=/run/user/<user_id>/bus"),
+            ),
+    )
 }
 
 async fn desktop_nitifier_task(
     ctx: ModuleContext,
     mut shutdown: ShutdownSignal,
 ) -> Result<CleanExit, ModuleError> {
-    let mut receiver = ctx.get_receiver();
+    let mut receiver = ctx.get_output_receiver();
     let mut rx_config = ctx.get_config();
     let mut config = rx_config.read()?;
 
@@ -51,7 +82,7 @@ async fn handle_event(config: &Config, event: Arc<Event>) {
     if let Some(Threat {
         source,
         description,
-        extra: _,
+        ..
     }) = &event.header().threat
     {
         let payload = event.payload();
@@ -0,0 +1,321 @@
+//! Statistics of the DNS questions by parent domain, to spot the data encoded
+//! in the subdomains of DNS tunnels. Questions are counted whatever the process
+//! sending them, so that a tunnel can't stay under the thresholds by spreading
+//! its queries across processes.
+//!
+//! Tunnels like iodine or dnscat2 send a large number of queries to the
+//! subdomains of a domain under control of the attacker, each one with a
+//! different and long subdomain carrying the data. Legitimate traffic queries
+//! a few names again and again, with short labels.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Distinct domains tracked in an interval, so that queries of random domains
+/// can't exhaust the memory. When full, the half queried the least is evicted.
+pub const MAX_DOMAINS: usize = 10_000;
+
+/// Distinct processes counted for a domain.
+pub const MAX_PROCESSES: usize = 64;
+
+/// Second level labels under which domains are registered in some country
+/// code top level domains, like `co.uk`.
+const GENERIC_SECOND_LEVEL: &[&str] = &["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+/// Values over which the queries to a domain look like a tunnel, in an
+/// interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thresholds {
+    /// Queries, whatever the subdomain
+    pub queries: u64,
+    /// Distinct subdomains, together with `label_length`
+    pub unique_subdomains: usize,
+    /// Average length of the labels of the subdomains
+    pub label_length: u64,
+}
+
+/// Extra data of the threats, with the statistics of the domain in the
+/// interval when the thresholds were crossed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsTunnelData {
+    pub domain: String,
+    /// Distinct processes which queried the domain, up to [`MAX_PROCESSES`]
+    pub processes: usize,
+    pub queries: u64,
+    pub unique_subdomains: usize,
+    pub average_label_length: f64,
+    /// Average Shannon entropy of the subdomains, in bits per character
+    pub average_entropy: f64,
+}
+
+#[derive(Debug, Default)]
+struct DomainStats {
+    queries: u64,
+    labels: u64,
+    label_bytes: u64,
+    /// Hashes of the subdomains, up to the threshold
+    subdomains: HashSet<u64>,
+    processes: HashSet<i32>,
+    entropy: f64,
+    reported: bool,
+}
+
+impl DomainStats {
+    fn average_label_length(&self) -> f64 {
+        if self.labels == 0 {
+            0.0
+        } else {
+            self.label_bytes as f64 / self.labels as f64
+        }
+    }
+
+    fn average_entropy(&self) -> f64 {
+        if self.subdomains.is_empty() {
+            0.0
+        } else {
+            self.entropy / self.subdomains.len() as f64
+        }
+    }
+}
+
+/// Questions counted by parent domain over an interval.
+#[derive(Debug)]
+pub struct Detector {
+    thresholds: Thresholds,
+    ignored_domains: Vec<String>,
+    stats: HashMap<String, DomainStats>,
+}
+
+impl Detector {
+    pub fn new(thresholds: Thresholds, ignored_domains: Vec<String>) -> Self {
+        Self {
+            thresholds,
+            ignored_domains,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Count a question of process `pid` for the normalized `name`, returning
+    /// the statistics of its parent domain when they cross the thresholds for
+    /// the first time in the interval.
+    pub fn record(&mut self, pid: i32, name: &str) -> Option<DnsTunnelData> {
+        let name = name.trim_end_matches('.');
+        let (subdomain, domain) = split_domain(name);
+        if domain.is_empty() || self.is_ignored(name) {
+            return None;
+        }
+        if !self.stats.contains_key(domain) && self.stats.len() >= MAX_DOMAINS {
+            self.evict();
+        }
+        let thresholds = &self.thresholds;
+        let stats = self.stats.entry(domain.to_string()).or_default();
+        stats.queries += 1;
+        if stats.processes.len() < MAX_PROCESSES {
+            stats.processes.insert(pid);
+        }
+        if !subdomain.is_empty() {
+            for label in subdomain.split('.') {
+                stats.labels += 1;
+                stats.label_bytes += label.len() as u64;
+            }
+            if stats.subdomains.len() < thresholds.unique_subdomains
+                && stats.subdomains.insert(hash(subdomain))
+            {
+                stats.entropy += entropy(subdomain);
+            }
+        }
+
+        if stats.reported {
+            return None;
+        }
+        let average_label_length = stats.average_label_length();
+        let tunneling = stats.queries >= thresholds.queries
+            || (stats.subdomains.len() >= thresholds.unique_subdomains
+                && average_label_length >= thresholds.label_length as f64);
+        if !tunneling {
+            return None;
+        }
+        stats.reported = true;
+        Some(DnsTunnelData {
+            domain: domain.to_string(),
+            processes: stats.processes.len(),
+            queries: stats.queries,
+            unique_subdomains: stats.subdomains.len(),
+            average_label_length,
+            average_entropy: stats.average_entropy(),
+        })
+    }
+
+    /// Forget the statistics, at the end of an interval.
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+
+    /// Forget the half of the domains queried the least: the random names of
+    /// a flood are queried once, while the domain of a tunnel keeps growing.
+    fn evict(&mut self) {
+        let mut domains: Vec<(u64, String)> = self
+            .stats
+            .iter()
+            .map(|(domain, stats)| (stats.queries, domain.clone()))
+            .collect();
+        domains.sort_unstable();
+        for (_, domain) in domains.into_iter().take(MAX_DOMAINS / 2) {
+            self.stats.remove(&domain);
+        }
+    }
+
+    /// Whether `name` is one of the ignored domains or their subdomains.
+    fn is_ignored(&self, name: &str) -> bool {
+        self.ignored_domains.iter().any(|ignored| {
+            name.strip_suffix(ignored.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+    }
+}
+
+/// Split a name into its subdomain and the domain registered by its owner,
+/// approximated by the last two labels, or three under generic second level
+/// labels like `co.uk`.
+pub fn split_domain(name: &str) -> (&str, &str) {
+    let mut labels = name.rsplit('.');
+    let tld = labels.next().unwrap_or_default();
+    let second_level = labels.next().unwrap_or_default();
+    let parent_labels = if tld.len() == 2 && GENERIC_SECOND_LEVEL.contains(&second_level) {
+        3
+    } else {
+        2
+    };
+    match name.rmatch_indices('.').nth(parent_labels - 1) {
+        Some((index, _)) => (&name[..index], &name[index + 1..]),
+        None => ("", name),
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Shannon entropy of the characters of a subdomain, ignoring the dots.
+fn entropy(subdomain: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    let mut len = 0;
+    for byte in subdomain.bytes().filter(|byte| *byte != b'.') {
+        counts[byte as usize] += 1;
+        len += 1;
+    }
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> Detector {
+        Detector::new(
+            Thresholds {
+                queries: 100,
+                unique_subdomains: 10,
+                label_length: 20,
+            },
+            vec!["dnsbl.example.org".to_string()],
+        )
+    }
+
+    #[test]
+    fn split_domains() {
+        assert_eq!(split_domain("www.example.com"), ("www", "example.com"));
+        assert_eq!(split_domain("a.b.example.com"), ("a.b", "example.com"));
+        assert_eq!(split_domain("example.com"), ("", "example.com"));
+        assert_eq!(split_domain("www.bbc.co.uk"), ("www", "bbc.co.uk"));
+        assert_eq!(split_domain("t.co"), ("", "t.co"));
+        assert_eq!(split_domain("localhost"), ("", "localhost"));
+    }
+
+    #[test]
+    fn tunnel() {
+        let mut detector = detector();
+        let mut threats = Vec::new();
+        for index in 0..20 {
+            let name = format!("{index:02}f3a9c1e7b5d2f8a4c6e0b9d3f7a1c5e.example.com");
+            threats.extend(detector.record(42, &name));
+        }
+        // Reported once, when the 10th subdomain is seen
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].domain, "example.com");
+        assert_eq!(threats[0].queries, 10);
+        assert_eq!(threats[0].unique_subdomains, 10);
+        assert_eq!(threats[0].average_label_length, 33.0);
+        assert!(threats[0].average_entropy > 3.5);
+
+        detector.reset();
+        assert!(detector
+            .record(42, "00f3a9c1e7b5d2f8a4c6e0b9d3f7a1c5e.example.com")
+            .is_none());
+    }
+
+    #[test]
+    fn normal_traffic() {
+        let mut detector = detector();
+        for index in 0..99 {
+            // Short labels, and the same names queried again and again
+            assert!(detector
+                .record(42, &format!("host{index}.example.com"))
+                .is_none());
+            let pid = if index % 2 == 0 { 7 } else { 8 };
+            assert!(detector.record(pid, "www.example.net").is_none());
+            // Ignored domains
+            let name = format!("{index}.{index}.0.10.dnsbl.example.org.");
+            assert!(detector.record(42, &name).is_none());
+        }
+        // Too many queries, whatever the process
+        let threat = detector.record(7, "www.example.net").unwrap();
+        assert_eq!(threat.processes, 2);
+        assert!(detector.record(7, "www.example.net").is_none());
+    }
+
+    #[test]
+    fn tunnel_across_processes() {
+        let mut detector = detector();
+        let threats: Vec<_> = (0..10)
+            .filter_map(|index| {
+                let name = format!("{index:02}f3a9c1e7b5d2f8a4c6e0b9d3f7a1c5e.example.com");
+                detector.record(100 + index, &name)
+            })
+            .collect();
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].processes, 10);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut detector = detector();
+        for _ in 0..50 {
+            detector.record(42, "www.tunnel.com");
+        }
+        // A flood of random domains doesn't hide the tunnel
+        for index in 0..MAX_DOMAINS * 2 {
+            assert!(detector
+                .record(7, &format!("www.random{index}.com"))
+                .is_none());
+        }
+        assert!(detector.stats.len() <= MAX_DOMAINS);
+        for _ in 0..49 {
+            assert!(detector.record(42, "www.tunnel.com").is_none());
+        }
+        assert_eq!(detector.record(42, "www.tunnel.com").unwrap().queries, 100);
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use pulsar_core::{
+    event::{Severity, Value},
+    pdk::{
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+        ModuleError, Payload, PulsarModule, ShutdownSignal, Version,
+    },
+};
+
+pub mod detector;
+
+use detector::{Detector, Thresholds};
+
+const MODULE_NAME: &str = "dns-exfiltration";
+const DEFAULT_INTERVAL: u64 = 60;
+const DEFAULT_MAX_QUERIES: u64 = 1000;
+const DEFAULT_MAX_SUBDOMAINS: usize = 50;
+const DEFAULT_MIN_LABEL_LENGTH: u64 = 20;
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        dns_exfiltration_task,
+    )
+    // DNS queries are produced by network-monitor
+    .depends_on("network-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "interval",
+                    ConfigKind::Integer,
+                    "Seconds over which the queries to a domain are counted",
+                )
+                .default_value(DEFAULT_INTERVAL)
+                .range(1, 86400),
+            )
+            .field(
+                ConfigField::new(
+                    "max_queries",
+                    ConfigKind::Integer,
+                    "Queries to a domain in an interval raising a threat",
+                )
+                .default_value(DEFAULT_MAX_QUERIES)
+                .range(1, i64::MAX),
+            )
+            .field(
+                ConfigField::new(
+                    "max_subdomains",
+                    ConfigKind::Integer,
+                    "Distinct subdomains with long labels of a domain queried in an interval raising a threat",
+                )
+                .default_value(DEFAULT_MAX_SUBDOMAINS)
+                .range(1, 100_000),
+            )
+            .field(
+                ConfigField::new(
+                    "min_label_length",
+                    ConfigKind::Integer,
+                    "Average length of the labels of the subdomains counted by max_subdomains",
+                )
+                .default_value(DEFAULT_MIN_LABEL_LENGTH)
+                .range(0, 63),
+            )
+            .field(ConfigField::new(
+                "ignored_domains",
+                ConfigKind::List,
+                "Domains never reported, like DNS blocklists queried by mail servers",
+            )),
+    )
+}
+
+async fn dns_exfiltration_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    let mut config: Config = rx_config.read()?;
+    let mut detector = Detector::new(config.thresholds.clone(), config.ignored_domains.clone());
+    let mut interval = reset_timer(&config);
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                config = rx_config.read()?;
+                detector = Detector::new(config.thresholds.clone(), config.ignored_domains.clone());
+                interval = reset_timer(&config);
+            }
+            _ = interval.tick() => detector.reset(),
+            event = receiver.recv() => {
+                let event = event?;
+                let Payload::DnsQuery { questions } = event.payload() else {
+                    continue;
+                };
+                if event.header().threat.is_some() {
+                    continue;
+                }
+                for question in questions {
+                    if let Some(data) = detector.record(event.header().pid, &question.normalized) {
+                        sender.send_threat_derived(
+                            &event,
+                            format!("Possible DNS tunnel to {}", data.domain),
+                            Severity::High,
+                            Value::try_from(data).ok(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Build the timer resetting the statistics at the end of every interval.
+fn reset_timer(config: &Config) -> tokio::time::Interval {
+    let period = Duration::from_secs(config.interval.max(1));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
+#[derive(Clone)]
+struct Config {
+    interval: u64,
+    thresholds: Thresholds,
+    ignored_domains: Vec<String>,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            interval: config.with_default("interval", DEFAULT_INTERVAL)?,
+            thresholds: Thresholds {
+                queries: config.with_default("max_queries", DEFAULT_MAX_QUERIES)?,
+                unique_subdomains: config.with_default("max_subdomains", DEFAULT_MAX_SUBDOMAINS)?,
+                label_length: config.with_default("min_label_length", DEFAULT_MIN_LABEL_LENGTH)?,
+            },
+            ignored_domains: config
+                .get_list::<String>("ignored_domains")?
+                .into_iter()
+                .map(|domain| domain.trim_end_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,244 @@
+//! Allowlist of trusted executables, identified by the SHA-256 of their content.
+//!
+//! The allowlist file uses the `sha256sum` format: one `<hash>  <path>` line per
+//! executable, where the path is only informative. Empty lines and lines
+//! starting with `#` are ignored.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Describes an error loading the allowlist.
+#[derive(Error, Debug)]
+pub enum AllowlistError {
+    #[error("error reading allowlist {path}")]
+    Loading {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+    #[error("invalid hash at {path}:{line}")]
+    InvalidHash { path: String, line: usize },
+}
+
+#[derive(Debug, Default)]
+pub struct Allowlist {
+    hashes: HashSet<String>,
+}
+
+impl Allowlist {
+    pub fn from_file(path: &Path) -> Result<Self, AllowlistError> {
+        let body = fs::read_to_string(path).map_err(|error| AllowlistError::Loading {
+            path: path.display().to_string(),
+            error,
+        })?;
+        Self::parse(&body).map_err(|line| AllowlistError::InvalidHash {
+            path: path.display().to_string(),
+            line,
+        })
+    }
+
+    /// Parse the allowlist, returning the number of the first invalid line.
+    fn parse(body: &str) -> Result<Self, usize> {
+        let mut hashes = HashSet::new();
+        for (index, line) in body.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hash = line.split_whitespace().next().unwrap_or_default();
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(index + 1);
+            }
+            hashes.insert(hash.to_ascii_lowercase());
+        }
+        Ok(Self { hashes })
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+}
+
+/// Return the hex encoded SHA-256 of a file.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Identifies a version of a file, changing when it's replaced or modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileVersion {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+}
+
+impl From<&fs::Metadata> for FileVersion {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+        }
+    }
+}
+
+/// Caches the hashes of executables, so that a binary is read only the first
+/// time it's run and after every change. Entries are keyed by the version of
+/// the file, not by its path: the same binary is read through the
+/// `/proc/<pid>/exe` of each of its processes.
+#[derive(Debug)]
+pub struct HashCache {
+    entries: HashMap<FileVersion, String>,
+    capacity: usize,
+}
+
+impl HashCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+        }
+    }
+
+    pub fn hash(&mut self, path: &Path) -> io::Result<String> {
+        let version = FileVersion::from(&fs::metadata(path)?);
+        if let Some(hash) = self.entries.get(&version) {
+            return Ok(hash.clone());
+        }
+        let hash = hash_file(path)?;
+        if self.entries.len() >= self.capacity {
+            self.entries.clear();
+        }
+        self.entries.insert(version, hash.clone());
+        Ok(hash)
+    }
+}
+
+/// Hash the executable files found in the given directories and their
+/// subdirectories, writing them to `output` in the allowlist format.
+/// Missing directories are skipped and symlinks to directories are not
+/// followed. Returns the number of files.
+pub fn record(paths: &[PathBuf], output: &mut impl Write) -> io::Result<usize> {
+    writeln!(output, "# Generated by `pulsar baseline record`")?;
+    let mut count = 0;
+    for path in paths {
+        match record_dir(path, output) {
+            Ok(files) => count += files,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::warn!("Skipping missing directory {}", path.display())
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(count)
+}
+
+fn record_dir(path: &Path, output: &mut impl Write) -> io::Result<usize> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    let mut count = 0;
+    for entry in entries {
+        let Ok(file_type) = fs::symlink_metadata(&entry).map(|m| m.file_type()) else {
+            continue;
+        };
+        if file_type.is_dir() {
+            count += record_dir(&entry, output)?;
+            continue;
+        }
+        // Follow symlinks to files, which are run by their link path
+        let Ok(metadata) = fs::metadata(&entry) else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+            continue;
+        }
+        match hash_file(&entry) {
+            Ok(hash) => {
+                writeln!(output, "{hash}  {}", entry.display())?;
+                count += 1;
+            }
+            Err(err) => log::warn!("Skipping {}: {err}", entry.display()),
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use super::*;
+
+    // sha256 of "hello"
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn parse() {
+        let body = format!(
+            "# comment\n\n{HELLO}  /usr/local/bin/hello\n{}\n",
+            HELLO.replace('2', "3").to_uppercase()
+        );
+        let allowlist = Allowlist::parse(&body).unwrap();
+        assert_eq!(allowlist.len(), 2);
+        assert!(allowlist.contains(HELLO));
+        assert!(allowlist.contains(&HELLO.replace('2', "3")));
+        assert!(!allowlist.contains(&"0".repeat(64)));
+
+        assert_eq!(Allowlist::parse("# ok\nnot-a-hash /bin/ls").unwrap_err(), 2);
+        assert_eq!(Allowlist::parse(&HELLO[1..]).unwrap_err(), 1);
+    }
+
+    #[test]
+    fn record_and_cache() {
+        let dir = temp_dir().join("pulsar_allowlist_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let exe = dir.join("sub").join("hello");
+        fs::write(&exe, "hello").unwrap();
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+        // not executable
+        fs::write(dir.join("data"), "data").unwrap();
+
+        let mut output = Vec::new();
+        let paths = [dir.clone(), dir.join("missing")];
+        assert_eq!(record(&paths, &mut output).unwrap(), 1);
+        let output = String::from_utf8(output).unwrap();
+        let allowlist = Allowlist::parse(&output).unwrap();
+        assert!(allowlist.contains(HELLO));
+        assert!(output.contains(&format!("{HELLO}  {}", exe.display())));
+
+        let mut cache = HashCache::new(16);
+        assert_eq!(cache.hash(&exe).unwrap(), HELLO);
+        fs::write(&exe, "hello, world").unwrap();
+        assert_ne!(cache.hash(&exe).unwrap(), HELLO);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use pulsar_core::{
+    event::{Severity, Value},
+    pdk::{
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+        ModuleContext, ModuleError, ModuleSender, Payload, PulsarModule, ShutdownSignal, Version,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+pub mod allowlist;
+
+use allowlist::{Allowlist, HashCache};
+
+const MODULE_NAME: &str = "exec-allowlist";
+pub const DEFAULT_ALLOWLIST_PATH: &str = "/var/lib/pulsar/allowlist.sha256";
+const DEFAULT_SCOPED_PATHS: &str = "/";
+/// Directories recorded by `pulsar baseline record` when every path is in scope.
+pub const DEFAULT_BASELINE_PATHS: [&str; 4] = ["/bin", "/sbin", "/usr", "/opt"];
+const DEFAULT_CACHE_SIZE: u64 = 4096;
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        exec_allowlist_task,
+    )
+    // Exec events are produced by process-monitor
+    .depends_on("process-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "allowlist_path",
+                    ConfigKind::Path,
+                    "File with the SHA-256 of the trusted executables",
+                )
+                .default_value(DEFAULT_ALLOWLIST_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "scoped_paths",
+                    ConfigKind::List,
+                    "Path prefixes of the executables checked against the allowlist",
+                )
+                .default_value(DEFAULT_SCOPED_PATHS),
+            )
+            .field(
+                ConfigField::new(
+                    "cache_size",
+                    ConfigKind::Integer,
+                    "Number of executable hashes kept in memory",
+                )
+                .default_value(DEFAULT_CACHE_SIZE)
+                .range(1, 1_000_000),
+            ),
+    )
+}
+
+async fn exec_allowlist_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    let mut checker = Arc::new(Checker::new(rx_config.read()?)?);
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                checker = Arc::new(Checker::new(rx_config.read()?)?);
+            }
+            event = receiver.recv() => {
+                let event = event?;
+                if checker.in_scope(&event) {
+                    // Hashing reads the whole executable
+                    let checker = checker.clone();
+                    let sender = sender.clone();
+                    tokio::task::spawn_blocking(move || checker.check(&sender, &event));
+                }
+            }
+        }
+    }
+}
+
+/// Extra data of the threats raised for executables missing from the allowlist.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecAllowlistData {
+    pub sha256: String,
+}
+
+struct Checker {
+    allowlist: Allowlist,
+    scoped_paths: Vec<PathBuf>,
+    cache: Mutex<HashCache>,
+    /// Mount namespace of the daemon, where the paths of the events resolve
+    /// to the same files
+    host_mnt_namespace: Option<u32>,
+}
+
+impl Checker {
+    fn new(config: Config) -> Result<Self, ModuleError> {
+        let allowlist = Allowlist::from_file(&config.allowlist_path)?;
+        log::debug!("Loaded {} trusted executables", allowlist.len());
+        Ok(Self {
+            allowlist,
+            scoped_paths: config.scoped_paths,
+            cache: Mutex::new(HashCache::new(config.cache_size)),
+            host_mnt_namespace: std::fs::metadata("/proc/self/ns/mnt")
+                .ok()
+                .map(|metadata| metadata.ino() as u32),
+        })
+    }
+
+    fn in_scope(&self, event: &Event) -> bool {
+        let Payload::Exec { filename, .. } = event.payload() else {
+            return false;
+        };
+        event.header().threat.is_none()
+            && self
+                .scoped_paths
+                .iter()
+                .any(|scope| Path::new(filename).starts_with(scope))
+    }
+
+    fn check(&self, sender: &ModuleSender, event: &Event) {
+        let Payload::Exec {
+            filename,
+            namespaces,
+            ..
+        } = event.payload()
+        else {
+            return;
+        };
+        let hash = {
+            let mut cache = self.cache.lock().unwrap();
+            // The path of the event is resolved in the mount namespace of the
+            // process: a container can run its own binary at an allowlisted
+            // host path. The executable is read through the process instead,
+            // falling back to the path only when the process has already
+            // exited and shares the mount namespace of the daemon.
+            let exe = format!("/proc/{}/exe", event.header().pid);
+            cache
+                .hash(Path::new(&exe))
+                .or_else(|err| match self.host_mnt_namespace {
+                    Some(mnt) if namespaces.mnt == mnt => cache.hash(Path::new(filename)),
+                    _ => Err(err),
+                })
+        };
+        match hash {
+            Ok(hash) if self.allowlist.contains(&hash) => {}
+            Ok(hash) => sender.send_threat_derived(
+                event,
+                format!("Executable {filename} is not in the allowlist"),
+                Severity::High,
+                Value::try_from(ExecAllowlistData { sha256: hash }).ok(),
+            ),
+            Err(err) => log::warn!("Error hashing {filename}: {err}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    allowlist_path: PathBuf,
+    scoped_paths: Vec<PathBuf>,
+    cache_size: usize,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            allowlist_path: config
+                .with_default("allowlist_path", PathBuf::from(DEFAULT_ALLOWLIST_PATH))?,
+            scoped_paths: config
+                .get_list_with_default("scoped_paths", vec![PathBuf::from(DEFAULT_SCOPED_PATHS)])?,
+            cache_size: config.with_default("cache_size", DEFAULT_CACHE_SIZE as usize)?,
+        })
+    }
+}
@@ -15,10 +15,40 @@ char LICENSE[] SEC("license") = "GPL v2";
 #define FILE_OPENED 4
 #define FILE_LINK 5
 #define FILE_RENAME 6
+#define XATTR_CHANGED 7
+#define CAPABILITIES_CHANGED 8
+#define SYSCTL_CHANGED 9
+#define FIFO_CREATED 10
+#define PIPE_TRANSFER 11
+
+#define XATTR_SECURITY_PREFIX "security."
+#define XATTR_SECURITY_PREFIX_LEN (sizeof(XATTR_SECURITY_PREFIX) - 1)
+#define XATTR_NAME_CAPS "security.capability"
+#define XATTR_NAME_MAX_LEN 64
+
+#define VFS_CAP_REVISION_MASK 0xFF000000
+#define VFS_CAP_REVISION_1 0x01000000
+#define VFS_CAP_FLAGS_EFFECTIVE 0x000001
+#define VFS_CAP_V1_SIZE 12
+
+// Sysctl values are short, except for a few strings like kernel.core_pattern
+#define SYSCTL_VALUE_MAX 256
+// Segments of the data of writev read for a sysctl value
+#define SYSCTL_SEGMENTS_MAX 4
+
+#define PIPEFS_MAGIC 0x50495045
+#define S_IFMT 00170000
+#define S_IFIFO 0010000
 
 struct file_opened_event {
   struct buffer_index filename;
   int flags;
+  u32 mode;
+  int fd;
+  // Identity of the opened file, used by userspace to cache its path
+  u32 dev;
+  u64 ino;
+  struct path_truncation truncation;
 };
 
 struct file_link_event {
@@ -30,10 +60,75 @@ struct file_link_event {
 struct file_rename_event {
   struct buffer_index source;
   struct buffer_index destination;
+  bool overwrite;
+};
+
+struct xattr_changed_event {
+  struct buffer_index filename;
+  struct buffer_index name;
+  bool removed;
+};
+
+struct capabilities_changed_event {
+  struct buffer_index filename;
+  u64 permitted;
+  u64 inheritable;
+  bool effective;
+};
+
+struct sysctl_changed_event {
+  struct buffer_index filename;
+  struct buffer_index value;
+  bool host_process;
+};
+
+struct fifo_created_event {
+  struct buffer_index filename;
+  u32 mode;
+};
+
+struct pipe_transfer_event {
+  // Empty for anonymous pipes
+  struct buffer_index filename;
+  u64 ino;
+  pid_t writer;
+  bool anonymous;
+};
+
+// A pipe read by a process other than its last writer, already reported.
+struct pipe_pair {
+  u64 pipe;
+  pid_t writer;
+  pid_t reader;
+};
+
+// Open syscall in progress in a thread. The file is saved by the file_open
+// hook and reported when the syscall returns the file descriptor.
+struct pending_open {
+  // Path argument of the syscall, in user memory
+  const char *pathname;
+  // Set by file_open, null when the open failed before the permission check
+  struct file *file;
+  u64 timestamp;
+  // Read by file_open, the file is released when the open fails
+  int flags;
+  u32 mode;
+  u32 dev;
+  u64 ino;
 };
 
 GLOBAL_INTEREST_MAP_DECLARATION;
 
+// Map thread id to the open syscall it's running. Entries are removed when
+// the syscall returns, the LRU map avoids leaking the ones of threads killed
+// in between.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, u64);
+  __type(value, struct pending_open);
+  __uint(max_entries, 4096);
+} pending_open_map SEC(".maps");
+
 OUTPUT_MAP(fs_event, {
   struct buffer_index created;
   struct buffer_index deleted;
@@ -42,8 +137,95 @@ OUTPUT_MAP(fs_event, {
   struct file_opened_event opened;
   struct file_link_event link;
   struct file_rename_event rename;
+  struct xattr_changed_event xattr;
+  struct capabilities_changed_event capabilities;
+  struct sysctl_changed_event sysctl;
+  struct fifo_created_event fifo_created;
+  struct pipe_transfer_event pipe_transfer;
 });
 
+// Last process writing to every pipe, by the address of its inode.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, u64);
+  __type(value, pid_t);
+  __uint(max_entries, 8192);
+} pipe_writer_map SEC(".maps");
+
+// Writers and readers of a pipe are reported once, not on every read.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, struct pipe_pair);
+  __type(value, u8);
+  __uint(max_entries, 8192);
+} pipe_pair_map SEC(".maps");
+
+// Whether anonymous pipes are reported too, set by userspace: they connect
+// most shell pipelines, while named pipes are rarely used.
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, u32);
+  __type(value, u32);
+  __uint(max_entries, 1);
+} pipe_config_map SEC(".maps");
+
+// Map of path prefixes populated by userspace from the module configuration.
+// The longest matching prefix decides if a path is watched (1) or ignored (0),
+// paths not matching any prefix are ignored.
+#define PATH_FILTER_MAX 256
+
+struct path_filter_key {
+  u32 prefixlen;
+  u8 data[PATH_FILTER_MAX];
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_LPM_TRIE);
+  __type(key, struct path_filter_key);
+  __type(value, u8);
+  __uint(map_flags, BPF_F_NO_PREALLOC);
+  __uint(max_entries, 1024);
+} path_filter_map SEC(".maps");
+
+// The lookup key is too big for the stack
+struct {
+  __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+  __type(key, u32);
+  __type(value, struct path_filter_key);
+  __uint(max_entries, 1);
+} path_filter_key_map SEC(".maps");
+
+// Check the path at index against path_filter_map. Only the first
+// PATH_FILTER_MAX bytes of the path are considered.
+static __always_inline bool is_watched(struct buffer *buffer,
+                                       struct buffer_index *index) {
+  u32 zero = 0;
+  struct path_filter_key *key =
+      bpf_map_lookup_elem(&path_filter_key_map, &zero);
+  if (!key)
+    return true;
+  u32 len = index->len;
+  if (len > PATH_FILTER_MAX)
+    len = PATH_FILTER_MAX;
+  u32 start = index->start & HALF_BUFFER_MASK;
+  if (bpf_probe_read_kernel(key->data, len,
+                            &((char *)buffer->buffer)[start]) < 0)
+    return true;
+  key->prefixlen = len * 8;
+  u8 *watched = bpf_map_lookup_elem(&path_filter_map, key);
+  return watched && *watched;
+}
+
+// Emit the event only if the file at path is in a watched tree.
+static __always_inline void output_if_watched(void *ctx,
+                                              struct fs_event *event,
+                                              struct buffer_index *path) {
+  if (is_watched(&event->buffer, path))
+    output_fs_event(ctx, event);
+  else
+    discard_fs_event(event);
+}
+
 // A struct path contains a dentry and it's mount point.
 // Most LSM methods take the dentry of the target file and its parent folder
 // struct path. This utility function returns the struct path of the dentry,
@@ -65,12 +247,22 @@ static __always_inline void on_path_mknod(void *ctx, struct path *dir,
   pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
   if (tgid < 0)
     return;
+  struct path path = make_path(dentry, dir);
+  // Named pipes created by mkfifo or mknod
+  if ((mode & S_IFMT) == S_IFIFO) {
+    struct fs_event *event = init_fs_event(FIFO_CREATED, tgid);
+    if (!event)
+      return;
+    get_path_str(&path, &event->buffer, &event->fifo_created.filename);
+    event->fifo_created.mode = mode;
+    output_if_watched(ctx, event, &event->fifo_created.filename);
+    return;
+  }
   struct fs_event *event = init_fs_event(FILE_CREATED, tgid);
   if (!event)
     return;
-  struct path path = make_path(dentry, dir);
   get_path_str(&path, &event->buffer, &event->created);
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->created);
 }
 
 PULSAR_LSM_HOOK(path_unlink, struct path *, dir, struct dentry *, dentry);
@@ -84,21 +276,153 @@ static __always_inline void on_path_unlink(void *ctx, struct path *dir,
     return;
   struct path path = make_path(dentry, dir);
   get_path_str(&path, &event->buffer, &event->deleted);
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->deleted);
 }
 
+// Emit a file opened event for a file opened outside of the open syscalls,
+// for example by execve or io_uring: it has no file descriptor.
+static __always_inline void emit_file_opened(void *ctx, pid_t tgid,
+                                             struct file *file) {
+  struct fs_event *event = init_fs_event(FILE_OPENED, tgid);
+  if (!event)
+    return;
+  struct path path = BPF_CORE_READ(file, f_path);
+  get_path_str_truncation(&path, &event->buffer, &event->opened.filename,
+                          &event->opened.truncation);
+  event->opened.flags = BPF_CORE_READ(file, f_flags);
+  event->opened.mode = BPF_CORE_READ(file, f_inode, i_mode);
+  event->opened.dev = BPF_CORE_READ(file, f_inode, i_sb, s_dev);
+  event->opened.ino = BPF_CORE_READ(file, f_inode, i_ino);
+  event->opened.fd = -1;
+  output_if_watched(ctx, event, &event->opened.filename);
+}
+
+// The file descriptor is not allocated yet when the file_open hook is called:
+// files opened by the open syscalls are saved and reported when the syscall
+// returns, the others right away.
 PULSAR_LSM_HOOK(file_open, struct file *, file);
 static __always_inline void on_file_open(void *ctx, struct file *file) {
   pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
   if (tgid < 0)
     return;
-  struct fs_event *event = init_fs_event(FILE_OPENED, tgid);
+  u64 pid_tgid = bpf_get_current_pid_tgid();
+  struct pending_open *pending =
+      bpf_map_lookup_elem(&pending_open_map, &pid_tgid);
+  if (!pending || pending->file) {
+    emit_file_opened(ctx, tgid, file);
+    return;
+  }
+  pending->file = file;
+  pending->timestamp = bpf_ktime_get_ns();
+  pending->flags = BPF_CORE_READ(file, f_flags);
+  pending->mode = BPF_CORE_READ(file, f_inode, i_mode);
+  pending->dev = BPF_CORE_READ(file, f_inode, i_sb, s_dev);
+  pending->ino = BPF_CORE_READ(file, f_inode, i_ino);
+}
+
+static __always_inline void on_open_enter(const char *pathname) {
+  if (tracker_interesting_tgid(&GLOBAL_INTEREST_MAP) < 0)
+    return;
+  u64 pid_tgid = bpf_get_current_pid_tgid();
+  struct pending_open pending = {
+      .pathname = pathname,
+  };
+  bpf_map_update_elem(&pending_open_map, &pid_tgid, &pending, BPF_ANY);
+}
+
+// Report the file opened by the syscall. When the open failed after the
+// permission check, the file is already released and the path is the one
+// passed to the syscall, which can be relative.
+static __always_inline void on_open_exit(void *ctx, long ret) {
+  u64 pid_tgid = bpf_get_current_pid_tgid();
+  struct pending_open *entry =
+      bpf_map_lookup_elem(&pending_open_map, &pid_tgid);
+  if (!entry)
+    return;
+  struct pending_open pending = *entry;
+  bpf_map_delete_elem(&pending_open_map, &pid_tgid);
+  // The open failed before reaching the file_open hook
+  if (!pending.file)
+    return;
+  struct fs_event *event = init_fs_event(FILE_OPENED, pid_tgid >> 32);
   if (!event)
     return;
-  struct path path = BPF_CORE_READ(file, f_path);
-  get_path_str(&path, &event->buffer, &event->opened.filename);
-  event->opened.flags = BPF_CORE_READ(file, f_flags);
-  output_fs_event(ctx, event);
+  event->timestamp = pending.timestamp;
+  if (ret >= 0) {
+    // The file is kept alive by the new file descriptor
+    struct path path = BPF_CORE_READ(pending.file, f_path);
+    get_path_str_truncation(&path, &event->buffer, &event->opened.filename,
+                            &event->opened.truncation);
+  } else {
+    event->opened.truncation.truncated = false;
+    buffer_index_init(&event->buffer, &event->opened.filename);
+    buffer_append_user_str(&event->buffer, &event->opened.filename,
+                           pending.pathname, HALF_BUFFER_MASK);
+  }
+  event->opened.flags = pending.flags;
+  event->opened.mode = pending.mode;
+  event->opened.dev = pending.dev;
+  event->opened.ino = pending.ino;
+  event->opened.fd = ret >= 0 ? ret : -1;
+  output_if_watched(ctx, event, &event->opened.filename);
+}
+
+// open and creat exist only on some architectures, like x86_64. openat2 was
+// added in kernel 5.6. Userspace attaches the ones available.
+
+SEC("tracepoint/sys_enter_open")
+int BPF_PROG(sys_enter_open, struct pt_regs *regs, int __syscall_nr,
+             const char *filename, int flags, umode_t mode) {
+  on_open_enter(filename);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_open")
+int BPF_PROG(sys_exit_open, struct pt_regs *regs, int __syscall_nr, long ret) {
+  on_open_exit(ctx, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_creat")
+int BPF_PROG(sys_enter_creat, struct pt_regs *regs, int __syscall_nr,
+             const char *pathname, umode_t mode) {
+  on_open_enter(pathname);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_creat")
+int BPF_PROG(sys_exit_creat, struct pt_regs *regs, int __syscall_nr, long ret) {
+  on_open_exit(ctx, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_openat")
+int BPF_PROG(sys_enter_openat, struct pt_regs *regs, int __syscall_nr, int dfd,
+             const char *filename, int flags, umode_t mode) {
+  on_open_enter(filename);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_openat")
+int BPF_PROG(sys_exit_openat, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_open_exit(ctx, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_openat2")
+int BPF_PROG(sys_enter_openat2, struct pt_regs *regs, int __syscall_nr,
+             int dfd, const char *filename, struct open_how *how,
+             size_t usize) {
+  on_open_enter(filename);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_openat2")
+int BPF_PROG(sys_exit_openat2, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_open_exit(ctx, ret);
+  return 0;
 }
 
 PULSAR_LSM_HOOK(path_link, struct dentry *, old_dentry, struct path *, new_dir,
@@ -117,7 +441,7 @@ static __always_inline void on_path_link(void *ctx, struct dentry *old_dentry,
   struct path destination = make_path(old_dentry, new_dir);
   get_path_str(&destination, &event->buffer, &event->link.destination);
   event->link.hard_link = true;
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->link.source);
 }
 
 PULSAR_LSM_HOOK(path_symlink, struct path *, dir, struct dentry *, dentry,
@@ -137,7 +461,7 @@ static __always_inline void on_path_symlink(void *ctx, struct path *dir,
   buffer_append_str(&event->buffer, &event->link.destination, old_name,
                     BUFFER_MAX);
   event->link.hard_link = false;
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->link.source);
 }
 
 PULSAR_LSM_HOOK(path_mkdir, struct path *, dir, struct dentry *, dentry,
@@ -152,7 +476,7 @@ static __always_inline void on_path_mkdir(void *ctx, struct path *dir,
     return;
   struct path path = make_path(dentry, dir);
   get_path_str(&path, &event->buffer, &event->dir_created);
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->dir_created);
 }
 
 PULSAR_LSM_HOOK(path_rmdir, struct path *, dir, struct dentry *, dentry);
@@ -166,7 +490,7 @@ static __always_inline void on_path_rmdir(void *ctx, struct path *dir,
     return;
   struct path path = make_path(dentry, dir);
   get_path_str(&path, &event->buffer, &event->dir_deleted);
-  output_fs_event(ctx, event);
+  output_if_watched(ctx, event, &event->dir_deleted);
 }
 
 PULSAR_LSM_HOOK(path_rename, struct path *, old_dir, struct dentry *,
@@ -186,5 +510,410 @@ static __always_inline void on_path_rename(void *ctx, struct path *old_dir,
   struct path destination = make_path(new_dentry, new_dir);
   get_path_str(&source, &event->buffer, &event->rename.source);
   get_path_str(&destination, &event->buffer, &event->rename.destination);
+  // A positive destination dentry means an existing file is being replaced
+  event->rename.overwrite = BPF_CORE_READ(new_dentry, d_inode) != NULL;
+  // Moving a file out of a watched tree is reported as well
+  if (is_watched(&event->buffer, &event->rename.source))
+    output_fs_event(ctx, event);
+  else
+    output_if_watched(ctx, event, &event->rename.destination);
+}
+
+// Inode hooks don't receive the mount point of the file, so we resolve the
+// path from the root of the current task. Files on a different mount are
+// reported relative to their file system.
+static __always_inline struct path task_root_path(struct dentry *dentry) {
+  struct task_struct *task = (struct task_struct *)bpf_get_current_task();
+  struct path path = {
+      .dentry = dentry,
+      .mnt = BPF_CORE_READ(task, fs, root.mnt),
+  };
+  return path;
+}
+
+// Returns true if name is in the security.* namespace, the only one used
+// for access control. Name must be a kernel copy of XATTR_NAME_MAX_LEN bytes.
+static __always_inline bool is_security_xattr(const char *name) {
+  const char prefix[] = XATTR_SECURITY_PREFIX;
+#pragma unroll
+  for (int i = 0; i < XATTR_SECURITY_PREFIX_LEN; i++) {
+    if (name[i] != prefix[i])
+      return false;
+  }
+  return true;
+}
+
+static __always_inline bool is_caps_xattr(const char *name) {
+  const char caps[] = XATTR_NAME_CAPS;
+#pragma unroll
+  for (int i = 0; i < sizeof(caps); i++) {
+    if (name[i] != caps[i])
+      return false;
+  }
+  return true;
+}
+
+static __always_inline void emit_xattr_changed(void *ctx, pid_t tgid,
+                                               struct dentry *dentry,
+                                               const char *name,
+                                               bool removed) {
+  struct fs_event *event = init_fs_event(XATTR_CHANGED, tgid);
+  if (!event)
+    return;
+  struct path path = task_root_path(dentry);
+  get_path_str(&path, &event->buffer, &event->xattr.filename);
+  buffer_index_init(&event->buffer, &event->xattr.name);
+  buffer_append_str(&event->buffer, &event->xattr.name, name,
+                    XATTR_NAME_MAX_LEN);
+  event->xattr.removed = removed;
+  output_if_watched(ctx, event, &event->xattr.filename);
+}
+
+// Decode the security.capability value set by setcap. The kernel has
+// already converted it from the user format in cap_convert_nscap.
+static __always_inline void emit_capabilities_changed(void *ctx, pid_t tgid,
+                                                      struct dentry *dentry,
+                                                      const void *value,
+                                                      size_t size) {
+  struct vfs_cap_data caps = {};
+  if (size < VFS_CAP_V1_SIZE)
+    return;
+  if (bpf_probe_read_kernel(&caps, VFS_CAP_V1_SIZE, value) < 0)
+    return;
+  u32 revision = caps.magic_etc & VFS_CAP_REVISION_MASK;
+  if (revision != VFS_CAP_REVISION_1 && size >= sizeof(caps)) {
+    if (bpf_probe_read_kernel(&caps, sizeof(caps), value) < 0)
+      return;
+  }
+  struct fs_event *event = init_fs_event(CAPABILITIES_CHANGED, tgid);
+  if (!event)
+    return;
+  struct path path = task_root_path(dentry);
+  get_path_str(&path, &event->buffer, &event->capabilities.filename);
+  event->capabilities.permitted =
+      ((u64)caps.data[1].permitted << 32) | caps.data[0].permitted;
+  event->capabilities.inheritable =
+      ((u64)caps.data[1].inheritable << 32) | caps.data[0].inheritable;
+  event->capabilities.effective = caps.magic_etc & VFS_CAP_FLAGS_EFFECTIVE;
+  output_if_watched(ctx, event, &event->capabilities.filename);
+}
+
+static __always_inline void on_inode_setxattr(void *ctx, struct dentry *dentry,
+                                              const char *name,
+                                              const void *value, size_t size) {
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  char xattr_name[XATTR_NAME_MAX_LEN] = {};
+  if (bpf_probe_read_kernel_str(xattr_name, sizeof(xattr_name), name) < 0)
+    return;
+  if (!is_security_xattr(xattr_name))
+    return;
+  if (is_caps_xattr(xattr_name))
+    emit_capabilities_changed(ctx, tgid, dentry, value, size);
+  else
+    emit_xattr_changed(ctx, tgid, dentry, name, false);
+}
+
+static __always_inline void on_inode_removexattr(void *ctx,
+                                                 struct dentry *dentry,
+                                                 const char *name) {
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  char xattr_name[XATTR_NAME_MAX_LEN] = {};
+  if (bpf_probe_read_kernel_str(xattr_name, sizeof(xattr_name), name) < 0)
+    return;
+  if (!is_security_xattr(xattr_name))
+    return;
+  emit_xattr_changed(ctx, tgid, dentry, name, true);
+}
+
+// The xattr hooks gained an idmap argument in kernel 5.12, so they can't be
+// declared with PULSAR_LSM_HOOK: the arguments position is chosen at load
+// time instead.
+static __always_inline bool xattr_hooks_have_idmap() {
+  return LINUX_KERNEL_VERSION >= KERNEL_VERSION(5, 12, 0);
+}
+
+SEC("lsm/inode_setxattr")
+int inode_setxattr(unsigned long long *ctx) {
+  if (xattr_hooks_have_idmap()) {
+    on_inode_setxattr(ctx, (struct dentry *)ctx[1], (const char *)ctx[2],
+                      (const void *)ctx[3], ctx[4]);
+    return ctx[6];
+  }
+  on_inode_setxattr(ctx, (struct dentry *)ctx[0], (const char *)ctx[1],
+                    (const void *)ctx[2], ctx[3]);
+  return ctx[5];
+}
+
+SEC("fentry/security_inode_setxattr")
+int fentry_security_inode_setxattr(unsigned long long *ctx) {
+  if (xattr_hooks_have_idmap())
+    on_inode_setxattr(ctx, (struct dentry *)ctx[1], (const char *)ctx[2],
+                      (const void *)ctx[3], ctx[4]);
+  else
+    on_inode_setxattr(ctx, (struct dentry *)ctx[0], (const char *)ctx[1],
+                      (const void *)ctx[2], ctx[3]);
+  return 0;
+}
+
+SEC("kprobe/security_inode_setxattr")
+int security_inode_setxattr(struct pt_regs *ctx) {
+  if (xattr_hooks_have_idmap())
+    on_inode_setxattr(ctx, (struct dentry *)PT_REGS_PARM2(ctx),
+                      (const char *)PT_REGS_PARM3(ctx),
+                      (const void *)PT_REGS_PARM4(ctx), PT_REGS_PARM5(ctx));
+  else
+    on_inode_setxattr(ctx, (struct dentry *)PT_REGS_PARM1(ctx),
+                      (const char *)PT_REGS_PARM2(ctx),
+                      (const void *)PT_REGS_PARM3(ctx), PT_REGS_PARM4(ctx));
+  return 0;
+}
+
+SEC("lsm/inode_removexattr")
+int inode_removexattr(unsigned long long *ctx) {
+  if (xattr_hooks_have_idmap()) {
+    on_inode_removexattr(ctx, (struct dentry *)ctx[1], (const char *)ctx[2]);
+    return ctx[3];
+  }
+  on_inode_removexattr(ctx, (struct dentry *)ctx[0], (const char *)ctx[1]);
+  return ctx[2];
+}
+
+SEC("fentry/security_inode_removexattr")
+int fentry_security_inode_removexattr(unsigned long long *ctx) {
+  if (xattr_hooks_have_idmap())
+    on_inode_removexattr(ctx, (struct dentry *)ctx[1], (const char *)ctx[2]);
+  else
+    on_inode_removexattr(ctx, (struct dentry *)ctx[0], (const char *)ctx[1]);
+  return 0;
+}
+
+SEC("kprobe/security_inode_removexattr")
+int security_inode_removexattr(struct pt_regs *ctx) {
+  if (xattr_hooks_have_idmap())
+    on_inode_removexattr(ctx, (struct dentry *)PT_REGS_PARM2(ctx),
+                         (const char *)PT_REGS_PARM3(ctx));
+  else
+    on_inode_removexattr(ctx, (struct dentry *)PT_REGS_PARM1(ctx),
+                         (const char *)PT_REGS_PARM2(ctx));
+  return 0;
+}
+
+// Anonymous pipes are reported only when enabled in pipe_config_map.
+static __always_inline bool pipe_is_watched(struct inode *inode,
+                                            bool *anonymous) {
+  *anonymous = BPF_CORE_READ(inode, i_sb, s_magic) == PIPEFS_MAGIC;
+  if (!*anonymous)
+    return true;
+  u32 zero = 0;
+  u32 *enabled = bpf_map_lookup_elem(&pipe_config_map, &zero);
+  return enabled && *enabled;
+}
+
+// Remember the last writer of a pipe, compared with its readers.
+static __always_inline void on_pipe_write(struct file *file) {
+  struct inode *inode = BPF_CORE_READ(file, f_inode);
+  bool anonymous;
+  if (!pipe_is_watched(inode, &anonymous))
+    return;
+  u64 pipe = (u64)inode;
+  pid_t tgid = bpf_get_current_pid_tgid() >> 32;
+  pid_t *writer = bpf_map_lookup_elem(&pipe_writer_map, &pipe);
+  if (writer && *writer == tgid)
+    return;
+  bpf_map_update_elem(&pipe_writer_map, &pipe, &tgid, BPF_ANY);
+}
+
+// Report the first read of a pipe by a process other than the last writer,
+// the transfer of data between two processes.
+static __always_inline void on_pipe_read(void *ctx, struct file *file) {
+  struct inode *inode = BPF_CORE_READ(file, f_inode);
+  bool anonymous;
+  if (!pipe_is_watched(inode, &anonymous))
+    return;
+  u64 pipe = (u64)inode;
+  pid_t *writer = bpf_map_lookup_elem(&pipe_writer_map, &pipe);
+  if (!writer)
+    return;
+  pid_t tgid = bpf_get_current_pid_tgid() >> 32;
+  struct pipe_pair pair = {
+      .pipe = pipe,
+      .writer = *writer,
+      .reader = tgid,
+  };
+  if (pair.writer == tgid || bpf_map_lookup_elem(&pipe_pair_map, &pair))
+    return;
+  u8 reported = 1;
+  bpf_map_update_elem(&pipe_pair_map, &pair, &reported, BPF_ANY);
+  if (!tracker_is_interesting(&GLOBAL_INTEREST_MAP, tgid, __func__, true,
+                              true))
+    return;
+  struct fs_event *event = init_fs_event(PIPE_TRANSFER, tgid);
+  if (!event)
+    return;
+  event->pipe_transfer.ino = BPF_CORE_READ(inode, i_ino);
+  event->pipe_transfer.writer = pair.writer;
+  event->pipe_transfer.anonymous = anonymous;
+  if (anonymous) {
+    buffer_index_init(&event->buffer, &event->pipe_transfer.filename);
+    output_fs_event(ctx, event);
+    return;
+  }
+  struct path path = BPF_CORE_READ(file, f_path);
+  get_path_str(&path, &event->buffer, &event->pipe_transfer.filename);
+  output_if_watched(ctx, event, &event->pipe_transfer.filename);
+}
+
+// pipe_write and pipe_read are the write_iter and read_iter handlers of the
+// anonymous and named pipes: they see read, write, readv, writev and io_uring,
+// and only run for pipes.
+SEC("fentry/pipe_write")
+int BPF_PROG(fentry_pipe_write, struct kiocb *iocb, struct iov_iter *from) {
+  if (BPF_CORE_READ(from, count) != 0)
+    on_pipe_write(BPF_CORE_READ(iocb, ki_filp));
+  return 0;
+}
+
+SEC("kprobe/pipe_write")
+int BPF_KPROBE(pipe_write, struct kiocb *iocb, struct iov_iter *from) {
+  if (BPF_CORE_READ(from, count) != 0)
+    on_pipe_write(BPF_CORE_READ(iocb, ki_filp));
+  return 0;
+}
+
+SEC("fentry/pipe_read")
+int BPF_PROG(fentry_pipe_read, struct kiocb *iocb, struct iov_iter *to) {
+  if (BPF_CORE_READ(to, count) != 0)
+    on_pipe_read(ctx, BPF_CORE_READ(iocb, ki_filp));
+  return 0;
+}
+
+SEC("kprobe/pipe_read")
+int BPF_KPROBE(pipe_read, struct kiocb *iocb, struct iov_iter *to) {
+  if (BPF_CORE_READ(to, count) != 0)
+    on_pipe_read(ctx, BPF_CORE_READ(iocb, ki_filp));
+  return 0;
+}
+
+// struct iov_iter before 6.4, when iov was renamed __iov
+struct iov_iter___pre_6_4 {
+  const struct iovec *iov;
+};
+
+// struct iov_iter before 5.14, when the iterator type was ORed with the
+// direction of the transfer, READ (0) or WRITE (1)
+struct iov_iter___pre_5_14 {
+  unsigned int type;
+};
+
+// Copy the user memory described by an iov_iter: a single buffer (ITER_UBUF,
+// used by write since 6.0) or an array of them (ITER_IOVEC, used by writev,
+// io_uring and by write before 6.0).
+static __always_inline void buffer_append_iov_iter(struct buffer *buffer,
+                                                   struct buffer_index *index,
+                                                   struct iov_iter *iter,
+                                                   size_t count) {
+  unsigned int type;
+  if (bpf_core_field_exists(iter->iter_type)) {
+    type = BPF_CORE_READ(iter, iter_type);
+  } else {
+    struct iov_iter___pre_5_14 *old = (void *)iter;
+    type = BPF_CORE_READ(old, type) & ~1;
+  }
+  if (bpf_core_enum_value_exists(enum iter_type, ITER_UBUF) &&
+      type == bpf_core_enum_value(enum iter_type, ITER_UBUF)) {
+    buffer_append_user_memory(buffer, index, BPF_CORE_READ(iter, ubuf), count);
+    return;
+  }
+  if (type != bpf_core_enum_value(enum iter_type, ITER_IOVEC))
+    return;
+  const struct iovec *iov;
+  if (bpf_core_field_exists(iter->__iov)) {
+    iov = BPF_CORE_READ(iter, __iov);
+  } else {
+    struct iov_iter___pre_6_4 *old = (void *)iter;
+    iov = BPF_CORE_READ(old, iov);
+  }
+#pragma unroll
+  for (int i = 0; i < SYSCTL_SEGMENTS_MAX; i++) {
+    if (count == 0)
+      return;
+    size_t len = BPF_CORE_READ(iov + i, iov_len);
+    if (len > count)
+      len = count;
+    buffer_append_user_memory(buffer, index, BPF_CORE_READ(iov + i, iov_base),
+                              len);
+    count -= len;
+  }
+}
+
+// proc_sys_write takes an iov_iter since 5.10
+static __always_inline bool proc_sys_write_has_iter() {
+  return LINUX_KERNEL_VERSION >= KERNEL_VERSION(5, 10, 0);
+}
+
+// Sysctl changes are reported regardless of the path filter. The value is
+// the one written, which may still be rejected by the kernel. Processes of
+// the host run in the initial pid namespace, the ones of containers don't.
+static __always_inline void on_proc_sys_write(void *ctx, struct file *file,
+                                              const char *buf,
+                                              struct iov_iter *iter,
+                                              size_t count) {
+  if (count == 0)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  struct fs_event *event = init_fs_event(SYSCTL_CHANGED, tgid);
+  if (!event)
+    return;
+  struct task_struct *task = (struct task_struct *)bpf_get_current_task();
+  event->sysctl.host_process = BPF_CORE_READ(task, thread_pid, level) == 0;
+  struct path path = BPF_CORE_READ(file, f_path);
+  get_path_str(&path, &event->buffer, &event->sysctl.filename);
+  if (count > SYSCTL_VALUE_MAX)
+    count = SYSCTL_VALUE_MAX;
+  buffer_index_init(&event->buffer, &event->sysctl.value);
+  if (iter)
+    buffer_append_iov_iter(&event->buffer, &event->sysctl.value, iter, count);
+  else
+    buffer_append_user_memory(&event->buffer, &event->sysctl.value,
+                              (void *)buf, count);
   output_fs_event(ctx, event);
 }
+
+// proc_sys_write is the write handler of every file in /proc/sys, the write
+// side of proc_sys_call_handler: it sees write, pwrite, writev, pwritev and
+// io_uring, and only runs for sysctl files.
+static __always_inline void on_proc_sys_write_iter(void *ctx,
+                                                   struct kiocb *iocb,
+                                                   struct iov_iter *iter) {
+  on_proc_sys_write(ctx, BPF_CORE_READ(iocb, ki_filp), NULL, iter,
+                    BPF_CORE_READ(iter, count));
+}
+
+SEC("fentry/proc_sys_write")
+int fentry_proc_sys_write(unsigned long long *ctx) {
+  if (proc_sys_write_has_iter())
+    on_proc_sys_write_iter(ctx, (struct kiocb *)ctx[0],
+                           (struct iov_iter *)ctx[1]);
+  else
+    on_proc_sys_write(ctx, (struct file *)ctx[0], (const char *)ctx[1], NULL,
+                      ctx[2]);
+  return 0;
+}
+
+SEC("kprobe/proc_sys_write")
+int proc_sys_write(struct pt_regs *ctx) {
+  if (proc_sys_write_has_iter())
+    on_proc_sys_write_iter(ctx, (struct kiocb *)PT_REGS_PARM1(ctx),
+                           (struct iov_iter *)PT_REGS_PARM2(ctx));
+  else
+    on_proc_sys_write(ctx, (struct file *)PT_REGS_PARM1(ctx),
+                      (const char *)PT_REGS_PARM2(ctx), NULL,
+                      PT_REGS_PARM3(ctx));
+  return 0;
+}
@@ -1,8 +1,16 @@
+use std::path::Path;
+
 use bpf_common::{
-    ebpf_program, parsing::BufferIndex, program::BpfContext, BpfSender, Program, ProgramBuilder,
-    ProgramError,
+    ebpf_program,
+    parsing::{BufferIndex, PathTruncation},
+    program::BpfContext,
+    BpfSender, Pid, Program, ProgramBuilder, ProgramError,
 };
 
+pub mod path_cache;
+pub mod path_filter;
+pub mod pipes;
+
 const MODULE_NAME: &str = "file-system-monitor";
 
 pub async fn program(
@@ -14,7 +22,7 @@ pub async fn program(
     let mut builder = ProgramBuilder::new(ctx, MODULE_NAME, binary);
     // LSM hooks provide the perfet intercept point for file system operations.
     // If LSM eBPF programs is not supported, we'll attach to the same kernel
-    // functions, but using kprobes.
+    // functions, using fentry or kprobes.
     if attach_to_lsm {
         builder = builder
             .lsm("path_mknod")
@@ -24,23 +32,55 @@ pub async fn program(
             .lsm("path_rename")
             .lsm("file_open")
             .lsm("path_link")
-            .lsm("path_symlink");
+            .lsm("path_symlink")
+            .lsm("inode_setxattr")
+            .lsm("inode_removexattr");
     } else {
         builder = builder
-            .kprobe("security_path_mknod")
-            .kprobe("security_path_unlink")
-            .kprobe("security_path_mkdir")
-            .kprobe("security_path_rmdir")
-            .kprobe("security_path_rename")
-            .kprobe("security_file_open")
-            .kprobe("security_path_link")
-            .kprobe("security_path_symlink");
+            .fentry_or_kprobe("security_path_mknod")
+            .fentry_or_kprobe("security_path_unlink")
+            .fentry_or_kprobe("security_path_mkdir")
+            .fentry_or_kprobe("security_path_rmdir")
+            .fentry_or_kprobe("security_path_rename")
+            .fentry_or_kprobe("security_file_open")
+            .fentry_or_kprobe("security_path_link")
+            .fentry_or_kprobe("security_path_symlink")
+            .fentry_or_kprobe("security_inode_setxattr")
+            .fentry_or_kprobe("security_inode_removexattr");
+    }
+    // File descriptors are collected when the open syscalls return
+    for syscall in OPEN_SYSCALLS {
+        if syscall_tracepoint_exists(syscall) {
+            builder = builder
+                .tracepoint("syscalls", &format!("sys_enter_{syscall}"))
+                .tracepoint("syscalls", &format!("sys_exit_{syscall}"));
+        }
     }
+    // Values written to /proc/sys are read before the sysctl handler runs
+    builder = builder.fentry_or_kprobe("proc_sys_write");
+    // Pipes are attributed to their writers and readers
+    builder = builder
+        .fentry_or_kprobe("pipe_write")
+        .fentry_or_kprobe("pipe_read");
     let mut program = builder.start().await?;
     program.read_events("map_output_fs_event", sender).await?;
+    // Watch everything until the module configuration is applied
+    path_filter::set_path_filter(&mut program, &["/".to_string()], &[])?;
     Ok(program)
 }
 
+/// Syscalls opening files, `open` and `creat` are missing on some
+/// architectures and `openat2` on kernels older than 5.6.
+const OPEN_SYSCALLS: [&str; 4] = ["open", "creat", "openat", "openat2"];
+
+fn syscall_tracepoint_exists(syscall: &str) -> bool {
+    ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"]
+        .iter()
+        .any(|tracefs| {
+            Path::new(&format!("{tracefs}/events/syscalls/sys_enter_{syscall}")).exists()
+        })
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub enum FsEvent {
@@ -59,6 +99,11 @@ pub enum FsEvent {
     FileOpened {
         filename: BufferIndex<str>,
         flags: i32,
+        mode: u32,
+        fd: i32,
+        dev: u32,
+        ino: u64,
+        truncation: PathTruncation,
     },
     FileLink {
         source: BufferIndex<str>,
@@ -68,6 +113,34 @@ pub enum FsEvent {
     FileRename {
         source: BufferIndex<str>,
         destination: BufferIndex<str>,
+        overwrite: bool,
+    },
+    XattrChanged {
+        filename: BufferIndex<str>,
+        name: BufferIndex<str>,
+        removed: bool,
+    },
+    CapabilitiesChanged {
+        filename: BufferIndex<str>,
+        permitted: u64,
+        inheritable: u64,
+        effective: bool,
+    },
+    SysctlChanged {
+        filename: BufferIndex<str>,
+        value: BufferIndex<[u8]>,
+        host_process: bool,
+    },
+    FifoCreated {
+        filename: BufferIndex<str>,
+        mode: u32,
+    },
+    /// Read of a pipe by a process other than its last writer, see [`pipes`]
+    PipeTransfer {
+        filename: BufferIndex<str>,
+        ino: u64,
+        writer: Pid,
+        anonymous: bool,
     },
 }
 
@@ -75,16 +148,23 @@ pub mod pulsar {
 
     use std::{
         os::unix::prelude::FileTypeExt,
+        path::{Component, Path},
         time::{Duration, Instant},
     };
 
     use super::*;
+    use crate::{
+        path_cache::{FileId, PathCache},
+        path_filter::set_path_filter,
+        pipes::set_anonymous_pipes,
+    };
     use bpf_common::{parsing::IndexError, program::BpfEvent};
     use pulsar_core::{
-        event::FileFlags,
+        event::{Capabilities, FileFlags},
         pdk::{
-            CleanExit, ConfigError, Event, IntoPayload, ModuleConfig, ModuleContext, ModuleError,
-            ModuleSender, Payload, PulsarModule, ShutdownSignal, Version,
+            CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, IntoPayload,
+            ModuleConfig, ModuleContext, ModuleError, ModuleSender, Payload, PulsarModule,
+            ShutdownSignal, Version,
         },
     };
     use tokio::{fs::File, io::AsyncReadExt};
@@ -96,16 +176,60 @@ pub mod pulsar {
             true,
             fs_monitor_task,
         )
+        .uses_ebpf()
+        // Events are enriched by the process tracker, fed by process-monitor
+        .depends_on("process-monitor")
+        .with_config_schema(
+            ConfigSchema::new(1)
+                .field(
+                    ConfigField::new(
+                        "elf_check_enabled",
+                        ConfigKind::Bool,
+                        "Emit an event when an ELF file is opened",
+                    )
+                    .default_value(true),
+                )
+                .field(
+                    ConfigField::new(
+                        "elf_check_whitelist",
+                        ConfigKind::List,
+                        "Paths excluded from the ELF check",
+                    )
+                    .default_value("/proc,/sys,/dev"),
+                )
+                .field(
+                    ConfigField::new(
+                        "watched_paths",
+                        ConfigKind::List,
+                        "Path prefixes generating events, filtered in the kernel",
+                    )
+                    .default_value("/"),
+                )
+                .field(ConfigField::new(
+                    "ignored_paths",
+                    ConfigKind::List,
+                    "Path prefixes excluded from watched_paths",
+                ))
+                .field(
+                    ConfigField::new(
+                        "anonymous_pipes",
+                        ConfigKind::Bool,
+                        "Report the transfers on anonymous pipes too, like the ones of shell pipelines",
+                    )
+                    .default_value(false),
+                ),
+        )
     }
 
     async fn fs_monitor_task(
         ctx: ModuleContext,
         mut shutdown: ShutdownSignal,
     ) -> Result<CleanExit, ModuleError> {
-        let _program = program(ctx.get_bpf_context(), ctx.get_sender()).await?;
+        let mut program = program(ctx.get_bpf_context(), ctx.get_sender()).await?;
         let mut receiver = ctx.get_receiver();
         let mut rx_config = ctx.get_config();
         let mut config: Config = rx_config.read()?;
+        config.apply(&mut program)?;
         let sender = ctx.get_sender();
         loop {
             // enable receiver only if the elf checker is enabled
@@ -122,6 +246,7 @@ pub mod pulsar {
                 }
                 _ = rx_config.changed() => {
                     config = rx_config.read()?;
+                    config.apply(&mut program)?;
                 }
                 r = shutdown.recv() => return r,
             }
@@ -133,7 +258,10 @@ pub mod pulsar {
 
         fn try_into_payload(data: BpfEvent<Self>) -> Result<Payload, Self::Error> {
             let BpfEvent {
-                payload, buffer, ..
+                payload,
+                buffer,
+                pid,
+                ..
             } = data;
             Ok(match payload {
                 FsEvent::FileCreated { filename } => Payload::FileCreated {
@@ -148,34 +276,162 @@ pub mod pulsar {
                 FsEvent::DirDeleted { filename } => Payload::DirDeleted {
                     dirname: filename.string(&buffer)?,
                 },
-                FsEvent::FileOpened { filename, flags } => Payload::FileOpened {
-                    filename: filename.string(&buffer)?,
-                    flags: FileFlags::from_raw_unchecked(flags),
-                },
+                FsEvent::FileOpened {
+                    filename,
+                    flags,
+                    mode,
+                    fd,
+                    dev,
+                    ino,
+                    truncation,
+                } => {
+                    let filename = filename.string(&buffer)?;
+                    let mut cache = PathCache::global().lock().unwrap();
+                    let resolved_path = cache.resolve(&filename, &truncation);
+                    // Failed opens carry the path passed to the syscall
+                    if let Some(resolved_path) =
+                        resolved_path.as_ref().filter(|p| p.starts_with('/'))
+                    {
+                        cache.insert(FileId::from_kernel(dev, ino), resolved_path);
+                    }
+                    Payload::FileOpened {
+                        resolved_path: resolved_path.unwrap_or_else(|| filename.clone()),
+                        filename,
+                        flags: FileFlags::from_raw_unchecked(flags),
+                        mode,
+                        fd,
+                    }
+                }
                 FsEvent::FileLink {
                     source,
                     destination,
                     hard_link,
-                } => Payload::FileLink {
-                    source: source.string(&buffer)?,
-                    destination: destination.string(&buffer)?,
-                    hard_link,
-                },
+                } => {
+                    let source = source.string(&buffer)?;
+                    let destination = resolve_link_target(&source, &destination.string(&buffer)?);
+                    Payload::FileLink {
+                        source,
+                        destination,
+                        hard_link,
+                    }
+                }
                 FsEvent::FileRename {
                     source,
                     destination,
+                    overwrite,
                 } => Payload::FileRename {
                     source: source.string(&buffer)?,
                     destination: destination.string(&buffer)?,
+                    overwrite,
+                },
+                FsEvent::XattrChanged {
+                    filename,
+                    name,
+                    removed,
+                } => Payload::XattrChanged {
+                    filename: filename.string(&buffer)?,
+                    name: name.string(&buffer)?,
+                    removed,
+                },
+                FsEvent::CapabilitiesChanged {
+                    filename,
+                    permitted,
+                    inheritable,
+                    effective,
+                } => Payload::FileCapabilitiesChanged {
+                    filename: filename.string(&buffer)?,
+                    permitted: Capabilities::from_raw_unchecked(permitted),
+                    inheritable: Capabilities::from_raw_unchecked(inheritable),
+                    effective,
+                },
+                FsEvent::SysctlChanged {
+                    filename,
+                    value,
+                    host_process,
+                } => Payload::SysctlChanged {
+                    name: sysctl_name(&filename.string(&buffer)?),
+                    value: String::from_utf8_lossy(value.bytes(&buffer)?)
+                        .trim()
+                        .to_string(),
+                    host_process,
+                },
+                FsEvent::FifoCreated { filename, mode } => Payload::FifoCreated {
+                    filename: filename.string(&buffer)?,
+                    mode,
+                },
+                FsEvent::PipeTransfer {
+                    filename,
+                    ino,
+                    writer,
+                    anonymous,
+                } => Payload::PipeTransfer {
+                    filename: filename.string(&buffer)?,
+                    inode: ino,
+                    writer_pid: writer.as_raw(),
+                    reader_pid: pid.as_raw(),
+                    anonymous,
                 },
             })
         }
     }
 
+    /// Name of the kernel parameter of a file in /proc/sys, like `sysctl` shows
+    /// it: `/proc/sys/net/ipv4/ip_forward` is `net.ipv4.ip_forward`. Dots in the
+    /// path, like the ones of VLAN interfaces, become slashes. Paths outside of
+    /// /proc/sys, when procfs is mounted somewhere else, are returned as they are.
+    pub fn sysctl_name(path: &str) -> String {
+        match path.strip_prefix("/proc/sys/") {
+            Some(name) => name
+                .chars()
+                .map(|c| match c {
+                    '.' => '/',
+                    '/' => '.',
+                    c => c,
+                })
+                .collect(),
+            None => path.to_string(),
+        }
+    }
+
+    /// Symlink targets are stored as written by the user: relative targets are
+    /// resolved against the directory containing the link, without following
+    /// other symlinks.
+    pub fn resolve_link_target(link: &str, target: &str) -> String {
+        let target = Path::new(target);
+        if target.is_absolute() {
+            return target.to_string_lossy().into_owned();
+        }
+        let mut resolved = Path::new(link)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        for component in target.components() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
+        }
+        resolved.to_string_lossy().into_owned()
+    }
+
     #[derive(Clone, Debug, Default)]
     pub struct Config {
         elf_check_enabled: bool,
         elf_check_whitelist: Vec<String>,
+        watched_paths: Vec<String>,
+        ignored_paths: Vec<String>,
+        anonymous_pipes: bool,
+    }
+
+    impl Config {
+        /// Configure the filters of the probes.
+        fn apply(&self, program: &mut Program) -> Result<(), ProgramError> {
+            set_path_filter(program, &self.watched_paths, &self.ignored_paths)?;
+            set_anonymous_pipes(program, self.anonymous_pipes)
+        }
     }
 
     impl TryFrom<&ModuleConfig> for Config {
@@ -192,13 +448,20 @@ pub mod pulsar {
                         String::from("/dev"),
                     ],
                 )?,
+                watched_paths: config
+                    .get_list_with_default("watched_paths", vec![String::from("/")])?,
+                ignored_paths: config.get_list_with_default("ignored_paths", Vec::new())?,
+                anonymous_pipes: config.with_default("anonymous_pipes", false)?,
             })
         }
     }
 
     /// Check if an opened file is an ELF
     async fn check_elf(sender: &ModuleSender, config: &Config, event: &Event) {
-        if let Payload::FileOpened { filename, flags } = event.payload() {
+        if let Payload::FileOpened {
+            filename, flags, ..
+        } = event.payload()
+        {
             let now = Instant::now();
             let should_check = !config
                 .elf_check_whitelist
@@ -255,7 +518,14 @@ pub mod pulsar {
 
 #[cfg(feature = "test-suite")]
 pub mod test_suite {
-    use std::{env::temp_dir, fs::OpenOptions};
+    use std::{
+        env::temp_dir,
+        fs::OpenOptions,
+        os::unix::{
+            fs::{MetadataExt, OpenOptionsExt},
+            io::AsRawFd,
+        },
+    };
 
     use super::*;
     use bpf_common::{
@@ -269,13 +539,20 @@ pub mod test_suite {
             name: "file-system-monitor",
             tests: vec![
                 open_file(),
+                open_file_failed(),
                 create_file(),
+                create_fifo(),
+                pipe_transfer(),
                 unlink_file(),
                 symlink(),
                 hardlink(),
                 mkdir(),
                 rmdir(),
                 rename(),
+                rename_overwrite(),
+                relative_symlink(),
+                set_capabilities(),
+                ignored_path(),
             ],
         }
     }
@@ -297,6 +574,96 @@ pub mod test_suite {
         })
     }
 
+    fn create_fifo() -> TestCase {
+        TestCase::new("create_fifo", async {
+            let path = temp_dir().join("fifo_name_1");
+            TestRunner::with_ebpf(program)
+                .run(|| {
+                    _ = std::fs::remove_file(&path);
+                    nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU)
+                        .expect("creating fifo failed");
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::FifoCreated,
+                    (filename, path.to_str().unwrap().into(), "filename")
+                ))
+                .report()
+        })
+    }
+
+    fn pipe_transfer() -> TestCase {
+        TestCase::new("pipe_transfer", async {
+            use std::io::{IoSlice, Read, Write};
+
+            use nix::{
+                sys::wait::waitpid,
+                unistd::{fork, ForkResult},
+            };
+
+            let path = temp_dir().join("pipe_transfer");
+            let mut writer_pid = Pid::from_raw(0);
+            TestRunner::with_ebpf(program)
+                .run(|| {
+                    _ = std::fs::remove_file(&path);
+                    nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU)
+                        .expect("creating fifo failed");
+                    match unsafe { fork() }.unwrap() {
+                        ForkResult::Child => {
+                            // Vectored writes go through the pipe handlers too
+                            let mut fifo = OpenOptions::new().write(true).open(&path).unwrap();
+                            _ = fifo.write_vectored(&[IoSlice::new(b"hel"), IoSlice::new(b"lo")]);
+                            unsafe { nix::libc::_exit(0) };
+                        }
+                        ForkResult::Parent { child } => {
+                            writer_pid = child;
+                            let mut data = Vec::new();
+                            std::fs::File::open(&path)
+                                .unwrap()
+                                .read_to_end(&mut data)
+                                .unwrap();
+                            waitpid(child, None).unwrap();
+                        }
+                    }
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::PipeTransfer,
+                    (filename, path.to_str().unwrap().into(), "filename"),
+                    (writer, writer_pid, "writer pid"),
+                    (anonymous, false, "anonymous")
+                ))
+                .report()
+        })
+    }
+
+    fn ignored_path() -> TestCase {
+        TestCase::new("ignored_path", async {
+            let dir = temp_dir().join("ignored_path");
+            let path = dir.join("file");
+            _ = std::fs::create_dir(&dir);
+            let ignored = dir.to_str().unwrap().to_string();
+            TestRunner::with_ebpf(move |ctx, sender| {
+                let ignored = ignored.clone();
+                async move {
+                    let mut program = program(ctx, sender).await?;
+                    path_filter::set_path_filter(&mut program, &["/".to_string()], &[ignored])?;
+                    Ok(program)
+                }
+            })
+            .run(|| {
+                _ = std::fs::remove_file(&path);
+                std::fs::File::create(&path).expect("creating file failed");
+            })
+            .await
+            .expect_no_event(event_check!(
+                FsEvent::FileCreated,
+                (filename, path.to_str().unwrap().into(), "filename")
+            ))
+            .report()
+        })
+    }
+
     fn unlink_file() -> TestCase {
         TestCase::new("unlink_file", async {
             let path = temp_dir().join("unlink_file");
@@ -326,17 +693,44 @@ pub mod test_suite {
 
             _ = std::fs::remove_file(&path);
             std::fs::write(&path, b"hello_world").unwrap();
+            let expected_mode = std::fs::metadata(&path).unwrap().mode();
             let mut options = OpenOptions::new();
             options.read(true).write(true);
+            let mut expected_fd = -1;
+            TestRunner::with_ebpf(program)
+                .run(|| {
+                    expected_fd = options.open(&path).unwrap().as_raw_fd();
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::FileOpened,
+                    (filename, path.to_str().unwrap().into(), "filename"),
+                    (flags, expected_flags, "open flags"),
+                    (mode, expected_mode, "file mode"),
+                    (fd, expected_fd, "file descriptor")
+                ))
+                .report()
+        })
+    }
+
+    /// Opening a FIFO for writing without readers fails after the permission
+    /// check, the open is reported without file descriptor.
+    fn open_file_failed() -> TestCase {
+        TestCase::new("open_file_failed", async {
+            let path = temp_dir().join("open_file_failed");
+            _ = std::fs::remove_file(&path);
+            nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+            let mut options = OpenOptions::new();
+            options.write(true).custom_flags(nix::libc::O_NONBLOCK);
             TestRunner::with_ebpf(program)
                 .run(|| {
-                    options.open(&path).unwrap();
+                    options.open(&path).unwrap_err();
                 })
                 .await
                 .expect_event(event_check!(
                     FsEvent::FileOpened,
                     (filename, path.to_str().unwrap().into(), "filename"),
-                    (flags, expected_flags, "open flags")
+                    (fd, -1, "file descriptor")
                 ))
                 .report()
         })
@@ -367,6 +761,58 @@ pub mod test_suite {
         })
     }
 
+    fn relative_symlink() -> TestCase {
+        TestCase::new("relative_symlink", async {
+            let source = temp_dir().join("relative_source");
+            _ = std::fs::remove_file(&source);
+            TestRunner::with_ebpf(program)
+                .run(|| {
+                    std::os::unix::fs::symlink("../etc/shadow", &source).unwrap();
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::FileLink,
+                    (source, source.to_str().unwrap().into(), "source"),
+                    (destination, "../etc/shadow".into(), "destination"),
+                    (hard_link, false, "hard_link")
+                ))
+                .report()
+        })
+    }
+
+    fn set_capabilities() -> TestCase {
+        TestCase::new("set_capabilities", async {
+            let path = temp_dir().join("set_capabilities");
+            std::fs::write(&path, b"").unwrap();
+            // struct vfs_cap_data, revision 2 with CAP_NET_RAW effective
+            let cap_net_raw: u32 = 1 << 13;
+            let value: Vec<u8> = [0x0200_0001, cap_net_raw, 0, 0, 0]
+                .iter()
+                .flat_map(|word: &u32| word.to_le_bytes())
+                .collect();
+            let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+            TestRunner::with_ebpf(program)
+                .run(|| unsafe {
+                    let r = nix::libc::setxattr(
+                        c_path.as_ptr(),
+                        "security.capability\0".as_ptr() as *const nix::libc::c_char,
+                        value.as_ptr() as *const nix::libc::c_void,
+                        value.len(),
+                        0,
+                    );
+                    assert_eq!(r, 0, "setxattr failed");
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::CapabilitiesChanged,
+                    (filename, path.to_str().unwrap().into(), "filename"),
+                    (permitted, cap_net_raw as u64, "permitted"),
+                    (effective, true, "effective")
+                ))
+                .report()
+        })
+    }
+
     fn hardlink() -> TestCase {
         TestCase::new("hardlink", async {
             let source = temp_dir().join("source");
@@ -448,9 +894,70 @@ pub mod test_suite {
                         destination,
                         destination.to_str().unwrap().into(),
                         "destination"
-                    )
+                    ),
+                    (overwrite, false, "overwrite")
+                ))
+                .report()
+        })
+    }
+
+    fn rename_overwrite() -> TestCase {
+        TestCase::new("rename_overwrite", async {
+            let source = temp_dir().join("rename_overwrite_source");
+            let destination = temp_dir().join("rename_overwrite_destination");
+            std::fs::write(&source, b"new binary").unwrap();
+            std::fs::write(&destination, b"old binary").unwrap();
+            TestRunner::with_ebpf(program)
+                .run(|| {
+                    std::fs::rename(&source, &destination).unwrap();
+                })
+                .await
+                .expect_event(event_check!(
+                    FsEvent::FileRename,
+                    (source, source.to_str().unwrap().into(), "source"),
+                    (
+                        destination,
+                        destination.to_str().unwrap().into(),
+                        "destination"
+                    ),
+                    (overwrite, true, "overwrite")
                 ))
                 .report()
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::pulsar::{resolve_link_target, sysctl_name};
+
+    #[test]
+    fn link_target() {
+        assert_eq!(
+            resolve_link_target("/tmp/link", "/etc/shadow"),
+            "/etc/shadow"
+        );
+        assert_eq!(resolve_link_target("/tmp/link", "shadow"), "/tmp/shadow");
+        assert_eq!(
+            resolve_link_target("/tmp/a/link", "../../etc/./shadow"),
+            "/etc/shadow"
+        );
+        assert_eq!(resolve_link_target("/link", "../../etc"), "/etc");
+    }
+
+    #[test]
+    fn sysctl_names() {
+        assert_eq!(
+            sysctl_name("/proc/sys/net/ipv4/ip_forward"),
+            "net.ipv4.ip_forward"
+        );
+        assert_eq!(
+            sysctl_name("/proc/sys/net/ipv4/conf/eth0.100/forwarding"),
+            "net.ipv4.conf.eth0/100.forwarding"
+        );
+        assert_eq!(
+            sysctl_name("/host/proc/sys/kernel/modules_disabled"),
+            "/host/proc/sys/kernel/modules_disabled"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Userspace cache of file paths, indexed by device and inode.
+//!
+//! The probes build paths by walking the dentry tree with a bounded number of
+//! iterations, which on kernels older than 5.17 is only 20 path components.
+//! When a path is truncated, the probes send the device and inode of the first
+//! missing ancestor: here we look it up among the paths of previously opened
+//! files and directories, or among the mount points, to rebuild the full path.
+
+use std::{
+    collections::HashMap,
+    os::unix::fs::MetadataExt,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use bpf_common::parsing::PathTruncation;
+
+/// The cache is cleared when it grows over this size
+const MAX_CACHED_PATHS: usize = 65536;
+
+/// Minimum interval between two reloads of the mount table
+const MOUNTS_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    /// Device in the userspace encoding, as returned by `stat`
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    /// Convert a device from the kernel `dev_t` encoding, used internally by
+    /// the kernel and the probes.
+    pub fn from_kernel(dev: u32, ino: u64) -> Self {
+        let major = dev >> 20;
+        let minor = dev & 0xfffff;
+        Self {
+            dev: nix::sys::stat::makedev(major as u64, minor as u64),
+            ino,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PathCache {
+    paths: HashMap<FileId, String>,
+    mounts_refresh: Option<Instant>,
+}
+
+impl PathCache {
+    /// Global cache shared by the file-system-monitor events
+    pub fn global() -> &'static Mutex<PathCache> {
+        static CACHE: OnceLock<Mutex<PathCache>> = OnceLock::new();
+        CACHE.get_or_init(Default::default)
+    }
+
+    /// Remember the full path of a file
+    pub fn insert(&mut self, id: FileId, path: &str) {
+        if self.paths.len() >= MAX_CACHED_PATHS {
+            self.paths.clear();
+        }
+        self.paths.insert(id, path.to_string());
+    }
+
+    /// Returns the full path of a path emitted by the probes: the path itself
+    /// when complete, or the joined ancestor path when it's truncated and the
+    /// ancestor is known.
+    pub fn resolve(&mut self, path: &str, truncation: &PathTruncation) -> Option<String> {
+        if !truncation.truncated {
+            return Some(path.to_string());
+        }
+        let ancestor = FileId::from_kernel(truncation.dev, truncation.ino);
+        if !self.paths.contains_key(&ancestor) {
+            self.refresh_mounts();
+        }
+        self.paths
+            .get(&ancestor)
+            .map(|ancestor_path| join(ancestor_path, path))
+    }
+
+    /// Add the mount points of the system to the cache. This is rate limited
+    /// since it's triggered by cache misses.
+    fn refresh_mounts(&mut self) {
+        if self
+            .mounts_refresh
+            .is_some_and(|last| last.elapsed() < MOUNTS_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.mounts_refresh = Some(Instant::now());
+        let mountinfo = match std::fs::read_to_string("/proc/self/mountinfo") {
+            Ok(mountinfo) => mountinfo,
+            Err(err) => {
+                log::warn!("Error reading mount table: {err}");
+                return;
+            }
+        };
+        for mount_point in parse_mount_points(&mountinfo) {
+            if let Ok(metadata) = std::fs::metadata(&mount_point) {
+                let id = FileId {
+                    dev: metadata.dev(),
+                    ino: metadata.ino(),
+                };
+                self.insert(id, &mount_point);
+            }
+        }
+    }
+}
+
+/// Join an ancestor path with a path relative to it, as emitted by the probes
+/// with a leading slash.
+fn join(ancestor: &str, path: &str) -> String {
+    format!("{}{}", ancestor.trim_end_matches('/'), path)
+}
+
+/// Extract the mount points, the fifth field of every mountinfo line.
+fn parse_mount_points(mountinfo: &str) -> impl Iterator<Item = String> + '_ {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+}
+
+/// Mountinfo escapes spaces, tabs, newlines and backslashes as octal values.
+fn unescape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        result.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 4);
+        match escape.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(byte) => {
+                result.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_dev_encoding() {
+        // 8:1 is encoded as (8 << 20 | 1) in the kernel and as 0x801 by stat
+        let id = FileId::from_kernel(8 << 20 | 1, 42);
+        assert_eq!(id.dev, 0x801);
+        assert_eq!(id.ino, 42);
+    }
+
+    #[test]
+    fn resolve_truncated_path() {
+        // Avoid reading the mount table of the test host
+        let mut cache = PathCache {
+            mounts_refresh: Some(Instant::now()),
+            ..Default::default()
+        };
+        cache.insert(FileId::from_kernel(1, 10), "/very/deep");
+
+        let complete = PathTruncation {
+            truncated: false,
+            dev: 0,
+            ino: 0,
+        };
+        assert_eq!(cache.resolve("/a/b", &complete).unwrap(), "/a/b");
+
+        let known = PathTruncation {
+            truncated: true,
+            dev: 1,
+            ino: 10,
+        };
+        assert_eq!(cache.resolve("/a/b", &known).unwrap(), "/very/deep/a/b");
+
+        let unknown = PathTruncation {
+            truncated: true,
+            dev: 1,
+            ino: 11,
+        };
+        assert_eq!(cache.resolve("/a/b", &unknown), None);
+
+        cache.insert(FileId::from_kernel(2, 2), "/");
+        let root = PathTruncation {
+            truncated: true,
+            dev: 2,
+            ino: 2,
+        };
+        assert_eq!(cache.resolve("/a/b", &root).unwrap(), "/a/b");
+    }
+
+    #[test]
+    fn mount_points() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:30 / /mnt/my\\040disk rw - tmpfs tmpfs rw
+";
+        assert_eq!(
+            parse_mount_points(mountinfo).collect::<Vec<_>>(),
+            vec!["/", "/mnt/my disk"]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! In-kernel filtering of file events by path.
+//!
+//! The probes check every path against `path_filter_map`, a longest prefix match
+//! trie filled from the module configuration: the longest matching prefix decides
+//! if the event is emitted, so `ignored_paths` can exclude sub-trees of
+//! `watched_paths`. Paths not matching any prefix are dropped.
+
+use bpf_common::{
+    aya::maps::lpm_trie::{Key, LpmTrie},
+    Program, ProgramError,
+};
+
+/// Must match PATH_FILTER_MAX in probes.bpf.c
+pub const PATH_FILTER_MAX: usize = 256;
+
+const PATH_FILTER_MAP: &str = "path_filter_map";
+
+const WATCHED: u8 = 1;
+const IGNORED: u8 = 0;
+
+/// Replace the content of the path filter map with the given prefixes.
+/// Prefixes are matched as strings, so `/tmp` matches `/tmpfile` as well.
+pub fn set_path_filter(
+    program: &mut Program,
+    watched: &[String],
+    ignored: &[String],
+) -> Result<(), ProgramError> {
+    let map = program
+        .bpf()
+        .map_mut(PATH_FILTER_MAP)
+        .ok_or_else(|| ProgramError::MapNotFound(PATH_FILTER_MAP.to_string()))?;
+    let mut trie: LpmTrie<_, [u8; PATH_FILTER_MAX], u8> = LpmTrie::try_from(map)?;
+
+    let old_keys = trie.keys().collect::<Result<Vec<_>, _>>()?;
+    for key in old_keys {
+        trie.remove(&key)?;
+    }
+
+    let prefixes = watched
+        .iter()
+        .map(|prefix| (prefix, WATCHED))
+        .chain(ignored.iter().map(|prefix| (prefix, IGNORED)));
+    for (prefix, value) in prefixes {
+        let (prefix_len, data) = prefix_key(prefix);
+        trie.insert(&Key::new(prefix_len, data), value, 0)?;
+    }
+    Ok(())
+}
+
+/// Returns the prefix length in bits and the key data of a path prefix.
+/// Prefixes longer than PATH_FILTER_MAX are truncated, like paths are by the probes.
+fn prefix_key(prefix: &str) -> (u32, [u8; PATH_FILTER_MAX]) {
+    let bytes = prefix.as_bytes();
+    let len = bytes.len().min(PATH_FILTER_MAX);
+    let mut data = [0; PATH_FILTER_MAX];
+    data[..len].copy_from_slice(&bytes[..len]);
+    (len as u32 * 8, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_from_prefix() {
+        let (prefix_len, data) = prefix_key("/etc");
+        assert_eq!(prefix_len, 32);
+        assert_eq!(&data[..5], b"/etc\0");
+
+        let long = "/a".repeat(PATH_FILTER_MAX);
+        let (prefix_len, data) = prefix_key(&long);
+        assert_eq!(prefix_len, PATH_FILTER_MAX as u32 * 8);
+        assert_eq!(&data[..], &long.as_bytes()[..PATH_FILTER_MAX]);
+    }
+}
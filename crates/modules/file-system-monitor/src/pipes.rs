@@ -0,0 +1,27 @@
+//! Attribution of the data passed through pipes.
+//!
+//! The probes remember the last process writing to every pipe, in
+//! `pipe_writer_map`, and report the first read by another process of every
+//! writer and reader pair: the event is sent by the reader, with the pid of the
+//! writer. This shows processes handing data or commands to each other, like a
+//! dropper staging a payload through a named pipe. The probes attach to the
+//! read and write handlers of the pipes, which see `read`, `write`, `readv`,
+//! `writev` and `io_uring`, not `splice` and `vmsplice`.
+//!
+//! Anonymous pipes connect most shell pipelines, so they're only reported when
+//! enabled in `pipe_config_map`. Named pipes are subject to the path filter.
+
+use bpf_common::{aya::maps::Array, Program, ProgramError};
+
+const PIPE_CONFIG_MAP: &str = "pipe_config_map";
+
+/// Enable or disable the reports of anonymous pipes.
+pub fn set_anonymous_pipes(program: &mut Program, enabled: bool) -> Result<(), ProgramError> {
+    let map = program
+        .bpf()
+        .map_mut(PIPE_CONFIG_MAP)
+        .ok_or_else(|| ProgramError::MapNotFound(PIPE_CONFIG_MAP.to_string()))?;
+    let mut array: Array<_, u32> = Array::try_from(map)?;
+    array.set(0, u32::from(enabled), 0)?;
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Conversion of the recorded events of `fixtures/`, which runs without root
+//! privileges or eBPF support.
+
+use bpf_common::fixtures;
+use file_system_monitor::FsEvent;
+use pulsar_core::pdk::{IntoPayload, Payload};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/events.fixture");
+
+fn payloads() -> Vec<Payload> {
+    fixtures::load::<FsEvent>(FIXTURE)
+        .unwrap()
+        .into_iter()
+        .map(|event| FsEvent::try_into_payload(event).unwrap())
+        .collect()
+}
+
+#[test]
+fn dropped_script() {
+    let payloads = payloads();
+    assert_eq!(payloads.len(), 4);
+    assert!(matches!(
+        &payloads[0],
+        Payload::FileCreated { filename } if filename == "/tmp/job.sh"
+    ));
+    assert!(matches!(
+        &payloads[1],
+        Payload::FileRename { source, destination, overwrite: false }
+            if source == "/tmp/job.sh" && destination == "/etc/cron.hourly/job"
+    ));
+    // Relative symlink targets are resolved against the link directory
+    assert!(matches!(
+        &payloads[2],
+        Payload::FileLink { source, destination, hard_link: false }
+            if source == "/usr/local/bin/job" && destination == "/etc/cron.hourly/job"
+    ));
+    assert!(matches!(
+        &payloads[3],
+        Payload::DirDeleted { dirname } if dirname == "/tmp/old"
+    ));
+}
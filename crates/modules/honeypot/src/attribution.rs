@@ -0,0 +1,264 @@
+//! Attribution of the connections to the decoy ports.
+//!
+//! Remote clients are identified by their address. Clients running on this
+//! host are identified by their process instead: the connection is matched
+//! with the `Connect` event of the network-monitor with the same destination.
+//! The event may be received before or after the connection is accepted, so
+//! both wait up to [`ATTRIBUTION_TIMEOUT`] for the other one; local connections
+//! without a matching event are then reported by address.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::pdk::{Event, Payload};
+
+use crate::listener::Connection;
+
+/// Time waited for the `Connect` event of a local connection, and the other
+/// way around.
+pub const ATTRIBUTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connection to report, with its source.
+#[derive(Debug)]
+pub enum Report {
+    /// Connection from a remote client, or from an unknown local process
+    Address(Connection),
+    /// Connection from a process of this host, with its `Connect` event
+    Process(Connection, Arc<Event>),
+}
+
+impl Report {
+    pub fn connection(&self) -> &Connection {
+        match self {
+            Report::Address(connection) | Report::Process(connection, _) => connection,
+        }
+    }
+}
+
+/// Source of the connections reported once per `cooldown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Address(IpAddr),
+    Process(i32),
+}
+
+pub struct Attribution {
+    ports: Vec<u16>,
+    ignored_sources: Vec<IpAddr>,
+    cooldown: Duration,
+    /// Last report by source and port
+    reported: HashMap<(Source, u16), SystemTime>,
+    /// Local connections waiting for their `Connect` event
+    pending: Vec<Connection>,
+    /// `Connect` events to the decoy ports waiting for their connection
+    connects: Vec<(SocketAddr, Arc<Event>)>,
+}
+
+impl Attribution {
+    pub fn new(ports: Vec<u16>, ignored_sources: Vec<IpAddr>, cooldown: Duration) -> Self {
+        Self {
+            ports,
+            ignored_sources,
+            cooldown,
+            reported: HashMap::new(),
+            pending: Vec::new(),
+            connects: Vec::new(),
+        }
+    }
+
+    /// Handle a connection accepted on a decoy port.
+    pub fn connection(&mut self, connection: Connection) -> Option<Report> {
+        if self
+            .ignored_sources
+            .contains(&connection.source.ip().to_canonical())
+        {
+            return None;
+        }
+        if !connection.is_local() {
+            return self.throttle(Report::Address(connection));
+        }
+        let destination = canonical(connection.destination);
+        match self
+            .connects
+            .iter()
+            .position(|(connect, _)| *connect == destination)
+        {
+            Some(index) => {
+                let (_, event) = self.connects.remove(index);
+                self.throttle(Report::Process(connection, event))
+            }
+            None => {
+                self.pending.push(connection);
+                None
+            }
+        }
+    }
+
+    /// Handle an event of the network-monitor, returning the pending local
+    /// connection it was made by.
+    pub fn event(&mut self, event: &Arc<Event>) -> Option<Report> {
+        let Payload::Connect {
+            destination,
+            is_tcp: true,
+        } = event.payload()
+        else {
+            return None;
+        };
+        if !self.ports.contains(&destination.port) {
+            return None;
+        }
+        let destination = canonical(SocketAddr::new(destination.ip, destination.port));
+        match self
+            .pending
+            .iter()
+            .position(|connection| canonical(connection.destination) == destination)
+        {
+            Some(index) => {
+                let connection = self.pending.remove(index);
+                self.throttle(Report::Process(connection, event.clone()))
+            }
+            None => {
+                self.connects.push((destination, event.clone()));
+                None
+            }
+        }
+    }
+
+    /// Report the local connections whose `Connect` event didn't arrive in
+    /// time, and forget the old events and reports.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Report> {
+        let expired = |time: SystemTime| time + ATTRIBUTION_TIMEOUT <= now;
+        self.connects
+            .retain(|(_, event)| !expired(event.header().timestamp));
+        self.reported.retain(|_, time| *time + self.cooldown > now);
+        let (unattributed, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|connection| expired(connection.time));
+        self.pending = pending;
+        unattributed
+            .into_iter()
+            .filter_map(|connection| self.throttle(Report::Address(connection)))
+            .collect()
+    }
+
+    /// Drop the reports of sources already reported for the port in the last
+    /// `cooldown`, like the many connections of a port scan.
+    fn throttle(&mut self, report: Report) -> Option<Report> {
+        let connection = report.connection();
+        let source = match &report {
+            Report::Address(connection) => Source::Address(connection.source.ip().to_canonical()),
+            Report::Process(_, event) => Source::Process(event.header().pid),
+        };
+        let key = (source, connection.destination.port());
+        match self.reported.get(&key) {
+            Some(time) if *time + self.cooldown > connection.time => None,
+            _ => {
+                self.reported.insert(key, connection.time);
+                Some(report)
+            }
+        }
+    }
+}
+
+fn canonical(address: SocketAddr) -> SocketAddr {
+    SocketAddr::new(address.ip().to_canonical(), address.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use pulsar_core::event::{Header, Host};
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn connection(source: &str, destination: &str, seconds: u64) -> Connection {
+        Connection {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            time: at(seconds),
+        }
+    }
+
+    fn connect(pid: i32, destination: &str, seconds: u64) -> Arc<Event> {
+        Arc::new(Event::new(
+            Header {
+                id: 1,
+                image: "/usr/bin/nc".to_string(),
+                pid,
+                parent_pid: 1,
+                timestamp: at(seconds),
+                ..Header::test_default()
+            },
+            Payload::Connect {
+                destination: Host::from(destination.parse::<SocketAddr>().unwrap()),
+                is_tcp: true,
+            },
+        ))
+    }
+
+    fn attribution() -> Attribution {
+        Attribution::new(
+            vec![23],
+            vec!["10.0.0.9".parse().unwrap()],
+            Duration::from_secs(60),
+        )
+    }
+
+    #[test]
+    fn remote() {
+        let mut attribution = attribution();
+        let report = attribution.connection(connection("1.2.3.4:5555", "10.0.0.1:23", 0));
+        assert!(matches!(report, Some(Report::Address(_))));
+        // Reported once per cooldown
+        assert!(attribution
+            .connection(connection("1.2.3.4:5556", "10.0.0.1:23", 10))
+            .is_none());
+        attribution.expire(at(70));
+        assert!(attribution
+            .connection(connection("1.2.3.4:5557", "10.0.0.1:23", 70))
+            .is_some());
+        // Ignored source
+        assert!(attribution
+            .connection(connection("10.0.0.9:5555", "10.0.0.1:23", 0))
+            .is_none());
+    }
+
+    #[test]
+    fn local() {
+        let mut attribution = attribution();
+        // Event received first
+        assert!(attribution.event(&connect(42, "127.0.0.1:23", 0)).is_none());
+        let report = attribution.connection(connection("127.0.0.1:5555", "127.0.0.1:23", 0));
+        assert!(matches!(report, Some(Report::Process(_, event)) if event.header().pid == 42));
+
+        // Connection accepted first, on a dual stack socket
+        assert!(attribution
+            .connection(connection(
+                "[::ffff:10.0.0.1]:5555",
+                "[::ffff:10.0.0.1]:23",
+                0
+            ))
+            .is_none());
+        let report = attribution.event(&connect(43, "10.0.0.1:23", 0));
+        assert!(matches!(report, Some(Report::Process(_, event)) if event.header().pid == 43));
+
+        // Other ports are not followed
+        assert!(attribution.event(&connect(44, "1.2.3.4:443", 0)).is_none());
+        assert!(attribution.connects.is_empty());
+
+        // Without event, reported by address
+        attribution.connection(connection("127.0.0.1:5556", "127.0.0.1:23", 10));
+        assert!(attribution.expire(at(11)).is_empty());
+        let reports = attribution.expire(at(12));
+        assert!(matches!(reports.as_slice(), [Report::Address(_)]));
+    }
+}
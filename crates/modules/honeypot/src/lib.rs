@@ -0,0 +1,239 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{
+    event::{Host, Severity, Threat, Value},
+    pdk::{
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+        ModuleContext, ModuleError, ModuleSender, Payload, PulsarModule, ShutdownSignal, Version,
+    },
+    Pid, Timestamp,
+};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+pub mod attribution;
+pub mod listener;
+
+use attribution::{Attribution, Report};
+use listener::Listeners;
+
+const MODULE_NAME: &str = "honeypot";
+const DEFAULT_BIND_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_COOLDOWN: u64 = 60;
+/// Connections waiting to be reported, the others are dropped.
+const CONNECTIONS_QUEUE_SIZE: usize = 1024;
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        honeypot_task,
+    )
+    // Connections from local processes are attributed with its events
+    .depends_on("network-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(ConfigField::new(
+                "ports",
+                ConfigKind::List,
+                "TCP ports of the decoy services, raising a threat on every connection",
+            ))
+            .field(
+                ConfigField::new(
+                    "bind_address",
+                    ConfigKind::String,
+                    "Address of the decoy services, `::` for IPv4 and IPv6",
+                )
+                .default_value(DEFAULT_BIND_ADDRESS),
+            )
+            .field(ConfigField::new(
+                "action",
+                ConfigKind::String,
+                "Playbook run by threat-response on the threats",
+            ))
+            .field(
+                ConfigField::new(
+                    "cooldown",
+                    ConfigKind::Integer,
+                    "Seconds during which the connections from the same source to a port are reported once",
+                )
+                .default_value(DEFAULT_COOLDOWN)
+                .range(0, 86400),
+            )
+            .field(ConfigField::new(
+                "ignored_sources",
+                ConfigKind::List,
+                "Addresses never reported, like the ones of vulnerability scanners",
+            )),
+    )
+}
+
+async fn honeypot_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    let (tx_connections, mut rx_connections) = mpsc::channel(CONNECTIONS_QUEUE_SIZE);
+    let mut config: Config = rx_config.read()?;
+    let mut listeners =
+        Listeners::bind(config.bind_address, &config.ports, tx_connections.clone()).await?;
+    let mut attribution = config.attribution();
+    let mut expire_interval = tokio::time::interval(Duration::from_secs(1));
+    let own_pid = std::process::id() as i32;
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => {
+                listeners.stop().await;
+                return r;
+            }
+            _ = rx_config.changed() => {
+                config = rx_config.read()?;
+                listeners.stop().await;
+                // A port taken in the meantime must not stop the other decoys
+                listeners = Listeners::bind_available(
+                    config.bind_address,
+                    &config.ports,
+                    tx_connections.clone(),
+                )
+                .await;
+                attribution = config.attribution();
+            }
+            _ = expire_interval.tick() => {
+                for report in attribution.expire(SystemTime::now()) {
+                    send_report(&sender, &config, report);
+                }
+            }
+            connection = rx_connections.recv() => {
+                // The module keeps a sender, the channel is never closed
+                let Some(connection) = connection else { continue };
+                if let Some(report) = attribution.connection(connection) {
+                    send_report(&sender, &config, report);
+                }
+            }
+            event = receiver.recv() => {
+                let event = event?;
+                if event.header().threat.is_some() || event.header().pid == own_pid {
+                    continue;
+                }
+                if let Some(report) = attribution.event(&event) {
+                    send_report(&sender, &config, report);
+                }
+            }
+        }
+    }
+}
+
+/// Extra data of the threats.
+#[derive(Serialize)]
+struct HoneypotData {
+    port: u16,
+    source: String,
+    /// Playbook run by threat-response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<String>,
+}
+
+/// Send the threat of a connection. Remote clients are reported with an
+/// `Accept` of the daemon, so that `block_network` blocks their address, local
+/// processes with the headers of their `Connect`, so that `kill_process` kills
+/// them. The payload of local threats is empty: the destination of the
+/// `Connect` is the address of the host, which `block_network` must not block.
+fn send_report(sender: &ModuleSender, config: &Config, report: Report) {
+    let connection = report.connection();
+    let port = connection.destination.port();
+    let data = HoneypotData {
+        port,
+        source: connection.source.to_string(),
+        action: config.action.clone(),
+    };
+    let extra = Value::try_from(data).ok();
+    match &report {
+        Report::Address(connection) => {
+            let description = format!(
+                "Connection to honeypot port {port} from {}",
+                connection.source
+            );
+            let payload = Payload::Accept {
+                source: host(connection.source),
+                destination: host(connection.destination),
+                reuseport_group: 0,
+            };
+            let threat = Threat {
+                id: String::new(),
+                source: MODULE_NAME.into(),
+                description,
+                severity: Severity::High,
+                outputs: None,
+                extra,
+                evidence: None,
+            };
+            sender.send_threat_payload(
+                Pid::from_raw(std::process::id() as i32),
+                Timestamp::now(),
+                payload,
+                threat,
+            );
+        }
+        Report::Process(_, event) => {
+            let description = format!(
+                "Connection to honeypot port {port} from {} (pid {})",
+                event.header().image,
+                event.header().pid
+            );
+            let event = Event::new(event.header().clone(), Payload::Empty);
+            sender.send_threat_derived(&event, description, Severity::High, extra);
+        }
+    }
+}
+
+fn host(address: SocketAddr) -> Host {
+    let mut host = Host::from(address);
+    host.unmap_ipv4();
+    host
+}
+
+#[derive(Clone)]
+struct Config {
+    ports: Vec<u16>,
+    bind_address: IpAddr,
+    action: Option<String>,
+    cooldown: u64,
+    ignored_sources: Vec<IpAddr>,
+}
+
+impl Config {
+    fn attribution(&self) -> Attribution {
+        Attribution::new(
+            self.ports.clone(),
+            self.ignored_sources
+                .iter()
+                .map(|address| address.to_canonical())
+                .collect(),
+            Duration::from_secs(self.cooldown),
+        )
+    }
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ports: config.get_list("ports")?,
+            bind_address: config.with_default("bind_address", DEFAULT_BIND_ADDRESS)?,
+            action: config
+                .get_raw("action")
+                .filter(|action| !action.is_empty())
+                .map(str::to_string),
+            cooldown: config.with_default("cooldown", DEFAULT_COOLDOWN)?,
+            ignored_sources: config.get_list("ignored_sources")?,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+//! Decoy listening sockets.
+//!
+//! Every port is served by a task accepting the connections and closing them
+//! right away: nothing is ever read from or written to the clients.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::SystemTime,
+};
+
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+
+/// Connection accepted on a decoy port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub source: SocketAddr,
+    /// Local address of the connection, with the decoy port
+    pub destination: SocketAddr,
+    pub time: SystemTime,
+}
+
+impl Connection {
+    /// Check if the client runs on this host: it connected to one of the local
+    /// addresses, so its address is local too.
+    pub fn is_local(&self) -> bool {
+        let source = self.source.ip().to_canonical();
+        source.is_loopback() || source == self.destination.ip().to_canonical()
+    }
+}
+
+/// The tasks accepting connections on the decoy ports.
+#[derive(Default)]
+pub struct Listeners {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Listeners {
+    /// Listen on the ports of `address`, sending the accepted connections to
+    /// `tx`. Connections are dropped when the channel is full, like in a port
+    /// scan.
+    pub async fn bind(
+        address: IpAddr,
+        ports: &[u16],
+        tx: mpsc::Sender<Connection>,
+    ) -> std::io::Result<Self> {
+        let mut listeners = Vec::new();
+        for port in ports {
+            listeners.push(bind(address, *port).await?);
+        }
+        Ok(Self::spawn(listeners, tx))
+    }
+
+    /// Like [`Listeners::bind`], logging the ports which can't be bound
+    /// instead of failing, to keep the other decoys running after a
+    /// configuration change.
+    pub async fn bind_available(
+        address: IpAddr,
+        ports: &[u16],
+        tx: mpsc::Sender<Connection>,
+    ) -> Self {
+        let mut listeners = Vec::new();
+        for port in ports {
+            match bind(address, *port).await {
+                Ok(listener) => listeners.push(listener),
+                Err(err) => log::error!("Error listening on honeypot port: {err}"),
+            }
+        }
+        Self::spawn(listeners, tx)
+    }
+
+    fn spawn(listeners: Vec<TcpListener>, tx: mpsc::Sender<Connection>) -> Self {
+        let tasks = listeners
+            .into_iter()
+            .map(|listener| tokio::spawn(accept(listener, tx.clone())))
+            .collect();
+        Self { tasks }
+    }
+
+    /// Stop listening, waiting for the ports to be released so that they can
+    /// be bound again.
+    pub async fn stop(self) {
+        for task in self.tasks {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+async fn bind(address: IpAddr, port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind((address, port)).await.map_err(|err| {
+        std::io::Error::new(err.kind(), format!("binding {address} port {port}: {err}"))
+    })
+}
+
+async fn accept(listener: TcpListener, tx: mpsc::Sender<Connection>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, source)) => {
+                let Ok(destination) = stream.local_addr() else {
+                    continue;
+                };
+                let connection = Connection {
+                    source,
+                    destination,
+                    time: SystemTime::now(),
+                };
+                if tx.try_send(connection).is_err() {
+                    log::debug!("Dropping honeypot connection from {source}");
+                }
+            }
+            Err(err) => log::warn!("Error accepting honeypot connection: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn accept_connections() {
+        let (tx, mut rx) = mpsc::channel(8);
+        // Find a free port
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = IpAddr::from([127, 0, 0, 1]);
+        let listeners = Listeners::bind(address, &[port], tx).await.unwrap();
+
+        let client = tokio::net::TcpStream::connect((address, port))
+            .await
+            .unwrap();
+        let connection = rx.recv().await.unwrap();
+        assert_eq!(connection.source, client.local_addr().unwrap());
+        assert_eq!(connection.destination.port(), port);
+        assert!(connection.is_local());
+
+        // The port can be bound again once stopped
+        listeners.stop().await;
+        let (tx, _rx) = mpsc::channel(8);
+        Listeners::bind(address, &[port], tx)
+            .await
+            .unwrap()
+            .stop()
+            .await;
+    }
+
+    #[tokio::test]
+    async fn port_in_use() {
+        let address = IpAddr::from([127, 0, 0, 1]);
+        let taken = std::net::TcpListener::bind((address, 0)).unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let free_port = std::net::TcpListener::bind((address, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (tx, mut rx) = mpsc::channel(8);
+        assert!(
+            Listeners::bind(address, &[free_port, taken_port], tx.clone())
+                .await
+                .is_err()
+        );
+
+        // The free port is still served
+        let listeners = Listeners::bind_available(address, &[free_port, taken_port], tx).await;
+        assert_eq!(listeners.tasks.len(), 1);
+        let _client = tokio::net::TcpStream::connect((address, free_port))
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap().destination.port(), free_port);
+        listeners.stop().await;
+    }
+}
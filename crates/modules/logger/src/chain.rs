@@ -0,0 +1,387 @@
+//! Tamper-evident log of the threats, where every record includes the hash of
+//! the previous one.
+//!
+//! Records are JSON lines like `{"seq":1,"prev":"00..","hash":"3f..","event":{..}}`,
+//! where `hash` is the SHA-256 of the sequence number, the previous hash and
+//! the event as written. Changing, removing or reordering records breaks the
+//! chain, while truncating the log is detected by comparing the head with the
+//! last one anchored outside of the host.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+};
+
+use pulsar_core::pdk::Event;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Previous hash of the first record.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Socket of the native protocol of systemd-journald.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Bytes read from the end of the log to find the last record on start.
+const MAX_RECORD_LEN: u64 = 1024 * 1024;
+
+const EVENT_FIELD: &str = ",\"event\":";
+
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("error opening event log {path}")]
+    Opening {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+    #[error("error writing event log {path}")]
+    Writing {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+    #[error("error serializing event")]
+    Serializing(#[from] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("error reading line {line}")]
+    Reading {
+        line: u64,
+        #[source]
+        error: io::Error,
+    },
+    #[error("line {line} is not a record of the event log")]
+    Malformed { line: u64 },
+    #[error("line {line} doesn't follow the previous record: records were removed or reordered")]
+    Broken { line: u64 },
+    #[error("line {line} doesn't match its hash: the record was modified")]
+    Modified { line: u64 },
+    #[error("the anchored hash {hash} is not in the log: records were removed from its end")]
+    AnchorNotFound { hash: String },
+}
+
+#[derive(Error, Debug)]
+pub enum AnchorError {
+    #[error("error sending the head to the journal")]
+    Journal(#[from] io::Error),
+    #[error("error sending the head to {url}")]
+    Remote {
+        url: String,
+        #[source]
+        error: reqwest::Error,
+    },
+}
+
+/// Last record of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl Head {
+    fn genesis() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RecordHeader {
+    seq: u64,
+    prev: String,
+    hash: String,
+}
+
+fn record_hash(seq: u64, prev: &str, event: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{seq}\n{prev}\n{event}"));
+    hex::encode(hasher.finalize())
+}
+
+/// Split a line in its header and the event as written, which is hashed.
+fn parse_record(line: &str) -> Option<(RecordHeader, &str)> {
+    let header = serde_json::from_str(line).ok()?;
+    let start = line.find(EVENT_FIELD)? + EVENT_FIELD.len();
+    let event = line.get(start..)?.strip_suffix('}')?;
+    Some((header, event))
+}
+
+/// Event log open for appending.
+pub struct HashChain {
+    path: PathBuf,
+    file: File,
+    head: Head,
+}
+
+impl HashChain {
+    /// Open the log at `path`, continuing the chain of its last record.
+    pub fn open(path: &Path) -> Result<Self, ChainError> {
+        let opening = |error| ChainError::Opening {
+            path: path.display().to_string(),
+            error,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(opening)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(opening)?;
+        let head = match last_line(&mut file).map_err(opening)? {
+            None => Head::genesis(),
+            Some(line) => match parse_record(&line) {
+                Some((header, _)) => Head {
+                    seq: header.seq,
+                    hash: header.hash,
+                },
+                // Verification will report where the new chain starts
+                None => {
+                    log::warn!(
+                        "Last record of {} is invalid, starting a new chain",
+                        path.display()
+                    );
+                    Head::genesis()
+                }
+            },
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            head,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn head(&self) -> &Head {
+        &self.head
+    }
+
+    /// Append an event, written to the file before returning.
+    pub fn append(&mut self, event: &Event) -> Result<(), ChainError> {
+        let event = serde_json::to_string(event)?;
+        let seq = self.head.seq + 1;
+        let hash = record_hash(seq, &self.head.hash, &event);
+        let line = format!(
+            "{{\"seq\":{seq},\"prev\":\"{}\",\"hash\":\"{hash}\"{EVENT_FIELD}{event}}}\n",
+            self.head.hash
+        );
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|error| ChainError::Writing {
+                path: self.path.display().to_string(),
+                error,
+            })?;
+        self.head = Head { seq, hash };
+        Ok(())
+    }
+}
+
+/// Last non empty line of the file, if it's shorter than MAX_RECORD_LEN.
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(MAX_RECORD_LEN)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(tail
+        .split(|byte| *byte == b'\n')
+        .rfind(|line| !line.is_empty())
+        .map(|line| String::from_utf8_lossy(line).into_owned()))
+}
+
+/// Check every record of a log, returning its head. When the hash of an
+/// anchored head is given, it must be one of the records.
+pub fn verify(log: impl BufRead, anchor: Option<&str>) -> Result<Head, VerifyError> {
+    let mut head = Head::genesis();
+    let mut anchor_found = false;
+    for (index, line) in log.lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let line = line.map_err(|error| VerifyError::Reading {
+            line: line_number,
+            error,
+        })?;
+        let (header, event) =
+            parse_record(&line).ok_or(VerifyError::Malformed { line: line_number })?;
+        if header.seq != head.seq + 1 || header.prev != head.hash {
+            return Err(VerifyError::Broken { line: line_number });
+        }
+        if record_hash(header.seq, &header.prev, event) != header.hash {
+            return Err(VerifyError::Modified { line: line_number });
+        }
+        anchor_found |= anchor == Some(header.hash.as_str());
+        head = Head {
+            seq: header.seq,
+            hash: header.hash,
+        };
+    }
+    match anchor {
+        Some(hash) if !anchor_found => Err(VerifyError::AnchorNotFound {
+            hash: hash.to_string(),
+        }),
+        _ => Ok(head),
+    }
+}
+
+/// Destination of the head of the chain, sent periodically so that the log
+/// can't be rewritten without being noticed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anchor {
+    /// The systemd journal, with the `PULSAR_LOG_SEQ` and `PULSAR_LOG_HASH` fields
+    Journal,
+    /// HTTP endpoint receiving the head as JSON with a POST request
+    Remote(String),
+}
+
+impl Anchor {
+    /// Parse `journal` or an HTTP URL.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value == "journal" {
+            Some(Self::Journal)
+        } else if value.starts_with("http://") || value.starts_with("https://") {
+            Some(Self::Remote(value.to_string()))
+        } else {
+            None
+        }
+    }
+
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        path: &Path,
+        head: &Head,
+    ) -> Result<(), AnchorError> {
+        match self {
+            Anchor::Journal => {
+                let message = format!(
+                    "MESSAGE=Event log {path} head {seq} {hash}\n\
+                     SYSLOG_IDENTIFIER=pulsar\n\
+                     PRIORITY=6\n\
+                     PULSAR_LOG_PATH={path}\n\
+                     PULSAR_LOG_SEQ={seq}\n\
+                     PULSAR_LOG_HASH={hash}\n",
+                    path = path.display(),
+                    seq = head.seq,
+                    hash = head.hash,
+                );
+                UnixDatagram::unbound()?.send_to(message.as_bytes(), JOURNAL_SOCKET)?;
+                Ok(())
+            }
+            Anchor::Remote(url) => {
+                let body = serde_json::json!({
+                    "path": path,
+                    "seq": head.seq,
+                    "hash": head.hash,
+                });
+                client
+                    .post(url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ())
+                    .map_err(|error| AnchorError::Remote {
+                        url: url.clone(),
+                        error,
+                    })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, io::BufReader};
+
+    use pulsar_core::{event::Header, pdk::Payload};
+
+    use super::*;
+
+    fn event(pid: i32) -> Event {
+        Event::new(
+            Header {
+                id: pid as u64,
+                image: "/usr/bin/bash".to_string(),
+                pid,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 0 },
+        )
+    }
+
+    fn verify_lines(lines: &[&str], anchor: Option<&str>) -> Result<Head, VerifyError> {
+        verify(BufReader::new(lines.join("\n").as_bytes()), anchor)
+    }
+
+    #[test]
+    fn chain() {
+        let dir = temp_dir().join(format!("pulsar-logger-chain-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("events.log");
+
+        let mut chain = HashChain::open(&path).unwrap();
+        assert_eq!(chain.head(), &Head::genesis());
+        chain.append(&event(1)).unwrap();
+        chain.append(&event(2)).unwrap();
+        let anchored = chain.head().clone();
+        drop(chain);
+
+        // The chain continues after a restart
+        let mut chain = HashChain::open(&path).unwrap();
+        assert_eq!(chain.head(), &anchored);
+        chain.append(&event(3)).unwrap();
+        let head = chain.head().clone();
+        assert_eq!(head.seq, 3);
+
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(verify_lines(&lines, None).unwrap(), head);
+        assert_eq!(verify_lines(&lines, Some(&anchored.hash)).unwrap(), head);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let modified = lines[1].replace("\"pid\":2", "\"pid\":4");
+        assert_ne!(modified, lines[1]);
+        assert!(matches!(
+            verify_lines(&[lines[0], &modified, lines[2]], None),
+            Err(VerifyError::Modified { line: 2 })
+        ));
+        assert!(matches!(
+            verify_lines(&[lines[0], lines[2]], None),
+            Err(VerifyError::Broken { line: 2 })
+        ));
+        assert!(matches!(
+            verify_lines(&[lines[0], "garbage"], None),
+            Err(VerifyError::Malformed { line: 2 })
+        ));
+        // Truncated after the anchor was sent
+        assert!(matches!(
+            verify_lines(&lines[..1], Some(&anchored.hash)),
+            Err(VerifyError::AnchorNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(Anchor::parse("journal"), Some(Anchor::Journal));
+        assert_eq!(
+            Anchor::parse("https://logs.example.com/anchors"),
+            Some(Anchor::Remote(
+                "https://logs.example.com/anchors".to_string()
+            ))
+        );
+        assert_eq!(Anchor::parse("syslog"), None);
+    }
+}
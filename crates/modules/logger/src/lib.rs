@@ -1,9 +1,21 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use pulsar_core::pdk::{
-    CleanExit, ConfigError, Event, ModuleConfig, ModuleContext, ModuleError, PulsarModule,
-    ShutdownSignal, Version,
+    CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+    ModuleContext, ModuleError, PulsarModule, ShutdownSignal, Version,
 };
 
+pub mod chain;
+
+use chain::{Anchor, HashChain, Head};
+
 const MODULE_NAME: &str = "logger";
+const DEFAULT_ANCHOR_INTERVAL: u64 = 300;
 
 pub fn module() -> PulsarModule {
     PulsarModule::new(
@@ -12,22 +24,57 @@ pub fn module() -> PulsarModule {
         true,
         logger_task,
     )
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new("console", ConfigKind::Bool, "Print threats to the console")
+                    .default_value(true),
+            )
+            .field(ConfigField::new(
+                "chain_path",
+                ConfigKind::Path,
+                "File where threats are appended as a hash chain, detecting changes to the log",
+            ))
+            .field(ConfigField::new(
+                "chain_anchor",
+                ConfigKind::String,
+                "Where the head of the chain is sent periodically: journal or an HTTP URL",
+            ))
+            .field(
+                ConfigField::new(
+                    "chain_anchor_interval",
+                    ConfigKind::Integer,
+                    "Seconds between two anchors of the head of the chain",
+                )
+                .default_value(DEFAULT_ANCHOR_INTERVAL)
+                .range(1, 86400),
+            ),
+    )
 }
 
 async fn logger_task(
     ctx: ModuleContext,
     mut shutdown: ShutdownSignal,
 ) -> Result<CleanExit, ModuleError> {
-    let mut receiver = ctx.get_receiver();
+    let mut receiver = ctx.get_output_receiver();
     let mut rx_config = ctx.get_config();
-    let mut logger = Logger::from_config(rx_config.read()?);
+    let mut logger = Logger::from_config(rx_config.read()?)?;
+    let mut anchor_interval = tokio::time::interval(logger.anchor_interval);
 
     loop {
         tokio::select! {
-            r = shutdown.recv() => return r,
+            r = shutdown.recv() => {
+                // The last threats must be anchored before the daemon exits
+                if let Some(send_anchor) = logger.anchor_head() {
+                    send_anchor.await;
+                }
+                return r;
+            }
             _ = rx_config.changed() => {
-                logger = Logger::from_config(rx_config.read()?);
+                logger = Logger::from_config(rx_config.read()?)?;
+                anchor_interval = tokio::time::interval(logger.anchor_interval);
             }
+            _ = anchor_interval.tick() => logger.anchor(),
             msg = receiver.recv() => {
                 let msg = msg?;
                 logger.process(&msg)
@@ -39,7 +86,9 @@ async fn logger_task(
 #[derive(Clone)]
 struct Config {
     console: bool,
-    // file: bool, //TODO:
+    chain_path: Option<PathBuf>,
+    chain_anchor: Option<Anchor>,
+    chain_anchor_interval: u64,
     // syslog: bool, //TODO:
 }
 
@@ -47,9 +96,25 @@ impl TryFrom<&ModuleConfig> for Config {
     type Error = ConfigError;
 
     fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let chain_anchor = match config.get_raw("chain_anchor") {
+            None | Some("") => None,
+            Some(value) => Some(
+                Anchor::parse(value).ok_or_else(|| ConfigError::InvalidValue {
+                    field: "chain_anchor".to_string(),
+                    value: value.to_string(),
+                    err: "expected journal or an http:// or https:// URL".to_string(),
+                })?,
+            ),
+        };
         Ok(Self {
             console: config.with_default("console", true)?,
-            // file: config.required("file")?,
+            chain_path: config
+                .get_raw("chain_path")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            chain_anchor,
+            chain_anchor_interval: config
+                .with_default("chain_anchor_interval", DEFAULT_ANCHOR_INTERVAL)?,
             // syslog: config.required("syslog")?,
         })
     }
@@ -57,18 +122,81 @@ impl TryFrom<&ModuleConfig> for Config {
 
 struct Logger {
     console: bool,
+    chain: Option<HashChain>,
+    anchor: Option<Anchor>,
+    anchor_interval: Duration,
+    /// Head sent by the last successful anchor, to skip the anchors without
+    /// new records. Updated by the anchor futures once the head is sent.
+    anchored: Arc<Mutex<Option<Head>>>,
+    http_client: reqwest::Client,
 }
 
 impl Logger {
-    fn from_config(rx_config: Config) -> Self {
-        let Config { console } = rx_config;
-        Self { console }
+    fn from_config(rx_config: Config) -> Result<Self, chain::ChainError> {
+        let Config {
+            console,
+            chain_path,
+            chain_anchor,
+            chain_anchor_interval,
+        } = rx_config;
+        let chain = chain_path.as_deref().map(HashChain::open).transpose()?;
+        Ok(Self {
+            console,
+            chain,
+            anchor: chain_anchor,
+            anchor_interval: Duration::from_secs(chain_anchor_interval),
+            anchored: Arc::new(Mutex::new(None)),
+            http_client: reqwest::Client::new(),
+        })
     }
 
-    fn process(&self, event: &Event) {
-        if event.header().threat.is_some() && self.console {
+    fn process(&mut self, event: &Event) {
+        if event.header().threat.is_none() {
+            return;
+        }
+        if self.console {
             terminal::print_event(event);
         }
+        if let Some(chain) = &mut self.chain {
+            if let Err(err) = chain.append(event) {
+                log::error!("{err}");
+            }
+        }
+    }
+
+    /// Send the head of the chain to the anchor in background.
+    fn anchor(&mut self) {
+        if let Some(send_anchor) = self.anchor_head() {
+            tokio::spawn(send_anchor);
+        }
+    }
+
+    /// Future sending the head of the chain to the anchor, if it changed since
+    /// the last successful anchor: a failed one is retried on the next tick.
+    fn anchor_head(&mut self) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let (Some(chain), Some(anchor)) = (&self.chain, &self.anchor) else {
+            return None;
+        };
+        let head = chain.head().clone();
+        if self.anchored.lock().unwrap().as_ref() == Some(&head) {
+            return None;
+        }
+        let anchor = anchor.clone();
+        let client = self.http_client.clone();
+        let path = chain.path().to_path_buf();
+        let anchored = self.anchored.clone();
+        Some(async move {
+            match anchor.send(&client, &path, &head).await {
+                Ok(()) => {
+                    // A slower anchor of an older head must not replace it
+                    let mut anchored = anchored.lock().unwrap();
+                    if anchored.as_ref().is_none_or(|last| last.seq < head.seq) {
+                        *anchored = Some(head);
+                    }
+                }
+                Err(err) => log::warn!("{err}"),
+            }
+        })
     }
 }
 
@@ -82,19 +210,57 @@ pub mod terminal {
         let image = &header.image;
         let pid = &header.pid;
         let payload = event.payload();
+        let id = match header.parent_event_id {
+            Some(parent_event_id) => format!("#{} <- #{parent_event_id}", header.id),
+            None => format!("#{}", header.id),
+        };
+        let id = match &header.coalesced {
+            Some(coalesced) => format!("{id} x{}", coalesced.count),
+            None => id,
+        };
 
         if let Some(Threat {
+            id: threat_id,
             source,
             description,
-            extra: _,
+            ..
         }) = &event.header().threat
         {
             println!(
-                "[{time} \x1b[1;30;43mTHREAT\x1b[0m  {image} ({pid})] [{source} - {description}] {payload}"
+                "[{time} \x1b[1;30;43mTHREAT\x1b[0m  {image} ({pid}) {id}] [{source} - {description} ({threat_id})] {payload}"
             )
         } else {
             let source = &header.source;
-            println!("[{time} \x1b[1;30;46mEVENT\x1b[0m  {image} ({pid})] [{source}] {payload}")
+            println!(
+                "[{time} \x1b[1;30;46mEVENT\x1b[0m  {image} ({pid}) {id}] [{source}] {payload}"
+            )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn failed_anchor() {
+        let dir = temp_dir().join(format!("pulsar-logger-anchor-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut logger = Logger::from_config(Config {
+            console: false,
+            chain_path: Some(dir.join("events.log")),
+            // Nothing listens on the discard port
+            chain_anchor: Some(Anchor::Remote("http://127.0.0.1:9".to_string())),
+            chain_anchor_interval: DEFAULT_ANCHOR_INTERVAL,
+        })
+        .unwrap();
+
+        logger.anchor_head().unwrap().await;
+        assert!(logger.anchored.lock().unwrap().is_none());
+        // Retried on the next tick
+        assert!(logger.anchor_head().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -15,6 +15,7 @@ char LICENSE[] SEC("license") = "GPL v2";
 #define EVENT_SEND 4
 #define EVENT_RECV 5
 #define EVENT_CLOSE 6
+#define EVENT_FIREWALL 7
 
 #define PROTO_TCP 0
 #define PROTO_UDP 1
@@ -24,7 +25,39 @@ char LICENSE[] SEC("license") = "GPL v2";
 #define AF_INET 2   /* Internet IP Protocol */
 #define AF_INET6 10 /* IP version 6 */
 
-#define MAX_DATA_SIZE 4096
+// Data copied in a single event. Longer messages are copied in several
+// events, the chunks, reassembled by userspace.
+#define CHUNK_SIZE 4096
+#define MAX_CHUNKS 16
+
+// Copy state of the data of a message
+#define CAPTURE_DISABLED 0
+#define CAPTURE_EMPTY 1
+#define CAPTURE_COMPLETE 2
+#define CAPTURE_TRUNCATED 3
+
+// Firewall backends
+#define FIREWALL_NFTABLES 0
+#define FIREWALL_IPTABLES_LEGACY 1
+
+#define NETLINK_NETFILTER 12
+#define NFNL_SUBSYS_NFTABLES 10
+#define NFT_MSG_NEWTABLE 0
+#define NFT_MSG_DELTABLE 2
+#define NFT_MSG_NEWCHAIN 3
+#define NFT_MSG_DELCHAIN 5
+#define NFT_MSG_NEWRULE 6
+#define NFT_MSG_DELRULE 8
+#define NFT_MSG_DESTROYTABLE 26
+#define NFT_MSG_DESTROYCHAIN 27
+#define NFT_MSG_DESTROYRULE 28
+// Messages of a netlink batch inspected, further ones are not counted
+#define MAX_NFT_MESSAGES 64
+
+#define SOL_IP 0
+#define SOL_IPV6 41
+// Same value for IP6T_SO_SET_REPLACE
+#define IPT_SO_SET_REPLACE 64
 
 struct address {
   u8 ip_ver;
@@ -47,6 +80,7 @@ struct connect_event {
 struct accept_event {
   struct address source;
   struct address destination;
+  u32 reuseport_group;
 };
 
 struct msg_event {
@@ -55,16 +89,32 @@ struct msg_event {
   struct buffer_index data;
   u32 data_len;
   u8 proto;
+  u8 capture;
+  // Index of the chunk of data copied in this event, out of `chunks`
+  u8 chunk;
+  u8 chunks;
+  u64 offset;
 };
 
 struct close_event {
   pid_t original_pid;
   struct address source;
   struct address destination;
+  char comm[TASK_COMM_LEN];
+};
+
+struct firewall_event {
+  u8 backend;
+  u32 tables_added;
+  u32 tables_removed;
+  u32 chains_added;
+  u32 chains_removed;
+  u32 rules_added;
+  u32 rules_removed;
 };
 
 struct arguments {
-  void *data[3];
+  void *data[4];
 };
 
 GLOBAL_INTEREST_MAP_DECLARATION;
@@ -77,15 +127,43 @@ OUTPUT_MAP(network_event, {
   struct msg_event send;
   struct msg_event recv;
   struct close_event close;
+  struct firewall_event firewall;
 });
 
-// Map a socket pointer to its creating process
+// Process owning a socket, saved in process context when the socket is
+// connected or accepted. Later state changes, like the close, often run in
+// softirq context, where the current task is unrelated or the idle task.
+struct sock_owner {
+  pid_t tgid;
+  char comm[TASK_COMM_LEN];
+  // Bytes sent and received on the stream since the owner was saved
+  u64 sent;
+  u64 received;
+};
+
+// Map a TCP socket pointer to its owning process. Entries of sockets never
+// reaching TCP_CLOSE are eventually evicted.
 struct {
-  __uint(type, BPF_MAP_TYPE_HASH);
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
   __type(key, struct sock *);
-  __type(value, pid_t);
-  __uint(max_entries, 10240);
-} tcp_set_state_map SEC(".maps");
+  __type(value, struct sock_owner);
+  __uint(max_entries, 16384);
+} sock_owner_map SEC(".maps");
+
+// Bytes of data to copy from messages, written by userspace from the module
+// configuration. UDP messages are copied to parse DNS, the first message of TCP
+// streams to detect the protocol.
+struct capture_config {
+  u32 size;
+  u32 first_message_size;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, u32);
+  __type(value, struct capture_config);
+  __uint(max_entries, 1);
+} capture_config_map SEC(".maps");
 
 // Maps for sharing data between various hook points
 struct {
@@ -95,6 +173,14 @@ struct {
   __uint(max_entries, 1024);
 } args_map SEC(".maps");
 
+// Messages being sent, emitted when the syscall returns the bytes sent
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, u64);
+  __type(value, struct arguments);
+  __uint(max_entries, 1024);
+} send_args_map SEC(".maps");
+
 const int IPV6_NUM_OCTECTS = 16;
 const int IPV4_NUM_OCTECTS = 4;
 
@@ -153,6 +239,16 @@ static void reset_unused_fields_v6(struct sockaddr_in6 *v6) {
   v6->sin6_scope_id = 0;
 }
 
+// Link-local addresses are meaningful only along with their scope, which is
+// the interface the socket is bound to.
+static __always_inline void copy_skc_scope_id(struct sock_common *sk,
+                                              struct sockaddr_in6 *v6) {
+  u8 *octets = v6->sin6_addr.in6_u.u6_addr8;
+  if (octets[0] == 0xfe && (octets[1] & 0xc0) == 0x80) {
+    v6->sin6_scope_id = BPF_CORE_READ(sk, skc_bound_dev_if);
+  }
+}
+
 // Copy an address from the source part of sock_common
 static __always_inline void copy_skc_source(struct sock_common *sk,
                                             struct address *addr) {
@@ -174,6 +270,7 @@ static __always_inline void copy_skc_source(struct sock_common *sk,
     bpf_core_read(&addr->v6.sin6_addr, IPV6_NUM_OCTECTS,
                   &sk->skc_v6_rcv_saddr.in6_u.u6_addr32);
     reset_unused_fields_v6(&addr->v6);
+    copy_skc_scope_id(sk, &addr->v6);
     break;
   }
   default:
@@ -200,6 +297,7 @@ static __always_inline void copy_skc_dest(struct sock_common *sk,
     bpf_core_read(&addr->v6.sin6_addr, IPV6_NUM_OCTECTS,
                   &sk->skc_v6_daddr.in6_u.u6_addr32);
     reset_unused_fields_v6(&addr->v6);
+    copy_skc_scope_id(sk, &addr->v6);
     break;
   }
   default:
@@ -217,6 +315,43 @@ static __always_inline u16 get_sock_protocol(struct sock *sk) {
   }
 }
 
+// Remember the process owning a TCP socket. The first owner is kept, since
+// the socket can be shared with other processes, like children after a fork.
+static __always_inline void save_sock_owner(struct sock *sk, pid_t tgid) {
+  if (get_sock_protocol(sk) != PROTO_TCP)
+    return;
+  if (bpf_map_lookup_elem(&sock_owner_map, &sk))
+    return;
+  struct sock_owner owner = {0};
+  owner.tgid = tgid;
+  bpf_get_current_comm(&owner.comm, sizeof(owner.comm));
+  if (bpf_map_update_elem(&sock_owner_map, &sk, &owner, BPF_ANY))
+    LOG_ERROR("updating sock_owner_map");
+}
+
+// Count the bytes of a message on a TCP stream, returning the offset of the
+// message. Streams whose owner was saved after connect or accept count from
+// their first message seen. The update is not atomic, so concurrent messages
+// on the same socket may get the same offset.
+static __always_inline u64 stream_offset(struct sock *sk, pid_t tgid, u32 len,
+                                         bool received) {
+  if (get_sock_protocol(sk) != PROTO_TCP)
+    return 0;
+  save_sock_owner(sk, tgid);
+  struct sock_owner *owner = bpf_map_lookup_elem(&sock_owner_map, &sk);
+  if (!owner)
+    return 0;
+  u64 offset;
+  if (received) {
+    offset = owner->received;
+    owner->received = offset + len;
+  } else {
+    offset = owner->sent;
+    owner->sent = offset + len;
+  }
+  return offset;
+}
+
 PULSAR_LSM_HOOK(socket_bind, struct socket *, sock, struct sockaddr *, address,
                 int, addrlen);
 void __always_inline on_socket_bind(void *ctx, struct socket *sock,
@@ -244,6 +379,8 @@ void __always_inline on_socket_listen(void *ctx, struct socket *sock,
     return;
   struct sock *sk = BPF_CORE_READ(sock, sk);
   copy_skc_source(&sk->__sk_common, &event->listen);
+  // Report the close of the listening socket too
+  save_sock_owner(sk, tgid);
 
   output_network_event(ctx, event);
 }
@@ -261,8 +398,10 @@ static __always_inline void on_socket_connect(void *ctx, struct socket *sock,
     return;
   event->timestamp = bpf_ktime_get_ns();
 
+  struct sock *sk = BPF_CORE_READ(sock, sk);
+  save_sock_owner(sk, tgid);
   copy_sockaddr(address, &event->connect.destination, false);
-  event->connect.proto = get_sock_protocol(BPF_CORE_READ(sock, sk));
+  event->connect.proto = get_sock_protocol(sk);
 
   output_network_event(ctx, event);
 }
@@ -278,6 +417,7 @@ static __always_inline void on_socket_accept(void *ctx, struct socket *sock,
   if (tracker_interesting_tgid(&GLOBAL_INTEREST_MAP) >= 0) {
     struct arguments args = {0};
     args.data[0] = newsock;
+    args.data[1] = sock;
     u64 pid_tgid = bpf_get_current_pid_tgid();
     bpf_map_update_elem(&args_map, &pid_tgid, &args, BPF_ANY);
   }
@@ -292,6 +432,7 @@ static __always_inline void on_accept_exit(void *ctx, long ret) {
     return;
   }
   struct socket *sock = (struct socket *)args->data[0];
+  struct socket *listen_sock = (struct socket *)args->data[1];
   bpf_map_delete_elem(&args_map, &pid_tgid);
 
   // Ignore failed accept
@@ -308,15 +449,20 @@ static __always_inline void on_accept_exit(void *ctx, long ret) {
   if (!event)
     return;
   struct sock *sk = BPF_CORE_READ(sock, sk);
+  save_sock_owner(sk, tgid);
   copy_skc_source(&sk->__sk_common, &event->accept.destination);
   copy_skc_dest(&sk->__sk_common, &event->accept.source);
+  // With SO_REUSEPORT, many processes listen on the same port with their own
+  // socket: the connection belongs to the accepting process, we record the
+  // group of listening sockets it came from. Zero when not in a group.
+  event->accept.reuseport_group =
+      BPF_CORE_READ(listen_sock, sk, sk_reuseport_cb, reuseport_id);
   output_network_event(ctx, event);
 }
 
 #define ITER_UBUF 5
 
-static __always_inline void
-*get_iov_base(const void *msg_iter) {
+static __always_inline void *get_iov_base(const void *msg_iter) {
   // Definition of `struct iov_iter` used in new kernels (>=6.4).
   const struct iov_iter *msg_iter_nocompat = msg_iter;
 
@@ -334,55 +480,186 @@ static __always_inline void
   return BPF_CORE_READ(msg_iter_compat, iov, iov_base);
 }
 
-static __always_inline void
-read_iovec(struct buffer *buffer, struct msg_event *output, void *iov_base) {
-  size_t len = output->data_len;
-
-  unsigned int msg_iter_type = 0;
+// Length of the buffer returned by `get_iov_base`: with vectored IO, only the
+// first segment is read.
+static __always_inline u64 get_iov_len(const void *msg_iter) {
+  const struct iov_iter *msg_iter_nocompat = msg_iter;
 
-  if (len > MAX_DATA_SIZE) {
-    LOG_DEBUG("len=%d MAX_DATA_SIZE=%d", len, MAX_DATA_SIZE);
+  if (bpf_core_field_exists(msg_iter_nocompat->__ubuf_iovec)) {
+    if (BPF_CORE_READ(msg_iter_nocompat, iter_type) == ITER_UBUF) {
+      return BPF_CORE_READ(msg_iter_nocompat, __ubuf_iovec.iov_len);
+    }
+    return BPF_CORE_READ(msg_iter_nocompat, __iov, iov_len);
   }
 
-  // limit the index to avoid "min value is negative, either use unsigned or
-  // 'var &= const'"
-  len &= (MAX_DATA_SIZE - 1);
+  const struct iov_iter_compat *msg_iter_compat = msg_iter;
+  return BPF_CORE_READ(msg_iter_compat, iov, iov_len);
+}
+
+// Bytes to copy from a message, 0 if its data isn't copied.
+static __always_inline u32 capture_size(u16 proto, u64 offset) {
+  u32 zero = 0;
+  struct capture_config *config =
+      bpf_map_lookup_elem(&capture_config_map, &zero);
+  if (!config)
+    return 0;
+  if (proto == PROTO_UDP)
+    return config->size;
+  if (offset == 0)
+    return config->first_message_size;
+  return 0;
+}
 
+static __always_inline void read_chunk(struct buffer *buffer,
+                                       struct msg_event *output, void *iov_base,
+                                       u32 len) {
+  u32 start = output->chunk * CHUNK_SIZE;
+  u32 chunk_len = len - start;
+  if (chunk_len > CHUNK_SIZE)
+    chunk_len = CHUNK_SIZE;
   buffer_index_init(buffer, &output->data);
-  buffer_append_user_memory(buffer, &output->data, iov_base, len);
-  LOG_DEBUG("get data size %d -> %d", len, len & (MAX_DATA_SIZE - 1));
+  buffer_append_user_memory(buffer, &output->data, iov_base + start,
+                            chunk_len);
 }
 
-PULSAR_LSM_HOOK(socket_sendmsg, struct socket *, sock, struct msghdr *, msg,
-                int, size);
-static __always_inline void on_socket_sendmsg(void *ctx, struct socket *sock,
-                                              struct msghdr *msg, int size) {
-  if (size <= 0)
-    return;
+// Copy the first chunk of the message data in `output`, returning the number
+// of bytes to copy in total, at most `size`.
+static __always_inline u32 read_first_chunk(struct buffer *buffer,
+                                            struct msg_event *output,
+                                            void *iov_base, u64 iov_len,
+                                            u32 size) {
+  u32 len = output->data_len;
+  output->capture = CAPTURE_COMPLETE;
+  if (len > iov_len) {
+    len = iov_len;
+    output->capture = CAPTURE_TRUNCATED;
+  }
+  if (size > MAX_CHUNKS * CHUNK_SIZE)
+    size = MAX_CHUNKS * CHUNK_SIZE;
+  if (len > size) {
+    LOG_DEBUG("len=%d capture size=%d", len, size);
+    len = size;
+    output->capture = CAPTURE_TRUNCATED;
+  }
+  output->chunk = 0;
+  output->chunks = len ? (len + CHUNK_SIZE - 1) / CHUNK_SIZE : 1;
+
+  read_chunk(buffer, output, iov_base, len);
+  // the user memory couldn't be read
+  if (len && output->data.len == 0) {
+    output->capture = CAPTURE_EMPTY;
+    output->chunks = 1;
+  }
+  return len;
+}
 
-  struct sock *sk = BPF_CORE_READ(sock, sk);
-  u16 proto = get_sock_protocol(sk);
-  void *iov_base = get_iov_base(&msg->msg_iter);
+// Emit the chunks of data following the first one, copied in an event
+// identical to `msg` and with the same timestamp.
+static __always_inline void output_next_chunks(void *ctx, int event_type,
+                                               pid_t tgid, u64 timestamp,
+                                               struct msg_event *msg,
+                                               void *iov_base, u32 len) {
+#pragma unroll
+  for (int i = 1; i < MAX_CHUNKS; i++) {
+    if (i >= msg->chunks)
+      return;
+    struct network_event *event = init_network_event(event_type, tgid);
+    if (!event)
+      return;
+    event->timestamp = timestamp;
+    // send and recv are the same type in the same union
+    struct msg_event *chunk = &event->send;
+    *chunk = *msg;
+    chunk->chunk = i;
+    read_chunk(&event->buffer, chunk, iov_base, len);
+    output_network_event(ctx, event);
+  }
+}
 
-  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
-  if (tgid < 0)
+// Emit a sent message, `len` bytes long. Zero-length messages are only
+// possible with UDP.
+static __always_inline void emit_sendmsg(void *ctx, pid_t tgid,
+                                         struct arguments *args, long len) {
+  struct sock *sk = (struct sock *)args->data[0];
+  void *iov_base = args->data[1];
+  u64 iov_len = (u64)args->data[2];
+  u16 proto = get_sock_protocol(sk);
+  if (len < 0 || (len == 0 && proto != PROTO_UDP))
     return;
+
   struct network_event *event = init_network_event(EVENT_SEND, tgid);
   if (!event)
     return;
   event->send.proto = proto;
-  event->send.data_len = size;
-  // Copy data only for UDP events since we want to intercept DNS requests
-  if (proto == PROTO_UDP) {
-    read_iovec(&event->buffer, &event->send, iov_base);
+  event->send.data_len = len;
+  event->send.offset = stream_offset(sk, tgid, len, false);
+  u32 capture = capture_size(proto, event->send.offset);
+  u32 captured_len = 0;
+  if (capture) {
+    captured_len = read_first_chunk(&event->buffer, &event->send, iov_base,
+                                    iov_len, capture);
   } else {
     event->send.data.len = 0;
+    event->send.capture = CAPTURE_DISABLED;
+    event->send.chunk = 0;
+    event->send.chunks = 1;
   }
 
   copy_skc_source(&sk->__sk_common, &event->send.source);
   copy_skc_dest(&sk->__sk_common, &event->send.destination);
 
+  // The event memory may be reused once emitted
+  struct msg_event first = event->send;
+  u64 timestamp = event->timestamp;
   output_network_event(ctx, event);
+  output_next_chunks(ctx, EVENT_SEND, tgid, timestamp, &first, iov_base,
+                     captured_len);
+}
+
+// Save the message in `send_args_map`, to emit it once the syscall returns the
+// bytes really sent.
+PULSAR_LSM_HOOK(socket_sendmsg, struct socket *, sock, struct msghdr *, msg,
+                int, size);
+static __always_inline void on_socket_sendmsg(void *ctx, struct socket *sock,
+                                              struct msghdr *msg, int size) {
+  if (size < 0)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+
+  u64 pid_tgid = bpf_get_current_pid_tgid();
+  // A message still pending was sent by sendmmsg, which returns the number of
+  // messages, or by a syscall we don't intercept: emit it whole.
+  struct arguments *pending = bpf_map_lookup_elem(&send_args_map, &pid_tgid);
+  if (pending)
+    emit_sendmsg(ctx, tgid, pending, (long)pending->data[3]);
+
+  struct arguments args = {0};
+  args.data[0] = BPF_CORE_READ(sock, sk);
+  args.data[1] = get_iov_base(&msg->msg_iter);
+  args.data[2] = (void *)get_iov_len(&msg->msg_iter);
+  args.data[3] = (void *)(long)size;
+  if (bpf_map_update_elem(&send_args_map, &pid_tgid, &args, BPF_ANY))
+    LOG_ERROR("insert error on send_args_map: %d", pid_tgid);
+}
+
+// Emit the message saved by `socket_sendmsg`, of `ret` bytes, when exiting the
+// syscall. With sendmmsg, `ret` is the number of messages sent.
+static __always_inline void do_sendmsg(void *ctx, long ret, bool messages) {
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+
+  u64 pid_tgid = bpf_get_current_pid_tgid();
+  struct arguments *args = bpf_map_lookup_elem(&send_args_map, &pid_tgid);
+  if (!args)
+    return;
+  struct arguments saved = *args;
+  bpf_map_delete_elem(&send_args_map, &pid_tgid);
+  if (messages)
+    ret = ret > 0 ? (long)saved.data[3] : -1;
+  emit_sendmsg(ctx, tgid, &saved, ret);
 }
 
 static __always_inline void save_recvmsg_addr(void *ctx,
@@ -407,10 +684,12 @@ static __always_inline void on_socket_recvmsg(void *ctx, struct socket *sock,
   u64 pid_tgid = bpf_get_current_pid_tgid();
   struct sock *sk = (struct sock *)BPF_CORE_READ(sock, sk);
   void *iov_base = get_iov_base(&msg->msg_iter);
+  u64 iov_len = get_iov_len(&msg->msg_iter);
 
   struct arguments args = {0};
   args.data[0] = sk;
   args.data[1] = iov_base;
+  args.data[3] = (void *)iov_len;
 
   struct arguments *old_args = bpf_map_lookup_elem(&args_map, &pid_tgid);
   if (old_args) {
@@ -439,23 +718,32 @@ static __always_inline void do_recvmsg(void *ctx, long ret) {
   }
   struct sock *sk = (struct sock *)args->data[0];
   void *iov_base = (void *)args->data[1];
+  u64 iov_len = (u64)args->data[3];
 
+  // Zero-length messages are only possible with UDP, for TCP it's the end of
+  // the stream.
   int len = ret;
-  if (len <= 0)
+  u16 proto = get_sock_protocol(sk);
+  if (len < 0 || (len == 0 && proto != PROTO_UDP))
     return;
 
   struct network_event *event = init_network_event(EVENT_RECV, tgid);
   if (!event)
     return;
 
-  u16 proto = get_sock_protocol(sk);
   event->recv.proto = proto;
   event->recv.data_len = len;
-  // Copy data only for UDP events since we want to intercept DNS replies
-  if (proto == PROTO_UDP) {
-    read_iovec(&event->buffer, &event->recv, iov_base);
+  event->recv.offset = stream_offset(sk, tgid, len, true);
+  u32 capture = capture_size(proto, event->recv.offset);
+  u32 captured_len = 0;
+  if (capture) {
+    captured_len = read_first_chunk(&event->buffer, &event->recv, iov_base,
+                                    iov_len, capture);
   } else {
     event->recv.data.len = 0;
+    event->recv.capture = CAPTURE_DISABLED;
+    event->recv.chunk = 0;
+    event->recv.chunks = 1;
   }
 
   u16 family = BPF_CORE_READ(sk, __sk_common.skc_family);
@@ -477,57 +765,148 @@ static __always_inline void do_recvmsg(void *ctx, long ret) {
     copy_skc_dest(&sk->__sk_common, &event->recv.destination);
   }
 
+  // The event memory may be reused once emitted
+  struct msg_event first = event->recv;
+  u64 timestamp = event->timestamp;
   output_network_event(ctx, event);
+  output_next_chunks(ctx, EVENT_RECV, tgid, timestamp, &first, iov_base,
+                     captured_len);
 }
 
-SEC("kprobe/tcp_set_state")
-int tcp_set_state(struct pt_regs *regs) {
-  pid_t tgid = bpf_get_current_pid_tgid() >> 32;
-  // this function may be called after the process has already exited,
-  // so we don't want to log errors in case tgid has already been
-  // deleted from map_interest
-  if (!tracker_is_interesting(&GLOBAL_INTEREST_MAP, tgid, __func__, false,
-                              true))
-    return 0;
-
-  int ret;
-  struct sock *sk = (struct sock *)PT_REGS_PARM1(regs);
-  int state = (int)PT_REGS_PARM2(regs);
+static __always_inline void on_tcp_set_state(void *ctx, struct sock *sk,
+                                             int state) {
   if (state == TCP_SYN_SENT || state == TCP_LAST_ACK) {
-    ret = bpf_map_update_elem(&tcp_set_state_map, &sk, &tgid, BPF_ANY);
-    if (ret) {
-      LOG_ERROR("updating tcp_set_state_map");
-    }
-    return 0;
+    // These transitions happen in process context: save the owner if the
+    // socket was created before the probes were attached.
+    pid_t tgid = bpf_get_current_pid_tgid() >> 32;
+    if (tracker_is_interesting(&GLOBAL_INTEREST_MAP, tgid, __func__, false,
+                               true))
+      save_sock_owner(sk, tgid);
+    return;
   }
   if (state != TCP_CLOSE)
-    return 0;
-  pid_t *id = bpf_map_lookup_elem(&tcp_set_state_map, &sk);
-  pid_t original_pid = tgid;
-  if (!id) {
-    LOG_DEBUG("can't retrieve the original pid");
-    return 0;
-  } else {
-    original_pid = *id;
-  }
-  ret = bpf_map_delete_elem(&tcp_set_state_map, &sk);
-  if (ret) {
-    LOG_ERROR("deleting from tcp_set_state_map");
+    return;
+
+  // The close often runs in softirq context, with the idle task or an
+  // unrelated process as current task: attribute it to the socket owner.
+  struct sock_owner *found = bpf_map_lookup_elem(&sock_owner_map, &sk);
+  if (!found) {
+    LOG_DEBUG("can't retrieve the socket owner");
+    return;
   }
+  struct sock_owner owner = *found;
+  bpf_map_delete_elem(&sock_owner_map, &sk);
 
-  u16 family = 0;
-  bpf_core_read(&family, sizeof(family), &sk->__sk_common.skc_family);
-  short ipver = family == AF_INET ? 4 : 6;
-  u32 key = 0;
+  // the owner may have already exited, so we don't want to log errors in
+  // case its tgid has already been deleted from map_interest
+  if (!tracker_is_interesting(&GLOBAL_INTEREST_MAP, owner.tgid, __func__,
+                              false, true))
+    return;
 
-  struct network_event *event = init_network_event(EVENT_CLOSE, tgid);
+  struct network_event *event = init_network_event(EVENT_CLOSE, owner.tgid);
   if (!event)
-    return 0;
-  event->close.original_pid = original_pid;
+    return;
+  event->close.original_pid = owner.tgid;
+  __builtin_memcpy(event->close.comm, owner.comm, TASK_COMM_LEN);
   copy_skc_source(&sk->__sk_common, &event->close.source);
   copy_skc_dest(&sk->__sk_common, &event->close.destination);
 
-  output_network_event(regs, event);
+  output_network_event(ctx, event);
+}
+
+// nf_tables changes are committed as batches of netlink messages sent to a
+// NETLINK_NETFILTER socket, the same for nft and iptables-nft: count the
+// tables, chains and rules added and removed by the batch.
+PULSAR_LSM_HOOK(netlink_send, struct sock *, sk, struct sk_buff *, skb);
+static __always_inline void on_netlink_send(void *ctx, struct sock *sk,
+                                            struct sk_buff *skb) {
+  if (BPF_CORE_READ_BITFIELD_PROBED(sk, sk_protocol) != NETLINK_NETFILTER)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+
+  unsigned char *data = BPF_CORE_READ(skb, data);
+  u32 len = BPF_CORE_READ(skb, len);
+  struct firewall_event firewall = {.backend = FIREWALL_NFTABLES};
+  u32 offset = 0;
+  for (int i = 0; i < MAX_NFT_MESSAGES; i++) {
+    struct nlmsghdr nlh;
+    if (offset + sizeof(nlh) > len)
+      break;
+    if (bpf_core_read(&nlh, sizeof(nlh), data + offset) != 0)
+      break;
+    if (nlh.nlmsg_len < sizeof(nlh))
+      break;
+    if (nlh.nlmsg_type >> 8 == NFNL_SUBSYS_NFTABLES) {
+      switch (nlh.nlmsg_type & 0xff) {
+      case NFT_MSG_NEWTABLE:
+        firewall.tables_added++;
+        break;
+      case NFT_MSG_DELTABLE:
+      case NFT_MSG_DESTROYTABLE:
+        firewall.tables_removed++;
+        break;
+      case NFT_MSG_NEWCHAIN:
+        firewall.chains_added++;
+        break;
+      case NFT_MSG_DELCHAIN:
+      case NFT_MSG_DESTROYCHAIN:
+        firewall.chains_removed++;
+        break;
+      case NFT_MSG_NEWRULE:
+        firewall.rules_added++;
+        break;
+      case NFT_MSG_DELRULE:
+      case NFT_MSG_DESTROYRULE:
+        firewall.rules_removed++;
+        break;
+      }
+    }
+    // Messages are aligned to 4 bytes
+    offset += (nlh.nlmsg_len + 3) & ~3;
+  }
+  // Only the batches changing the ruleset, not the ones listing it
+  if (!(firewall.tables_added | firewall.tables_removed |
+        firewall.chains_added | firewall.chains_removed |
+        firewall.rules_added | firewall.rules_removed))
+    return;
+
+  struct network_event *event = init_network_event(EVENT_FIREWALL, tgid);
+  if (!event)
+    return;
+  event->firewall = firewall;
+  output_network_event(ctx, event);
+}
+
+// Legacy iptables replaces a whole table at once with setsockopt.
+PULSAR_LSM_HOOK(socket_setsockopt, struct socket *, sock, int, level, int,
+                optname);
+static __always_inline void on_socket_setsockopt(void *ctx,
+                                                 struct socket *sock,
+                                                 int level, int optname) {
+  if ((level != SOL_IP && level != SOL_IPV6) || optname != IPT_SO_SET_REPLACE)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  struct network_event *event = init_network_event(EVENT_FIREWALL, tgid);
+  if (!event)
+    return;
+  event->firewall =
+      (struct firewall_event){.backend = FIREWALL_IPTABLES_LEGACY};
+  output_network_event(ctx, event);
+}
+
+SEC("fentry/tcp_set_state")
+int BPF_PROG(fentry_tcp_set_state, struct sock *sk, int state) {
+  on_tcp_set_state(ctx, sk, state);
+  return 0;
+}
+
+SEC("kprobe/tcp_set_state")
+int BPF_KPROBE(tcp_set_state, struct sock *sk, int state) {
+  on_tcp_set_state(ctx, sk, state);
   return 0;
 }
 
@@ -580,6 +959,41 @@ int BPF_PROG(sys_exit_recvfrom, struct pt_regs *regs, int __syscall_nr,
   return 0;
 }
 
+SEC("tracepoint/sys_exit_sendmsg")
+int BPF_PROG(sys_exit_sendmsg, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  do_sendmsg(ctx, ret, false);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_sendmmsg")
+int BPF_PROG(sys_exit_sendmmsg, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  do_sendmsg(ctx, ret, true);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_sendto")
+int BPF_PROG(sys_exit_sendto, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  do_sendmsg(ctx, ret, false);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_write")
+int BPF_PROG(sys_exit_write, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  do_sendmsg(ctx, ret, false);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_writev")
+int BPF_PROG(sys_exit_writev, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  do_sendmsg(ctx, ret, false);
+  return 0;
+}
+
 SEC("tracepoint/sys_exit_read")
 int BPF_PROG(sys_exit_read, struct pt_regs *regs, int __syscall_nr, long ret) {
   do_recvmsg(ctx, ret);
@@ -0,0 +1,284 @@
+//! Copy of the data of sent and received messages.
+//!
+//! The probes copy the data of UDP messages and of the first message of TCP
+//! streams, up to the sizes in `capture_config_map`. Every event carries at
+//! most [`CHUNK_SIZE`] bytes: longer messages are copied in several events,
+//! the chunks, emitted one after the other on the same CPU with the pid and
+//! timestamp of the first one. The [`Reassembler`] joins them back.
+//!
+//! The data of single-chunk messages shares the event buffer, the chunks of
+//! longer ones are joined in a [`BufferArena`]: messages don't allocate.
+
+use bpf_common::{
+    aya::{maps::Array, Pod},
+    parsing::{BufferArena, IndexError},
+    program::BpfEvent,
+    time::Timestamp,
+    Pid, Program, ProgramError,
+};
+
+use bytes::Bytes;
+
+use crate::{Capture, NetworkEvent};
+
+/// Must match CHUNK_SIZE in probes.bpf.c
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Must match MAX_CHUNKS in probes.bpf.c
+pub const MAX_CHUNKS: usize = 16;
+
+/// Largest capture size, bigger ones are capped by the probes.
+pub const MAX_CAPTURE_SIZE: usize = CHUNK_SIZE * MAX_CHUNKS;
+
+const CAPTURE_CONFIG_MAP: &str = "capture_config_map";
+
+/// Messages waiting for their next chunks. Chunks are lost only when the
+/// perf buffer is full, in that case the oldest message is sent truncated.
+const MAX_PENDING: usize = 16;
+
+/// Bytes of data copied from messages, 0 to disable the copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct CaptureConfig {
+    /// UDP messages, parsed for DNS
+    pub size: u32,
+    /// First message of TCP streams, parsed to detect the protocol
+    pub first_message_size: u32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            size: CHUNK_SIZE as u32,
+            first_message_size: CHUNK_SIZE as u32,
+        }
+    }
+}
+
+unsafe impl Pod for CaptureConfig {}
+
+/// Set the capture sizes used by the probes.
+pub fn set_capture_config(
+    program: &mut Program,
+    config: &CaptureConfig,
+) -> Result<(), ProgramError> {
+    let map = program
+        .bpf()
+        .map_mut(CAPTURE_CONFIG_MAP)
+        .ok_or_else(|| ProgramError::MapNotFound(CAPTURE_CONFIG_MAP.to_string()))?;
+    let mut array: Array<_, CaptureConfig> = Array::try_from(map)?;
+    array.set(0, config, 0)?;
+    Ok(())
+}
+
+/// A chunk of the data of a message.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub pid: Pid,
+    pub timestamp: Timestamp,
+    pub index: u8,
+    pub count: u8,
+    pub data: Bytes,
+}
+
+/// The first event of a message, with the data of all its chunks.
+#[derive(Debug)]
+pub struct Message<T> {
+    pub event: T,
+    pub data: Bytes,
+    /// Some chunks were lost
+    pub truncated: bool,
+}
+
+struct Pending<T> {
+    pid: Pid,
+    timestamp: Timestamp,
+    event: T,
+    /// Chunks received, in order
+    chunks: Vec<Bytes>,
+}
+
+/// Joins the chunks of the messages read from the perf buffer of a CPU.
+pub struct Reassembler<T> {
+    pending: Vec<Pending<T>>,
+    arena: BufferArena,
+}
+
+impl<T> Default for Reassembler<T> {
+    fn default() -> Self {
+        Self {
+            pending: Vec::new(),
+            arena: BufferArena::default(),
+        }
+    }
+}
+
+/// Chunks are never split across CPUs and every CPU gets a clone of the
+/// sender: the pending messages are not cloned.
+impl<T> Clone for Reassembler<T> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<T> Reassembler<T> {
+    /// Add a chunk, returning its message once the last chunk is received.
+    /// The `event` of chunks following the first one is dropped.
+    pub fn push(&mut self, chunk: Chunk, event: T) -> Option<Message<T>> {
+        if chunk.index == 0 {
+            if chunk.count <= 1 {
+                return Some(Message {
+                    event,
+                    data: chunk.data,
+                    truncated: false,
+                });
+            }
+            self.pending.push(Pending {
+                pid: chunk.pid,
+                timestamp: chunk.timestamp,
+                event,
+                chunks: vec![chunk.data],
+            });
+            if self.pending.len() > MAX_PENDING {
+                return Some(self.take(0, true));
+            }
+            return None;
+        }
+
+        let position = self
+            .pending
+            .iter()
+            .position(|pending| pending.pid == chunk.pid && pending.timestamp == chunk.timestamp)?;
+        let pending = &mut self.pending[position];
+        if chunk.index as usize != pending.chunks.len() {
+            return Some(self.take(position, true));
+        }
+        pending.chunks.push(chunk.data);
+        if pending.chunks.len() >= chunk.count as usize {
+            return Some(self.take(position, false));
+        }
+        None
+    }
+
+    fn take(&mut self, position: usize, truncated: bool) -> Message<T> {
+        let pending = self.pending.remove(position);
+        Message {
+            event: pending.event,
+            data: self.arena.concat(&pending.chunks),
+            truncated,
+        }
+    }
+}
+
+impl Reassembler<BpfEvent<NetworkEvent>> {
+    /// Add an event, returning it with the data of its message once complete.
+    /// Events without data are returned right away.
+    pub fn push_event(
+        &mut self,
+        mut event: BpfEvent<NetworkEvent>,
+    ) -> Option<Message<BpfEvent<NetworkEvent>>> {
+        let (index, count) = match &event.payload {
+            NetworkEvent::Send { chunk, chunks, .. }
+            | NetworkEvent::Receive { chunk, chunks, .. } => (*chunk, *chunks),
+            _ => {
+                return Some(Message {
+                    event,
+                    data: Bytes::new(),
+                    truncated: false,
+                })
+            }
+        };
+        // Shares the event buffer
+        let data = match data(&event) {
+            Ok(data) => event.buffer.slice_ref(data),
+            Err(err) => {
+                log::error!("Error getting message data: {err}");
+                set_capture(&mut event.payload, Capture::Empty);
+                Bytes::new()
+            }
+        };
+        let chunk = Chunk {
+            pid: event.pid,
+            timestamp: event.timestamp,
+            index,
+            count,
+            data,
+        };
+        let mut message = self.push(chunk, event)?;
+        if message.truncated {
+            set_capture(&mut message.event.payload, Capture::Truncated);
+        }
+        Some(message)
+    }
+}
+
+fn data(event: &BpfEvent<NetworkEvent>) -> Result<&[u8], IndexError> {
+    match &event.payload {
+        NetworkEvent::Send { data, .. } | NetworkEvent::Receive { data, .. } => {
+            data.bytes(&event.buffer)
+        }
+        _ => Ok(&[]),
+    }
+}
+
+fn set_capture(event: &mut NetworkEvent, value: Capture) {
+    if let NetworkEvent::Send { capture, .. } | NetworkEvent::Receive { capture, .. } = event {
+        *capture = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(pid: i32, timestamp: u64, index: u8, count: u8, data: &'static [u8]) -> Chunk {
+        Chunk {
+            pid: Pid::from_raw(pid),
+            timestamp: Timestamp::from(timestamp),
+            index,
+            count,
+            data: Bytes::from_static(data),
+        }
+    }
+
+    #[test]
+    fn reassemble_chunks() {
+        let mut reassembler = Reassembler::default();
+        let single = reassembler.push(chunk(1, 1, 0, 1, b"single"), "single");
+        assert_eq!(&single.unwrap().data[..], b"single");
+
+        // Chunks of messages on the same CPU may be interleaved by nested events
+        assert!(reassembler.push(chunk(1, 2, 0, 3, b"a"), "first").is_none());
+        assert!(reassembler
+            .push(chunk(2, 2, 0, 2, b"x"), "second")
+            .is_none());
+        assert!(reassembler.push(chunk(1, 2, 1, 3, b"b"), "").is_none());
+        let second = reassembler.push(chunk(2, 2, 1, 2, b"y"), "").unwrap();
+        assert_eq!((second.event, &second.data[..]), ("second", &b"xy"[..]));
+        let first = reassembler.push(chunk(1, 2, 2, 3, b"c"), "").unwrap();
+        assert_eq!((first.event, &first.data[..]), ("first", &b"abc"[..]));
+        assert!(!first.truncated);
+
+        // Unknown messages are ignored
+        assert!(reassembler.push(chunk(1, 3, 1, 2, b"?"), "").is_none());
+    }
+
+    #[test]
+    fn lost_chunks() {
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(chunk(1, 1, 0, 3, b"a"), "lost").is_none());
+        let lost = reassembler.push(chunk(1, 1, 2, 3, b"c"), "").unwrap();
+        assert_eq!((lost.event, &lost.data[..]), ("lost", &b"a"[..]));
+        assert!(lost.truncated);
+
+        // The oldest message is dropped when too many are pending
+        for timestamp in 0..MAX_PENDING as u64 {
+            assert!(reassembler
+                .push(chunk(1, timestamp, 0, 2, b"a"), "pending")
+                .is_none());
+        }
+        let oldest = reassembler.push(chunk(1, 100, 0, 2, b"a"), "new").unwrap();
+        assert!(oldest.truncated);
+        assert!(reassembler.push(chunk(1, 0, 1, 2, b"b"), "").is_none());
+    }
+}
@@ -0,0 +1,68 @@
+//! Extraction of DNS queries and responses from network messages.
+
+use std::fmt::{self, Write};
+
+use pulsar_core::{
+    domain,
+    event::{DnsAnswer, DnsQuestion},
+    pdk::Payload,
+};
+
+/// Parses DNS messages, formatting their strings in a buffer reused for every
+/// message: each string is allocated once, with its final size.
+#[derive(Debug, Default, Clone)]
+pub struct DnsParser {
+    scratch: String,
+}
+
+impl DnsParser {
+    /// Parse a message as a DNS packet, returning a `DnsQuery` when it contains
+    /// only questions and a `DnsResponse` when it contains answers.
+    pub fn parse(&mut self, data: &[u8]) -> Option<Payload> {
+        // any valid dns data?
+        let dns = dns_parser::Packet::parse(data).ok()?;
+        let with_q = !dns.questions.is_empty();
+        let with_a = !dns.answers.is_empty();
+
+        let mut questions = Vec::with_capacity(dns.questions.len());
+        for q in dns.questions {
+            let name = self.format(format_args!("{}", q.qname));
+            questions.push(DnsQuestion {
+                normalized: domain::normalize(&name),
+                dga_score: domain::dga_score(&name),
+                // Set by the network-monitor, which knows the domains seen before
+                first_seen: false,
+                name,
+                qtype: self.format(format_args!("{:?}", q.qtype)),
+                qclass: self.format(format_args!("{:?}", q.qclass)),
+            });
+        }
+
+        let mut answers = Vec::with_capacity(dns.answers.len());
+        for a in dns.answers {
+            answers.push(DnsAnswer {
+                name: self.format(format_args!("{}", a.name)),
+                class: self.format(format_args!("{:?}", a.cls)),
+                ttl: a.ttl,
+                data: self.format(format_args!("{:?}", a.data)),
+            });
+        }
+
+        if with_q && !with_a {
+            Some(Payload::DnsQuery { questions })
+        } else if with_a {
+            Some(Payload::DnsResponse { answers, questions })
+        } else {
+            None
+        }
+    }
+
+    /// Format in the scratch buffer, which grows to fit the longest string,
+    /// and return a copy of the exact size.
+    fn format(&mut self, args: fmt::Arguments) -> String {
+        self.scratch.clear();
+        // Writing to a String never fails
+        let _ = self.scratch.write_fmt(args);
+        self.scratch.as_str().to_owned()
+    }
+}
@@ -0,0 +1,257 @@
+//! Changes of the local firewall, reported as `FirewallChange` events.
+//!
+//! The probes count the tables, chains and rules added and removed by the
+//! nf_tables netlink batches, sent by both nft and iptables-nft, and see the
+//! tables replaced by legacy iptables. When the process is one of these tools,
+//! its command line describes the change better than the counters, like
+//! `append to filter INPUT: -p tcp --dport 4444 -j ACCEPT`.
+
+use std::{fmt, path::Path};
+
+/// Subcommands of nft changing the ruleset.
+const NFT_CHANGES: &[&str] = &[
+    "add", "create", "delete", "destroy", "flush", "insert", "rename", "replace", "reset",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FirewallBackend {
+    Nftables = 0,
+    IptablesLegacy = 1,
+}
+
+impl fmt::Display for FirewallBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirewallBackend::Nftables => write!(f, "nftables"),
+            FirewallBackend::IptablesLegacy => write!(f, "iptables-legacy"),
+        }
+    }
+}
+
+/// Objects changed by a nf_tables batch, all 0 for legacy iptables.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FirewallCounts {
+    pub tables_added: u32,
+    pub tables_removed: u32,
+    pub chains_added: u32,
+    pub chains_removed: u32,
+    pub rules_added: u32,
+    pub rules_removed: u32,
+}
+
+/// Describe a change from its counters, like `added 2 rules, removed 1 chain`.
+pub fn summarize_counts(backend: FirewallBackend, counts: &FirewallCounts) -> String {
+    if backend == FirewallBackend::IptablesLegacy {
+        return "replaced an iptables table".to_string();
+    }
+    let changes = [
+        ("added", counts.tables_added, "table"),
+        ("removed", counts.tables_removed, "table"),
+        ("added", counts.chains_added, "chain"),
+        ("removed", counts.chains_removed, "chain"),
+        ("added", counts.rules_added, "rule"),
+        ("removed", counts.rules_removed, "rule"),
+    ];
+    changes
+        .iter()
+        .filter(|(_, count, _)| *count > 0)
+        .map(|(verb, count, object)| {
+            let plural = if *count == 1 { "" } else { "s" };
+            format!("{verb} {count} {object}{plural}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Describe a change from the command line of iptables, ip6tables, their
+/// restore variants or nft. `None` for other processes.
+pub fn summarize_command(argv: &[String]) -> Option<String> {
+    let (program, args) = argv.split_first()?;
+    let name = Path::new(program).file_name()?.to_str()?;
+    if name.starts_with("iptables") || name.starts_with("ip6tables") {
+        if name.contains("-restore") {
+            Some(restore(args))
+        } else if name.contains("-save") {
+            None
+        } else {
+            iptables(args)
+        }
+    } else if name == "nft" {
+        nft(args)
+    } else {
+        None
+    }
+}
+
+fn iptables(args: &[String]) -> Option<String> {
+    let mut table = "filter";
+    let mut action = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter().map(String::as_str).peekable();
+    while let Some(arg) = args.next() {
+        let verb = match arg {
+            "-t" | "--table" => {
+                table = args.next()?;
+                continue;
+            }
+            "-w" | "--wait" => {
+                args.next_if(|seconds| !seconds.starts_with('-'));
+                continue;
+            }
+            "-W" | "--wait-interval" => {
+                args.next();
+                continue;
+            }
+            "-A" | "--append" => "append to",
+            "-I" | "--insert" => "insert into",
+            "-D" | "--delete" => "delete from",
+            "-R" | "--replace" => "replace in",
+            "-F" | "--flush" => "flush",
+            "-Z" | "--zero" => "zero the counters of",
+            "-N" | "--new-chain" => "create chain",
+            "-X" | "--delete-chain" => "delete chain",
+            "-E" | "--rename-chain" => "rename chain",
+            "-P" | "--policy" => "set the policy of",
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        // The chain is optional for the commands on every chain, like -F
+        let chain = args.next_if(|chain| !chain.starts_with('-'));
+        action = Some((verb, chain));
+    }
+
+    let (verb, chain) = action?;
+    let mut summary = match chain {
+        Some(chain) => format!("{verb} {table} {chain}"),
+        None => format!("{verb} {table}"),
+    };
+    if !rest.is_empty() {
+        summary.push_str(": ");
+        summary.push_str(&rest.join(" "));
+    }
+    Some(summary)
+}
+
+fn restore(args: &[String]) -> String {
+    let mut file = None;
+    let mut args = args.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        match arg {
+            "-T" | "--table" | "-M" | "--modprobe" | "-W" | "--wait-interval" => {
+                args.next();
+            }
+            _ if !arg.starts_with('-') => file = Some(arg),
+            _ => {}
+        }
+    }
+    match file {
+        Some(file) => format!("restore rules from {file}"),
+        None => "restore rules from standard input".to_string(),
+    }
+}
+
+fn nft(args: &[String]) -> Option<String> {
+    let mut words = Vec::new();
+    let mut args = args.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        match arg {
+            "-f" | "--file" => return Some(format!("load rules from {}", args.next()?)),
+            "-I" | "--includepath" | "-D" | "--define" => {
+                args.next();
+            }
+            _ if arg.starts_with('-') => {}
+            _ => words.push(arg),
+        }
+    }
+    // The command can be given as a single argument
+    let command = words.join(" ");
+    let subcommand = command.split_whitespace().next()?;
+    NFT_CHANGES.contains(&subcommand).then_some(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(command: &str) -> Option<String> {
+        let argv: Vec<String> = command.split(' ').map(String::from).collect();
+        summarize_command(&argv)
+    }
+
+    #[test]
+    fn iptables_commands() {
+        assert_eq!(
+            summary("/usr/sbin/iptables -w -A INPUT -p tcp --dport 4444 -j ACCEPT").as_deref(),
+            Some("append to filter INPUT: -p tcp --dport 4444 -j ACCEPT")
+        );
+        assert_eq!(
+            summary("iptables-nft -t nat -I PREROUTING 1 -p tcp -j REDIRECT --to-ports 8080")
+                .as_deref(),
+            Some("insert into nat PREROUTING: 1 -p tcp -j REDIRECT --to-ports 8080")
+        );
+        assert_eq!(
+            summary("ip6tables -w 5 -D INPUT 3").as_deref(),
+            Some("delete from filter INPUT: 3")
+        );
+        assert_eq!(summary("iptables -F").as_deref(), Some("flush filter"));
+        assert_eq!(
+            summary("iptables --table mangle --flush OUTPUT").as_deref(),
+            Some("flush mangle OUTPUT")
+        );
+        assert_eq!(
+            summary("iptables-legacy -P INPUT ACCEPT").as_deref(),
+            Some("set the policy of filter INPUT: ACCEPT")
+        );
+        assert_eq!(
+            summary("/sbin/iptables-restore --noflush /etc/iptables/rules.v4").as_deref(),
+            Some("restore rules from /etc/iptables/rules.v4")
+        );
+        assert_eq!(
+            summary("ip6tables-legacy-restore -w").as_deref(),
+            Some("restore rules from standard input")
+        );
+        // Listing doesn't change the rules
+        assert_eq!(summary("iptables -L -n"), None);
+        assert_eq!(summary("iptables-save"), None);
+    }
+
+    #[test]
+    fn nft_commands() {
+        assert_eq!(
+            summary("/usr/sbin/nft add rule inet filter input tcp dport 22 accept").as_deref(),
+            Some("add rule inet filter input tcp dport 22 accept")
+        );
+        assert_eq!(
+            summarize_command(&["nft".to_string(), "flush ruleset".to_string()]).as_deref(),
+            Some("flush ruleset")
+        );
+        assert_eq!(
+            summary("nft -f /etc/nftables.conf").as_deref(),
+            Some("load rules from /etc/nftables.conf")
+        );
+        assert_eq!(summary("nft -a list ruleset"), None);
+        assert_eq!(summary("/usr/bin/dockerd --iptables"), None);
+        assert_eq!(summarize_command(&[]), None);
+    }
+
+    #[test]
+    fn counts() {
+        let counts = FirewallCounts {
+            chains_removed: 1,
+            rules_added: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            summarize_counts(FirewallBackend::Nftables, &counts),
+            "removed 1 chain, added 2 rules"
+        );
+        assert_eq!(
+            summarize_counts(FirewallBackend::IptablesLegacy, &FirewallCounts::default()),
+            "replaced an iptables table"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Detection of TCP connections resuming after a long silence.
+//!
+//! The time of the last message sent or received is kept for every TCP
+//! connection, by local and remote address. A message on a connection silent
+//! for longer than the threshold is reported with an `IdleConnection` event,
+//! like an implant waking up on a connection kept open to its command and
+//! control server. Connections are forgotten when closed.
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use pulsar_core::pdk::Payload;
+
+/// Connections tracked. When full, the most silent quarter is forgotten, so
+/// that connections whose close was missed can't exhaust the memory.
+pub const MAX_CONNECTIONS: usize = 65536;
+
+#[derive(Debug, Default)]
+pub struct IdleConnections {
+    /// Silence reported, disabled when zero
+    threshold: Duration,
+    /// Nanoseconds since boot of the last message, by local and remote address
+    last_activity: HashMap<(SocketAddr, SocketAddr), u64>,
+}
+
+impl IdleConnections {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            last_activity: HashMap::new(),
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: Duration) {
+        self.threshold = threshold;
+        if threshold.is_zero() {
+            self.last_activity.clear();
+        }
+    }
+
+    /// Record a message on a connection at `timestamp`, in nanoseconds since
+    /// boot, returning an `IdleConnection` if it was silent past the threshold.
+    pub fn message(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        outgoing: bool,
+        timestamp: u64,
+    ) -> Option<Payload> {
+        if self.threshold.is_zero() {
+            return None;
+        }
+        if self.last_activity.len() >= MAX_CONNECTIONS
+            && !self.last_activity.contains_key(&(local, remote))
+        {
+            self.forget_most_silent();
+        }
+        // Messages processed by different workers may be out of order
+        let last = self
+            .last_activity
+            .entry((local, remote))
+            .or_insert(timestamp);
+        let silence = Duration::from_nanos(timestamp.saturating_sub(*last));
+        *last = (*last).max(timestamp);
+        (silence >= self.threshold).then(|| Payload::IdleConnection {
+            source: local.into(),
+            destination: remote.into(),
+            idle: silence.as_secs(),
+            outgoing,
+        })
+    }
+
+    pub fn close(&mut self, local: SocketAddr, remote: SocketAddr) {
+        self.last_activity.remove(&(local, remote));
+    }
+
+    fn forget_most_silent(&mut self) {
+        let mut times: Vec<u64> = self.last_activity.values().copied().collect();
+        let (_, cutoff, _) = times.select_nth_unstable(MAX_CONNECTIONS / 4);
+        let cutoff = *cutoff;
+        self.last_activity.retain(|_, last| *last > cutoff);
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_activity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_activity.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn resumed_connection() {
+        let mut idle = IdleConnections::new(Duration::from_secs(600));
+        let local: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        let remote: SocketAddr = "203.0.113.7:443".parse().unwrap();
+        assert!(idle.message(local, remote, true, 100 * SECOND).is_none());
+        assert!(idle.message(local, remote, false, 400 * SECOND).is_none());
+        // Silent for 10 minutes since the last message
+        let Some(Payload::IdleConnection {
+            source,
+            destination,
+            idle: silence,
+            outgoing,
+        }) = idle.message(local, remote, true, 1000 * SECOND)
+        else {
+            panic!("idle connection not reported");
+        };
+        assert_eq!((source.ip, source.port), (local.ip(), local.port()));
+        assert_eq!(destination.ip, remote.ip());
+        assert_eq!(silence, 600);
+        assert!(outgoing);
+        // Late messages don't move the last activity back
+        assert!(idle.message(local, remote, false, 900 * SECOND).is_none());
+        assert!(idle.message(local, remote, false, 1500 * SECOND).is_none());
+
+        // A new connection with the same addresses starts silent
+        idle.close(local, remote);
+        assert!(idle.is_empty());
+        assert!(idle.message(local, remote, true, 5000 * SECOND).is_none());
+
+        idle.set_threshold(Duration::ZERO);
+        assert!(idle.message(local, remote, true, 9000 * SECOND).is_none());
+        assert!(idle.is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let mut idle = IdleConnections::new(Duration::from_secs(1));
+        let remote: SocketAddr = "203.0.113.7:443".parse().unwrap();
+        for port in 0..=MAX_CONNECTIONS as u64 {
+            let local = SocketAddr::from(([10, 0, (port >> 16) as u8, 2], port as u16));
+            idle.message(local, remote, true, port);
+        }
+        assert_eq!(idle.len(), MAX_CONNECTIONS - MAX_CONNECTIONS / 4);
+    }
+}
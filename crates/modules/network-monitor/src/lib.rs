@@ -1,23 +1,34 @@
 use std::{
     fmt,
-    net::{Ipv4Addr, SocketAddr},
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
 };
 
 use bpf_common::{
     ebpf_program, parsing::BufferIndex, program::BpfContext, BpfSender, Pid, Program,
     ProgramBuilder, ProgramError,
 };
+use capture::{set_capture_config, CaptureConfig};
+use firewall::{FirewallBackend, FirewallCounts};
 use nix::sys::socket::{SockaddrIn, SockaddrIn6};
 
+pub mod capture;
+pub mod dns;
+pub mod firewall;
+pub mod idle;
+pub mod seen;
+pub mod stratum;
+
 const MODULE_NAME: &str = "network-monitor";
 
-// This program intercepts network bind, connect, accept, send, receive and close events.
+// This program intercepts network bind, connect, accept, send, receive and close events,
+// and the changes of the local firewall.
 // If possible we use stable kernel hook points, like LSM or tracepoints. We fall back to
-// kprobes if LSM is unavailable or a feature would not be possible.
+// fentry, or kprobes on kernels without BPF trampolines, if LSM is unavailable or a
+// feature would not be possible.
 //
 // # Bind
 // We find the address the server binds to in the `socket_bind` LSM hook.
-// The `security_*` fallback kprobe is used.
+// The `security_*` fallback fentry or kprobe is used.
 //
 // # Connect
 // We find the address the client connects to in the `socket_connect` LSM hook.
@@ -31,7 +42,14 @@ const MODULE_NAME: &str = "network-monitor";
 // the kernel exits the syscall which caused the "accept" in the first place.
 //
 // # Send
-// We read the address and content of sent messages using the `socket_sendmsg` LSM hook.
+// In the `socket_sendmsg` LSM hook we save the socket and the message in `send_args_map`,
+// and we emit the event when exiting the syscall, which returns how many bytes were really
+// sent: sendmsg, sendmmsg, sendto, write, writev. Since sendmmsg returns the number of
+// messages, its messages are reported whole.
+// The content is copied for UDP and for the first message of TCP streams, in chunks of
+// 4096 bytes, see the `capture` module. For TCP streams we count the bytes sent and
+// received in `sock_owner_map`, to report the offset of every message. Zero-length
+// messages are reported for UDP only.
 //
 // # Receive
 // We use the same strategy of "Accept": in `socket_recvmsg` we save in `args_map` the
@@ -44,14 +62,38 @@ const MODULE_NAME: &str = "network-monitor";
 // the `struct sockaddr` pointer. It will be used when exiting to read the source address.
 //
 // # Close
-// We use the `tcp_set_state` kprobe to discover when a TCP connection is closed.
+// We use the `tcp_set_state` fentry or kprobe to discover when a TCP connection is closed.
+// The close often happens in softirq context, where the current task is the idle
+// task or an unrelated process: the owner of the socket, with its `comm`, is saved
+// in `sock_owner_map` when the socket is connected, accepted or listening, and the
+// close is attributed to it.
+//
+// # Firewall
+// nft and iptables-nft commit their changes as batches of nf_tables netlink messages:
+// the `netlink_send` LSM hook counts the tables, chains and rules added and removed by
+// the batches sent to NETLINK_NETFILTER sockets. Legacy iptables replaces whole tables
+// with the `IPT_SO_SET_REPLACE` option, seen in the `socket_setsockopt` hook.
 pub async fn program(
     ctx: BpfContext,
     sender: impl BpfSender<NetworkEvent>,
+) -> Result<Program, ProgramError> {
+    program_with_disabled_hooks(ctx, sender, &[]).await
+}
+
+/// Start the probes without the given hooks, see [`ProgramBuilder::disable`].
+/// The events depending on them are not reported.
+pub async fn program_with_disabled_hooks(
+    ctx: BpfContext,
+    sender: impl BpfSender<NetworkEvent>,
+    disabled_hooks: &[String],
 ) -> Result<Program, ProgramError> {
     let attach_to_lsm = ctx.lsm_supported();
     let binary = ebpf_program!(&ctx, "probes");
     let mut builder = ProgramBuilder::new(ctx, MODULE_NAME, binary)
+        .disable(disabled_hooks)
+        // Floods of received data. Sent data is never muted: DNS queries and
+        // stratum requests are derived from it.
+        .storm_events(&[EVENT_RECEIVE])
         .tracepoint("syscalls", "sys_exit_accept4")
         .tracepoint("syscalls", "sys_exit_accept")
         .tracepoint("syscalls", "sys_exit_recvmsg")
@@ -59,8 +101,13 @@ pub async fn program(
         .tracepoint("syscalls", "sys_enter_recvfrom")
         .tracepoint("syscalls", "sys_exit_recvfrom")
         .tracepoint("syscalls", "sys_exit_read")
+        .tracepoint("syscalls", "sys_exit_sendmsg")
+        .tracepoint("syscalls", "sys_exit_sendmmsg")
+        .tracepoint("syscalls", "sys_exit_sendto")
+        .tracepoint("syscalls", "sys_exit_write")
+        .tracepoint("syscalls", "sys_exit_writev")
         .tracepoint("syscalls", "sys_exit_readv")
-        .kprobe("tcp_set_state");
+        .fentry_or_kprobe("tcp_set_state");
     if attach_to_lsm {
         builder = builder
             .lsm("socket_bind")
@@ -68,23 +115,31 @@ pub async fn program(
             .lsm("socket_connect")
             .lsm("socket_accept")
             .lsm("socket_sendmsg")
-            .lsm("socket_recvmsg");
+            .lsm("socket_recvmsg")
+            .lsm("netlink_send")
+            .lsm("socket_setsockopt");
     } else {
         builder = builder
-            .kprobe("security_socket_bind")
-            .kprobe("security_socket_listen")
-            .kprobe("security_socket_connect")
-            .kprobe("security_socket_accept")
-            .kprobe("security_socket_sendmsg")
-            .kprobe("security_socket_recvmsg");
+            .fentry_or_kprobe("security_socket_bind")
+            .fentry_or_kprobe("security_socket_listen")
+            .fentry_or_kprobe("security_socket_connect")
+            .fentry_or_kprobe("security_socket_accept")
+            .fentry_or_kprobe("security_socket_sendmsg")
+            .fentry_or_kprobe("security_socket_recvmsg")
+            .fentry_or_kprobe("security_netlink_send")
+            .fentry_or_kprobe("security_socket_setsockopt");
     }
     let mut program = builder.start().await?;
+    set_capture_config(&mut program, &CaptureConfig::default())?;
     program
         .read_events("map_output_network_event", sender)
         .await?;
     Ok(program)
 }
 
+/// Event type of [`NetworkEvent::Receive`], `EVENT_RECV` in probes.bpf.c
+const EVENT_RECEIVE: u32 = 5;
+
 #[derive(Debug)]
 #[repr(C)]
 pub enum NetworkEvent {
@@ -103,6 +158,8 @@ pub enum NetworkEvent {
     Accept {
         src: Addr,
         dst: Addr,
+        /// Id of the SO_REUSEPORT group of the listening socket, 0 if none
+        reuseport_group: u32,
         // TCP-only
     },
     // NOTE: source/destination here indicate the communication side rather
@@ -113,6 +170,12 @@ pub enum NetworkEvent {
         data: BufferIndex<[u8]>,
         data_len: u32,
         proto: Proto,
+        capture: Capture,
+        /// Index of the chunk of data in this event, see [`capture`]
+        chunk: u8,
+        chunks: u8,
+        /// Offset of the message in the TCP stream, 0 for UDP
+        offset: u64,
     },
     Receive {
         src: Addr,
@@ -120,13 +183,32 @@ pub enum NetworkEvent {
         data: BufferIndex<[u8]>,
         data_len: u32,
         proto: Proto,
+        capture: Capture,
+        /// Index of the chunk of data in this event, see [`capture`]
+        chunk: u8,
+        chunks: u8,
+        /// Offset of the message in the TCP stream, 0 for UDP
+        offset: u64,
     },
     Close {
         original_pid: Pid,
         src: Addr,
         dst: Addr,
+        /// Name of the task owning the socket when it was connected or accepted
+        comm: [u8; 16],
         // TCP-only
     },
+    /// Change of the firewall, see [`firewall`]
+    Firewall {
+        backend: FirewallBackend,
+        counts: FirewallCounts,
+    },
+}
+
+/// Convert a NUL terminated task name.
+fn comm_to_string(comm: &[u8]) -> String {
+    let len = comm.iter().position(|&c| c == 0).unwrap_or(comm.len());
+    String::from_utf8_lossy(&comm[..len]).into_owned()
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -145,6 +227,15 @@ impl From<SocketAddr> for Addr {
     }
 }
 
+impl From<Addr> for SocketAddr {
+    fn from(value: Addr) -> Self {
+        match value {
+            Addr::V4(v) => SocketAddr::V4(SocketAddrV4::from(v)),
+            Addr::V6(v) => SocketAddr::V6(SocketAddrV6::from(v)),
+        }
+    }
+}
+
 impl fmt::Display for Addr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -161,32 +252,97 @@ pub enum Proto {
     UDP = 1,
 }
 
+/// Copy state of `data` in messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Capture {
+    Disabled = 0,
+    Empty = 1,
+    Complete = 2,
+    Truncated = 3,
+}
+
 impl fmt::Display for NetworkEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             NetworkEvent::Bind { addr, proto } => write!(f, "bind on {addr} ({proto:?})"),
             NetworkEvent::Listen { addr } => write!(f, "listen on {addr}"),
             NetworkEvent::Connect { dst, proto } => write!(f, "connect -> {dst} ({proto:?})"),
-            NetworkEvent::Accept { src, dst } => write!(f, "accept {src} -> {dst}"),
-            NetworkEvent::Send { data_len, .. } => write!(f, "sent {data_len} bytes"),
-            NetworkEvent::Receive { data_len, .. } => write!(f, "received {data_len} bytes"),
+            NetworkEvent::Accept {
+                src,
+                dst,
+                reuseport_group,
+            } => write!(
+                f,
+                "accept {src} -> {dst} (reuseport group: {reuseport_group})"
+            ),
+            NetworkEvent::Send {
+                data_len,
+                offset,
+                capture,
+                ..
+            } => write!(
+                f,
+                "sent {data_len} bytes at {offset} (capture: {capture:?})"
+            ),
+            NetworkEvent::Receive {
+                data_len,
+                offset,
+                capture,
+                ..
+            } => write!(
+                f,
+                "received {data_len} bytes at {offset} (capture: {capture:?})"
+            ),
             NetworkEvent::Close {
                 src,
                 dst,
                 original_pid,
-            } => write!(f, "close {src} -> {dst} (original pid: {original_pid})"),
+                comm,
+            } => write!(
+                f,
+                "close {src} -> {dst} (original pid: {original_pid}, comm: {})",
+                comm_to_string(comm)
+            ),
+            NetworkEvent::Firewall { backend, counts } => {
+                write!(f, "firewall change ({backend}): {counts:?}")
+            }
         }
     }
 }
 
 pub mod pulsar {
+    use std::{
+        future::Future,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
     use super::*;
-    use bpf_common::{parsing::IndexError, program::BpfEvent, BpfSenderWrapper};
+    use crate::{
+        capture::{
+            set_capture_config, CaptureConfig, Message, Reassembler, CHUNK_SIZE, MAX_CAPTURE_SIZE,
+        },
+        dns::DnsParser,
+        idle::IdleConnections,
+        seen::{SeenDomains, DEFAULT_SEEN_DOMAINS_PATH},
+    };
+    use bpf_common::{
+        parsing::IndexError,
+        program::BpfEvent,
+        time::Timestamp,
+        worker_pool::{WorkerPool, WorkerPoolConfig},
+    };
     use pulsar_core::{
-        event::{DnsAnswer, DnsQuestion, Host},
+        event::{DataCapture, Host},
         pdk::{
-            CleanExit, IntoPayload, ModuleContext, ModuleError, Payload, PulsarModule,
-            ShutdownSignal, Version,
+            process_tracker::ProcessTrackerHandle, CleanExit, ConfigError, ConfigField, ConfigKind,
+            ConfigSchema, IntoPayload, ModuleConfig, ModuleContext, ModuleError, ModuleSender,
+            Payload, PulsarModule, ShutdownSignal, Version,
         },
     };
 
@@ -197,46 +353,465 @@ pub mod pulsar {
             true,
             network_monitor_task,
         )
+        .uses_ebpf()
+        // Events are enriched by the process tracker, fed by process-monitor
+        .depends_on("process-monitor")
+        .with_config_schema(
+            ConfigSchema::new(1)
+                .field(
+                    ConfigField::new(
+                        "normalize_mapped_ipv4",
+                        ConfigKind::Bool,
+                        "Report IPv4-mapped IPv6 addresses of dual-stack sockets as IPv4",
+                    )
+                    .default_value(true),
+                )
+                .field(
+                    ConfigField::new(
+                        "capture_size",
+                        ConfigKind::Integer,
+                        "Bytes of data copied from UDP messages, parsed for DNS",
+                    )
+                    .default_value(CHUNK_SIZE)
+                    .range(0, MAX_CAPTURE_SIZE as i64),
+                )
+                .field(
+                    ConfigField::new(
+                        "first_message_capture_size",
+                        ConfigKind::Integer,
+                        "Bytes of data copied from the first message of TCP streams",
+                    )
+                    .default_value(CHUNK_SIZE)
+                    .range(0, MAX_CAPTURE_SIZE as i64),
+                )
+                .field(
+                    ConfigField::new(
+                        "track_seen_domains",
+                        ConfigKind::Bool,
+                        "Flag the DNS questions of domains never queried before on this host",
+                    )
+                    .default_value(true),
+                )
+                .field(
+                    ConfigField::new(
+                        "seen_domains_path",
+                        ConfigKind::Path,
+                        "File with the domains queried before on this host",
+                    )
+                    .default_value(DEFAULT_SEEN_DOMAINS_PATH),
+                )
+                .field(ConfigField::new(
+                    "popular_domains_path",
+                    ConfigKind::Path,
+                    "List of popular domains, like a top-1M list, never flagged as first seen",
+                ))
+                .field(ConfigField::new(
+                    "disabled_hooks",
+                    ConfigKind::List,
+                    "Hooks not attached, like sys_exit_read,sys_exit_readv on IO-heavy hosts",
+                ))
+                .field(
+                    ConfigField::new(
+                        "idle_threshold",
+                        ConfigKind::Integer,
+                        "Seconds of silence after which a message on a TCP connection is reported, 0 to disable",
+                    )
+                    .default_value(DEFAULT_IDLE_THRESHOLD)
+                    .range(0, i64::MAX),
+                )
+                .field(
+                    ConfigField::new(
+                        "workers",
+                        ConfigKind::Integer,
+                        "Tasks parsing the messages, 0 to parse them on the reader of the probes",
+                    )
+                    .default_value(DEFAULT_WORKERS)
+                    .range(0, i64::MAX),
+                )
+                .field(
+                    ConfigField::new(
+                        "worker_queue_size",
+                        ConfigKind::Integer,
+                        "Messages waiting to be parsed by a worker after which new ones are dropped",
+                    )
+                    .default_value(DEFAULT_WORKER_QUEUE_SIZE)
+                    .range(1, i64::MAX),
+                ),
+        )
     }
 
+    const SEEN_DOMAINS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+    const DEFAULT_IDLE_THRESHOLD: u64 = 3600;
+    const DEFAULT_WORKERS: usize = 2;
+    const DEFAULT_WORKER_QUEUE_SIZE: usize = 1024;
+
     async fn network_monitor_task(
         ctx: ModuleContext,
         mut shutdown: ShutdownSignal,
     ) -> Result<CleanExit, ModuleError> {
-        let sender = ctx.get_sender();
-        let dns_sender = ctx.get_sender();
-        // intercept DNS
-        let sender = BpfSenderWrapper::new(sender, move |event: &BpfEvent<NetworkEvent>| {
-            if let Some(dns_event) = collect_dns_if_any(event) {
-                dns_sender.send(event.pid, event.timestamp, dns_event);
-            }
+        let mut rx_config = ctx.get_config();
+        let mut config: Config = rx_config.read()?;
+        let normalize_mapped_ipv4 = Arc::new(AtomicBool::new(config.normalize_mapped_ipv4));
+        let seen_domains = Arc::new(Mutex::new(load_seen_domains(&config)));
+        let idle_connections = Arc::new(Mutex::new(IdleConnections::new(Duration::from_secs(
+            config.idle_threshold,
+        ))));
+        let processor = MessageProcessor {
+            sender: ctx.get_sender(),
+            normalize_mapped_ipv4: normalize_mapped_ipv4.clone(),
+            seen_domains: seen_domains.clone(),
+            idle_connections: idle_connections.clone(),
+            process_tracker: ctx.get_process_tracker(),
+            dns_parser: DnsParser::default(),
+        };
+        // Started once: changing the workers needs a restart of the module
+        let workers = (config.workers.workers > 0).then(|| {
+            let mut processor = processor.clone();
+            WorkerPool::start(config.workers, move |message| processor.process(message))
         });
-        let _program = program(ctx.get_bpf_context(), sender).await?;
-        shutdown.recv().await
+        let sender = NetworkSender {
+            reassembler: Reassembler::default(),
+            processor,
+            workers,
+        };
+        let mut program = program_with_disabled_hooks(
+            ctx.get_bpf_context(),
+            sender.clone(),
+            &config.disabled_hooks,
+        )
+        .await?;
+        set_capture_config(&mut program, &config.capture)?;
+        let mut seen_domains_interval = tokio::time::interval(SEEN_DOMAINS_FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                r = shutdown.recv() => {
+                    flush_seen_domains(&mut seen_domains.lock().unwrap());
+                    return r;
+                }
+                _ = rx_config.changed() => {
+                    let previous = config.clone();
+                    config = rx_config.read()?;
+                    if config.disabled_hooks != previous.disabled_hooks {
+                        // The old probes must be detached and their pinned
+                        // maps removed before the new ones are pinned.
+                        log::info!(
+                            "Restarting the probes with disabled hooks {:?}",
+                            config.disabled_hooks
+                        );
+                        drop(program);
+                        program = program_with_disabled_hooks(
+                            ctx.get_bpf_context(),
+                            sender.clone(),
+                            &config.disabled_hooks,
+                        )
+                        .await?;
+                    }
+                    normalize_mapped_ipv4.store(config.normalize_mapped_ipv4, Ordering::Relaxed);
+                    set_capture_config(&mut program, &config.capture)?;
+                    idle_connections
+                        .lock()
+                        .unwrap()
+                        .set_threshold(Duration::from_secs(config.idle_threshold));
+                    if config.seen_domains != previous.seen_domains {
+                        let mut seen_domains = seen_domains.lock().unwrap();
+                        flush_seen_domains(&mut seen_domains);
+                        *seen_domains = load_seen_domains(&config);
+                    }
+                }
+                _ = seen_domains_interval.tick() => {
+                    flush_seen_domains(&mut seen_domains.lock().unwrap());
+                }
+            }
+        }
     }
 
-    impl From<Addr> for Host {
-        fn from(value: Addr) -> Self {
-            match value {
-                Addr::V4(v) => {
-                    let bits = v.ip();
-                    let octects = [
-                        (bits >> 24) as u8,
-                        (bits >> 16) as u8,
-                        (bits >> 8) as u8,
-                        bits as u8,
-                    ];
-
-                    Host {
-                        ip: Ipv4Addr::from(octects).into(),
-                        port: v.port(),
+    /// Load the domains seen before on this host, if tracked.
+    fn load_seen_domains(config: &Config) -> Option<SeenDomains> {
+        let config = &config.seen_domains;
+        config
+            .enabled
+            .then(|| SeenDomains::load(&config.path, config.popular_path.as_deref()))
+    }
+
+    fn flush_seen_domains(seen_domains: &mut Option<SeenDomains>) {
+        if let Some(seen_domains) = seen_domains {
+            if let Err(err) = seen_domains.flush() {
+                log::warn!("Error saving seen domains: {err}");
+            }
+        }
+    }
+
+    /// Reassembles the messages of network events and hands them to the
+    /// workers, or processes them right away without workers.
+    #[derive(Clone)]
+    struct NetworkSender {
+        reassembler: Reassembler<BpfEvent<NetworkEvent>>,
+        processor: MessageProcessor,
+        workers: Option<WorkerPool<Message<BpfEvent<NetworkEvent>>>>,
+    }
+
+    /// Sends network events, normalizing their addresses according to the
+    /// module configuration, the DNS and mining pool requests found in the
+    /// data of messages and the TCP connections resuming after a silence.
+    #[derive(Clone)]
+    struct MessageProcessor {
+        sender: ModuleSender,
+        normalize_mapped_ipv4: Arc<AtomicBool>,
+        seen_domains: Arc<Mutex<Option<SeenDomains>>>,
+        idle_connections: Arc<Mutex<IdleConnections>>,
+        process_tracker: ProcessTrackerHandle,
+        /// Not shared: every worker has its own
+        dns_parser: DnsParser,
+    }
+
+    impl MessageProcessor {
+        /// Parse a message, returning the future sending its events in order:
+        /// the workers await it before processing their next message.
+        fn process(
+            &mut self,
+            message: Message<BpfEvent<NetworkEvent>>,
+        ) -> impl Future<Output = ()> + Send + 'static {
+            let pid = message.event.pid;
+            let timestamp = message.event.timestamp;
+            let mut payloads = Vec::new();
+            if let Some(mut dns_event) = collect_dns_if_any(&message, &mut self.dns_parser) {
+                if let Some(seen_domains) = self.seen_domains.lock().unwrap().as_mut() {
+                    seen_domains.mark(&mut dns_event);
+                }
+                payloads.push(dns_event);
+            }
+            payloads.extend(collect_stratum_if_any(&message));
+            payloads.extend(self.track_idle_connection(&message.event));
+            match NetworkEvent::try_into_payload(message.event) {
+                Ok(payload) => payloads.push(payload),
+                Err(e) => self.sender.raise_error(Box::new(e)),
+            }
+
+            let sender = self.sender.clone();
+            let process_tracker = self.process_tracker.clone();
+            let normalize_mapped_ipv4 = self.normalize_mapped_ipv4.load(Ordering::Relaxed);
+            async move {
+                for mut payload in payloads {
+                    if normalize_mapped_ipv4 {
+                        unmap_ipv4(&mut payload);
+                    }
+                    if matches!(payload, Payload::FirewallChange { .. }) {
+                        add_firewall_command(&process_tracker, pid, timestamp, &mut payload).await;
+                    }
+                    sender.send_ordered(pid, timestamp, payload).await;
+                }
+            }
+        }
+
+        /// Record the messages and closes of TCP connections, returning an
+        /// `IdleConnection` for a message after a long silence.
+        fn track_idle_connection(&self, event: &BpfEvent<NetworkEvent>) -> Option<Payload> {
+            let mut idle_connections = self.idle_connections.lock().unwrap();
+            match &event.payload {
+                NetworkEvent::Send {
+                    src,
+                    dst,
+                    proto: Proto::TCP,
+                    ..
+                } => idle_connections.message(
+                    src.clone().into(),
+                    dst.clone().into(),
+                    true,
+                    event.timestamp.raw(),
+                ),
+                NetworkEvent::Receive {
+                    src,
+                    dst,
+                    proto: Proto::TCP,
+                    ..
+                } => idle_connections.message(
+                    src.clone().into(),
+                    dst.clone().into(),
+                    false,
+                    event.timestamp.raw(),
+                ),
+                NetworkEvent::Close { src, dst, .. } => {
+                    idle_connections.close(src.clone().into(), dst.clone().into());
+                    None
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Add to a firewall change the command line of the process, which
+    /// describes the change when it's a known firewall tool.
+    async fn add_firewall_command(
+        process_tracker: &ProcessTrackerHandle,
+        pid: Pid,
+        timestamp: Timestamp,
+        payload: &mut Payload,
+    ) {
+        if let (
+            Payload::FirewallChange {
+                command, summary, ..
+            },
+            Ok(info),
+        ) = (payload, process_tracker.get(pid, timestamp).await)
+        {
+            if let Some(command_summary) = firewall::summarize_command(&info.argv) {
+                *summary = command_summary;
+            }
+            *command = info.argv;
+        }
+    }
+
+    impl BpfSender<NetworkEvent> for NetworkSender {
+        fn send(&mut self, data: Result<BpfEvent<NetworkEvent>, ProgramError>) {
+            match data {
+                Ok(data) => {
+                    let Some(message) = self.reassembler.push_event(data) else {
+                        return;
+                    };
+                    match &self.workers {
+                        Some(workers) => {
+                            workers.dispatch(WorkerKey::from(&message.event), message);
+                        }
+                        None => {
+                            tokio::spawn(self.processor.process(message));
+                        }
                     }
                 }
+                Err(e) => self.processor.sender.raise_error(Box::new(e)),
+            }
+        }
+    }
+
+    /// Key assigning the messages to the workers: the events of a socket are
+    /// processed in order, whichever task sends them. The peer identifies
+    /// the flow of a connected socket from the connect or accept to the
+    /// close, the local address the one of a listening socket.
+    #[derive(Debug, PartialEq, Eq, Hash)]
+    enum WorkerKey {
+        Peer(SocketAddr),
+        Local(SocketAddr),
+        Process(Pid),
+    }
+
+    impl From<&BpfEvent<NetworkEvent>> for WorkerKey {
+        fn from(event: &BpfEvent<NetworkEvent>) -> Self {
+            match &event.payload {
+                NetworkEvent::Connect { dst, .. }
+                | NetworkEvent::Send { dst, .. }
+                | NetworkEvent::Receive { dst, .. }
+                | NetworkEvent::Close { dst, .. } => WorkerKey::Peer(dst.clone().into()),
+                NetworkEvent::Accept { src, .. } => WorkerKey::Peer(src.clone().into()),
+                NetworkEvent::Bind { addr, .. } | NetworkEvent::Listen { addr } => {
+                    WorkerKey::Local(addr.clone().into())
+                }
+                NetworkEvent::Firewall { .. } => WorkerKey::Process(event.pid),
+            }
+        }
+    }
+
+    /// Convert the IPv4-mapped addresses of a network event to IPv4.
+    fn unmap_ipv4(payload: &mut Payload) {
+        match payload {
+            Payload::Bind { address, .. } | Payload::Listen { address } => address.unmap_ipv4(),
+            Payload::Connect { destination, .. } | Payload::StratumRequest { destination, .. } => {
+                destination.unmap_ipv4()
+            }
+            Payload::Accept {
+                source,
+                destination,
+                ..
+            }
+            | Payload::Close {
+                source,
+                destination,
+                ..
+            }
+            | Payload::Send {
+                source,
+                destination,
+                ..
+            }
+            | Payload::Receive {
+                source,
+                destination,
+                ..
+            }
+            | Payload::IdleConnection {
+                source,
+                destination,
+                ..
+            } => {
+                source.unmap_ipv4();
+                destination.unmap_ipv4();
+            }
+            _ => {}
+        }
+    }
+
+    #[derive(Clone)]
+    struct Config {
+        normalize_mapped_ipv4: bool,
+        capture: CaptureConfig,
+        seen_domains: SeenDomainsConfig,
+        disabled_hooks: Vec<String>,
+        idle_threshold: u64,
+        workers: WorkerPoolConfig,
+    }
 
-                Addr::V6(v) => Host {
-                    ip: v.ip().into(),
-                    port: v.port(),
+    #[derive(Clone, PartialEq)]
+    struct SeenDomainsConfig {
+        enabled: bool,
+        path: PathBuf,
+        popular_path: Option<PathBuf>,
+    }
+
+    impl TryFrom<&ModuleConfig> for Config {
+        type Error = ConfigError;
+
+        fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+            let default = CaptureConfig::default();
+            Ok(Self {
+                normalize_mapped_ipv4: config.with_default("normalize_mapped_ipv4", true)?,
+                capture: CaptureConfig {
+                    size: config.with_default("capture_size", default.size)?,
+                    first_message_size: config
+                        .with_default("first_message_capture_size", default.first_message_size)?,
+                },
+                seen_domains: SeenDomainsConfig {
+                    enabled: config.with_default("track_seen_domains", true)?,
+                    path: config.with_default(
+                        "seen_domains_path",
+                        PathBuf::from(DEFAULT_SEEN_DOMAINS_PATH),
+                    )?,
+                    popular_path: config
+                        .get_raw("popular_domains_path")
+                        .filter(|path| !path.is_empty())
+                        .map(PathBuf::from),
                 },
+                disabled_hooks: config.get_list_with_default("disabled_hooks", Vec::new())?,
+                idle_threshold: config.with_default("idle_threshold", DEFAULT_IDLE_THRESHOLD)?,
+                workers: WorkerPoolConfig {
+                    workers: config.with_default("workers", DEFAULT_WORKERS)?,
+                    queue_size: config
+                        .with_default("worker_queue_size", DEFAULT_WORKER_QUEUE_SIZE)?,
+                },
+            })
+        }
+    }
+
+    impl From<Addr> for Host {
+        fn from(value: Addr) -> Self {
+            SocketAddr::from(value).into()
+        }
+    }
+
+    impl From<Capture> for DataCapture {
+        fn from(value: Capture) -> Self {
+            match value {
+                Capture::Disabled => DataCapture::Disabled,
+                Capture::Empty => DataCapture::Empty,
+                Capture::Complete => DataCapture::Complete,
+                Capture::Truncated => DataCapture::Truncated,
             }
         }
     }
@@ -257,94 +832,318 @@ pub mod pulsar {
                     destination: dst.into(),
                     is_tcp: matches!(proto, Proto::TCP),
                 },
-                NetworkEvent::Accept { src, dst } => Payload::Accept {
+                NetworkEvent::Accept {
+                    src,
+                    dst,
+                    reuseport_group,
+                } => Payload::Accept {
                     source: src.into(),
                     destination: dst.into(),
+                    reuseport_group,
                 },
                 NetworkEvent::Send {
                     src,
                     dst,
                     data_len,
                     proto,
+                    capture,
+                    offset,
                     ..
                 } => Payload::Send {
                     source: src.into(),
                     destination: dst.into(),
                     len: data_len as usize,
                     is_tcp: matches!(proto, Proto::TCP),
+                    offset,
+                    capture: capture.into(),
                 },
                 NetworkEvent::Receive {
                     src,
                     dst,
                     data_len,
                     proto,
+                    capture,
+                    offset,
                     ..
                 } => Payload::Receive {
                     source: src.into(),
                     destination: dst.into(),
                     len: data_len as usize,
                     is_tcp: matches!(proto, Proto::TCP),
+                    offset,
+                    capture: capture.into(),
                 },
                 NetworkEvent::Close {
                     src,
                     dst,
                     original_pid: _,
+                    comm,
                 } => Payload::Close {
                     source: src.into(),
                     destination: dst.into(),
+                    comm: comm_to_string(&comm),
+                },
+                NetworkEvent::Firewall { backend, counts } => Payload::FirewallChange {
+                    backend: backend.to_string(),
+                    tables_added: counts.tables_added,
+                    tables_removed: counts.tables_removed,
+                    chains_added: counts.chains_added,
+                    chains_removed: counts.chains_removed,
+                    rules_added: counts.rules_added,
+                    rules_removed: counts.rules_removed,
+                    // Filled in from the process tracker by the sender
+                    command: Vec::new(),
+                    summary: firewall::summarize_counts(backend, &counts),
                 },
             })
         }
     }
 
-    fn collect_dns_if_any(event: &BpfEvent<NetworkEvent>) -> Option<Payload> {
-        let data = match &event.payload {
-            NetworkEvent::Send { data, .. } => data,
-            NetworkEvent::Receive { data, .. } => data,
+    /// Stratum request sent in a TCP message, see [`stratum`].
+    pub fn collect_stratum_if_any(message: &Message<BpfEvent<NetworkEvent>>) -> Option<Payload> {
+        let dst = match &message.event.payload {
+            NetworkEvent::Send {
+                dst,
+                proto: Proto::TCP,
+                ..
+            } => dst,
+            _ => return None,
+        };
+        let request = stratum::parse_request(&message.data)?;
+        Some(Payload::StratumRequest {
+            destination: dst.clone().into(),
+            method: request.method,
+            agent: request.agent,
+        })
+    }
+
+    /// DNS query or response in a UDP message, see [`dns`].
+    pub fn collect_dns_if_any(
+        message: &Message<BpfEvent<NetworkEvent>>,
+        parser: &mut DnsParser,
+    ) -> Option<Payload> {
+        match &message.event.payload {
+            NetworkEvent::Send {
+                proto: Proto::UDP, ..
+            }
+            | NetworkEvent::Receive {
+                proto: Proto::UDP, ..
+            } => {}
             _ => return None,
         };
 
-        if data.is_empty() {
+        if message.data.is_empty() {
             return None;
         }
-        let data = data
-            .bytes(&event.buffer)
-            .map_err(|err| {
-                log::error!("[dns] Error getting message: {}", err);
-            })
-            .ok()?;
-
-        // any valid dns data?
-        let dns = dns_parser::Packet::parse(data).ok()?;
-        let with_q = !dns.questions.is_empty();
-        let with_a = !dns.answers.is_empty();
-
-        let mut questions = Vec::new();
-        for q in dns.questions {
-            questions.push(DnsQuestion {
-                name: format!("{}", q.qname),
-                qtype: format!("{:?}", q.qtype),
-                qclass: format!("{:?}", q.qclass),
-            });
+        parser.parse(&message.data)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+
+        use bpf_common::fixtures;
+        use pulsar_core::{
+            bus::Bus,
+            event::Namespaces,
+            pdk::process_tracker::{start_process_tracker, TrackerUpdate},
+        };
+        use tokio::sync::mpsc;
+
+        use super::*;
+
+        const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/events.fixture");
+
+        /// Peer of the socket of a network event.
+        fn peer(payload: &Payload) -> Option<String> {
+            match payload {
+                Payload::Connect { destination, .. }
+                | Payload::Send { destination, .. }
+                | Payload::Receive { destination, .. }
+                | Payload::Close { destination, .. } => Some(destination.to_string()),
+                Payload::Accept { source, .. } => Some(source.to_string()),
+                _ => None,
+            }
         }
 
-        let mut answers = Vec::new();
-        for a in dns.answers {
-            answers.push(DnsAnswer {
-                name: format!("{}", a.name),
-                class: format!("{:?}", a.cls),
-                ttl: a.ttl,
-                data: format!("{:?}", a.data),
-            });
+        #[tokio::test(flavor = "multi_thread")]
+        async fn socket_events_in_order() {
+            const ROUNDS: u64 = 20;
+            let bus = Bus::new();
+            let mut rx = bus.get_receiver();
+            let process_tracker = start_process_tracker();
+            let (signal_sender, _signal_receiver) = mpsc::channel(1);
+            let processor = MessageProcessor {
+                sender: ModuleSender::new(
+                    bus.get_sender(),
+                    MODULE_NAME.into(),
+                    process_tracker.clone(),
+                    signal_sender,
+                ),
+                normalize_mapped_ipv4: Arc::new(AtomicBool::new(true)),
+                seen_domains: Arc::new(Mutex::new(None)),
+                idle_connections: Arc::new(Mutex::new(IdleConnections::new(Duration::from_secs(
+                    DEFAULT_IDLE_THRESHOLD,
+                )))),
+                process_tracker: process_tracker.clone(),
+                dns_parser: DnsParser::default(),
+            };
+            let workers = WorkerPool::start(
+                WorkerPoolConfig {
+                    workers: 4,
+                    queue_size: 1024,
+                },
+                {
+                    let mut processor = processor.clone();
+                    move |message| processor.process(message)
+                },
+            );
+            let mut sender = NetworkSender {
+                reassembler: Reassembler::default(),
+                processor,
+                workers: Some(workers),
+            };
+
+            // The fixture is replayed several times, later every round, with
+            // the upload of 5000 bytes in two chunks on one socket
+            let events = fixtures::load::<NetworkEvent>(FIXTURE).unwrap();
+            let first = events.iter().map(|event| event.timestamp.raw()).min();
+            let last = events.iter().map(|event| event.timestamp.raw()).max();
+            let round_duration = last.unwrap() - first.unwrap() + 1;
+            for event in &events {
+                process_tracker.update(TrackerUpdate::Fork {
+                    pid: event.pid,
+                    timestamp: 0.into(),
+                    ppid: Pid::from_raw(1),
+                    namespaces: Namespaces::default(),
+                });
+            }
+            for round in 0..ROUNDS {
+                for mut event in fixtures::load::<NetworkEvent>(FIXTURE).unwrap() {
+                    event.timestamp = event.timestamp + round * round_duration;
+                    sender.send(Ok(event));
+                }
+            }
+
+            let mut timestamps: HashMap<String, Vec<u64>> = HashMap::new();
+            let mut uploads = 0;
+            while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await
+            {
+                if let Payload::Send { len: 5000, .. } = event.payload() {
+                    uploads += 1;
+                }
+                if let Some(peer) = peer(event.payload()) {
+                    timestamps
+                        .entry(peer)
+                        .or_default()
+                        .push(event.header().raw_timestamp);
+                }
+            }
+            assert_eq!(uploads, ROUNDS);
+            assert!(timestamps.len() > 1);
+            for (peer, timestamps) in timestamps {
+                assert!(
+                    timestamps.windows(2).all(|pair| pair[0] <= pair[1]),
+                    "events of {peer} out of order: {timestamps:?}"
+                );
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
 
-        if with_q && !with_a {
-            Some(Payload::DnsQuery { questions })
-        } else if with_a {
-            Some(Payload::DnsResponse { answers, questions })
-        } else {
-            None
+    use nix::{libc, sys::socket::SockaddrLike};
+    use proptest::prelude::*;
+    use pulsar_core::event::Host;
+
+    use super::*;
+
+    // Build an address from the raw sockaddr_in the probes copy from the kernel,
+    // where address and port are in network byte order.
+    fn addr_from_raw_v4(octets: [u8; 4], port: u16) -> Addr {
+        let raw = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: port.to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes(octets),
+            },
+            sin_zero: [0; 8],
+        };
+        let addr = unsafe {
+            SockaddrIn::from_raw(
+                &raw as *const _ as *const libc::sockaddr,
+                Some(size_of::<libc::sockaddr_in>() as libc::socklen_t),
+            )
+        };
+        Addr::V4(addr.unwrap())
+    }
+
+    fn addr_from_raw_v6(octets: [u8; 16], port: u16, scope_id: u32) -> Addr {
+        let raw = libc::sockaddr_in6 {
+            sin6_family: libc::AF_INET6 as libc::sa_family_t,
+            sin6_port: port.to_be(),
+            sin6_flowinfo: 0,
+            sin6_addr: libc::in6_addr { s6_addr: octets },
+            sin6_scope_id: scope_id,
+        };
+        let addr = unsafe {
+            SockaddrIn6::from_raw(
+                &raw as *const _ as *const libc::sockaddr,
+                Some(size_of::<libc::sockaddr_in6>() as libc::socklen_t),
+            )
+        };
+        Addr::V6(addr.unwrap())
+    }
+
+    proptest! {
+        #[test]
+        fn socket_addr_round_trip(addr in any::<SocketAddr>()) {
+            prop_assert_eq!(SocketAddr::from(Addr::from(addr)), addr);
+        }
+
+        #[test]
+        fn display_matches_socket_addr(addr in any::<SocketAddr>()) {
+            prop_assert_eq!(Addr::from(addr).to_string(), addr.to_string());
+        }
+
+        #[test]
+        fn host_matches_socket_addr(addr in any::<SocketAddr>()) {
+            let host = Host::from(Addr::from(addr));
+            prop_assert_eq!(host.ip, addr.ip());
+            prop_assert_eq!(host.port, addr.port());
+            prop_assert_eq!(host.to_string(), addr.to_string());
+            if let SocketAddr::V6(v6) = addr {
+                prop_assert_eq!(host.scope_id, v6.scope_id());
+                prop_assert_eq!(host.flowinfo, v6.flowinfo());
+            }
+        }
+
+        #[test]
+        fn raw_v4_byte_order(octets in any::<[u8; 4]>(), port in any::<u16>()) {
+            let expected = SocketAddrV4::new(octets.into(), port);
+            let addr = addr_from_raw_v4(octets, port);
+            prop_assert_eq!(SocketAddr::from(addr.clone()), SocketAddr::V4(expected));
+            prop_assert_eq!(Host::from(addr).ip, *expected.ip());
         }
+
+        #[test]
+        fn raw_v6_scope_id(octets in any::<[u8; 16]>(), port in any::<u16>(), scope_id in any::<u32>()) {
+            let expected = SocketAddrV6::new(octets.into(), port, 0, scope_id);
+            let addr = addr_from_raw_v6(octets, port, scope_id);
+            prop_assert_eq!(addr.to_string(), expected.to_string());
+            prop_assert_eq!(SocketAddr::from(addr), SocketAddr::V6(expected));
+        }
+    }
+
+    #[test]
+    fn link_local_scope_id() {
+        let addr: SocketAddr = "[fe80::1%2]:8080".parse().unwrap();
+        assert_eq!(Addr::from(addr).to_string(), "[fe80::1%2]:8080");
+        let octets = "fe80::1".parse::<std::net::Ipv6Addr>().unwrap().octets();
+        assert_eq!(SocketAddr::from(addr_from_raw_v6(octets, 8080, 2)), addr);
     }
 }
 
@@ -352,20 +1151,26 @@ pub mod pulsar {
 pub mod test_suite {
     use std::{
         io::{Read, Write},
-        net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+        net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket},
+        os::fd::FromRawFd,
         time::Duration,
     };
 
     use bpf_common::{
         event_check,
-        test_runner::{TestCase, TestReport, TestRunner, TestSuite},
+        program::BpfEvent,
+        test_runner::{Check, CheckResult, TestCase, TestReport, TestRunner, TestSuite},
     };
     use nix::{
         libc::kill,
+        sys::socket::{
+            bind, listen, setsockopt, socket, sockopt, AddressFamily, SockFlag, SockType,
+        },
         unistd::{fork, ForkResult},
     };
 
     use super::*;
+    use crate::capture::CHUNK_SIZE;
 
     pub fn tests() -> TestSuite {
         TestSuite {
@@ -381,10 +1186,15 @@ pub mod test_suite {
                 listen_ipv6(),
                 accept_ipv4(),
                 accept_ipv6(),
+                accept_reuseport(),
                 udp_ipv4_sendmsg_recvmsg(),
                 udp_ipv6_sendmsg_recvmsg(),
                 tcp_ipv4_sendmsg_recvmsg(),
                 tcp_ipv6_sendmsg_recvmsg(),
+                udp_sendmsg_truncated(),
+                udp_sendmsg_chunks(),
+                tcp_stream_offset(),
+                udp_empty_message(),
                 close_ipv4(),
                 close_ipv6(),
             ],
@@ -402,7 +1212,7 @@ pub mod test_suite {
     async fn run_bind_test(bind_addr: &str) -> TestReport {
         let bind_addr: SocketAddr = bind_addr.parse().unwrap();
         TestRunner::with_ebpf(program)
-            .run(|| {
+            .run_sandboxed(|| {
                 let _listener = TcpListener::bind(bind_addr).unwrap();
             })
             .await
@@ -418,7 +1228,7 @@ pub mod test_suite {
         TestCase::new("bind_udp", async {
             let bind_addr: SocketAddr = "127.0.0.1:18001".parse().unwrap();
             TestRunner::with_ebpf(program)
-                .run(|| {
+                .run_sandboxed(|| {
                     let _listener = UdpSocket::bind(bind_addr).unwrap();
                 })
                 .await
@@ -441,9 +1251,9 @@ pub mod test_suite {
 
     async fn run_connect_test(dest: &str) -> TestReport {
         let dest: SocketAddr = dest.parse().unwrap();
-        let _listener = TcpListener::bind(dest).unwrap();
         TestRunner::with_ebpf(program)
-            .run(|| {
+            .run_sandboxed(|| {
+                let _listener = TcpListener::bind(dest).unwrap();
                 TcpStream::connect(dest).unwrap();
             })
             .await
@@ -459,9 +1269,9 @@ pub mod test_suite {
         TestCase::new("connect_udp", async {
             let bind_addr1: SocketAddr = "127.0.0.1:18021".parse().unwrap();
             let bind_addr2: SocketAddr = "127.0.0.1:18022".parse().unwrap();
-            let _listener = UdpSocket::bind(bind_addr1).unwrap();
             TestRunner::with_ebpf(program)
-                .run(|| {
+                .run_sandboxed(|| {
+                    let _listener = UdpSocket::bind(bind_addr1).unwrap();
                     UdpSocket::bind(bind_addr2)
                         .unwrap()
                         .connect(bind_addr1)
@@ -489,7 +1299,7 @@ pub mod test_suite {
         // This is identical to the bind test
         let bind_addr: SocketAddr = bind_addr.parse().unwrap();
         TestRunner::with_ebpf(program)
-            .run(|| {
+            .run_sandboxed(|| {
                 let _listener = TcpListener::bind(bind_addr).unwrap();
             })
             .await
@@ -512,7 +1322,7 @@ pub mod test_suite {
         let dest: SocketAddr = dest.parse().unwrap();
         let mut source = dest;
         TestRunner::with_ebpf(program)
-            .run(|| {
+            .run_sandboxed(|| {
                 let listener = TcpListener::bind(dest).unwrap();
                 let handle =
                     std::thread::spawn(move || TcpStream::connect(dest).unwrap().local_addr());
@@ -523,11 +1333,64 @@ pub mod test_suite {
             .expect_event(event_check!(
                 NetworkEvent::Accept,
                 (src, source.into(), "source address"),
-                (dst, dest.into(), "destination address")
+                (dst, dest.into(), "destination address"),
+                (reuseport_group, 0u32, "reuseport group")
             ))
             .report()
     }
 
+    fn accept_reuseport() -> TestCase {
+        TestCase::new("accept_reuseport", async {
+            let dest: SocketAddrV4 = "127.0.0.1:18120".parse().unwrap();
+            let result = TestRunner::with_ebpf(program)
+                .run_sandboxed(move || {
+                    // Like nginx with `reuseport`, every worker has its own socket
+                    let listener = reuseport_listener(dest);
+                    let other_worker = reuseport_listener(dest);
+                    listener.set_nonblocking(true).unwrap();
+                    other_worker.set_nonblocking(true).unwrap();
+                    let _stream = TcpStream::connect(dest).unwrap();
+                    // The connection goes to one of the two sockets
+                    while listener.accept().is_err() && other_worker.accept().is_err() {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                })
+                .await;
+            let mut checks = event_check!(
+                NetworkEvent::Accept,
+                (dst, SocketAddr::V4(dest).into(), "destination address")
+            );
+            checks.push(Check::new("reuseport group", |event: &BpfEvent<_>| {
+                let found = match event.payload {
+                    NetworkEvent::Accept {
+                        reuseport_group, ..
+                    } => reuseport_group,
+                    _ => 0,
+                };
+                CheckResult {
+                    success: found != 0,
+                    found: found.to_string(),
+                    expected: "a group id".to_string(),
+                }
+            }));
+            result.expect_event(checks).report()
+        })
+    }
+
+    fn reuseport_listener(addr: SocketAddrV4) -> TcpListener {
+        let fd = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        setsockopt(fd, sockopt::ReusePort, &true).unwrap();
+        bind(fd, &SockaddrIn::from(addr)).unwrap();
+        listen(fd, 16).unwrap();
+        unsafe { TcpListener::from_raw_fd(fd) }
+    }
+
     fn udp_ipv4_sendmsg_recvmsg() -> TestCase {
         TestCase::new(
             "udp_ipv4_sendmsg_recvmsg",
@@ -564,12 +1427,10 @@ pub mod test_suite {
         // for TCP it's overriden on connection
         let mut source = dest;
         let msg = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let data_copied = match proto {
-            Proto::TCP => Vec::new(),
-            Proto::UDP => msg.to_vec(),
-        };
+        // The first message of TCP streams is copied as well
+        let data_copied = msg.to_vec();
         TestRunner::with_ebpf(program)
-            .run(|| match proto {
+            .run_sandboxed(|| match proto {
                 Proto::UDP => {
                     source.set_port(dest.port() + 1);
                     let receiver = UdpSocket::bind(dest).unwrap();
@@ -595,23 +1456,170 @@ pub mod test_suite {
                 }
             })
             .await
-            .expect_event(event_check!(
-                NetworkEvent::Send,
-                (dst, dest.into(), "destination address"),
-                (src, source.into(), "source address"),
-                (data, data_copied.clone(), "data copy"),
-                (data_len, msg.len() as u32, "real message len"),
-                (proto, proto, "protocol")
-            ))
-            .expect_event(event_check!(
-                NetworkEvent::Receive,
-                (dst, source.into(), "destination address"),
-                (src, dest.into(), "source address"),
-                (data, data_copied.clone(), "data copy"),
-                (data_len, msg.len() as u32, "real message len"),
-                (proto, proto, "protocol")
-            ))
+            .expect_sequence(vec![
+                event_check!(
+                    NetworkEvent::Send,
+                    (dst, dest.into(), "destination address"),
+                    (src, source.into(), "source address"),
+                    (data, data_copied.clone(), "data copy"),
+                    (data_len, msg.len() as u32, "real message len"),
+                    (proto, proto, "protocol"),
+                    (capture, Capture::Complete, "data capture"),
+                    (offset, 0u64, "stream offset")
+                ),
+                event_check!(
+                    NetworkEvent::Receive,
+                    (dst, source.into(), "destination address"),
+                    (src, dest.into(), "source address"),
+                    (data, data_copied.clone(), "data copy"),
+                    (data_len, msg.len() as u32, "real message len"),
+                    (proto, proto, "protocol"),
+                    (capture, Capture::Complete, "data capture"),
+                    (offset, 0u64, "stream offset")
+                ),
+            ])
+            .report()
+    }
+
+    // Only the first MAX_DATA_SIZE bytes of a message are copied.
+    fn udp_sendmsg_truncated() -> TestCase {
+        TestCase::new("udp_sendmsg_truncated", async {
+            let dest: SocketAddr = "127.0.0.1:18130".parse().unwrap();
+            let msg: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+            let data_copied = msg[..4096].to_vec();
+            TestRunner::with_ebpf(program)
+                .run_sandboxed(|| {
+                    let receiver = UdpSocket::bind(dest).unwrap();
+                    let s = UdpSocket::bind("127.0.0.1:18131").unwrap();
+                    s.send_to(&msg, dest).unwrap();
+                    let mut buf = [0; 8192];
+                    assert_eq!(receiver.recv(&mut buf).unwrap(), msg.len());
+                })
+                .await
+                .expect_event(event_check!(
+                    NetworkEvent::Send,
+                    (dst, dest.into(), "destination address"),
+                    (data, data_copied, "data copy"),
+                    (data_len, msg.len() as u32, "real message len"),
+                    (capture, Capture::Truncated, "data capture")
+                ))
+                .report()
+        })
+    }
+
+    // Messages longer than a chunk are copied in several events.
+    fn udp_sendmsg_chunks() -> TestCase {
+        TestCase::new("udp_sendmsg_chunks", async {
+            let dest: SocketAddr = "127.0.0.1:18150".parse().unwrap();
+            let msg: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+            let config = CaptureConfig {
+                size: 16384,
+                first_message_size: 0,
+            };
+            TestRunner::with_ebpf(move |ctx, sender| async move {
+                let mut program = program(ctx, sender).await?;
+                set_capture_config(&mut program, &config)?;
+                Ok(program)
+            })
+            .run_sandboxed(|| {
+                let receiver = UdpSocket::bind(dest).unwrap();
+                let s = UdpSocket::bind("127.0.0.1:18151").unwrap();
+                s.send_to(&msg, dest).unwrap();
+                let mut buf = [0; 16384];
+                assert_eq!(receiver.recv(&mut buf).unwrap(), msg.len());
+            })
+            .await
+            .expect_sequence(
+                [
+                    0..CHUNK_SIZE,
+                    CHUNK_SIZE..2 * CHUNK_SIZE,
+                    2 * CHUNK_SIZE..msg.len(),
+                ]
+                .into_iter()
+                .enumerate()
+                .map(|(index, range)| {
+                    event_check!(
+                        NetworkEvent::Send,
+                        (dst, dest.into(), "destination address"),
+                        (chunk, index as u8, "chunk index"),
+                        (chunks, 3u8, "chunk count"),
+                        (data, msg[range].to_vec(), "data copy"),
+                        (capture, Capture::Complete, "data capture")
+                    )
+                })
+                .collect(),
+            )
             .report()
+        })
+    }
+
+    // Every message on a TCP stream has the offset of its first byte.
+    fn tcp_stream_offset() -> TestCase {
+        TestCase::new("tcp_stream_offset", async {
+            let dest: SocketAddr = "127.0.0.1:18140".parse().unwrap();
+            let msg = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+            TestRunner::with_ebpf(program)
+                .run_sandboxed(|| {
+                    let listener = TcpListener::bind(dest).unwrap();
+                    let t = std::thread::spawn(move || {
+                        let mut client = TcpStream::connect(dest).unwrap();
+                        client.write_all(&msg).unwrap();
+                        client.write_all(&msg).unwrap();
+                    });
+                    let mut connection = listener.accept().unwrap().0;
+                    let mut buf = [0; 512];
+                    let mut received = 0;
+                    while received < 2 * msg.len() {
+                        received += connection.read(&mut buf).unwrap();
+                    }
+                    t.join().unwrap();
+                })
+                .await
+                .expect_sequence(vec![
+                    event_check!(
+                        NetworkEvent::Send,
+                        (dst, dest.into(), "destination address"),
+                        (offset, 0u64, "stream offset")
+                    ),
+                    event_check!(
+                        NetworkEvent::Send,
+                        (dst, dest.into(), "destination address"),
+                        (offset, msg.len() as u64, "stream offset"),
+                        (capture, Capture::Disabled, "data capture")
+                    ),
+                ])
+                .report()
+        })
+    }
+
+    // Zero-length datagrams are valid messages, with nothing to copy.
+    fn udp_empty_message() -> TestCase {
+        TestCase::new("udp_empty_message", async {
+            let dest: SocketAddr = "127.0.0.1:18160".parse().unwrap();
+            TestRunner::with_ebpf(program)
+                .run_sandboxed(|| {
+                    let receiver = UdpSocket::bind(dest).unwrap();
+                    let s = UdpSocket::bind("127.0.0.1:18161").unwrap();
+                    s.send_to(&[], dest).unwrap();
+                    let mut buf = [0; 512];
+                    assert_eq!(receiver.recv(&mut buf).unwrap(), 0);
+                })
+                .await
+                .expect_sequence(vec![
+                    event_check!(
+                        NetworkEvent::Send,
+                        (dst, dest.into(), "destination address"),
+                        (data_len, 0u32, "message len"),
+                        (capture, Capture::Complete, "data capture")
+                    ),
+                    event_check!(
+                        NetworkEvent::Receive,
+                        (data_len, 0u32, "message len"),
+                        (capture, Capture::Complete, "data capture")
+                    ),
+                ])
+                .report()
+        })
     }
 
     fn close_ipv4() -> TestCase {
@@ -626,29 +1634,29 @@ pub mod test_suite {
         let dest: SocketAddr = dest.parse().unwrap();
         let mut source = dest;
         let mut expected_pid = Pid::from_raw(0);
-        let listener = TcpListener::bind(dest).unwrap();
         // The on_tcp_set_state hook may be called by a process different from
-        // the original creator the connection. This happens for example if it
-        // receives a SIGKILL. We test this to make sure we're still emitting
-        // an event with the correct origianl pid.
+        // the original creator the connection, or in softirq context. This
+        // happens for example if it receives a SIGKILL. We test this to make
+        // sure we're still emitting an event from the original pid.
         TestRunner::with_ebpf(program)
-            .run(|| match unsafe { fork() }.unwrap() {
-                ForkResult::Child => {
-                    let _conn = TcpStream::connect(dest).unwrap();
-                }
-                ForkResult::Parent { child } => {
-                    expected_pid = child;
-                    let (_connection, addr) = listener.accept().unwrap();
-                    unsafe { kill(child.as_raw(), 9) };
-                    source = addr;
-                    std::thread::sleep(Duration::from_millis(100));
+            .run_sandboxed(|| {
+                let listener = TcpListener::bind(dest).unwrap();
+                match unsafe { fork() }.unwrap() {
+                    ForkResult::Child => {
+                        let _conn = TcpStream::connect(dest).unwrap();
+                    }
+                    ForkResult::Parent { child } => {
+                        expected_pid = child;
+                        let (_connection, addr) = listener.accept().unwrap();
+                        unsafe { kill(child.as_raw(), 9) };
+                        source = addr;
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
                 }
             })
-            // We use a custom check where we ignore the event pid since
-            // it might be 0 or a ksoftirqd process.
             .await
             .expect_custom_event(
-                None,
+                Some(expected_pid),
                 true,
                 event_check!(
                     NetworkEvent::Close,
@@ -0,0 +1,213 @@
+//! Domains already queried on this host, to flag the DNS questions of domains
+//! contacted for the first time.
+//!
+//! The domains are kept in a file with one normalized name per line, so they
+//! survive restarts of the agent. Names seen since the last [`SeenDomains::flush`]
+//! are appended to it. Domains of an optional list of popular domains, like the
+//! top-1M lists of Tranco or Umbrella, are never reported as first seen, nor
+//! are their subdomains.
+
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use pulsar_core::{domain, pdk::Payload};
+
+pub const DEFAULT_SEEN_DOMAINS_PATH: &str = "/var/lib/pulsar/seen_domains";
+
+/// Domains remembered. Further domains are still reported as first seen, but
+/// not remembered, so that queries of random subdomains can't exhaust the
+/// memory or the disk.
+pub const MAX_SEEN_DOMAINS: usize = 100_000;
+
+#[derive(Debug, Default)]
+pub struct SeenDomains {
+    path: Option<PathBuf>,
+    seen: HashSet<String>,
+    popular: HashSet<String>,
+    pending: Vec<String>,
+}
+
+impl SeenDomains {
+    /// Load the domains seen before from `path`, which is created on the first
+    /// flush if missing, and the popular domains from `popular_path`.
+    pub fn load(path: &Path, popular_path: Option<&Path>) -> Self {
+        let seen = match fs::read_to_string(path) {
+            Ok(body) => parse(&body).take(MAX_SEEN_DOMAINS).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => {
+                log::warn!("Error reading seen domains {}: {err}", path.display());
+                HashSet::new()
+            }
+        };
+        let popular = match popular_path {
+            Some(popular_path) => match fs::read_to_string(popular_path) {
+                Ok(body) => parse(&body).collect(),
+                Err(err) => {
+                    log::warn!(
+                        "Error reading popular domains {}: {err}",
+                        popular_path.display()
+                    );
+                    HashSet::new()
+                }
+            },
+            None => HashSet::new(),
+        };
+        log::debug!(
+            "Loaded {} seen domains and {} popular domains",
+            seen.len(),
+            popular.len()
+        );
+        Self {
+            path: Some(path.to_path_buf()),
+            seen,
+            popular,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether `name`, a normalized domain name, is contacted for the first
+    /// time. It's remembered as seen afterwards.
+    pub fn first_seen(&mut self, name: &str) -> bool {
+        if name.is_empty() || self.seen.contains(name) || self.is_popular(name) {
+            return false;
+        }
+        if self.seen.len() < MAX_SEEN_DOMAINS {
+            self.seen.insert(name.to_string());
+            self.pending.push(name.to_string());
+        }
+        true
+    }
+
+    /// Set `first_seen` on the questions of a `DnsQuery` or `DnsResponse`.
+    /// Other payloads are ignored.
+    pub fn mark(&mut self, payload: &mut Payload) {
+        let (Payload::DnsQuery { questions } | Payload::DnsResponse { questions, .. }) = payload
+        else {
+            return;
+        };
+        for question in questions {
+            question.first_seen = self.first_seen(&question.normalized);
+        }
+    }
+
+    /// Append the domains seen since the last flush to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut body = self.pending.join("\n");
+        body.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(body.as_bytes())?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Whether `name` or one of its parent domains, other than the top level
+    /// domain, is popular.
+    fn is_popular(&self, name: &str) -> bool {
+        let mut suffix = name;
+        while let Some((_, parent)) = suffix.split_once('.') {
+            if self.popular.contains(suffix) {
+                return true;
+            }
+            suffix = parent;
+        }
+        false
+    }
+}
+
+/// Domains of a file with one domain per line, or ranked lists with
+/// `<rank>,<domain>` lines. Empty lines and lines starting with `#` are ignored.
+fn parse(body: &str) -> impl Iterator<Item = String> + '_ {
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.rsplit(',').next())
+        .map(|name| domain::normalize(name.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use pulsar_core::event::DnsQuestion;
+
+    use super::*;
+
+    fn query(names: &[&str]) -> Payload {
+        Payload::DnsQuery {
+            questions: names
+                .iter()
+                .map(|name| DnsQuestion {
+                    name: name.to_string(),
+                    normalized: domain::normalize(name),
+                    dga_score: 0,
+                    first_seen: false,
+                    qtype: "A".to_string(),
+                    qclass: "IN".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn first_seen(payload: &Payload) -> Vec<bool> {
+        match payload {
+            Payload::DnsQuery { questions } => questions.iter().map(|q| q.first_seen).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn first_contact() {
+        let mut seen = SeenDomains {
+            popular: parse("# rank,domain\n1,google.com\n2,Example.ORG\n").collect(),
+            ..Default::default()
+        };
+
+        let mut payload = query(&["new.example.net", "www.google.com", "example.org."]);
+        seen.mark(&mut payload);
+        assert_eq!(first_seen(&payload), [true, false, false]);
+
+        let mut payload = query(&["NEW.example.net", "other.example.net", "com"]);
+        seen.mark(&mut payload);
+        assert_eq!(first_seen(&payload), [false, true, true]);
+        assert!(!seen.first_seen(""));
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = temp_dir().join("pulsar_seen_domains_test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("seen_domains");
+
+        let mut seen = SeenDomains::load(&path, Some(&dir.join("missing")));
+        assert!(seen.first_seen("example.com"));
+        assert!(seen.first_seen("example.org"));
+        seen.flush().unwrap();
+        assert!(seen.first_seen("example.net"));
+        seen.flush().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "example.com\nexample.org\nexample.net\n"
+        );
+
+        let mut seen = SeenDomains::load(&path, None);
+        assert!(!seen.first_seen("example.org"));
+        assert!(seen.first_seen("example.io"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Detection of the stratum mining protocol.
+//!
+//! Stratum is a line based JSON-RPC protocol used by miners to talk with mining
+//! pools. Bitcoin-like pools use `mining.*` methods, while Monero pools, used
+//! by most of the cryptojacking malware, start with a `login` request which
+//! usually contains the name of the miner as `agent`.
+
+use serde_json::Value;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StratumRequest {
+    pub method: String,
+    pub agent: String,
+}
+
+/// Parse the first line of sent data as a stratum request.
+pub fn parse_request(data: &[u8]) -> Option<StratumRequest> {
+    // Avoid parsing data which can't be a JSON-RPC request
+    if data.first() != Some(&b'{') {
+        return None;
+    }
+    let line = data.split(|byte| *byte == b'\n').next()?;
+    let request: Value = serde_json::from_slice(line).ok()?;
+    let method = request.get("method")?.as_str()?;
+    if method != "login" && !method.starts_with("mining.") {
+        return None;
+    }
+    let agent = match method {
+        "login" => request.pointer("/params/agent"),
+        // mining.subscribe has the user agent as first parameter
+        "mining.subscribe" => request.pointer("/params/0"),
+        _ => None,
+    };
+    Some(StratumRequest {
+        method: method.to_string(),
+        agent: agent
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monero_login() {
+        let data = br#"{"id":1,"jsonrpc":"2.0","method":"login","params":{"login":"wallet","pass":"x","agent":"XMRig/6.20.0 (Linux x86_64) libuv/1.44.2 gcc/12.2.0"}}
+"#;
+        assert_eq!(
+            parse_request(data),
+            Some(StratumRequest {
+                method: "login".to_string(),
+                agent: "XMRig/6.20.0 (Linux x86_64) libuv/1.44.2 gcc/12.2.0".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn bitcoin_subscribe() {
+        let data = br#"{"id": 1, "method": "mining.subscribe", "params": ["cgminer/4.10.0"]}
+{"id": 2, "method": "mining.authorize", "params": ["user", "pass"]}
+"#;
+        assert_eq!(
+            parse_request(data),
+            Some(StratumRequest {
+                method: "mining.subscribe".to_string(),
+                agent: "cgminer/4.10.0".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn not_stratum() {
+        assert_eq!(parse_request(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(parse_request(br#"{"method":"eth_call"}"#), None);
+        assert_eq!(parse_request(b"{ truncated"), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! Reassembly, DNS and stratum extraction and conversion of the recorded events
+//! of `fixtures/`, which runs without root privileges or eBPF support.
+
+use std::net::IpAddr;
+
+use bpf_common::{fixtures, program::BpfEvent};
+use network_monitor::{
+    capture::{Message, Reassembler},
+    dns::DnsParser,
+    pulsar::{collect_dns_if_any, collect_stratum_if_any},
+    NetworkEvent,
+};
+use pulsar_core::{
+    event::DataCapture,
+    pdk::{IntoPayload, Payload},
+};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/events.fixture");
+
+/// Messages of the fixture, with the chunks of their data joined.
+fn messages() -> Vec<Message<BpfEvent<NetworkEvent>>> {
+    let mut reassembler = Reassembler::default();
+    fixtures::load::<NetworkEvent>(FIXTURE)
+        .unwrap()
+        .into_iter()
+        .filter_map(|event| reassembler.push_event(event))
+        .collect()
+}
+
+#[test]
+fn dns() {
+    let messages = messages();
+    let mut parser = DnsParser::default();
+    let Some(Payload::DnsQuery { questions }) = collect_dns_if_any(&messages[0], &mut parser)
+    else {
+        panic!("no DNS query in {:?}", messages[0]);
+    };
+    assert_eq!(questions.len(), 1);
+    assert_eq!(questions[0].name, "example.com");
+    assert_eq!(questions[0].normalized, "example.com");
+    assert_eq!(questions[0].qtype, "A");
+
+    let Some(Payload::DnsResponse { answers, .. }) = collect_dns_if_any(&messages[1], &mut parser)
+    else {
+        panic!("no DNS response in {:?}", messages[1]);
+    };
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].ttl, 300);
+    assert_eq!(answers[0].data, "A(Record(93.184.216.34))");
+}
+
+#[test]
+fn chunked_message() {
+    let mut messages = messages();
+    assert_eq!(messages.len(), 7);
+    let message = messages.remove(3);
+    assert_eq!(message.data.len(), 5000);
+    assert!(message.data.starts_with(b"POST /upload HTTP/1.1\r\n"));
+    assert!(!message.truncated);
+    assert!(collect_dns_if_any(&message, &mut DnsParser::default()).is_none());
+    assert!(collect_stratum_if_any(&message).is_none());
+
+    let Payload::Send {
+        destination,
+        len,
+        is_tcp,
+        capture,
+        ..
+    } = NetworkEvent::try_into_payload(message.event).unwrap()
+    else {
+        panic!("unexpected payload");
+    };
+    assert_eq!(len, 5000);
+    assert!(is_tcp);
+    assert_eq!(capture, DataCapture::Complete);
+    assert_eq!(destination.port, 80);
+    assert_eq!(
+        destination.ip,
+        "::ffff:93.184.216.34".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[test]
+fn mining_pool() {
+    let mut messages = messages();
+    let Some(Payload::StratumRequest {
+        destination,
+        method,
+        agent,
+    }) = collect_stratum_if_any(&messages[5])
+    else {
+        panic!("no stratum request in {:?}", messages[5]);
+    };
+    assert_eq!(destination.to_string(), "203.0.113.7:3333");
+    assert_eq!(method, "login");
+    assert_eq!(agent, "XMRig/6.21.0");
+
+    let close = messages.remove(6);
+    let Payload::Close { comm, .. } = NetworkEvent::try_into_payload(close.event).unwrap() else {
+        panic!("unexpected payload");
+    };
+    assert_eq!(comm, "xmrig");
+}
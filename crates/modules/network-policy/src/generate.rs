@@ -0,0 +1,558 @@
+//! Policies allowing the traffic of a [`Profile`], in the formats printed by
+//! `pulsar policy generate`.
+//!
+//! - [`rules`]: rules of the rules-engine module raising threats for the
+//!   connections outside the profile, by process.
+//! - [`network_policy`]: Kubernetes `NetworkPolicy` for the pods of the workload.
+//! - [`nftables`]: ruleset dropping the traffic outside the profile on the host.
+//!
+//! Traffic on the loopback interface is always allowed by the firewall
+//! policies, they don't restrict the processes.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    net::IpAddr,
+};
+
+use crate::profile::{Direction, Flow, Profile, Protocol};
+
+const HEADER: &str = "# Generated by `pulsar policy generate` from the network traffic observed\n\
+                      # by the network-policy module, review it before use.\n";
+
+/// Rules raising a threat for every connection of a process to a destination
+/// not seen during the learning period, for every connection accepted on
+/// another port, and for the processes without network traffic in the profile.
+///
+/// The protocol is not checked, and images with spaces or quotes can't be
+/// written in conditions, so they're skipped.
+pub fn rules(profile: &Profile) -> String {
+    let mut egress: BTreeMap<&str, BTreeMap<IpAddr, BTreeSet<u16>>> = BTreeMap::new();
+    let mut ingress: BTreeMap<&str, BTreeSet<u16>> = BTreeMap::new();
+    let mut skipped = BTreeSet::new();
+    for flow in &profile.flows {
+        if flow.image.contains(|c: char| c.is_whitespace() || c == '"') {
+            skipped.insert(flow.image.as_str());
+            continue;
+        }
+        match (flow.direction, flow.address) {
+            (Direction::Egress, Some(address)) => {
+                egress
+                    .entry(&flow.image)
+                    .or_default()
+                    .entry(address)
+                    .or_default()
+                    .insert(flow.port);
+            }
+            (Direction::Ingress, _) if flow.protocol == Protocol::Tcp => {
+                ingress.entry(&flow.image).or_default().insert(flow.port);
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = HEADER.to_string();
+    for image in skipped {
+        writeln!(out, "# Skipped {image}").unwrap();
+    }
+    for (image, destinations) in &egress {
+        let allowed = destinations
+            .iter()
+            .map(|(address, ports)| {
+                format!(
+                    "(payload.destination.ip == \"{address}\" AND payload.destination.port IN [{}])",
+                    join(ports)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        rule(
+            &mut out,
+            &format!("Unexpected connection from {image}"),
+            "Connect",
+            &format!("header.image == \"{image}\" AND NOT ({allowed})"),
+        );
+    }
+    rule(
+        &mut out,
+        "Unexpected process connecting",
+        "Connect",
+        &not_images(egress.keys().copied().collect()),
+    );
+    for (image, ports) in &ingress {
+        rule(
+            &mut out,
+            &format!("Unexpected connection accepted by {image}"),
+            "Accept",
+            &format!(
+                "header.image == \"{image}\" AND NOT payload.destination.port IN [{}]",
+                join(ports)
+            ),
+        );
+    }
+    rule(
+        &mut out,
+        "Unexpected process accepting connections",
+        "Accept",
+        &not_images(ingress.keys().copied().collect()),
+    );
+    out
+}
+
+/// Kubernetes `NetworkPolicy` named `name`, allowing the ingress ports and the
+/// egress destinations of the profile. The pod selector must be filled in.
+pub fn network_policy(profile: &Profile, name: &str) -> String {
+    let (destinations, local_ports) = firewall_flows(profile);
+
+    let mut out = HEADER.to_string();
+    writeln!(out, "apiVersion: networking.k8s.io/v1").unwrap();
+    writeln!(out, "kind: NetworkPolicy").unwrap();
+    writeln!(out, "metadata:\n  name: {name}").unwrap();
+    writeln!(out, "spec:").unwrap();
+    writeln!(out, "  # Select the pods of the workload").unwrap();
+    writeln!(out, "  podSelector: {{}}").unwrap();
+    writeln!(out, "  policyTypes:\n    - Ingress\n    - Egress").unwrap();
+
+    if local_ports.is_empty() {
+        writeln!(out, "  ingress: []").unwrap();
+    } else {
+        writeln!(out, "  ingress:\n    - ports:").unwrap();
+        for (protocol, port) in &local_ports {
+            k8s_port(&mut out, "        ", *protocol, *port);
+        }
+    }
+
+    let mut by_address: BTreeMap<IpAddr, BTreeSet<(Protocol, u16)>> = BTreeMap::new();
+    for ((address, protocol), (ports, _)) in &destinations {
+        let address_ports = by_address.entry(*address).or_default();
+        address_ports.extend(ports.iter().map(|port| (*protocol, *port)));
+    }
+    if by_address.is_empty() {
+        writeln!(out, "  egress: []").unwrap();
+    } else {
+        writeln!(out, "  egress:").unwrap();
+        for (address, ports) in &by_address {
+            let prefix = if address.is_ipv4() { 32 } else { 128 };
+            writeln!(out, "    - to:\n        - ipBlock:").unwrap();
+            writeln!(out, "            cidr: {address}/{prefix}").unwrap();
+            writeln!(out, "      ports:").unwrap();
+            for (protocol, port) in ports {
+                k8s_port(&mut out, "        ", *protocol, *port);
+            }
+        }
+    }
+    out
+}
+
+/// nftables ruleset of the `pulsar_policy` table, dropping the packets of new
+/// connections outside the profile. ICMPv6 is allowed, since IPv6 doesn't work
+/// without neighbor discovery.
+pub fn nftables(profile: &Profile) -> String {
+    let (destinations, local_ports) = firewall_flows(profile);
+
+    let mut out = HEADER.to_string();
+    writeln!(out, "table inet pulsar_policy {{").unwrap();
+    nft_chain_start(&mut out, "input", "iifname");
+    let mut by_protocol: BTreeMap<Protocol, BTreeSet<u16>> = BTreeMap::new();
+    for (protocol, port) in &local_ports {
+        by_protocol.entry(*protocol).or_default().insert(*port);
+    }
+    for (protocol, ports) in &by_protocol {
+        writeln!(
+            out,
+            "\t\t{} dport {} accept",
+            nft_protocol(*protocol),
+            nft_set(ports)
+        )
+        .unwrap();
+    }
+    writeln!(out, "\t}}\n").unwrap();
+
+    nft_chain_start(&mut out, "output", "oifname");
+    for ((address, protocol), (ports, images)) in &destinations {
+        let family = if address.is_ipv4() { "ip" } else { "ip6" };
+        writeln!(out, "\t\t# {}", join(images)).unwrap();
+        writeln!(
+            out,
+            "\t\t{family} daddr {address} {} dport {} accept",
+            nft_protocol(*protocol),
+            nft_set(ports)
+        )
+        .unwrap();
+    }
+    writeln!(out, "\t}}\n}}").unwrap();
+    out
+}
+
+/// Egress ports and processes by destination and protocol, and ingress ports
+/// by protocol, outside the loopback interface.
+#[allow(clippy::type_complexity)]
+fn firewall_flows(
+    profile: &Profile,
+) -> (
+    BTreeMap<(IpAddr, Protocol), (BTreeSet<u16>, BTreeSet<&str>)>,
+    BTreeSet<(Protocol, u16)>,
+) {
+    let mut destinations: BTreeMap<_, (BTreeSet<_>, BTreeSet<_>)> = BTreeMap::new();
+    let mut local_ports = BTreeSet::new();
+    for Flow {
+        direction,
+        protocol,
+        address,
+        port,
+        image,
+    } in &profile.flows
+    {
+        match (direction, address) {
+            (Direction::Egress, Some(address)) if !address.is_loopback() => {
+                let (ports, images) = destinations.entry((*address, *protocol)).or_default();
+                ports.insert(*port);
+                images.insert(image.as_str());
+            }
+            (Direction::Ingress, _) => {
+                local_ports.insert((*protocol, *port));
+            }
+            _ => {}
+        }
+    }
+    (destinations, local_ports)
+}
+
+fn rule(out: &mut String, name: &str, event_type: &str, condition: &str) {
+    writeln!(out, "\n- name: {}", yaml_quote(name)).unwrap();
+    writeln!(out, "  type: {event_type}").unwrap();
+    writeln!(out, "  condition: {}", yaml_quote(condition)).unwrap();
+}
+
+/// Condition matching the processes other than `images`.
+fn not_images(images: Vec<&str>) -> String {
+    if images.is_empty() {
+        // Every process
+        return "header.pid > 0".to_string();
+    }
+    let images = images
+        .iter()
+        .map(|image| format!("\"{image}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("NOT header.image IN [{images}]")
+}
+
+fn yaml_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn join<T: ToString>(values: impl IntoIterator<Item = T>) -> String {
+    values
+        .into_iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn k8s_port(out: &mut String, indent: &str, protocol: Protocol, port: u16) {
+    let protocol = match protocol {
+        Protocol::Tcp => "TCP",
+        Protocol::Udp => "UDP",
+    };
+    writeln!(
+        out,
+        "{indent}- protocol: {protocol}\n{indent}  port: {port}"
+    )
+    .unwrap();
+}
+
+fn nft_chain_start(out: &mut String, hook: &str, interface: &str) {
+    writeln!(out, "\tchain {hook} {{").unwrap();
+    writeln!(
+        out,
+        "\t\ttype filter hook {hook} priority filter; policy drop;"
+    )
+    .unwrap();
+    writeln!(out, "\t\tct state established,related accept").unwrap();
+    writeln!(out, "\t\t{interface} \"lo\" accept").unwrap();
+    writeln!(out, "\t\tmeta l4proto ipv6-icmp accept").unwrap();
+}
+
+fn nft_protocol(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+    }
+}
+
+fn nft_set(ports: &BTreeSet<u16>) -> String {
+    if ports.len() == 1 {
+        join(ports)
+    } else {
+        format!("{{ {} }}", join(ports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use pulsar_core::{
+        event::{Header, Host},
+        pdk::{Event, Payload},
+    };
+    use rules_engine::{CustomPayloads, RuleEngine, RuleFormat};
+
+    use super::*;
+
+    fn flow(
+        direction: Direction,
+        protocol: Protocol,
+        address: &str,
+        port: u16,
+        image: &str,
+    ) -> Flow {
+        Flow {
+            direction,
+            protocol,
+            address: address.parse().ok(),
+            port,
+            image: image.to_string(),
+        }
+    }
+
+    fn profile() -> Profile {
+        let mut profile = Profile::new(UNIX_EPOCH);
+        profile.flows.extend([
+            flow(
+                Direction::Egress,
+                Protocol::Tcp,
+                "93.184.216.34",
+                443,
+                "/usr/bin/curl",
+            ),
+            flow(
+                Direction::Egress,
+                Protocol::Tcp,
+                "93.184.216.34",
+                80,
+                "/usr/bin/curl",
+            ),
+            flow(
+                Direction::Egress,
+                Protocol::Udp,
+                "1.1.1.1",
+                53,
+                "/usr/bin/curl",
+            ),
+            flow(
+                Direction::Egress,
+                Protocol::Udp,
+                "1.1.1.1",
+                53,
+                "/usr/bin/dig",
+            ),
+            flow(
+                Direction::Egress,
+                Protocol::Tcp,
+                "2606:4700::1",
+                443,
+                "/usr/bin/curl",
+            ),
+            flow(
+                Direction::Egress,
+                Protocol::Tcp,
+                "127.0.0.1",
+                5432,
+                "/usr/bin/app",
+            ),
+            flow(Direction::Ingress, Protocol::Tcp, "", 80, "/usr/sbin/nginx"),
+            flow(
+                Direction::Ingress,
+                Protocol::Tcp,
+                "",
+                443,
+                "/usr/sbin/nginx",
+            ),
+            flow(Direction::Ingress, Protocol::Udp, "", 123, "/usr/sbin/ntpd"),
+            flow(
+                Direction::Ingress,
+                Protocol::Tcp,
+                "",
+                22,
+                "/opt/my app/sshd",
+            ),
+        ]);
+        profile
+    }
+
+    fn event(image: &str, payload: Payload) -> Event {
+        Event::new(
+            Header {
+                image: image.to_string(),
+                pid: 42,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    fn host(address: &str) -> Host {
+        address.parse::<std::net::SocketAddr>().unwrap().into()
+    }
+
+    fn matches(engine: &RuleEngine, event: &Event) -> Vec<String> {
+        engine
+            .process(event)
+            .iter()
+            .map(|rule| rule.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn generated_rules() {
+        let body = rules(&profile());
+        assert!(body.contains("# Skipped /opt/my app/sshd\n"));
+        let engine =
+            RuleEngine::from_str(&body, RuleFormat::Yaml, &CustomPayloads::default()).unwrap();
+
+        let connect = |image, address| {
+            event(
+                image,
+                Payload::Connect {
+                    destination: host(address),
+                    is_tcp: true,
+                },
+            )
+        };
+        assert!(matches(&engine, &connect("/usr/bin/curl", "93.184.216.34:80")).is_empty());
+        assert!(matches(&engine, &connect("/usr/bin/curl", "[2606:4700::1]:443")).is_empty());
+        assert!(matches(&engine, &connect("/usr/bin/app", "127.0.0.1:5432")).is_empty());
+        assert_eq!(
+            matches(&engine, &connect("/usr/bin/curl", "93.184.216.34:22")),
+            ["Unexpected connection from /usr/bin/curl"]
+        );
+        assert_eq!(
+            matches(&engine, &connect("/usr/bin/dig", "93.184.216.34:80")),
+            ["Unexpected connection from /usr/bin/dig"]
+        );
+        assert_eq!(
+            matches(&engine, &connect("/tmp/x", "1.1.1.1:53")),
+            ["Unexpected process connecting"]
+        );
+
+        let accept = |image, address| {
+            event(
+                image,
+                Payload::Accept {
+                    source: host("10.0.0.1:40000"),
+                    destination: host(address),
+                    reuseport_group: 0,
+                },
+            )
+        };
+        assert!(matches(&engine, &accept("/usr/sbin/nginx", "10.0.0.2:443")).is_empty());
+        assert_eq!(
+            matches(&engine, &accept("/usr/sbin/nginx", "10.0.0.2:8080")),
+            ["Unexpected connection accepted by /usr/sbin/nginx"]
+        );
+        assert_eq!(
+            matches(&engine, &accept("/usr/sbin/ntpd", "10.0.0.2:123")),
+            ["Unexpected process accepting connections"]
+        );
+
+        // Any traffic is unexpected without a profile
+        let engine = RuleEngine::from_str(
+            &rules(&Profile::new(UNIX_EPOCH)),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            matches(&engine, &connect("/usr/bin/curl", "93.184.216.34:80")),
+            ["Unexpected process connecting"]
+        );
+    }
+
+    #[test]
+    fn generated_network_policy() {
+        let body = network_policy(&profile(), "web");
+        assert_eq!(
+            body.strip_prefix(HEADER).unwrap(),
+            "apiVersion: networking.k8s.io/v1
+kind: NetworkPolicy
+metadata:
+  name: web
+spec:
+  # Select the pods of the workload
+  podSelector: {}
+  policyTypes:
+    - Ingress
+    - Egress
+  ingress:
+    - ports:
+        - protocol: TCP
+          port: 22
+        - protocol: TCP
+          port: 80
+        - protocol: TCP
+          port: 443
+        - protocol: UDP
+          port: 123
+  egress:
+    - to:
+        - ipBlock:
+            cidr: 1.1.1.1/32
+      ports:
+        - protocol: UDP
+          port: 53
+    - to:
+        - ipBlock:
+            cidr: 93.184.216.34/32
+      ports:
+        - protocol: TCP
+          port: 80
+        - protocol: TCP
+          port: 443
+    - to:
+        - ipBlock:
+            cidr: 2606:4700::1/128
+      ports:
+        - protocol: TCP
+          port: 443
+"
+        );
+
+        let body = network_policy(&Profile::new(UNIX_EPOCH), "web");
+        assert!(body.ends_with("  ingress: []\n  egress: []\n"));
+    }
+
+    #[test]
+    fn generated_nftables() {
+        let body = nftables(&profile());
+        assert_eq!(
+            body.strip_prefix(HEADER).unwrap(),
+            "table inet pulsar_policy {
+\tchain input {
+\t\ttype filter hook input priority filter; policy drop;
+\t\tct state established,related accept
+\t\tiifname \"lo\" accept
+\t\tmeta l4proto ipv6-icmp accept
+\t\ttcp dport { 22, 80, 443 } accept
+\t\tudp dport 123 accept
+\t}
+
+\tchain output {
+\t\ttype filter hook output priority filter; policy drop;
+\t\tct state established,related accept
+\t\toifname \"lo\" accept
+\t\tmeta l4proto ipv6-icmp accept
+\t\t# /usr/bin/curl, /usr/bin/dig
+\t\tip daddr 1.1.1.1 udp dport 53 accept
+\t\t# /usr/bin/curl
+\t\tip daddr 93.184.216.34 tcp dport { 80, 443 } accept
+\t\t# /usr/bin/curl
+\t\tip6 daddr 2606:4700::1 tcp dport 443 accept
+\t}
+}
+"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::pdk::{
+    CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+    ModuleContext, ModuleError, PulsarModule, ShutdownSignal, Version,
+};
+
+pub mod generate;
+pub mod profile;
+
+use profile::{Profile, ProfileError, DEFAULT_PROFILE_PATH};
+
+const MODULE_NAME: &str = "network-policy";
+const DEFAULT_LEARNING_DAYS: u64 = 7;
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        network_policy_task,
+    )
+    // Network events are produced by network-monitor
+    .depends_on("network-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "profile_path",
+                    ConfigKind::Path,
+                    "File with the network traffic learned, from which the policies are generated",
+                )
+                .default_value(DEFAULT_PROFILE_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "learning_days",
+                    ConfigKind::Integer,
+                    "Days of network traffic learned, from the first start of the module",
+                )
+                .default_value(DEFAULT_LEARNING_DAYS)
+                .range(1, 3650),
+            ),
+    )
+}
+
+async fn network_policy_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let mut learner = Learner::new(rx_config.read()?)?;
+    let mut save_interval = tokio::time::interval(SAVE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => {
+                learner.save();
+                return r;
+            }
+            _ = rx_config.changed() => {
+                learner.save();
+                learner = Learner::new(rx_config.read()?)?;
+            }
+            _ = save_interval.tick() => learner.save(),
+            event = receiver.recv() => learner.record(&*event?),
+        }
+    }
+}
+
+/// Profile being learned, saved periodically until the end of the learning
+/// period.
+struct Learner {
+    config: Config,
+    profile: Profile,
+    changed: bool,
+    complete: bool,
+}
+
+impl Learner {
+    fn new(config: Config) -> Result<Self, ProfileError> {
+        // New profiles are saved right away to remember the start of learning
+        let (profile, changed) = match Profile::load(&config.profile_path)? {
+            Some(profile) => (profile, false),
+            None => {
+                log::info!(
+                    "Learning the network traffic for {} days",
+                    config.learning_days
+                );
+                (Profile::new(SystemTime::now()), true)
+            }
+        };
+        let mut learner = Self {
+            config,
+            profile,
+            changed,
+            complete: false,
+        };
+        learner.check_complete();
+        Ok(learner)
+    }
+
+    fn record(&mut self, event: &Event) {
+        // Threats are copies of events already recorded
+        if self.complete || event.header().threat.is_some() {
+            return;
+        }
+        self.changed |= self.profile.record(event);
+    }
+
+    fn save(&mut self) {
+        if self.changed {
+            match self.profile.save(&self.config.profile_path) {
+                Ok(()) => self.changed = false,
+                Err(err) => log::warn!("{err}"),
+            }
+        }
+        self.check_complete();
+    }
+
+    fn check_complete(&mut self) {
+        if self.complete {
+            return;
+        }
+        let duration = Duration::from_secs(self.config.learning_days * 86400);
+        if self.profile.is_complete(duration, SystemTime::now()) {
+            self.complete = true;
+            log::info!(
+                "Network traffic learned: {} flows in {}, generate the policy with `pulsar policy generate`",
+                self.profile.flows.len(),
+                self.config.profile_path.display()
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    profile_path: PathBuf,
+    learning_days: u64,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            profile_path: config
+                .with_default("profile_path", PathBuf::from(DEFAULT_PROFILE_PATH))?,
+            learning_days: config.with_default("learning_days", DEFAULT_LEARNING_DAYS)?,
+        })
+    }
+}
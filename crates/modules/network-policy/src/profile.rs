@@ -0,0 +1,367 @@
+//! Network traffic of the processes observed during the learning period, from
+//! which the policies are generated.
+//!
+//! Connections are recorded as egress flows to their destination address and
+//! port, while accepted connections and listening sockets are recorded as
+//! ingress flows to their local port, allowed from any source. The profile is
+//! saved as JSON, so that learning survives restarts of the agent.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{
+    event::Host,
+    pdk::{Event, Payload},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const DEFAULT_PROFILE_PATH: &str = "/var/lib/pulsar/network_profile.json";
+
+/// Flows kept in the profile, so that a process scanning the network can't
+/// exhaust the memory.
+pub const MAX_FLOWS: usize = 10_000;
+
+/// Describes an error loading or saving the profile.
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("error reading profile {path}")]
+    Loading {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+    #[error("invalid profile {path}")]
+    Invalid {
+        path: String,
+        #[source]
+        error: serde_json::Error,
+    },
+    #[error("error writing profile {path}")]
+    Saving {
+        path: String,
+        #[source]
+        error: io::Error,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Egress,
+    Ingress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn new(is_tcp: bool) -> Self {
+        if is_tcp {
+            Self::Tcp
+        } else {
+            Self::Udp
+        }
+    }
+}
+
+/// Traffic of a process observed during the learning period.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Flow {
+    pub direction: Direction,
+    pub protocol: Protocol,
+    /// Destination of egress flows, `None` for ingress flows
+    pub address: Option<IpAddr>,
+    /// Destination port of egress flows, local port of ingress flows
+    pub port: u16,
+    pub image: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Start of the learning period
+    pub started: SystemTime,
+    pub flows: BTreeSet<Flow>,
+}
+
+impl Profile {
+    pub fn new(started: SystemTime) -> Self {
+        Self {
+            started,
+            flows: BTreeSet::new(),
+        }
+    }
+
+    /// Load the profile saved at `path`, `None` if learning didn't start yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, ProfileError> {
+        let body = match fs::read_to_string(path) {
+            Ok(body) => body,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(ProfileError::Loading {
+                    path: path.display().to_string(),
+                    error,
+                })
+            }
+        };
+        serde_json::from_str(&body)
+            .map(Some)
+            .map_err(|error| ProfileError::Invalid {
+                path: path.display().to_string(),
+                error,
+            })
+    }
+
+    /// Save the profile to `path`, replacing the previous one atomically.
+    pub fn save(&self, path: &Path) -> Result<(), ProfileError> {
+        let saving = |error| ProfileError::Saving {
+            path: path.display().to_string(),
+            error,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(saving)?;
+        }
+        let body = serde_json::to_vec_pretty(self)
+            .map_err(io::Error::from)
+            .map_err(saving)?;
+        let tmp = path.with_extension("tmp");
+        fs::File::create(&tmp)
+            .and_then(|mut file| file.write_all(&body))
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(saving)
+    }
+
+    /// Whether the learning period of `duration` is over at `now`.
+    pub fn is_complete(&self, duration: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.started).unwrap_or_default() >= duration
+    }
+
+    /// Add the flow of a network event, returning whether the profile changed.
+    /// Other events are ignored.
+    pub fn record(&mut self, event: &Event) -> bool {
+        let image = &event.header().image;
+        let flow = match event.payload() {
+            Payload::Connect {
+                destination,
+                is_tcp,
+            } => Some(Flow {
+                direction: Direction::Egress,
+                protocol: Protocol::new(*is_tcp),
+                address: Some(unmapped(destination)),
+                port: destination.port,
+                image: image.clone(),
+            }),
+            Payload::Accept { destination, .. } => ingress(Protocol::Tcp, destination, image),
+            Payload::Listen { address } => ingress(Protocol::Tcp, address, image),
+            // Sockets bound to port 0 only send datagrams
+            Payload::Bind {
+                address,
+                is_tcp: false,
+            } if address.port != 0 => ingress(Protocol::Udp, address, image),
+            _ => None,
+        };
+        let Some(flow) = flow else {
+            return false;
+        };
+        if self.flows.len() >= MAX_FLOWS {
+            return false;
+        }
+        self.flows.insert(flow)
+    }
+}
+
+/// Ingress flow to a local address, `None` when it can't be reached from
+/// other hosts, like sockets listening on the loopback interface.
+fn ingress(protocol: Protocol, address: &Host, image: &str) -> Option<Flow> {
+    (!unmapped(address).is_loopback()).then(|| Flow {
+        direction: Direction::Ingress,
+        protocol,
+        address: None,
+        port: address.port,
+        image: image.to_string(),
+    })
+}
+
+/// Address of a host, with IPv4-mapped IPv6 addresses converted to IPv4.
+fn unmapped(host: &Host) -> IpAddr {
+    let mut host = host.clone();
+    host.unmap_ipv4();
+    host.ip
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, time::UNIX_EPOCH};
+
+    use pulsar_core::event::Header;
+
+    use super::*;
+
+    fn event(image: &str, payload: Payload) -> Event {
+        Event::new(
+            Header {
+                image: image.to_string(),
+                pid: 42,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    fn host(address: &str) -> Host {
+        address.parse::<std::net::SocketAddr>().unwrap().into()
+    }
+
+    #[test]
+    fn learning() {
+        let mut profile = Profile::new(UNIX_EPOCH);
+        let connect = |address, is_tcp| Payload::Connect {
+            destination: host(address),
+            is_tcp,
+        };
+        assert!(profile.record(&event("/usr/bin/curl", connect("93.184.216.34:443", true))));
+        assert!(!profile.record(&event("/usr/bin/curl", connect("93.184.216.34:443", true))));
+        assert!(profile.record(&event(
+            "/usr/bin/curl",
+            connect("[::ffff:93.184.216.34]:80", true)
+        )));
+        assert!(profile.record(&event(
+            "/usr/lib/systemd/systemd-resolved",
+            connect("1.1.1.1:53", false)
+        )));
+        assert!(profile.record(&event(
+            "/usr/sbin/nginx",
+            Payload::Listen {
+                address: host("0.0.0.0:80"),
+            }
+        )));
+        assert!(!profile.record(&event(
+            "/usr/sbin/nginx",
+            Payload::Accept {
+                source: host("10.0.0.1:53422"),
+                destination: host("10.0.0.2:80"),
+                reuseport_group: 0,
+            }
+        )));
+        // Reachable only from the host
+        assert!(!profile.record(&event(
+            "/usr/sbin/nginx",
+            Payload::Listen {
+                address: host("127.0.0.1:8080"),
+            }
+        )));
+        assert!(profile.record(&event(
+            "/usr/sbin/ntpd",
+            Payload::Bind {
+                address: host("[::]:123"),
+                is_tcp: false,
+            }
+        )));
+        assert!(!profile.record(&event(
+            "/usr/bin/dig",
+            Payload::Bind {
+                address: host("0.0.0.0:0"),
+                is_tcp: false,
+            }
+        )));
+        assert!(!profile.record(&event("/usr/bin/true", Payload::Exit { exit_code: 0 })));
+
+        let flows: Vec<_> = profile
+            .flows
+            .iter()
+            .map(|flow| {
+                (
+                    flow.direction,
+                    flow.protocol,
+                    flow.address.map(|address| address.to_string()),
+                    flow.port,
+                    flow.image.as_str(),
+                )
+            })
+            .collect();
+        let address = |address: &str| Some(address.to_string());
+        assert_eq!(
+            flows,
+            [
+                (
+                    Direction::Egress,
+                    Protocol::Tcp,
+                    address("93.184.216.34"),
+                    80,
+                    "/usr/bin/curl"
+                ),
+                (
+                    Direction::Egress,
+                    Protocol::Tcp,
+                    address("93.184.216.34"),
+                    443,
+                    "/usr/bin/curl"
+                ),
+                (
+                    Direction::Egress,
+                    Protocol::Udp,
+                    address("1.1.1.1"),
+                    53,
+                    "/usr/lib/systemd/systemd-resolved"
+                ),
+                (
+                    Direction::Ingress,
+                    Protocol::Tcp,
+                    None,
+                    80,
+                    "/usr/sbin/nginx"
+                ),
+                (
+                    Direction::Ingress,
+                    Protocol::Udp,
+                    None,
+                    123,
+                    "/usr/sbin/ntpd"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = temp_dir().join("pulsar_network_profile_test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("network_profile.json");
+        assert!(Profile::load(&path).unwrap().is_none());
+
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut profile = Profile::new(started);
+        profile.record(&event(
+            "/usr/sbin/nginx",
+            Payload::Listen {
+                address: host("0.0.0.0:443"),
+            },
+        ));
+        profile.save(&path).unwrap();
+        assert_eq!(Profile::load(&path).unwrap(), Some(profile.clone()));
+
+        let day = Duration::from_secs(86400);
+        assert!(!profile.is_complete(7 * day, started + 6 * day));
+        assert!(profile.is_complete(7 * day, started + 7 * day));
+        // Clock moved backwards
+        assert!(!profile.is_complete(7 * day, UNIX_EPOCH));
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            Profile::load(&path),
+            Err(ProfileError::Invalid { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
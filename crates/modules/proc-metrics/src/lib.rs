@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use bpf_common::{time::Timestamp, Pid};
+use nix::unistd::{sysconf, SysconfVar};
+use pulsar_core::pdk::{
+    CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+    ModuleError, ModuleSender, Payload, PulsarModule, ShutdownSignal, Version,
+};
+use sampler::{read_processes, Metrics, Sampler};
+use tokio::time::Instant;
+
+mod sampler;
+
+const MODULE_NAME: &str = "proc-metrics";
+const DEFAULT_INTERVAL: u64 = 10;
+const DEFAULT_SUSTAINED_SAMPLES: u64 = 6;
+const DEFAULT_MIN_CPU_USAGE: u64 = 1;
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        proc_metrics_task,
+    )
+    // Events are enriched by the process tracker, fed by process-monitor
+    .depends_on("process-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new("interval", ConfigKind::Integer, "Seconds between samples")
+                    .default_value(DEFAULT_INTERVAL)
+                    .range(1, 3600),
+            )
+            .field(
+                ConfigField::new(
+                    "sustained_samples",
+                    ConfigKind::Integer,
+                    "Number of samples considered by the sustained CPU usage",
+                )
+                .default_value(DEFAULT_SUSTAINED_SAMPLES)
+                .range(1, 1000),
+            )
+            .field(
+                ConfigField::new(
+                    "min_cpu_usage",
+                    ConfigKind::Integer,
+                    "Processes using a lower percent of CPU are not reported, 0 to report all",
+                )
+                .default_value(DEFAULT_MIN_CPU_USAGE)
+                .range(0, i64::MAX),
+            ),
+    )
+}
+
+async fn proc_metrics_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let sender = ctx.get_sender();
+    let mut rx_config = ctx.get_config();
+    let mut config: Config = rx_config.read()?;
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)?.unwrap_or(4096) as u64;
+    let ticks_per_second = sysconf(SysconfVar::CLK_TCK)?.unwrap_or(100) as u64;
+    let mut sampler = Sampler::new(ticks_per_second, config.sustained_samples);
+    let mut interval = sample_timer(&config);
+    let mut last_sample = Instant::now();
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                config = rx_config.read()?;
+                sampler = Sampler::new(ticks_per_second, config.sustained_samples);
+                interval = sample_timer(&config);
+            }
+            now = interval.tick() => {
+                let readings = tokio::task::spawn_blocking(move || read_processes(page_size))
+                    .await?;
+                let elapsed = now.duration_since(last_sample);
+                last_sample = now;
+                for (pid, metrics) in sampler.sample(readings, elapsed) {
+                    if metrics.cpu_usage >= config.min_cpu_usage {
+                        send_metrics(&sender, pid, metrics);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn sample_timer(config: &Config) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
+fn send_metrics(sender: &ModuleSender, pid: i32, metrics: Metrics) {
+    let Metrics {
+        cpu_usage,
+        cpu_usage_sustained,
+        memory_rss,
+        memory_virtual,
+        io_read,
+        io_write,
+    } = metrics;
+    sender.send(
+        Pid::from_raw(pid),
+        Timestamp::now(),
+        Payload::ProcessMetrics {
+            cpu_usage,
+            cpu_usage_sustained,
+            memory_rss,
+            memory_virtual,
+            io_read,
+            io_write,
+        },
+    );
+}
+
+#[derive(Clone)]
+struct Config {
+    interval: u64,
+    sustained_samples: usize,
+    min_cpu_usage: u32,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            interval: config.with_default("interval", DEFAULT_INTERVAL)?,
+            sustained_samples: config
+                .with_default("sustained_samples", DEFAULT_SUSTAINED_SAMPLES as usize)?,
+            min_cpu_usage: config.with_default("min_cpu_usage", DEFAULT_MIN_CPU_USAGE as u32)?,
+        })
+    }
+}
@@ -0,0 +1,246 @@
+//! Sampling of the resource usage of processes from procfs.
+//!
+//! Every round reads the cumulative counters of `/proc/<pid>/stat` and
+//! `/proc/<pid>/io`, which are turned into usage over the sampling interval by
+//! comparing them with the previous round.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    time::Duration,
+};
+
+/// Flag of kernel threads in `/proc/<pid>/stat`
+const PF_KTHREAD: u64 = 0x00200000;
+
+/// Cumulative counters of a process, as read from procfs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reading {
+    /// Start time of the process, used to detect reused pids
+    pub start_time: u64,
+    /// User and system CPU time, in clock ticks
+    pub cpu_ticks: u64,
+    pub memory_rss: u64,
+    pub memory_virtual: u64,
+    pub io_read: u64,
+    pub io_write: u64,
+}
+
+/// Resource usage of a process over the last sampling interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    pub cpu_usage: u32,
+    pub cpu_usage_sustained: u32,
+    pub memory_rss: u64,
+    pub memory_virtual: u64,
+    pub io_read: u64,
+    pub io_write: u64,
+}
+
+struct History {
+    last: Reading,
+    cpu_window: VecDeque<u32>,
+}
+
+pub struct Sampler {
+    ticks_per_second: u64,
+    sustained_samples: usize,
+    processes: HashMap<i32, History>,
+}
+
+impl Sampler {
+    /// `sustained_samples` is the number of consecutive samples considered by
+    /// [`Metrics::cpu_usage_sustained`].
+    pub fn new(ticks_per_second: u64, sustained_samples: usize) -> Self {
+        Self {
+            ticks_per_second,
+            sustained_samples: sustained_samples.max(1),
+            processes: HashMap::new(),
+        }
+    }
+
+    /// Compute the metrics of a round of readings, taken `elapsed` after the
+    /// previous one. Processes seen for the first time have no metrics yet,
+    /// processes missing from the readings are forgotten.
+    pub fn sample(
+        &mut self,
+        readings: Vec<(i32, Reading)>,
+        elapsed: Duration,
+    ) -> Vec<(i32, Metrics)> {
+        let mut previous = std::mem::take(&mut self.processes);
+        let mut metrics = Vec::new();
+        for (pid, reading) in readings {
+            let history = match previous.remove(&pid) {
+                Some(mut history) if history.last.start_time == reading.start_time => {
+                    let cpu_usage = self.cpu_usage(&history.last, &reading, elapsed);
+                    if history.cpu_window.len() == self.sustained_samples {
+                        history.cpu_window.pop_front();
+                    }
+                    history.cpu_window.push_back(cpu_usage);
+                    let cpu_usage_sustained = if history.cpu_window.len() == self.sustained_samples
+                    {
+                        history.cpu_window.iter().copied().min().unwrap_or_default()
+                    } else {
+                        0
+                    };
+                    metrics.push((
+                        pid,
+                        Metrics {
+                            cpu_usage,
+                            cpu_usage_sustained,
+                            memory_rss: reading.memory_rss,
+                            memory_virtual: reading.memory_virtual,
+                            io_read: reading.io_read.saturating_sub(history.last.io_read),
+                            io_write: reading.io_write.saturating_sub(history.last.io_write),
+                        },
+                    ));
+                    history.last = reading;
+                    history
+                }
+                _ => History {
+                    last: reading,
+                    cpu_window: VecDeque::with_capacity(self.sustained_samples),
+                },
+            };
+            self.processes.insert(pid, history);
+        }
+        metrics
+    }
+
+    /// CPU usage in percent of one CPU: multi-threaded processes can go over 100.
+    fn cpu_usage(&self, last: &Reading, reading: &Reading, elapsed: Duration) -> u32 {
+        let elapsed_ticks = elapsed.as_secs_f64() * self.ticks_per_second as f64;
+        if elapsed_ticks <= 0.0 {
+            return 0;
+        }
+        let used_ticks = reading.cpu_ticks.saturating_sub(last.cpu_ticks);
+        (used_ticks as f64 * 100.0 / elapsed_ticks).round() as u32
+    }
+}
+
+/// Read the counters of all the user space processes.
+pub fn read_processes(page_size: u64) -> Vec<(i32, Reading)> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::warn!("Error reading /proc: {err}");
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(|pid| Some((pid, read_process(pid, page_size)?)))
+        .collect()
+}
+
+/// Read the counters of a process, `None` if it exited or is a kernel thread.
+fn read_process(pid: i32, page_size: u64) -> Option<Reading> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let mut reading = parse_stat(&stat, page_size)?;
+    // The io file can't be read for processes of other users without
+    // CAP_SYS_PTRACE, in that case IO is reported as zero.
+    if let Ok(io) = fs::read_to_string(format!("/proc/{pid}/io")) {
+        (reading.io_read, reading.io_write) = parse_io(&io);
+    }
+    Some(reading)
+}
+
+/// Parse `/proc/<pid>/stat`, see `man 5 proc` for the fields. The command
+/// name can contain spaces and parenthesis, so fields are split after the
+/// last closing parenthesis, starting from the third one.
+fn parse_stat(stat: &str, page_size: u64) -> Option<Reading> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let field = |number: usize| -> Option<u64> { fields.get(number - 3)?.parse().ok() };
+    if field(9)? & PF_KTHREAD != 0 {
+        return None;
+    }
+    Some(Reading {
+        start_time: field(22)?,
+        cpu_ticks: field(14)? + field(15)?,
+        memory_rss: field(24)? * page_size,
+        memory_virtual: field(23)?,
+        io_read: 0,
+        io_write: 0,
+    })
+}
+
+/// Parse the bytes read and written by `/proc/<pid>/io`.
+fn parse_io(io: &str) -> (u64, u64) {
+    let mut read = 0;
+    let mut write = 0;
+    for line in io.lines() {
+        match line.split_once(": ") {
+            Some(("read_bytes", value)) => read = value.parse().unwrap_or_default(),
+            Some(("write_bytes", value)) => write = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    (read, write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat() {
+        let stat = "1234 (my (weird) cmd) S 1 1234 1234 0 -1 4194560 3301 0 0 0 \
+                    250 50 0 0 20 0 4 0 98765 104857600 2560 18446744073709551615 \
+                    1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0";
+        assert_eq!(
+            parse_stat(stat, 4096),
+            Some(Reading {
+                start_time: 98765,
+                cpu_ticks: 300,
+                memory_rss: 2560 * 4096,
+                memory_virtual: 104857600,
+                io_read: 0,
+                io_write: 0,
+            })
+        );
+
+        let kthread = "2 (kthreadd) S 0 0 0 0 -1 2129984 0 0 0 0 0 0 0 0 20 0 1 0 \
+                       12 0 0 18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0";
+        assert_eq!(parse_stat(kthread, 4096), None);
+    }
+
+    #[test]
+    fn io() {
+        let io = "rchar: 1000\nwchar: 2000\nsyscr: 10\nsyscw: 20\n\
+                  read_bytes: 4096\nwrite_bytes: 8192\ncancelled_write_bytes: 0\n";
+        assert_eq!(parse_io(io), (4096, 8192));
+    }
+
+    #[test]
+    fn cpu_usage() {
+        let reading = |cpu_ticks, io_read| {
+            vec![(
+                42,
+                Reading {
+                    start_time: 1,
+                    cpu_ticks,
+                    io_read,
+                    ..Default::default()
+                },
+            )]
+        };
+        let second = Duration::from_secs(1);
+        let mut sampler = Sampler::new(100, 2);
+
+        assert!(sampler.sample(reading(0, 0), second).is_empty());
+        let metrics = sampler.sample(reading(150, 100), second);
+        assert_eq!(metrics[0].1.cpu_usage, 150);
+        assert_eq!(metrics[0].1.cpu_usage_sustained, 0);
+        assert_eq!(metrics[0].1.io_read, 100);
+        let metrics = sampler.sample(reading(200, 100), second);
+        assert_eq!(metrics[0].1.cpu_usage, 50);
+        assert_eq!(metrics[0].1.cpu_usage_sustained, 50);
+        assert_eq!(metrics[0].1.io_read, 0);
+
+        // A new process with a reused pid starts from scratch
+        let mut reused = reading(500, 0);
+        reused[0].1.start_time = 2;
+        assert!(sampler.sample(reused, second).is_empty());
+    }
+}
@@ -8,6 +8,7 @@
 #include "interest_tracking.bpf.h"
 #include "loop.bpf.h"
 #include "output.bpf.h"
+#include "process_info.bpf.h"
 #include "vmlinux.h"
 
 char LICENSE[] SEC("license") = "GPL v2";
@@ -19,12 +20,61 @@ char LICENSE[] SEC("license") = "GPL v2";
 #define EVENT_CGROUP_MKDIR 4
 #define EVENT_CGROUP_RMDIR 5
 #define EVENT_CGROUP_ATTACH 6
+#define EVENT_EXEC_MEMORY 7
+#define EVENT_TIME_CHANGED 8
+#define EVENT_SHARED_MEMORY_CREATED 9
+#define EVENT_SHARED_MEMORY_MAPPED 10
+
+#define EXEC_MEMORY_MMAP 0
+#define EXEC_MEMORY_MPROTECT 1
+
+#define TIME_SETTIMEOFDAY 0
+#define TIME_CLOCK_SETTIME 1
+#define TIME_ADJTIMEX 2
+#define TIME_CLOCK_ADJTIME 3
+
+#define CLOCK_REALTIME 0
+#define ADJ_SETOFFSET 0x0100
+#define ADJ_NANO 0x2000
+
+#define PROT_WRITE 0x2
+#define PROT_EXEC 0x4
+#define VM_WRITE 0x2
+#define VM_EXEC 0x4
+
+#define SHM_SYSV 0
+#define SHM_POSIX 1
+#define SHM_MEMFD 2
+
+#define MAP_SHARED 0x01
+#define O_CREAT 00000100
+#define IPC_CREAT 00001000
+#define SHM_RDONLY 010000
+#define TMPFS_MAGIC 0x01021994
+// Names of memfd_create and paths of shm_open, truncated past this length
+#define SHM_NAME_MAX 256
+// shm_open of the C libraries opens its objects in /dev/shm
+#define SHM_DIR "/dev/shm/"
+#define SHM_DIR_LEN (sizeof(SHM_DIR) - 1)
+// Name of the files of memfd_create, followed by the one given by the caller
+#define MEMFD_PREFIX "memfd:"
+#define MEMFD_PREFIX_LEN (sizeof(MEMFD_PREFIX) - 1)
 
 #define MAX_ORPHANS 50
 #define MAX_ORPHANS_UNROLL 30
 
 #define MAX_PENDING_DEAD_PARENTS 30
 
+// The environment is scanned at exec one variable at a time, and only the
+// ones matching env_allowlist_map are copied. Before 5.17, without bpf_loop,
+// only the first MAX_ENV_VARS_UNROLL variables are scanned.
+#define MAX_ENV_VARS 4096
+#define MAX_ENV_VARS_UNROLL 32
+// Variables longer than this are skipped
+#define MAX_ENV_VAR_LEN 4096
+// Bytes of the variables matched against env_allowlist_map
+#define ENV_NAME_MAX 64
+
 struct namespaces {
   unsigned int uts;
   unsigned int ipc;
@@ -44,7 +94,12 @@ struct exec_event {
   struct buffer_index filename;
   int argc;
   struct buffer_index argv;
+  struct buffer_index envp;
+  struct buffer_index cwd;
   struct namespaces namespaces;
+  u32 stdin_mode;
+  bool has_tty;
+  bool chrooted;
 };
 
 struct exit_event {
@@ -66,7 +121,44 @@ struct cgroup_attach_event {
   u64 id;
 };
 
+struct exec_memory_event {
+  u32 syscall;
+  u64 address;
+  u64 length;
+  bool anonymous;
+  bool writable;
+  struct buffer_index filename;
+};
+
+struct time_changed_event {
+  u32 syscall;
+  // New wall clock time, or the offset added to it by adjtimex
+  s64 seconds;
+  s64 nanoseconds;
+};
+
+struct shared_memory_created_event {
+  u32 kind;
+  // Key of System V segments
+  s32 key;
+  // Size of System V segments, the others are sized later with ftruncate
+  u64 size;
+  struct buffer_index name;
+};
+
+struct shared_memory_mapped_event {
+  u32 kind;
+  s32 key;
+  u64 size;
+  u64 ino;
+  // First process which mapped the segment
+  pid_t first;
+  bool writable;
+  struct buffer_index name;
+};
+
 GLOBAL_INTEREST_MAP_DECLARATION;
+PROCESS_INFO_MAP_DECLARATION;
 MAP_RULES(m_rules);
 MAP_CGROUP_RULES(m_cgroup_rules);
 
@@ -78,6 +170,10 @@ OUTPUT_MAP(process_event, {
   struct cgroup_event cgroup_mkdir;
   struct cgroup_event cgroup_rmdir;
   struct cgroup_attach_event cgroup_attach;
+  struct exec_memory_event exec_memory;
+  struct time_changed_event time_changed;
+  struct shared_memory_created_event shared_memory_created;
+  struct shared_memory_mapped_event shared_memory_mapped;
 });
 
 struct pending_dead_process {
@@ -98,6 +194,22 @@ struct {
   __uint(max_entries, MAX_PENDING_DEAD_PARENTS);
 } orphans_map SEC(".maps");
 
+struct env_allowlist_key {
+  u32 prefixlen;
+  u8 data[ENV_NAME_MAX];
+};
+
+// Prefixes of the environment variables copied at exec, populated by
+// userspace from the `env_allowlist` of the module: `NAME=` for a name, or
+// the prefix of a name ending with `*`.
+struct {
+  __uint(type, BPF_MAP_TYPE_LPM_TRIE);
+  __type(key, struct env_allowlist_key);
+  __type(value, u8);
+  __uint(map_flags, BPF_F_NO_PREALLOC);
+  __uint(max_entries, 256);
+} env_allowlist_map SEC(".maps");
+
 // This hook intercepts new process creations, inherits interest for the child
 // from the parent and emits a fork event.
 SEC("raw_tracepoint/sched_process_fork")
@@ -113,6 +225,7 @@ int BPF_PROG(sched_process_fork, struct task_struct *parent,
   }
   // Propagate whitelist to child
   tracker_fork(&GLOBAL_INTEREST_MAP, parent, child);
+  process_info_fork(parent, child);
   LOG_DEBUG("fork %d %d", parent_tgid, child_tgid);
 
   struct process_event *event = init_process_event(EVENT_FORK, child_tgid);
@@ -132,6 +245,79 @@ int BPF_PROG(sched_process_fork, struct task_struct *parent,
   return 0;
 }
 
+// The root directory of the process differs from the root of its mount
+// namespace, because of chroot. Containers changing their root with
+// pivot_root are not considered chrooted.
+static __always_inline bool is_chrooted(struct task_struct *p) {
+  struct path root = BPF_CORE_READ(p, fs, root);
+  struct mount *ns_root = BPF_CORE_READ(p, nsproxy, mnt_ns, root);
+  if (!ns_root)
+    return false;
+  return root.mnt != &ns_root->mnt ||
+         root.dentry != BPF_CORE_READ(ns_root, mnt.mnt_root);
+}
+
+// Context for loop_scan_env
+struct ctx_scan_env {
+  struct buffer *buffer;
+  struct buffer_index *index;
+  // User memory of the next variable, and end of the environment
+  unsigned long next;
+  unsigned long end;
+  // The next read continues a variable longer than MAX_ENV_VAR_LEN
+  bool skipping;
+};
+
+// Read the next environment variable at the end of the buffer, keeping it
+// there only if it matches env_allowlist_map. Used in the scan_env loop.
+static __always_inline long loop_scan_env(u32 i, void *callback_ctx) {
+  struct ctx_scan_env *c = callback_ctx;
+  if (c->next >= c->end)
+    return LOOP_STOP;
+  struct buffer *buffer = c->buffer;
+  struct buffer_index *index = c->index;
+  u32 pos = index->start + index->len;
+  if (pos >= HALF_BUFFER_MASK)
+    return LOOP_STOP;
+  char *variable = &((char *)buffer->buffer)[pos & HALF_BUFFER_MASK];
+  long r = bpf_probe_read_user_str(variable, MAX_ENV_VAR_LEN, (void *)c->next);
+  if (r <= 0)
+    return LOOP_STOP;
+  // A full read is cut: the variable continues from its last byte
+  bool truncated = r == MAX_ENV_VAR_LEN;
+  c->next += truncated ? r - 1 : r;
+  bool skipping = c->skipping;
+  c->skipping = truncated;
+  if (skipping || truncated)
+    return LOOP_CONTINUE;
+  // The terminating 0 is part of the key, so a variable shorter than a
+  // prefix never matches it
+  struct env_allowlist_key key = {.prefixlen = ENV_NAME_MAX * 8};
+  if (bpf_probe_read_kernel(key.data, ENV_NAME_MAX, variable) < 0)
+    return LOOP_STOP;
+  if (bpf_map_lookup_elem(&env_allowlist_map, &key)) {
+    // Keep the variable with its terminating 0
+    index->len += r;
+    buffer->len += r;
+  }
+  return LOOP_CONTINUE;
+}
+
+// Copy the allowed variables of the environment, as 0 terminated strings.
+static __always_inline void scan_env(struct buffer *buffer,
+                                     struct buffer_index *index,
+                                     struct mm_struct *mm) {
+  struct ctx_scan_env c = {
+      .buffer = buffer,
+      .index = index,
+      .next = BPF_CORE_READ(mm, env_start),
+      .end = BPF_CORE_READ(mm, env_end),
+      .skipping = false,
+  };
+  buffer_index_init(buffer, index);
+  LOOP(MAX_ENV_VARS, MAX_ENV_VARS_UNROLL, loop_scan_env, &c);
+}
+
 SEC("raw_tracepoint/sched_process_exec")
 int BPF_PROG(sched_process_exec, struct task_struct *p, pid_t old_pid,
              struct linux_binprm *bprm) {
@@ -150,6 +336,14 @@ int BPF_PROG(sched_process_exec, struct task_struct *p, pid_t old_pid,
   event->exec.namespaces.time = BPF_CORE_READ(p, nsproxy, time_ns, ns.inum);
   event->exec.namespaces.cgroup = BPF_CORE_READ(p, nsproxy, cgroup_ns, ns.inum);
 
+  // Terminal details, used to detect interactive sessions
+  event->exec.has_tty = BPF_CORE_READ(p, signal, tty) != NULL;
+  struct file **fd = BPF_CORE_READ(p, files, fdt, fd);
+  struct file *stdin_file = NULL;
+  bpf_core_read(&stdin_file, sizeof(stdin_file), &fd[0]);
+  event->exec.stdin_mode =
+      stdin_file ? BPF_CORE_READ(stdin_file, f_inode, i_mode) : 0;
+
   // This is needed because the first MAX_IMAGE_LEN bytes of buffer will
   // be used as a lookup key for the target and whitelist maps and garbage
   // would make the search fail.
@@ -175,6 +369,14 @@ int BPF_PROG(sched_process_exec, struct task_struct *p, pid_t old_pid,
 
   // Check target and whitelist
   tracker_check_rules(&GLOBAL_INTEREST_MAP, &m_rules, p, image);
+  process_info_exec(p, image);
+
+  // The working directory is copied after the image lookup, which needs the
+  // image alone in the buffer, but before the arguments and the environment,
+  // which can fill it
+  struct path pwd = BPF_CORE_READ(p, fs, pwd);
+  get_path_str(&pwd, &event->buffer, &event->exec.cwd);
+  event->exec.chrooted = is_chrooted(p);
 
   struct task_struct *task = (struct task_struct *)bpf_get_current_task();
   struct mm_struct *mm = BPF_CORE_READ(task, mm);
@@ -185,6 +387,8 @@ int BPF_PROG(sched_process_exec, struct task_struct *p, pid_t old_pid,
   buffer_append_user_memory(&event->buffer, &event->exec.argv, (void *)start,
                             len);
 
+  scan_env(&event->buffer, &event->exec.envp, mm);
+
   output_process_event(ctx, event);
 
   return 0;
@@ -264,6 +468,7 @@ int BPF_PROG(sched_process_exit, struct task_struct *p) {
     // we've already emitted the exit event.
     return 0;
   }
+  process_info_exit(tgid);
 
   struct process_event *event = init_process_event(EVENT_EXIT, tgid);
   if (!event)
@@ -300,6 +505,7 @@ static __always_inline long loop_orphan_adopted(u32 i, void *callback_ctx) {
   if (!event) // memory error
     return LOOP_STOP;
   event->change_parent.ppid = BPF_CORE_READ(orphan, parent, pid);
+  process_info_change_parent(tgid, event->change_parent.ppid);
   LOG_DEBUG("New parent for %d: %d", event->pid, event->change_parent.ppid);
   output_process_event(c->ctx, event);
   return LOOP_CONTINUE;
@@ -395,3 +601,420 @@ int BPF_PROG(cgroup_attach_task, struct cgroup *cgrp, const char *path,
   output_process_event(ctx, event);
   return 0;
 }
+
+// First process mapping every shared memory segment, by the address of its
+// inode.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, u64);
+  __type(value, pid_t);
+  __uint(max_entries, 8192);
+} shm_mapper_map SEC(".maps");
+
+// A segment mapped by a process other than the first one, already reported.
+struct shm_pair {
+  u64 segment;
+  pid_t first;
+  pid_t mapper;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, struct shm_pair);
+  __type(value, u8);
+  __uint(max_entries, 8192);
+} shm_pair_map SEC(".maps");
+
+// Returns the event reporting a segment mapped by the current process, if it
+// was mapped by another process first and the pair was not reported yet.
+static __always_inline struct process_event *
+init_shared_memory_mapped(struct inode *inode) {
+  u64 segment = (u64)inode;
+  pid_t tgid = bpf_get_current_pid_tgid() >> 32;
+  pid_t *first = bpf_map_lookup_elem(&shm_mapper_map, &segment);
+  if (!first) {
+    bpf_map_update_elem(&shm_mapper_map, &segment, &tgid, BPF_ANY);
+    return NULL;
+  }
+  struct shm_pair pair = {
+      .segment = segment,
+      .first = *first,
+      .mapper = tgid,
+  };
+  if (pair.first == tgid || bpf_map_lookup_elem(&shm_pair_map, &pair))
+    return NULL;
+  u8 reported = 1;
+  bpf_map_update_elem(&shm_pair_map, &pair, &reported, BPF_ANY);
+  if (!tracker_is_interesting(&GLOBAL_INTEREST_MAP, tgid, __func__, true,
+                              true))
+    return NULL;
+  struct process_event *event =
+      init_process_event(EVENT_SHARED_MEMORY_MAPPED, tgid);
+  if (!event)
+    return NULL;
+  event->shared_memory_mapped.first = pair.first;
+  event->shared_memory_mapped.ino = BPF_CORE_READ(inode, i_ino);
+  return event;
+}
+
+static __always_inline bool is_memfd(struct file *file) {
+  const char prefix[] = MEMFD_PREFIX;
+  char name[MEMFD_PREFIX_LEN];
+  const unsigned char *dname = BPF_CORE_READ(file, f_path.dentry, d_name.name);
+  if (bpf_probe_read_kernel(name, sizeof(name), dname) < 0)
+    return false;
+#pragma unroll
+  for (int i = 0; i < MEMFD_PREFIX_LEN; i++) {
+    if (name[i] != prefix[i])
+      return false;
+  }
+  return true;
+}
+
+// Shared mappings of memory files: the ones of memfd_create and the files of
+// tmpfs, like the ones of shm_open in /dev/shm.
+static __always_inline void on_shared_file_mapping(void *ctx, struct file *file,
+                                                   bool writable) {
+  struct inode *inode = BPF_CORE_READ(file, f_inode);
+  if (BPF_CORE_READ(inode, i_sb, s_magic) != TMPFS_MAGIC)
+    return;
+  struct process_event *event = init_shared_memory_mapped(inode);
+  if (!event)
+    return;
+  event->shared_memory_mapped.kind = is_memfd(file) ? SHM_MEMFD : SHM_POSIX;
+  event->shared_memory_mapped.key = 0;
+  event->shared_memory_mapped.size = BPF_CORE_READ(inode, i_size);
+  event->shared_memory_mapped.writable = writable;
+  struct path path = BPF_CORE_READ(file, f_path);
+  get_path_str(&path, &event->buffer, &event->shared_memory_mapped.name);
+  output_process_event(ctx, event);
+}
+
+// System V segments are mapped by shmat, which doesn't call the mmap_file
+// hook. The permissions are the first field of the segment.
+PULSAR_LSM_HOOK(shm_shmat, struct kern_ipc_perm *, perm, char *, shmaddr, int,
+                shmflg);
+static __always_inline void on_shm_shmat(void *ctx, struct kern_ipc_perm *perm,
+                                         char *shmaddr, int shmflg) {
+  struct shmid_kernel *shp = (struct shmid_kernel *)perm;
+  struct inode *inode = BPF_CORE_READ(shp, shm_file, f_inode);
+  struct process_event *event = init_shared_memory_mapped(inode);
+  if (!event)
+    return;
+  event->shared_memory_mapped.kind = SHM_SYSV;
+  event->shared_memory_mapped.key = BPF_CORE_READ(perm, key);
+  event->shared_memory_mapped.size = BPF_CORE_READ(shp, shm_segsz);
+  event->shared_memory_mapped.writable = !(shmflg & SHM_RDONLY);
+  buffer_index_init(&event->buffer, &event->shared_memory_mapped.name);
+  output_process_event(ctx, event);
+}
+
+static __always_inline void output_exec_memory(void *ctx, pid_t tgid,
+                                               u32 syscall, u64 address,
+                                               u64 length, struct file *file,
+                                               bool writable) {
+  struct process_event *event = init_process_event(EVENT_EXEC_MEMORY, tgid);
+  if (!event)
+    return;
+  event->exec_memory.syscall = syscall;
+  event->exec_memory.address = address;
+  event->exec_memory.length = length;
+  event->exec_memory.anonymous = file == NULL;
+  event->exec_memory.writable = writable;
+  if (file) {
+    struct path path = BPF_CORE_READ(file, f_path);
+    get_path_str(&path, &event->buffer, &event->exec_memory.filename);
+  } else {
+    buffer_index_init(&event->buffer, &event->exec_memory.filename);
+  }
+  output_process_event(ctx, event);
+}
+
+// Executable mappings are interesting only when anonymous or writable:
+// regular shared libraries and executables are mapped read-only.
+// The address is not allocated yet when this hook runs.
+PULSAR_LSM_HOOK(mmap_file, struct file *, file, unsigned long, reqprot,
+                unsigned long, prot, unsigned long, flags);
+static __always_inline void on_mmap_file(void *ctx, struct file *file,
+                                         unsigned long reqprot,
+                                         unsigned long prot,
+                                         unsigned long flags) {
+  if (file && (flags & MAP_SHARED))
+    on_shared_file_mapping(ctx, file, prot & PROT_WRITE);
+  if (!(prot & PROT_EXEC))
+    return;
+  bool writable = prot & PROT_WRITE;
+  if (file && !writable)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  output_exec_memory(ctx, tgid, EXEC_MEMORY_MMAP, 0, 0, file, writable);
+}
+
+// Report regions becoming executable, which are anonymous or writable.
+// The address and length are the ones of the whole memory area, which may be
+// larger than the range passed to mprotect.
+PULSAR_LSM_HOOK(file_mprotect, struct vm_area_struct *, vma, unsigned long,
+                reqprot, unsigned long, prot);
+static __always_inline void on_file_mprotect(void *ctx,
+                                             struct vm_area_struct *vma,
+                                             unsigned long reqprot,
+                                             unsigned long prot) {
+  if (!(prot & PROT_EXEC))
+    return;
+  unsigned long vm_flags = BPF_CORE_READ(vma, vm_flags);
+  if (vm_flags & VM_EXEC)
+    return;
+  struct file *file = BPF_CORE_READ(vma, vm_file);
+  bool writable = (prot & PROT_WRITE) || (vm_flags & VM_WRITE);
+  if (file && !writable)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  u64 start = BPF_CORE_READ(vma, vm_start);
+  u64 end = BPF_CORE_READ(vma, vm_end);
+  output_exec_memory(ctx, tgid, EXEC_MEMORY_MPROTECT, start, end - start, file,
+                     writable);
+}
+
+// Clock changes requested by a thread, saved until the syscall returns to
+// report only the successful ones.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, u64);
+  __type(value, struct time_changed_event);
+  __uint(max_entries, 1024);
+} pending_time_changes SEC(".maps");
+
+static __always_inline void save_time_change(u32 syscall, s64 seconds,
+                                             s64 nanoseconds) {
+  u64 id = bpf_get_current_pid_tgid();
+  struct time_changed_event change = {
+      .syscall = syscall,
+      .seconds = seconds,
+      .nanoseconds = nanoseconds,
+  };
+  bpf_map_update_elem(&pending_time_changes, &id, &change, BPF_ANY);
+}
+
+static __always_inline void on_time_change_exit(void *ctx, long ret) {
+  u64 id = bpf_get_current_pid_tgid();
+  struct time_changed_event *change =
+      bpf_map_lookup_elem(&pending_time_changes, &id);
+  if (!change)
+    return;
+  struct time_changed_event saved = *change;
+  bpf_map_delete_elem(&pending_time_changes, &id);
+  if (ret < 0)
+    return;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    return;
+  struct process_event *event = init_process_event(EVENT_TIME_CHANGED, tgid);
+  if (!event)
+    return;
+  event->time_changed = saved;
+  output_process_event(ctx, event);
+}
+
+// Only ADJ_SETOFFSET steps the clock: the other adjtimex modes, used by NTP
+// daemons, slew it gradually.
+static __always_inline void save_clock_offset(u32 syscall,
+                                              struct __kernel_timex *txc) {
+  unsigned int modes = 0;
+  if (bpf_probe_read_user(&modes, sizeof(modes), &txc->modes) < 0)
+    return;
+  if (!(modes & ADJ_SETOFFSET))
+    return;
+  struct __kernel_timex_timeval offset = {};
+  if (bpf_probe_read_user(&offset, sizeof(offset), &txc->time) < 0)
+    return;
+  s64 nanoseconds = offset.tv_usec;
+  if (!(modes & ADJ_NANO))
+    nanoseconds *= 1000;
+  save_time_change(syscall, offset.tv_sec, nanoseconds);
+}
+
+SEC("tracepoint/sys_enter_settimeofday")
+int BPF_PROG(sys_enter_settimeofday, struct pt_regs *regs, int __syscall_nr,
+             struct __kernel_old_timeval *tv, struct timezone *tz) {
+  struct __kernel_old_timeval time = {};
+  // Only the timezone is set when tv is NULL
+  if (!tv || bpf_probe_read_user(&time, sizeof(time), tv) < 0)
+    return 0;
+  save_time_change(TIME_SETTIMEOFDAY, time.tv_sec, time.tv_usec * 1000);
+  return 0;
+}
+
+// Other clocks can't be set, except for the PTP hardware clocks
+SEC("tracepoint/sys_enter_clock_settime")
+int BPF_PROG(sys_enter_clock_settime, struct pt_regs *regs, int __syscall_nr,
+             clockid_t which_clock, struct __kernel_timespec *tp) {
+  struct __kernel_timespec time = {};
+  if (which_clock != CLOCK_REALTIME ||
+      bpf_probe_read_user(&time, sizeof(time), tp) < 0)
+    return 0;
+  save_time_change(TIME_CLOCK_SETTIME, time.tv_sec, time.tv_nsec);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_adjtimex")
+int BPF_PROG(sys_enter_adjtimex, struct pt_regs *regs, int __syscall_nr,
+             struct __kernel_timex *txc) {
+  save_clock_offset(TIME_ADJTIMEX, txc);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_clock_adjtime")
+int BPF_PROG(sys_enter_clock_adjtime, struct pt_regs *regs, int __syscall_nr,
+             clockid_t which_clock, struct __kernel_timex *txc) {
+  if (which_clock == CLOCK_REALTIME)
+    save_clock_offset(TIME_CLOCK_ADJTIME, txc);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_settimeofday")
+int BPF_PROG(sys_exit_settimeofday, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_time_change_exit(ctx, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_clock_settime")
+int BPF_PROG(sys_exit_clock_settime, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_time_change_exit(ctx, ret);
+  return 0;
+}
+
+// adjtimex returns the clock state on success
+SEC("tracepoint/sys_exit_adjtimex")
+int BPF_PROG(sys_exit_adjtimex, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_time_change_exit(ctx, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_clock_adjtime")
+int BPF_PROG(sys_exit_clock_adjtime, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_time_change_exit(ctx, ret);
+  return 0;
+}
+
+// Shared memory created by a thread, saved until the syscall returns to
+// report only the successful ones.
+struct pending_shm {
+  u32 kind;
+  s32 key;
+  u64 size;
+  char name[SHM_NAME_MAX];
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, u64);
+  __type(value, struct pending_shm);
+  __uint(max_entries, 1024);
+} pending_shm_creations SEC(".maps");
+
+static __always_inline void save_shm_creation(u32 kind, s32 key, u64 size,
+                                              const char *name) {
+  struct pending_shm pending = {
+      .kind = kind,
+      .key = key,
+      .size = size,
+  };
+  if (name &&
+      bpf_probe_read_user_str(pending.name, sizeof(pending.name), name) < 0)
+    return;
+  u64 id = bpf_get_current_pid_tgid();
+  bpf_map_update_elem(&pending_shm_creations, &id, &pending, BPF_ANY);
+}
+
+static __always_inline void on_shm_creation_exit(void *ctx, u32 kind,
+                                                 long ret) {
+  u64 id = bpf_get_current_pid_tgid();
+  struct pending_shm *pending =
+      bpf_map_lookup_elem(&pending_shm_creations, &id);
+  if (!pending)
+    return;
+  if (ret < 0 || pending->kind != kind)
+    goto out;
+  pid_t tgid = tracker_interesting_tgid(&GLOBAL_INTEREST_MAP);
+  if (tgid < 0)
+    goto out;
+  struct process_event *event =
+      init_process_event(EVENT_SHARED_MEMORY_CREATED, tgid);
+  if (!event)
+    goto out;
+  event->shared_memory_created.kind = pending->kind;
+  event->shared_memory_created.key = pending->key;
+  event->shared_memory_created.size = pending->size;
+  buffer_index_init(&event->buffer, &event->shared_memory_created.name);
+  if (kind != SHM_SYSV)
+    buffer_append_str(&event->buffer, &event->shared_memory_created.name,
+                      pending->name, SHM_NAME_MAX);
+  output_process_event(ctx, event);
+out:
+  bpf_map_delete_elem(&pending_shm_creations, &id);
+}
+
+// shmget without IPC_CREAT only looks up existing segments. With it, the
+// segment of the key may exist already.
+SEC("tracepoint/sys_enter_shmget")
+int BPF_PROG(sys_enter_shmget, struct pt_regs *regs, int __syscall_nr,
+             key_t key, size_t size, int shmflg) {
+  if (shmflg & IPC_CREAT)
+    save_shm_creation(SHM_SYSV, key, size, NULL);
+  return 0;
+}
+
+SEC("tracepoint/sys_enter_memfd_create")
+int BPF_PROG(sys_enter_memfd_create, struct pt_regs *regs, int __syscall_nr,
+             const char *uname, unsigned int flags) {
+  save_shm_creation(SHM_MEMFD, 0, 0, uname);
+  return 0;
+}
+
+// shm_open is a wrapper of openat on /dev/shm, with O_CREAT to create objects
+SEC("tracepoint/sys_enter_openat")
+int BPF_PROG(sys_enter_openat, struct pt_regs *regs, int __syscall_nr, int dfd,
+             const char *filename, int flags, umode_t mode) {
+  if (!(flags & O_CREAT))
+    return 0;
+  const char prefix[] = SHM_DIR;
+  char dir[SHM_DIR_LEN];
+  if (bpf_probe_read_user(dir, sizeof(dir), filename) < 0)
+    return 0;
+#pragma unroll
+  for (int i = 0; i < SHM_DIR_LEN; i++) {
+    if (dir[i] != prefix[i])
+      return 0;
+  }
+  save_shm_creation(SHM_POSIX, 0, 0, filename);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_shmget")
+int BPF_PROG(sys_exit_shmget, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_shm_creation_exit(ctx, SHM_SYSV, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_memfd_create")
+int BPF_PROG(sys_exit_memfd_create, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_shm_creation_exit(ctx, SHM_MEMFD, ret);
+  return 0;
+}
+
+SEC("tracepoint/sys_exit_openat")
+int BPF_PROG(sys_exit_openat, struct pt_regs *regs, int __syscall_nr,
+             long ret) {
+  on_shm_creation_exit(ctx, SHM_POSIX, ret);
+  return 0;
+}
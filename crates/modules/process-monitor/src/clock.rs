@@ -0,0 +1,82 @@
+//! Changes of the wall clock, reported as `TimeChanged` events.
+//!
+//! `settimeofday` and `clock_settime` set a new time, while `adjtimex` and
+//! `clock_adjtime` with `ADJ_SETOFFSET` add an offset to the current one. The
+//! time before the change is the wall clock when the syscall was made.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values of `ProcessEvent::TimeChanged::syscall`
+pub const TIME_SETTIMEOFDAY: u32 = 0;
+pub const TIME_CLOCK_SETTIME: u32 = 1;
+pub const TIME_ADJTIMEX: u32 = 2;
+pub const TIME_CLOCK_ADJTIME: u32 = 3;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimeChange {
+    pub syscall: &'static str,
+    /// New wall clock time, in seconds since the UNIX epoch
+    pub time: i64,
+    /// Seconds added to the wall clock, negative when moved backwards
+    pub delta: i64,
+}
+
+impl TimeChange {
+    pub fn new(syscall: u32, seconds: i64, nanoseconds: i64, before: SystemTime) -> Self {
+        let value = seconds as i128 * NANOS_PER_SEC + nanoseconds as i128;
+        let before = match before.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_nanos() as i128,
+            Err(err) => -(err.duration().as_nanos() as i128),
+        };
+        let (syscall, time, delta) = match syscall {
+            TIME_ADJTIMEX => ("adjtimex", before + value, value),
+            TIME_CLOCK_ADJTIME => ("clock_adjtime", before + value, value),
+            TIME_CLOCK_SETTIME => ("clock_settime", value, value - before),
+            _ => ("settimeofday", value, value - before),
+        };
+        Self {
+            syscall,
+            time: (time / NANOS_PER_SEC) as i64,
+            delta: (delta / NANOS_PER_SEC) as i64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn time_changes() {
+        let before = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            TimeChange::new(TIME_CLOCK_SETTIME, 1_600_000_000, 500_000_000, before),
+            TimeChange {
+                syscall: "clock_settime",
+                time: 1_600_000_000,
+                delta: -99_999_999,
+            }
+        );
+        assert_eq!(
+            TimeChange::new(TIME_SETTIMEOFDAY, 1_700_003_600, 0, before),
+            TimeChange {
+                syscall: "settimeofday",
+                time: 1_700_003_600,
+                delta: 3600,
+            }
+        );
+        // Offsets are added to the current time
+        assert_eq!(
+            TimeChange::new(TIME_ADJTIMEX, -86_400, 0, before),
+            TimeChange {
+                syscall: "adjtimex",
+                time: 1_699_913_600,
+                delta: -86_400,
+            }
+        );
+    }
+}
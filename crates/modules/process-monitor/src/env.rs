@@ -0,0 +1,172 @@
+//! Environment variables reported in the `Exec` events.
+//!
+//! The probe scans the whole environment of every executed process and copies
+//! only the variables of the `env_allowlist`, matched against the prefixes of
+//! `env_allowlist_map`. The default ones are those used to hijack the dynamic
+//! loader or to tamper with the shell history.
+
+use std::sync::{OnceLock, RwLock};
+
+use bpf_common::{
+    aya::maps::lpm_trie::{Key, LpmTrie},
+    Program, ProgramError,
+};
+use pulsar_core::pdk::{ConfigError, ModuleConfig};
+
+/// Must match ENV_NAME_MAX in probes.bpf.c
+pub const ENV_NAME_MAX: usize = 64;
+
+const ENV_ALLOWLIST_MAP: &str = "env_allowlist_map";
+
+pub const DEFAULT_ENV_ALLOWLIST: &str =
+    "LD_PRELOAD,LD_LIBRARY_PATH,LD_AUDIT,HISTFILE,HISTCONTROL,PROMPT_COMMAND,SSH_*";
+
+/// Names of the environment variables kept, or prefixes when ending with `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvAllowlist {
+    names: Vec<String>,
+}
+
+impl EnvAllowlist {
+    /// Allowlist shared by the process-monitor events, updated on
+    /// configuration changes
+    pub fn global() -> &'static RwLock<EnvAllowlist> {
+        static ALLOWLIST: OnceLock<RwLock<EnvAllowlist>> = OnceLock::new();
+        ALLOWLIST.get_or_init(|| RwLock::new(Self::new(DEFAULT_ENV_ALLOWLIST.split(','))))
+    }
+
+    pub fn new<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            names: names.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        self.names
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == allowed,
+            })
+    }
+
+    /// Prefixes of the allowed `NAME=value` entries: `NAME=` for the names,
+    /// or the names without the final `*`.
+    fn entry_prefixes(&self) -> impl Iterator<Item = String> + '_ {
+        self.names.iter().map(|name| match name.strip_suffix('*') {
+            Some(prefix) => prefix.to_string(),
+            None => format!("{name}="),
+        })
+    }
+
+    /// Allowed `NAME=value` entries of the 0 separated environment copied by
+    /// the probe. The probe already skips the other variables, except the
+    /// ones sharing the first ENV_NAME_MAX bytes of an allowed name.
+    /// An entry without its terminating 0 is dropped.
+    pub fn filter(&self, environ: &[u8]) -> Vec<String> {
+        let mut entries: Vec<&[u8]> = environ.split(|byte| *byte == 0).collect();
+        // The last entry is empty when the copy ends with a 0, otherwise
+        // it was truncated
+        entries.pop();
+        entries
+            .into_iter()
+            .map(String::from_utf8_lossy)
+            .filter(|entry| match entry.split_once('=') {
+                Some((name, _)) => self.allows(name),
+                None => false,
+            })
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Replace the content of the allowlist map of the probe.
+pub fn set_env_allowlist(
+    program: &mut Program,
+    allowlist: &EnvAllowlist,
+) -> Result<(), ProgramError> {
+    let map = program
+        .bpf()
+        .map_mut(ENV_ALLOWLIST_MAP)
+        .ok_or_else(|| ProgramError::MapNotFound(ENV_ALLOWLIST_MAP.to_string()))?;
+    let mut trie: LpmTrie<_, [u8; ENV_NAME_MAX], u8> = LpmTrie::try_from(map)?;
+
+    let old_keys = trie.keys().collect::<Result<Vec<_>, _>>()?;
+    for key in old_keys {
+        trie.remove(&key)?;
+    }
+    for prefix in allowlist.entry_prefixes() {
+        let (prefix_len, data) = prefix_key(&prefix);
+        trie.insert(&Key::new(prefix_len, data), 1, 0)?;
+    }
+    Ok(())
+}
+
+/// Returns the prefix length in bits and the key data of an entry prefix.
+/// Prefixes longer than ENV_NAME_MAX are truncated, like the variables by the
+/// probe.
+fn prefix_key(prefix: &str) -> (u32, [u8; ENV_NAME_MAX]) {
+    let bytes = prefix.as_bytes();
+    let len = bytes.len().min(ENV_NAME_MAX);
+    let mut data = [0; ENV_NAME_MAX];
+    data[..len].copy_from_slice(&bytes[..len]);
+    (len as u32 * 8, data)
+}
+
+impl TryFrom<&ModuleConfig> for EnvAllowlist {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let default = DEFAULT_ENV_ALLOWLIST.split(',').map(String::from).collect();
+        Ok(Self::new(config.get_list_with_default::<String>(
+            "env_allowlist",
+            default,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_environment() {
+        let allowlist = EnvAllowlist::new(DEFAULT_ENV_ALLOWLIST.split(','));
+        let environ =
+            b"HOME=/root\0LD_PRELOAD=/tmp/x.so\0SSH_CLIENT=10.0.0.1 22 22\0LD_PRELOADED=1\0";
+        assert_eq!(
+            allowlist.filter(environ),
+            vec!["LD_PRELOAD=/tmp/x.so", "SSH_CLIENT=10.0.0.1 22 22"]
+        );
+
+        let truncated = b"HISTFILE=/dev/null\0LD_PRELOAD=/tmp/x";
+        assert_eq!(allowlist.filter(truncated), vec!["HISTFILE=/dev/null"]);
+        assert!(allowlist.filter(b"").is_empty());
+    }
+
+    #[test]
+    fn probe_prefixes() {
+        let allowlist = EnvAllowlist::new(["HISTFILE", "SSH_*"]);
+        assert_eq!(
+            allowlist.entry_prefixes().collect::<Vec<_>>(),
+            ["HISTFILE=", "SSH_"]
+        );
+        let (prefix_len, data) = prefix_key("HISTFILE=");
+        assert_eq!(prefix_len, 72);
+        assert_eq!(&data[..10], b"HISTFILE=\0");
+
+        let long = "A".repeat(ENV_NAME_MAX + 1);
+        assert_eq!(prefix_key(&long).0, ENV_NAME_MAX as u32 * 8);
+    }
+
+    #[test]
+    fn config() {
+        let mut config = ModuleConfig::default();
+        assert!(EnvAllowlist::try_from(&config).unwrap().allows("SSH_TTY"));
+
+        config.insert("env_allowlist".to_string(), "HISTFILE".to_string());
+        let allowlist = EnvAllowlist::try_from(&config).unwrap();
+        assert!(allowlist.allows("HISTFILE"));
+        assert!(!allowlist.allows("LD_PRELOAD"));
+    }
+}
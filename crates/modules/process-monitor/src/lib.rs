@@ -5,14 +5,19 @@ use bpf_common::{
 };
 use pulsar_core::event::Namespaces;
 
+pub mod clock;
+pub mod env;
+pub mod shm;
+
 const MODULE_NAME: &str = "process-monitor";
 
 pub async fn program(
     ctx: BpfContext,
     sender: impl BpfSender<ProcessEvent>,
 ) -> Result<Program, ProgramError> {
+    let attach_to_lsm = ctx.lsm_supported();
     let binary = ebpf_program!(&ctx, "probes");
-    let mut program = ProgramBuilder::new(ctx, MODULE_NAME, binary)
+    let mut builder = ProgramBuilder::new(ctx, MODULE_NAME, binary)
         .raw_tracepoint("sched_process_exec")
         .raw_tracepoint("sched_process_exit")
         .raw_tracepoint("sched_process_fork")
@@ -20,8 +25,40 @@ pub async fn program(
         .raw_tracepoint("cgroup_mkdir")
         .raw_tracepoint("cgroup_rmdir")
         .raw_tracepoint("cgroup_attach_task")
-        .start()
-        .await?;
+        .max_entries(
+            bpf_filtering::maps::DEFAULT_PROCESS_INFO,
+            bpf_filtering::maps::process_info_max_entries(),
+        );
+    // Executable memory is detected on the LSM hooks, or on the same kernel
+    // functions using fentry or kprobes when LSM eBPF programs are not supported.
+    // The same hooks report shared memory mapped by several processes.
+    builder = if attach_to_lsm {
+        builder
+            .lsm("mmap_file")
+            .lsm("file_mprotect")
+            .lsm("shm_shmat")
+    } else {
+        builder
+            .fentry_or_kprobe("security_mmap_file")
+            .fentry_or_kprobe("security_file_mprotect")
+            .fentry_or_kprobe("security_shm_shmat")
+    };
+    // Clock changes are saved when the syscalls start and reported if they succeed
+    for syscall in ["settimeofday", "clock_settime", "adjtimex", "clock_adjtime"] {
+        builder = builder
+            .tracepoint("syscalls", &format!("sys_enter_{syscall}"))
+            .tracepoint("syscalls", &format!("sys_exit_{syscall}"));
+    }
+    // Shared memory creations are reported if the syscalls succeed
+    for syscall in ["shmget", "memfd_create", "openat"] {
+        builder = builder
+            .tracepoint("syscalls", &format!("sys_enter_{syscall}"))
+            .tracepoint("syscalls", &format!("sys_exit_{syscall}"));
+    }
+    let mut program = builder.start().await?;
+    // Environment variables are copied from the first exec
+    let allowlist = env::EnvAllowlist::global().read().unwrap().clone();
+    env::set_env_allowlist(&mut program, &allowlist)?;
     program
         .read_events("map_output_process_event", sender)
         .await?;
@@ -42,7 +79,12 @@ pub enum ProcessEvent {
         filename: BufferIndex<str>,
         argc: u32,
         argv: BufferIndex<str>, // 0 separated strings
+        envp: BufferIndex<str>, // 0 terminated strings, only the allowed ones
+        cwd: BufferIndex<str>,
         namespaces: Namespaces,
+        stdin_mode: u32,
+        has_tty: bool,
+        chrooted: bool,
     },
     Exit {
         exit_code: u32,
@@ -63,8 +105,41 @@ pub enum ProcessEvent {
         path: BufferIndex<str>,
         id: u64,
     },
+    ExecMemory {
+        syscall: u32,
+        address: u64,
+        length: u64,
+        anonymous: bool,
+        writable: bool,
+        filename: BufferIndex<str>,
+    },
+    TimeChanged {
+        syscall: u32,
+        seconds: i64,
+        nanoseconds: i64,
+    },
+    SharedMemoryCreated {
+        kind: u32,
+        key: i32,
+        size: u64,
+        name: BufferIndex<str>,
+    },
+    /// Segment mapped by a process other than the first one, see [`shm`]
+    SharedMemoryMapped {
+        kind: u32,
+        key: i32,
+        size: u64,
+        ino: u64,
+        first: Pid,
+        writable: bool,
+        name: BufferIndex<str>,
+    },
 }
 
+/// Values of `ProcessEvent::ExecMemory::syscall`
+pub const EXEC_MEMORY_MMAP: u32 = 0;
+pub const EXEC_MEMORY_MPROTECT: u32 = 1;
+
 fn extract_parameters(argv: &[u8]) -> Vec<String> {
     // Ignore the last byte as it's always a 0. Not doing this would
     // produce a trailing "" argument.
@@ -83,12 +158,21 @@ fn extract_parameters(argv: &[u8]) -> Vec<String> {
 pub mod pulsar {
     use super::*;
     use bpf_common::{parsing::IndexError, program::BpfEvent, BpfSenderWrapper};
+    use pulsar_core::interpreter::{script_path, shell_command};
     use pulsar_core::pdk::{
-        process_tracker::TrackerUpdate, CleanExit, IntoPayload, ModuleContext, ModuleError,
+        process_tracker::{StdinKind, TerminalInfo, TrackerUpdate},
+        CleanExit, ConfigField, ConfigKind, ConfigSchema, IntoPayload, ModuleContext, ModuleError,
         Payload, PulsarModule, ShutdownSignal, Version,
     };
+    use pulsar_core::shell::ShellCommand;
+    use std::path::Path;
     use tokio::sync::mpsc;
 
+    use crate::{
+        clock::TimeChange,
+        env::{set_env_allowlist, EnvAllowlist},
+    };
+
     pub fn module() -> PulsarModule {
         PulsarModule::new(
             MODULE_NAME,
@@ -96,14 +180,28 @@ pub mod pulsar {
             true,
             process_monitor_task,
         )
+        .uses_ebpf()
+        .with_config_schema(
+            ConfigSchema::new(1)
+                .extend(bpf_filtering::config::Config::schema_fields())
+                .field(
+                    ConfigField::new(
+                        "env_allowlist",
+                        ConfigKind::List,
+                        "Environment variables reported in Exec events, or prefixes ending with `*`",
+                    )
+                    .default_value(env::DEFAULT_ENV_ALLOWLIST),
+                ),
+        )
     }
 
     async fn process_monitor_task(
         ctx: ModuleContext,
         mut shutdown: ShutdownSignal,
     ) -> Result<CleanExit, ModuleError> {
-        let rx_config = ctx.get_config();
+        let mut rx_config = ctx.get_config();
         let filtering_config: bpf_filtering::config::Config = rx_config.read()?;
+        *EnvAllowlist::global().write().unwrap() = rx_config.read()?;
         let process_tracker = ctx.get_process_tracker();
         let (tx_processes, mut rx_processes) = mpsc::unbounded_channel();
         let mut program = program(
@@ -123,6 +221,9 @@ pub mod pulsar {
                         argc,
                         ref argv,
                         namespaces,
+                        stdin_mode,
+                        has_tty,
+                        ..
                     } => {
                         let argv =
                             extract_parameters(argv.bytes(&event.buffer).unwrap_or_else(|err| {
@@ -144,6 +245,10 @@ pub mod pulsar {
                             timestamp: event.timestamp,
                             argv,
                             namespaces,
+                            terminal: TerminalInfo {
+                                has_tty,
+                                stdin: StdinKind::from_mode(stdin_mode),
+                            },
                         }
                     }
                     ProcessEvent::Exit { .. } => TrackerUpdate::Exit {
@@ -176,6 +281,11 @@ pub mod pulsar {
             tokio::select! {
                 r = shutdown.recv() => return r,
                 Some(msg) = rx_processes.recv() => process_tracker.update(msg),
+                _ = rx_config.changed() => {
+                    let allowlist: EnvAllowlist = rx_config.read()?;
+                    set_env_allowlist(&mut program, &allowlist)?;
+                    *EnvAllowlist::global().write().unwrap() = allowlist;
+                }
             }
         }
     }
@@ -184,7 +294,11 @@ pub mod pulsar {
         type Error = IndexError;
         fn try_into_payload(event: BpfEvent<ProcessEvent>) -> Result<Payload, IndexError> {
             let BpfEvent {
-                payload, buffer, ..
+                payload,
+                buffer,
+                timestamp,
+                pid,
+                ..
             } = event;
             Ok(match payload {
                 ProcessEvent::Fork { ppid, namespaces } => Payload::Fork {
@@ -195,13 +309,40 @@ pub mod pulsar {
                     filename,
                     argc,
                     argv,
+                    envp,
+                    cwd,
                     namespaces,
-                } => Payload::Exec {
-                    filename: filename.string(&buffer)?,
-                    argc: argc as usize,
-                    argv: extract_parameters(argv.bytes(&buffer)?).into(),
-                    namespaces,
-                },
+                    chrooted,
+                    ..
+                } => {
+                    let filename = filename.string(&buffer)?;
+                    let argv = extract_parameters(argv.bytes(&buffer)?);
+                    let cwd = cwd.string(&buffer)?;
+                    let script = script_path(&filename, &argv)
+                        .map(|script| resolve_script(&cwd, script))
+                        .unwrap_or_default();
+                    let shell = shell_command(&filename, &argv)
+                        .map(ShellCommand::parse)
+                        .unwrap_or_default();
+                    Payload::Exec {
+                        filename,
+                        argc: argc as usize,
+                        argv: argv.into(),
+                        namespaces,
+                        script,
+                        shell,
+                        env: EnvAllowlist::global()
+                            .read()
+                            .unwrap()
+                            .filter(envp.bytes(&buffer)?),
+                        cwd,
+                        chrooted,
+                        package: String::new(),
+                        package_version: String::new(),
+                        verified: false,
+                        package_checked: false,
+                    }
+                }
                 ProcessEvent::Exit { exit_code } => Payload::Exit { exit_code },
                 ProcessEvent::ChangeParent { ppid } => Payload::ChangeParent {
                     ppid: ppid.as_raw(),
@@ -219,9 +360,77 @@ pub mod pulsar {
                     cgroup_id: id,
                     attached_pid: pid.as_raw(),
                 },
+                ProcessEvent::ExecMemory {
+                    syscall,
+                    address,
+                    length,
+                    anonymous,
+                    writable,
+                    filename,
+                } => Payload::ExecMemory {
+                    syscall: match syscall {
+                        EXEC_MEMORY_MPROTECT => "mprotect",
+                        _ => "mmap",
+                    }
+                    .to_string(),
+                    address,
+                    length,
+                    filename: filename.string(&buffer)?,
+                    anonymous,
+                    writable,
+                },
+                ProcessEvent::TimeChanged {
+                    syscall,
+                    seconds,
+                    nanoseconds,
+                } => {
+                    let before = timestamp.wall_clock_before_step();
+                    let change = TimeChange::new(syscall, seconds, nanoseconds, before);
+                    Payload::TimeChanged {
+                        syscall: change.syscall.to_string(),
+                        time: change.time,
+                        delta: change.delta,
+                    }
+                }
+                ProcessEvent::SharedMemoryCreated {
+                    kind,
+                    key,
+                    size,
+                    name,
+                } => Payload::SharedMemoryCreated {
+                    kind: shm::kind_name(kind).to_string(),
+                    name: shm::segment_name(kind, key, &name.string(&buffer)?),
+                    size,
+                },
+                ProcessEvent::SharedMemoryMapped {
+                    kind,
+                    key,
+                    size,
+                    ino,
+                    first,
+                    writable,
+                    name,
+                } => Payload::SharedMemoryMapped {
+                    kind: shm::kind_name(kind).to_string(),
+                    name: shm::segment_name(kind, key, &name.string(&buffer)?),
+                    size,
+                    inode: ino,
+                    first_pid: first.as_raw(),
+                    mapper_pid: pid.as_raw(),
+                    writable,
+                },
             })
         }
     }
+
+    /// Make a relative script path absolute using the working directory of
+    /// the process.
+    fn resolve_script(cwd: &str, script: &str) -> String {
+        if script.starts_with('/') || cwd.is_empty() {
+            return script.to_string();
+        }
+        Path::new(cwd).join(script).to_string_lossy().into_owned()
+    }
 }
 
 #[cfg(feature = "test-suite")]
@@ -245,6 +454,7 @@ pub mod test_suite {
             tests: vec![
                 fork_event(),
                 exec_event(),
+                exec_env_event(),
                 relative_exec_event(),
                 exit_event(),
                 exit_event_no_thread(),
@@ -252,6 +462,10 @@ pub mod test_suite {
                 cgroup_mkdir(),
                 cgroup_rmdir(),
                 cgroup_attach(),
+                exec_memory_mmap(),
+                exec_memory_mprotect(),
+                shared_memory_created(),
+                shared_memory_mapped(),
             ],
         }
     }
@@ -304,11 +518,37 @@ pub mod test_suite {
         })
     }
 
+    fn exec_env_event() -> TestCase {
+        TestCase::new("exec_env_event", async {
+            let mut child_pid = Pid::from_raw(0);
+            test_runner()
+                .run(|| {
+                    let mut child = std::process::Command::new("echo")
+                        .env_clear()
+                        .env("LD_PRELOAD", "/tmp/x.so")
+                        .spawn()
+                        .unwrap();
+                    child_pid = Pid::from_raw(child.id() as i32);
+                    child.wait().unwrap();
+                })
+                .await
+                .expect_event_from_pid(
+                    child_pid,
+                    event_check!(
+                        ProcessEvent::Exec,
+                        (envp, String::from("LD_PRELOAD=/tmp/x.so\0"), "environment")
+                    ),
+                )
+                .report()
+        })
+    }
+
     fn relative_exec_event() -> TestCase {
         TestCase::new("relative_exec_event", async {
             let mut child_pid = Pid::from_raw(0);
             let echo_buff = find_executable("echo").canonicalize().unwrap();
             let echo_path = echo_buff.as_path().to_str().unwrap().to_string();
+            let echo_dir = echo_buff.parent().unwrap().to_str().unwrap().to_string();
             test_runner()
                 .run(|| {
                     let mut child = std::process::Command::new("./echo")
@@ -322,7 +562,12 @@ pub mod test_suite {
                 .await
                 .expect_event_from_pid(
                     child_pid,
-                    event_check!(ProcessEvent::Exec, (filename, echo_path, "exec filename")),
+                    event_check!(
+                        ProcessEvent::Exec,
+                        (filename, echo_path, "exec filename"),
+                        (cwd, echo_dir, "working directory"),
+                        (chrooted, false, "chrooted")
+                    ),
                 )
                 .report()
         })
@@ -499,4 +744,116 @@ pub mod test_suite {
                 .report()
         })
     }
+
+    fn exec_memory_mmap() -> TestCase {
+        TestCase::new("exec_memory_mmap", async {
+            test_runner()
+                .run(|| unsafe {
+                    let prot = nix::libc::PROT_READ | nix::libc::PROT_WRITE | nix::libc::PROT_EXEC;
+                    let flags = nix::libc::MAP_PRIVATE | nix::libc::MAP_ANONYMOUS;
+                    let address = nix::libc::mmap(std::ptr::null_mut(), 4096, prot, flags, -1, 0);
+                    assert_ne!(address, nix::libc::MAP_FAILED);
+                    nix::libc::munmap(address, 4096);
+                })
+                .await
+                .expect_event_from_pid(
+                    Pid::from_raw(std::process::id() as i32),
+                    event_check!(
+                        ProcessEvent::ExecMemory,
+                        (syscall, EXEC_MEMORY_MMAP, "syscall"),
+                        (anonymous, true, "anonymous"),
+                        (writable, true, "writable")
+                    ),
+                )
+                .report()
+        })
+    }
+
+    fn exec_memory_mprotect() -> TestCase {
+        TestCase::new("exec_memory_mprotect", async {
+            test_runner()
+                .run(|| unsafe {
+                    let prot = nix::libc::PROT_READ | nix::libc::PROT_WRITE;
+                    let flags = nix::libc::MAP_PRIVATE | nix::libc::MAP_ANONYMOUS;
+                    let address = nix::libc::mmap(std::ptr::null_mut(), 4096, prot, flags, -1, 0);
+                    assert_ne!(address, nix::libc::MAP_FAILED);
+                    let prot = nix::libc::PROT_READ | nix::libc::PROT_EXEC;
+                    assert_eq!(nix::libc::mprotect(address, 4096, prot), 0);
+                    nix::libc::munmap(address, 4096);
+                })
+                .await
+                .expect_event_from_pid(
+                    Pid::from_raw(std::process::id() as i32),
+                    event_check!(
+                        ProcessEvent::ExecMemory,
+                        (syscall, EXEC_MEMORY_MPROTECT, "syscall"),
+                        (anonymous, true, "anonymous"),
+                        (writable, true, "writable")
+                    ),
+                )
+                .report()
+        })
+    }
+
+    fn shared_memory_created() -> TestCase {
+        TestCase::new("shared_memory_created", async {
+            test_runner()
+                .run(|| unsafe {
+                    let fd = nix::libc::memfd_create(b"pulsar_created\0".as_ptr().cast(), 0);
+                    assert!(fd >= 0);
+                    nix::libc::close(fd);
+                })
+                .await
+                .expect_event_from_pid(
+                    Pid::from_raw(std::process::id() as i32),
+                    event_check!(
+                        ProcessEvent::SharedMemoryCreated,
+                        (kind, shm::SHM_MEMFD, "kind"),
+                        (name, "pulsar_created".to_string(), "name")
+                    ),
+                )
+                .report()
+        })
+    }
+
+    /// A memfd mapped by the test process, then by a child which didn't
+    /// inherit the mapping.
+    fn shared_memory_mapped() -> TestCase {
+        TestCase::new("shared_memory_mapped", async {
+            let mut child_pid = Pid::from_raw(0);
+            test_runner()
+                .run(|| unsafe {
+                    let fd = nix::libc::memfd_create(b"pulsar_mapped\0".as_ptr().cast(), 0);
+                    assert!(fd >= 0);
+                    assert_eq!(nix::libc::ftruncate(fd, 4096), 0);
+                    let map = || {
+                        let prot = nix::libc::PROT_READ | nix::libc::PROT_WRITE;
+                        let flags = nix::libc::MAP_SHARED;
+                        let address =
+                            nix::libc::mmap(std::ptr::null_mut(), 4096, prot, flags, fd, 0);
+                        assert_ne!(address, nix::libc::MAP_FAILED);
+                        address
+                    };
+                    let address = map();
+                    nix::libc::munmap(address, 4096);
+                    child_pid = fork_and_run(|| {
+                        map();
+                        exit(0)
+                    });
+                    nix::libc::close(fd);
+                })
+                .await
+                .expect_event_from_pid(
+                    child_pid,
+                    event_check!(
+                        ProcessEvent::SharedMemoryMapped,
+                        (kind, shm::SHM_MEMFD, "kind"),
+                        (first, Pid::from_raw(std::process::id() as i32), "first"),
+                        (size, 4096u64, "size"),
+                        (writable, true, "writable")
+                    ),
+                )
+                .report()
+        })
+    }
 }
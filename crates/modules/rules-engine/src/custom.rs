@@ -0,0 +1,216 @@
+//! Support for rules on custom payloads.
+//!
+//! Payloads defined outside of pulsar-core travel on the bus as [`Payload::Custom`].
+//! Their types must be registered in [`CustomPayloads`] to be used as rule `type`:
+//! at match time the payload is deserialized and checked with the compiled rule.
+
+use std::{collections::HashMap, sync::Arc};
+
+use pulsar_core::{
+    event::{CustomPayload, Header},
+    pdk::Event,
+};
+use validatron::{Condition, Field, Match, Rule, Validatron, ValidatronError};
+
+use crate::dsl;
+
+/// Closure checking if an event matches a rule on a custom payload.
+pub(crate) type CustomMatcher = Box<dyn Fn(&Event) -> bool + Send + Sync>;
+
+type CompileFn = dyn Fn(Rule) -> Result<CustomMatcher, ValidatronError> + Send + Sync;
+
+/// Registry of custom payload types which can be used in rules.
+#[derive(Default, Clone)]
+pub struct CustomPayloads {
+    compilers: HashMap<&'static str, Arc<CompileFn>>,
+    bool_fields: HashMap<&'static str, fn(&[Field]) -> bool>,
+}
+
+impl CustomPayloads {
+    /// Allow rules with `type` equal to `T::NAME`.
+    pub fn register<T: CustomPayload>(mut self) -> Self {
+        self.compilers.insert(
+            T::NAME,
+            Arc::new(|rule: Rule| {
+                let rule = Rule {
+                    name: rule.name,
+                    condition: into_struct_paths(rule.condition),
+                };
+                let compiled = rule.compile::<CustomEvent<T>>()?;
+
+                let matcher: CustomMatcher = Box::new(move |event: &Event| {
+                    T::from_payload(event.payload()).is_some_and(|payload| {
+                        compiled.is_match(&CustomEvent {
+                            header: event.header().clone(),
+                            payload,
+                        })
+                    })
+                });
+                Ok(matcher)
+            }),
+        );
+        self.bool_fields.insert(T::NAME, |field_path| {
+            dsl::is_bool_field::<CustomEvent<T>>(&into_struct_path(field_path.to_vec()))
+        });
+        self
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.compilers.contains_key(name)
+    }
+
+    /// Check if the field path of the custom payload `name` is a boolean.
+    pub(crate) fn is_bool_field(&self, name: &str, field_path: &[Field]) -> bool {
+        self.bool_fields
+            .get(name)
+            .is_some_and(|bool_field| bool_field(field_path))
+    }
+
+    /// Compile a rule on the custom payload `name`, if registered.
+    pub(crate) fn compile(
+        &self,
+        name: &str,
+        rule: Rule,
+    ) -> Option<Result<CustomMatcher, ValidatronError>> {
+        self.compilers.get(name).map(|compile| compile(rule))
+    }
+}
+
+/// Event with a custom payload, used to evaluate rules with the same field
+/// paths as native events.
+#[derive(Validatron)]
+struct CustomEvent<T: CustomPayload> {
+    header: Header,
+    payload: T,
+}
+
+/// The DSL parses `payload.field` as a field of the payload enum variant,
+/// while custom payloads are plain structs.
+fn into_struct_paths(condition: Condition) -> Condition {
+    match condition {
+        Condition::And { l, r } => Condition::And {
+            l: Box::new(into_struct_paths(*l)),
+            r: Box::new(into_struct_paths(*r)),
+        },
+        Condition::Or { l, r } => Condition::Or {
+            l: Box::new(into_struct_paths(*l)),
+            r: Box::new(into_struct_paths(*r)),
+        },
+        Condition::Not { inner } => Condition::Not {
+            inner: Box::new(into_struct_paths(*inner)),
+        },
+        Condition::Base {
+            field_path,
+            op,
+            value,
+        } => Condition::Base {
+            field_path: into_struct_path(field_path),
+            op,
+            value: match value {
+                Match::Field(field_path) => Match::Field(into_struct_path(field_path)),
+                value => value,
+            },
+        },
+    }
+}
+
+fn into_struct_path(field_path: Vec<Field>) -> Vec<Field> {
+    field_path
+        .into_iter()
+        .map(|field| match field {
+            Field::Adt { field_name, .. } => Field::Simple { field_name },
+            field => field,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use pulsar_core::{
+        event::{Payload, Severity},
+        PulsarPayload,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{RuleEngine, RuleFormat, RuleMatch, RuleMode};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PulsarPayload)]
+    #[payload(name = "UsbAttached")]
+    struct UsbEvent {
+        vendor: String,
+        product: u32,
+        #[payload(skip)]
+        raw: Vec<u8>,
+    }
+
+    fn event(payload: Payload) -> Event {
+        Event::new(
+            Header {
+                id: 1,
+                image: "/usr/lib/systemd/systemd-udevd".to_string(),
+                pid: 42,
+                parent_pid: 1,
+                source: "usb-monitor".into(),
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    #[test]
+    fn custom_payload_rule() {
+        let custom_payloads = CustomPayloads::default().register::<UsbEvent>();
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Unknown usb device
+  type: UsbAttached
+  condition: payload.vendor == "evil" AND header.pid == 42
+"#,
+            RuleFormat::Yaml,
+            &custom_payloads,
+        )
+        .unwrap();
+
+        let usb_event = |vendor: &str| {
+            event(
+                Payload::try_from(UsbEvent {
+                    vendor: vendor.to_string(),
+                    product: 1,
+                    raw: Vec::new(),
+                })
+                .unwrap(),
+            )
+        };
+
+        assert_eq!(
+            engine.process(&usb_event("evil")),
+            vec![RuleMatch {
+                name: "Unknown usb device",
+                mode: RuleMode::Alert,
+                action: None,
+                severity: Severity::Medium,
+                outputs: None,
+            }]
+        );
+        assert!(engine.process(&usb_event("good")).is_empty());
+        assert!(engine.process(&event(Payload::Empty)).is_empty());
+    }
+
+    #[test]
+    fn skipped_field() {
+        let custom_payloads = CustomPayloads::default().register::<UsbEvent>();
+        let result = RuleEngine::from_str(
+            r#"
+- name: Raw data
+  type: UsbAttached
+  condition: payload.raw CONTAINS 1
+"#,
+            RuleFormat::Yaml,
+            &custom_payloads,
+        );
+        assert!(result.is_err());
+    }
+}
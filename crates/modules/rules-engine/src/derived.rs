@@ -0,0 +1,153 @@
+//! Derived attributes.
+//!
+//! Rule files can define named conditions in a `derived` section, like
+//! `is_private_dst: payload.dst.ip STARTS_WITH "10."`, and rules check them as
+//! `derived.is_private_dst`. Unlike fragments, which are copied in every rule
+//! using them, a derived attribute is computed at most once per event and its
+//! value is shared by all the rules of the event type.
+
+use std::cell::OnceCell;
+
+use pulsar_core::pdk::Event;
+use thiserror::Error;
+use validatron::{Condition, Field, Match, Operator, RelationalOperator};
+
+use crate::engine::RuleMatcher;
+
+/// First field of the path of derived attributes in rule conditions.
+pub const DERIVED_PREFIX: &str = "derived";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DerivedError {
+    #[error("derived attribute '{0}' not found")]
+    NotFound(String),
+    #[error("derived attribute '{0}' defined more than once")]
+    Duplicated(String),
+    #[error("derived attribute '{0}' can only be compared to \"true\" or \"false\"")]
+    InvalidComparison(String),
+    #[error("derived attribute '{0}' can't reference other derived attributes")]
+    Nested(String),
+}
+
+/// Check if the field path is a derived attribute.
+pub fn is_reference(field_path: &[Field]) -> bool {
+    match field_path.first() {
+        Some(Field::Simple { field_name }) => field_name == DERIVED_PREFIX,
+        _ => false,
+    }
+}
+
+/// Check if the condition uses derived attributes.
+pub fn contains_reference(condition: &Condition) -> bool {
+    match condition {
+        Condition::And { l, r } | Condition::Or { l, r } => {
+            contains_reference(l) || contains_reference(r)
+        }
+        Condition::Not { inner } => contains_reference(inner),
+        Condition::Base {
+            field_path, value, ..
+        } => {
+            is_reference(field_path)
+                || matches!(value, Match::Field(field_path) if is_reference(field_path))
+        }
+    }
+}
+
+/// Name of the derived attribute checked by a base condition and the value it
+/// must have, like `is_private_dst` and `false` for `derived.is_private_dst != "true"`.
+pub fn reference(
+    field_path: &[Field],
+    op: &Operator,
+    value: &Match,
+) -> Result<(String, bool), DerivedError> {
+    // Compared to another field, like `header.is_interactive == derived.is_shell`
+    let field_path = match value {
+        Match::Field(value_path) if !is_reference(field_path) => value_path,
+        _ => field_path,
+    };
+    let name = match field_path {
+        [_, Field::Simple { field_name }] => field_name.clone(),
+        _ => {
+            let path: Vec<&str> = field_path
+                .iter()
+                .skip(1)
+                .map(|field| match field {
+                    Field::Simple { field_name } | Field::Adt { field_name, .. } => {
+                        field_name.as_str()
+                    }
+                })
+                .collect();
+            return Err(DerivedError::NotFound(path.join(".")));
+        }
+    };
+    let expected = match value {
+        Match::Value(value) if value == "true" => true,
+        Match::Value(value) if value == "false" => false,
+        _ => return Err(DerivedError::InvalidComparison(name)),
+    };
+    match op {
+        Operator::Relational(RelationalOperator::Equals) => Ok((name, expected)),
+        Operator::Relational(RelationalOperator::NotEquals) => Ok((name, !expected)),
+        _ => Err(DerivedError::InvalidComparison(name)),
+    }
+}
+
+/// Values of the derived attributes of an event type, computed for the event
+/// when a rule checks them for the first time.
+pub struct DerivedValues<'a> {
+    matchers: &'a [RuleMatcher],
+    values: Vec<OnceCell<bool>>,
+}
+
+impl<'a> DerivedValues<'a> {
+    pub fn new(matchers: &'a [RuleMatcher]) -> Self {
+        Self {
+            matchers,
+            values: matchers.iter().map(|_| OnceCell::new()).collect(),
+        }
+    }
+
+    pub fn get(&self, index: usize, event: &Event) -> bool {
+        *self.values[index].get_or_init(|| self.matchers[index].is_match(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(fields: &[&str]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|field| Field::Simple {
+                field_name: field.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn references() {
+        let equals = Operator::Relational(RelationalOperator::Equals);
+        let not_equals = Operator::Relational(RelationalOperator::NotEquals);
+        let value = |value: &str| Match::Value(value.to_string());
+
+        assert!(is_reference(&path(&["derived", "is_shell"])));
+        assert!(!is_reference(&path(&["header", "derived"])));
+        assert_eq!(
+            reference(&path(&["derived", "is_shell"]), &equals, &value("true")),
+            Ok(("is_shell".to_string(), true))
+        );
+        assert_eq!(
+            reference(&path(&["derived", "is_shell"]), &not_equals, &value("true")),
+            Ok(("is_shell".to_string(), false))
+        );
+        assert_eq!(
+            reference(&path(&["derived", "is_shell"]), &equals, &value("yes")),
+            Err(DerivedError::InvalidComparison("is_shell".to_string()))
+        );
+        assert_eq!(
+            reference(&path(&["derived", "a", "b"]), &equals, &value("true")),
+            Err(DerivedError::NotFound("a.b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Helpers to produce readable rule loading errors.
+//!
+//! DSL errors are reported with the position of the failing token, mapped to the
+//! rule file when the condition can be found verbatim inside it. Unknown fields are
+//! reported with the closest valid field name, taken from the type schema.
+
+use std::fmt;
+
+use lalrpop_util::ParseError;
+use pulsar_core::suggest::closest;
+use validatron::{Condition, Field, Match, Validatron, ValidatronClassKind};
+
+/// Position of an error inside a rule file.
+pub enum Location<'a> {
+    /// Line and column inside the file, both starting from 1.
+    File {
+        filename: &'a str,
+        line: usize,
+        column: usize,
+    },
+    /// Column inside the condition, starting from 1. Used when the condition
+    /// can't be found in the file, for example when it contains fragments.
+    Condition { filename: &'a str, column: usize },
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::File {
+                filename,
+                line,
+                column,
+            } => write!(f, "{filename}:{line}:{column}"),
+            Location::Condition { filename, column } => {
+                write!(f, "{filename}, condition column {column}")
+            }
+        }
+    }
+}
+
+impl<'a> Location<'a> {
+    /// Locate the byte `offset` of `condition` inside the file `body`.
+    pub fn new(filename: &'a str, body: &str, condition: &str, offset: usize) -> Self {
+        let column = condition[..offset].chars().count() + 1;
+
+        match body.find(condition) {
+            Some(start) => {
+                let before = &body[..start + offset];
+                let line = before.matches('\n').count() + 1;
+                let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+                Location::File {
+                    filename,
+                    line,
+                    column: before[line_start..].chars().count() + 1,
+                }
+            }
+            None => Location::Condition { filename, column },
+        }
+    }
+}
+
+/// Returns the byte range of the token which caused the parse error.
+pub fn error_span<T, E>(error: &ParseError<usize, T, E>, condition: &str) -> (usize, usize) {
+    match error {
+        ParseError::InvalidToken { location } => {
+            // The invalid token is a single character, which may be multi-byte
+            let len = condition
+                .get(*location..)
+                .and_then(|rest| rest.chars().next())
+                .map_or(0, char::len_utf8);
+            (*location, *location + len)
+        }
+        ParseError::UnrecognizedEOF { location, .. } => (*location, *location),
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, _, end),
+        } => (*start, *end),
+        ParseError::User { .. } => (0, condition.len()),
+    }
+}
+
+/// Render the condition with a marker under the given byte range.
+pub fn snippet(condition: &str, (start, end): (usize, usize)) -> String {
+    let padding = condition[..start].chars().count();
+    let width = condition[start..end].chars().count().max(1);
+    format!(
+        "    {condition}\n    {}{}",
+        " ".repeat(padding),
+        "^".repeat(width)
+    )
+}
+
+/// Search the condition for a field path not present in `T` and return it,
+/// together with the closest valid path if there is a plausible one.
+pub fn invalid_field<T: Validatron>(condition: &Condition) -> Option<(String, Option<String>)> {
+    match condition {
+        Condition::And { l, r } | Condition::Or { l, r } => {
+            invalid_field::<T>(l).or_else(|| invalid_field::<T>(r))
+        }
+        Condition::Not { inner } => invalid_field::<T>(inner),
+        Condition::Base {
+            field_path, value, ..
+        } => check_field_path::<T>(field_path).or_else(|| match value {
+            Match::Field(field_path) => check_field_path::<T>(field_path),
+            Match::Value(_) => None,
+        }),
+    }
+}
+
+fn check_field_path<T: Validatron>(field_path: &[Field]) -> Option<(String, Option<String>)> {
+    let full_path = path_name(field_path);
+
+    let mut class = T::get_class();
+    for (index, field) in field_path.iter().enumerate() {
+        let next = match (class.kind(), field) {
+            (ValidatronClassKind::Struct(ztruct), Field::Simple { field_name }) => {
+                match ztruct.get_field(field_name) {
+                    Some(attribute) => attribute.get_class(),
+                    None => {
+                        let suggestion = closest(field_name, ztruct.field_names());
+                        return Some((
+                            full_path,
+                            suggestion.map(|s| replace(field_path, index, s)),
+                        ));
+                    }
+                }
+            }
+            (
+                ValidatronClassKind::Enum(enumz),
+                Field::Adt {
+                    variant_name,
+                    field_name,
+                },
+            ) => match enumz.get_variant_field(variant_name, field_name) {
+                Some(attribute) => attribute.get_class(),
+                None => {
+                    let suggestion = closest(field_name, enumz.variant_field_names(variant_name));
+                    return Some((full_path, suggestion.map(|s| replace(field_path, index, s))));
+                }
+            },
+            // Type mismatches are already well described by the validation error
+            _ => return None,
+        };
+        class = next;
+    }
+
+    None
+}
+
+/// Field path as written in the rules, like `payload.filename`.
+pub fn path_name(field_path: &[Field]) -> String {
+    join(field_path.iter().map(field_name))
+}
+
+fn field_name(field: &Field) -> &str {
+    match field {
+        Field::Simple { field_name } | Field::Adt { field_name, .. } => field_name,
+    }
+}
+
+fn join<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    names.collect::<Vec<_>>().join(".")
+}
+
+/// Returns the field path with the element at `index` replaced.
+fn replace(field_path: &[Field], index: usize, name: &str) -> String {
+    join(
+        field_path
+            .iter()
+            .enumerate()
+            .map(|(i, field)| if i == index { name } else { field_name(field) }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use pulsar_core::pdk::Event;
+
+    use super::*;
+    use crate::dsl;
+
+    #[test]
+    fn location_in_file() {
+        let body = "- name: test\n  type: Exec\n  condition: header.imag == 3\n";
+        let condition = "header.imag == 3";
+        assert_eq!(
+            Location::new("test.yaml", body, condition, 7).to_string(),
+            "test.yaml:3:21"
+        );
+        assert_eq!(
+            Location::new("test.yaml", "", condition, 7).to_string(),
+            "test.yaml, condition column 8"
+        );
+    }
+
+    #[test]
+    fn parse_error_snippet() {
+        let condition = r#"header.image === "/bin/sh""#;
+        let error = dsl::dsl::ConditionParser::new()
+            .parse("Exec", &dsl::is_bool_field::<Event>, condition)
+            .unwrap_err();
+        assert_eq!(
+            snippet(condition, error_span(&error, condition)),
+            "    header.image === \"/bin/sh\"\n                   ^"
+        );
+    }
+
+    #[test]
+    fn parse_error_snippet_unicode() {
+        let condition = "header.image == é";
+        let error = dsl::dsl::ConditionParser::new()
+            .parse("Exec", &dsl::is_bool_field::<Event>, condition)
+            .unwrap_err();
+        assert_eq!(
+            snippet(condition, error_span(&error, condition)),
+            "    header.image == é\n                    ^"
+        );
+    }
+
+    #[test]
+    fn field_suggestion() {
+        let condition = dsl::dsl::ConditionParser::new()
+            .parse(
+                "Exec",
+                &dsl::is_bool_field::<Event>,
+                r#"header.image == "/bin/sh" AND payload.filname == "/bin/nc""#,
+            )
+            .unwrap();
+        assert_eq!(
+            invalid_field::<Event>(&condition),
+            Some((
+                "payload.filname".to_string(),
+                Some("payload.filename".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn host_field_suggestion() {
+        let condition = dsl::dsl::ConditionParser::new()
+            .parse(
+                "Exec",
+                &dsl::is_bool_field::<Event>,
+                r#"header.host.hostnam == "web-01""#,
+            )
+            .unwrap();
+        assert_eq!(
+            invalid_field::<Event>(&condition),
+            Some((
+                "header.host.hostnam".to_string(),
+                Some("header.host.hostname".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn valid_fields() {
+        let condition = dsl::dsl::ConditionParser::new()
+            .parse(
+                "Exec",
+                &dsl::is_bool_field::<Event>,
+                r#"header.image == payload.filename"#,
+            )
+            .unwrap();
+        assert_eq!(invalid_field::<Event>(&condition), None);
+    }
+}
@@ -1,24 +1,61 @@
+use std::any::TypeId;
+
 use lalrpop_util::lalrpop_mod;
 use thiserror::Error;
+use validatron::{Field, Validatron, ValidatronClassKind};
 
 #[derive(Error, Debug)]
 pub enum DslError {
     #[error("Empty list is not allowed")]
     EmptyList,
+    #[error("Field '{0}' is not a boolean, it must be compared to a value")]
+    NotBoolean(String),
 }
 
 lalrpop_mod!(#[allow(clippy::all)] pub dsl); // syntesized by LALRPOP
 
+/// Check if the field path of `T` is a boolean, which can be used alone in a
+/// condition, like `payload.host_process`.
+pub fn is_bool_field<T: Validatron>(field_path: &[Field]) -> bool {
+    let mut class = T::get_class();
+    for field in field_path {
+        let next = match (class.kind(), field) {
+            (ValidatronClassKind::Struct(ztruct), Field::Simple { field_name }) => ztruct
+                .get_field(field_name)
+                .map(|attribute| attribute.get_class()),
+            (
+                ValidatronClassKind::Enum(enumz),
+                Field::Adt {
+                    variant_name,
+                    field_name,
+                },
+            ) => enumz
+                .get_variant_field(variant_name, field_name)
+                .map(|attribute| attribute.get_class()),
+            _ => None,
+        };
+        match next {
+            Some(next) => class = next,
+            None => return false,
+        }
+    }
+    matches!(class.kind(), ValidatronClassKind::Primitive(primitive) if primitive.field_type_id() == TypeId::of::<bool>())
+}
+
 #[cfg(test)]
 mod tests {
-    use validatron::{Condition, Field, Match, Operator, RelationalOperator, StringOperator};
+    use lalrpop_util::ParseError;
+    use pulsar_core::pdk::Event;
+    use validatron::{
+        Condition, Field, Match, MultiOperator, Operator, RelationalOperator, StringOperator,
+    };
 
     use super::*;
 
     #[test]
     fn one_letter_field_start() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"a == 3"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"a == 3"#)
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![Field::Simple {
@@ -33,7 +70,7 @@ mod tests {
     #[test]
     fn one_letter_field_nested_start() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header.pid == 3"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"header.pid == 3"#)
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![
@@ -52,14 +89,15 @@ mod tests {
 
     #[test]
     fn no_number_field_start() {
-        let parsed = dsl::ConditionParser::new().parse("Exec", r#"4ad == 3"#);
+        let parsed =
+            dsl::ConditionParser::new().parse("Exec", &is_bool_field::<Event>, r#"4ad == 3"#);
         assert!(parsed.is_err());
     }
 
     #[test]
     fn struct_field_num() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header.pid == 3"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"header.pid == 3"#)
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![
@@ -79,7 +117,7 @@ mod tests {
     #[test]
     fn simple_field_num() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header == 3"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"header == 3"#)
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![Field::Simple {
@@ -94,7 +132,11 @@ mod tests {
     #[test]
     fn simple_field_path() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"filename == "/etc/passwd""#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"filename == "/etc/passwd""#,
+            )
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![Field::Simple {
@@ -109,7 +151,7 @@ mod tests {
     #[test]
     fn simple_field_string() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"image == "systemd""#)
+            .parse("Exec", &is_bool_field::<Event>, r#"image == "systemd""#)
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![Field::Simple {
@@ -124,7 +166,11 @@ mod tests {
     #[test]
     fn simple_field_string_op() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"image STARTS_WITH "systemd""#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"image STARTS_WITH "systemd""#,
+            )
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![Field::Simple {
@@ -136,10 +182,126 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn any_operator() {
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"argv ANY STARTS_WITH "--proxy=""#,
+            )
+            .unwrap();
+        let expected = Condition::Base {
+            field_path: vec![Field::Simple {
+                field_name: "argv".to_string(),
+            }],
+            op: Operator::Multi(MultiOperator::Any(Box::new(Operator::String(
+                StringOperator::StartsWith,
+            )))),
+            value: Match::Value("--proxy=".to_string()),
+        };
+        assert_eq!(parsed, expected);
+
+        let parsed = dsl::ConditionParser::new().parse(
+            "Exec",
+            &is_bool_field::<Event>,
+            r#"argv ANY ANY == "-k""#,
+        );
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn looks_like() {
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "DnsQuery",
+                &is_bool_field::<Event>,
+                r#"ANY payload.questions.normalized LOOKS_LIKE "paypal.com""#,
+            )
+            .unwrap();
+        let Condition::Base { op, .. } = parsed else {
+            panic!("unexpected condition {parsed:?}");
+        };
+        assert_eq!(
+            op,
+            Operator::Multi(MultiOperator::Any(Box::new(Operator::String(
+                StringOperator::LooksLike
+            ))))
+        );
+    }
+
+    #[test]
+    fn quantifiers() {
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "DnsQuery",
+                &is_bool_field::<Event>,
+                r#"ANY payload.questions.name ENDS_WITH ".onion""#,
+            )
+            .unwrap();
+        let expected = Condition::Base {
+            field_path: vec![
+                Field::Simple {
+                    field_name: "payload".to_string(),
+                },
+                Field::Adt {
+                    variant_name: "DnsQuery".to_string(),
+                    field_name: "questions".to_string(),
+                },
+                Field::Simple {
+                    field_name: "name".to_string(),
+                },
+            ],
+            op: Operator::Multi(MultiOperator::Any(Box::new(Operator::String(
+                StringOperator::EndsWith,
+            )))),
+            value: Match::Value(".onion".to_string()),
+        };
+        assert_eq!(parsed, expected);
+
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "DnsResponse",
+                &is_bool_field::<Event>,
+                r#"ALL payload.answers.ttl < 60"#,
+            )
+            .unwrap();
+        let Condition::Base { op, .. } = parsed else {
+            panic!("unexpected condition {parsed:?}");
+        };
+        assert_eq!(
+            op,
+            Operator::Multi(MultiOperator::All(Box::new(Operator::Relational(
+                RelationalOperator::Less
+            ))))
+        );
+
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"argv ALL STARTS_WITH "--""#,
+            )
+            .unwrap();
+        let Condition::Base { op, .. } = parsed else {
+            panic!("unexpected condition {parsed:?}");
+        };
+        assert_eq!(
+            op,
+            Operator::Multi(MultiOperator::All(Box::new(Operator::String(
+                StringOperator::StartsWith
+            ))))
+        );
+    }
+
     #[test]
     fn nested_field() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"struct.field.nested == 3"#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"struct.field.nested == 3"#,
+            )
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![
@@ -162,7 +324,11 @@ mod tests {
     #[test]
     fn not_condition() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"NOT(header.image != "/usr/bin/sshd")"#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"NOT(header.image != "/usr/bin/sshd")"#,
+            )
             .unwrap();
         let expected = Condition::Not {
             inner: Box::new(Condition::Base {
@@ -184,7 +350,11 @@ mod tests {
     #[test]
     fn not_condition_space() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"NOT (header.image != "/usr/bin/sshd")"#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"NOT (header.image != "/usr/bin/sshd")"#,
+            )
             .unwrap();
         let expected = Condition::Not {
             inner: Box::new(Condition::Base {
@@ -208,6 +378,7 @@ mod tests {
         let parsed = dsl::ConditionParser::new()
             .parse(
                 "Exec",
+                &is_bool_field::<Event>,
                 r#"header.image != "/usr/bin/sshd" AND payload.filename == "/etc/shadow""#,
             )
             .unwrap();
@@ -246,6 +417,7 @@ mod tests {
         let parsed = dsl::ConditionParser::new()
             .parse(
                 "FileOpen",
+                &is_bool_field::<Event>,
                 r#"header.image != "/usr/bin/sshd" OR payload.filename == "/etc/shadow""#,
             )
             .unwrap();
@@ -282,7 +454,7 @@ mod tests {
     #[test]
     fn complex_condition() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec",r#"header.image == "/usr/bin/sshd" OR NOT(header.image == "/usr/bin/cat" AND payload.filename == "/etc/passwd")"#)
+            .parse("Exec", &is_bool_field::<Event>,r#"header.image == "/usr/bin/sshd" OR NOT(header.image == "/usr/bin/cat" AND payload.filename == "/etc/passwd")"#)
             .unwrap();
         let expected = Condition::Or {
             l: Box::new(Condition::Base {
@@ -333,7 +505,11 @@ mod tests {
     #[test]
     fn list_single_string() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header.image IN ["/usr/bin/cat"]"#)
+            .parse(
+                "Exec",
+                &is_bool_field::<Event>,
+                r#"header.image IN ["/usr/bin/cat"]"#,
+            )
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![
@@ -353,7 +529,7 @@ mod tests {
     #[test]
     fn list_two_num() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header.pid IN [4,2]"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"header.pid IN [4,2]"#)
             .unwrap();
         let expected = Condition::Or {
             l: Box::new(Condition::Base {
@@ -387,7 +563,7 @@ mod tests {
     #[test]
     fn list_three_num() {
         let parsed = dsl::ConditionParser::new()
-            .parse("Exec", r#"header.pid IN [6,6,6]"#)
+            .parse("Exec", &is_bool_field::<Event>, r#"header.pid IN [6,6,6]"#)
             .unwrap();
         let expected = Condition::Or {
             l: Box::new(Condition::Or {
@@ -434,14 +610,22 @@ mod tests {
 
     #[test]
     fn list_void() {
-        let parsed = dsl::ConditionParser::new().parse("Exec", r#"header.pid IN []"#);
+        let parsed = dsl::ConditionParser::new().parse(
+            "Exec",
+            &is_bool_field::<Event>,
+            r#"header.pid IN []"#,
+        );
         assert!(parsed.is_err());
     }
 
     #[test]
     fn simple_field_compare() {
         let parsed = dsl::ConditionParser::new()
-            .parse("FileDelete", r#"header.image == payload.filename"#)
+            .parse(
+                "FileDelete",
+                &is_bool_field::<Event>,
+                r#"header.image == payload.filename"#,
+            )
             .unwrap();
         let expected = Condition::Base {
             field_path: vec![
@@ -465,4 +649,54 @@ mod tests {
         };
         assert_eq!(parsed, expected);
     }
+
+    #[test]
+    fn boolean_field() {
+        let parsed = dsl::ConditionParser::new()
+            .parse("Exec", &is_bool_field::<Event>, r#"NOT derived.from_ssh"#)
+            .unwrap();
+        let expected = Condition::Not {
+            inner: Box::new(Condition::Base {
+                field_path: vec![
+                    Field::Simple {
+                        field_name: "derived".to_string(),
+                    },
+                    Field::Simple {
+                        field_name: "from_ssh".to_string(),
+                    },
+                ],
+                op: Operator::Relational(RelationalOperator::Equals),
+                value: Match::Value("true".to_string()),
+            }),
+        };
+        assert_eq!(parsed, expected);
+
+        let parsed = dsl::ConditionParser::new()
+            .parse(
+                "SysctlChanged",
+                &is_bool_field::<Event>,
+                r#"payload.host_process"#,
+            )
+            .unwrap();
+        let Condition::Base { value, .. } = parsed else {
+            panic!("unexpected condition {parsed:?}");
+        };
+        assert_eq!(value, Match::Value("true".to_string()));
+
+        // Other fields must be compared to a value
+        let parsed = dsl::ConditionParser::new().parse(
+            "Exec",
+            &is_bool_field::<Event>,
+            r#"payload.filename AND header.pid == 1"#,
+        );
+        assert!(matches!(
+            parsed,
+            Err(ParseError::User {
+                error: DslError::NotBoolean(field)
+            }) if field == "payload.filename"
+        ));
+        let parsed =
+            dsl::ConditionParser::new().parse("Exec", &is_bool_field::<Event>, r#"header"#);
+        assert!(parsed.is_err());
+    }
 }
@@ -1,23 +1,116 @@
-use std::{collections::HashMap, fs, path::Path, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 use glob::glob;
 use pulsar_core::{
-    event::PayloadDiscriminant,
+    event::{Evidence, PayloadDiscriminant, RulePack, Severity, Threat, Value},
+    inventory,
     pdk::{Event, ModuleSender},
+    rule_stats::{self, RuleProfile},
+    suggest::closest,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use validatron::{Rule, Ruleset, ValidatronError};
+use validatron::{CompiledRule, Condition, Field, Rule, ValidatronError};
 
-use crate::dsl;
+use crate::{
+    custom::{CustomMatcher, CustomPayloads},
+    derived::{self, DerivedError, DerivedValues},
+    diagnostics::{self, Location},
+    dsl, evidence,
+    fragments::{self, FragmentError},
+};
 
-const RULE_EXTENSION: &str = "yaml";
+/// Log target used to report matches of rules in [`RuleMode::Audit`].
+const AUDIT_LOG_TARGET: &str = "rules-engine::audit";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserRule {
     name: String,
     r#type: String,
     condition: String,
+    #[serde(default)]
+    mode: RuleMode,
+    /// Playbook run by the threat-response module when the rule raises a threat.
+    #[serde(default)]
+    action: Option<String>,
+    /// Severity of the threats, used to route them to the outputs.
+    #[serde(default)]
+    severity: Severity,
+    /// Outputs handling the threats, overriding the escalation policy.
+    #[serde(default)]
+    outputs: Option<Vec<String>>,
+    /// Compare strings ignoring the case, see [`CaseInsensitive`].
+    #[serde(default)]
+    case_insensitive: Option<CaseInsensitive>,
+}
+
+/// Conditions of a rule comparing strings ignoring the case, so that
+/// `/usr/bin/NC` matches `payload.filename == "/usr/bin/nc"`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CaseInsensitive {
+    /// Every condition of the rule when `true`
+    All(bool),
+    /// The conditions on these fields, like `[header.image, payload.filename]`
+    Fields(Vec<String>),
+}
+
+impl CaseInsensitive {
+    fn applies_to(&self, field_path: &[Field]) -> bool {
+        match self {
+            CaseInsensitive::All(all) => *all,
+            CaseInsensitive::Fields(fields) => fields.contains(&diagnostics::path_name(field_path)),
+        }
+    }
+
+    /// A field listed which is not checked by `condition`, most likely a typo.
+    /// The fields of the condition are validated when compiling it.
+    fn unknown_field(&self, condition: &Condition) -> Option<String> {
+        let CaseInsensitive::Fields(fields) = self else {
+            return None;
+        };
+        let used = evidence::field_paths(condition);
+        fields.iter().find(|field| !used.contains(field)).cloned()
+    }
+}
+
+/// Content of a rule file.
+///
+/// A file can be either a plain list of rules or a document with a `rules` list, a
+/// `fragments` map of named sub-conditions and a `derived` map of named attributes.
+/// Fragments and derived attributes are shared by all the rule files.
+#[derive(Debug, Default, Deserialize)]
+struct RuleDocument {
+    /// Version of the rule pack, reported in the host inventory.
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    fragments: HashMap<String, String>,
+    #[serde(default)]
+    derived: HashMap<String, String>,
+    #[serde(default)]
+    rules: Vec<UserRule>,
+}
+
+/// Describes what happens when a rule matches an event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMode {
+    /// Matches are sent on the bus as threat events.
+    #[default]
+    Alert,
+    /// Matches are only counted and logged. Useful to safely roll out new rules.
+    Audit,
 }
 
 /// Describes Pulsar Engine error.
@@ -32,121 +125,602 @@ pub enum PulsarEngineError {
         #[source]
         error: std::io::Error,
     },
-    #[error("Error parsing rule file: {filename}")]
+    #[error("Error parsing rule file {filename}: {error}")]
     RuleParsing {
         filename: String,
         #[source]
-        error: serde_yaml::Error,
+        error: RuleFormatError,
+    },
+    #[error("Error validating dsl of rule '{name}' at {location}: {message}\n{snippet}")]
+    DslError {
+        name: String,
+        location: String,
+        message: String,
+        snippet: String,
     },
-    #[error("Error validating dsl '{0}': {1}")]
-    DslError(String, String),
-    #[error("Error compiling rules: {error}")]
+    #[error("Error compiling rule '{name}' in {filename}: {error}{help}")]
     RuleCompile {
+        name: String,
+        filename: String,
         #[source]
         error: ValidatronError,
+        help: String,
     },
     #[error("Payload type '{0}' not found")]
     PayloadTypeNotFound(String),
+    #[error("Error loading fragments from {filename}")]
+    FragmentLoading {
+        filename: String,
+        #[source]
+        error: FragmentError,
+    },
+    #[error("Error expanding fragments in rule '{name}'")]
+    FragmentExpansion {
+        name: String,
+        #[source]
+        error: FragmentError,
+    },
+    #[error("Error loading derived attributes from {filename}")]
+    DerivedLoading {
+        filename: String,
+        #[source]
+        error: DerivedError,
+    },
+    #[error("Error checking derived attributes in rule '{name}'")]
+    DerivedReference {
+        name: String,
+        #[source]
+        error: DerivedError,
+    },
+    #[error("Field '{field}' in case_insensitive of rule '{name}' is not in its condition")]
+    CaseInsensitiveField { name: String, field: String },
 }
 
-#[derive(Clone)]
-pub struct PulsarEngine {
-    internal: Arc<PulsarEngineInternal>,
+/// Describes an error deserializing a rule file.
+#[derive(Error, Debug)]
+pub enum RuleFormatError {
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Toml(#[from] toml_edit::easy::de::Error),
 }
 
-impl PulsarEngine {
-    pub fn new(rules_path: &Path, sender: ModuleSender) -> Result<Self, PulsarEngineError> {
-        let raw_rules = load_user_rules_from_dir(rules_path)?;
+/// Rule engine decoupled from the Pulsar daemon.
+///
+/// It loads and compiles the rules and returns the matches of every processed event,
+/// allowing other projects to embed the detection engine over their own event sources.
+pub struct RuleEngine {
+    rulesets: HashMap<PayloadDiscriminant, Ruleset>,
+    rule_packs: Vec<RulePack>,
+}
 
-        let rules = parse_rules(raw_rules)?;
+impl RuleEngine {
+    /// Load all the rule files found in the given directory and its subdirectories.
+    ///
+    /// Rules can target the payload types registered in `custom_payloads`.
+    pub fn from_dir(
+        rules_path: &Path,
+        custom_payloads: &CustomPayloads,
+    ) -> Result<Self, PulsarEngineError> {
+        Self::from_loaded(load_user_rules_from_dir(rules_path)?, custom_payloads)
+    }
 
-        let mut rulesets = HashMap::new();
+    /// Load rules from the content of a single rule file.
+    pub fn from_str(
+        body: &str,
+        format: RuleFormat,
+        custom_payloads: &CustomPayloads,
+    ) -> Result<Self, PulsarEngineError> {
+        let rule_file = RuleFile {
+            path: "<memory>".to_string(),
+            body: body.to_string(),
+            format,
+        };
+        Self::from_loaded(
+            LoadedRules::from_files(vec![Arc::new(rule_file)])?,
+            custom_payloads,
+        )
+    }
 
-        for (discriminant, rules) in rules {
-            let ruleset = Ruleset::from_rules(rules)
-                .map_err(|error| PulsarEngineError::RuleCompile { error })?;
+    fn from_loaded(
+        mut loaded_rules: LoadedRules,
+        custom_payloads: &CustomPayloads,
+    ) -> Result<Self, PulsarEngineError> {
+        let rule_packs = std::mem::take(&mut loaded_rules.rule_packs);
+        let rulesets = parse_rules(loaded_rules, &rule_packs, custom_payloads)?;
 
-            if rulesets.insert(discriminant, ruleset).is_some() {
-                unreachable!("hashmap rules -> ruleset is a 1:1 map")
-            };
+        for (discriminant, ruleset) in &rulesets {
+            log::debug!(
+                "Loaded {} rules for {:?}",
+                ruleset.rules.len(),
+                discriminant
+            );
         }
 
-        Ok(PulsarEngine {
-            internal: Arc::new(PulsarEngineInternal { rulesets, sender }),
+        Ok(Self {
+            rulesets,
+            rule_packs,
         })
     }
 
+    /// Returns the loaded rule files, with their version.
+    pub fn rule_packs(&self) -> &[RulePack] {
+        &self.rule_packs
+    }
+
+    /// Check the event against the rules of its payload type and return the matching ones.
+    pub fn process(&self, event: &Event) -> Vec<RuleMatch<'_>> {
+        // Get payload discriminant from current event
+        let discriminant = PayloadDiscriminant::from(event.payload());
+
+        // Match against a discriminant ruleset if there is one
+        let Some(ruleset) = self.rulesets.get(&discriminant) else {
+            return Vec::new();
+        };
+
+        let derived = DerivedValues::new(&ruleset.derived);
+        ruleset
+            .rules
+            .iter()
+            .filter_map(|rule| rule.check(event, &derived))
+            .map(|(rule, _)| rule.to_match())
+            .collect()
+    }
+
+    /// Like [`RuleEngine::process`], with the [`Evidence`] of every match.
+    pub fn process_with_evidence(&self, event: &Event) -> Vec<(RuleMatch<'_>, Evidence)> {
+        let discriminant = PayloadDiscriminant::from(event.payload());
+        let Some(ruleset) = self.rulesets.get(&discriminant) else {
+            return Vec::new();
+        };
+
+        let derived = DerivedValues::new(&ruleset.derived);
+        ruleset
+            .rules
+            .iter()
+            .filter_map(|rule| rule.check(event, &derived))
+            .map(|(rule, matches)| {
+                let evidence = evidence::collect(event, &rule.fields, &rule.version, matches);
+                (rule.to_match(), evidence)
+            })
+            .collect()
+    }
+
+    /// Returns the evaluations, matches and evaluation time of every loaded rule
+    /// since the engine creation.
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        self.rulesets
+            .values()
+            .flat_map(|ruleset| &ruleset.rules)
+            .map(|rule| RuleStats {
+                name: rule.name.clone(),
+                mode: rule.mode,
+                evaluations: rule.evaluations.load(Ordering::Relaxed),
+                matches: rule.matches.load(Ordering::Relaxed),
+                eval_time_ns: rule.eval_time_ns.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Returns the `outputs` of the rules which are not among the `modules`:
+    /// their threats would never be notified there.
+    pub fn unknown_outputs(&self, modules: &[String]) -> Vec<UnknownOutput> {
+        let mut unknown: Vec<UnknownOutput> = self
+            .rulesets
+            .values()
+            .flat_map(|ruleset| &ruleset.rules)
+            .flat_map(|rule| {
+                rule.outputs
+                    .iter()
+                    .flatten()
+                    .filter(|output| !modules.contains(output))
+                    .map(|output| UnknownOutput {
+                        rule: rule.name.clone(),
+                        output: output.clone(),
+                        suggestion: closest(output, modules.iter().map(String::as_str))
+                            .map(str::to_string),
+                    })
+            })
+            .collect();
+        unknown.sort_by(|a, b| (&a.rule, &a.output).cmp(&(&b.rule, &b.output)));
+        unknown
+    }
+}
+
+/// An output of a rule which is not a module, see [`RuleEngine::unknown_outputs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownOutput {
+    pub rule: String,
+    pub output: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule '{}' routes its threats to unknown output {}",
+            self.rule, self.output
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean {suggestion}?")?;
+        }
+        Ok(())
+    }
+}
+
+/// A rule matching an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch<'a> {
+    pub name: &'a str,
+    pub mode: RuleMode,
+    /// Playbook to run on the threat, see [`RuleEngineData`].
+    pub action: Option<&'a str>,
+    pub severity: Severity,
+    /// Outputs handling the threat instead of the ones of the escalation policy.
+    pub outputs: Option<&'a [String]>,
+}
+
+/// [`RuleEngine`] running inside the Pulsar daemon, sending matches on the bus.
+#[derive(Clone)]
+pub struct PulsarEngine {
+    internal: Arc<PulsarEngineInternal>,
+}
+
+impl PulsarEngine {
+    pub fn new(
+        rules_path: &Path,
+        sender: ModuleSender,
+        custom_payloads: &CustomPayloads,
+    ) -> Result<Self, PulsarEngineError> {
+        let engine = RuleEngine::from_dir(rules_path, custom_payloads)?;
+        inventory::set_rule_packs(engine.rule_packs().to_vec());
+
+        let internal = Arc::new(PulsarEngineInternal { engine, sender });
+        // Served for `pulsar rules stats` until the engine is dropped
+        let weak_internal = Arc::downgrade(&internal);
+        rule_stats::set_provider(move || {
+            weak_internal
+                .upgrade()
+                .map(|internal| {
+                    internal
+                        .engine
+                        .rule_stats()
+                        .into_iter()
+                        .map(RuleProfile::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+
+        Ok(PulsarEngine { internal })
+    }
+
     pub fn process(&self, event: &Event) {
         // Run the engine only on non threat events to avoid creating loops
         if event.header().threat.is_none() {
-            // Get payload discriminant from current event
-            let discriminant = PayloadDiscriminant::from(event.payload());
-
-            // Match against a discriminant ruleset if there is one
-            if let Some(ruleset) = self.internal.rulesets.get(&discriminant) {
-                for rule in ruleset.matches(event) {
-                    self.internal
-                        .sender
-                        .send_threat_derived(event, rule.name.clone(), None)
+            for (rule, evidence) in self.internal.engine.process_with_evidence(event) {
+                match rule.mode {
+                    RuleMode::Alert => self.internal.sender.send_derived_threat(
+                        event,
+                        Threat {
+                            id: String::new(),
+                            source: crate::MODULE_NAME.into(),
+                            description: rule.name.to_string(),
+                            severity: rule.severity,
+                            outputs: rule.outputs.map(<[String]>::to_vec),
+                            extra: rule.action.and_then(|action| {
+                                Value::try_from(RuleEngineData {
+                                    rule_name: rule.name.to_string(),
+                                    action: Some(action.to_string()),
+                                })
+                                .ok()
+                            }),
+                            evidence: Some(evidence),
+                        },
+                    ),
+                    RuleMode::Audit => log::info!(
+                        target: AUDIT_LOG_TARGET,
+                        "rule '{}' matched [{}:{}] {}",
+                        rule.name,
+                        event.header().pid,
+                        event.header().image,
+                        event.payload()
+                    ),
                 }
             }
         }
     }
+
+    /// Returns the statistics of every loaded rule since the engine creation.
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        self.internal.engine.rule_stats()
+    }
+
+    /// See [`RuleEngine::unknown_outputs`].
+    pub fn unknown_outputs(&self, modules: &[String]) -> Vec<UnknownOutput> {
+        self.internal.engine.unknown_outputs(modules)
+    }
+}
+
+/// Counters of a single rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleStats {
+    pub name: String,
+    pub mode: RuleMode,
+    /// Events of the rule type checked against it
+    pub evaluations: u64,
+    pub matches: u64,
+    /// Total time spent checking the rule, including the derived attributes it
+    /// computed first
+    pub eval_time_ns: u64,
 }
 
-fn load_user_rules_from_dir(rules_path: &Path) -> Result<Vec<UserRule>, PulsarEngineError> {
+impl From<RuleStats> for RuleProfile {
+    fn from(stats: RuleStats) -> Self {
+        let mode = match stats.mode {
+            RuleMode::Alert => "alert",
+            RuleMode::Audit => "audit",
+        };
+        RuleProfile {
+            name: stats.name,
+            mode: mode.to_string(),
+            evaluations: stats.evaluations,
+            matches: stats.matches,
+            eval_time_ns: stats.eval_time_ns,
+        }
+    }
+}
+
+/// Rules, fragments and derived attributes collected from all the rule files.
+#[derive(Default)]
+struct LoadedRules {
+    fragments: HashMap<String, String>,
+    derived: HashMap<String, (String, Arc<RuleFile>)>,
+    rules: Vec<(UserRule, Arc<RuleFile>)>,
+    rule_packs: Vec<RulePack>,
+}
+
+fn load_user_rules_from_dir(rules_path: &Path) -> Result<LoadedRules, PulsarEngineError> {
     let mut rule_files = Vec::new();
 
-    let expr = format!("{}/**/*.{}", rules_path.display(), RULE_EXTENSION);
+    let expr = format!("{}/**/*", rules_path.display());
     let entries = glob(&expr)?;
     for path in entries.flatten() {
-        let rule_file = RuleFile::from(&path)?;
-        rule_files.push(rule_file);
+        let Some(format) = RuleFormat::from_path(&path) else {
+            continue;
+        };
+        let rule_file = RuleFile::from(&path, format)?;
+        rule_files.push(Arc::new(rule_file));
     }
 
-    let rules = rule_files
-        .into_iter()
-        .map(|rule_file| {
-            serde_yaml::from_str::<Vec<UserRule>>(&rule_file.body).map_err(|error| {
-                PulsarEngineError::RuleParsing {
-                    filename: rule_file.path,
-                    error,
+    LoadedRules::from_files(rule_files)
+}
+
+impl LoadedRules {
+    /// Collect rules, fragments and derived attributes of the given files.
+    fn from_files(rule_files: Vec<Arc<RuleFile>>) -> Result<Self, PulsarEngineError> {
+        let mut result = LoadedRules::default();
+
+        for rule_file in rule_files {
+            let document = rule_file.parse()?;
+
+            result.rule_packs.push(RulePack {
+                name: rule_file.path.clone(),
+                version: document
+                    .version
+                    .unwrap_or_else(|| content_hash(&rule_file.body)),
+            });
+            for (name, fragment) in document.fragments {
+                if result.fragments.contains_key(&name) {
+                    return Err(PulsarEngineError::FragmentLoading {
+                        filename: rule_file.path.clone(),
+                        error: FragmentError::Duplicated(name),
+                    });
                 }
-            })
-        })
-        .collect::<Result<Vec<Vec<UserRule>>, PulsarEngineError>>()?;
+                result.fragments.insert(name, fragment);
+            }
+            for (name, condition) in document.derived {
+                if result.derived.contains_key(&name) {
+                    return Err(PulsarEngineError::DerivedLoading {
+                        filename: rule_file.path.clone(),
+                        error: DerivedError::Duplicated(name),
+                    });
+                }
+                result.derived.insert(name, (condition, rule_file.clone()));
+            }
+
+            result.rules.extend(
+                document
+                    .rules
+                    .into_iter()
+                    .map(|rule| (rule, rule_file.clone())),
+            );
+        }
 
-    Ok(rules.into_iter().flatten().collect())
+        Ok(result)
+    }
+}
+
+/// FNV-1a hash of a rule file, formatted as hexadecimal, used as the version of
+/// rule packs without one.
+fn content_hash(body: &str) -> String {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let hash = body.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    format!("{hash:016x}")
 }
 
 fn parse_rules(
-    user_rules: Vec<UserRule>,
-) -> Result<HashMap<PayloadDiscriminant, Vec<Rule>>, PulsarEngineError> {
+    loaded_rules: LoadedRules,
+    rule_packs: &[RulePack],
+    custom_payloads: &CustomPayloads,
+) -> Result<HashMap<PayloadDiscriminant, Ruleset>, PulsarEngineError> {
     let parser = dsl::dsl::ConditionParser::new();
+    let mut derived_compiler = DerivedCompiler {
+        parser: &parser,
+        fragments: &loaded_rules.fragments,
+        definitions: &loaded_rules.derived,
+        custom_payloads,
+        indexes: HashMap::new(),
+    };
 
-    let rules = user_rules
-        .into_iter()
-        .map(|user_rule| parse_rule(&parser, user_rule))
-        .collect::<Result<Vec<(PayloadDiscriminant, Rule)>, PulsarEngineError>>()?;
+    let versions: HashMap<&str, &str> = rule_packs
+        .iter()
+        .map(|rule_pack| (rule_pack.name.as_str(), rule_pack.version.as_str()))
+        .collect();
 
-    let mut m = HashMap::new();
-    for (k, v) in rules {
-        m.entry(k).or_insert_with(Vec::new).push(v)
+    let mut m: HashMap<PayloadDiscriminant, Ruleset> = HashMap::new();
+    for (mut user_rule, rule_file) in loaded_rules.rules {
+        user_rule.condition = fragments::expand(&user_rule.condition, &loaded_rules.fragments)
+            .map_err(|error| PulsarEngineError::FragmentExpansion {
+                name: user_rule.name.clone(),
+                error,
+            })?;
+        let mode = user_rule.mode;
+        let action = user_rule.action.clone();
+        let severity = user_rule.severity;
+        let outputs = user_rule.outputs.clone();
+        let payload_type = user_rule.r#type.clone();
+        let case_insensitive = user_rule.case_insensitive.clone();
+        let (discriminant, mut rule) = parse_rule(&parser, user_rule, custom_payloads, &rule_file)?;
+        if let Some(case_insensitive) = &case_insensitive {
+            if let Some(field) = case_insensitive.unknown_field(&rule.condition) {
+                return Err(PulsarEngineError::CaseInsensitiveField {
+                    name: rule.name,
+                    field,
+                });
+            }
+            rule.condition = fold_case(rule.condition, case_insensitive);
+        }
+        let name = rule.name.clone();
+        let fields = evidence::field_paths(&rule.condition);
+        let ruleset = m.entry(discriminant).or_default();
+        let predicate = compile_predicate(
+            rule,
+            &payload_type,
+            custom_payloads,
+            &rule_file,
+            &mut |derived_name| {
+                derived_compiler.index(derived_name, &name, &payload_type, &mut ruleset.derived)
+            },
+        )?;
+
+        ruleset.rules.push(EngineRule {
+            name,
+            predicate,
+            version: versions
+                .get(rule_file.path.as_str())
+                .unwrap_or(&"")
+                .to_string(),
+            fields,
+            mode,
+            action,
+            severity,
+            outputs,
+            evaluations: AtomicU64::new(0),
+            matches: AtomicU64::new(0),
+            eval_time_ns: AtomicU64::new(0),
+        })
     }
 
     Ok(m)
 }
 
+/// Compiles the derived attributes used by the rules, once for every payload type.
+struct DerivedCompiler<'a> {
+    parser: &'a dsl::dsl::ConditionParser,
+    fragments: &'a HashMap<String, String>,
+    definitions: &'a HashMap<String, (String, Arc<RuleFile>)>,
+    custom_payloads: &'a CustomPayloads,
+    /// Index in the ruleset by payload type and name of the compiled attributes.
+    indexes: HashMap<(String, String), usize>,
+}
+
+impl DerivedCompiler<'_> {
+    /// Index of the derived attribute `name` in `matchers`, the derived attributes
+    /// of the ruleset of `payload_type`, compiling it the first time it's used.
+    fn index(
+        &mut self,
+        name: &str,
+        rule_name: &str,
+        payload_type: &str,
+        matchers: &mut Vec<RuleMatcher>,
+    ) -> Result<usize, PulsarEngineError> {
+        let key = (payload_type.to_string(), name.to_string());
+        if let Some(index) = self.indexes.get(&key) {
+            return Ok(*index);
+        }
+
+        let (condition, rule_file) =
+            self.definitions
+                .get(name)
+                .ok_or_else(|| PulsarEngineError::DerivedReference {
+                    name: rule_name.to_string(),
+                    error: DerivedError::NotFound(name.to_string()),
+                })?;
+        let derived_name = format!("{}.{name}", derived::DERIVED_PREFIX);
+        let condition = fragments::expand(condition, self.fragments).map_err(|error| {
+            PulsarEngineError::FragmentExpansion {
+                name: derived_name.clone(),
+                error,
+            }
+        })?;
+        let condition = parse_condition(
+            self.parser,
+            &derived_name,
+            payload_type,
+            self.custom_payloads,
+            &condition,
+            rule_file,
+        )?;
+        if derived::contains_reference(&condition) {
+            return Err(PulsarEngineError::DerivedReference {
+                name: derived_name,
+                error: DerivedError::Nested(name.to_string()),
+            });
+        }
+        let rule = Rule {
+            name: derived_name,
+            condition,
+        };
+        matchers.push(compile_rule(
+            rule,
+            payload_type,
+            self.custom_payloads,
+            rule_file,
+        )?);
+
+        self.indexes.insert(key, matchers.len() - 1);
+        Ok(matchers.len() - 1)
+    }
+}
+
 fn parse_rule(
     parser: &dsl::dsl::ConditionParser,
     user_rule: UserRule,
+    custom_payloads: &CustomPayloads,
+    rule_file: &RuleFile,
 ) -> Result<(PayloadDiscriminant, Rule), PulsarEngineError> {
-    let payload_discriminant = PayloadDiscriminant::from_str(&user_rule.r#type)
-        .map_err(|_| PulsarEngineError::PayloadTypeNotFound(user_rule.r#type.clone()))?;
+    let payload_discriminant = match PayloadDiscriminant::from_str(&user_rule.r#type) {
+        Ok(payload_discriminant) => payload_discriminant,
+        Err(_) if custom_payloads.contains(&user_rule.r#type) => PayloadDiscriminant::Custom,
+        Err(_) => return Err(PulsarEngineError::PayloadTypeNotFound(user_rule.r#type)),
+    };
 
-    let condition = parser
-        .parse(&user_rule.r#type, &user_rule.condition)
-        .map_err(|err| PulsarEngineError::DslError(user_rule.condition.clone(), err.to_string()))?;
+    let condition = parse_condition(
+        parser,
+        &user_rule.name,
+        &user_rule.r#type,
+        custom_payloads,
+        &user_rule.condition,
+        rule_file,
+    )?;
 
     Ok((
         payload_discriminant,
@@ -157,42 +731,350 @@ fn parse_rule(
     ))
 }
 
+/// Parse the condition of a rule, locating syntax errors in its rule file.
+fn parse_condition(
+    parser: &dsl::dsl::ConditionParser,
+    name: &str,
+    payload_type: &str,
+    custom_payloads: &CustomPayloads,
+    condition: &str,
+    rule_file: &RuleFile,
+) -> Result<Condition, PulsarEngineError> {
+    let bool_field = |field_path: &[Field]| {
+        if PayloadDiscriminant::from_str(payload_type).is_ok() {
+            dsl::is_bool_field::<Event>(field_path)
+        } else {
+            custom_payloads.is_bool_field(payload_type, field_path)
+        }
+    };
+    parser
+        .parse(payload_type, &bool_field, condition)
+        .map_err(|err| {
+            let span = diagnostics::error_span(&err, condition);
+            let location = Location::new(&rule_file.path, &rule_file.body, condition, span.0);
+            PulsarEngineError::DslError {
+                name: name.to_string(),
+                location: location.to_string(),
+                message: err.to_string(),
+                snippet: diagnostics::snippet(condition, span),
+            }
+        })
+}
+
+/// Make the conditions in the scope of `case_insensitive` ignore the case.
+/// Only string fields are affected: validatron lowercases their values when
+/// compiling them, and the other types, like file flags, are left unchanged.
+fn fold_case(condition: Condition, case_insensitive: &CaseInsensitive) -> Condition {
+    let fold = |condition: Box<Condition>| Box::new(fold_case(*condition, case_insensitive));
+    match condition {
+        Condition::And { l, r } => Condition::And {
+            l: fold(l),
+            r: fold(r),
+        },
+        Condition::Or { l, r } => Condition::Or {
+            l: fold(l),
+            r: fold(r),
+        },
+        Condition::Not { inner } => Condition::Not { inner: fold(inner) },
+        Condition::Base {
+            field_path,
+            op,
+            value,
+        } if case_insensitive.applies_to(&field_path) && !derived::is_reference(&field_path) => {
+            Condition::Base {
+                field_path,
+                op: op.case_insensitive(),
+                value,
+            }
+        }
+        condition => condition,
+    }
+}
+
+/// Compile a rule, checking the derived attributes it uses with the ones of
+/// `derived_index`.
+fn compile_predicate(
+    rule: Rule,
+    payload_type: &str,
+    custom_payloads: &CustomPayloads,
+    rule_file: &RuleFile,
+    derived_index: &mut dyn FnMut(&str) -> Result<usize, PulsarEngineError>,
+) -> Result<Predicate, PulsarEngineError> {
+    // Conditions without derived attributes are compiled in a single closure
+    if !derived::contains_reference(&rule.condition) {
+        return compile_rule(rule, payload_type, custom_payloads, rule_file).map(Predicate::Match);
+    }
+
+    let Rule { name, condition } = rule;
+    let mut compile = |condition: Box<Condition>| {
+        let rule = Rule {
+            name: name.clone(),
+            condition: *condition,
+        };
+        compile_predicate(
+            rule,
+            payload_type,
+            custom_payloads,
+            rule_file,
+            derived_index,
+        )
+        .map(Box::new)
+    };
+    match condition {
+        Condition::And { l, r } => Ok(Predicate::And(compile(l)?, compile(r)?)),
+        Condition::Or { l, r } => Ok(Predicate::Or(compile(l)?, compile(r)?)),
+        Condition::Not { inner } => Ok(Predicate::Not(compile(inner)?)),
+        Condition::Base {
+            field_path,
+            op,
+            value,
+        } => {
+            let (derived_name, expected) =
+                derived::reference(&field_path, &op, &value).map_err(|error| {
+                    PulsarEngineError::DerivedReference {
+                        name: name.clone(),
+                        error,
+                    }
+                })?;
+            Ok(Predicate::Derived {
+                index: derived_index(&derived_name)?,
+                expected,
+            })
+        }
+    }
+}
+
+/// Compile a rule, suggesting the correct field name in case of typos.
+fn compile_rule(
+    rule: Rule,
+    payload_type: &str,
+    custom_payloads: &CustomPayloads,
+    rule_file: &RuleFile,
+) -> Result<RuleMatcher, PulsarEngineError> {
+    let name = rule.name.clone();
+    let condition = rule.condition.clone();
+
+    let compiled = if PayloadDiscriminant::from_str(payload_type).is_ok() {
+        rule.compile().map(RuleMatcher::Native)
+    } else {
+        custom_payloads
+            .compile(payload_type, rule)
+            .expect("custom payload checked during parsing")
+            .map(RuleMatcher::Custom)
+    };
+
+    compiled.map_err(|error| {
+        let help = match diagnostics::invalid_field::<Event>(&condition) {
+            Some((field, Some(suggestion))) => {
+                format!("\n    help: unknown field '{field}', did you mean '{suggestion}'?")
+            }
+            _ => String::new(),
+        };
+        PulsarEngineError::RuleCompile {
+            name,
+            filename: rule_file.path.clone(),
+            error,
+            help,
+        }
+    })
+}
+
+/// Extra data of the threats raised by rules with an `action`.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RuleEngineData {
     pub rule_name: String,
+    /// Name of the playbook the threat-response module runs on the threat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
 }
 
 struct PulsarEngineInternal {
-    rulesets: HashMap<PayloadDiscriminant, Ruleset<Event>>,
+    engine: RuleEngine,
     sender: ModuleSender,
 }
 
+/// Rules of a payload type, with the derived attributes they use.
+#[derive(Default)]
+struct Ruleset {
+    rules: Vec<EngineRule>,
+    derived: Vec<RuleMatcher>,
+}
+
+/// A compiled rule with its runtime state.
+struct EngineRule {
+    name: String,
+    predicate: Predicate,
+    /// Version of the rule pack of the rule
+    version: String,
+    /// Fields checked by the rule, reported in the evidence of its threats
+    fields: Vec<String>,
+    mode: RuleMode,
+    action: Option<String>,
+    severity: Severity,
+    outputs: Option<Vec<String>>,
+    evaluations: AtomicU64,
+    matches: AtomicU64,
+    eval_time_ns: AtomicU64,
+}
+
+impl EngineRule {
+    /// Check the event against the rule, updating its counters. Returns the
+    /// rule and its number of matches when the event matches.
+    fn check(&self, event: &Event, derived: &DerivedValues) -> Option<(&Self, u64)> {
+        let start = Instant::now();
+        let is_match = self.predicate.is_match(event, derived);
+        self.eval_time_ns
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        is_match.then(|| (self, self.matches.fetch_add(1, Ordering::Relaxed) + 1))
+    }
+
+    fn to_match(&self) -> RuleMatch<'_> {
+        RuleMatch {
+            name: &self.name,
+            mode: self.mode,
+            action: self.action.as_deref(),
+            severity: self.severity,
+            outputs: self.outputs.as_deref(),
+        }
+    }
+}
+
+pub(crate) enum RuleMatcher {
+    /// Rule on a payload defined in pulsar-core.
+    Native(CompiledRule<Event>),
+    /// Rule on a payload registered in [`CustomPayloads`].
+    Custom(CustomMatcher),
+}
+
+impl RuleMatcher {
+    pub(crate) fn is_match(&self, event: &Event) -> bool {
+        match self {
+            RuleMatcher::Native(compiled) => compiled.is_match(event),
+            RuleMatcher::Custom(matcher) => matcher(event),
+        }
+    }
+}
+
+/// Condition of a rule, split on the derived attributes it checks.
+enum Predicate {
+    Match(RuleMatcher),
+    /// The derived attribute at `index` in the ruleset has the `expected` value
+    Derived {
+        index: usize,
+        expected: bool,
+    },
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    fn is_match(&self, event: &Event, derived: &DerivedValues) -> bool {
+        match self {
+            Predicate::Match(matcher) => matcher.is_match(event),
+            Predicate::Derived { index, expected } => derived.get(*index, event) == *expected,
+            Predicate::And(l, r) => l.is_match(event, derived) && r.is_match(event, derived),
+            Predicate::Or(l, r) => l.is_match(event, derived) || r.is_match(event, derived),
+            Predicate::Not(inner) => !inner.is_match(event, derived),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct RuleFile {
     path: String,
     body: String,
+    format: RuleFormat,
+}
+
+/// Supported rule file formats. All of them share the same schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl RuleFormat {
+    /// Detect the format from the file extension.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yaml" => Some(RuleFormat::Yaml),
+            "json" => Some(RuleFormat::Json),
+            "toml" => Some(RuleFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Deserialize a rule file body. YAML and JSON files can contain either a plain
+    /// list of rules or a document with fragments. TOML only supports documents, since
+    /// top level arrays are not allowed.
+    fn parse(&self, body: &str) -> Result<RuleDocument, RuleFormatError> {
+        match self {
+            RuleFormat::Yaml => match serde_yaml::from_str::<serde_yaml::Value>(body)? {
+                serde_yaml::Value::Sequence(_) => Ok(RuleDocument {
+                    rules: serde_yaml::from_str(body)?,
+                    ..Default::default()
+                }),
+                _ => Ok(serde_yaml::from_str(body)?),
+            },
+            RuleFormat::Json => match serde_json::from_str::<serde_json::Value>(body)? {
+                serde_json::Value::Array(_) => Ok(RuleDocument {
+                    rules: serde_json::from_str(body)?,
+                    ..Default::default()
+                }),
+                _ => Ok(serde_json::from_str(body)?),
+            },
+            RuleFormat::Toml => Ok(toml_edit::easy::from_str(body)?),
+        }
+    }
 }
 
 impl RuleFile {
-    pub fn from(path: &Path) -> Result<Self, PulsarEngineError> {
+    pub fn from(path: &Path, format: RuleFormat) -> Result<Self, PulsarEngineError> {
         log::debug!("loading rule {}", path.display());
         let body = fs::read_to_string(path).map_err(|error| PulsarEngineError::RuleLoading {
             name: path.display().to_string(),
             error,
         })?;
         let path = path.display().to_string();
-        Ok(Self { path, body })
+        Ok(Self { path, body, format })
+    }
+
+    fn parse(&self) -> Result<RuleDocument, PulsarEngineError> {
+        self.format
+            .parse(&self.body)
+            .map_err(|error| PulsarEngineError::RuleParsing {
+                filename: self.path.clone(),
+                error,
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use pulsar_core::event::PayloadDiscriminant;
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use pulsar_core::{
+        domain::{dga_score, normalize},
+        event::{
+            Capabilities, DnsAnswer, DnsQuestion, Evidence, FileFlags, Header, Host, Payload,
+            PayloadDiscriminant, Severity, Value,
+        },
+        host::HostInfo,
+        pdk::{process_tracker::exec_chain_hash, Event},
+        shell::ShellCommand,
+    };
     use validatron::{Condition, Field, Match, Operator, RelationalOperator, Rule};
 
     use crate::{
+        custom::CustomPayloads,
         dsl,
-        engine::{parse_rule, UserRule},
+        engine::{
+            parse_rule, PulsarEngineError, RuleEngine, RuleEngineData, RuleFile, RuleFormat,
+            RuleMatch, RuleMode, UserRule,
+        },
     };
 
     #[test]
@@ -203,9 +1085,21 @@ mod tests {
             name: "Open netcat".to_string(),
             r#type: "Exec".to_string(),
             condition: r#"payload.filename == "/usr/bin/nc""#.to_string(),
+            mode: RuleMode::default(),
+            action: None,
+            severity: Severity::default(),
+            outputs: None,
+            case_insensitive: None,
+        };
+
+        let rule_file = RuleFile {
+            path: "test.yaml".to_string(),
+            body: String::new(),
+            format: RuleFormat::Yaml,
         };
 
-        let parsed = parse_rule(&parser, user_rule).unwrap();
+        let parsed =
+            parse_rule(&parser, user_rule, &CustomPayloads::default(), &rule_file).unwrap();
 
         let expected = (
             PayloadDiscriminant::Exec,
@@ -229,4 +1123,1162 @@ mod tests {
 
         assert_eq!(parsed, expected);
     }
+
+    #[test]
+    fn test_rule_mode() {
+        let user_rules: Vec<UserRule> = serde_yaml::from_str(
+            r#"
+- name: Alert rule
+  type: Exec
+  condition: payload.filename == "/usr/bin/nc"
+
+- name: Audit rule
+  type: Exec
+  condition: payload.filename == "/usr/bin/nc"
+  mode: audit
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(user_rules[0].mode, RuleMode::Alert);
+        assert_eq!(user_rules[1].mode, RuleMode::Audit);
+    }
+
+    #[test]
+    fn test_rule_document() {
+        let rule_file = RuleFile {
+            path: "test.yaml".to_string(),
+            body: r#"
+fragments:
+  is_netcat: payload.filename == "/usr/bin/nc"
+
+rules:
+  - name: Open netcat
+    type: Exec
+    condition: "@is_netcat"
+"#
+            .to_string(),
+            format: RuleFormat::Yaml,
+        };
+
+        let document = rule_file.parse().unwrap();
+        assert_eq!(document.fragments.len(), 1);
+        assert_eq!(document.rules.len(), 1);
+        assert_eq!(document.rules[0].condition, "@is_netcat");
+    }
+
+    #[test]
+    fn test_rule_packs() {
+        let rule_packs = |body| {
+            RuleEngine::from_str(body, RuleFormat::Yaml, &CustomPayloads::default())
+                .unwrap()
+                .rule_packs()
+                .to_vec()
+        };
+        let versioned = rule_packs(
+            r#"
+version: "2024.05"
+rules:
+  - name: Exit with error
+    type: Exit
+    condition: payload.exit_code == 1
+"#,
+        );
+        assert_eq!(versioned[0].name, "<memory>");
+        assert_eq!(versioned[0].version, "2024.05");
+
+        // Without a version, the content hash tells changed rules apart
+        let plain = "- name: Exit with error\n  type: Exit\n  condition: payload.exit_code == 1\n";
+        let version = &rule_packs(plain)[0].version;
+        assert_eq!(version.len(), 16);
+        assert_eq!(version, &rule_packs(plain)[0].version);
+        assert_ne!(version, &rule_packs(&plain.replace('1', "2"))[0].version);
+    }
+
+    #[test]
+    fn test_dsl_error_location() {
+        let parser = dsl::dsl::ConditionParser::new();
+
+        let rule_file = RuleFile {
+            path: "test.yaml".to_string(),
+            body: r#"
+- name: Open netcat
+  type: Exec
+  condition: payload.filename = "/usr/bin/nc"
+"#
+            .to_string(),
+            format: RuleFormat::Yaml,
+        };
+        let mut user_rules = rule_file.parse().unwrap().rules;
+
+        let error = parse_rule(
+            &parser,
+            user_rules.remove(0),
+            &CustomPayloads::default(),
+            &rule_file,
+        )
+        .unwrap_err();
+        match error {
+            PulsarEngineError::DslError { location, .. } => {
+                assert_eq!(location, "test.yaml:4:31")
+            }
+            _ => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[test]
+    fn test_rule_formats() {
+        let json = RuleFormat::Json
+            .parse(
+                r#"[{"name": "Open netcat", "type": "Exec", "condition": "payload.filename == \"/usr/bin/nc\""}]"#,
+            )
+            .unwrap();
+        assert_eq!(json.rules[0].name, "Open netcat");
+
+        let toml = RuleFormat::Toml
+            .parse(
+                r#"
+[fragments]
+is_netcat = 'payload.filename == "/usr/bin/nc"'
+
+[[rules]]
+name = "Open netcat"
+type = "Exec"
+condition = "@is_netcat"
+mode = "audit"
+"#,
+            )
+            .unwrap();
+        assert_eq!(toml.fragments.len(), 1);
+        assert_eq!(toml.rules[0].mode, RuleMode::Audit);
+    }
+
+    #[test]
+    fn test_rule_action() {
+        let document = RuleFormat::Yaml
+            .parse(
+                r#"
+- name: Executed miner
+  type: Exec
+  condition: payload.filename == "/usr/bin/xmrig"
+  action: contain_miner
+  severity: critical
+  outputs: [logger, smtp-notifier]
+- name: Executed netcat
+  type: Exec
+  condition: payload.filename == "/usr/bin/nc"
+"#,
+            )
+            .unwrap();
+        assert_eq!(document.rules[0].action.as_deref(), Some("contain_miner"));
+        assert_eq!(document.rules[0].severity, Severity::Critical);
+        assert_eq!(document.rules[1].action, None);
+        assert_eq!(document.rules[1].severity, Severity::Medium);
+        assert_eq!(
+            document.rules[0].outputs,
+            Some(vec!["logger".to_string(), "smtp-notifier".to_string()])
+        );
+        assert_eq!(document.rules[1].outputs, None);
+
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Executed miner
+  type: Exec
+  condition: payload.filename == "/usr/bin/xmrig"
+  outputs: [logger, smtp-notifer, pager]
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+        let modules = ["logger".to_string(), "smtp-notifier".to_string()];
+        let unknown = engine.unknown_outputs(&modules);
+        assert_eq!(unknown.len(), 2);
+        assert_eq!(
+            unknown[1].to_string(),
+            "rule 'Executed miner' routes its threats to unknown output smtp-notifer, \
+             did you mean smtp-notifier?"
+        );
+        assert_eq!(unknown[0].output, "pager");
+        assert_eq!(unknown[0].suggestion, None);
+
+        let extra = Value::try_from(RuleEngineData {
+            rule_name: "Executed miner".to_string(),
+            action: Some("contain_miner".to_string()),
+        })
+        .unwrap();
+        let data: RuleEngineData = extra.try_into().unwrap();
+        assert_eq!(data.action.as_deref(), Some("contain_miner"));
+    }
+
+    #[test]
+    fn test_embedded_engine() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Exit with error
+  type: Exit
+  condition: payload.exit_code == 1
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |exit_code| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/false".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::Exit { exit_code },
+            )
+        };
+
+        assert_eq!(
+            engine.process(&event(1)),
+            vec![RuleMatch {
+                name: "Exit with error",
+                mode: RuleMode::Alert,
+                action: None,
+                severity: Severity::Medium,
+                outputs: None,
+            }]
+        );
+        assert!(engine.process(&event(0)).is_empty());
+        let stats = &engine.rule_stats()[0];
+        assert_eq!((stats.evaluations, stats.matches), (2, 1));
+    }
+
+    #[test]
+    fn test_process_header_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Curl from ssh session
+  type: Exit
+  condition: header.exec_chain CONTAINS "/usr/sbin/sshd" AND header.image == "/usr/bin/curl"
+- name: Unexpected exec chain
+  type: Exit
+  condition: NOT header.exec_chain_hash IN ["2f0e1a1f93b2c0e4"]
+- name: Interactive curl
+  type: Exit
+  condition: header.is_interactive == "true" AND header.image == "/usr/bin/curl"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let exec_chain = vec!["/usr/sbin/sshd".to_string(), "/usr/bin/curl".to_string()];
+        let event = Event::new(
+            Header {
+                id: 1,
+                image: "/usr/bin/curl".to_string(),
+                pid: 42,
+                parent_pid: 1,
+                exec_chain_hash: exec_chain_hash(&exec_chain),
+                exec_chain,
+                is_interactive: true,
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 0 },
+        );
+
+        assert_eq!(
+            engine.process(&event),
+            vec![
+                RuleMatch {
+                    name: "Curl from ssh session",
+                    mode: RuleMode::Alert,
+                    action: None,
+                    severity: Severity::Medium,
+                    outputs: None,
+                },
+                RuleMatch {
+                    name: "Unexpected exec chain",
+                    mode: RuleMode::Alert,
+                    action: None,
+                    severity: Severity::Medium,
+                    outputs: None,
+                },
+                RuleMatch {
+                    name: "Interactive curl",
+                    mode: RuleMode::Alert,
+                    action: None,
+                    severity: Severity::Medium,
+                    outputs: None,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_host_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Curl on web servers
+  type: Exit
+  condition: header.host.hostname STARTS_WITH "web-" AND header.image == "/usr/bin/curl"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |hostname: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/curl".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    host: Arc::new(HostInfo {
+                        hostname: hostname.to_string(),
+                        ..Default::default()
+                    }),
+                    ..Header::test_default()
+                },
+                Payload::Exit { exit_code: 0 },
+            )
+        };
+
+        assert_eq!(engine.process(&event("web-01")).len(), 1);
+        assert!(engine.process(&event("db-01")).is_empty());
+        assert!(RuleEngine::from_str(
+            r#"
+- name: Unknown host field
+  type: Exit
+  condition: header.host.kernel == "6.1"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_capabilities_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Setuid capability added
+  type: FileCapabilitiesChanged
+  condition: payload.permitted CONTAINS "CAP_SETUID" AND payload.effective == "true"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |permitted| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/sbin/setcap".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::FileCapabilitiesChanged {
+                    filename: "/usr/bin/python3".to_string(),
+                    permitted: Capabilities::from_raw_unchecked(permitted),
+                    inheritable: Capabilities::from_raw_unchecked(0),
+                    effective: true,
+                },
+            )
+        };
+
+        assert_eq!(engine.process(&event((1 << 7) | (1 << 13))).len(), 1);
+        assert!(engine.process(&event(1 << 13)).is_empty());
+        assert!(RuleEngine::from_str(
+            r#"
+- name: Unknown capability
+  type: FileCapabilitiesChanged
+  condition: payload.permitted CONTAINS "CAP_NONE"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_script_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Python script from tmp
+  type: Exec
+  condition: payload.script STARTS_WITH "/tmp/" AND payload.script ENDS_WITH ".py"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |script: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/python3".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::Exec {
+                    filename: "/usr/bin/python3".to_string(),
+                    argc: 2,
+                    argv: vec!["python3".to_string(), script.to_string()].into(),
+                    namespaces: Default::default(),
+                    script: script.to_string(),
+                    shell: Default::default(),
+                    env: Vec::new(),
+                    cwd: String::new(),
+                    chrooted: false,
+                    package: String::new(),
+                    package_version: String::new(),
+                    verified: false,
+                    package_checked: false,
+                },
+            )
+        };
+
+        assert_eq!(engine.process(&event("/tmp/x.py")).len(), 1);
+        assert!(engine.process(&event("/opt/app/manage.py")).is_empty());
+    }
+
+    #[test]
+    fn test_argv_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Insecure curl
+  type: Exec
+  condition: payload.argv ANY == "--insecure" OR payload.argv ANY STARTS_WITH "--proxy="
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |args: &[&str]| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/curl".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::Exec {
+                    filename: "/usr/bin/curl".to_string(),
+                    argc: args.len(),
+                    argv: args
+                        .iter()
+                        .map(|arg| arg.to_string())
+                        .collect::<Vec<_>>()
+                        .into(),
+                    namespaces: Default::default(),
+                    script: String::new(),
+                    shell: Default::default(),
+                    env: Vec::new(),
+                    cwd: String::new(),
+                    chrooted: false,
+                    package: String::new(),
+                    package_version: String::new(),
+                    verified: false,
+                    package_checked: false,
+                },
+            )
+        };
+
+        assert_eq!(engine.process(&event(&["curl", "--insecure"])).len(), 1);
+        assert_eq!(
+            engine
+                .process(&event(&["curl", "--proxy=http://10.0.0.1"]))
+                .len(),
+            1
+        );
+        // Arguments are matched whole, not as substrings of the command line
+        assert!(engine
+            .process(&event(&["curl", "https://example.com/--insecure"]))
+            .is_empty());
+        assert!(RuleEngine::from_str(
+            r#"
+- name: Not a collection
+  type: Exec
+  condition: payload.filename ANY == "/usr/bin/curl"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_shell_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Download piped to shell
+  type: Exec
+  condition: payload.shell.pipelines ANY == "curl|bash" OR payload.shell.pipelines ANY == "curl|sh"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |command: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/bin/sh".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::Exec {
+                    filename: "/bin/sh".to_string(),
+                    argc: 3,
+                    argv: vec!["sh".to_string(), "-c".to_string(), command.to_string()].into(),
+                    namespaces: Default::default(),
+                    script: String::new(),
+                    shell: ShellCommand::parse(command),
+                    env: Vec::new(),
+                    cwd: String::new(),
+                    chrooted: false,
+                    package: String::new(),
+                    package_version: String::new(),
+                    verified: false,
+                    package_checked: false,
+                },
+            )
+        };
+
+        assert_eq!(
+            engine
+                .process(&event("curl -s https://example.com/x | bash"))
+                .len(),
+            1
+        );
+        assert!(engine
+            .process(&event("echo 'curl -s https://example.com/x | bash'"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_dns_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Onion domain query
+  type: DnsQuery
+  condition: ANY payload.questions.name ENDS_WITH ".onion"
+
+- name: Lookalike domain query
+  type: DnsQuery
+  condition: ANY payload.questions.normalized LOOKS_LIKE "paypal.com"
+
+- name: Generated domain query
+  type: DnsQuery
+  condition: ANY payload.questions.dga_score > 60
+
+- name: New external destination
+  type: DnsQuery
+  condition: ANY payload.questions.first_seen == "true"
+
+- name: Short lived answers
+  type: DnsResponse
+  condition: ALL payload.answers.ttl < 60 AND payload.answers.ttl ANY < 60
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |payload| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/curl".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                payload,
+            )
+        };
+        let question = |name: &str| DnsQuestion {
+            name: name.to_string(),
+            normalized: name.to_string(),
+            dga_score: dga_score(name),
+            first_seen: false,
+            qtype: "A".to_string(),
+            qclass: "IN".to_string(),
+        };
+        let answer = |ttl| DnsAnswer {
+            name: "example.com".to_string(),
+            class: "IN".to_string(),
+            ttl,
+            data: "A(Record(93.184.216.34))".to_string(),
+        };
+
+        let query = |names: &[&str]| Payload::DnsQuery {
+            questions: names.iter().map(|name| question(name)).collect(),
+        };
+        assert_eq!(
+            engine
+                .process(&event(query(&["example.com", "hidden.onion"])))
+                .len(),
+            1
+        );
+        assert!(engine.process(&event(query(&["example.com"]))).is_empty());
+        // Punycode of pаypal.com, with a Cyrillic а
+        let lookalike = Payload::DnsQuery {
+            questions: vec![DnsQuestion {
+                normalized: normalize("xn--pypal-4ve.com"),
+                ..question("xn--pypal-4ve.com")
+            }],
+        };
+        assert_eq!(engine.process(&event(lookalike)).len(), 1);
+        assert!(engine.process(&event(query(&["paypal.com"]))).is_empty());
+        assert_eq!(
+            engine.process(&event(query(&["xjw3k9qpz7vmd.com"]))).len(),
+            1
+        );
+        let first_contact = Payload::DnsQuery {
+            questions: vec![
+                question("example.com"),
+                DnsQuestion {
+                    first_seen: true,
+                    ..question("example.net")
+                },
+            ],
+        };
+        assert_eq!(engine.process(&event(first_contact)).len(), 1);
+
+        let response = |ttls: &[u32]| Payload::DnsResponse {
+            questions: vec![question("example.com")],
+            answers: ttls.iter().map(|ttl| answer(*ttl)).collect(),
+        };
+        assert_eq!(engine.process(&event(response(&[30, 10]))).len(), 1);
+        assert!(engine.process(&event(response(&[30, 300]))).is_empty());
+        // ALL holds on empty lists, ANY doesn't
+        assert!(engine.process(&event(response(&[]))).is_empty());
+    }
+
+    #[test]
+    fn test_miner_rules() {
+        let engine = RuleEngine::from_str(
+            include_str!("../../../../rules/miners.yaml"),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |payload| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/tmp/kworker".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                payload,
+            )
+        };
+        let pool = |port| Host {
+            ip: "10.0.0.1".parse().unwrap(),
+            port,
+            scope_id: 0,
+            flowinfo: 0,
+            raw_ip: None,
+        };
+
+        let connect = |port| Payload::Connect {
+            destination: pool(port),
+            is_tcp: true,
+        };
+        assert_eq!(engine.process(&event(connect(3333))).len(), 1);
+        assert!(engine.process(&event(connect(443))).is_empty());
+
+        let login = Payload::StratumRequest {
+            destination: pool(443),
+            method: "login".to_string(),
+            agent: "XMRig/6.20.0".to_string(),
+        };
+        assert_eq!(engine.process(&event(login)).len(), 1);
+
+        let metrics = |cpu_usage_sustained| Payload::ProcessMetrics {
+            cpu_usage: 100,
+            cpu_usage_sustained,
+            memory_rss: 0,
+            memory_virtual: 0,
+            io_read: 0,
+            io_write: 0,
+        };
+        assert_eq!(engine.process(&event(metrics(95))).len(), 1);
+        assert!(engine.process(&event(metrics(50))).is_empty());
+    }
+
+    #[test]
+    fn test_firewall_rules() {
+        let engine = RuleEngine::from_str(
+            include_str!("../../../../rules/basic-rules.yaml"),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |command: &str, summary: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/sbin/nft".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::FirewallChange {
+                    backend: "nftables".to_string(),
+                    tables_added: 0,
+                    tables_removed: 1,
+                    chains_added: 0,
+                    chains_removed: 0,
+                    rules_added: 0,
+                    rules_removed: 0,
+                    command: command.split(' ').map(String::from).collect(),
+                    summary: summary.to_string(),
+                },
+            )
+        };
+
+        assert_eq!(
+            engine.process(&event("nft flush ruleset", "flush ruleset"))[0].name,
+            "Local firewall flushed"
+        );
+        assert!(engine
+            .process(&event(
+                "nft -f /etc/nftables.conf",
+                "load rules from /etc/nftables.conf"
+            ))
+            .is_empty());
+    }
+    #[test]
+    fn test_package_rules() {
+        let engine = RuleEngine::from_str(
+            include_str!("../../../../rules/packages.yaml"),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |package: &str, verified: bool, package_checked: bool| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/ls".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::Exec {
+                    filename: "/usr/bin/ls".to_string(),
+                    argc: 1,
+                    argv: vec!["ls".to_string()].into(),
+                    namespaces: Default::default(),
+                    script: String::new(),
+                    shell: Default::default(),
+                    env: Vec::new(),
+                    cwd: String::new(),
+                    chrooted: false,
+                    package: package.to_string(),
+                    package_version: String::new(),
+                    verified,
+                    package_checked,
+                },
+            )
+        };
+        let matched = |package: &str, verified: bool, package_checked: bool| {
+            engine
+                .process(&event(package, verified, package_checked))
+                .into_iter()
+                .map(|rule| rule.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(matched("coreutils", true, true).is_empty());
+        assert_eq!(
+            matched("coreutils", false, true),
+            ["Modified package executable"]
+        );
+        assert_eq!(
+            matched("", false, true),
+            ["Executable not owned by any package"]
+        );
+        // Not checked, like in a container
+        assert!(matched("", false, false).is_empty());
+    }
+
+    #[test]
+    fn test_sysctl_rules() {
+        let engine = RuleEngine::from_str(
+            include_str!("../../../../rules/sysctl.yaml"),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |image: &str, host_process: bool, name: &str, value: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: image.to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::SysctlChanged {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    host_process,
+                },
+            )
+        };
+        let matched = |name: &str, value: &str| {
+            engine
+                .process(&event("/usr/sbin/sysctl", true, name, value))
+                .into_iter()
+                .map(|rule| rule.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            matched("net.ipv4.ip_forward", "1"),
+            ["IP forwarding enabled"]
+        );
+        assert!(matched("net.ipv4.ip_forward", "0").is_empty());
+        assert!(engine
+            .process(&event("/usr/bin/dockerd", true, "net.ipv4.ip_forward", "1"))
+            .is_empty());
+        // A container can name its binaries like dockerd
+        assert_eq!(
+            engine
+                .process(&event(
+                    "/usr/bin/dockerd",
+                    false,
+                    "net.ipv4.ip_forward",
+                    "1"
+                ))
+                .len(),
+            1
+        );
+        assert_eq!(
+            matched("kernel.yama.ptrace_scope", "0"),
+            ["Ptrace restrictions disabled"]
+        );
+        assert!(matched("kernel.yama.ptrace_scope", "2").is_empty());
+        assert_eq!(
+            matched("kernel.modules_disabled", "0"),
+            ["Kernel module loading re-enabled"]
+        );
+        assert!(matched("kernel.modules_disabled", "1").is_empty());
+        assert_eq!(
+            matched("kernel.randomize_va_space", "0"),
+            ["Kernel hardening disabled"]
+        );
+        assert_eq!(
+            matched("kernel.core_pattern", "|/tmp/.x/payload"),
+            ["Kernel usermode helper changed"]
+        );
+        assert!(matched(
+            "kernel.core_pattern",
+            "|/usr/lib/systemd/systemd-coredump %P %u %g %s %t %c %h"
+        )
+        .is_empty());
+        assert_eq!(
+            matched("kernel.modprobe", "/tmp/modprobe"),
+            ["Kernel usermode helper changed"]
+        );
+        assert!(matched("kernel.modprobe", "/sbin/modprobe").is_empty());
+    }
+    #[test]
+    fn test_time_rules() {
+        let engine = RuleEngine::from_str(
+            include_str!("../../../../rules/basic-rules.yaml"),
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |image: &str, delta| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: image.to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::TimeChanged {
+                    syscall: "clock_settime".to_string(),
+                    time: 1_600_000_000,
+                    delta,
+                },
+            )
+        };
+
+        assert_eq!(
+            engine.process(&event("/usr/bin/date", -86_400))[0].name,
+            "System time changed"
+        );
+        assert!(engine.process(&event("/usr/sbin/chronyd", 2)).is_empty());
+    }
+
+    #[test]
+    fn test_case_insensitive_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Netcat from ssh
+  type: Exit
+  case_insensitive: true
+  condition: header.image IN ["/usr/bin/nc", "/usr/bin/ncat"] AND header.exec_chain CONTAINS "/usr/sbin/SSHD"
+- name: PowerShell from ssh
+  type: Exit
+  case_insensitive: [header.image]
+  condition: header.image ENDS_WITH "/pwsh" AND header.exec_chain CONTAINS "/usr/sbin/sshd"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |image: &str, parent: &str| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: image.to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    exec_chain: vec![parent.to_string(), image.to_string()],
+                    ..Header::test_default()
+                },
+                Payload::Exit { exit_code: 0 },
+            )
+        };
+        let matches = |image, parent| -> Vec<&str> {
+            engine
+                .process(&event(image, parent))
+                .iter()
+                .map(|rule| rule.name)
+                .collect()
+        };
+
+        assert_eq!(
+            matches("/usr/bin/NC", "/usr/sbin/sshd"),
+            ["Netcat from ssh"]
+        );
+        assert_eq!(
+            matches("/usr/bin/Ncat", "/USR/SBIN/SSHD"),
+            ["Netcat from ssh"]
+        );
+        assert_eq!(
+            matches("/opt/microsoft/PWSH", "/usr/sbin/sshd"),
+            ["PowerShell from ssh"]
+        );
+        // Only the image ignores the case
+        assert!(matches("/opt/microsoft/pwsh", "/USR/SBIN/SSHD").is_empty());
+    }
+
+    #[test]
+    fn test_case_insensitive_flags() {
+        let engine = RuleEngine::from_str(
+            r#"
+- name: Schedule a Cron job
+  type: FileOpened
+  case_insensitive: true
+  condition: payload.filename STARTS_WITH "/etc/cron" AND payload.flags CONTAINS "O_WRONLY"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |filename: &str, flags: i32| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: "/usr/bin/vim".to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    ..Header::test_default()
+                },
+                Payload::FileOpened {
+                    filename: filename.to_string(),
+                    flags: FileFlags::from_raw_unchecked(flags),
+                    mode: 0o100644,
+                    fd: 3,
+                    resolved_path: filename.to_string(),
+                },
+            )
+        };
+
+        // Flags are parsed as written, only the filename ignores the case
+        assert_eq!(engine.process(&event("/ETC/CRON.D/job", 1)).len(), 1);
+        assert!(engine.process(&event("/etc/cron.d/job", 0)).is_empty());
+    }
+
+    #[test]
+    fn test_case_insensitive_unknown_field() {
+        let error = RuleEngine::from_str(
+            r#"
+- name: PowerShell
+  type: Exit
+  case_insensitive: [header.imag]
+  condition: header.image ENDS_WITH "/pwsh"
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error,
+            PulsarEngineError::CaseInsensitiveField { field, .. } if field == "header.imag"
+        ));
+    }
+
+    #[test]
+    fn test_derived_rules() {
+        let engine = RuleEngine::from_str(
+            r#"
+derived:
+  from_ssh: header.exec_chain CONTAINS "/usr/sbin/sshd"
+  is_netcat: header.image IN ["/usr/bin/nc", "/usr/bin/ncat"]
+rules:
+  - name: Netcat from ssh
+    type: Exit
+    condition: derived.from_ssh AND derived.is_netcat
+  - name: Netcat outside ssh
+    type: Exit
+    condition: derived.is_netcat AND NOT derived.from_ssh
+  - name: Failed from ssh
+    type: Exit
+    condition: derived.from_ssh == "true" AND payload.exit_code != 0
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+
+        let event = |image: &str, parent: &str, exit_code: u32| {
+            Event::new(
+                Header {
+                    id: 1,
+                    image: image.to_string(),
+                    pid: 42,
+                    parent_pid: 1,
+                    exec_chain: vec![parent.to_string(), image.to_string()],
+                    ..Header::test_default()
+                },
+                Payload::Exit { exit_code },
+            )
+        };
+        let matches = |image, parent, exit_code| -> Vec<&str> {
+            engine
+                .process(&event(image, parent, exit_code))
+                .iter()
+                .map(|rule| rule.name)
+                .collect()
+        };
+
+        assert_eq!(
+            matches("/usr/bin/nc", "/usr/sbin/sshd", 0),
+            ["Netcat from ssh"]
+        );
+        assert_eq!(
+            matches("/usr/bin/ncat", "/usr/sbin/cron", 0),
+            ["Netcat outside ssh"]
+        );
+        assert_eq!(
+            matches("/usr/bin/ls", "/usr/sbin/sshd", 2),
+            ["Failed from ssh"]
+        );
+
+        let error = |body: &str| {
+            RuleEngine::from_str(body, RuleFormat::Yaml, &CustomPayloads::default())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            error(
+                r#"
+rules:
+  - name: Missing
+    type: Exit
+    condition: derived.missing
+"#
+            ),
+            "Error checking derived attributes in rule 'Missing'"
+        );
+        assert_eq!(
+            error(
+                r#"
+derived:
+  a: header.pid == 1
+  b: derived.a OR header.pid == 2
+rules:
+  - name: Nested
+    type: Exit
+    condition: derived.b
+"#
+            ),
+            "Error checking derived attributes in rule 'derived.b'"
+        );
+    }
+
+    #[test]
+    fn test_evidence() {
+        let engine = RuleEngine::from_str(
+            r#"
+version: "2024.05"
+derived:
+  is_netcat: header.image == "/usr/bin/nc"
+rules:
+  - name: Netcat failed
+    type: Exit
+    condition: derived.is_netcat AND payload.exit_code != 0 AND header.pid > 1
+"#,
+            RuleFormat::Yaml,
+            &CustomPayloads::default(),
+        )
+        .unwrap();
+        let event = Event::new(
+            Header {
+                id: 1,
+                image: "/usr/bin/nc".to_string(),
+                pid: 42,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 2 },
+        );
+
+        engine.process(&event);
+        let matches = engine.process_with_evidence(&event);
+        assert_eq!(matches.len(), 1);
+        let (rule, evidence) = &matches[0];
+        assert_eq!(rule.name, "Netcat failed");
+        assert_eq!(
+            evidence,
+            &Evidence {
+                rule_version: "2024.05".to_string(),
+                fields: BTreeMap::from([
+                    ("header.pid".to_string(), "42".to_string()),
+                    ("payload.exit_code".to_string(), "2".to_string()),
+                ]),
+                counters: BTreeMap::from([("rule_matches".to_string(), 2)]),
+            }
+        );
+    }
 }
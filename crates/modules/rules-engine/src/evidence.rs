@@ -0,0 +1,130 @@
+//! Evidence of the threats raised by rules.
+//!
+//! Besides the rule name, threats carry the values of the fields checked by the
+//! rule, the version of its rule pack and the number of matches of the rule, so
+//! that an alert can be understood in the SIEM without querying the raw events.
+//! Values are read from the event serialized as JSON, only when a rule matches.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use pulsar_core::{event::Evidence, pdk::Event};
+use serde_json::Value;
+use validatron::{Condition, Match};
+
+use crate::{derived, diagnostics};
+
+/// Counter of the matches of the rule since it was loaded.
+pub const MATCHES_COUNTER: &str = "rule_matches";
+
+/// Paths of the fields checked by a condition, like `payload.filename`. The
+/// fields of derived attributes are not checked by the rule itself: they're
+/// left out.
+pub fn field_paths(condition: &Condition) -> Vec<String> {
+    fn collect(condition: &Condition, paths: &mut BTreeSet<String>) {
+        match condition {
+            Condition::And { l, r } | Condition::Or { l, r } => {
+                collect(l, paths);
+                collect(r, paths);
+            }
+            Condition::Not { inner } => collect(inner, paths),
+            Condition::Base {
+                field_path, value, ..
+            } => {
+                if !derived::is_reference(field_path) {
+                    paths.insert(diagnostics::path_name(field_path));
+                }
+                if let Match::Field(value_path) = value {
+                    if !derived::is_reference(value_path) {
+                        paths.insert(diagnostics::path_name(value_path));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut paths = BTreeSet::new();
+    collect(condition, &mut paths);
+    paths.into_iter().collect()
+}
+
+/// Evidence of a rule checking `fields` which matched the event.
+pub fn collect(event: &Event, fields: &[String], rule_version: &str, matches: u64) -> Evidence {
+    let event = serde_json::to_value(event).unwrap_or_default();
+    Evidence {
+        rule_version: rule_version.to_string(),
+        fields: fields
+            .iter()
+            .filter_map(|path| Some((path.clone(), field_value(&event, path)?)))
+            .collect(),
+        counters: BTreeMap::from([(MATCHES_COUNTER.to_string(), matches)]),
+    }
+}
+
+/// Value of the field at `path` in the serialized event: strings as they are,
+/// other values as JSON. The fields of the items of a collection, like
+/// `payload.questions.name`, are collected in a list.
+fn field_value(event: &Value, path: &str) -> Option<String> {
+    let mut fields = path.split('.');
+    let root = match fields.next()? {
+        "payload" => {
+            let payload = event.get("payload")?;
+            let content = payload.get("content")?;
+            // Custom payloads are wrapped with their description
+            match payload.get("type").and_then(Value::as_str) {
+                Some("Custom") => content.get("value")?,
+                _ => content,
+            }
+        }
+        root => event.get(root)?,
+    };
+    let mut values = vec![root];
+    for field in fields {
+        values = values
+            .into_iter()
+            .flat_map(|value| match value {
+                Value::Array(items) => items.iter().collect(),
+                value => vec![value],
+            })
+            .filter_map(|value| value.get(field))
+            .collect();
+    }
+    match values.as_slice() {
+        [] => None,
+        [Value::String(value)] => Some(value.clone()),
+        [value] => Some(value.to_string()),
+        values => Some(Value::from_iter(values.iter().map(|value| (*value).clone())).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn field_values() {
+        let event = json!({
+            "header": { "image": "/usr/bin/dig", "pid": 42 },
+            "payload": {
+                "type": "DnsQuery",
+                "content": {
+                    "questions": [
+                        { "name": "a.onion", "qtype": "A" },
+                        { "name": "b.onion", "qtype": "AAAA" }
+                    ]
+                }
+            }
+        });
+        assert_eq!(
+            field_value(&event, "header.image").as_deref(),
+            Some("/usr/bin/dig")
+        );
+        assert_eq!(field_value(&event, "header.pid").as_deref(), Some("42"));
+        assert_eq!(
+            field_value(&event, "payload.questions.name").as_deref(),
+            Some(r#"["a.onion","b.onion"]"#)
+        );
+        assert_eq!(field_value(&event, "payload.answers"), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Shared condition fragments.
+//!
+//! Rule files can define named sub-conditions in a `fragments` section. Rule conditions
+//! reference them with `@name` and every reference is replaced by the parenthesized
+//! fragment before the condition is parsed. Fragments can reference other fragments.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+const FRAGMENT_PREFIX: char = '@';
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FragmentError {
+    #[error("fragment '{0}' not found")]
+    NotFound(String),
+    #[error("fragment '{0}' defined more than once")]
+    Duplicated(String),
+    #[error("fragment cycle detected: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+/// Expand all the fragment references found in the condition.
+pub fn expand(
+    condition: &str,
+    fragments: &HashMap<String, String>,
+) -> Result<String, FragmentError> {
+    expand_inner(condition, fragments, &mut Vec::new())
+}
+
+/// Recursive expansion. `stack` contains the fragments currently being expanded
+/// and it's used to detect cycles.
+fn expand_inner(
+    condition: &str,
+    fragments: &HashMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String, FragmentError> {
+    let mut output = String::with_capacity(condition.len());
+    let mut chars = condition.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                output.push(c);
+            }
+            FRAGMENT_PREFIX if !in_string => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+                {
+                    name.push(c);
+                }
+
+                if let Some(position) = stack.iter().position(|item| *item == name) {
+                    let mut cycle = stack[position..].to_vec();
+                    cycle.push(name);
+                    return Err(FragmentError::Cycle(cycle));
+                }
+
+                let fragment = fragments
+                    .get(&name)
+                    .ok_or_else(|| FragmentError::NotFound(name.clone()))?;
+
+                stack.push(name);
+                let expanded = expand_inner(fragment, fragments, stack)?;
+                stack.pop();
+
+                output.push('(');
+                output.push_str(&expanded);
+                output.push(')');
+            }
+            c => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragments(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn no_fragments() {
+        let condition = r#"header.image == "/usr/bin/sshd""#;
+        assert_eq!(expand(condition, &HashMap::new()).unwrap(), condition);
+    }
+
+    #[test]
+    fn simple_fragment() {
+        let fragments = fragments(&[("is_shell", r#"header.image == "/bin/sh""#)]);
+        assert_eq!(
+            expand(
+                r#"@is_shell AND payload.filename == "/etc/shadow""#,
+                &fragments
+            )
+            .unwrap(),
+            r#"(header.image == "/bin/sh") AND payload.filename == "/etc/shadow""#
+        );
+    }
+
+    #[test]
+    fn nested_fragment() {
+        let fragments = fragments(&[
+            ("sh", r#"header.image == "/bin/sh""#),
+            ("bash", r#"header.image == "/bin/bash""#),
+            ("is_shell", "@sh OR @bash"),
+        ]);
+        assert_eq!(
+            expand("NOT @is_shell", &fragments).unwrap(),
+            r#"NOT ((header.image == "/bin/sh") OR (header.image == "/bin/bash"))"#
+        );
+    }
+
+    #[test]
+    fn prefix_inside_string() {
+        let condition = r#"payload.filename == "/tmp/@file""#;
+        assert_eq!(expand(condition, &HashMap::new()).unwrap(), condition);
+    }
+
+    #[test]
+    fn missing_fragment() {
+        assert_eq!(
+            expand("@missing", &HashMap::new()),
+            Err(FragmentError::NotFound("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn fragment_cycle() {
+        let fragments = fragments(&[("a", "@b"), ("b", "@c"), ("c", "@b")]);
+        assert_eq!(
+            expand("@a", &fragments),
+            Err(FragmentError::Cycle(vec![
+                "b".to_string(),
+                "c".to_string(),
+                "b".to_string()
+            ]))
+        );
+    }
+}
@@ -1,44 +1,85 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use engine::PulsarEngine;
 use pulsar_core::pdk::{
-    CleanExit, ConfigError, ModuleConfig, ModuleContext, ModuleError, PulsarModule, ShutdownSignal,
-    Version,
+    CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+    ModuleError, PulsarModule, ShutdownSignal, Version,
 };
 
+mod custom;
+mod derived;
+mod diagnostics;
 mod dsl;
 mod engine;
+mod evidence;
+mod fragments;
 
-pub use engine::RuleEngineData;
+pub use custom::CustomPayloads;
+pub use engine::{
+    PulsarEngineError, RuleEngine, RuleEngineData, RuleFormat, RuleFormatError, RuleMatch,
+    RuleMode, RuleStats, UnknownOutput,
+};
 
 const DEFAULT_RULES_PATH: &str = "/var/lib/pulsar/rules";
+const DEFAULT_STATS_INTERVAL: u64 = 300;
 const MODULE_NAME: &str = "rules-engine";
 
 pub fn module() -> PulsarModule {
+    module_with_custom_payloads(CustomPayloads::default())
+}
+
+/// Rules engine module supporting rules on the given custom payload types.
+pub fn module_with_custom_payloads(custom_payloads: CustomPayloads) -> PulsarModule {
     PulsarModule::new(
         MODULE_NAME,
         Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
         true,
-        rules_engine_task,
+        move |ctx, shutdown| rules_engine_task(ctx, shutdown, custom_payloads.clone()),
+    )
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "rules_path",
+                    ConfigKind::Path,
+                    "Folder containing the rules",
+                )
+                .default_value(DEFAULT_RULES_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "stats_interval",
+                    ConfigKind::Integer,
+                    "Seconds between rule statistics logs, 0 to disable",
+                )
+                .default_value(DEFAULT_STATS_INTERVAL)
+                .range(0, i64::MAX),
+            ),
     )
 }
 
 async fn rules_engine_task(
     ctx: ModuleContext,
     mut shutdown: ShutdownSignal,
+    custom_payloads: CustomPayloads,
 ) -> Result<CleanExit, ModuleError> {
     let mut receiver = ctx.get_receiver();
     let mut rx_config = ctx.get_config();
     let config: Config = rx_config.read()?;
-    let mut engine = PulsarEngine::new(&config.rules_path, ctx.get_sender())?;
+    let mut engine = PulsarEngine::new(&config.rules_path, ctx.get_sender(), &custom_payloads)?;
+    check_outputs(&ctx, &engine);
+    let mut stats_interval = stats_timer(&config);
 
     loop {
         tokio::select! {
             r = shutdown.recv() => return r,
             _ = rx_config.changed() => {
                 let config: Config = rx_config.read()?;
-                engine = PulsarEngine::new(&config.rules_path, ctx.get_sender())?;
+                engine = PulsarEngine::new(&config.rules_path, ctx.get_sender(), &custom_payloads)?;
+                check_outputs(&ctx, &engine);
+                stats_interval = stats_timer(&config);
             }
+            _ = stats_interval.tick() => log_rule_stats(&engine),
             // handle pulsar message
             event = receiver.recv() => {
                 let event = event?;
@@ -48,9 +89,63 @@ async fn rules_engine_task(
     }
 }
 
+/// Warn about the `outputs` of the rules which are not modules of the daemon,
+/// probably misspelled. The modules are listed by the daemon, which may still
+/// be starting: the rules are checked in the background.
+fn check_outputs(ctx: &ModuleContext, engine: &PulsarEngine) {
+    let daemon = ctx.get_daemon_handle();
+    let engine = engine.clone();
+    tokio::spawn(async move {
+        let modules: Vec<String> = daemon
+            .modules()
+            .await
+            .into_iter()
+            .map(|module| module.name)
+            .collect();
+        for unknown in engine.unknown_outputs(&modules) {
+            log::warn!("{unknown}");
+        }
+    });
+}
+
+/// Build the timer used to periodically log rule statistics.
+///
+/// A `stats_interval` of zero disables the logging.
+fn stats_timer(config: &Config) -> tokio::time::Interval {
+    let period = if config.stats_interval == 0 {
+        // Practically never
+        Duration::from_secs(u32::MAX as u64)
+    } else {
+        Duration::from_secs(config.stats_interval)
+    };
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
+fn log_rule_stats(engine: &PulsarEngine) {
+    for RuleStats {
+        name,
+        mode,
+        evaluations,
+        matches,
+        eval_time_ns,
+    } in engine.rule_stats()
+    {
+        if matches > 0 {
+            log::info!(
+                target: MODULE_NAME,
+                "rule '{name}' ({mode:?}) matched {matches} of {evaluations} events in {} ms",
+                eval_time_ns / 1_000_000
+            );
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Config {
     rules_path: PathBuf,
+    stats_interval: u64,
 }
 
 impl TryFrom<&ModuleConfig> for Config {
@@ -67,6 +162,11 @@ impl TryFrom<&ModuleConfig> for Config {
             });
         }
 
-        Ok(Self { rules_path })
+        let stats_interval = config.with_default("stats_interval", DEFAULT_STATS_INTERVAL)?;
+
+        Ok(Self {
+            rules_path,
+            stats_interval,
+        })
     }
 }
@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: GPL-2.0
+#include "vmlinux.h"
+
+#include "bpf/bpf_core_read.h"
+#include "bpf/bpf_helpers.h"
+#include "buffer.bpf.h"
+#include "common.bpf.h"
+#include "get_path.bpf.h"
+#include "output.bpf.h"
+#include "process_info.bpf.h"
+
+char LICENSE[] SEC("license") = "GPL v2";
+
+#define SIGNAL_SENT 0
+#define PTRACE_ACCESS 1
+#define FILE_CHANGED 2
+#define MAP_OPENED 3
+
+#define FILE_WRITE 0
+#define FILE_DELETE 1
+#define FILE_RENAME 2
+#define FILE_CREATE 3
+
+#define FMODE_WRITE 0x2
+#define PTRACE_MODE_ATTACH 0x02
+#define MAP_NAME_LEN 16
+#define SIGNAL_IMAGE_LEN 256
+
+// The image of the sender is captured here, since the sender can exit before
+// the event is read.
+struct signal_event {
+  int sig;
+  struct buffer_index image;
+};
+
+struct ptrace_event {
+  u32 mode;
+};
+
+struct file_changed_event {
+  u32 operation;
+  struct buffer_index filename;
+};
+
+struct map_opened_event {
+  u32 id;
+  char name[MAP_NAME_LEN];
+};
+
+OUTPUT_MAP(tamper_event, {
+  struct signal_event signal;
+  struct ptrace_event ptrace;
+  struct file_changed_event file;
+  struct map_opened_event map;
+});
+
+// Process of the daemon, set by userspace: nothing is reported until it's set,
+// and its own actions are never tampering.
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, u32);
+  __type(value, pid_t);
+  __uint(max_entries, 1);
+} protected_pid_map SEC(".maps");
+
+// Device in the kernel encoding. Both fields are 64 bits to avoid padding in
+// the hash key.
+struct file_key {
+  u64 ino;
+  u64 dev;
+};
+
+// Files of the daemon, set by userspace. Files created in the protected
+// directories are reported too.
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, struct file_key);
+  __type(value, u8);
+  __uint(max_entries, 4096);
+} protected_files_map SEC(".maps");
+
+// Identifiers of the maps of the daemon, set by userspace.
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __type(key, u32);
+  __type(value, u8);
+  __uint(max_entries, 1024);
+} protected_maps_map SEC(".maps");
+
+// Last signal sent to the daemon. It's pinned, so that a signal killing the
+// daemon before it could read the event is reported by the next instance,
+// with the image of the sender which is likely gone by then.
+struct signal_record {
+  u64 timestamp;
+  pid_t sender;
+  int sig;
+  char image[SIGNAL_IMAGE_LEN];
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, u32);
+  __type(value, struct signal_record);
+  __uint(max_entries, 1);
+} signal_record_map SEC(".maps");
+
+// The image of the sender is recorded from the metadata shared by
+// process-monitor, maintained on exec.
+PROCESS_INFO_MAP_DECLARATION;
+
+// Returns the pid of the process tampering with the daemon, or -1 when the
+// daemon is not configured yet or the current process is the daemon.
+static __always_inline pid_t tampering_tgid() {
+  u32 zero = 0;
+  pid_t *protected = bpf_map_lookup_elem(&protected_pid_map, &zero);
+  if (!protected || *protected == 0)
+    return -1;
+  pid_t tgid = bpf_get_current_pid_tgid() >> 32;
+  if (tgid == *protected)
+    return -1;
+  return tgid;
+}
+
+static __always_inline bool is_protected_task(struct task_struct *task) {
+  u32 zero = 0;
+  pid_t *protected = bpf_map_lookup_elem(&protected_pid_map, &zero);
+  return protected && *protected != 0 &&
+         BPF_CORE_READ(task, tgid) == *protected;
+}
+
+static __always_inline bool is_protected_inode(struct inode *inode) {
+  if (!inode)
+    return false;
+  struct file_key key = {
+      .ino = BPF_CORE_READ(inode, i_ino),
+      .dev = BPF_CORE_READ(inode, i_sb, s_dev),
+  };
+  return bpf_map_lookup_elem(&protected_files_map, &key) != NULL;
+}
+
+// Signal 0 only checks if the process exists.
+PULSAR_LSM_HOOK(task_kill, struct task_struct *, p, struct kernel_siginfo *,
+                info, int, sig, const struct cred *, cred);
+static __always_inline void on_task_kill(void *ctx, struct task_struct *p,
+                                         struct kernel_siginfo *info, int sig,
+                                         const struct cred *cred) {
+  if (sig == 0 || !is_protected_task(p))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  u32 zero = 0;
+  struct signal_record *record = bpf_map_lookup_elem(&signal_record_map, &zero);
+  if (record) {
+    record->timestamp = bpf_ktime_get_ns();
+    record->sender = tgid;
+    record->sig = sig;
+    struct process_info *info = process_info_get(tgid);
+    if (info) {
+      __builtin_memcpy(record->image, info->image, MAX_IMAGE_LEN);
+    } else {
+      record->image[0] = '\0';
+    }
+  }
+  struct tamper_event *event = init_tamper_event(SIGNAL_SENT, tgid);
+  if (!event)
+    return;
+  event->signal.sig = sig;
+  struct task_struct *task = (struct task_struct *)bpf_get_current_task();
+  struct path exe = BPF_CORE_READ(task, mm, exe_file, f_path);
+  get_path_str(&exe, &event->buffer, &event->signal.image);
+  output_tamper_event(ctx, event);
+}
+
+// Called for PTRACE_ATTACH, the accesses to /proc/<pid>/mem and
+// process_vm_readv/writev in attach mode. Read mode is used by tools like `ps`
+// reading /proc/<pid>/stat, and is not reported.
+PULSAR_LSM_HOOK(ptrace_access_check, struct task_struct *, child, unsigned int,
+                mode);
+static __always_inline void on_ptrace_access_check(void *ctx,
+                                                   struct task_struct *child,
+                                                   unsigned int mode) {
+  if (!(mode & PTRACE_MODE_ATTACH) || !is_protected_task(child))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  struct tamper_event *event = init_tamper_event(PTRACE_ACCESS, tgid);
+  if (!event)
+    return;
+  event->ptrace.mode = mode;
+  output_tamper_event(ctx, event);
+}
+
+static __always_inline void output_file_changed(void *ctx, pid_t tgid,
+                                                u32 operation,
+                                                struct path *path) {
+  struct tamper_event *event = init_tamper_event(FILE_CHANGED, tgid);
+  if (!event)
+    return;
+  event->file.operation = operation;
+  get_path_str(path, &event->buffer, &event->file.filename);
+  output_tamper_event(ctx, event);
+}
+
+static __always_inline struct path make_path(struct dentry *target_dentry,
+                                             struct path *parent_path) {
+  struct path target_path = {
+      .dentry = target_dentry,
+      .mnt = BPF_CORE_READ(parent_path, mnt),
+  };
+  return target_path;
+}
+
+PULSAR_LSM_HOOK(file_open, struct file *, file);
+static __always_inline void on_file_open(void *ctx, struct file *file) {
+  if (!(BPF_CORE_READ(file, f_mode) & FMODE_WRITE))
+    return;
+  if (!is_protected_inode(BPF_CORE_READ(file, f_inode)))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  struct path path = BPF_CORE_READ(file, f_path);
+  output_file_changed(ctx, tgid, FILE_WRITE, &path);
+}
+
+PULSAR_LSM_HOOK(path_unlink, struct path *, dir, struct dentry *, dentry);
+static __always_inline void on_path_unlink(void *ctx, struct path *dir,
+                                           struct dentry *dentry) {
+  if (!is_protected_inode(BPF_CORE_READ(dentry, d_inode)))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  struct path path = make_path(dentry, dir);
+  output_file_changed(ctx, tgid, FILE_DELETE, &path);
+}
+
+// Renaming a protected file, or replacing it with another one
+PULSAR_LSM_HOOK(path_rename, struct path *, old_dir, struct dentry *,
+                old_dentry, struct path *, new_dir, struct dentry *,
+                new_dentry);
+static __always_inline void on_path_rename(void *ctx, struct path *old_dir,
+                                           struct dentry *old_dentry,
+                                           struct path *new_dir,
+                                           struct dentry *new_dentry) {
+  struct path path;
+  if (is_protected_inode(BPF_CORE_READ(old_dentry, d_inode)))
+    path = make_path(old_dentry, old_dir);
+  else if (is_protected_inode(BPF_CORE_READ(new_dentry, d_inode)))
+    path = make_path(new_dentry, new_dir);
+  else
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  output_file_changed(ctx, tgid, FILE_RENAME, &path);
+}
+
+// Files created in a protected directory, like a new rule file
+PULSAR_LSM_HOOK(path_mknod, struct path *, dir, struct dentry *, dentry,
+                umode_t, mode, unsigned int, dev);
+static __always_inline void on_path_mknod(void *ctx, struct path *dir,
+                                          struct dentry *dentry, umode_t mode,
+                                          unsigned int dev) {
+  if (!is_protected_inode(BPF_CORE_READ(dir, dentry, d_inode)))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  struct path path = make_path(dentry, dir);
+  output_file_changed(ctx, tgid, FILE_CREATE, &path);
+}
+
+// A process got a file descriptor of a map, by its identifier or from its
+// pinned path. Only write access is reported: reading the maps is harmless.
+PULSAR_LSM_HOOK(bpf_map, struct bpf_map *, map, fmode_t, fmode);
+static __always_inline void on_bpf_map(void *ctx, struct bpf_map *map,
+                                       fmode_t fmode) {
+  if (!(fmode & FMODE_WRITE))
+    return;
+  u32 id = BPF_CORE_READ(map, id);
+  if (!bpf_map_lookup_elem(&protected_maps_map, &id))
+    return;
+  pid_t tgid = tampering_tgid();
+  if (tgid < 0)
+    return;
+  struct tamper_event *event = init_tamper_event(MAP_OPENED, tgid);
+  if (!event)
+    return;
+  event->map.id = id;
+  bpf_core_read_str(event->map.name, MAP_NAME_LEN, &map->name);
+  output_tamper_event(ctx, event);
+}
@@ -0,0 +1,435 @@
+use bpf_common::{
+    ebpf_program, parsing::BufferIndex, program::BpfContext, BpfSender, Program, ProgramBuilder,
+    ProgramError,
+};
+
+pub mod protect;
+pub mod tampering;
+
+const MODULE_NAME: &str = "self-protection";
+
+pub async fn program(
+    ctx: BpfContext,
+    sender: impl BpfSender<TamperEvent>,
+) -> Result<Program, ProgramError> {
+    let attach_to_lsm = ctx.lsm_supported();
+    let binary = ebpf_program!(&ctx, "probes");
+    let mut builder = ProgramBuilder::new(ctx, MODULE_NAME, binary).max_entries(
+        bpf_filtering::maps::DEFAULT_PROCESS_INFO,
+        bpf_filtering::maps::process_info_max_entries(),
+    );
+    if attach_to_lsm {
+        builder = builder
+            .lsm("task_kill")
+            .lsm("ptrace_access_check")
+            .lsm("file_open")
+            .lsm("path_unlink")
+            .lsm("path_rename")
+            .lsm("path_mknod")
+            .lsm("bpf_map");
+    } else {
+        builder = builder
+            .fentry_or_kprobe("security_task_kill")
+            .fentry_or_kprobe("security_ptrace_access_check")
+            .fentry_or_kprobe("security_file_open")
+            .fentry_or_kprobe("security_path_unlink")
+            .fentry_or_kprobe("security_path_rename")
+            .fentry_or_kprobe("security_path_mknod")
+            .fentry_or_kprobe("security_bpf_map");
+    }
+    // Survives a SIGKILL of the daemon, see [`protect::previous_signal`]
+    builder = builder.pin_map(protect::SIGNAL_RECORD_MAP);
+    let mut program = builder.start().await?;
+    program
+        .read_events("map_output_tamper_event", sender)
+        .await?;
+    Ok(program)
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub enum TamperEvent {
+    Signal {
+        sig: i32,
+        /// Executable of the sender
+        image: BufferIndex<str>,
+    },
+    Ptrace {
+        mode: u32,
+    },
+    FileChanged {
+        operation: u32,
+        filename: BufferIndex<str>,
+    },
+    MapOpened {
+        id: u32,
+        name: [u8; 16],
+    },
+}
+
+pub mod pulsar {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::tampering::Tampering;
+    use bpf_common::{program::BpfEvent, time::Timestamp, Pid};
+    use pulsar_core::{
+        event::{Severity, Value},
+        pdk::{
+            process_tracker::ProcessTrackerHandle, CleanExit, ConfigError, ConfigField, ConfigKind,
+            ConfigSchema, ModuleConfig, ModuleContext, ModuleError, ModuleSender, PulsarModule,
+            ShutdownSignal, Version,
+        },
+    };
+    use tokio::sync::mpsc;
+
+    const DEFAULT_PROTECTED_PATHS: &str = "/var/lib/pulsar";
+    /// Interval between the lookups of the files and maps of the daemon, which
+    /// change as files are written and modules are restarted.
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+    pub fn module() -> PulsarModule {
+        PulsarModule::new(
+            MODULE_NAME,
+            Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+            true,
+            self_protection_task,
+        )
+        .uses_ebpf()
+        // Threats are enriched by the process tracker, fed by process-monitor
+        .depends_on("process-monitor")
+        .with_config_schema(
+            ConfigSchema::new(1)
+                .field(
+                    ConfigField::new(
+                        "protected_paths",
+                        ConfigKind::List,
+                        "Files and folders of the daemon, like its configuration and rules",
+                    )
+                    .default_value(DEFAULT_PROTECTED_PATHS),
+                )
+                .field(ConfigField::new(
+                    "ignored_images",
+                    ConfigKind::List,
+                    "Executables allowed to signal the daemon and change its files, like the service manager",
+                )),
+        )
+    }
+
+    async fn self_protection_task(
+        ctx: ModuleContext,
+        mut shutdown: ShutdownSignal,
+    ) -> Result<CleanExit, ModuleError> {
+        // No event type is muted by storm control: an attacker can't hide in
+        // a storm of its own events
+        let bpf_context = ctx.get_bpf_context();
+        let previous_signal = protect::previous_signal(&bpf_context);
+        // Tampering is never dropped: the queue grows while the reporter
+        // waits for the process tracker
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut program = program(bpf_context.clone(), tx).await?;
+        let mut rx_config = ctx.get_config();
+        let mut config: Config = rx_config.read()?;
+        config.apply(&mut program)?;
+        protect::set_protected_pid(&mut program, std::process::id())?;
+        let reporter = Reporter {
+            sender: ctx.get_sender(),
+            process_tracker: ctx.get_process_tracker(),
+        };
+        if let Some(record) = previous_signal {
+            let tampering = Tampering::killed(record.sig);
+            let pid = Pid::from_raw(record.sender);
+            // The pid was likely reused since: the image is the one recorded
+            // by the probe
+            let image = record.image().unwrap_or_else(|| format!("process {pid}"));
+            reporter.report(&config, pid, record.timestamp.into(), image, tampering);
+        }
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                r = shutdown.recv() => return r,
+                _ = rx_config.changed() => {
+                    config = rx_config.read()?;
+                    config.apply(&mut program)?;
+                }
+                _ = refresh.tick() => config.apply(&mut program)?,
+                Some(event) = rx.recv() => {
+                    let BpfEvent { timestamp, pid, payload, buffer } = match event {
+                        Ok(event) => event,
+                        Err(err) => {
+                            log::error!("Error reading tampering event: {err}");
+                            continue;
+                        }
+                    };
+                    let mut sender_image = None;
+                    let tampering = match payload {
+                        TamperEvent::Signal { sig, image } => {
+                            // Reported now, not by the next instance
+                            if let Err(err) = protect::clear_signal_record(&bpf_context) {
+                                log::warn!("Error clearing the signal record: {err}");
+                            }
+                            sender_image = image
+                                .string(&buffer)
+                                .ok()
+                                .filter(|image| !image.is_empty());
+                            Tampering::signal(sig)
+                        }
+                        TamperEvent::Ptrace { .. } => Tampering::Ptrace,
+                        TamperEvent::FileChanged { operation, filename } => {
+                            let path = filename
+                                .string(&buffer)
+                                .unwrap_or_else(|err| format!("<{err}>"));
+                            Tampering::file(operation, path)
+                        }
+                        TamperEvent::MapOpened { id, name } => Tampering::map(id, &name),
+                    };
+                    let image = match sender_image {
+                        Some(image) => image,
+                        None => reporter.image(pid, timestamp).await,
+                    };
+                    reporter.report(&config, pid, timestamp, image, tampering);
+                }
+            }
+        }
+    }
+
+    struct Reporter {
+        sender: ModuleSender,
+        process_tracker: ProcessTrackerHandle,
+    }
+
+    impl Reporter {
+        /// Image of the process tampering with the daemon.
+        async fn image(&self, pid: Pid, timestamp: Timestamp) -> String {
+            match self.process_tracker.get(pid, timestamp).await {
+                Ok(info) => info.image,
+                Err(_) => std::fs::read_link(format!("/proc/{pid}/exe"))
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| format!("process {pid}")),
+            }
+        }
+
+        /// Raise a critical threat, unless the image is ignored. The threat
+        /// is sent directly on the bus, without going through the rules, and
+        /// logged right away in case the daemon is killed before it's output.
+        fn report(
+            &self,
+            config: &Config,
+            pid: Pid,
+            timestamp: Timestamp,
+            image: String,
+            tampering: Tampering,
+        ) {
+            if config.ignored_images.contains(&image) {
+                log::debug!("Ignored tampering by {image}: {tampering:?}");
+                return;
+            }
+            let description = tampering.description(&image);
+            log::error!("Tampering detected: {description}");
+            self.sender.send_threat(
+                pid,
+                timestamp,
+                description,
+                Severity::Critical,
+                Value::try_from(tampering).ok(),
+            );
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Config {
+        protected_paths: Vec<PathBuf>,
+        ignored_images: Vec<String>,
+    }
+
+    impl Config {
+        /// Protect the files and maps of the daemon, looking them up again.
+        fn apply(&self, program: &mut Program) -> Result<(), ProgramError> {
+            let files = protect::protected_files(&self.protected_paths);
+            protect::set_protected_files(program, &files)?;
+            protect::set_protected_maps(program, &protect::protected_maps())
+        }
+    }
+
+    impl TryFrom<&ModuleConfig> for Config {
+        type Error = ConfigError;
+
+        fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+            Ok(Config {
+                protected_paths: config.get_list_with_default(
+                    "protected_paths",
+                    vec![PathBuf::from(DEFAULT_PROTECTED_PATHS)],
+                )?,
+                ignored_images: config.get_list("ignored_images")?,
+            })
+        }
+    }
+}
+
+#[cfg(feature = "test-suite")]
+pub mod test_suite {
+    use std::{
+        env::temp_dir,
+        fs,
+        process::Command,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use bpf_common::{
+        event_check,
+        test_runner::{TestCase, TestRunner, TestSuite},
+    };
+    use nix::{
+        sys::{
+            ptrace,
+            signal::{kill, Signal},
+            wait::waitpid,
+        },
+        unistd::Pid,
+    };
+
+    /// `PTRACE_MODE_ATTACH | PTRACE_MODE_REALCREDS`, used by `PTRACE_ATTACH`
+    const PTRACE_ATTACH_MODE: u32 = 0x12;
+    /// `BPF_MAP_GET_FD_BY_ID` command of the bpf syscall
+    const BPF_MAP_GET_FD_BY_ID: libc::c_long = 14;
+
+    pub fn tests() -> TestSuite {
+        TestSuite {
+            name: "self-protection",
+            tests: vec![signal_sent(), ptrace_attach(), file_written(), map_opened()],
+        }
+    }
+
+    /// A signal sent to the protected process, here a child of the test
+    fn signal_sent() -> TestCase {
+        TestCase::new("signal_sent", async {
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let child_pid = child.id();
+            let image = fs::read_link("/proc/self/exe").unwrap();
+            let result = TestRunner::with_ebpf(move |ctx, sender| async move {
+                let mut program = program(ctx, sender).await?;
+                protect::set_protected_pid(&mut program, child_pid)?;
+                Ok(program)
+            })
+            .run(|| kill(Pid::from_raw(child_pid as i32), Signal::SIGCONT).unwrap())
+            .await
+            .expect_event(event_check!(
+                TamperEvent::Signal,
+                (sig, Signal::SIGCONT as i32, "signal"),
+                (image, image.to_str().unwrap().into(), "sender image")
+            ))
+            .report();
+            _ = child.kill();
+            _ = child.wait();
+            result
+        })
+    }
+
+    /// The test attaching to the protected process with `ptrace`
+    fn ptrace_attach() -> TestCase {
+        TestCase::new("ptrace_attach", async {
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let child_pid = child.id();
+            let result = TestRunner::with_ebpf(move |ctx, sender| async move {
+                let mut program = program(ctx, sender).await?;
+                protect::set_protected_pid(&mut program, child_pid)?;
+                Ok(program)
+            })
+            .run(|| {
+                let pid = Pid::from_raw(child_pid as i32);
+                ptrace::attach(pid).unwrap();
+                _ = waitpid(pid, None);
+                ptrace::detach(pid, None).unwrap();
+            })
+            .await
+            .expect_event(event_check!(
+                TamperEvent::Ptrace,
+                (mode, PTRACE_ATTACH_MODE, "ptrace mode")
+            ))
+            .report();
+            _ = child.kill();
+            _ = child.wait();
+            result
+        })
+    }
+
+    /// A protected file opened for writing
+    fn file_written() -> TestCase {
+        TestCase::new("file_written", async {
+            let path = temp_dir().join("self_protection_file");
+            fs::write(&path, "").unwrap();
+            let files = protect::protected_files(&[path.clone()]);
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let child_pid = child.id();
+            let result = TestRunner::with_ebpf(move |ctx, sender| {
+                let files = files.clone();
+                async move {
+                    let mut program = program(ctx, sender).await?;
+                    protect::set_protected_files(&mut program, &files)?;
+                    protect::set_protected_pid(&mut program, child_pid)?;
+                    Ok(program)
+                }
+            })
+            .run(|| fs::write(&path, "tampered").unwrap())
+            .await
+            .expect_event(event_check!(
+                TamperEvent::FileChanged,
+                (operation, tampering::FILE_WRITE, "operation"),
+                (filename, path.to_str().unwrap().into(), "filename")
+            ))
+            .report();
+            _ = child.kill();
+            _ = child.wait();
+            _ = fs::remove_file(&path);
+            result
+        })
+    }
+
+    /// A protected map opened for writing by its identifier, like `bpftool`
+    /// does. The maps of the test are protected, they're the ones of the
+    /// program.
+    fn map_opened() -> TestCase {
+        TestCase::new("map_opened", async {
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let child_pid = child.id();
+            let opened = Arc::new(AtomicU32::new(0));
+            let opened_id = opened.clone();
+            let result = TestRunner::with_ebpf(move |ctx, sender| async move {
+                let mut program = program(ctx, sender).await?;
+                let maps = protect::protected_maps();
+                protect::set_protected_maps(&mut program, &maps)?;
+                protect::set_protected_pid(&mut program, child_pid)?;
+                Ok(program)
+            })
+            .run(move || {
+                let id = protect::protected_maps().into_iter().min().unwrap();
+                opened_id.store(id, Ordering::SeqCst);
+                // union bpf_attr: map_id, next_id, open_flags
+                let attr: [u32; 3] = [id, 0, 0];
+                let fd = unsafe {
+                    libc::syscall(
+                        libc::SYS_bpf,
+                        BPF_MAP_GET_FD_BY_ID,
+                        attr.as_ptr(),
+                        std::mem::size_of_val(&attr),
+                    )
+                };
+                assert!(fd >= 0, "error opening map {id}");
+                unsafe { libc::close(fd as i32) };
+            })
+            .await
+            .expect_event(event_check!(
+                TamperEvent::MapOpened,
+                (id, opened.load(Ordering::SeqCst), "map id")
+            ))
+            .report();
+            _ = child.kill();
+            _ = child.wait();
+            result
+        })
+    }
+}
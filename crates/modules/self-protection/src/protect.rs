@@ -0,0 +1,248 @@
+//! Configuration of the probes with the process, files and maps of the daemon.
+//!
+//! The maps of the daemon are found in `/proc/self/fdinfo`, which shows the
+//! `map_id` of every map file descriptor. They're looked up again
+//! periodically, since the modules load their maps when they start.
+//!
+//! The last signal sent to the daemon is kept in a pinned map, so that a
+//! `SIGKILL` is reported by the next instance.
+
+use std::{collections::HashSet, fs, os::unix::fs::MetadataExt, path::PathBuf};
+
+use bpf_common::{
+    aya::maps::{Array, HashMap, Map},
+    program::BpfContext,
+    Program, ProgramError,
+};
+
+use crate::MODULE_NAME;
+
+const PROTECTED_PID_MAP: &str = "protected_pid_map";
+const PROTECTED_FILES_MAP: &str = "protected_files_map";
+const PROTECTED_MAPS_MAP: &str = "protected_maps_map";
+pub const SIGNAL_RECORD_MAP: &str = "signal_record_map";
+
+/// Must match the size of `protected_files_map` in probes.bpf.c
+pub const MAX_FILES: usize = 4096;
+
+/// Inode and device, in the kernel encoding, of a protected file. Must match
+/// `struct file_key` in probes.bpf.c
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FileKey {
+    pub ino: u64,
+    pub dev: u64,
+}
+// Plain old data which can be safely memcopied by aya.
+unsafe impl bpf_common::aya::Pod for FileKey {}
+
+/// Must match `SIGNAL_IMAGE_LEN` in probes.bpf.c
+pub const SIGNAL_IMAGE_LEN: usize = 256;
+
+/// Last signal sent to the daemon. Must match `struct signal_record` in
+/// probes.bpf.c
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SignalRecord {
+    /// Nanoseconds since boot
+    pub timestamp: u64,
+    pub sender: i32,
+    pub sig: i32,
+    /// Executable of the sender from the process info map, null terminated
+    /// and truncated to the map's image length
+    pub image: [u8; SIGNAL_IMAGE_LEN],
+}
+// Plain old data which can be safely memcopied by aya.
+unsafe impl bpf_common::aya::Pod for SignalRecord {}
+
+impl Default for SignalRecord {
+    fn default() -> Self {
+        Self {
+            timestamp: 0,
+            sender: 0,
+            sig: 0,
+            image: [0; SIGNAL_IMAGE_LEN],
+        }
+    }
+}
+
+impl SignalRecord {
+    /// Executable of the sender, when it was recorded.
+    pub fn image(&self) -> Option<String> {
+        let len = self.image.iter().position(|c| *c == 0)?;
+        (len > 0).then(|| String::from_utf8_lossy(&self.image[..len]).into_owned())
+    }
+}
+
+/// The last signal recorded by the previous instance of the daemon, which
+/// wasn't reported because the daemon was killed. Must be called before
+/// starting the program, which replaces the pinned map.
+pub fn previous_signal(ctx: &BpfContext) -> Option<SignalRecord> {
+    let map = ctx.open_pinned_map(MODULE_NAME, SIGNAL_RECORD_MAP).ok()?;
+    let array: Array<_, SignalRecord> = Array::try_from(Map::Array(map)).ok()?;
+    array.get(&0, 0).ok().filter(|record| record.sig != 0)
+}
+
+/// Forget the last signal, once reported.
+pub fn clear_signal_record(ctx: &BpfContext) -> Result<(), ProgramError> {
+    let map = ctx.open_pinned_map(MODULE_NAME, SIGNAL_RECORD_MAP)?;
+    let mut array: Array<_, SignalRecord> = Array::try_from(Map::Array(map))?;
+    array.set(0, SignalRecord::default(), 0)?;
+    Ok(())
+}
+
+/// Set the process of the daemon, enabling the probes.
+pub fn set_protected_pid(program: &mut Program, pid: u32) -> Result<(), ProgramError> {
+    let map = program
+        .bpf()
+        .map_mut(PROTECTED_PID_MAP)
+        .ok_or_else(|| ProgramError::MapNotFound(PROTECTED_PID_MAP.to_string()))?;
+    let mut array: Array<_, u32> = Array::try_from(map)?;
+    array.set(0, pid, 0)?;
+    Ok(())
+}
+
+/// Replace the protected files.
+pub fn set_protected_files(
+    program: &mut Program,
+    files: &HashSet<FileKey>,
+) -> Result<(), ProgramError> {
+    update_set(program, PROTECTED_FILES_MAP, files)
+}
+
+/// Replace the protected maps.
+pub fn set_protected_maps(program: &mut Program, ids: &HashSet<u32>) -> Result<(), ProgramError> {
+    update_set(program, PROTECTED_MAPS_MAP, ids)
+}
+
+/// Make the keys of a hash map equal to `keys`, without emptying it first:
+/// the probes would miss the accesses in between.
+fn update_set<K>(program: &mut Program, name: &str, keys: &HashSet<K>) -> Result<(), ProgramError>
+where
+    K: bpf_common::aya::Pod + Copy + Eq + std::hash::Hash,
+{
+    let map = program
+        .bpf()
+        .map_mut(name)
+        .ok_or_else(|| ProgramError::MapNotFound(name.to_string()))?;
+    let mut map: HashMap<_, K, u8> = HashMap::try_from(map)?;
+    let old_keys = map.keys().collect::<Result<Vec<_>, _>>()?;
+    for key in old_keys.iter().filter(|key| !keys.contains(key)) {
+        map.remove(key)?;
+    }
+    for key in keys {
+        map.insert(*key, 1, 0)?;
+    }
+    Ok(())
+}
+
+/// Files and folders under `paths`, and the executable of the daemon, up to
+/// [`MAX_FILES`]. Symbolic links are not followed.
+pub fn protected_files(paths: &[PathBuf]) -> HashSet<FileKey> {
+    let mut files = HashSet::new();
+    if let Ok(metadata) = fs::metadata("/proc/self/exe") {
+        files.insert(file_key(&metadata));
+    }
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        if files.len() >= MAX_FILES {
+            log::warn!("More than {MAX_FILES} protected files, ignoring the others");
+            break;
+        }
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        files.insert(file_key(&metadata));
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+    files
+}
+
+fn file_key(metadata: &fs::Metadata) -> FileKey {
+    FileKey {
+        ino: metadata.ino(),
+        dev: kernel_dev(metadata.dev()),
+    }
+}
+
+/// Convert a device from the userspace encoding, as returned by `stat`, to the
+/// one used internally by the kernel.
+pub fn kernel_dev(dev: u64) -> u64 {
+    let major = nix::sys::stat::major(dev);
+    let minor = nix::sys::stat::minor(dev);
+    (major << 20) | (minor & 0xfffff)
+}
+
+/// Identifiers of the eBPF maps opened by the daemon.
+pub fn protected_maps() -> HashSet<u32> {
+    let Ok(entries) = fs::read_dir("/proc/self/fdinfo") else {
+        return HashSet::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|fdinfo| map_id(&fdinfo))
+        .collect()
+}
+
+/// The `map_id` of the fdinfo of an eBPF map.
+fn map_id(fdinfo: &str) -> Option<u32> {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("map_id:"))
+        .and_then(|id| id.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn map_fdinfo() {
+        let fdinfo = "pos:\t0\nflags:\t02000002\nmnt_id:\t15\nino:\t1057\nmap_type:\t1\n\
+                      key_size:\t4\nvalue_size:\t1\nmax_entries:\t1024\nmap_flags:\t0x0\n\
+                      map_extra:\t0x0\nmemlock:\t8192\nmap_id:\t342\nfrozen:\t0\n";
+        assert_eq!(map_id(fdinfo), Some(342));
+        assert_eq!(
+            map_id("pos:\t0\nflags:\t02\nmnt_id:\t15\nino:\t1057\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn signal_record_image() {
+        let mut record = SignalRecord::default();
+        assert_eq!(record.image(), None);
+        record.image[..13].copy_from_slice(b"/usr/bin/kill");
+        assert_eq!(record.image().as_deref(), Some("/usr/bin/kill"));
+    }
+
+    #[test]
+    fn kernel_devices() {
+        // /dev/sda1 is 8:1, /dev/nvme0n1p2 259:2
+        assert_eq!(kernel_dev(nix::sys::stat::makedev(8, 1)), (8 << 20) | 1);
+        assert_eq!(kernel_dev(nix::sys::stat::makedev(259, 2)), (259 << 20) | 2);
+    }
+
+    #[test]
+    fn folder_files() {
+        let dir = std::env::temp_dir().join(format!("self-protection-{}", std::process::id()));
+        fs::create_dir_all(dir.join("rules")).unwrap();
+        fs::write(dir.join("pulsar.ini"), "").unwrap();
+        fs::write(dir.join("rules/basic.yaml"), "").unwrap();
+        let files = protected_files(&[dir.clone(), dir.join("missing")]);
+        let key = |path: &Path| file_key(&fs::symlink_metadata(path).unwrap());
+        for path in [&dir, &dir.join("rules"), &dir.join("rules/basic.yaml")] {
+            assert!(files.contains(&key(path)));
+        }
+        // The folders, the two files and the executable
+        assert_eq!(files.len(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
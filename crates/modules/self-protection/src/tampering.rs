@@ -0,0 +1,122 @@
+//! Tampering with the daemon, reported as critical threats.
+
+use nix::sys::signal::Signal;
+use serde::Serialize;
+
+/// Values of the `operation` of `TamperEvent::FileChanged`
+pub const FILE_WRITE: u32 = 0;
+pub const FILE_DELETE: u32 = 1;
+pub const FILE_RENAME: u32 = 2;
+pub const FILE_CREATE: u32 = 3;
+
+/// Extra data of the threats, with the `kind` of tampering.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Tampering {
+    /// A signal was sent to the daemon
+    Signal { signal: String },
+    /// The daemon was killed by a signal, reported by the next instance
+    Killed { signal: String },
+    /// A process attached to the daemon or accessed its memory
+    Ptrace,
+    /// A file of the daemon was changed
+    File { operation: String, path: String },
+    /// A process opened a map of the daemon for writing
+    Map { id: u32, name: String },
+}
+
+impl Tampering {
+    pub fn signal(sig: i32) -> Self {
+        Tampering::Signal {
+            signal: signal_name(sig),
+        }
+    }
+
+    pub fn killed(sig: i32) -> Self {
+        Tampering::Killed {
+            signal: signal_name(sig),
+        }
+    }
+
+    pub fn file(operation: u32, path: String) -> Self {
+        let operation = match operation {
+            FILE_WRITE => "write",
+            FILE_DELETE => "delete",
+            FILE_RENAME => "rename",
+            _ => "create",
+        };
+        Tampering::File {
+            operation: operation.to_string(),
+            path,
+        }
+    }
+
+    /// Map names are truncated to 15 characters, and null terminated.
+    pub fn map(id: u32, name: &[u8]) -> Self {
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Tampering::Map {
+            id,
+            name: String::from_utf8_lossy(&name[..len]).into_owned(),
+        }
+    }
+
+    pub fn description(&self, image: &str) -> String {
+        match self {
+            Tampering::Signal { signal } => format!("{image} sent {signal} to pulsard"),
+            Tampering::Killed { signal } => {
+                format!("{image} sent {signal} to the previous pulsard, which then stopped")
+            }
+            Tampering::Ptrace => format!("{image} attached to pulsard or accessed its memory"),
+            Tampering::File { operation, path } => {
+                format!("{image} tried to {operation} {path}, a file of pulsard")
+            }
+            Tampering::Map { id, name } => {
+                format!("{image} opened map {name} ({id}) of pulsard for writing")
+            }
+        }
+    }
+}
+
+/// Name of a signal, like `SIGKILL`, or its number for real-time signals.
+fn signal_name(sig: i32) -> String {
+    Signal::try_from(sig)
+        .map(|signal| signal.as_str().to_string())
+        .unwrap_or_else(|_| sig.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(
+            Tampering::signal(9).description("/usr/bin/kill"),
+            "/usr/bin/kill sent SIGKILL to pulsard"
+        );
+        assert_eq!(
+            Tampering::signal(40),
+            Tampering::Signal {
+                signal: "40".into()
+            }
+        );
+        assert_eq!(
+            Tampering::file(FILE_DELETE, "/var/lib/pulsar/rules/basic.yaml".into())
+                .description("/usr/bin/rm"),
+            "/usr/bin/rm tried to delete /var/lib/pulsar/rules/basic.yaml, a file of pulsard"
+        );
+        let mut name = [0u8; 16];
+        name[..12].copy_from_slice(b"rules_engine");
+        assert_eq!(
+            Tampering::map(42, &name),
+            Tampering::Map {
+                id: 42,
+                name: "rules_engine".into()
+            }
+        );
+        assert_eq!(
+            Tampering::map(42, b"map_output_proc_").description("/usr/sbin/bpftool"),
+            "/usr/sbin/bpftool opened map map_output_proc_ (42) of pulsard for writing"
+        );
+    }
+}
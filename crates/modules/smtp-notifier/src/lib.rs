@@ -3,14 +3,18 @@ use std::{default::Default, error::Error, fmt, str::FromStr};
 use anyhow::Context;
 use lettre::{
     message::{header::ContentType, Mailbox},
-    transport::smtp::authentication::Credentials,
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Certificate, Identity, Tls, TlsParameters, TlsVersion as SmtpTlsVersion},
+    },
     AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
 use pulsar_core::{
     event::Threat,
     pdk::{
-        CleanExit, ConfigError, ModuleConfig, ModuleContext, ModuleError, PulsarModule,
-        ShutdownSignal, Version,
+        tls::{self, TlsConfig, TlsVersion},
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+        ModuleError, PulsarModule, ShutdownSignal, Version,
     },
 };
 
@@ -18,6 +22,9 @@ mod template;
 
 const MODULE_NAME: &str = "smtp-notifier";
 
+/// The TLS fields of the SMTP connection are not prefixed, like `tls_ca`.
+const TLS_PREFIX: &str = "";
+
 pub fn module() -> PulsarModule {
     PulsarModule::new(
         MODULE_NAME,
@@ -25,15 +32,63 @@ pub fn module() -> PulsarModule {
         false,
         smtp_notifier_task,
     )
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(ConfigField::new(
+                "server",
+                ConfigKind::String,
+                "SMTP server address",
+            ))
+            .field(ConfigField::new(
+                "username",
+                ConfigKind::String,
+                "SMTP username",
+            ))
+            .field(ConfigField::new(
+                "password",
+                ConfigKind::String,
+                "SMTP password",
+            ))
+            .field(ConfigField::new(
+                "receivers",
+                ConfigKind::List,
+                "Email addresses receiving the notifications",
+            ))
+            .field(
+                ConfigField::new("port", ConfigKind::Integer, "SMTP server port")
+                    .default_value(465)
+                    .range(1, u16::MAX.into()),
+            )
+            .field(
+                ConfigField::new(
+                    "encryption",
+                    ConfigKind::Choice(vec![
+                        "tls".to_string(),
+                        "starttls".to_string(),
+                        "none".to_string(),
+                    ]),
+                    "Connection encryption",
+                )
+                .default_value("tls"),
+            )
+            .field(ConfigField::new(
+                "sender",
+                ConfigKind::String,
+                "Sender address, if `username` is not an email address",
+            ))
+            .extend(TlsConfig::schema_fields(TLS_PREFIX)),
+    )
 }
 
 async fn smtp_notifier_task(
     ctx: ModuleContext,
     mut shutdown: ShutdownSignal,
 ) -> Result<CleanExit, ModuleError> {
-    let mut receiver = ctx.get_receiver();
+    let mut receiver = ctx.get_output_receiver();
     let mut rx_config = ctx.get_config();
     let mut config: SmtpNotifierConfig = rx_config.read()?;
+    let mut tls_reload = tls::reload_notifications();
+    let mut tls = tls_parameters(&config)?;
 
     let template = template::Template::new()?;
 
@@ -42,8 +97,20 @@ async fn smtp_notifier_task(
             // Handle configuration changes:
             _ = rx_config.changed() => {
                 config = rx_config.read()?;
+                tls = tls_parameters(&config)?;
                 continue;
             }
+            _ = tls_reload.changed() => {
+                // Keep the current certificates if the new ones are invalid
+                match tls_parameters(&config) {
+                    Ok(new_tls) => {
+                        log::info!("Reloaded SMTP TLS certificates");
+                        tls = new_tls;
+                    }
+                    Err(err) => log::error!("Error reloading SMTP TLS certificates: {err}"),
+                }
+                tls_reload.done();
+            }
             r = shutdown.recv() => return r,
             msg = receiver.recv() => {
                 let event = msg?;
@@ -53,7 +120,7 @@ async fn smtp_notifier_task(
                 if let Some(Threat {
                     source,
                     description,
-                    extra: _,
+                    ..
                 }) = &header.threat
                 {
                     let payload = event.payload();
@@ -71,15 +138,13 @@ async fn smtp_notifier_task(
 
                     let message = message_builder.body(body)?;
 
-                    let smtp_transport = match config.encryption {
-                        Encryption::None => {
-                            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.server.as_str())
-                        }
-                        Encryption::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(config.server.as_str())?,
-                        Encryption::StartTls => {
-                            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(config.server.as_str())?
-                        }
-                    };
+                    let mut smtp_transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.server.as_str());
+                    if let Some(tls) = &tls {
+                        smtp_transport = smtp_transport.tls(match config.encryption {
+                            Encryption::StartTls => Tls::Required(tls.clone()),
+                            _ => Tls::Wrapper(tls.clone()),
+                        });
+                    }
 
                     smtp_transport
                         .credentials(Credentials::new(config.username.clone(), config.password.clone()))
@@ -93,6 +158,32 @@ async fn smtp_notifier_task(
     }
 }
 
+/// TLS parameters of the connection, reading the certificates, or `None`
+/// without encryption.
+fn tls_parameters(config: &SmtpNotifierConfig) -> Result<Option<TlsParameters>, ModuleError> {
+    if matches!(config.encryption, Encryption::None) {
+        return Ok(None);
+    }
+    let material = config.tls.load()?;
+    let domain = config
+        .tls
+        .server_name
+        .clone()
+        .unwrap_or_else(|| config.server.clone());
+    let mut parameters =
+        TlsParameters::builder(domain).set_min_tls_version(match config.tls.min_version {
+            TlsVersion::Tls12 => SmtpTlsVersion::Tlsv12,
+            TlsVersion::Tls13 => SmtpTlsVersion::Tlsv13,
+        });
+    for ca in material.ca_certificates() {
+        parameters = parameters.add_root_certificate(Certificate::from_pem(ca)?);
+    }
+    if let (Some(cert), Some(key)) = (&material.cert, &material.key) {
+        parameters = parameters.identify_with(Identity::from_pem(cert, key)?);
+    }
+    Ok(Some(parameters.build()?))
+}
+
 #[derive(Debug)]
 struct ParseEncryptionError;
 
@@ -140,6 +231,7 @@ struct SmtpNotifierConfig {
     port: u16,
     encryption: Encryption,
     sender: Mailbox,
+    tls: TlsConfig,
 }
 
 impl TryFrom<&ModuleConfig> for SmtpNotifierConfig {
@@ -184,6 +276,7 @@ impl TryFrom<&ModuleConfig> for SmtpNotifierConfig {
             port: config.with_default::<u16>("port", 465)?,
             encryption: config.with_default::<Encryption>("encryption", Default::default())?,
             sender,
+            tls: TlsConfig::from_config(config, TLS_PREFIX)?,
         })
     }
 }
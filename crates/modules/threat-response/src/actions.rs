@@ -0,0 +1,320 @@
+//! Implementation of the response actions.
+
+use std::{
+    fs,
+    net::{IpAddr, SocketAddrV4, SocketAddrV6},
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    ifaddrs::getifaddrs,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use pulsar_core::{
+    event::Host,
+    pdk::{Event, Payload},
+};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::process::Command;
+
+use crate::{
+    playbook::Action,
+    quarantine::{Quarantine, QuarantineError},
+};
+
+/// Header with the [`Header::delivery_key`] of the event posted to webhooks,
+/// so that receivers can drop the notifications retried by a playbook.
+///
+/// [`Header::delivery_key`]: pulsar_core::event::Header::delivery_key
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// Resources shared by the actions of every playbook run.
+pub struct ResponseContext {
+    pub snapshots_path: PathBuf,
+    pub quarantine: Quarantine,
+    pub http_client: reqwest::Client,
+}
+
+/// Describes the failure of a response action.
+#[derive(Error, Debug)]
+pub enum ActionError {
+    #[error("refusing to kill process {0}")]
+    ProtectedProcess(i32),
+    #[error("error killing process {pid}")]
+    Kill {
+        pid: i32,
+        #[source]
+        error: Errno,
+    },
+    #[error("the threat has no remote address")]
+    NoRemoteAddress,
+    #[error("refusing to block local address {0}")]
+    ProtectedAddress(IpAddr),
+    #[error("error running {command}")]
+    CommandSpawn {
+        command: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("{command} failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+    #[error("error writing snapshot {path}")]
+    Snapshot {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("error serializing snapshot")]
+    Serialization(#[from] serde_json::Error),
+    #[error("error sending webhook notification")]
+    Webhook(#[from] reqwest::Error),
+    #[error("the threat has no file")]
+    NoFile,
+    #[error("error quarantining file")]
+    Quarantine(#[from] QuarantineError),
+}
+
+impl Action {
+    pub async fn run(&self, ctx: &ResponseContext, event: &Event) -> Result<(), ActionError> {
+        match self {
+            Action::KillProcess => kill_process(event.header().pid),
+            Action::BlockNetwork => {
+                let host = remote_host(event.payload()).ok_or(ActionError::NoRemoteAddress)?;
+                block_address(host.ip).await
+            }
+            Action::Snapshot => snapshot(&ctx.snapshots_path, event),
+            Action::NotifyWebhook { url } => {
+                ctx.http_client
+                    .post(url)
+                    .header(IDEMPOTENCY_KEY, event.header().delivery_key())
+                    .json(event)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(())
+            }
+            Action::QuarantineFile => {
+                let file = threat_file(event.payload()).ok_or(ActionError::NoFile)?;
+                let record = ctx.quarantine.add(Path::new(file), event)?;
+                log::info!("Quarantined {file} as {}", record.id);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn kill_process(pid: i32) -> Result<(), ActionError> {
+    if pid <= 1 || pid as u32 == std::process::id() {
+        return Err(ActionError::ProtectedProcess(pid));
+    }
+    match kill(Pid::from_raw(pid), Signal::SIGKILL) {
+        // Already exited
+        Ok(()) | Err(Errno::ESRCH) => Ok(()),
+        Err(error) => Err(ActionError::Kill { pid, error }),
+    }
+}
+
+/// Return the address of the other side of a network event.
+fn remote_host(payload: &Payload) -> Option<&Host> {
+    match payload {
+        Payload::Connect { destination, .. }
+        | Payload::Send { destination, .. }
+        | Payload::Close { destination, .. }
+        | Payload::StratumRequest { destination, .. }
+        | Payload::IdleConnection { destination, .. } => Some(destination),
+        Payload::Accept { source, .. } | Payload::Receive { source, .. } => Some(source),
+        _ => None,
+    }
+}
+
+/// Return the file created, opened or executed by a file or process event.
+fn threat_file(payload: &Payload) -> Option<&str> {
+    let file = match payload {
+        Payload::FileCreated { filename }
+        | Payload::ElfOpened { filename, .. }
+        | Payload::Exec { filename, .. }
+        | Payload::FileCapabilitiesChanged { filename, .. } => filename,
+        Payload::FileOpened { resolved_path, .. } => resolved_path,
+        Payload::FileRename { destination, .. } => destination,
+        _ => return None,
+    };
+    Some(file.as_str()).filter(|file| !file.is_empty())
+}
+
+async fn block_address(ip: IpAddr) -> Result<(), ActionError> {
+    let ip = ip.to_canonical();
+    if ip.is_loopback() || ip.is_unspecified() || local_addresses().contains(&ip) {
+        return Err(ActionError::ProtectedAddress(ip));
+    }
+    let iptables = match ip {
+        IpAddr::V4(_) => "iptables",
+        IpAddr::V6(_) => "ip6tables",
+    };
+    let ip = ip.to_string();
+    run_command(iptables, &["-w", "-I", "OUTPUT", "-d", &ip, "-j", "DROP"]).await?;
+    run_command(iptables, &["-w", "-I", "INPUT", "-s", &ip, "-j", "DROP"]).await
+}
+
+/// Addresses of the network interfaces of the host, which would be cut off by
+/// blocking them.
+fn local_addresses() -> Vec<IpAddr> {
+    let addresses = match getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(err) => {
+            log::warn!("Error listing the network interfaces: {err}");
+            return Vec::new();
+        }
+    };
+    addresses
+        .filter_map(|interface| interface.address)
+        .filter_map(
+            |address| match (address.as_sockaddr_in(), address.as_sockaddr_in6()) {
+                (Some(v4), _) => Some(IpAddr::from(*SocketAddrV4::from(*v4).ip())),
+                (_, Some(v6)) => Some(IpAddr::from(*SocketAddrV6::from(*v6).ip())),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+async fn run_command(executable: &str, args: &[&str]) -> Result<(), ActionError> {
+    let command = format!("{executable} {}", args.join(" "));
+    let output = Command::new(executable)
+        .args(args)
+        .output()
+        .await
+        .map_err(|error| ActionError::CommandSpawn {
+            command: command.clone(),
+            error,
+        })?;
+    if !output.status.success() {
+        return Err(ActionError::CommandFailed {
+            command,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    event: &'a Event,
+    /// State of the process, missing if it has already exited
+    process: Option<ProcessSnapshot>,
+}
+
+#[derive(Serialize)]
+struct ProcessSnapshot {
+    exe: PathBuf,
+    cwd: PathBuf,
+    cmdline: Vec<String>,
+    status: String,
+}
+
+impl ProcessSnapshot {
+    fn read(pid: i32) -> std::io::Result<Self> {
+        let proc = PathBuf::from(format!("/proc/{pid}"));
+        let cmdline = fs::read(proc.join("cmdline"))?
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        Ok(Self {
+            exe: fs::read_link(proc.join("exe"))?,
+            cwd: fs::read_link(proc.join("cwd"))?,
+            cmdline,
+            status: fs::read_to_string(proc.join("status"))?,
+        })
+    }
+}
+
+/// Save the threat and the state of its process to `<snapshots_path>/<event id>.json`.
+fn snapshot(snapshots_path: &Path, event: &Event) -> Result<(), ActionError> {
+    let path = snapshots_path.join(format!("{}.json", event.header().id));
+    let snapshot = Snapshot {
+        event,
+        process: ProcessSnapshot::read(event.header().pid).ok(),
+    };
+    let body = serde_json::to_vec_pretty(&snapshot)?;
+    fs::create_dir_all(snapshots_path)
+        .and_then(|_| fs::write(&path, body))
+        .map_err(|error| ActionError::Snapshot {
+            path: path.display().to_string(),
+            error,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use pulsar_core::event::DataCapture;
+
+    use super::*;
+
+    #[test]
+    fn protected_processes() {
+        assert!(matches!(
+            kill_process(1),
+            Err(ActionError::ProtectedProcess(1))
+        ));
+        let pid = std::process::id() as i32;
+        assert!(matches!(
+            kill_process(pid),
+            Err(ActionError::ProtectedProcess(_))
+        ));
+    }
+
+    #[test]
+    fn remote_address() {
+        let host = |addr: &str| Host::from(addr.parse::<std::net::SocketAddr>().unwrap());
+        let connect = Payload::Connect {
+            destination: host("10.0.0.1:3333"),
+            is_tcp: true,
+        };
+        assert_eq!(remote_host(&connect).unwrap().port, 3333);
+        let receive = Payload::Receive {
+            source: host("10.0.0.2:53"),
+            destination: host("10.0.0.1:4000"),
+            len: 10,
+            is_tcp: false,
+            offset: 0,
+            capture: DataCapture::Complete,
+        };
+        assert_eq!(remote_host(&receive).unwrap().port, 53);
+        assert!(remote_host(&Payload::Exit { exit_code: 0 }).is_none());
+    }
+
+    #[test]
+    fn threat_files() {
+        let created = Payload::FileCreated {
+            filename: "/tmp/payload".to_string(),
+        };
+        assert_eq!(threat_file(&created), Some("/tmp/payload"));
+        let rename = Payload::FileRename {
+            source: "/tmp/payload".to_string(),
+            destination: "/usr/bin/ls".to_string(),
+            overwrite: true,
+        };
+        assert_eq!(threat_file(&rename), Some("/usr/bin/ls"));
+        assert_eq!(threat_file(&Payload::Exit { exit_code: 0 }), None);
+    }
+
+    #[tokio::test]
+    async fn protected_addresses() {
+        for ip in ["127.0.0.1", "::1", "0.0.0.0", "::ffff:127.0.0.1"] {
+            assert!(matches!(
+                block_address(ip.parse().unwrap()).await,
+                Err(ActionError::ProtectedAddress(_))
+            ));
+        }
+        // Blocking the address of the host would cut it off
+        for ip in local_addresses() {
+            assert!(matches!(
+                block_address(ip).await,
+                Err(ActionError::ProtectedAddress(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use actions::ResponseContext;
+use pulsar_core::{
+    event::Threat,
+    pdk::{
+        tls::{self, TlsConfig, TlsVersion},
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, Event, ModuleConfig,
+        ModuleContext, ModuleError, ModuleSender, Payload, PulsarModule, ShutdownSignal, Version,
+    },
+};
+use quarantine::{Quarantine, DEFAULT_QUARANTINE_PATH};
+use serde::Deserialize;
+
+mod actions;
+mod playbook;
+pub mod quarantine;
+
+pub use actions::ActionError;
+pub use playbook::{Action, OnFailure, Playbook, PlaybookError, Playbooks, Step};
+
+const MODULE_NAME: &str = "threat-response";
+const DEFAULT_PLAYBOOKS_PATH: &str = "/var/lib/pulsar/playbooks";
+const DEFAULT_SNAPSHOTS_PATH: &str = "/var/lib/pulsar/snapshots";
+const DEFAULT_WEBHOOK_TIMEOUT: u64 = 10;
+/// Prefix of the TLS settings of the webhook client.
+const WEBHOOK_PREFIX: &str = "webhook_";
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        threat_response_task,
+    )
+    // Replayed threats name processes and addresses of another host
+    .acts_on_host()
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "playbooks_path",
+                    ConfigKind::Path,
+                    "Folder containing the playbooks",
+                )
+                .default_value(DEFAULT_PLAYBOOKS_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "snapshots_path",
+                    ConfigKind::Path,
+                    "Folder where the snapshot action saves threats",
+                )
+                .default_value(DEFAULT_SNAPSHOTS_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "quarantine_path",
+                    ConfigKind::Path,
+                    "Folder where the quarantine_file action moves files",
+                )
+                .default_value(DEFAULT_QUARANTINE_PATH),
+            )
+            .field(
+                ConfigField::new(
+                    "webhook_timeout",
+                    ConfigKind::Integer,
+                    "Seconds before webhook notifications time out",
+                )
+                .default_value(DEFAULT_WEBHOOK_TIMEOUT)
+                .range(1, 300),
+            )
+            .extend(TlsConfig::schema_fields(WEBHOOK_PREFIX)),
+    )
+}
+
+async fn threat_response_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_output_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    let mut tls_reload = tls::reload_notifications();
+    let mut responder = Responder::new(rx_config.read()?)?;
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                responder = Responder::new(rx_config.read()?)?;
+            }
+            _ = tls_reload.changed() => {
+                // Keep the current client if the new certificates are invalid
+                match Responder::new(rx_config.read()?) {
+                    Ok(new_responder) => {
+                        log::info!("Reloaded webhook TLS certificates");
+                        responder = new_responder;
+                    }
+                    Err(err) => log::error!("Error reloading webhook TLS certificates: {err}"),
+                }
+                tls_reload.done();
+            }
+            event = receiver.recv() => {
+                responder.handle_event(&sender, event?);
+            }
+        }
+    }
+}
+
+/// Extra data of threats requesting a playbook, like the ones raised by rules
+/// with an `action`.
+#[derive(Deserialize)]
+struct ThreatAction {
+    action: String,
+}
+
+struct Responder {
+    playbooks: Playbooks,
+    ctx: Arc<ResponseContext>,
+}
+
+impl Responder {
+    fn new(config: Config) -> Result<Self, ModuleError> {
+        let playbooks = Playbooks::from_dir(&config.playbooks_path)?;
+        log::debug!("Loaded {} playbooks", playbooks.len());
+        let tls = config.webhook_tls.load()?;
+        let mut http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhook_timeout))
+            .min_tls_version(match config.webhook_tls.min_version {
+                TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+                TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+            });
+        if let Some(ca) = &tls.ca {
+            // With rustls all the certificates of the bundle are added
+            http_client = http_client.add_root_certificate(reqwest::Certificate::from_pem(ca)?);
+        }
+        if let Some(identity) = tls.identity() {
+            http_client = http_client.identity(reqwest::Identity::from_pem(&identity)?);
+        }
+        let http_client = http_client.build()?;
+        Ok(Self {
+            playbooks,
+            ctx: Arc::new(ResponseContext {
+                snapshots_path: config.snapshots_path,
+                quarantine: Quarantine::new(config.quarantine_path),
+                http_client,
+            }),
+        })
+    }
+
+    /// Run the playbook requested by a threat in the background.
+    fn handle_event(&self, sender: &ModuleSender, event: Arc<Event>) {
+        let Some(Threat {
+            extra: Some(extra), ..
+        }) = &event.header().threat
+        else {
+            return;
+        };
+        let Ok(ThreatAction { action }) = extra.clone().try_into() else {
+            return;
+        };
+        match self.playbooks.get(&action) {
+            Some(playbook) => {
+                tokio::spawn(run_playbook(
+                    self.ctx.clone(),
+                    sender.clone(),
+                    playbook,
+                    event,
+                ));
+            }
+            None => log::warn!(
+                "Unknown playbook '{action}' requested by threat {}",
+                event.header().id
+            ),
+        }
+    }
+}
+
+/// Run the steps of a playbook in order, sending an audit event for each one.
+async fn run_playbook(
+    ctx: Arc<ResponseContext>,
+    sender: ModuleSender,
+    playbook: Arc<Playbook>,
+    event: Arc<Event>,
+) {
+    for step in &playbook.steps {
+        let result = step.action.run(&ctx, &event).await;
+        sender.send_derived(
+            &event,
+            Payload::PlaybookStep {
+                playbook: playbook.name.clone(),
+                action: step.action.name().to_string(),
+                success: result.is_ok(),
+                error: result
+                    .as_ref()
+                    .err()
+                    .map(|err| err.to_string())
+                    .unwrap_or_default(),
+            },
+        );
+        if let Err(err) = result {
+            log::error!(
+                "Playbook '{}' step {} failed on threat {}: {err}",
+                playbook.name,
+                step.action.name(),
+                event.header().id
+            );
+            if step.on_failure == OnFailure::Abort {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    playbooks_path: PathBuf,
+    snapshots_path: PathBuf,
+    quarantine_path: PathBuf,
+    webhook_timeout: u64,
+    webhook_tls: TlsConfig,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let playbooks_path =
+            config.with_default("playbooks_path", PathBuf::from(DEFAULT_PLAYBOOKS_PATH))?;
+
+        if !playbooks_path.exists() {
+            return Err(ConfigError::InvalidValue {
+                field: "playbooks_path".to_string(),
+                value: playbooks_path.display().to_string(),
+                err: format!("Directory '{}' not exists", playbooks_path.display()),
+            });
+        }
+
+        let webhook_tls = TlsConfig::from_config(config, WEBHOOK_PREFIX)?;
+        if let Some(server_name) = &webhook_tls.server_name {
+            // reqwest always uses the host of the URL
+            return Err(ConfigError::InvalidValue {
+                field: format!("{WEBHOOK_PREFIX}tls_server_name"),
+                value: server_name.clone(),
+                err: "not supported by webhooks, use the server name in the URL".to_string(),
+            });
+        }
+
+        Ok(Self {
+            playbooks_path,
+            snapshots_path: config
+                .with_default("snapshots_path", PathBuf::from(DEFAULT_SNAPSHOTS_PATH))?,
+            quarantine_path: config
+                .with_default("quarantine_path", PathBuf::from(DEFAULT_QUARANTINE_PATH))?,
+            webhook_timeout: config.with_default("webhook_timeout", DEFAULT_WEBHOOK_TIMEOUT)?,
+            webhook_tls,
+        })
+    }
+}
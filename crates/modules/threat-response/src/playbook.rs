@@ -0,0 +1,232 @@
+//! Playbooks are named sequences of response actions, defined in YAML files and
+//! referenced by rules with `action: <playbook name>`.
+
+use std::{collections::HashMap, fs, path::Path, str::FromStr, sync::Arc};
+
+use glob::glob;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Playbook {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+}
+
+/// Response action run by a playbook step.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Kill the process which caused the threat.
+    KillProcess,
+    /// Drop the traffic from and to the remote address of the threat.
+    BlockNetwork,
+    /// Save the threat and the state of its process to the snapshots folder.
+    Snapshot,
+    /// Post the threat event as JSON to the given url.
+    NotifyWebhook { url: String },
+    /// Move the file involved in the threat to the quarantine folder.
+    QuarantineFile,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::KillProcess => "kill_process",
+            Action::BlockNetwork => "block_network",
+            Action::Snapshot => "snapshot",
+            Action::NotifyWebhook { .. } => "notify_webhook",
+            Action::QuarantineFile => "quarantine_file",
+        }
+    }
+}
+
+/// Describes what happens to the following steps when a step fails.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// The following steps are skipped.
+    #[default]
+    Abort,
+    /// The following steps are run anyway.
+    Continue,
+}
+
+/// Describes an error loading playbooks.
+#[derive(Error, Debug)]
+pub enum PlaybookError {
+    #[error("Error listing playbooks: {0}")]
+    Listing(#[from] glob::PatternError),
+    #[error("Error reading playbook file {filename}")]
+    Loading {
+        filename: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("Error parsing playbook file {filename}: {error}")]
+    Parsing {
+        filename: String,
+        #[source]
+        error: serde_yaml::Error,
+    },
+    #[error("Playbook '{name}' in {filename} is already defined")]
+    Duplicated { name: String, filename: String },
+    #[error("Playbook '{name}' in {filename} has no steps")]
+    NoSteps { name: String, filename: String },
+}
+
+/// Playbooks loaded from the playbook files, by name.
+#[derive(Debug, Default)]
+pub struct Playbooks {
+    playbooks: HashMap<String, Arc<Playbook>>,
+}
+
+impl Playbooks {
+    /// Load all the `.yaml` and `.yml` files found in the given directory and its
+    /// subdirectories. Each file contains a list of playbooks.
+    pub fn from_dir(path: &Path) -> Result<Self, PlaybookError> {
+        let mut playbooks = Playbooks::default();
+        let expr = format!("{}/**/*", path.display());
+        for path in glob(&expr)?.flatten() {
+            if !matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "yml")
+            ) {
+                continue;
+            }
+            let filename = path.display().to_string();
+            log::debug!("loading playbooks from {filename}");
+            let body = fs::read_to_string(&path).map_err(|error| PlaybookError::Loading {
+                filename: filename.clone(),
+                error,
+            })?;
+            playbooks.add(&body, &filename)?;
+        }
+        Ok(playbooks)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Playbook>> {
+        self.playbooks.get(name).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.playbooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.playbooks.is_empty()
+    }
+
+    fn add(&mut self, body: &str, filename: &str) -> Result<(), PlaybookError> {
+        let playbooks: Vec<Playbook> =
+            serde_yaml::from_str(body).map_err(|error| PlaybookError::Parsing {
+                filename: filename.to_string(),
+                error,
+            })?;
+        for playbook in playbooks {
+            if playbook.steps.is_empty() {
+                return Err(PlaybookError::NoSteps {
+                    name: playbook.name,
+                    filename: filename.to_string(),
+                });
+            }
+            if self.playbooks.contains_key(&playbook.name) {
+                return Err(PlaybookError::Duplicated {
+                    name: playbook.name,
+                    filename: filename.to_string(),
+                });
+            }
+            self.playbooks
+                .insert(playbook.name.clone(), Arc::new(playbook));
+        }
+        Ok(())
+    }
+}
+
+/// Load the playbooks of a single file.
+impl FromStr for Playbooks {
+    type Err = PlaybookError;
+
+    fn from_str(body: &str) -> Result<Self, Self::Err> {
+        let mut playbooks = Playbooks::default();
+        playbooks.add(body, "<memory>")?;
+        Ok(playbooks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let playbooks: Playbooks = r#"
+- name: contain_miner
+  steps:
+    - action: snapshot
+      on_failure: continue
+    - action: kill_process
+    - action: quarantine_file
+    - action: block_network
+    - action: notify_webhook
+      url: https://hooks.example.com/pulsar
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(playbooks.len(), 1);
+
+        let playbook = playbooks.get("contain_miner").unwrap();
+        let actions: Vec<_> = playbook.steps.iter().map(|step| &step.action).collect();
+        assert_eq!(
+            actions,
+            [
+                &Action::Snapshot,
+                &Action::KillProcess,
+                &Action::QuarantineFile,
+                &Action::BlockNetwork,
+                &Action::NotifyWebhook {
+                    url: "https://hooks.example.com/pulsar".to_string()
+                }
+            ]
+        );
+        assert_eq!(playbook.steps[0].on_failure, OnFailure::Continue);
+        assert_eq!(playbook.steps[1].on_failure, OnFailure::Abort);
+        assert!(playbooks.get("unknown").is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        for body in [
+            // unknown action
+            "[{name: p, steps: [{action: reboot}]}]",
+            // missing url
+            "[{name: p, steps: [{action: notify_webhook}]}]",
+            "[{name: p, steps: [{action: snapshot, on_failure: retry}]}]",
+        ] {
+            assert!(
+                matches!(
+                    body.parse::<Playbooks>(),
+                    Err(PlaybookError::Parsing { .. })
+                ),
+                "{body}"
+            );
+        }
+        assert!(matches!(
+            "[{name: p, steps: []}]".parse::<Playbooks>(),
+            Err(PlaybookError::NoSteps { .. })
+        ));
+        assert!(matches!(
+            "[{name: p, steps: [{action: snapshot}]}, {name: p, steps: [{action: kill_process}]}]"
+                .parse::<Playbooks>(),
+            Err(PlaybookError::Duplicated { .. })
+        ));
+    }
+}
@@ -0,0 +1,289 @@
+//! The quarantine keeps the files involved in threats out of reach, so that
+//! they can be analyzed and, in case of false positives, restored.
+//!
+//! Quarantined files are moved to `<quarantine_path>/<id>` with no permissions,
+//! next to a `<id>.json` record describing where they came from.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use nix::{
+    errno::Errno,
+    unistd::{chown, Gid, Uid},
+};
+use pulsar_core::pdk::Event;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const DEFAULT_QUARANTINE_PATH: &str = "/var/lib/pulsar/quarantine";
+
+/// Describes an error moving files in or out of the quarantine.
+#[derive(Error, Debug)]
+pub enum QuarantineError {
+    #[error("error accessing {path}")]
+    Io {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("{0} is not a regular file")]
+    NotAFile(String),
+    #[error("invalid quarantine id '{0}'")]
+    InvalidId(String),
+    #[error("no quarantined file with id '{0}'")]
+    NotFound(String),
+    #[error("refusing to overwrite {0}")]
+    AlreadyExists(String),
+    #[error("error restoring the owner of {path}")]
+    Chown {
+        path: String,
+        #[source]
+        error: Errno,
+    },
+    #[error("invalid quarantine record")]
+    Record(#[from] serde_json::Error),
+}
+
+/// Metadata of a quarantined file, needed to restore it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub id: String,
+    pub original_path: PathBuf,
+    /// Permission bits of the original file
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub quarantined_at: DateTime<Utc>,
+    /// Id of the threat event which caused the quarantine
+    pub event_id: u64,
+    /// Description of the threat
+    pub threat: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    path: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Move `file` to the quarantine and remove all its permissions.
+    pub fn add(&self, file: &Path, event: &Event) -> Result<QuarantineRecord, QuarantineError> {
+        let metadata = fs::symlink_metadata(file).map_err(io_error(file))?;
+        if !metadata.is_file() {
+            return Err(QuarantineError::NotAFile(file.display().to_string()));
+        }
+        self.create_dir()?;
+
+        let quarantined_at = Utc::now();
+        let record = QuarantineRecord {
+            id: format!("{}-{}", quarantined_at.timestamp(), event.header().id),
+            original_path: file.to_path_buf(),
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len(),
+            quarantined_at,
+            event_id: event.header().id,
+            threat: event
+                .header()
+                .threat
+                .as_ref()
+                .map(|threat| threat.description.clone())
+                .unwrap_or_default(),
+        };
+        let destination = self.path.join(&record.id);
+        move_file(file, &destination)?;
+        fs::set_permissions(&destination, fs::Permissions::from_mode(0o000))
+            .map_err(io_error(&destination))?;
+        self.write_record(&record)?;
+        Ok(record)
+    }
+
+    /// List the quarantined files, oldest first.
+    pub fn list(&self) -> Result<Vec<QuarantineRecord>, QuarantineError> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(io_error(&self.path)(error)),
+        };
+        let mut records = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(io_error(&self.path))?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                let body = fs::read(&path).map_err(io_error(&path))?;
+                records.push(serde_json::from_slice::<QuarantineRecord>(&body)?);
+            }
+        }
+        records.sort_by_key(|record| record.quarantined_at);
+        Ok(records)
+    }
+
+    /// Move a quarantined file back to its original path, with its original
+    /// permissions and owner.
+    pub fn restore(&self, id: &str) -> Result<QuarantineRecord, QuarantineError> {
+        if id.is_empty() || id.contains('/') || id.starts_with('.') {
+            return Err(QuarantineError::InvalidId(id.to_string()));
+        }
+        let record_path = self.record_path(id);
+        let body = match fs::read(&record_path) {
+            Ok(body) => body,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(QuarantineError::NotFound(id.to_string()))
+            }
+            Err(error) => return Err(io_error(&record_path)(error)),
+        };
+        let record: QuarantineRecord = serde_json::from_slice(&body)?;
+        let original_path = &record.original_path;
+        if fs::symlink_metadata(original_path).is_ok() {
+            return Err(QuarantineError::AlreadyExists(
+                original_path.display().to_string(),
+            ));
+        }
+
+        move_file(&self.path.join(id), original_path)?;
+        chown(
+            original_path,
+            Some(Uid::from_raw(record.uid)),
+            Some(Gid::from_raw(record.gid)),
+        )
+        .map_err(|error| QuarantineError::Chown {
+            path: original_path.display().to_string(),
+            error,
+        })?;
+        fs::set_permissions(original_path, fs::Permissions::from_mode(record.mode))
+            .map_err(io_error(original_path))?;
+        fs::remove_file(&record_path).map_err(io_error(&record_path))?;
+        Ok(record)
+    }
+
+    fn create_dir(&self) -> Result<(), QuarantineError> {
+        fs::create_dir_all(&self.path)
+            .and_then(|_| fs::set_permissions(&self.path, fs::Permissions::from_mode(0o700)))
+            .map_err(io_error(&self.path))
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!("{id}.json"))
+    }
+
+    fn write_record(&self, record: &QuarantineRecord) -> Result<(), QuarantineError> {
+        let path = self.record_path(&record.id);
+        let body = serde_json::to_vec_pretty(record)?;
+        fs::write(&path, body)
+            .and_then(|_| fs::set_permissions(&path, fs::Permissions::from_mode(0o600)))
+            .map_err(io_error(&path))
+    }
+}
+
+/// Rename `source` to `destination`, falling back to copying it when they are
+/// on different filesystems.
+fn move_file(source: &Path, destination: &Path) -> Result<(), QuarantineError> {
+    match fs::rename(source, destination) {
+        Ok(()) => Ok(()),
+        Err(error) if error.raw_os_error() == Some(Errno::EXDEV as i32) => {
+            fs::copy(source, destination).map_err(io_error(destination))?;
+            fs::remove_file(source).map_err(io_error(source))
+        }
+        Err(error) => Err(io_error(source)(error)),
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> QuarantineError + '_ {
+    move |error| QuarantineError::Io {
+        path: path.display().to_string(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use pulsar_core::{
+        event::{Header, Severity, Threat},
+        pdk::Payload,
+    };
+
+    use super::*;
+
+    fn threat_event() -> Event {
+        Event::new(
+            Header {
+                id: 42,
+                image: "/tmp/dropper".to_string(),
+                pid: 1000,
+                parent_pid: 1,
+                threat: Some(Threat {
+                    id: String::new(),
+                    source: "rules-engine".into(),
+                    description: "dropped payload".to_string(),
+                    severity: Severity::High,
+                    outputs: None,
+                    extra: None,
+                    evidence: None,
+                }),
+                source: "file-system-monitor".into(),
+                ..Header::test_default()
+            },
+            Payload::FileCreated {
+                filename: "/tmp/payload".to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn quarantine_and_restore() {
+        let dir = temp_dir().join("pulsar_quarantine_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("payload");
+        fs::write(&file, b"malicious").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o751)).unwrap();
+
+        let quarantine = Quarantine::new(dir.join("quarantine"));
+        assert!(quarantine.list().unwrap().is_empty());
+        let record = quarantine.add(&file, &threat_event()).unwrap();
+        assert!(!file.exists());
+        assert_eq!(record.mode, 0o751);
+        assert_eq!(record.size, 9);
+        assert_eq!(record.threat, "dropped payload");
+        let quarantined = fs::metadata(dir.join("quarantine").join(&record.id)).unwrap();
+        assert_eq!(quarantined.mode() & 0o7777, 0);
+        assert_eq!(quarantine.list().unwrap(), std::slice::from_ref(&record));
+
+        assert_eq!(quarantine.restore(&record.id).unwrap(), record);
+        assert_eq!(fs::read(&file).unwrap(), b"malicious");
+        assert_eq!(fs::metadata(&file).unwrap().mode() & 0o7777, 0o751);
+        assert!(quarantine.list().unwrap().is_empty());
+        assert!(matches!(
+            quarantine.restore(&record.id),
+            Err(QuarantineError::NotFound(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let quarantine = Quarantine::new(temp_dir().join("pulsar_quarantine_invalid"));
+        for id in ["", "../passwd", ".hidden"] {
+            assert!(matches!(
+                quarantine.restore(id),
+                Err(QuarantineError::InvalidId(_))
+            ));
+        }
+        assert!(matches!(
+            quarantine.add(&temp_dir(), &threat_event()),
+            Err(QuarantineError::NotAFile(_))
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{
+    event::{Payload, Severity},
+    packages::{Package, PackageDb},
+    pdk::{
+        CleanExit, ConfigError, ConfigField, ConfigKind, ConfigSchema, ModuleConfig, ModuleContext,
+        ModuleError, ModuleSender, PulsarModule, ShutdownSignal, Version,
+    },
+    Pid, Timestamp,
+};
+
+pub mod osv;
+pub mod report;
+pub mod version;
+
+use osv::VulnerabilityDb;
+use report::{Reporter, VulnerablePackage};
+
+const MODULE_NAME: &str = "vulnerabilities";
+const DEFAULT_OSV_PATH: &str = "/var/lib/pulsar/osv";
+const DEFAULT_MIN_SEVERITY: Severity = Severity::Critical;
+const DEFAULT_REPORT_INTERVAL: u64 = 86400;
+
+pub fn module() -> PulsarModule {
+    PulsarModule::new(
+        MODULE_NAME,
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        false,
+        vulnerabilities_task,
+    )
+    // Executables run after the startup
+    .depends_on("process-monitor")
+    .with_config_schema(
+        ConfigSchema::new(1)
+            .field(
+                ConfigField::new(
+                    "osv_path",
+                    ConfigKind::Path,
+                    "Folder with the OSV entries of the vulnerabilities, in JSON",
+                )
+                .default_value(DEFAULT_OSV_PATH),
+            )
+            .field(ConfigField::new(
+                "ecosystem",
+                ConfigKind::String,
+                "OSV ecosystem of the host packages, like `Debian:12`, required",
+            ))
+            .field(
+                ConfigField::new(
+                    "min_severity",
+                    ConfigKind::Choice(vec![
+                        "low".to_string(),
+                        "medium".to_string(),
+                        "high".to_string(),
+                        "critical".to_string(),
+                    ]),
+                    "Lowest severity of the vulnerabilities reported",
+                )
+                .default_value(DEFAULT_MIN_SEVERITY),
+            )
+            .field(
+                ConfigField::new(
+                    "report_interval",
+                    ConfigKind::Integer,
+                    "Seconds after which a vulnerable package still running is reported again",
+                )
+                .default_value(DEFAULT_REPORT_INTERVAL)
+                .range(60, 30 * 86400),
+            ),
+    )
+}
+
+async fn vulnerabilities_task(
+    ctx: ModuleContext,
+    mut shutdown: ShutdownSignal,
+) -> Result<CleanExit, ModuleError> {
+    let mut receiver = ctx.get_receiver();
+    let mut rx_config = ctx.get_config();
+    let sender = ctx.get_sender();
+    // Reading the package databases can take a few seconds, the first time:
+    // they're shared with the `package_db` middleware
+    let packages = tokio::task::spawn_blocking(PackageDb::shared).await?;
+    let mut reporter = load(&rx_config.read()?).await?;
+    let host_namespace = mnt_namespace("self");
+    scan_processes(&packages, &mut reporter, &sender).await?;
+    let mut expire_interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        tokio::select! {
+            r = shutdown.recv() => return r,
+            _ = rx_config.changed() => {
+                reporter = load(&rx_config.read()?).await?;
+                scan_processes(&packages, &mut reporter, &sender).await?;
+            }
+            _ = expire_interval.tick() => reporter.expire(SystemTime::now()),
+            event = receiver.recv() => {
+                let event = event?;
+                let Payload::Exec {
+                    filename,
+                    chrooted,
+                    namespaces,
+                    ..
+                } = event.payload()
+                else {
+                    continue;
+                };
+                if *chrooted || host_namespace != Some(namespaces.mnt) {
+                    continue;
+                }
+                // The package of the event, set by the `package_db` middleware,
+                // lacks the source package
+                let report = packages.get(Path::new(filename)).and_then(|(_, file)| {
+                    reporter.check(&file.package, filename, SystemTime::now())
+                });
+                if let Some(payload) = report.and_then(|report| Payload::try_from(report).ok()) {
+                    sender.send_derived(&event, payload);
+                }
+            }
+        }
+    }
+}
+
+/// Load the vulnerabilities of the configuration.
+async fn load(config: &Config) -> Result<Reporter, ModuleError> {
+    let config = config.clone();
+    let db = tokio::task::spawn_blocking(move || {
+        VulnerabilityDb::load(&config.osv_path, &config.ecosystem, config.min_severity)
+    })
+    .await??;
+    log::info!("Loaded {} vulnerabilities", db.len());
+    Ok(Reporter::new(
+        db,
+        Duration::from_secs(config.report_interval),
+    ))
+}
+
+/// Report the vulnerable packages of the processes already running.
+async fn scan_processes(
+    packages: &Arc<PackageDb>,
+    reporter: &mut Reporter,
+    sender: &ModuleSender,
+) -> Result<(), ModuleError> {
+    let packages = packages.clone();
+    let running = tokio::task::spawn_blocking(move || running_packages(&packages)).await?;
+    for (pid, filename, package) in running {
+        if let Some(report) = reporter.check(&package, &filename, SystemTime::now()) {
+            send(sender, pid, report);
+        }
+    }
+    Ok(())
+}
+
+/// Executables and packages of the processes running on the host, reading
+/// `/proc`: the files of other mount namespaces are not the ones of the
+/// package database.
+fn running_packages(packages: &PackageDb) -> Vec<(Pid, String, Arc<Package>)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let host_namespace = mnt_namespace("self");
+    let mut running = Vec::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        if host_namespace.is_none() || mnt_namespace(&pid.to_string()) != host_namespace {
+            continue;
+        }
+        let Ok(exe) = fs::read_link(entry.path().join("exe")) else {
+            continue;
+        };
+        let Some((_, file)) = packages.get(&exe) else {
+            continue;
+        };
+        running.push((
+            Pid::from_raw(pid),
+            exe.to_string_lossy().into_owned(),
+            file.package.clone(),
+        ));
+    }
+    running
+}
+
+/// Mount namespace of a process, `/proc/<pid>/ns/mnt` links to `mnt:[<inode>]`.
+fn mnt_namespace(pid: &str) -> Option<u32> {
+    fs::read_link(format!("/proc/{pid}/ns/mnt"))
+        .ok()?
+        .to_str()?
+        .strip_prefix("mnt:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+fn send(sender: &ModuleSender, pid: Pid, report: VulnerablePackage) {
+    match Payload::try_from(report) {
+        Ok(payload) => sender.send(pid, Timestamp::now(), payload),
+        Err(err) => log::warn!("Error serializing vulnerable package: {err}"),
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    osv_path: PathBuf,
+    ecosystem: String,
+    min_severity: Severity,
+    report_interval: u64,
+}
+
+impl TryFrom<&ModuleConfig> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            osv_path: config.with_default("osv_path", PathBuf::from(DEFAULT_OSV_PATH))?,
+            ecosystem: required_ecosystem(config)?,
+            min_severity: config.with_default("min_severity", DEFAULT_MIN_SEVERITY)?,
+            report_interval: config.with_default("report_interval", DEFAULT_REPORT_INTERVAL)?,
+        })
+    }
+}
+
+/// The vulnerabilities of other ecosystems would be matched with the versions
+/// of the host, which use another scheme: the ecosystem is required.
+fn required_ecosystem(config: &ModuleConfig) -> Result<String, ConfigError> {
+    let ecosystem: String = config.required("ecosystem")?;
+    if ecosystem.trim().is_empty() {
+        return Err(ConfigError::RequiredValue {
+            field: "ecosystem".to_string(),
+        });
+    }
+    Ok(ecosystem.trim().to_string())
+}
@@ -0,0 +1,455 @@
+//! Vulnerabilities of a local export of the OSV database.
+//!
+//! Every `.json` file in the export directory, and its subdirectories, is an
+//! [OSV entry](https://ossf.github.io/osv-schema/), like the ones of the
+//! `all.zip` archive published for every ecosystem. Only the entries of the
+//! ecosystem of the host at least as severe as `min_severity` are kept,
+//! indexed by the name of the affected packages, which are source packages
+//! for the Linux distributions. The severity is the one given by the database, like for GitHub
+//! advisories, or the one of the CVSS v3 score otherwise; entries without
+//! both are skipped.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use pulsar_core::event::Severity;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::version;
+
+#[derive(Error, Debug)]
+pub enum OsvError {
+    #[error("error reading {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    id: String,
+    #[serde(default)]
+    withdrawn: Option<String>,
+    #[serde(default)]
+    affected: Vec<Affected>,
+    #[serde(default)]
+    severity: Vec<Score>,
+    #[serde(default)]
+    database_specific: Option<DatabaseSpecific>,
+}
+
+#[derive(Deserialize)]
+struct Affected {
+    package: Option<AffectedPackage>,
+    #[serde(default)]
+    ranges: Vec<Range>,
+    #[serde(default)]
+    versions: Vec<String>,
+    #[serde(default)]
+    severity: Vec<Score>,
+}
+
+#[derive(Deserialize)]
+struct AffectedPackage {
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    events: Vec<RangeEvent>,
+}
+
+/// One of `introduced`, `fixed` and `last_affected`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RangeEvent {
+    Introduced(String),
+    Fixed(String),
+    LastAffected(String),
+    Limit(String),
+}
+
+#[derive(Deserialize)]
+struct Score {
+    #[serde(rename = "type")]
+    kind: String,
+    score: String,
+}
+
+#[derive(Deserialize)]
+struct DatabaseSpecific {
+    severity: Option<String>,
+}
+
+/// Vulnerability of a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vulnerability {
+    pub id: String,
+    pub severity: Severity,
+    /// Ranges of affected versions, as events sorted by version
+    ranges: Vec<Vec<RangeEvent>>,
+    /// Affected versions, besides the ranges
+    versions: Vec<String>,
+}
+
+impl Vulnerability {
+    pub fn affects(&self, version: &str) -> bool {
+        self.versions.iter().any(|affected| affected == version)
+            || self.ranges.iter().any(|events| in_range(events, version))
+    }
+
+    /// Version fixing the vulnerability in the range of `version`, if any.
+    pub fn fixed_version(&self, version: &str) -> Option<&str> {
+        self.ranges
+            .iter()
+            .filter(|events| in_range(events, version))
+            .flat_map(|events| events.iter())
+            .find_map(|event| match event {
+                RangeEvent::Fixed(fixed) if version::compare(version, fixed) == Ordering::Less => {
+                    Some(fixed.as_str())
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Check if a version is affected by the events of a range: each event
+/// changes the state of the following versions.
+fn in_range(events: &[RangeEvent], version: &str) -> bool {
+    let mut affected = false;
+    for event in events {
+        match event {
+            RangeEvent::Introduced(introduced) => {
+                if introduced == "0" || version::compare(version, introduced) != Ordering::Less {
+                    affected = true;
+                }
+            }
+            RangeEvent::Fixed(fixed) | RangeEvent::Limit(fixed) => {
+                if version::compare(version, fixed) != Ordering::Less {
+                    affected = false;
+                }
+            }
+            RangeEvent::LastAffected(last) => {
+                if version::compare(version, last) == Ordering::Greater {
+                    affected = false;
+                }
+            }
+        }
+    }
+    affected
+}
+
+/// Vulnerabilities by package name.
+#[derive(Debug, Default)]
+pub struct VulnerabilityDb {
+    packages: HashMap<String, Vec<Vulnerability>>,
+}
+
+impl VulnerabilityDb {
+    /// Load the entries of the export at `path`, affecting packages of
+    /// `ecosystem`, like `Debian:12`. Invalid entries are logged and skipped.
+    pub fn load(path: &Path, ecosystem: &str, min_severity: Severity) -> Result<Self, OsvError> {
+        let mut db = Self::default();
+        let mut files = vec![path.to_path_buf()];
+        while let Some(path) = files.pop() {
+            let read_error = |error| OsvError::Read {
+                path: path.clone(),
+                error,
+            };
+            if path.is_dir() {
+                for entry in fs::read_dir(&path).map_err(read_error)? {
+                    files.push(entry.map_err(read_error)?.path());
+                }
+                continue;
+            }
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let body = fs::read_to_string(&path).map_err(read_error)?;
+            match serde_json::from_str::<Entry>(&body) {
+                Ok(entry) => db.add(entry, ecosystem, min_severity),
+                Err(err) => log::warn!("Invalid OSV entry {}: {err}", path.display()),
+            }
+        }
+        Ok(db)
+    }
+
+    fn add(&mut self, entry: Entry, ecosystem: &str, min_severity: Severity) {
+        if entry.withdrawn.is_some() {
+            return;
+        }
+        for affected in entry.affected {
+            let Some(package) = affected.package else {
+                continue;
+            };
+            if package.ecosystem != ecosystem {
+                continue;
+            }
+            let severity = entry
+                .database_specific
+                .as_ref()
+                .and_then(|database| database_severity(database.severity.as_deref()?))
+                .or_else(|| cvss_severity(&affected.severity))
+                .or_else(|| cvss_severity(&entry.severity));
+            let Some(severity) = severity.filter(|severity| *severity >= min_severity) else {
+                continue;
+            };
+            let ranges = affected
+                .ranges
+                .into_iter()
+                // Git commits can't be compared with package versions
+                .filter(|range| range.kind != "GIT")
+                .map(|range| range.events)
+                .collect();
+            self.packages
+                .entry(package.name)
+                .or_default()
+                .push(Vulnerability {
+                    id: entry.id.clone(),
+                    severity,
+                    ranges,
+                    versions: affected.versions,
+                });
+        }
+    }
+
+    /// Vulnerabilities affecting a version of a package.
+    pub fn affecting<'a>(
+        &'a self,
+        package: &str,
+        version: &'a str,
+    ) -> impl Iterator<Item = &'a Vulnerability> {
+        self.packages
+            .get(package)
+            .into_iter()
+            .flatten()
+            .filter(move |vulnerability| vulnerability.affects(version))
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+}
+
+/// Severity given by the database, `MODERATE` for GitHub advisories.
+fn database_severity(severity: &str) -> Option<Severity> {
+    match severity.to_ascii_lowercase().as_str() {
+        "moderate" => Some(Severity::Medium),
+        severity => Severity::from_str(severity).ok(),
+    }
+}
+
+/// Severity of the highest CVSS v3 score.
+fn cvss_severity(scores: &[Score]) -> Option<Severity> {
+    let score = scores
+        .iter()
+        .filter(|score| score.kind == "CVSS_V3")
+        .filter_map(|score| cvss3_base_score(&score.score))
+        .fold(None, |max: Option<f64>, score| {
+            Some(max.map_or(score, |max| max.max(score)))
+        })?;
+    match score {
+        score if score >= 9.0 => Some(Severity::Critical),
+        score if score >= 7.0 => Some(Severity::High),
+        score if score >= 4.0 => Some(Severity::Medium),
+        score if score > 0.0 => Some(Severity::Low),
+        _ => None,
+    }
+}
+
+/// Base score of a CVSS v3 vector, like `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`,
+/// see the formulas of the CVSS v3.1 specification.
+fn cvss3_base_score(vector: &str) -> Option<f64> {
+    let metrics: HashMap<&str, &str> = vector
+        .strip_prefix("CVSS:3.")?
+        .split('/')
+        .skip(1)
+        .filter_map(|metric| metric.split_once(':'))
+        .collect();
+    let metric = |name: &str| metrics.get(name).copied();
+    let changed = match metric("S")? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let attack_vector = match metric("AV")? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        "P" => 0.2,
+        _ => return None,
+    };
+    let attack_complexity = match metric("AC")? {
+        "L" => 0.77,
+        "H" => 0.44,
+        _ => return None,
+    };
+    let privileges = match (metric("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let user_interaction = match metric("UI")? {
+        "N" => 0.85,
+        "R" => 0.62,
+        _ => return None,
+    };
+    let impact = |name: &str| match metric(name)? {
+        "H" => Some(0.56),
+        "L" => Some(0.22),
+        "N" => Some(0.0),
+        _ => None,
+    };
+    let iss = 1.0 - (1.0 - impact("C")?) * (1.0 - impact("I")?) * (1.0 - impact("A")?);
+    let impact = if changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02f64).powi(15)
+    } else {
+        6.42 * iss
+    };
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * attack_vector * attack_complexity * privileges * user_interaction;
+    let score = if changed {
+        1.08 * (impact + exploitability)
+    } else {
+        impact + exploitability
+    };
+    Some(round_up(score.min(10.0)))
+}
+
+/// Round up to one decimal, avoiding floating point errors like the CVSS
+/// specification.
+fn round_up(value: f64) -> f64 {
+    let integer = (value * 100000.0).round() as u64;
+    if integer.is_multiple_of(10000) {
+        integer as f64 / 100000.0
+    } else {
+        ((integer / 10000) + 1) as f64 / 10.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = r#"{
+        "id": "DSA-5000-1",
+        "aliases": ["CVE-2023-0001"],
+        "affected": [{
+            "package": { "ecosystem": "Debian:12", "name": "openssl" },
+            "ranges": [{
+                "type": "ECOSYSTEM",
+                "events": [{ "introduced": "0" }, { "fixed": "3.0.11-1~deb12u2" }]
+            }]
+        }, {
+            "package": { "ecosystem": "Debian:11", "name": "openssl" },
+            "versions": ["1.1.1n-0+deb11u5"]
+        }],
+        "severity": [{ "type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H" }]
+    }"#;
+
+    #[test]
+    fn load() {
+        let mut db = VulnerabilityDb::default();
+        db.add(
+            serde_json::from_str(ENTRY).unwrap(),
+            "Debian:12",
+            Severity::Critical,
+        );
+        let ids = |version| {
+            db.affecting("openssl", version)
+                .map(|vulnerability| vulnerability.id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("3.0.11-1~deb12u1"), ["DSA-5000-1"]);
+        assert!(ids("3.0.11-1~deb12u2").is_empty());
+        // In the range of Debian:12, the versions of Debian:11 are skipped
+        assert_eq!(ids("1.1.1n-0+deb11u5"), ["DSA-5000-1"]);
+        assert_eq!(
+            db.affecting("openssl", "3.0.9-1")
+                .find_map(|vulnerability| vulnerability.fixed_version("3.0.9-1")),
+            Some("3.0.11-1~deb12u2")
+        );
+        assert_eq!(db.affecting("libssl", "3.0.9-1").count(), 0);
+
+        // Other ecosystems and lower severities are skipped
+        let mut db = VulnerabilityDb::default();
+        db.add(
+            serde_json::from_str(ENTRY).unwrap(),
+            "Debian",
+            Severity::Low,
+        );
+        assert!(db.is_empty());
+        let mut db = VulnerabilityDb::default();
+        db.add(
+            serde_json::from_str(ENTRY).unwrap(),
+            "Debian:11",
+            Severity::Low,
+        );
+        assert_eq!(db.affecting("openssl", "1.1.1n-0+deb11u5").count(), 1);
+        let mut db = VulnerabilityDb::default();
+        let entry = ENTRY.replace("C:H/I:H/A:H", "C:L/I:N/A:N");
+        db.add(
+            serde_json::from_str(&entry).unwrap(),
+            "Debian:12",
+            Severity::High,
+        );
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn ranges() {
+        let events = [
+            RangeEvent::Introduced("1.0".to_string()),
+            RangeEvent::LastAffected("1.5".to_string()),
+            RangeEvent::Introduced("2.0".to_string()),
+            RangeEvent::Fixed("2.3".to_string()),
+        ];
+        for (version, affected) in [
+            ("0.9", false),
+            ("1.0", true),
+            ("1.5", true),
+            ("1.6", false),
+            ("2.2", true),
+            ("2.3", false),
+        ] {
+            assert_eq!(in_range(&events, version), affected, "{version}");
+        }
+    }
+
+    #[test]
+    fn cvss() {
+        for (vector, score) in [
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H", 9.8),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H", 10.0),
+            ("CVSS:3.0/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:H/A:H", 7.8),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N", 6.1),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N", 0.0),
+        ] {
+            assert_eq!(cvss3_base_score(vector), Some(score), "{vector}");
+        }
+        assert_eq!(cvss3_base_score("CVSS:2.0/AV:N"), None);
+        assert_eq!(database_severity("MODERATE"), Some(Severity::Medium));
+        assert_eq!(database_severity("CRITICAL"), Some(Severity::Critical));
+    }
+}
@@ -0,0 +1,181 @@
+//! Reports of the vulnerable packages found running.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use pulsar_core::{packages::Package, PulsarPayload};
+use serde::{Deserialize, Serialize};
+
+use crate::osv::VulnerabilityDb;
+
+/// Running executable of a package with known vulnerabilities.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, PulsarPayload)]
+#[payload(name = "VulnerablePackage")]
+pub struct VulnerablePackage {
+    pub package: String,
+    pub version: String,
+    /// Source package, the one named by the vulnerabilities
+    pub source: String,
+    /// Executable of the process
+    pub filename: String,
+    /// Highest severity of the vulnerabilities
+    pub severity: String,
+    /// Identifiers of the OSV entries, like `CVE-2024-3094`
+    pub vulnerabilities: Vec<String>,
+    /// Version fixing all the vulnerabilities which have a fix, empty if none
+    pub fixed_version: String,
+}
+
+/// Checks the running packages, reporting each version at most once every
+/// `interval`: long running services would be reported on every exec
+/// otherwise.
+pub struct Reporter {
+    db: VulnerabilityDb,
+    interval: Duration,
+    /// Source packages versions reported
+    reported: HashMap<(String, String), SystemTime>,
+}
+
+impl Reporter {
+    pub fn new(db: VulnerabilityDb, interval: Duration) -> Self {
+        Self {
+            db,
+            interval,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Report of a package version run by `filename`, if vulnerable and not
+    /// reported in the last interval. The vulnerabilities are the ones of its
+    /// source package, like OSV entries of `openssl` for `libssl3`.
+    pub fn check(
+        &mut self,
+        package: &Package,
+        filename: &str,
+        now: SystemTime,
+    ) -> Option<VulnerablePackage> {
+        let version = package.source_version.as_str();
+        let key = (package.source.clone(), version.to_string());
+        if self.reported.get(&key).is_some_and(|reported| {
+            now.duration_since(*reported).unwrap_or_default() < self.interval
+        }) {
+            return None;
+        }
+        let vulnerabilities: Vec<_> = self.db.affecting(&package.source, version).collect();
+        let severity = vulnerabilities
+            .iter()
+            .map(|vulnerability| vulnerability.severity)
+            .max()?;
+        let mut ids: Vec<String> = vulnerabilities
+            .iter()
+            .map(|vulnerability| vulnerability.id.clone())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        let fixed_version = vulnerabilities
+            .iter()
+            .filter_map(|vulnerability| vulnerability.fixed_version(version))
+            .max_by(|a, b| crate::version::compare(a, b))
+            .unwrap_or_default()
+            .to_string();
+        self.reported.insert(key, now);
+        Some(VulnerablePackage {
+            package: package.name.clone(),
+            version: package.version.clone(),
+            source: package.source.clone(),
+            filename: filename.to_string(),
+            severity: severity.to_string(),
+            vulnerabilities: ids,
+            fixed_version,
+        })
+    }
+
+    /// Forget the versions reported before the interval.
+    pub fn expire(&mut self, now: SystemTime) {
+        let interval = self.interval;
+        self.reported
+            .retain(|_, reported| now.duration_since(*reported).unwrap_or_default() < interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pulsar_core::event::Severity;
+
+    use super::*;
+
+    #[test]
+    fn report_once() {
+        let dir = std::env::temp_dir().join(format!("osv-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Debian")).unwrap();
+        let entry = |id: &str, fixed: &str, severity: &str| {
+            format!(
+                r#"{{
+                    "id": "{id}",
+                    "affected": [{{
+                        "package": {{ "ecosystem": "Debian:12", "name": "xz-utils" }},
+                        "ranges": [{{ "type": "ECOSYSTEM", "events": [{{ "introduced": "5.6.0" }}, {{ "fixed": "{fixed}" }}] }}]
+                    }}],
+                    "database_specific": {{ "severity": "{severity}" }}
+                }}"#
+            )
+        };
+        fs::write(
+            dir.join("Debian/CVE-2024-3094.json"),
+            entry("CVE-2024-3094", "5.6.1+really5.4.5-1", "CRITICAL"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Debian/CVE-2024-0001.json"),
+            entry("CVE-2024-0001", "5.6.2", "HIGH"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Debian/CVE-2024-0002.json"),
+            entry("CVE-2024-0002", "5.6.3", "LOW"),
+        )
+        .unwrap();
+        fs::write(dir.join("README"), "not an entry").unwrap();
+        let db = VulnerabilityDb::load(&dir, "Debian:12", Severity::High).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let xz = |version| Package::new("xz-utils", version);
+        let interval = Duration::from_secs(60);
+        let mut reporter = Reporter::new(db, interval);
+        let now = SystemTime::now();
+        assert_eq!(
+            reporter.check(&xz("5.6.0-0.2"), "/usr/bin/xz", now),
+            Some(VulnerablePackage {
+                package: "xz-utils".to_string(),
+                version: "5.6.0-0.2".to_string(),
+                source: "xz-utils".to_string(),
+                filename: "/usr/bin/xz".to_string(),
+                severity: "critical".to_string(),
+                vulnerabilities: vec!["CVE-2024-0001".to_string(), "CVE-2024-3094".to_string()],
+                fixed_version: "5.6.2".to_string(),
+            })
+        );
+        // Already reported, including for the other packages of the source
+        assert_eq!(reporter.check(&xz("5.6.0-0.2"), "/usr/bin/xz", now), None);
+        let mut liblzma = Package::new("liblzma5", "5.6.0-0.2+b1");
+        liblzma.source = "xz-utils".to_string();
+        liblzma.source_version = "5.6.0-0.2".to_string();
+        assert_eq!(reporter.check(&liblzma, "/usr/bin/lzmainfo", now), None);
+        assert_eq!(reporter.check(&xz("5.4.1-0.2"), "/usr/bin/xz", now), None);
+
+        // Reported again after the interval, by the source package
+        let later = now + interval;
+        reporter.expire(later);
+        let report = reporter
+            .check(&liblzma, "/usr/bin/lzmainfo", later)
+            .unwrap();
+        assert_eq!(
+            (report.package.as_str(), report.source.as_str()),
+            ("liblzma5", "xz-utils")
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Comparison of package versions with the algorithm of dpkg.
+//!
+//! Versions are made of `[epoch:]upstream[-revision]`. The upstream version
+//! and the revision are compared by alternating their non-digit and digit
+//! parts: letters sort before other characters, and `~` before anything,
+//! even the end of the version, so that `1.0~rc1` is older than `1.0`. The
+//! versions of rpm and apk are compared correctly in most cases too.
+
+use std::cmp::Ordering;
+
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (epoch_a, upstream_a, revision_a) = split(a);
+    let (epoch_b, upstream_b, revision_b) = split(b);
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| compare_part(upstream_a, upstream_b))
+        .then_with(|| compare_part(revision_a, revision_b))
+}
+
+/// Split a version into its epoch, upstream version and revision.
+fn split(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.bytes().all(|c| c.is_ascii_digit()) => {
+            (epoch.parse().unwrap_or(0), rest)
+        }
+        _ => (0, version),
+    };
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while !a.is_empty() || !b.is_empty() {
+        // Non-digit prefix
+        loop {
+            let (ca, cb) = (a.first().copied(), b.first().copied());
+            if ca.is_none_or(|c| c.is_ascii_digit()) && cb.is_none_or(|c| c.is_ascii_digit()) {
+                break;
+            }
+            match order(ca).cmp(&order(cb)) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+            a = a.get(1..).unwrap_or_default();
+            b = b.get(1..).unwrap_or_default();
+        }
+        // Digit prefix, without leading zeros
+        let digits_a = a.iter().take_while(|c| c.is_ascii_digit()).count();
+        let digits_b = b.iter().take_while(|c| c.is_ascii_digit()).count();
+        let number_a = trim_zeros(&a[..digits_a]);
+        let number_b = trim_zeros(&b[..digits_b]);
+        match number_a
+            .len()
+            .cmp(&number_b.len())
+            .then_with(|| number_a.cmp(number_b))
+        {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        a = &a[digits_a..];
+        b = &b[digits_b..];
+    }
+    Ordering::Equal
+}
+
+/// Weight of a character of the non-digit parts, `None` for the end.
+fn order(c: Option<u8>) -> i32 {
+    match c {
+        Some(b'~') => -1,
+        None => 0,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        use Ordering::*;
+        let cases = [
+            ("1.0", "1.0", Equal),
+            ("1.0", "1.00", Equal),
+            ("1.2", "1.10", Less),
+            ("1.0~rc1", "1.0", Less),
+            ("1.0~rc1", "1.0~rc2", Less),
+            ("1.0a", "1.0", Greater),
+            ("1.0+dfsg", "1.0a", Greater),
+            ("1:1.0", "2.0", Greater),
+            ("3.0.11-1~deb12u2", "3.0.11-1", Less),
+            ("3.0.11-1", "3.0.9-1", Greater),
+            ("2.36.1-r5", "2.36.1-r12", Less),
+            ("5.2.15-1.fc38", "5.2.15-2.fc38", Less),
+        ];
+        for (a, b, ordering) in cases {
+            assert_eq!(compare(a, b), ordering, "{a} {b}");
+            assert_eq!(compare(b, a), ordering.reverse(), "{b} {a}");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields, LitStr,
+};
+
+/// Procedural macro to define custom Pulsar payloads outside of `pulsar-core`.
+///
+/// It implements for a struct with named fields:
+/// - the `Validatron` reflection, so the payload fields can be used in rules;
+/// - the `CustomPayload` trait, to convert the struct from and to `Payload::Custom`;
+/// - `TryFrom<T> for Payload`, to send the struct with a `ModuleSender`.
+///
+/// Example:
+///
+/// ```ignore
+/// use pulsar_core::PulsarPayload;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize, PulsarPayload)]
+/// #[payload(name = "UsbAttached")]
+/// pub struct UsbEvent {
+///     pub vendor: String,
+///     pub product: String,
+///     #[payload(skip)]
+///     pub raw: Vec<u8>,
+/// }
+/// ```
+///
+/// Rules can then match it with `type: UsbAttached` and `payload.vendor == "..."`.
+/// The payload name defaults to the struct name.
+#[proc_macro_derive(PulsarPayload, attributes(payload))]
+pub fn derive_pulsar_payload(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input as DeriveInput);
+
+    match impl_pulsar_payload(input) {
+        Ok(ts2) => TokenStream::from(ts2),
+        Err(err) => TokenStream::from(err.to_compile_error()),
+    }
+}
+
+fn impl_pulsar_payload(input: DeriveInput) -> Result<TokenStream2, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "Generic payloads are not supported",
+        ));
+    }
+
+    let name = &input.ident;
+
+    let payload_name = match payload_attributes(&input.attrs)?.name {
+        Some(payload_name) => payload_name,
+        None => name.to_string(),
+    };
+
+    let fields = match &input.data {
+        Data::Struct(data_struct) => match &data_struct.fields {
+            Fields::Named(fields_named) => &fields_named.named,
+            _ => {
+                return Err(Error::new(
+                    data_struct.fields.span(),
+                    "Only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => return Err(Error::new(input.span(), "Only structs are supported")),
+    };
+
+    let mut add_field_lines = Vec::new();
+    for field in fields {
+        if payload_attributes(&field.attrs)?.skip {
+            continue;
+        }
+
+        // Safe because we are processing named fields
+        let field_ident = match field.ident {
+            Some(ref i) => i,
+            None => unreachable!(),
+        };
+
+        let field_name = field_ident.to_string();
+
+        add_field_lines.push(quote! {
+            .add_field(#field_name, Box::new(|x| &x.#field_ident))
+        });
+    }
+
+    let quote = quote! {
+        impl ::pulsar_core::validatron::Validatron for #name {
+            fn get_class() -> ::pulsar_core::validatron::ValidatronClass {
+                <Self as ::pulsar_core::validatron::Validatron>::class_builder()
+                    .struct_class_builder()
+                    #(#add_field_lines)*
+                    .build()
+            }
+        }
+
+        impl ::pulsar_core::event::CustomPayload for #name {
+            const NAME: &'static str = #payload_name;
+        }
+
+        impl ::std::convert::TryFrom<#name> for ::pulsar_core::event::Payload {
+            type Error = String;
+
+            fn try_from(value: #name) -> Result<Self, Self::Error> {
+                ::pulsar_core::event::CustomPayload::into_payload(value)
+            }
+        }
+    };
+
+    Ok(quote)
+}
+
+/// Options set with the `payload` attribute.
+#[derive(Default)]
+struct PayloadAttributes {
+    name: Option<String>,
+    skip: bool,
+}
+
+fn payload_attributes(attrs: &[Attribute]) -> Result<PayloadAttributes, Error> {
+    let mut result = PayloadAttributes::default();
+
+    for attr in attrs {
+        if attr.path().is_ident("payload") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    result.skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("name") {
+                    let name: LitStr = meta.value()?.parse()?;
+                    result.name = Some(name.value());
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported attribute"))
+                }
+            })?;
+        }
+    }
+
+    Ok(result)
+}
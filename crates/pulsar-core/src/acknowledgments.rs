@@ -0,0 +1,138 @@
+//! Acknowledgments of recurring threats, silencing a known-benign threat on
+//! this host for a while without disabling the rule which detects it.
+//!
+//! Every threat has an [`Threat::id`] identifying its recurrences: the same
+//! module reporting the same description for the same image. While the id is
+//! acknowledged, [`crate::pdk::policy::EscalationPolicy::allows`] keeps the
+//! threat from every output module. Threats are still sent on the bus, so they
+//! are kept in the history and counted by the rules engine.
+//!
+//! Acknowledgments are kept in memory and lost when the daemon restarts.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{event::Threat, pdk::process_tracker::exec_chain_hash};
+
+static ACKNOWLEDGMENTS: Mutex<Vec<Acknowledgment>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acknowledgment {
+    /// Acknowledged [`Threat::id`]
+    pub id: String,
+    /// Time after which the threat is notified again
+    pub until: SystemTime,
+    /// Why the threat was acknowledged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Identifier of the recurrences of a threat, from the module reporting it,
+/// its description and the image of the process.
+pub fn threat_id(threat: &Threat, image: &str) -> String {
+    exec_chain_hash(&[
+        threat.source.to_string(),
+        threat.description.clone(),
+        image.to_string(),
+    ])
+}
+
+/// Silence the threats with `id` for `ttl`, replacing a previous
+/// acknowledgment of the same id.
+pub fn acknowledge(id: &str, ttl: Duration, reason: Option<String>) -> Acknowledgment {
+    let acknowledgment = Acknowledgment {
+        id: id.to_string(),
+        until: SystemTime::now() + ttl,
+        reason,
+    };
+    let mut acknowledgments = ACKNOWLEDGMENTS.lock().unwrap();
+    acknowledgments.retain(|acknowledgment| acknowledgment.id != id);
+    acknowledgments.push(acknowledgment.clone());
+    acknowledgment
+}
+
+/// Notify again the threats with `id`. Returns false if it wasn't acknowledged.
+pub fn unacknowledge(id: &str) -> bool {
+    let mut acknowledgments = ACKNOWLEDGMENTS.lock().unwrap();
+    let len = acknowledgments.len();
+    acknowledgments.retain(|acknowledgment| acknowledgment.id != id);
+    acknowledgments.len() != len
+}
+
+/// Acknowledgments not yet expired.
+pub fn acknowledgments() -> Vec<Acknowledgment> {
+    let mut acknowledgments = ACKNOWLEDGMENTS.lock().unwrap();
+    let now = SystemTime::now();
+    acknowledgments.retain(|acknowledgment| acknowledgment.until > now);
+    acknowledgments.clone()
+}
+
+/// The threats with `id` are silenced.
+pub fn is_acknowledged(id: &str) -> bool {
+    if id.is_empty() {
+        return false;
+    }
+    let now = SystemTime::now();
+    ACKNOWLEDGMENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|acknowledgment| acknowledgment.id == id && acknowledgment.until > now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acknowledge_threat() {
+        let id = "1f2e3d4c5b6a7980";
+        assert!(!is_acknowledged(id));
+
+        acknowledge(id, Duration::from_secs(60), Some("backup job".to_string()));
+        assert!(is_acknowledged(id));
+        assert!(acknowledgments()
+            .iter()
+            .any(|acknowledgment| acknowledgment.id == id));
+
+        assert!(unacknowledge(id));
+        assert!(!is_acknowledged(id));
+        assert!(!unacknowledge(id));
+    }
+
+    #[test]
+    fn expired_acknowledgment() {
+        let id = "0a1b2c3d4e5f6071";
+        acknowledge(id, Duration::ZERO, None);
+        assert!(!is_acknowledged(id));
+        assert!(acknowledgments()
+            .iter()
+            .all(|acknowledgment| acknowledgment.id != id));
+        assert!(!is_acknowledged(""));
+    }
+
+    #[test]
+    fn recurring_threat_id() {
+        let threat = Threat {
+            id: String::new(),
+            source: "rules-engine".into(),
+            description: "Curl from ssh session".to_string(),
+            severity: Default::default(),
+            outputs: None,
+            extra: None,
+            evidence: None,
+        };
+        assert_eq!(
+            threat_id(&threat, "/usr/bin/curl"),
+            threat_id(&threat, "/usr/bin/curl")
+        );
+        assert_ne!(
+            threat_id(&threat, "/usr/bin/curl"),
+            threat_id(&threat, "/usr/bin/wget")
+        );
+    }
+}
@@ -1,12 +1,36 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, Weak,
+    },
+};
 use thiserror::Error;
 use tokio::sync::broadcast;
 
-use crate::pdk::Event;
+use crate::{
+    coalesce::{CoalesceConfig, Coalescer},
+    middleware::Middleware,
+    output::OutputFilter,
+    pdk::Event,
+    tap::Taps,
+};
 
 #[derive(Clone)]
 pub struct Bus {
     tx: broadcast::Sender<Arc<Event>>,
+    early_buffer: Option<Arc<EarlyBuffer>>,
+    coalescer: Option<Arc<Coalescer>>,
+    middlewares: Arc<[Arc<dyn Middleware>]>,
+    output_tx: broadcast::Sender<Arc<Event>>,
+    /// Shared by the clones, like the one of the coalescer, so that the filters
+    /// registered later apply to all of them
+    output_filters: Arc<RwLock<Vec<Arc<dyn OutputFilter>>>>,
+    /// Events broadcast to the receivers, the position of the last one
+    broadcasts: Arc<AtomicU64>,
+    /// Events sent to the outputs, the position of the last one
+    output_broadcasts: Arc<AtomicU64>,
+    taps: Arc<Taps>,
 }
 
 /// Describes a bus error.
@@ -18,13 +42,245 @@ pub enum BusError {
 
 const BUFFER_SIZE: usize = 1000;
 
+/// Events lost by the receivers lagging behind, on every bus.
+static LOST_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Events and threats sent, on every bus.
+static SENT_EVENTS: AtomicU64 = AtomicU64::new(0);
+static SENT_THREATS: AtomicU64 = AtomicU64::new(0);
+
+/// Calls of [`Bus::send`] started and completed, on every bus.
+static SENDS_STARTED: AtomicU64 = AtomicU64::new(0);
+static SENDS_COMPLETED: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of events lost by the receivers lagging behind the bus.
+pub fn lost_events() -> u64 {
+    LOST_EVENTS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn count_lost_events(lost: u64) {
+    LOST_EVENTS.fetch_add(lost, Ordering::Relaxed);
+}
+
+/// Total number of events sent on the bus, after coalescing.
+pub fn sent_events() -> u64 {
+    SENT_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Total number of threats sent on the bus.
+pub fn sent_threats() -> u64 {
+    SENT_THREATS.load(Ordering::Relaxed)
+}
+
+/// Calls of [`Bus::send`] started and completed, see [`send_progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendProgress {
+    pub started: u64,
+    pub completed: u64,
+}
+
+impl SendProgress {
+    /// Calls still running the middlewares, the coalescing or the broadcast.
+    pub fn in_flight(&self) -> u64 {
+        self.started.saturating_sub(self.completed)
+    }
+}
+
+/// Progress of the sends on every bus: calls started and never completed mean
+/// a sender is stuck on a lock, see [`crate::watchdog`].
+pub fn send_progress() -> SendProgress {
+    // Completed first, so that it's never ahead of started
+    let completed = SENDS_COMPLETED.load(Ordering::Relaxed);
+    SendProgress {
+        started: SENDS_STARTED.load(Ordering::Relaxed),
+        completed,
+    }
+}
+
+/// Counts a call of [`Bus::send`] as completed when dropped, on every return.
+struct SendGuard;
+
+impl SendGuard {
+    fn start() -> Self {
+        SENDS_STARTED.fetch_add(1, Ordering::Relaxed);
+        SendGuard
+    }
+}
+
+impl Drop for SendGuard {
+    fn drop(&mut self) {
+        SENDS_COMPLETED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Receivers of the modules, see [`receiver_progress`].
+static RECEIVERS: Mutex<Vec<Weak<ReceiverPosition>>> = Mutex::new(Vec::new());
+
+/// Position of a module receiver among the events broadcast by its bus,
+/// updated on every receive.
+#[derive(Debug)]
+pub(crate) struct ReceiverPosition {
+    name: String,
+    broadcasts: Arc<AtomicU64>,
+    /// Events received, or lost by lagging behind
+    position: AtomicU64,
+    /// Events received, including the ones of the early buffer
+    received: AtomicU64,
+}
+
+impl ReceiverPosition {
+    /// Record an event received from the broadcast.
+    pub(crate) fn received(&self) {
+        self.position.fetch_add(1, Ordering::Relaxed);
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the events lost by lagging behind the broadcast.
+    pub(crate) fn lagged(&self, lost: u64) {
+        self.position.fetch_add(lost, Ordering::Relaxed);
+    }
+
+    /// Record an event received from the early buffer.
+    pub(crate) fn received_early(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Progress of a module receiver, see [`receiver_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiverProgress {
+    /// Unique identifier of the receiver
+    pub id: usize,
+    /// Module owning the receiver
+    pub name: String,
+    /// Events received
+    pub received: u64,
+    /// Events broadcast and not received yet
+    pub pending: u64,
+}
+
+/// Progress of the receivers of the modules: a receiver with pending events
+/// and none received means a stuck consumer, see [`crate::watchdog`].
+pub fn receiver_progress() -> Vec<ReceiverProgress> {
+    let mut receivers = RECEIVERS.lock().unwrap();
+    receivers.retain(|receiver| receiver.strong_count() > 0);
+    receivers
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|receiver| {
+            // Position first, so that it's never ahead of the broadcasts
+            let position = receiver.position.load(Ordering::Relaxed);
+            ReceiverProgress {
+                id: Arc::as_ptr(&receiver) as usize,
+                name: receiver.name.clone(),
+                received: receiver.received.load(Ordering::Relaxed),
+                pending: receiver
+                    .broadcasts
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(position),
+            }
+        })
+        .collect()
+}
+
+/// Events sent before the consumers are started, kept to be replayed to them.
+struct EarlyBuffer {
+    active: AtomicBool,
+    capacity: usize,
+    events: Mutex<EarlyEvents>,
+}
+
+#[derive(Default)]
+struct EarlyEvents {
+    events: VecDeque<EarlyEvent>,
+    dropped: u64,
+}
+
+/// Event kept by the early buffer, with the channels it was sent to.
+struct EarlyEvent {
+    event: Arc<Event>,
+    /// Sent to the receivers of the bus
+    bus: bool,
+    /// Sent to the outputs, after the output filters
+    output: bool,
+}
+
 impl Bus {
     pub fn new() -> Self {
         let (tx, _rx) = broadcast::channel(BUFFER_SIZE);
-        Self { tx }
+        let (output_tx, _rx) = broadcast::channel(BUFFER_SIZE);
+        Self {
+            tx,
+            early_buffer: None,
+            coalescer: None,
+            middlewares: Arc::new([]),
+            output_tx,
+            output_filters: Default::default(),
+            broadcasts: Default::default(),
+            output_broadcasts: Default::default(),
+            taps: Default::default(),
+        }
+    }
+
+    /// Create a bus which keeps the last `capacity` events until
+    /// [`Bus::release_early_buffer`] is called. Receivers created in the
+    /// meantime get the kept events first, so modules started late, like during
+    /// boot, don't miss what happened before.
+    pub fn with_early_buffer(capacity: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(BUFFER_SIZE);
+        let (output_tx, _rx) = broadcast::channel(BUFFER_SIZE);
+        Self {
+            tx,
+            early_buffer: Some(Arc::new(EarlyBuffer {
+                active: AtomicBool::new(true),
+                capacity,
+                events: Mutex::new(EarlyEvents::default()),
+            })),
+            coalescer: None,
+            middlewares: Arc::new([]),
+            output_tx,
+            output_filters: Default::default(),
+            broadcasts: Default::default(),
+            output_broadcasts: Default::default(),
+            taps: Default::default(),
+        }
+    }
+
+    /// Coalesce the repeated identical events of the payload types in
+    /// `config`, see [`crate::coalesce`]. Must be called within a Tokio
+    /// runtime, which flushes the coalesced events.
+    pub fn with_coalescing(self, config: CoalesceConfig) -> Self {
+        if config.intervals.is_empty() {
+            return self;
+        }
+        let coalescer = Coalescer::start(self.clone(), config);
+        Self {
+            coalescer: Some(coalescer),
+            ..self
+        }
+    }
+
+    /// Run the middleware on the events sent, after the ones already
+    /// registered, see [`crate::middleware`].
+    pub fn with_middleware(self, middleware: impl Middleware) -> Self {
+        let mut middlewares = self.middlewares.to_vec();
+        middlewares.push(Arc::new(middleware));
+        Self {
+            middlewares: middlewares.into(),
+            ..self
+        }
+    }
+
+    /// Run the filter on the events sent to the outputs, after the ones
+    /// already registered, see [`crate::output`]. The filters are shared with
+    /// the existing clones of the bus.
+    pub fn with_output_filter(self, filter: impl OutputFilter) -> Self {
+        self.output_filters.write().unwrap().push(Arc::new(filter));
+        self
     }
 
     pub fn send(&self, event: Event) -> Result<(), BusError> {
+        let _guard = SendGuard::start();
         log::trace!(
             target: &format!("event::{}", event.header.source),
             "{:?} [{}:{}]  {:?}",
@@ -34,10 +290,89 @@ impl Bus {
             event.payload
         );
 
-        let _ = self.tx.send(Arc::new(event));
+        let Some(event) = self
+            .middlewares
+            .iter()
+            .try_fold(event, |event, middleware| middleware.process(event))
+        else {
+            return Ok(());
+        };
+        let event = match &self.coalescer {
+            Some(coalescer) => match coalescer.coalesce(event) {
+                Some(event) => event,
+                None => return Ok(()),
+            },
+            None => event,
+        };
+        self.broadcast(event);
         Ok(())
     }
 
+    /// Send the event to the taps and the receivers, skipping the middlewares
+    /// and the coalescing.
+    pub(crate) fn broadcast(&self, event: Event) {
+        SENT_EVENTS.fetch_add(1, Ordering::Relaxed);
+        self.broadcasts.fetch_add(1, Ordering::Relaxed);
+        if event.header.threat.is_some() {
+            SENT_THREATS.fetch_add(1, Ordering::Relaxed);
+        }
+        let event = Arc::new(event);
+        let output = self
+            .output_filters
+            .read()
+            .unwrap()
+            .iter()
+            .all(|filter| filter.keep(&event));
+        let _early = self.keep_early(&event, true, output);
+        self.taps.publish(&event);
+        if output {
+            self.output_broadcasts.fetch_add(1, Ordering::Relaxed);
+            let _ = self.output_tx.send(event.clone());
+        }
+        let _ = self.tx.send(event);
+    }
+
+    /// Send an event to the outputs only, like the summaries of the output
+    /// filters.
+    pub(crate) fn send_output(&self, event: Event) {
+        SENT_EVENTS.fetch_add(1, Ordering::Relaxed);
+        let event = Arc::new(event);
+        let _early = self.keep_early(&event, false, true);
+        self.output_broadcasts.fetch_add(1, Ordering::Relaxed);
+        let _ = self.output_tx.send(event);
+    }
+
+    /// While the early buffer is active, keep the event sent to the bus and/or
+    /// the outputs, returning the lock to hold while sending it, so that
+    /// receivers created concurrently see every event once.
+    fn keep_early(
+        &self,
+        event: &Arc<Event>,
+        bus: bool,
+        output: bool,
+    ) -> Option<MutexGuard<'_, EarlyEvents>> {
+        self.active_early_buffer().map(|early_buffer| {
+            let mut early = early_buffer.events.lock().unwrap();
+            if early_buffer.active.load(Ordering::Relaxed) && (bus || output) {
+                if early.events.len() >= early_buffer.capacity {
+                    early.events.pop_front();
+                    early.dropped += 1;
+                }
+                early.events.push_back(EarlyEvent {
+                    event: event.clone(),
+                    bus,
+                    output,
+                });
+            }
+            early
+        })
+    }
+
+    /// Number of events not yet received by every receiver.
+    pub fn pending(&self) -> usize {
+        self.tx.len()
+    }
+
     pub fn get_sender(&self) -> Self {
         self.clone()
     }
@@ -45,6 +380,96 @@ impl Bus {
     pub fn get_receiver(&self) -> broadcast::Receiver<Arc<Event>> {
         self.tx.subscribe()
     }
+
+    /// Get a receiver of the events sent to the outputs, which went through
+    /// the output filters.
+    pub fn get_output_receiver(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.output_tx.subscribe()
+    }
+
+    /// Get a receiver along with the events kept by the early buffer, which
+    /// precede the ones received from it.
+    pub fn get_receiver_with_backlog(
+        &self,
+    ) -> (VecDeque<Arc<Event>>, broadcast::Receiver<Arc<Event>>) {
+        self.with_backlog(&self.tx, |early| early.bus)
+    }
+
+    /// Like [`Bus::get_receiver_with_backlog`], for the events sent to the
+    /// outputs.
+    pub fn get_output_receiver_with_backlog(
+        &self,
+    ) -> (VecDeque<Arc<Event>>, broadcast::Receiver<Arc<Event>>) {
+        self.with_backlog(&self.output_tx, |early| early.output)
+    }
+
+    fn with_backlog(
+        &self,
+        tx: &broadcast::Sender<Arc<Event>>,
+        sent_to: impl Fn(&EarlyEvent) -> bool,
+    ) -> (VecDeque<Arc<Event>>, broadcast::Receiver<Arc<Event>>) {
+        match self.active_early_buffer() {
+            Some(early_buffer) => {
+                let early = early_buffer.events.lock().unwrap();
+                let backlog = early
+                    .events
+                    .iter()
+                    .filter(|early| sent_to(early))
+                    .map(|early| early.event.clone())
+                    .collect();
+                (backlog, tx.subscribe())
+            }
+            None => (VecDeque::new(), tx.subscribe()),
+        }
+    }
+
+    /// Track the progress of the receiver of a module, created at the same
+    /// time, see [`receiver_progress`].
+    pub(crate) fn track_receiver(&self, name: &str) -> Arc<ReceiverPosition> {
+        track(name, &self.broadcasts)
+    }
+
+    /// Like [`Bus::track_receiver`], for a receiver of the events sent to the
+    /// outputs.
+    pub(crate) fn track_output_receiver(&self, name: &str) -> Arc<ReceiverPosition> {
+        track(name, &self.output_broadcasts)
+    }
+
+    pub(crate) fn taps(&self) -> Arc<Taps> {
+        self.taps.clone()
+    }
+
+    /// Stop keeping events for new receivers and free the early buffer.
+    pub fn release_early_buffer(&self) {
+        if let Some(early_buffer) = self.active_early_buffer() {
+            let mut early = early_buffer.events.lock().unwrap();
+            early_buffer.active.store(false, Ordering::Relaxed);
+            if early.dropped > 0 {
+                log::warn!(
+                    "Early buffer full: {} events sent before startup was completed were lost",
+                    early.dropped
+                );
+            }
+            *early = EarlyEvents::default();
+        }
+    }
+
+    fn active_early_buffer(&self) -> Option<&EarlyBuffer> {
+        self.early_buffer
+            .as_deref()
+            .filter(|early_buffer| early_buffer.active.load(Ordering::Relaxed))
+    }
+}
+
+fn track(name: &str, broadcasts: &Arc<AtomicU64>) -> Arc<ReceiverPosition> {
+    let receiver = Arc::new(ReceiverPosition {
+        name: name.to_string(),
+        broadcasts: broadcasts.clone(),
+        position: AtomicU64::new(broadcasts.load(Ordering::Relaxed)),
+        received: AtomicU64::new(0),
+    });
+    RECEIVERS.lock().unwrap().push(Arc::downgrade(&receiver));
+    receiver
 }
 
 impl Default for Bus {
@@ -52,3 +477,123 @@ impl Default for Bus {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::event::{Header, Payload};
+
+    fn event(exit_code: u32) -> Event {
+        Event::new(
+            Header {
+                pid: 42,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code },
+        )
+    }
+
+    fn exit_code(event: &Event) -> u32 {
+        match event.payload() {
+            Payload::Exit { exit_code } => *exit_code,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn early_buffer() {
+        let bus = Bus::with_early_buffer(2);
+        for i in 0..3 {
+            bus.send(event(i)).unwrap();
+        }
+
+        let (backlog, mut rx) = bus.get_receiver_with_backlog();
+        assert_eq!(
+            backlog.iter().map(|e| exit_code(e)).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        bus.send(event(3)).unwrap();
+        assert_eq!(exit_code(&rx.try_recv().unwrap()), 3);
+
+        bus.release_early_buffer();
+        let (backlog, _rx) = bus.get_receiver_with_backlog();
+        assert!(backlog.is_empty());
+    }
+
+    #[test]
+    fn middlewares() {
+        struct AddOne;
+
+        impl Middleware for AddOne {
+            fn process(&self, mut event: Event) -> Option<Event> {
+                if let Payload::Exit { exit_code } = &mut event.payload {
+                    *exit_code += 1;
+                }
+                Some(event)
+            }
+        }
+
+        struct DropOdd;
+
+        impl Middleware for DropOdd {
+            fn process(&self, event: Event) -> Option<Event> {
+                exit_code(&event).is_multiple_of(2).then_some(event)
+            }
+        }
+
+        // Run in order: dropping after the increment
+        let bus = Bus::new().with_middleware(AddOne).with_middleware(DropOdd);
+        let mut rx = bus.get_receiver();
+        for i in 0..4 {
+            bus.send(event(i)).unwrap();
+        }
+        assert_eq!(exit_code(&rx.try_recv().unwrap()), 2);
+        assert_eq!(exit_code(&rx.try_recv().unwrap()), 4);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn receivers() {
+        let bus = Bus::new();
+        let position = bus.track_receiver("test");
+        let id = Arc::as_ptr(&position) as usize;
+        let progress = || {
+            receiver_progress()
+                .into_iter()
+                .find(|receiver| receiver.id == id)
+                .map(|receiver| (receiver.received, receiver.pending))
+        };
+        for i in 0..3 {
+            bus.send(event(i)).unwrap();
+        }
+        assert_eq!(progress(), Some((0, 3)));
+        position.received();
+        position.lagged(1);
+        assert_eq!(progress(), Some((1, 1)));
+        drop(position);
+        assert_eq!(progress(), None);
+    }
+
+    #[test]
+    fn progress() {
+        struct DropAll;
+
+        impl Middleware for DropAll {
+            fn process(&self, _event: Event) -> Option<Event> {
+                None
+            }
+        }
+
+        let before = send_progress();
+        let bus = Bus::new().with_middleware(DropAll);
+        for i in 0..3 {
+            bus.send(event(i)).unwrap();
+        }
+        // Dropped events complete too. Other tests send concurrently.
+        let after = send_progress();
+        assert!(after.completed >= before.completed + 3);
+        assert!(after.started >= after.completed);
+    }
+}
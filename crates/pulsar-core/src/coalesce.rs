@@ -0,0 +1,337 @@
+//! Coalescing of repeated identical events, like a process rewriting the same
+//! file or sending the same UDP datagram in a loop.
+//!
+//! Events of the configured payload types are held by the [`Bus`] for their
+//! interval: the ones of the same process with the same payload are merged into
+//! the first one, which is then sent with the number of repetitions in the
+//! [`Coalesced`] header.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::{
+    bus::Bus,
+    event::{Coalesced, PayloadDiscriminant},
+    pdk::{ConfigError, Event, ModuleConfig},
+};
+
+/// Events waiting for their interval to elapse, above which the new ones
+/// are sent without coalescing.
+const MAX_PENDING: usize = 10000;
+
+/// Period of the check for the elapsed intervals.
+const FLUSH_PERIOD: Duration = Duration::from_millis(100);
+
+const DEFAULT_INTERVAL: u64 = 1;
+
+/// Payload types to coalesce with their interval.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    pub intervals: HashMap<PayloadDiscriminant, Duration>,
+}
+
+impl TryFrom<&ModuleConfig> for CoalesceConfig {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let default_interval = config.with_default("coalesce_interval", DEFAULT_INTERVAL)?;
+        let intervals = config
+            .get_list_with_default::<CoalesceEntry>("coalesce_events", Vec::new())?
+            .into_iter()
+            .map(|entry| {
+                let secs = entry.interval.unwrap_or(default_interval);
+                (entry.payload_type, Duration::from_secs(secs))
+            })
+            .collect();
+        Ok(Self { intervals })
+    }
+}
+
+/// Item of `coalesce_events`: a payload type, optionally followed by its own
+/// interval in seconds, like `FileOpened:5`.
+struct CoalesceEntry {
+    payload_type: PayloadDiscriminant,
+    interval: Option<u64>,
+}
+
+#[derive(Error, Debug)]
+#[error("{0} is not a payload type, optionally followed by `:<seconds>`")]
+struct InvalidCoalesceEntry(String);
+
+impl FromStr for CoalesceEntry {
+    type Err = InvalidCoalesceEntry;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCoalesceEntry(s.to_string());
+        let (payload_type, interval) = match s.split_once(':') {
+            Some((payload_type, interval)) => {
+                (payload_type, Some(interval.parse().map_err(|_| invalid())?))
+            }
+            None => (s, None),
+        };
+        Ok(Self {
+            payload_type: PayloadDiscriminant::from_str(payload_type.trim())
+                .map_err(|_| invalid())?,
+            interval,
+        })
+    }
+}
+
+/// Identity of the repetitions of an event.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    source: String,
+    pid: i32,
+    image: String,
+    payload: String,
+}
+
+struct Pending {
+    event: Event,
+    deadline: Instant,
+    coalesced: Coalesced,
+}
+
+pub(crate) struct Coalescer {
+    /// Bus receiving the coalesced events, without coalescing.
+    bus: Bus,
+    intervals: HashMap<PayloadDiscriminant, Duration>,
+    pending: Mutex<HashMap<Key, Pending>>,
+}
+
+impl Coalescer {
+    /// Create the coalescer of `bus`, flushed by a background task until it's
+    /// dropped.
+    pub(crate) fn start(bus: Bus, config: CoalesceConfig) -> Arc<Self> {
+        let coalescer = Arc::new(Self {
+            bus,
+            intervals: config.intervals,
+            pending: Mutex::new(HashMap::new()),
+        });
+        let weak = Arc::downgrade(&coalescer);
+        tokio::spawn(flush_task(weak));
+        coalescer
+    }
+
+    /// Hold the event if it must be coalesced, otherwise give it back.
+    pub(crate) fn coalesce(&self, event: Event) -> Option<Event> {
+        if event.header.threat.is_some() {
+            return Some(event);
+        }
+        let payload_type = PayloadDiscriminant::from(&event.payload);
+        let Some(interval) = self.intervals.get(&payload_type) else {
+            return Some(event);
+        };
+        let payload = match serde_json::to_string(&event.payload) {
+            Ok(payload) => payload,
+            Err(_) => return Some(event),
+        };
+        let key = Key {
+            source: event.header.source.to_string(),
+            pid: event.header.pid,
+            image: event.header.image.clone(),
+            payload,
+        };
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(pending) = pending.get_mut(&key) {
+            pending.coalesced.count += 1;
+            pending.coalesced.last_seen = event.header.timestamp;
+            return None;
+        }
+        if pending.len() >= MAX_PENDING {
+            return Some(event);
+        }
+        let timestamp = event.header.timestamp;
+        pending.insert(
+            key,
+            Pending {
+                event,
+                deadline: Instant::now() + *interval,
+                coalesced: Coalesced {
+                    count: 1,
+                    first_seen: timestamp,
+                    last_seen: timestamp,
+                },
+            },
+        );
+        None
+    }
+
+    /// Send the events whose interval elapsed before `now`.
+    pub(crate) fn flush(&self, now: Instant) {
+        let expired: HashMap<Key, Pending> = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.values().all(|pending| pending.deadline > now) {
+                return;
+            }
+            let (expired, waiting) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition(|(_, pending)| pending.deadline <= now);
+            *pending = waiting;
+            expired
+        };
+        for Pending {
+            mut event,
+            coalesced,
+            ..
+        } in expired.into_values()
+        {
+            if coalesced.count > 1 {
+                event.header.coalesced = Some(coalesced);
+            }
+            self.bus.broadcast(event);
+        }
+    }
+}
+
+async fn flush_task(coalescer: Weak<Coalescer>) {
+    let mut interval = tokio::time::interval(FLUSH_PERIOD);
+    loop {
+        interval.tick().await;
+        match coalescer.upgrade() {
+            Some(coalescer) => coalescer.flush(Instant::now()),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::{
+        event::{Header, Payload},
+        output::Sample,
+    };
+
+    fn event(pid: i32, filename: &str, timestamp: SystemTime) -> Event {
+        Event::new(
+            Header {
+                image: "/usr/bin/logger".to_string(),
+                pid,
+                parent_pid: 1,
+                timestamp,
+                ..Header::test_default()
+            },
+            Payload::FileCreated {
+                filename: filename.to_string(),
+            },
+        )
+    }
+
+    fn coalescer(bus: &Bus) -> Coalescer {
+        Coalescer {
+            bus: bus.clone(),
+            intervals: HashMap::from([(PayloadDiscriminant::FileCreated, Duration::from_secs(5))]),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn config() {
+        let mut config = ModuleConfig::default();
+        config.insert(
+            "coalesce_events".to_string(),
+            "FileCreated:5,Exit".to_string(),
+        );
+        config.insert("coalesce_interval".to_string(), "2".to_string());
+        assert_eq!(
+            CoalesceConfig::try_from(&config).unwrap().intervals,
+            HashMap::from([
+                (PayloadDiscriminant::FileCreated, Duration::from_secs(5)),
+                (PayloadDiscriminant::Exit, Duration::from_secs(2)),
+            ])
+        );
+
+        config.insert("coalesce_events".to_string(), "FileCreate".to_string());
+        assert!(CoalesceConfig::try_from(&config).is_err());
+        config.insert(
+            "coalesce_events".to_string(),
+            "FileCreated:soon".to_string(),
+        );
+        assert!(CoalesceConfig::try_from(&config).is_err());
+    }
+
+    #[test]
+    fn coalesce_identical_events() {
+        let bus = Bus::new();
+        let mut rx = bus.get_receiver();
+        let coalescer = coalescer(&bus);
+        let first = UNIX_EPOCH + Duration::from_secs(10);
+        let last = UNIX_EPOCH + Duration::from_secs(12);
+
+        assert!(coalescer.coalesce(event(42, "/tmp/a", first)).is_none());
+        assert!(coalescer.coalesce(event(42, "/tmp/a", first)).is_none());
+        assert!(coalescer.coalesce(event(42, "/tmp/a", last)).is_none());
+        assert!(coalescer.coalesce(event(42, "/tmp/b", last)).is_none());
+        assert!(coalescer.coalesce(event(43, "/tmp/a", last)).is_none());
+
+        coalescer.flush(Instant::now());
+        assert!(rx.try_recv().is_err());
+
+        coalescer.flush(Instant::now() + Duration::from_secs(5));
+        let mut events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        events.sort_by_key(|event| (event.header.pid, format!("{:?}", event.payload)));
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].header.coalesced,
+            Some(Coalesced {
+                count: 3,
+                first_seen: first,
+                last_seen: last,
+            })
+        );
+        assert_eq!(events[1].header.coalesced, None);
+        assert_eq!(events[2].header.coalesced, None);
+    }
+
+    #[test]
+    fn output_filters() {
+        let bus = Bus::new();
+        let coalescer = coalescer(&bus);
+        // Registered after the coalescer got its bus
+        let bus = bus.with_output_filter(Sample::new(HashMap::from([(
+            PayloadDiscriminant::FileCreated,
+            2,
+        )])));
+        let mut rx = bus.get_receiver();
+        let mut output = bus.get_output_receiver();
+
+        for filename in ["/tmp/a", "/tmp/b", "/tmp/c"] {
+            assert!(coalescer
+                .coalesce(event(42, filename, UNIX_EPOCH))
+                .is_none());
+        }
+        coalescer.flush(Instant::now() + Duration::from_secs(5));
+        assert_eq!(std::iter::from_fn(|| rx.try_recv().ok()).count(), 3);
+        assert_eq!(std::iter::from_fn(|| output.try_recv().ok()).count(), 2);
+    }
+
+    #[test]
+    fn other_events_pass() {
+        let bus = Bus::new();
+        let coalescer = coalescer(&bus);
+        let mut exit = event(42, "/tmp/a", UNIX_EPOCH);
+        exit.payload = Payload::Exit { exit_code: 0 };
+        assert!(coalescer.coalesce(exit).is_some());
+
+        let mut threat = event(42, "/tmp/a", UNIX_EPOCH);
+        threat.header.threat = Some(crate::event::Threat {
+            id: String::new(),
+            source: "test".into(),
+            description: "threat".to_string(),
+            severity: Default::default(),
+            outputs: None,
+            extra: None,
+            evidence: None,
+        });
+        assert!(coalescer.coalesce(threat).is_some());
+    }
+}
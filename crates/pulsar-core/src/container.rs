@@ -0,0 +1,154 @@
+//! Containers of the processes, identified from their cgroup.
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+/// Prefixes of the systemd scopes of the containers, by runtime.
+const SCOPE_PREFIXES: &[(&str, &str)] = &[
+    ("docker-", "docker"),
+    ("cri-containerd-", "containerd"),
+    ("crio-", "cri-o"),
+    ("libpod-", "podman"),
+];
+
+/// Programs of the runtimes isolating containers in a user-mode kernel or a
+/// virtual machine, by prefix of their file name.
+const SANDBOXED_RUNTIMES: &[(&str, &str)] = &[
+    ("runsc", "gvisor"),
+    ("containerd-shim-runsc-", "gvisor"),
+    ("containerd-shim-kata-", "kata"),
+    ("kata-runtime", "kata"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Container {
+    /// Id of the container, 64 hexadecimal characters
+    pub id: String,
+    /// Runtime which created the container, like `docker` or `containerd`,
+    /// or `unknown`
+    pub runtime: String,
+}
+
+/// Container of a running process, if any.
+pub fn of_process(pid: i32) -> Option<Container> {
+    let cgroup = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    from_cgroup(&cgroup)
+}
+
+/// Container of a process from the content of its `/proc/<pid>/cgroup`, with
+/// lines like `0::/system.slice/docker-<id>.scope` or `4:memory:/docker/<id>`.
+pub fn from_cgroup(cgroup: &str) -> Option<Container> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .find_map(from_cgroup_path)
+}
+
+fn from_cgroup_path(path: &str) -> Option<Container> {
+    path.rsplit('/').find_map(|segment| {
+        let segment = segment.trim_end_matches(".scope");
+        let (id, runtime) = SCOPE_PREFIXES
+            .iter()
+            .find_map(|(prefix, runtime)| Some((segment.strip_prefix(prefix)?, *runtime)))
+            .unwrap_or_else(|| {
+                // Layout of the cgroupfs driver, like /docker/<id>
+                let runtime = match path.trim_start_matches('/').split('/').next() {
+                    Some("docker") => "docker",
+                    Some(root) if root.starts_with("kubepods") => "kubernetes",
+                    _ => "unknown",
+                };
+                (segment, runtime)
+            });
+        (id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit())).then(|| Container {
+            id: id.to_string(),
+            runtime: runtime.to_string(),
+        })
+    })
+}
+
+/// Sandboxed runtime of a process, `gvisor` or `kata`, from the images in its
+/// exec chain. The host only sees the processes of the sandbox: the gVisor
+/// kernel and gofer started by `runsc`, or the virtual machine and shim of
+/// Kata Containers, not the ones of the application.
+pub fn sandboxed_runtime(exec_chain: &[String]) -> Option<&'static str> {
+    exec_chain.iter().find_map(|image| {
+        let name = image.rsplit('/').next()?;
+        SANDBOXED_RUNTIMES
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map(|(_, runtime)| *runtime)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4f9d2a7c1e8b4f9d2a7c1e8b4f9d2a7c1e8b4f9d2a7c1e8b4f9d2a7c1e8b4f9d";
+
+    fn container(id: &str, runtime: &str) -> Option<Container> {
+        Some(Container {
+            id: id.to_string(),
+            runtime: runtime.to_string(),
+        })
+    }
+
+    #[test]
+    fn cgroups() {
+        assert_eq!(
+            from_cgroup(&format!("0::/system.slice/docker-{ID}.scope\n")),
+            container(ID, "docker")
+        );
+        assert_eq!(
+            from_cgroup(&format!(
+                "12:cpuset:/\n4:memory:/docker/{ID}\n1:name=systemd:/docker/{ID}\n"
+            )),
+            container(ID, "docker")
+        );
+        assert_eq!(
+            from_cgroup(&format!(
+                "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1.slice/cri-containerd-{ID}.scope"
+            )),
+            container(ID, "containerd")
+        );
+        assert_eq!(
+            from_cgroup(&format!("0::/kubepods/besteffort/pod1/{ID}")),
+            container(ID, "kubernetes")
+        );
+        assert_eq!(
+            from_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            None
+        );
+        assert_eq!(from_cgroup(""), None);
+    }
+
+    #[test]
+    fn sandboxed_runtimes() {
+        let chain = |images: &[&str]| -> Vec<String> {
+            images.iter().map(|image| image.to_string()).collect()
+        };
+        assert_eq!(
+            sandboxed_runtime(&chain(&[
+                "/usr/lib/systemd/systemd",
+                "/usr/bin/containerd-shim-runc-v2",
+                "/usr/local/bin/runsc",
+            ])),
+            Some("gvisor")
+        );
+        assert_eq!(
+            sandboxed_runtime(&chain(&[
+                "/usr/bin/containerd-shim-kata-v2",
+                "/opt/kata/bin/qemu-system-x86_64",
+            ])),
+            Some("kata")
+        );
+        assert_eq!(
+            sandboxed_runtime(&chain(&[
+                "/usr/bin/containerd-shim-runc-v2",
+                "/usr/sbin/nginx"
+            ])),
+            None
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Normalization of domain names, so that rules match the name displayed to
+//! users instead of its encoding in DNS packets, and scoring of the names
+//! likely generated by an algorithm.
+
+/// Prefix of the labels encoded with punycode.
+const ACE_PREFIX: &str = "xn--";
+
+/// Normalize a domain name: labels encoded with punycode are decoded, the name
+/// is lowercased and the trailing dot of fully qualified names is removed.
+///
+/// Labels which are not valid punycode are kept as they are.
+pub fn normalize(name: &str) -> String {
+    name.trim_end_matches('.')
+        .split('.')
+        .map(|label| {
+            let lowercase = label.to_ascii_lowercase();
+            lowercase
+                .strip_prefix(ACE_PREFIX)
+                .and_then(punycode_decode)
+                .unwrap_or(lowercase)
+                .to_lowercase()
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Parameters of punycode, see RFC 3492
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Decode a punycode label, without the `xn--` prefix.
+fn punycode_decode(input: &str) -> Option<String> {
+    // Basic code points come before the last delimiter
+    let (basic, extended) = match input.rfind('-') {
+        Some(index) => (&input[..index], &input[index + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut digits = extended.bytes().peekable();
+    let (mut n, mut i, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut weight = 1u32;
+        let mut k = BASE;
+        loop {
+            let digit = match digits.next()? {
+                byte @ b'a'..=b'z' => byte - b'a',
+                byte @ b'A'..=b'Z' => byte - b'A',
+                byte @ b'0'..=b'9' => byte - b'0' + 26,
+                _ => return None,
+            } as u32;
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+            let threshold = if k <= bias {
+                T_MIN
+            } else if k >= bias + T_MAX {
+                T_MAX
+            } else {
+                k - bias
+            };
+            if digit < threshold {
+                break;
+            }
+            weight = weight.checked_mul(BASE - threshold)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+/// Labels shorter than this are too short to tell if they are random.
+const DGA_MIN_LEN: usize = 8;
+
+/// Most frequent pairs of letters in English text, sorted.
+const COMMON_BIGRAMS: &[&str] = &[
+    "ab", "ac", "ad", "ae", "af", "ag", "ah", "ai", "ak", "al", "am", "an", "ap", "ar", "as", "at",
+    "au", "av", "aw", "ax", "ay", "az", "ba", "be", "bi", "bl", "bo", "br", "bs", "bt", "bu", "by",
+    "ca", "cc", "ce", "ch", "ci", "ck", "cl", "co", "cr", "cs", "ct", "cu", "cy", "da", "dd", "de",
+    "dg", "di", "dl", "do", "dr", "ds", "du", "dy", "ea", "eb", "ec", "ed", "ee", "ef", "eg", "ei",
+    "ek", "el", "em", "en", "eo", "ep", "eq", "er", "es", "et", "eu", "ev", "ew", "ex", "ey", "fa",
+    "fe", "ff", "fi", "fl", "fo", "fr", "ft", "fu", "fy", "ga", "ge", "gg", "gh", "gi", "gl", "gn",
+    "go", "gr", "gs", "gt", "gu", "ha", "he", "hi", "hn", "ho", "hr", "hs", "ht", "hu", "hy", "ia",
+    "ib", "ic", "id", "ie", "if", "ig", "ii", "ik", "il", "im", "in", "io", "ip", "ir", "is", "it",
+    "iu", "iv", "ix", "iz", "je", "jo", "ju", "ka", "ke", "ki", "kl", "kn", "ks", "la", "ld", "le",
+    "lf", "li", "lk", "ll", "lm", "lo", "lp", "ls", "lt", "lu", "lv", "ly", "ma", "mb", "me", "mi",
+    "mm", "mo", "mp", "ms", "mu", "my", "na", "nc", "nd", "ne", "nf", "ng", "nh", "ni", "nk", "nl",
+    "nn", "no", "ns", "nt", "nu", "nv", "ny", "oa", "ob", "oc", "od", "oe", "of", "og", "oh", "oi",
+    "ok", "ol", "om", "on", "oo", "op", "or", "os", "ot", "ou", "ov", "ow", "ox", "oy", "pa", "pe",
+    "ph", "pi", "pl", "po", "pp", "pr", "ps", "pt", "pu", "py", "qu", "ra", "rb", "rc", "rd", "re",
+    "rf", "rg", "rh", "ri", "rk", "rl", "rm", "rn", "ro", "rp", "rr", "rs", "rt", "ru", "rv", "ry",
+    "sa", "sc", "se", "sf", "sh", "si", "sk", "sl", "sm", "sn", "so", "sp", "ss", "st", "su", "sw",
+    "sy", "ta", "te", "th", "ti", "tl", "tm", "tn", "to", "tr", "ts", "tt", "tu", "tw", "ty", "ua",
+    "ub", "uc", "ud", "ue", "uf", "ug", "ui", "ul", "um", "un", "uo", "up", "ur", "us", "ut", "va",
+    "ve", "vi", "vo", "wa", "we", "wh", "wi", "wn", "wo", "wr", "ws", "xa", "xe", "xp", "xt", "ya",
+    "yc", "ye", "yi", "yl", "yn", "yo", "yp", "ys", "yt", "za", "ze", "zi",
+];
+
+/// Likelihood, from 0 to 100, of a domain name being generated by an
+/// algorithm, like the ones of malware looking for their command and control
+/// servers.
+///
+/// The score is computed on the longest label before the top level domain,
+/// from its entropy, the share of letter pairs uncommon in English words,
+/// digits mixed with letters and long runs of consonants. Names over 60 are
+/// likely generated, while dictionary words and brands stay below 30.
+pub fn dga_score(name: &str) -> u32 {
+    let mut labels: Vec<&str> = name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+        .collect();
+    if labels.len() > 1 {
+        labels.pop();
+    }
+    let Some(label) = labels.into_iter().max_by_key(|label| label.len()) else {
+        return 0;
+    };
+    let label = label.to_ascii_lowercase();
+    let len = label.len();
+    if len < DGA_MIN_LEN {
+        return 0;
+    }
+
+    // Entropy relative to the one of a random string of the same length
+    let mut counts = [0usize; 256];
+    for byte in label.bytes() {
+        counts[byte as usize] += 1;
+    }
+    let entropy: f64 = counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len as f64;
+            -p * p.log2()
+        })
+        .sum();
+    let entropy = entropy / (len.min(36) as f64).log2();
+
+    let bytes = label.as_bytes();
+    let pairs: Vec<&[u8]> = bytes
+        .windows(2)
+        .filter(|pair| pair.iter().all(u8::is_ascii_alphabetic))
+        .collect();
+    let uncommon = if pairs.is_empty() {
+        1.0
+    } else {
+        let uncommon = pairs
+            .iter()
+            .filter(|pair| {
+                COMMON_BIGRAMS
+                    .binary_search_by(|bigram| bigram.as_bytes().cmp(pair))
+                    .is_err()
+            })
+            .count();
+        uncommon as f64 / pairs.len() as f64
+    };
+
+    let digits = bytes.iter().filter(|byte| byte.is_ascii_digit()).count();
+    let mixed_digits = digits > 0 && digits < len && digits as f64 / len as f64 > 0.15;
+
+    let mut run = 0;
+    let mut longest_run = 0u32;
+    for byte in bytes {
+        if byte.is_ascii_alphabetic() && !b"aeiouy".contains(byte) {
+            run += 1;
+            longest_run = longest_run.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    let consonants = f64::from(longest_run.saturating_sub(3).min(3)) / 3.0;
+
+    let score = 0.25 * ((entropy - 0.8) / 0.2).max(0.0)
+        + 0.45 * uncommon
+        + if mixed_digits { 0.15 } else { 0.0 }
+        + 0.15 * consonants;
+    (100.0 * score.clamp(0.0, 1.0)).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_punycode() {
+        assert_eq!(punycode_decode("mnchen-3ya").as_deref(), Some("münchen"));
+        assert_eq!(punycode_decode("pypal-4ve").as_deref(), Some("pаypal"));
+        assert_eq!(punycode_decode("r8jz45g").as_deref(), Some("例え"));
+        assert_eq!(punycode_decode("abc-").as_deref(), Some("abc"));
+        assert_eq!(punycode_decode("mnchen-3y!"), None);
+        assert_eq!(punycode_decode("zzzzzzzzzzzz"), None);
+    }
+
+    #[test]
+    fn normalize_names() {
+        assert_eq!(normalize("Example.COM."), "example.com");
+        assert_eq!(normalize("xn--pypal-4ve.com"), "pаypal.com");
+        assert_eq!(normalize("XN--MNCHEN-3YA.de"), "münchen.de");
+        // Invalid labels are kept
+        assert_eq!(normalize("xn--mnchen-3y!.de"), "xn--mnchen-3y!.de");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn dga_scores() {
+        for name in [
+            "google.com",
+            "wikipedia.org",
+            "stackoverflow.com",
+            "githubusercontent.com",
+            "login.microsoftonline.com",
+            "s3-eu-west-1.amazonaws.com",
+            "xn--pypal-4ve.com",
+        ] {
+            assert!(dga_score(name) < 30, "{name}: {}", dga_score(name));
+        }
+        for name in [
+            "xjw3k9qpz7vmd.com",
+            "qwkfjzhtyplm.org",
+            "4f9d2a7c1e8b.com",
+            "rgcmwdxvlfebqnpk.com.",
+        ] {
+            assert!(dga_score(name) > 60, "{name}: {}", dga_score(name));
+        }
+        assert_eq!(dga_score("qzx.com"), 0);
+        assert_eq!(dga_score(""), 0);
+    }
+}
@@ -1,6 +1,11 @@
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display},
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::SystemTime,
 };
 
@@ -9,8 +14,10 @@ use strum::{EnumDiscriminants, EnumString};
 use validatron::{Operator, Validatron, ValidatronError};
 
 use crate::{
+    host::{self, HostInfo},
     kernel::{self},
     pdk::ModuleName,
+    shell::ShellCommand,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validatron)]
@@ -20,6 +27,11 @@ pub struct Event {
 }
 
 impl Event {
+    /// Constructs a new event. Useful to feed events from other sources to the rules engine.
+    pub fn new(header: Header, payload: Payload) -> Self {
+        Self { header, payload }
+    }
+
     pub fn header(&self) -> &Header {
         &self.header
     }
@@ -29,18 +41,117 @@ impl Event {
     }
 }
 
+/// Returns a new event id, unique for the lifetime of the process.
+pub fn next_event_id() -> u64 {
+    static NEXT_EVENT_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_EVENT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Validatron)]
 pub struct Header {
+    /// Unique identifier of the event, see [`next_event_id`].
+    #[validatron(skip)]
+    #[serde(default)]
+    pub id: u64,
+    /// Identifier of the event which caused this one, set on derived events and threats.
+    #[validatron(skip)]
+    #[serde(default)]
+    pub parent_event_id: Option<u64>,
     pub image: String,
     pub pid: i32,
     pub parent_pid: i32,
     #[validatron(skip)]
     pub threat: Option<Threat>,
     pub source: ModuleName,
+    /// Wall clock time of the event.
     #[validatron(skip)]
     pub timestamp: SystemTime,
+    /// Time of the event in nanoseconds since boot, as read by the eBPF probe
+    /// from the monotonic clock, unaffected by wall clock changes.
+    #[validatron(skip)]
+    #[serde(default)]
+    pub raw_timestamp: u64,
     #[validatron(skip)]
     pub fork_time: SystemTime,
+    /// Images executed by the process and its ancestors, oldest first.
+    #[serde(default)]
+    pub exec_chain: Vec<String>,
+    /// Fingerprint of `exec_chain`, see [`crate::pdk::process_tracker::exec_chain_hash`].
+    #[serde(default)]
+    pub exec_chain_hash: String,
+    /// The process is part of a user terminal session, see [`crate::pdk::process_tracker::is_interactive`].
+    #[serde(default)]
+    pub is_interactive: bool,
+    /// User-mode kernel or virtual machine isolating the container of the
+    /// process, `gvisor` or `kata`, see [`crate::container::sandboxed_runtime`].
+    /// The events of these processes show the sandbox, not the application.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sandboxed_runtime: String,
+    /// Identity of the host where the event happened, see [`crate::host::host_info`].
+    /// Available to the rules as `header.host.hostname`, `header.host.machine_id`
+    /// and `header.host.agent_version`.
+    #[validatron(deref)]
+    #[serde(default)]
+    pub host: Arc<HostInfo>,
+    /// Repetitions merged into this event, see [`crate::coalesce`].
+    #[validatron(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalesced: Option<Coalesced>,
+}
+
+impl Header {
+    /// Header with every field empty or zero, for tests to override the
+    /// fields they check: `Header { pid: 42, ..Header::test_default() }`.
+    pub fn test_default() -> Self {
+        Self {
+            id: 0,
+            parent_event_id: None,
+            image: String::new(),
+            pid: 0,
+            parent_pid: 0,
+            threat: None,
+            source: "test".into(),
+            timestamp: SystemTime::UNIX_EPOCH,
+            raw_timestamp: 0,
+            fork_time: SystemTime::UNIX_EPOCH,
+            exec_chain: Vec::new(),
+            exec_chain_hash: String::new(),
+            is_interactive: false,
+            sandboxed_runtime: String::new(),
+            host: Default::default(),
+            coalesced: None,
+        }
+    }
+
+    /// Key identifying this event across deliveries, so that outputs retrying
+    /// after a partial failure let the receiver drop the duplicates, for example
+    /// as a Kafka key or an Elasticsearch document id.
+    ///
+    /// It combines the [`host::boot_hash`] of the host and boot with the
+    /// monotonic timestamp and the event id, so it's the same for every copy of
+    /// the event and unique across the agent restarts of a host.
+    pub fn delivery_key(&self) -> String {
+        let origin = host::boot_hash(&self.host.machine_id, host::boot_id());
+        let time = match self.raw_timestamp {
+            // Events not generated by a probe
+            0 => self
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|time| time.as_nanos() as u64)
+                .unwrap_or_default(),
+            raw_timestamp => raw_timestamp,
+        };
+        format!("{origin}-{time:016x}-{:x}", self.id)
+    }
+}
+
+/// Identical events of the same process merged into a single one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coalesced {
+    /// Number of events, including the first one
+    pub count: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
 }
 
 /// Representation of event threat information.
@@ -53,9 +164,36 @@ pub struct Header {
 /// `source`, `description` and `info` fields.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Threat {
+    /// Identifier of the recurrences of the threat, see [`crate::acknowledgments`]
+    #[serde(default)]
+    pub id: String,
     pub source: ModuleName,
     pub description: String,
+    /// Used to route the threat to the outputs, see [`crate::pdk::policy`]
+    #[serde(default)]
+    pub severity: Severity,
+    /// Outputs handling the threat instead of the ones of its severity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outputs: Option<Vec<String>>,
     pub extra: Option<Value>,
+    /// What caused the threat, so that it can be understood without the events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence: Option<Evidence>,
+}
+
+/// Structured explanation of a [`Threat`], like the values of the fields
+/// checked by the rule which raised it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evidence {
+    /// Version of the detection, like the version of the rule pack
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rule_version: String,
+    /// Values of the checked fields by path, like `payload.filename`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Counters of the detection, like the matches of the rule so far
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counters: BTreeMap<String, u64>,
 }
 
 impl Display for Threat {
@@ -64,6 +202,32 @@ impl Display for Threat {
     }
 }
 
+/// Severity of a [`Threat`].
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumString,
+    strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Severity {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Value(toml_edit::easy::Value);
 
@@ -142,15 +306,66 @@ pub enum Payload {
     FileOpened {
         filename: String,
         flags: FileFlags,
+        /// Type and permission bits of the opened file
+        mode: u32,
+        /// File descriptor returned by the open syscall, -1 when unknown
+        fd: i32,
+        /// Full path of the file. It differs from filename when the path was
+        /// too deep to be built by the probe and was rebuilt in userspace.
+        resolved_path: String,
     },
     FileLink {
         source: String,
+        /// Target of the link. Relative symlink targets are resolved against
+        /// the directory of the link.
         destination: String,
         hard_link: bool,
     },
     FileRename {
         source: String,
         destination: String,
+        /// The destination existed and was replaced
+        overwrite: bool,
+    },
+    /// An extended attribute in the `security.*` namespace was set or removed
+    XattrChanged {
+        filename: String,
+        name: String,
+        removed: bool,
+    },
+    /// File capabilities were set, for example with `setcap`
+    FileCapabilitiesChanged {
+        filename: String,
+        permitted: Capabilities,
+        inheritable: Capabilities,
+        effective: bool,
+    },
+    /// A kernel parameter was written in /proc/sys, for example with `sysctl -w`
+    SysctlChanged {
+        name: String,
+        value: String,
+        /// Written by a process of the host, in the initial pid namespace,
+        /// not by a container
+        host_process: bool,
+    },
+    /// A named pipe was created, for example with `mkfifo`
+    FifoCreated {
+        filename: String,
+        mode: u32,
+    },
+    /// Data written to a pipe by a process was read by another one. Every
+    /// writer and reader pair of a pipe is reported once, on the first read
+    PipeTransfer {
+        /// Path of the named pipe, empty for anonymous pipes
+        filename: String,
+        /// Inode of the pipe, shown as `pipe:[<inode>]` in `/proc/<pid>/fd`
+        /// for anonymous pipes
+        inode: u64,
+        /// Last process which wrote to the pipe
+        writer_pid: i32,
+        /// Process reading from the pipe, the one of the event
+        reader_pid: i32,
+        anonymous: bool,
     },
     ElfOpened {
         filename: String,
@@ -165,6 +380,37 @@ pub enum Payload {
         argc: usize,
         argv: Argv,
         namespaces: Namespaces,
+        /// Script run when `filename` is an interpreter, empty otherwise
+        #[serde(default)]
+        script: String,
+        /// Structure of the command run by a shell with `-c`, empty otherwise
+        #[serde(default)]
+        shell: ShellCommand,
+        /// Variables of the environment allowed by the process-monitor
+        /// `env_allowlist`, as `NAME=value`
+        #[serde(default)]
+        env: Vec<String>,
+        /// Working directory of the process
+        #[serde(default)]
+        cwd: String,
+        /// The root directory differs from the root of the mount namespace,
+        /// because of chroot
+        #[serde(default)]
+        chrooted: bool,
+        /// Package owning the executable, empty if it's not owned by any
+        /// package or wasn't checked, see [`crate::middleware::PackageInfo`]
+        #[serde(default)]
+        package: String,
+        /// Version of `package`
+        #[serde(default)]
+        package_version: String,
+        /// The executable has the digest recorded by its package manager
+        #[serde(default)]
+        verified: bool,
+        /// The executable was looked up in the package database: `package`
+        /// is empty only when no package owns it
+        #[serde(default)]
+        package_checked: bool,
     },
     Exit {
         exit_code: u32,
@@ -185,6 +431,68 @@ pub enum Payload {
         cgroup_id: u64,
         attached_pid: i32,
     },
+    /// Anonymous or writable memory was made executable
+    ExecMemory {
+        /// `mmap` or `mprotect`
+        syscall: String,
+        /// Start of the memory area, 0 for `mmap` since it's not allocated yet
+        address: u64,
+        /// Length of the memory area, 0 for `mmap`
+        length: u64,
+        /// File backing the memory, empty for anonymous memory
+        filename: String,
+        anonymous: bool,
+        writable: bool,
+    },
+    /// The wall clock was set or stepped
+    TimeChanged {
+        /// `settimeofday`, `clock_settime`, `adjtimex` or `clock_adjtime`
+        syscall: String,
+        /// New time, in seconds since the UNIX epoch
+        time: i64,
+        /// Seconds added to the clock, negative when moved backwards
+        delta: i64,
+    },
+    /// A shared memory segment was created with `shmget`, `shm_open` or
+    /// `memfd_create`
+    SharedMemoryCreated {
+        /// `sysv`, `posix` or `memfd`
+        kind: String,
+        /// Key of System V segments, path of POSIX ones, name of memfd ones
+        name: String,
+        /// Size of System V segments, 0 for the others which are sized later
+        size: u64,
+    },
+    /// A shared memory segment was mapped by a process other than the first
+    /// one. Every pair of processes is reported once per segment
+    SharedMemoryMapped {
+        /// `sysv`, `posix` or `memfd`. `posix` includes the other files of tmpfs
+        kind: String,
+        /// Key of System V segments, path of POSIX ones, name of memfd ones
+        name: String,
+        size: u64,
+        inode: u64,
+        /// First process which mapped the segment
+        first_pid: i32,
+        /// Process mapping the segment, the one of the event
+        mapper_pid: i32,
+        writable: bool,
+    },
+    /// Resource usage of a process, sampled by the proc-metrics module
+    ProcessMetrics {
+        /// CPU usage since the previous sample, in percent of one CPU
+        cpu_usage: u32,
+        /// Minimum CPU usage of the last samples, 0 until enough samples are taken
+        cpu_usage_sustained: u32,
+        /// Resident memory, in bytes
+        memory_rss: u64,
+        /// Virtual memory, in bytes
+        memory_virtual: u64,
+        /// Bytes read from storage since the previous sample
+        io_read: u64,
+        /// Bytes written to storage since the previous sample
+        io_write: u64,
+    },
     SyscallActivity {
         #[validatron(skip)]
         histogram: Vec<u64>,
@@ -203,32 +511,173 @@ pub enum Payload {
     Accept {
         source: Host,
         destination: Host,
+        /// Group of the listening sockets sharing the port with `SO_REUSEPORT`,
+        /// 0 if the listening socket is not in a group
+        #[serde(default)]
+        reuseport_group: u32,
     },
     Close {
         source: Host,
         destination: Host,
+        /// Name of the task owning the socket, which may have exited since
+        #[serde(default)]
+        comm: String,
     },
     Receive {
         source: Host,
         destination: Host,
         len: usize,
         is_tcp: bool,
+        /// Bytes received on the TCP stream before this message, 0 for UDP
+        #[serde(default)]
+        offset: u64,
+        /// Whether the message data was copied by the probe
+        #[serde(default)]
+        capture: DataCapture,
     },
     DnsQuery {
-        #[validatron(skip)]
         questions: Vec<DnsQuestion>,
     },
     DnsResponse {
-        #[validatron(skip)]
         questions: Vec<DnsQuestion>,
-        #[validatron(skip)]
         answers: Vec<DnsAnswer>,
     },
+    /// DNS queries counted by domain over an interval, sent to the outputs
+    /// instead of `DnsQuery` and `DnsResponse` when they're aggregated
+    DnsSummary {
+        /// Length of the interval, in seconds
+        interval: u64,
+        /// Questions of all the queries in the interval
+        queries: u64,
+        /// Questions of all the responses in the interval
+        responses: u64,
+        /// Most queried domains
+        #[validatron(skip)]
+        domains: Vec<DomainCount>,
+        /// Questions of the domains not listed
+        other: u64,
+    },
     Send {
         source: Host,
         destination: Host,
         len: usize,
         is_tcp: bool,
+        /// Bytes sent on the TCP stream before this message, 0 for UDP
+        #[serde(default)]
+        offset: u64,
+        /// Whether the message data was copied by the probe
+        #[serde(default)]
+        capture: DataCapture,
+    },
+    /// Request of the stratum mining protocol, sent to a mining pool
+    StratumRequest {
+        destination: Host,
+        /// JSON-RPC method, `login` or `mining.*`
+        method: String,
+        /// Miner user agent, empty if not sent
+        agent: String,
+    },
+    /// Message on an established TCP connection silent for longer than the
+    /// threshold of the network-monitor, like a dormant implant waking up
+    IdleConnection {
+        /// Local address
+        source: Host,
+        /// Remote address
+        destination: Host,
+        /// Seconds since the previous message, in both directions
+        idle: u64,
+        /// Whether the message resuming the connection was sent, rather than received
+        outgoing: bool,
+    },
+    /// Change of the local firewall, committed by a nf_tables netlink batch or
+    /// by replacing a legacy iptables table
+    FirewallChange {
+        /// `nftables` or `iptables-legacy`
+        backend: String,
+        /// Objects changed by the nf_tables batch, all 0 for `iptables-legacy`
+        tables_added: u32,
+        tables_removed: u32,
+        chains_added: u32,
+        chains_removed: u32,
+        rules_added: u32,
+        rules_removed: u32,
+        /// Command line of the process, like `iptables -A INPUT -j ACCEPT`
+        command: Vec<String>,
+        /// Description of the change, from the command line of iptables and
+        /// nft or from the counters, like `append to filter INPUT: -j ACCEPT`
+        summary: String,
+    },
+    /// Step of a response playbook run by the threat-response module on a threat
+    PlaybookStep {
+        playbook: String,
+        /// Action of the step, like `kill_process`
+        action: String,
+        success: bool,
+        /// Reason of the failure, empty on success
+        error: String,
+    },
+    /// A process exceeded the storm control threshold: its events of `payload_type`
+    /// are suppressed in the eBPF probe for `muted_secs`
+    EventStorm {
+        payload_type: String,
+        /// Events in the last second
+        events: u64,
+        muted_secs: u64,
+    },
+    /// Events of a container running in a sandboxed runtime show the sandbox,
+    /// not the application, see [`crate::visibility`]
+    DegradedVisibility {
+        /// `gvisor` or `kata`
+        runtime: String,
+        /// Container of the sandbox, empty if not found
+        container_id: String,
+        /// What the probes see of the container
+        reason: String,
+    },
+    /// Periodic liveness report of the agent, see [`crate::heartbeat`]
+    Heartbeat {
+        version: String,
+        /// Seconds since the daemon started
+        uptime: u64,
+        modules_running: u32,
+        modules_failed: u32,
+        #[validatron(skip)]
+        modules: Vec<ModuleHealth>,
+        /// Events lost by the receivers lagging behind the bus since the daemon started
+        bus_lost_events: u64,
+        /// Events lost by the eBPF probes with a full perf buffer, or dropped by
+        /// a full worker queue, since the daemon started
+        probe_lost_events: u64,
+    },
+    /// Last event of the daemon, sent on exit once the queued events are
+    /// processed, see [`crate::shutdown`]
+    Shutdown {
+        /// Seconds since the daemon started
+        uptime: u64,
+        /// Events sent on the bus since the daemon started
+        events: u64,
+        /// Threats sent on the bus since the daemon started
+        threats: u64,
+        bus_lost_events: u64,
+        probe_lost_events: u64,
+        /// Events still queued when the shutdown deadline expired
+        undelivered_events: u64,
+    },
+    /// Description of the host, used to interpret and scope its events, see
+    /// [`crate::inventory`]
+    HostInventory {
+        /// Kernel release, like `6.1.0-18-amd64`
+        kernel_version: String,
+        /// Name of the distribution from `/etc/os-release`, empty if unknown
+        distro: String,
+        /// Random id of the current boot
+        boot_id: String,
+        /// Running modules
+        modules: Vec<String>,
+        #[validatron(skip)]
+        rule_packs: Vec<RulePack>,
+        #[validatron(skip)]
+        interfaces: Vec<NetworkInterface>,
     },
     Custom {
         #[validatron(skip)]
@@ -240,6 +689,33 @@ pub enum Payload {
     Empty,
 }
 
+/// Payload type defined outside of pulsar-core, sent on the bus as [`Payload::Custom`].
+///
+/// Use `#[derive(PulsarPayload)]` to implement it together with the [`Validatron`]
+/// reflection, which allows to match the payload fields in rules.
+pub trait CustomPayload: Serialize + DeserializeOwned + Validatron + Send + Sync + 'static {
+    /// Payload name, used as rule type and as `description` of [`Payload::Custom`].
+    const NAME: &'static str;
+
+    /// Convert the custom payload into a [`Payload`].
+    fn into_payload(self) -> Result<Payload, String> {
+        Ok(Payload::Custom {
+            description: Self::NAME.to_string(),
+            value: Value::try_from(self)?,
+        })
+    }
+
+    /// Extract the custom payload from a [`Payload`], if it has the matching type.
+    fn from_payload(payload: &Payload) -> Option<Self> {
+        match payload {
+            Payload::Custom { description, value } if description == Self::NAME => {
+                value.clone().try_into().ok()
+            }
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Payload {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -247,24 +723,34 @@ impl fmt::Display for Payload {
             Payload::FileDeleted { filename } => write!(f,"File Deleted {{ filename: {filename} }}"),
             Payload::DirCreated { dirname } => write!(f,"Dir Created {{ dirname: {dirname} }}"),
             Payload::DirDeleted { dirname } => write!(f,"Dir Deleted {{ dirname: {dirname} }}"),
-            Payload::FileOpened { filename, flags } => write!(f,"File Opened {{ filename: {filename}, flags:{flags} }}"),
+            Payload::FileOpened { filename, flags, mode, fd, .. } => write!(f,"File Opened {{ filename: {filename}, flags:{flags}, mode: {mode:o}, fd: {fd} }}"),
             Payload::FileLink { source, destination, hard_link } => write!(f,"File Link {{ source: {source}, destination: {destination}, hard_link: {hard_link} }}"),
-            Payload::FileRename { source, destination } => write!(f,"File Rename {{ source: {source}, destination {destination} }}"),
+            Payload::FileRename { source, destination, overwrite } => write!(f,"File Rename {{ source: {source}, destination {destination}, overwrite: {overwrite} }}"),
+            Payload::XattrChanged { filename, name, removed } => write!(f,"Xattr Changed {{ filename: {filename}, name: {name}, removed: {removed} }}"),
+            Payload::FileCapabilitiesChanged { filename, permitted, inheritable, effective } => write!(f,"File Capabilities Changed {{ filename: {filename}, permitted: {permitted}, inheritable: {inheritable}, effective: {effective} }}"),
+            Payload::SysctlChanged { name, value, host_process } => write!(f,"Sysctl Changed {{ name: {name}, value: {value}, host_process: {host_process} }}"),
+            Payload::FifoCreated { filename, mode } => write!(f,"Fifo Created {{ filename: {filename}, mode: {mode:o} }}"),
+            Payload::PipeTransfer { filename, inode, writer_pid, reader_pid, anonymous } => write!(f,"Pipe Transfer {{ filename: {filename}, inode: {inode}, writer_pid: {writer_pid}, reader_pid: {reader_pid}, anonymous: {anonymous} }}"),
             Payload::ElfOpened { filename, flags } => write!(f,"Elf Opened {{ filename: {filename}, flags: {flags} }}"),
             Payload::Fork { ppid, namespaces } => write!(f,"Fork {{ ppid: {ppid}, namespaces: {namespaces} }}"),
-            Payload::Exec { filename, argc, argv, namespaces } => write!(f,"Exec {{ filename: {filename}, argc: {argc}, argv: {argv}, namespaces: {namespaces} }}"),
+            Payload::Exec { filename, argc, argv, namespaces, script, shell, env, cwd, chrooted, package, package_version, verified, package_checked } => write!(f,"Exec {{ filename: {filename}, argc: {argc}, argv: {argv}, namespaces: {namespaces}, script: {script}, shell: {shell}, env: {env:?}, cwd: {cwd}, chrooted: {chrooted}, package: {package}, package_version: {package_version}, verified: {verified}, package_checked: {package_checked} }}"),
             Payload::Exit { exit_code } => write!(f,"Exit {{ exit_code: {exit_code} }}"),
             Payload::ChangeParent { ppid } => write!(f,"Parent changed {{ ppid: {ppid} }}"),
             Payload::CgroupCreated { cgroup_path, cgroup_id } => write!(f,"Cgroup created {{ cgroup_path: {cgroup_path}, cgroup_id: {cgroup_id} }}"),
             Payload::CgroupDeleted { cgroup_path, cgroup_id } => write!(f,"Cgroup deleted {{ cgroup_path: {cgroup_path}, cgroup_id: {cgroup_id} }}"),
             Payload::CgroupAttach { cgroup_path, cgroup_id, attached_pid } => write!(f,"Process attached to cgroup {{ cgroup_path: {cgroup_path}, cgroup_id: {cgroup_id}, attached_pid {attached_pid} }}"),
+            Payload::ExecMemory { syscall, address, length, filename, anonymous, writable } => write!(f,"Executable memory {{ syscall: {syscall}, address: {address:#x}, length: {length}, filename: {filename}, anonymous: {anonymous}, writable: {writable} }}"),
+            Payload::TimeChanged { syscall, time, delta } => write!(f,"Time Changed {{ syscall: {syscall}, time: {time}, delta: {delta} }}"),
+            Payload::SharedMemoryCreated { kind, name, size } => write!(f,"Shared Memory Created {{ kind: {kind}, name: {name}, size: {size} }}"),
+            Payload::SharedMemoryMapped { kind, name, size, inode, first_pid, mapper_pid, writable } => write!(f,"Shared Memory Mapped {{ kind: {kind}, name: {name}, size: {size}, inode: {inode}, first_pid: {first_pid}, mapper_pid: {mapper_pid}, writable: {writable} }}"),
+            Payload::ProcessMetrics { cpu_usage, cpu_usage_sustained, memory_rss, memory_virtual, io_read, io_write } => write!(f,"Process Metrics {{ cpu_usage: {cpu_usage}%, cpu_usage_sustained: {cpu_usage_sustained}%, memory_rss: {memory_rss}, memory_virtual: {memory_virtual}, io_read: {io_read}, io_write: {io_write} }}"),
             Payload::SyscallActivity { .. } => write!(f,"Syscall Activity"),
             Payload::Bind { address, is_tcp } => write!(f,"Bind {{ address: {address}, is_tcp: {is_tcp} }}"),
             Payload::Listen { address } => write!(f,"Listen {{ address: {address} }}"),  
             Payload::Connect { destination, is_tcp } => write!(f,"Connect {{ destination: {destination}, is_tcp: {is_tcp} }}"),
-            Payload::Accept { source, destination } => write!(f,"Accept {{ source: {source}, destination: {destination} }}"),
-            Payload::Close { source, destination } => write!(f,"Close {{ source: {source}, destination: {destination} }}"),
-            Payload::Receive { source, destination, len, is_tcp } => write!(f,"Receive {{ source: {source}, destination: {destination}, len: {len}, is_tcp: {is_tcp} }}"),
+            Payload::Accept { source, destination, reuseport_group } => write!(f,"Accept {{ source: {source}, destination: {destination}, reuseport_group: {reuseport_group} }}"),
+            Payload::Close { source, destination, comm } => write!(f,"Close {{ source: {source}, destination: {destination}, comm: {comm} }}"),
+            Payload::Receive { source, destination, len, is_tcp, offset, capture } => write!(f,"Receive {{ source: {source}, destination: {destination}, len: {len}, is_tcp: {is_tcp}, offset: {offset}, capture: {capture} }}"),
             Payload::DnsQuery { questions } => {
                 write!(f,"Dns Query {{ questions: ")?;
                 print_vec(f, questions)?;
@@ -277,34 +763,202 @@ impl fmt::Display for Payload {
                 print_vec(f, answers)?;
                 write!(f," }}")
             },
-            Payload::Send { source, destination, len, is_tcp } => write!(f,"Send {{ source: {source}, destination {destination}, len: {len}, is_tcp: {is_tcp} }}"),
+            Payload::DnsSummary { interval, queries, responses, domains, other } => {
+                write!(f,"Dns Summary {{ interval: {interval}, queries: {queries}, responses: {responses}, domains: ")?;
+                print_vec(f, domains)?;
+                write!(f,", other: {other} }}")
+            },
+            Payload::Send { source, destination, len, is_tcp, offset, capture } => write!(f,"Send {{ source: {source}, destination {destination}, len: {len}, is_tcp: {is_tcp}, offset: {offset}, capture: {capture} }}"),
+            Payload::StratumRequest { destination, method, agent } => write!(f,"Stratum Request {{ destination: {destination}, method: {method}, agent: {agent} }}"),
+            Payload::IdleConnection { source, destination, idle, outgoing } => write!(f,"Idle Connection {{ source: {source}, destination: {destination}, idle: {idle}, outgoing: {outgoing} }}"),
+            Payload::FirewallChange { backend, summary, command, .. } => write!(f,"Firewall Change {{ backend: {backend}, summary: {summary}, command: {} }}", command.join(" ")),
+            Payload::PlaybookStep { playbook, action, success, error } => write!(f,"Playbook Step {{ playbook: {playbook}, action: {action}, success: {success}, error: {error} }}"),
+            Payload::EventStorm { payload_type, events, muted_secs } => write!(f,"Event storm detected, events suppressed {{ payload_type: {payload_type}, events: {events}, muted_secs: {muted_secs} }}"),
+            Payload::DegradedVisibility { runtime, container_id, reason } => write!(f,"Degraded Visibility {{ runtime: {runtime}, container_id: {container_id}, reason: {reason} }}"),
+            Payload::Heartbeat { version, uptime, modules_running, modules_failed, modules: _, bus_lost_events, probe_lost_events } => write!(f,"Heartbeat {{ version: {version}, uptime: {uptime}, modules_running: {modules_running}, modules_failed: {modules_failed}, bus_lost_events: {bus_lost_events}, probe_lost_events: {probe_lost_events} }}"),
+            Payload::Shutdown { uptime, events, threats, bus_lost_events, probe_lost_events, undelivered_events } => write!(f,"Shutdown {{ uptime: {uptime}, events: {events}, threats: {threats}, bus_lost_events: {bus_lost_events}, probe_lost_events: {probe_lost_events}, undelivered_events: {undelivered_events} }}"),
+            Payload::HostInventory { kernel_version, distro, boot_id, modules, rule_packs, interfaces } => {
+                write!(f,"Host Inventory {{ kernel_version: {kernel_version}, distro: {distro}, boot_id: {boot_id}, modules: {modules:?}, rule_packs: ")?;
+                print_vec(f, rule_packs)?;
+                write!(f,", interfaces: ")?;
+                print_vec(f, interfaces)?;
+                write!(f," }}")
+            },
             Payload::Custom { description, value:_ } => write!(f,"Custom {{ description: {description} }}"),
             Payload::Empty => write!(f,"Empty"),
         }
     }
 }
 
+/// Data copied from a sent or received message.
+///
+/// Only the first bytes of a message are copied, and only for the protocols
+/// the network-monitor parses.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum DataCapture {
+    /// Data is not copied for this protocol
+    #[default]
+    Disabled,
+    /// Data should have been copied, but could not be read
+    Empty,
+    /// The whole message was copied
+    Complete,
+    /// Only the first bytes of the message were copied
+    Truncated,
+}
+
+impl Validatron for DataCapture {
+    fn get_class() -> validatron::ValidatronClass {
+        Self::class_builder().primitive(
+            Box::new(|s| {
+                s.parse()
+                    .map_err(|_| ValidatronError::FieldValueParseError(s.to_string()))
+            }),
+            Box::new(|op| match op {
+                Operator::Relational(validatron::RelationalOperator::Equals) => {
+                    Ok(Box::new(|a, b| a == b))
+                }
+                Operator::Relational(validatron::RelationalOperator::NotEquals) => {
+                    Ok(Box::new(|a, b| a != b))
+                }
+                _ => Err(ValidatronError::OperatorNotAllowedOnType(
+                    op,
+                    "DataCapture".to_string(),
+                )),
+            }),
+        )
+    }
+}
+
 /// Encapsulates IP and port.
 #[derive(Debug, Clone, Serialize, Deserialize, Validatron)]
 pub struct Host {
     pub ip: IpAddr,
     pub port: u16,
+    /// IPv6 scope id, the interface index of link-local addresses. Always 0 for IPv4.
+    #[serde(default)]
+    pub scope_id: u32,
+    /// IPv6 flow label and traffic class. Always 0 for IPv4.
+    #[serde(default)]
+    pub flowinfo: u32,
+    /// Original address, set when `ip` was converted by [`Host::unmap_ipv4`].
+    #[validatron(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_ip: Option<IpAddr>,
+}
+
+impl Host {
+    /// Convert an IPv4-mapped IPv6 address, like `::ffff:10.0.0.1` used by
+    /// dual-stack sockets, to its IPv4 form, keeping the original in `raw_ip`.
+    pub fn unmap_ipv4(&mut self) {
+        if let IpAddr::V6(v6) = self.ip {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                self.raw_ip = Some(self.ip);
+                self.ip = v4.into();
+                self.scope_id = 0;
+                self.flowinfo = 0;
+            }
+        }
+    }
+}
+
+impl From<SocketAddr> for Host {
+    fn from(addr: SocketAddr) -> Self {
+        let (scope_id, flowinfo) = match addr {
+            SocketAddr::V4(_) => (0, 0),
+            SocketAddr::V6(v6) => (v6.scope_id(), v6.flowinfo()),
+        };
+        Host {
+            ip: addr.ip(),
+            port: addr.port(),
+            scope_id,
+            flowinfo,
+            raw_ip: None,
+        }
+    }
 }
 
 impl fmt::Display for Host {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.ip {
             IpAddr::V4(v4) => write!(f, "{v4}:{}", self.port),
+            IpAddr::V6(v6) if self.scope_id != 0 => {
+                write!(f, "[{v6}%{}]:{}", self.scope_id, self.port)
+            }
             IpAddr::V6(v6) => write!(f, "[{v6}]:{}", self.port),
         }
     }
 }
 
-/// Encapsulates data of a DNS question.
+/// Status of a module reported in [`Payload::Heartbeat`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleHealth {
+    pub name: String,
+    /// Module status, like `Running` or `Failed(reason)`
+    pub status: String,
+}
+
+/// Rule file loaded by the rules-engine, reported in [`Payload::HostInventory`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RulePack {
+    /// Path of the rule file
+    pub name: String,
+    /// Version declared in the file, or hash of its content
+    pub version: String,
+}
+
+impl fmt::Display for RulePack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+/// Network interface reported in [`Payload::HostInventory`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl fmt::Display for NetworkInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.name, self.addresses)
+    }
+}
+
+/// Number of DNS questions of a domain, reported in [`Payload::DnsSummary`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainCount {
+    pub name: String,
+    pub count: u64,
+}
+
+impl fmt::Display for DomainCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.count)
+    }
+}
+
+/// Encapsulates data of a DNS question.
+#[derive(Debug, Clone, Serialize, Deserialize, Validatron)]
 pub struct DnsQuestion {
     /// Question name string.
     pub name: String,
+    /// Question name as displayed to users, see [`crate::domain::normalize`].
+    #[serde(default)]
+    pub normalized: String,
+    /// Likelihood of the name being generated by an algorithm, from 0 to 100,
+    /// see [`crate::domain::dga_score`].
+    #[serde(default)]
+    pub dga_score: u32,
+    /// Whether the domain is contacted for the first time on this host, see
+    /// the network-monitor module.
+    #[serde(default)]
+    pub first_seen: bool,
     /// Question type.
     pub qtype: String,
     /// Question class.
@@ -318,7 +972,7 @@ impl fmt::Display for DnsQuestion {
 }
 
 /// Encapsulates data of a DNS answer.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validatron)]
 pub struct DnsAnswer {
     /// Name string.
     pub name: String,
@@ -381,19 +1035,17 @@ impl Validatron for FileFlags {
                     .ok_or_else(|| ValidatronError::FieldValueParseError(s.to_string()))
             }),
             Box::new(|op| match op {
-                Operator::Multi(op) => match op {
-                    validatron::MultiOperator::Contains => Ok(Box::new(|a, b| {
-                        if FileFlags::ACC_MODE_FLAGS
-                            .iter()
-                            .any(|(_, acc_mode_flag)| acc_mode_flag == &b.0)
-                        {
-                            let mode = a.0 & kernel::file::flags::O_ACCMODE;
-                            mode == b.0
-                        } else {
-                            (a.0 & b.0) > 0
-                        }
-                    })),
-                },
+                Operator::Multi(validatron::MultiOperator::Contains) => Ok(Box::new(|a, b| {
+                    if FileFlags::ACC_MODE_FLAGS
+                        .iter()
+                        .any(|(_, acc_mode_flag)| acc_mode_flag == &b.0)
+                    {
+                        let mode = a.0 & kernel::file::flags::O_ACCMODE;
+                        mode == b.0
+                    } else {
+                        (a.0 & b.0) > 0
+                    }
+                })),
                 _ => Err(ValidatronError::OperatorNotAllowedOnType(
                     op,
                     "FileFlags".to_string(),
@@ -439,35 +1091,91 @@ impl From<FileFlags> for i32 {
     }
 }
 
+// High level abstraction for capability sets bitmask
 #[repr(C)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Argv(Vec<String>);
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Capabilities(u64);
 
-impl From<Vec<String>> for Argv {
-    fn from(argv_list: Vec<String>) -> Self {
-        Self(argv_list)
+impl Capabilities {
+    pub fn from_raw_unchecked(capabilities: u64) -> Self {
+        Self(capabilities)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        kernel::capabilities::NAMES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.0 & (1 << bit) != 0)
+            .map(|(_, name)| *name)
     }
 }
 
-impl Validatron for Argv {
+impl Validatron for Capabilities {
     fn get_class() -> validatron::ValidatronClass {
         Self::class_builder().primitive(
-            Box::new(|s| Ok(Argv(vec![s.to_string()]))),
+            Box::new(|s| {
+                kernel::capabilities::NAMES
+                    .iter()
+                    .position(|name| *name == s)
+                    .map(|bit| Self(1 << bit))
+                    .ok_or_else(|| ValidatronError::FieldValueParseError(s.to_string()))
+            }),
             Box::new(|op| match op {
-                Operator::Multi(op) => match op {
-                    validatron::MultiOperator::Contains => {
-                        Ok(Box::new(|a, b| b.0.iter().all(|item| a.0.contains(item))))
-                    }
-                },
+                Operator::Multi(validatron::MultiOperator::Contains) => {
+                    Ok(Box::new(|a, b| (a.0 & b.0) == b.0))
+                }
                 _ => Err(ValidatronError::OperatorNotAllowedOnType(
                     op,
-                    "Argv".to_string(),
+                    "Capabilities".to_string(),
                 )),
             }),
         )
     }
 }
 
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}: {}", self.0, self)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.names().collect::<Vec<_>>().join(","))
+    }
+}
+
+impl From<Capabilities> for u64 {
+    fn from(capabilities: Capabilities) -> Self {
+        capabilities.0
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Argv(Vec<String>);
+
+impl From<Vec<String>> for Argv {
+    fn from(argv_list: Vec<String>) -> Self {
+        Self(argv_list)
+    }
+}
+
+impl Validatron for Argv {
+    fn get_class() -> validatron::ValidatronClass {
+        Self::class_builder().collection::<String>()
+    }
+}
+
+impl<'a> IntoIterator for &'a Argv {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
 impl From<Argv> for Vec<String> {
     fn from(argv: Argv) -> Self {
         argv.0
@@ -535,4 +1243,101 @@ mod tests {
 
         assert_eq!(native.field, deserialization.field);
     }
+
+    #[test]
+    fn capabilities_display() {
+        let capabilities = Capabilities::from_raw_unchecked((1 << 7) | (1 << 13) | (1 << 40));
+        assert_eq!(
+            capabilities.to_string(),
+            "(CAP_SETUID,CAP_NET_RAW,CAP_CHECKPOINT_RESTORE)"
+        );
+        assert_eq!(Capabilities::from_raw_unchecked(0).to_string(), "()");
+    }
+
+    #[test]
+    fn host_scope_id() {
+        for addr in ["10.0.0.1:80", "[::1]:80", "[fe80::1%2]:80"] {
+            let host = Host::from(addr.parse::<SocketAddr>().unwrap());
+            assert_eq!(host.to_string(), addr);
+        }
+
+        // Events serialized before the scope id was added
+        let host: Host = toml_edit::easy::from_str("ip = \"fe80::1\"\nport = 80").unwrap();
+        assert_eq!(host.scope_id, 0);
+        assert_eq!(host.flowinfo, 0);
+    }
+
+    #[test]
+    fn host_unmap_ipv4() {
+        let mut host = Host::from("[::ffff:10.0.0.1]:80".parse::<SocketAddr>().unwrap());
+        host.unmap_ipv4();
+        assert_eq!(host.to_string(), "10.0.0.1:80");
+        assert_eq!(host.raw_ip, Some("::ffff:10.0.0.1".parse().unwrap()));
+
+        let mut host = Host::from("[::1]:80".parse::<SocketAddr>().unwrap());
+        host.unmap_ipv4();
+        assert_eq!(host.to_string(), "[::1]:80");
+        assert_eq!(host.raw_ip, None);
+    }
+
+    #[test]
+    fn data_capture() {
+        let send = r#"{"type":"Send","content":{
+            "source":{"ip":"10.0.0.1","port":4000},
+            "destination":{"ip":"10.0.0.2","port":53},
+            "len":10,"is_tcp":false}}"#;
+        // Events serialized before the capture state was added
+        let Payload::Send {
+            offset, capture, ..
+        } = serde_json::from_str(send).unwrap()
+        else {
+            panic!("expected a Send payload");
+        };
+        assert_eq!(offset, 0);
+        assert_eq!(capture, DataCapture::Disabled);
+
+        assert_eq!(
+            serde_json::to_string(&DataCapture::Truncated).unwrap(),
+            "\"truncated\""
+        );
+        assert_eq!("empty".parse::<DataCapture>(), Ok(DataCapture::Empty));
+    }
+
+    #[test]
+    fn event_ids_are_unique() {
+        let first = next_event_id();
+        let second = next_event_id();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn delivery_key() {
+        let mut header = Header {
+            id: 42,
+            image: "/bin/sh".to_string(),
+            pid: 1000,
+            parent_pid: 1,
+            source: "process-monitor".into(),
+            timestamp: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1),
+            raw_timestamp: 0xabc,
+            ..Header::test_default()
+        };
+        let key = header.delivery_key();
+        assert!(key.ends_with("-0000000000000abc-2a"), "{key}");
+        assert!(key.starts_with(&host::boot_hash(&header.host.machine_id, host::boot_id())));
+        // Copies of the event share the key
+        assert_eq!(header.clone().delivery_key(), key);
+
+        header.id = 43;
+        assert_ne!(header.delivery_key(), key);
+
+        header.raw_timestamp = 0;
+        assert!(header.delivery_key().ends_with("-000000003b9aca00-2b"));
+
+        header.host = Arc::new(HostInfo {
+            machine_id: "b08dfa6083e7567a1921a715000001fb".to_string(),
+            ..Default::default()
+        });
+        assert!(!header.delivery_key().starts_with(&key[..16]));
+    }
 }
@@ -0,0 +1,141 @@
+//! Periodic liveness report of the agent, sent on the bus like any other
+//! event so that central collectors can tell dead or degraded agents from
+//! quiet ones.
+
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::{
+    bus::{self, Bus},
+    event::{next_event_id, Event, Header, ModuleHealth, Payload},
+    host::host_info,
+    pdk::{ModuleOverview, ModuleStatus, PulsarDaemonHandle},
+    Timestamp,
+};
+
+/// Source of the events sent by the daemon itself.
+pub const DAEMON_SOURCE: &str = "pulsard";
+
+/// Send a heartbeat every `interval`, the first one right away.
+pub fn start(bus: Bus, daemon: PulsarDaemonHandle, interval: Duration) {
+    let started = Instant::now();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let payload = heartbeat(&daemon.modules().await, started.elapsed());
+            let _ = bus.send(daemon_event(payload));
+        }
+    });
+}
+
+fn heartbeat(modules: &[ModuleOverview], uptime: Duration) -> Payload {
+    let count = |running: bool| {
+        modules
+            .iter()
+            .filter(|module| match module.status {
+                ModuleStatus::Running(_) => running,
+                ModuleStatus::Failed(_) => !running,
+                _ => false,
+            })
+            .count() as u32
+    };
+    let mut modules: Vec<ModuleHealth> = modules
+        .iter()
+        .map(|module| ModuleHealth {
+            name: module.name.clone(),
+            status: module.status.to_string(),
+        })
+        .collect();
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    Payload::Heartbeat {
+        version: host_info().agent_version.clone(),
+        uptime: uptime.as_secs(),
+        modules_running: count(true),
+        modules_failed: count(false),
+        modules,
+        bus_lost_events: bus::lost_events(),
+        probe_lost_events: bpf_common::program::lost_events(),
+    }
+}
+
+/// Build an event of the daemon process.
+pub(crate) fn daemon_event(payload: Payload) -> Event {
+    let timestamp = Timestamp::now();
+    let image = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    Event::new(
+        Header {
+            id: next_event_id(),
+            parent_event_id: None,
+            image: image.clone(),
+            pid: std::process::id() as i32,
+            parent_pid: 0,
+            threat: None,
+            source: DAEMON_SOURCE.into(),
+            timestamp: timestamp.into(),
+            raw_timestamp: timestamp.raw(),
+            fork_time: UNIX_EPOCH,
+            exec_chain: vec![image],
+            exec_chain_hash: String::new(),
+            is_interactive: false,
+            sandboxed_runtime: String::new(),
+            host: host_info(),
+            coalesced: None,
+        },
+        payload,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdk::Version;
+
+    fn module(name: &str, status: ModuleStatus) -> ModuleOverview {
+        ModuleOverview {
+            name: name.to_string(),
+            version: Version::new(1, 0, 0),
+            status,
+            config_schema: None,
+            uses_ebpf: false,
+        }
+    }
+
+    #[test]
+    fn module_health() {
+        let modules = [
+            module(
+                "network-monitor",
+                ModuleStatus::Failed("no BTF".to_string()),
+            ),
+            module("logger", ModuleStatus::Running(Vec::new())),
+            module("smtp-notifier", ModuleStatus::Stopped),
+            module("rules-engine", ModuleStatus::Running(Vec::new())),
+        ];
+        let Payload::Heartbeat {
+            uptime,
+            modules_running,
+            modules_failed,
+            modules,
+            ..
+        } = heartbeat(&modules, Duration::from_millis(61_500))
+        else {
+            panic!("not a heartbeat");
+        };
+        assert_eq!((uptime, modules_running, modules_failed), (61, 2, 1));
+        let statuses: Vec<_> = modules
+            .iter()
+            .map(|module| (module.name.as_str(), module.status.as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("logger", "Running"),
+                ("network-monitor", "Failed(\"no BTF\")"),
+                ("rules-engine", "Running"),
+                ("smtp-notifier", "Stopped"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Ring of the last events seen on the bus, queried to export the events of
+//! an incident.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    bus::{self, Bus},
+    pdk::Event,
+};
+
+/// Selection of the events of an incident.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// Process whose events are selected, together with its descendants
+    pub pid: Option<i32>,
+    /// Start of the time range, in milliseconds since the Unix epoch
+    pub since: Option<u64>,
+    /// End of the time range, in milliseconds since the Unix epoch
+    pub until: Option<u64>,
+}
+
+impl EventFilter {
+    fn in_range(&self, timestamp: SystemTime) -> bool {
+        let millis = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        self.since
+            .into_iter()
+            .all(|since| timestamp >= millis(since))
+            && self
+                .until
+                .into_iter()
+                .all(|until| timestamp <= millis(until))
+    }
+}
+
+#[derive(Clone)]
+pub struct EventHistory {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<Arc<Event>>>>,
+}
+
+impl EventHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Keep the events sent to the outputs of the bus, until the bus is
+    /// stopped.
+    pub fn record_bus(&self, bus: &Bus) {
+        let history = self.clone();
+        let mut rx = bus.get_output_receiver();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => history.record(event),
+                    Err(RecvError::Lagged(lost)) => {
+                        bus::count_lost_events(lost);
+                        log::warn!("Event history lagged behind, {lost} events lost")
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    pub fn record(&self, event: Arc<Event>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Return the kept events matching the filter, in the order they were sent.
+    ///
+    /// The descendants of the filtered process are found following the
+    /// `parent_pid` of the events, so they must be forked within the kept events.
+    pub fn query(&self, filter: &EventFilter) -> Vec<Arc<Event>> {
+        let events = self.events.lock().unwrap();
+        let mut tree: HashSet<i32> = filter.pid.into_iter().collect();
+        events
+            .iter()
+            .filter(|event| {
+                let header = event.header();
+                if filter.pid.is_some() {
+                    if tree.contains(&header.parent_pid) {
+                        tree.insert(header.pid);
+                    } else if !tree.contains(&header.pid) {
+                        return false;
+                    }
+                }
+                filter.in_range(header.timestamp)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Header, Payload};
+
+    fn event(pid: i32, parent_pid: i32, seconds: u64) -> Arc<Event> {
+        Arc::new(Event::new(
+            Header {
+                pid,
+                parent_pid,
+                timestamp: UNIX_EPOCH + Duration::from_secs(seconds),
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 0 },
+        ))
+    }
+
+    fn pids(events: Vec<Arc<Event>>) -> Vec<i32> {
+        events.iter().map(|event| event.header().pid).collect()
+    }
+
+    #[test]
+    fn query() {
+        let history = EventHistory::new(5);
+        for event in [
+            event(1, 0, 1),
+            event(10, 1, 2),
+            event(20, 1, 3),
+            event(11, 10, 4),
+            event(12, 11, 5),
+            event(21, 20, 6),
+        ] {
+            history.record(event);
+        }
+
+        // the oldest event is dropped
+        assert_eq!(pids(history.query(&EventFilter::default())).len(), 5);
+        assert_eq!(
+            pids(history.query(&EventFilter {
+                pid: Some(10),
+                ..Default::default()
+            })),
+            vec![10, 11, 12]
+        );
+        assert_eq!(
+            pids(history.query(&EventFilter {
+                pid: Some(10),
+                since: Some(3_000),
+                until: Some(4_000),
+            })),
+            vec![11]
+        );
+    }
+}
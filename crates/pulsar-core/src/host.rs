@@ -0,0 +1,123 @@
+//! Identity of the host running the agent, attached to every event so that
+//! the outputs of many hosts can be aggregated.
+
+use std::{
+    fs, iter,
+    sync::{Arc, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use validatron::Validatron;
+
+const MACHINE_ID_PATHS: [&str; 2] = ["/etc/machine-id", "/var/lib/dbus/machine-id"];
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+static HOST_INFO: OnceLock<Arc<HostInfo>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Validatron)]
+pub struct HostInfo {
+    /// Stable identifier of the host, from `/etc/machine-id`
+    pub machine_id: String,
+    /// Hostname at the start of the agent
+    pub hostname: String,
+    /// Name given to the host by the operator, see the `node_name` setting
+    #[validatron(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    pub agent_version: String,
+}
+
+impl HostInfo {
+    /// Read the identity of the current host.
+    pub fn detect(node_name: Option<String>, agent_version: &str) -> Self {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .map(|value| value.trim().to_string())
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            machine_id: MACHINE_ID_PATHS
+                .iter()
+                .find_map(|path| read(path))
+                .unwrap_or_default(),
+            hostname: read(HOSTNAME_PATH).unwrap_or_default(),
+            node_name: node_name.filter(|name| !name.is_empty()),
+            agent_version: agent_version.to_string(),
+        }
+    }
+}
+
+/// Set the host identity attached to the events. Fails if it's already set,
+/// either by a previous call or by an event sent before.
+pub fn init_host_info(info: HostInfo) -> Result<(), Arc<HostInfo>> {
+    HOST_INFO.set(Arc::new(info))
+}
+
+/// Host identity attached to the events, detected on first use if not set by
+/// [`init_host_info`].
+pub fn host_info() -> Arc<HostInfo> {
+    HOST_INFO
+        .get_or_init(|| Arc::new(HostInfo::detect(None, env!("CARGO_PKG_VERSION"))))
+        .clone()
+}
+
+/// Identifier of the current boot, empty if it can't be read.
+pub fn boot_id() -> &'static str {
+    static BOOT_ID: OnceLock<String> = OnceLock::new();
+    BOOT_ID.get_or_init(|| {
+        fs::read_to_string(BOOT_ID_PATH)
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    })
+}
+
+/// Fingerprint of a boot of a host, from its machine id and boot id: the same
+/// for every run of the agent during the boot, different after a reboot or on
+/// another host. Used as the origin of [`crate::event::Header::delivery_key`].
+///
+/// It's the FNV-1a hash of the two identifiers, formatted as hexadecimal.
+pub fn boot_hash(machine_id: &str, boot_id: &str) -> String {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET;
+    // The null byte separates the identifiers, so that moving characters from
+    // one to the other changes the hash
+    for byte in machine_id
+        .bytes()
+        .chain(iter::once(0))
+        .chain(boot_id.bytes())
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let info = HostInfo::detect(Some(String::new()), "1.0.0");
+        assert_eq!(info.node_name, None);
+        assert_eq!(info.agent_version, "1.0.0");
+        assert_eq!(
+            HostInfo::detect(Some("edge-01".to_string()), "1.0.0").node_name,
+            Some("edge-01".to_string())
+        );
+    }
+
+    #[test]
+    fn boot_hash_of_host_and_boot() {
+        let hash = boot_hash("machine", "boot");
+        assert_eq!(hash.len(), 16);
+        assert_eq!(boot_hash("machine", "boot"), hash);
+        assert_ne!(boot_hash("machine", "reboot"), hash);
+        assert_ne!(boot_hash("other-machine", "boot"), hash);
+        assert_ne!(boot_hash("machin", "eboot"), hash);
+    }
+}
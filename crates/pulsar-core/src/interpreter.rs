@@ -0,0 +1,168 @@
+//! Script executed by an interpreter, so rules can target the script instead of
+//! every execution of `python` or `bash`.
+
+use std::path::Path;
+
+/// Interpreters recognized from the name of the executable, without the
+/// version suffix like in `python3.11` or `php8.1`.
+const INTERPRETERS: &[&str] = &[
+    "python", "bash", "sh", "dash", "zsh", "ksh", "node", "nodejs", "perl", "ruby", "php", "lua",
+];
+
+/// Name of the interpreter if `filename` is one.
+pub fn interpreter(filename: &str) -> Option<&'static str> {
+    let name = Path::new(filename).file_name()?.to_str()?;
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|interpreter| **interpreter == name)
+        .copied()
+}
+
+/// Script run by the interpreter `filename` with arguments `argv`, the first
+/// argument which is not an option. Returns `None` if `filename` is not an
+/// interpreter or runs inline code, a module or the standard input.
+pub fn script_path<'a>(filename: &str, argv: &'a [String]) -> Option<&'a str> {
+    let interpreter = interpreter(filename)?;
+    let mut args = argv.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            return args.next().map(String::as_str);
+        }
+        match option(interpreter, arg) {
+            Some(Opt::Inline) => return None,
+            Some(Opt::Value) => {
+                args.next();
+            }
+            Some(Opt::Flag) => {}
+            None => return Some(arg),
+        }
+    }
+    None
+}
+
+/// Command string run by the shell `filename` with `-c`, like the pipeline in
+/// `sh -c 'curl -s https://example.com/install | bash'`.
+pub fn shell_command<'a>(filename: &str, argv: &'a [String]) -> Option<&'a str> {
+    let interpreter = interpreter(filename).filter(|interpreter| is_shell(interpreter))?;
+    let mut command_option = false;
+    let mut args = argv.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            return args.next().filter(|_| command_option).map(String::as_str);
+        }
+        match option(interpreter, arg) {
+            Some(Opt::Inline) if arg != "-" => command_option = true,
+            Some(Opt::Inline) => return None,
+            Some(Opt::Value) => {
+                args.next();
+            }
+            Some(Opt::Flag) => {}
+            None => return command_option.then_some(arg.as_str()),
+        }
+    }
+    None
+}
+
+fn is_shell(interpreter: &str) -> bool {
+    matches!(interpreter, "bash" | "sh" | "dash" | "zsh" | "ksh")
+}
+
+enum Opt {
+    /// Followed by code to run instead of a script, or by a module to run
+    /// like `python -m http.server`
+    Inline,
+    /// Takes the next argument as value
+    Value,
+    Flag,
+}
+
+fn option(interpreter: &str, arg: &str) -> Option<Opt> {
+    let shell = is_shell(interpreter);
+    match (interpreter, arg) {
+        // `-` runs the standard input
+        (_, "-" | "-c") => Some(Opt::Inline),
+        (_, "-e") if !shell => Some(Opt::Inline),
+        ("python", "-m") | ("perl", "-E") | ("php", "-r") => Some(Opt::Inline),
+        ("node" | "nodejs", "-p" | "--eval" | "--print") => Some(Opt::Inline),
+        ("python", "-W" | "-X" | "-Q") => Some(Opt::Value),
+        ("node" | "nodejs" | "ruby", "-r" | "-I") => Some(Opt::Value),
+        ("node" | "nodejs", "--require" | "--import" | "--loader") => Some(Opt::Value),
+        (_, "-o" | "+o" | "-O" | "+O" | "--rcfile" | "--init-file") if shell => Some(Opt::Value),
+        // Combined short options, like `bash -xc 'echo'`
+        (_, arg)
+            if shell && arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c') =>
+        {
+            Some(Opt::Inline)
+        }
+        (_, arg) if arg.starts_with('-') || (shell && arg.starts_with('+')) => Some(Opt::Flag),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &str) -> Vec<String> {
+        args.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn interpreters() {
+        assert_eq!(interpreter("/usr/bin/python3.11"), Some("python"));
+        assert_eq!(interpreter("/usr/bin/php8.1"), Some("php"));
+        assert_eq!(interpreter("/bin/bash"), Some("bash"));
+        assert_eq!(interpreter("/usr/bin/curl"), None);
+        assert_eq!(interpreter("/usr/bin/shred"), None);
+    }
+
+    #[test]
+    fn scripts() {
+        let args = argv("python3 -u -W ignore /tmp/x.py --verbose");
+        assert_eq!(script_path("/usr/bin/python3", &args), Some("/tmp/x.py"));
+        let args = argv("bash -x ./install.sh");
+        assert_eq!(script_path("/bin/bash", &args), Some("./install.sh"));
+        let args = argv("sh -e ./install.sh");
+        assert_eq!(script_path("/bin/sh", &args), Some("./install.sh"));
+        let args = argv("node --require ./hook.js server.js");
+        assert_eq!(script_path("/usr/bin/node", &args), Some("server.js"));
+        let args = argv("perl -- -weird.pl");
+        assert_eq!(script_path("/usr/bin/perl", &args), Some("-weird.pl"));
+        let args = argv("curl https://example.com");
+        assert_eq!(script_path("/usr/bin/curl", &args), None);
+    }
+
+    #[test]
+    fn inline_code() {
+        let args = argv("python3 -c print(1)");
+        assert_eq!(script_path("/usr/bin/python3", &args), None);
+        let args = argv("python3 -m http.server");
+        assert_eq!(script_path("/usr/bin/python3", &args), None);
+        let args = argv("sh -ec true");
+        assert_eq!(script_path("/bin/sh", &args), None);
+        let args = argv("bash -");
+        assert_eq!(script_path("/bin/bash", &args), None);
+        let args = argv("python3");
+        assert_eq!(script_path("/usr/bin/python3", &args), None);
+    }
+
+    #[test]
+    fn shell_commands() {
+        let args = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "curl -s https://example.com | bash".to_string(),
+        ];
+        assert_eq!(
+            shell_command("/bin/sh", &args),
+            Some("curl -s https://example.com | bash")
+        );
+        let args = argv("bash -xc true");
+        assert_eq!(shell_command("/bin/bash", &args), Some("true"));
+        let args = argv("bash ./install.sh");
+        assert_eq!(shell_command("/bin/bash", &args), None);
+        let args = argv("python3 -c print(1)");
+        assert_eq!(shell_command("/usr/bin/python3", &args), None);
+    }
+}
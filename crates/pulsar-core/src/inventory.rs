@@ -0,0 +1,159 @@
+//! Inventory of the host, sent on the bus so that collectors can interpret
+//! and scope the events of the agent.
+//!
+//! The inventory is sent on startup, every `interval` and whenever the rules
+//! are reloaded, since the rule packs are part of it.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use nix::ifaddrs::getifaddrs;
+use tokio::sync::watch;
+
+use crate::{
+    bus::Bus,
+    event::{NetworkInterface, Payload, RulePack},
+    heartbeat::daemon_event,
+    host,
+    pdk::{ModuleStatus, PulsarDaemonHandle},
+};
+
+const OSRELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+const OS_RELEASE_PATH: &str = "/etc/os-release";
+
+/// Time given to the rules to be loaded by a starting rules-engine before
+/// sending an inventory for the new rule packs.
+const RULES_SETTLE_TIME: Duration = Duration::from_secs(1);
+
+static RULE_PACKS: OnceLock<watch::Sender<Vec<RulePack>>> = OnceLock::new();
+
+/// Set the rule packs loaded by the rules-engine.
+pub fn set_rule_packs(rule_packs: Vec<RulePack>) {
+    rule_packs_sender().send_replace(rule_packs);
+}
+
+fn rule_packs_sender() -> &'static watch::Sender<Vec<RulePack>> {
+    RULE_PACKS.get_or_init(|| watch::channel(Vec::new()).0)
+}
+
+/// Send the inventory now, every `interval` and when the rule packs change.
+/// A zero `interval` sends it only on startup and on rule changes.
+pub fn start(bus: Bus, daemon: PulsarDaemonHandle, interval: Duration) {
+    let mut rx_rule_packs = rule_packs_sender().subscribe();
+    tokio::spawn(async move {
+        let period = if interval.is_zero() {
+            // Practically never
+            Duration::from_secs(u32::MAX as u64)
+        } else {
+            interval
+        };
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                changed = rx_rule_packs.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    tokio::time::sleep(RULES_SETTLE_TIME).await;
+                    interval.reset();
+                }
+            }
+            let rule_packs = rx_rule_packs.borrow_and_update().clone();
+            let payload = inventory(&daemon, rule_packs).await;
+            let _ = bus.send(daemon_event(payload));
+        }
+    });
+}
+
+async fn inventory(daemon: &PulsarDaemonHandle, rule_packs: Vec<RulePack>) -> Payload {
+    let read = |path| {
+        fs::read_to_string(path)
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut modules: Vec<String> = daemon
+        .modules()
+        .await
+        .into_iter()
+        .filter(|module| matches!(module.status, ModuleStatus::Running(_)))
+        .map(|module| module.name)
+        .collect();
+    modules.sort();
+    Payload::HostInventory {
+        kernel_version: read(OSRELEASE_PATH),
+        distro: distro(&read(OS_RELEASE_PATH)).unwrap_or_default(),
+        boot_id: host::boot_id().to_string(),
+        modules,
+        rule_packs,
+        interfaces: interfaces(),
+    }
+}
+
+/// Name of the distribution from the content of `/etc/os-release`.
+fn distro(os_release: &str) -> Option<String> {
+    let field = |name: &str| {
+        os_release.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix('=')?;
+            Some(value.trim_matches(|c| c == '"' || c == '\'').to_string())
+        })
+    };
+    field("PRETTY_NAME").or_else(|| field("NAME"))
+}
+
+/// Network interfaces with their IP addresses, sorted by name.
+fn interfaces() -> Vec<NetworkInterface> {
+    let addresses = match getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(err) => {
+            log::warn!("Error listing network interfaces: {err}");
+            return Vec::new();
+        }
+    };
+    let mut interfaces: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    for address in addresses {
+        let ips = interfaces.entry(address.interface_name).or_default();
+        let Some(address) = address.address else {
+            continue;
+        };
+        if let Some(ipv4) = address.as_sockaddr_in() {
+            ips.push(Ipv4Addr::from(ipv4.ip()).into());
+        } else if let Some(ipv6) = address.as_sockaddr_in6() {
+            ips.push(ipv6.ip().into());
+        }
+    }
+    interfaces
+        .into_iter()
+        .map(|(name, addresses)| NetworkInterface { name, addresses })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distro_name() {
+        let os_release = "NAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nPRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\n";
+        assert_eq!(
+            distro(os_release).as_deref(),
+            Some("Debian GNU/Linux 12 (bookworm)")
+        );
+        assert_eq!(distro("NAME=Alpine\n").as_deref(), Some("Alpine"));
+        assert_eq!(distro("ID=custom\n"), None);
+    }
+
+    #[test]
+    fn loopback_interface() {
+        let interfaces = interfaces();
+        let Some(lo) = interfaces.iter().find(|interface| interface.name == "lo") else {
+            return;
+        };
+        assert!(lo.addresses.iter().all(IpAddr::is_loopback));
+    }
+}
@@ -1,7 +1,31 @@
+pub mod acknowledgments;
 pub mod bus;
+pub mod coalesce;
+pub mod container;
+pub mod domain;
 pub mod event;
+pub mod heartbeat;
+pub mod history;
+pub mod host;
+pub mod interpreter;
+pub mod inventory;
+pub mod listeners;
+pub mod loadgen;
+pub mod middleware;
+pub mod output;
+pub mod packages;
 pub mod pdk;
+pub mod replay;
+pub mod rule_stats;
+pub mod shell;
+pub mod shutdown;
+pub mod suggest;
+pub mod tap;
+pub mod visibility;
+pub mod watchdog;
 
 pub use bpf_common::{time::Timestamp, Pid};
+pub use pulsar_core_derive::PulsarPayload;
+pub use validatron;
 
 pub mod kernel;
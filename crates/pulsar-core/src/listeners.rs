@@ -0,0 +1,484 @@
+//! Sockets listening on the host, with the process owning them and its
+//! container, kept from the network events sent on the bus. The table is
+//! exported by the daemon for network policy generators and asset inventories.
+//!
+//! The table is seeded on startup with the sockets found in `/proc/<pid>/net`
+//! of every network namespace. Then TCP sockets are added by `Listen` events
+//! and UDP sockets by `Bind` events with a port, and they're removed when
+//! closed. Sockets are tracked by inode rather than by process: when their
+//! process exits, they're given to another process holding them, like the
+//! child of a daemon forking in the background, and removed only if none is
+//! left.
+
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    bus::{self, Bus},
+    container::{self, Container},
+    event::{Header, Host},
+    pdk::{Event, Payload},
+};
+
+/// Sockets kept in the table, so that a process binding ports in a loop can't
+/// exhaust the memory.
+pub const MAX_LISTENERS: usize = 10_000;
+
+/// Socket listening for connections or datagrams.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Listener {
+    pub address: Host,
+    pub is_tcp: bool,
+    pub pid: i32,
+    pub image: String,
+    /// Container of the process, see [`container::of_process`]
+    pub container: Option<Container>,
+    /// When the socket started listening, or when the daemon started for the
+    /// sockets found on startup
+    pub since: SystemTime,
+    /// Inode of the socket, if found in `/proc/<pid>/net`
+    #[serde(default)]
+    pub inode: Option<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct ListenerTable {
+    listeners: Arc<Mutex<Vec<Listener>>>,
+}
+
+impl ListenerTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the table from procfs, then keep it updated from the events sent
+    /// on the bus, until the bus is stopped.
+    pub fn record_bus(&self, bus: &Bus) {
+        let table = self.clone();
+        let mut rx = bus.get_receiver();
+        tokio::spawn(async move {
+            match tokio::task::spawn_blocking(procfs_listeners).await {
+                Ok(found) => table.seed(found),
+                Err(err) => log::warn!("Error reading the listening sockets: {err}"),
+            }
+            loop {
+                match rx.recv().await {
+                    Ok(event) => table.record(&event),
+                    Err(RecvError::Lagged(lost)) => {
+                        bus::count_lost_events(lost);
+                        log::warn!("Listener table lagged behind, {lost} events lost")
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    pub fn record(&self, event: &Event) {
+        let header = event.header();
+        match event.payload() {
+            Payload::Listen { address } => self.add(header, address, true),
+            Payload::Bind {
+                address,
+                is_tcp: false,
+            } if address.port != 0 => self.add(header, address, false),
+            // Listening sockets have no peer, unlike the accepted connections
+            // sharing their address
+            Payload::Close {
+                source,
+                destination,
+                ..
+            } if destination.port == 0 => self.listeners.lock().unwrap().retain(|listener| {
+                listener.pid != header.pid || !listener.is_tcp || !same_address(listener, source)
+            }),
+            Payload::Exit { .. } => self.exited(header.pid),
+            _ => {}
+        }
+    }
+
+    /// Return the listening sockets, sorted by port.
+    pub fn snapshot(&self) -> Vec<Listener> {
+        let mut listeners = self.listeners.lock().unwrap().clone();
+        listeners.sort_by_key(|listener| (listener.address.port, !listener.is_tcp, listener.pid));
+        listeners
+    }
+
+    fn seed(&self, found: Vec<Listener>) {
+        let mut listeners = self.listeners.lock().unwrap();
+        for listener in found {
+            // Sockets reported by the events meanwhile are more precise
+            let known = listeners.iter().any(|known| {
+                known.is_tcp == listener.is_tcp
+                    && (known.inode == listener.inode
+                        || known.pid == listener.pid && same_address(known, &listener.address))
+            });
+            if !known && listeners.len() < MAX_LISTENERS {
+                listeners.push(listener);
+            }
+        }
+    }
+
+    fn add(&self, header: &Header, address: &Host, is_tcp: bool) {
+        // Read procfs before locking the table
+        let container = container::of_process(header.pid);
+        let inode = net_dir(header.pid).and_then(|dir| {
+            sockets(&dir)
+                .into_iter()
+                .find(|socket| {
+                    socket.is_tcp == is_tcp
+                        && socket.address.ip == address.ip
+                        && socket.address.port == address.port
+                })
+                .map(|socket| socket.inode)
+        });
+        let mut listeners = self.listeners.lock().unwrap();
+        listeners.retain(|listener| {
+            listener.pid != header.pid
+                || listener.is_tcp != is_tcp
+                || !same_address(listener, address)
+        });
+        if listeners.len() >= MAX_LISTENERS {
+            return;
+        }
+        listeners.push(Listener {
+            address: address.clone(),
+            is_tcp,
+            pid: header.pid,
+            image: header.image.clone(),
+            container,
+            since: header.timestamp,
+            inode,
+        });
+    }
+
+    /// Give the sockets of an exited process to another process holding
+    /// them, or remove them.
+    fn exited(&self, pid: i32) {
+        let inodes: Vec<Option<u64>> = self
+            .listeners
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|listener| listener.pid == pid)
+            .map(|listener| listener.inode)
+            .collect();
+        if inodes.is_empty() {
+            return;
+        }
+        let holders = if inodes.iter().any(Option::is_some) {
+            socket_holders(Some(pid))
+        } else {
+            HashMap::new()
+        };
+        let owners: HashMap<u64, Owner> = inodes
+            .into_iter()
+            .flatten()
+            .filter_map(|inode| Some((inode, Owner::of(*holders.get(&inode)?))))
+            .collect();
+        self.listeners.lock().unwrap().retain_mut(|listener| {
+            if listener.pid != pid {
+                return true;
+            }
+            match listener.inode.and_then(|inode| owners.get(&inode)) {
+                Some(owner) => {
+                    listener.pid = owner.pid;
+                    listener.image = owner.image.clone();
+                    listener.container = owner.container.clone();
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+fn same_address(listener: &Listener, address: &Host) -> bool {
+    listener.address.ip == address.ip && listener.address.port == address.port
+}
+
+/// Process holding a socket, read from procfs.
+struct Owner {
+    pid: i32,
+    image: String,
+    container: Option<Container>,
+}
+
+impl Owner {
+    fn of(pid: i32) -> Self {
+        Self {
+            pid,
+            image: fs::read_link(format!("/proc/{pid}/exe"))
+                .map(|exe| exe.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            container: container::of_process(pid),
+        }
+    }
+}
+
+/// Listening socket found in `/proc/<pid>/net`.
+#[derive(Debug)]
+struct Socket {
+    address: Host,
+    is_tcp: bool,
+    inode: u64,
+}
+
+/// `/proc/<pid>/net`, with the sockets of the network namespace of `pid`.
+fn net_dir(pid: i32) -> Option<PathBuf> {
+    let dir = PathBuf::from(format!("/proc/{pid}/net"));
+    dir.exists().then_some(dir)
+}
+
+/// Listening sockets of the network namespace of a `/proc/<pid>/net`
+/// directory: TCP sockets in the `LISTEN` state, and UDP sockets bound to a
+/// port and not connected.
+fn sockets(net_dir: &Path) -> Vec<Socket> {
+    [
+        ("tcp", true),
+        ("tcp6", true),
+        ("udp", false),
+        ("udp6", false),
+    ]
+    .into_iter()
+    .flat_map(|(file, is_tcp)| {
+        let content = fs::read_to_string(net_dir.join(file)).unwrap_or_default();
+        content
+            .lines()
+            .skip(1)
+            .filter_map(|line| parse_socket(line, is_tcp))
+            .collect::<Vec<_>>()
+    })
+    .collect()
+}
+
+/// `TCP_LISTEN` and `TCP_CLOSE` in the `st` column, the state of unconnected
+/// UDP sockets.
+const TCP_LISTEN: &str = "0A";
+const TCP_CLOSE: &str = "07";
+
+/// Parse a line of `/proc/net/{tcp,udp}{,6}`, like
+/// `0: 0100007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000 0 0 1234 ...`
+fn parse_socket(line: &str, is_tcp: bool) -> Option<Socket> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (local, remote, state, inode) = (
+        fields.get(1)?,
+        fields.get(2)?,
+        fields.get(3)?,
+        fields.get(9)?,
+    );
+    let listening = if is_tcp {
+        *state == TCP_LISTEN
+    } else {
+        *state == TCP_CLOSE && remote.ends_with(":0000")
+    };
+    let address = parse_address(local)?;
+    if !listening || address.port() == 0 {
+        return None;
+    }
+    Some(Socket {
+        address: address.into(),
+        is_tcp,
+        inode: inode.parse().ok()?,
+    })
+}
+
+/// Addresses are printed as 32 bits words in host byte order, the port in hex.
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    let (ip, port) = address.split_once(':')?;
+    let words = (0..ip.len() / 8)
+        .map(|i| u32::from_str_radix(ip.get(i * 8..i * 8 + 8)?, 16).ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, u16::from_str_radix(port, 16).ok()?))
+}
+
+/// Inodes of the sockets open by every process, except `except`, from the
+/// `socket:[<inode>]` links of `/proc/<pid>/fd`.
+fn socket_holders(except: Option<i32>) -> HashMap<u64, i32> {
+    let mut holders = HashMap::new();
+    for pid in pids().filter(|pid| Some(*pid) != except) {
+        let Ok(fds) = fs::read_dir(format!("/proc/{pid}/fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|target| target.strip_prefix("socket:["))
+                .and_then(|target| target.strip_suffix(']'))
+                .and_then(|inode| inode.parse().ok());
+            if let Some(inode) = inode {
+                holders.entry(inode).or_insert(pid);
+            }
+        }
+    }
+    holders
+}
+
+fn pids() -> impl Iterator<Item = i32> {
+    fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+}
+
+/// Listening sockets of every network namespace, with the processes
+/// holding them.
+fn procfs_listeners() -> Vec<Listener> {
+    let holders = socket_holders(None);
+    // One process per network namespace is enough to read its sockets
+    let mut namespaces: HashMap<PathBuf, i32> = HashMap::new();
+    for pid in holders.values() {
+        if let Ok(namespace) = fs::read_link(format!("/proc/{pid}/ns/net")) {
+            namespaces.entry(namespace).or_insert(*pid);
+        }
+    }
+    let since = SystemTime::now();
+    namespaces
+        .values()
+        .filter_map(|pid| net_dir(*pid))
+        .flat_map(|dir| sockets(&dir))
+        .filter_map(|socket| {
+            let owner = Owner::of(*holders.get(&socket.inode)?);
+            Some(Listener {
+                address: socket.address,
+                is_tcp: socket.is_tcp,
+                pid: owner.pid,
+                image: owner.image,
+                container: owner.container,
+                since,
+                inode: Some(socket.inode),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn event(pid: i32, payload: Payload) -> Event {
+        Event::new(
+            Header {
+                image: format!("/usr/bin/server{pid}"),
+                pid,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    fn host(address: &str) -> Host {
+        address.parse::<std::net::SocketAddr>().unwrap().into()
+    }
+
+    fn ports(table: &ListenerTable) -> Vec<(u16, bool, i32)> {
+        table
+            .snapshot()
+            .iter()
+            .map(|listener| (listener.address.port, listener.is_tcp, listener.pid))
+            .collect()
+    }
+
+    #[test]
+    fn listening_sockets() {
+        let table = ListenerTable::new();
+        let listen = |address| Payload::Listen {
+            address: host(address),
+        };
+        let bind = |address, is_tcp| Payload::Bind {
+            address: host(address),
+            is_tcp,
+        };
+        table.record(&event(-10, listen("0.0.0.0:443")));
+        table.record(&event(-10, listen("0.0.0.0:80")));
+        // Workers sharing the port with SO_REUSEPORT
+        table.record(&event(-11, listen("0.0.0.0:80")));
+        table.record(&event(-11, listen("0.0.0.0:80")));
+        table.record(&event(-12, bind("0.0.0.0:53", false)));
+        // Bound for outgoing connections
+        table.record(&event(-12, bind("0.0.0.0:0", false)));
+        table.record(&event(-12, bind("0.0.0.0:8080", true)));
+        assert_eq!(
+            ports(&table),
+            [
+                (53, false, -12),
+                (80, true, -11),
+                (80, true, -10),
+                (443, true, -10)
+            ]
+        );
+        assert_eq!(table.snapshot()[0].image, "/usr/bin/server-12");
+
+        table.record(&event(
+            -10,
+            Payload::Close {
+                source: host("0.0.0.0:80"),
+                destination: host("0.0.0.0:0"),
+                comm: String::new(),
+            },
+        ));
+        assert_eq!(
+            ports(&table),
+            [(53, false, -12), (80, true, -11), (443, true, -10)]
+        );
+        // Accepted connections share the address of the listening socket
+        table.record(&event(
+            -11,
+            Payload::Close {
+                source: host("0.0.0.0:80"),
+                destination: host("10.0.0.1:40000"),
+                comm: String::new(),
+            },
+        ));
+        assert_eq!(
+            ports(&table),
+            [(53, false, -12), (80, true, -11), (443, true, -10)]
+        );
+        // Not held by any other process
+        table.record(&event(-10, Payload::Exit { exit_code: 0 }));
+        assert_eq!(ports(&table), [(53, false, -12), (80, true, -11)]);
+    }
+
+    #[test]
+    fn procfs_sockets() {
+        let tcp = "   0: 0100007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000   101        0 21512 1 0000000000000000 100 0 0 10 0";
+        let socket = parse_socket(tcp, true).unwrap();
+        assert_eq!(socket.address.ip, "127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(socket.address.port, 53);
+        assert_eq!(socket.inode, 21512);
+        // Established connections are not listening
+        let established = tcp.replace(" 0A ", " 01 ");
+        assert!(parse_socket(&established, true).is_none());
+
+        let udp6 = "  12: 00000000000000000000000000000000:14E9 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 19122 2 0000000000000000 0";
+        let socket = parse_socket(udp6, false).unwrap();
+        assert_eq!(socket.address.ip, "::".parse::<IpAddr>().unwrap());
+        assert_eq!(socket.address.port, 5353);
+        // Connected UDP sockets have a peer
+        let connected = udp6.replace(
+            "00000000000000000000000000000000:0000",
+            "0000000000000000FFFF00000100007F:0035",
+        );
+        assert!(parse_socket(&connected, false).is_none());
+    }
+}
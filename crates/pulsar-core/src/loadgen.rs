@@ -0,0 +1,202 @@
+//! Synthetic events sent directly on the bus, to measure the throughput of
+//! the modules and the events they lose without generating real activity.
+//!
+//! Events come from fake processes with PIDs above the kernel `PID_MAX_LIMIT`,
+//! so that response actions triggered by them can't affect real processes.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::{self, Bus},
+    event::{next_event_id, DataCapture, Event, Header, Host, Namespaces, Payload},
+    host::host_info,
+    Timestamp,
+};
+
+/// Source of the synthetic events.
+pub const LOADGEN_SOURCE: &str = "loadgen";
+
+/// PID of the first synthetic process, above `PID_MAX_LIMIT` (4194304).
+pub const FIRST_PID: i32 = 5_000_000;
+
+/// Number of synthetic processes the events are spread on.
+const PROCESSES: u64 = 64;
+
+const TICK: Duration = Duration::from_millis(10);
+
+const IMAGE: &str = "/usr/bin/curl";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadgenConfig {
+    /// Events per second
+    pub rate: u64,
+    pub duration: Duration,
+    /// Relative weight of `Exec` events in the mix
+    pub exec: u32,
+    /// Relative weight of `Connect` events in the mix
+    pub connect: u32,
+    /// Relative weight of `Send` events in the mix
+    pub send: u32,
+}
+
+impl Default for LoadgenConfig {
+    fn default() -> Self {
+        Self {
+            rate: 10_000,
+            duration: Duration::from_secs(10),
+            exec: 1,
+            connect: 2,
+            send: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadgenReport {
+    pub sent: u64,
+    pub elapsed: Duration,
+    /// Events lost by the receivers lagging behind the bus
+    pub lost: u64,
+    /// Highest number of events waiting to be received
+    pub max_pending: usize,
+}
+
+impl LoadgenReport {
+    /// Events sent per second.
+    pub fn rate(&self) -> u64 {
+        (self.sent as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)) as u64
+    }
+}
+
+/// Send the configured mix of events on the bus until `config.duration` has
+/// elapsed.
+pub async fn run(bus: &Bus, config: &LoadgenConfig) -> LoadgenReport {
+    let lost_before = bus::lost_events();
+    let mut report = LoadgenReport::default();
+    let start = Instant::now();
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let elapsed = start.elapsed().min(config.duration);
+        // Catch up with the ticks delayed by a busy runtime
+        let target = (config.rate as u128 * elapsed.as_millis() / 1000) as u64;
+        while report.sent < target {
+            let _ = bus.send(event(report.sent, config));
+            report.sent += 1;
+        }
+        report.max_pending = report.max_pending.max(bus.pending());
+        if elapsed >= config.duration {
+            break;
+        }
+    }
+    report.elapsed = start.elapsed();
+    report.lost = bus::lost_events() - lost_before;
+    report
+}
+
+fn event(n: u64, config: &LoadgenConfig) -> Event {
+    let timestamp = Timestamp::now();
+    let pid = FIRST_PID + (n % PROCESSES) as i32;
+    let destination = Host {
+        ip: IpAddr::V4(Ipv4Addr::new(10, 0, (n >> 8) as u8, n as u8)),
+        port: 443,
+        scope_id: 0,
+        flowinfo: 0,
+        raw_ip: None,
+    };
+    let slot = n % (config.exec + config.connect + config.send).max(1) as u64;
+    let payload = if slot < config.exec as u64 {
+        Payload::Exec {
+            filename: IMAGE.to_string(),
+            argc: 2,
+            argv: vec!["curl".to_string(), format!("https://{}/", destination.ip)].into(),
+            namespaces: Namespaces::default(),
+            script: String::new(),
+            shell: Default::default(),
+            env: Vec::new(),
+            cwd: String::new(),
+            chrooted: false,
+            package: String::new(),
+            package_version: String::new(),
+            verified: false,
+            package_checked: false,
+        }
+    } else if slot < (config.exec + config.connect) as u64 {
+        Payload::Connect {
+            destination,
+            is_tcp: true,
+        }
+    } else {
+        Payload::Send {
+            source: Host {
+                ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                port: 40000 + (n % 20000) as u16,
+                ..destination.clone()
+            },
+            destination,
+            len: 512,
+            is_tcp: true,
+            offset: 0,
+            capture: DataCapture::Disabled,
+        }
+    };
+    Event::new(
+        Header {
+            id: next_event_id(),
+            parent_event_id: None,
+            image: IMAGE.to_string(),
+            pid,
+            parent_pid: FIRST_PID - 1,
+            threat: None,
+            source: LOADGEN_SOURCE.into(),
+            timestamp: timestamp.into(),
+            raw_timestamp: timestamp.raw(),
+            fork_time: UNIX_EPOCH,
+            exec_chain: vec![IMAGE.to_string()],
+            exec_chain_hash: String::new(),
+            is_interactive: false,
+            sandboxed_runtime: String::new(),
+            host: host_info(),
+            coalesced: None,
+        },
+        payload,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mix() {
+        let bus = Bus::new();
+        let mut rx = bus.get_receiver();
+        let config = LoadgenConfig {
+            rate: 1000,
+            duration: Duration::from_millis(200),
+            exec: 1,
+            connect: 1,
+            send: 2,
+        };
+        let report = run(&bus, &config).await;
+        assert_eq!(report.sent, 200);
+        assert_eq!(report.lost, 0);
+
+        let mut counts = [0; 3];
+        while let Ok(event) = rx.try_recv() {
+            assert!(event.header().pid >= FIRST_PID);
+            match event.payload() {
+                Payload::Exec { .. } => counts[0] += 1,
+                Payload::Connect { .. } => counts[1] += 1,
+                Payload::Send { .. } => counts[2] += 1,
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(counts, [50, 50, 100]);
+    }
+}
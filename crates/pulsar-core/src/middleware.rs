@@ -0,0 +1,512 @@
+//! Middlewares modifying the events sent on the [`Bus`].
+//!
+//! Middlewares are registered on the bus with [`Bus::with_middleware`] and run
+//! in order on every event sent, before coalescing and before the event reaches
+//! the taps and the receivers. Each one can modify the event or drop it, like:
+//!
+//! - [`SandboxedRuntime`]: enrichment, marking the events of sandboxed containers
+//! - [`Redact`]: redaction of secrets passed on the command line
+//! - [`PackageInfo`]: enrichment, adding the package of the executables run
+//!
+//! ```
+//! use pulsar_core::{bus::Bus, middleware::Middleware, pdk::Event};
+//!
+//! struct NoPulsard;
+//!
+//! impl Middleware for NoPulsard {
+//!     fn process(&self, event: Event) -> Option<Event> {
+//!         (event.header().image != "/usr/bin/pulsard").then_some(event)
+//!     }
+//! }
+//!
+//! let bus = Bus::new().with_middleware(NoPulsard);
+//! ```
+//!
+//! Events dropped by a middleware are never seen by the rules: filters which
+//! only reduce what is logged, like sampling, are [`crate::output`] filters.
+//! Middlewares see the events once converted, after the module sent them: the
+//! process-monitor still updates the process tracker from the eBPF events
+//! with a [`bpf_common::BpfSenderWrapper`], since the tracker must know a
+//! process before its events are sent.
+
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{sync_channel, SyncSender},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    bus::Bus,
+    container,
+    event::{Argv, Payload},
+    packages::{Digest, FileVersion, PackageDb, PackageFile},
+    pdk::{ConfigError, Event, ModuleConfig},
+};
+
+/// Replacement of the redacted values.
+pub const REDACTED: &str = "<redacted>";
+
+/// Stage of the [`Bus`] modifying the events before they're sent.
+pub trait Middleware: Send + Sync + 'static {
+    /// Return the modified event, or `None` to drop it.
+    fn process(&self, event: Event) -> Option<Event>;
+}
+
+/// Middlewares configured in the general section of the daemon configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MiddlewareConfig {
+    /// Options whose value is redacted from the arguments of `Exec` events
+    pub redact_args: Vec<String>,
+    /// Add the package of the executable to `Exec` events
+    pub package_db: bool,
+}
+
+impl TryFrom<&ModuleConfig> for MiddlewareConfig {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            redact_args: config.get_list_with_default("redact_args", Vec::new())?,
+            package_db: config.with_default("package_db", false)?,
+        })
+    }
+}
+
+impl MiddlewareConfig {
+    /// Register the configured middlewares on the bus.
+    pub fn register(self, mut bus: Bus) -> Bus {
+        if self.package_db {
+            bus = bus.with_middleware(PackageInfo::new(PackageDb::shared()));
+        }
+        if !self.redact_args.is_empty() {
+            bus = bus.with_middleware(Redact::new(self.redact_args));
+        }
+        bus
+    }
+}
+
+/// Set `header.sandboxed_runtime` from the exec chain of the process, see
+/// [`container::sandboxed_runtime`].
+pub struct SandboxedRuntime;
+
+impl Middleware for SandboxedRuntime {
+    fn process(&self, mut event: Event) -> Option<Event> {
+        if let Some(runtime) = container::sandboxed_runtime(&event.header.exec_chain) {
+            event.header.sandboxed_runtime = runtime.to_string();
+        }
+        Some(event)
+    }
+}
+
+/// Replace the values of the given options in the arguments of `Exec` events,
+/// both as `--password=value` and `--password value`, also inside the commands
+/// run by shells with `-c`.
+pub struct Redact {
+    options: Vec<String>,
+}
+
+impl Redact {
+    pub fn new(options: Vec<String>) -> Self {
+        Self { options }
+    }
+
+    fn redact_argv(&self, argv: &Argv) -> Option<Argv> {
+        let mut redacted = false;
+        let mut redact_next = false;
+        let args: Vec<String> = argv
+            .into_iter()
+            .map(|arg| {
+                if std::mem::take(&mut redact_next) {
+                    redacted = true;
+                    return REDACTED.to_string();
+                }
+                redact_next = self.options.iter().any(|option| option == arg);
+                match self.redact_words(arg) {
+                    Some(arg) => {
+                        redacted = true;
+                        arg
+                    }
+                    None => arg.clone(),
+                }
+            })
+            .collect();
+        redacted.then(|| Argv::from(args))
+    }
+
+    /// Redact the options inside an argument, like the command of `sh -c`.
+    fn redact_words(&self, arg: &str) -> Option<String> {
+        let mut redacted = false;
+        let mut redact_next = false;
+        let words: Vec<String> = arg
+            .split(' ')
+            .map(|word| {
+                if std::mem::take(&mut redact_next) && !word.is_empty() {
+                    redacted = true;
+                    return REDACTED.to_string();
+                }
+                for option in &self.options {
+                    if word == option {
+                        // The argument itself is handled by `redact_argv`
+                        redact_next = word != arg;
+                    } else if let Some(value) = word.strip_prefix(option.as_str()) {
+                        if value.starts_with('=') && value.len() > 1 {
+                            redacted = true;
+                            return format!("{option}={REDACTED}");
+                        }
+                    }
+                }
+                word.to_string()
+            })
+            .collect();
+        redacted.then(|| words.join(" "))
+    }
+}
+
+impl Middleware for Redact {
+    fn process(&self, mut event: Event) -> Option<Event> {
+        if let Payload::Exec { argv, .. } = &mut event.payload {
+            if let Some(redacted) = self.redact_argv(argv) {
+                *argv = redacted;
+            }
+        }
+        Some(event)
+    }
+}
+
+/// Set the `package`, `package_version`, `verified` and `package_checked`
+/// fields of `Exec` events from the [`PackageDb`] of the host. Processes in
+/// other mount namespaces, like containers, and chrooted ones run files which
+/// are not the ones of the host database: they're left out.
+///
+/// Resolving symlinks and hashing executables would block the sender of the
+/// event: they're done by a background thread, the first time an executable
+/// is run and after every change. Until then, the events of the executable
+/// are left unchecked.
+pub struct PackageInfo {
+    db: Arc<PackageDb>,
+    mnt_namespace: Option<u32>,
+    cache: Arc<PackageCache>,
+    jobs: SyncSender<PackageJob>,
+}
+
+/// Entries of each cache, which is cleared when full.
+const PACKAGE_CACHE_SIZE: usize = 4096;
+/// Executables waiting to be resolved or hashed, more are checked again on
+/// their next exec.
+const PACKAGE_JOBS: usize = 1024;
+
+/// Results of the background thread of [`PackageInfo`].
+#[derive(Default)]
+struct PackageCache {
+    /// Canonical path of the executables not found by their path, `None`
+    /// when not owned by any package
+    canonical: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+    /// Verification of the executables
+    verified: Mutex<HashMap<FileVersion, bool>>,
+}
+
+enum PackageJob {
+    Resolve(PathBuf),
+    Verify(PathBuf, FileVersion, Digest),
+}
+
+/// Outcome of the lookup of an executable.
+enum Lookup<'a> {
+    NotOwned,
+    Owned(PathBuf, &'a PackageFile),
+    /// Resolved or hashed in the background
+    Pending,
+}
+
+impl PackageInfo {
+    pub fn new(db: Arc<PackageDb>) -> Self {
+        // Links to `mnt:[<inode>]`
+        let mnt_namespace = fs::read_link("/proc/self/ns/mnt").ok().and_then(|link| {
+            link.to_str()?
+                .strip_prefix("mnt:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        });
+        let cache = Arc::new(PackageCache::default());
+        let (jobs, rx_jobs) = sync_channel(PACKAGE_JOBS);
+        let worker_cache = cache.clone();
+        let worker_db = db.clone();
+        // Stops when the middleware is dropped
+        std::thread::Builder::new()
+            .name("package-info".to_string())
+            .spawn(move || {
+                for job in rx_jobs {
+                    worker_cache.run(&worker_db, job);
+                }
+            })
+            .expect("spawning the package-info thread");
+        Self {
+            db,
+            mnt_namespace,
+            cache,
+            jobs,
+        }
+    }
+
+    /// Package file of the executable at `path`, with its canonical path.
+    fn lookup(&self, path: &Path) -> Lookup<'_> {
+        if let Some(file) = self.db.file(path) {
+            return Lookup::Owned(path.to_path_buf(), file);
+        }
+        let canonical = self.cache.canonical.lock().unwrap().get(path).cloned();
+        match canonical {
+            Some(Some(canonical)) => match self.db.file(&canonical) {
+                Some(file) => Lookup::Owned(canonical, file),
+                None => Lookup::NotOwned,
+            },
+            Some(None) => Lookup::NotOwned,
+            None => {
+                self.submit(PackageJob::Resolve(path.to_path_buf()));
+                Lookup::Pending
+            }
+        }
+    }
+
+    /// Verification of the executable, `None` while it's hashed.
+    fn verify(&self, path: &Path, digest: &Digest) -> Option<bool> {
+        let Ok(version) = fs::metadata(path).map(|metadata| FileVersion::from(&metadata)) else {
+            return Some(false);
+        };
+        if let Some(verified) = self.cache.verified.lock().unwrap().get(&version) {
+            return Some(*verified);
+        }
+        self.submit(PackageJob::Verify(
+            path.to_path_buf(),
+            version,
+            digest.clone(),
+        ));
+        None
+    }
+
+    fn submit(&self, job: PackageJob) {
+        if self.jobs.try_send(job).is_err() {
+            log::debug!("Package checks queue full, executable left unchecked");
+        }
+    }
+}
+
+impl PackageCache {
+    fn run(&self, db: &PackageDb, job: PackageJob) {
+        match job {
+            PackageJob::Resolve(path) => {
+                let canonical = db.get(&path).map(|(canonical, _)| canonical);
+                insert_bounded(&self.canonical, path, canonical);
+            }
+            PackageJob::Verify(path, version, digest) => {
+                if self.verified.lock().unwrap().contains_key(&version) {
+                    return;
+                }
+                let verified = digest.matches(&path).unwrap_or(false);
+                insert_bounded(&self.verified, version, verified);
+            }
+        }
+    }
+}
+
+fn insert_bounded<K: Eq + Hash, V>(cache: &Mutex<HashMap<K, V>>, key: K, value: V) {
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= PACKAGE_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(key, value);
+}
+
+impl Middleware for PackageInfo {
+    fn process(&self, mut event: Event) -> Option<Event> {
+        let Payload::Exec {
+            filename,
+            namespaces,
+            cwd,
+            chrooted,
+            package,
+            package_version,
+            verified,
+            package_checked,
+            ..
+        } = &mut event.payload
+        else {
+            return Some(event);
+        };
+        if *chrooted
+            || self
+                .mnt_namespace
+                .is_some_and(|mnt_namespace| mnt_namespace != namespaces.mnt)
+        {
+            return Some(event);
+        }
+        let path = Path::new(cwd.as_str()).join(filename.as_str());
+        match self.lookup(&path) {
+            Lookup::Pending => {}
+            Lookup::NotOwned => *package_checked = true,
+            Lookup::Owned(path, file) => {
+                let file_verified = match &file.digest {
+                    Some(digest) => self.verify(&path, digest),
+                    None => Some(false),
+                };
+                if let Some(file_verified) = file_verified {
+                    *package = file.package.name.clone();
+                    *package_version = file.package.version.clone();
+                    *verified = file_verified;
+                    *package_checked = true;
+                }
+            }
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::event::Header;
+
+    fn event(exec_chain: &[&str], payload: Payload) -> Event {
+        Event::new(
+            Header {
+                id: 1,
+                image: exec_chain.last().unwrap_or(&"").to_string(),
+                pid: 42,
+                parent_pid: 1,
+                exec_chain: exec_chain.iter().map(|image| image.to_string()).collect(),
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    fn exec(argv: &[&str]) -> Event {
+        event(
+            &["/bin/bash"],
+            Payload::Exec {
+                filename: argv[0].to_string(),
+                argc: argv.len(),
+                argv: Argv::from(argv.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()),
+                namespaces: Default::default(),
+                script: String::new(),
+                shell: Default::default(),
+                env: Vec::new(),
+                cwd: String::new(),
+                chrooted: false,
+                package: String::new(),
+                package_version: String::new(),
+                verified: false,
+                package_checked: false,
+            },
+        )
+    }
+
+    fn argv(event: &Event) -> Vec<String> {
+        let Payload::Exec { argv, .. } = event.payload() else {
+            panic!("not an Exec event");
+        };
+        argv.into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn redact() {
+        let redact = Redact::new(vec!["--password".to_string(), "-p".to_string()]);
+        let redacted = |args: &[&str]| argv(&redact.process(exec(args)).unwrap());
+
+        assert_eq!(
+            redacted(&["mysql", "--password=hunter2", "-p", "hunter2", "db"]),
+            ["mysql", "--password=<redacted>", "-p", "<redacted>", "db"]
+        );
+        assert_eq!(
+            redacted(&["sh", "-c", "mysql --password=hunter2 -p hunter2 db"]),
+            ["sh", "-c", "mysql --password=<redacted> -p <redacted> db"]
+        );
+        // Not an option with a value
+        assert_eq!(
+            redacted(&["ls", "--password-file", "-pl"]),
+            ["ls", "--password-file", "-pl"]
+        );
+    }
+
+    #[test]
+    fn sandboxed_runtime() {
+        let event = SandboxedRuntime
+            .process(event(
+                &["/usr/bin/containerd-shim-runsc-v1", "/usr/local/bin/runsc"],
+                Payload::Exit { exit_code: 0 },
+            ))
+            .unwrap();
+        assert_eq!(event.header().sandboxed_runtime, "gvisor");
+    }
+
+    #[test]
+    fn package_info() {
+        use crate::packages::{digest, Algorithm, Package};
+
+        let dir = std::env::temp_dir().join(format!("package-info-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tool");
+        fs::write(&tool, "abc").unwrap();
+        let mut db = PackageDb::default();
+        let package = Arc::new(Package::new("tools", "1.0-1"));
+        let digest = Digest {
+            algorithm: Algorithm::Sha256,
+            value: digest::hash(Algorithm::Sha256, b"abc"),
+        };
+        db.insert(tool.to_string_lossy().into_owned(), &package, Some(digest));
+        let mut package_info = PackageInfo::new(Arc::new(db));
+        // The test events have no namespaces
+        package_info.mnt_namespace = None;
+        let run = |filename: &str| {
+            let mut event = exec(&[filename]);
+            if let Payload::Exec { cwd, .. } = &mut event.payload {
+                *cwd = dir.to_string_lossy().into_owned();
+            }
+            let Payload::Exec {
+                package,
+                package_version,
+                verified,
+                package_checked,
+                ..
+            } = package_info.process(event).unwrap().payload
+            else {
+                panic!("not an Exec event");
+            };
+            (package, package_version, verified, package_checked)
+        };
+        // Unchecked until resolved or hashed in the background
+        let run_checked = |filename: &str| {
+            for _ in 0..500 {
+                let result = run(filename);
+                if result.3 {
+                    return result;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("{filename} never checked");
+        };
+
+        assert_eq!(run("./tool"), (String::new(), String::new(), false, false));
+        assert_eq!(
+            run_checked("./tool"),
+            ("tools".to_string(), "1.0-1".to_string(), true, true)
+        );
+        assert_eq!(
+            run_checked("/usr/bin/unknown"),
+            (String::new(), String::new(), false, true)
+        );
+        // Modified after the installation
+        fs::write(&tool, "abcd").unwrap();
+        assert_eq!(
+            run_checked("./tool"),
+            ("tools".to_string(), "1.0-1".to_string(), false, true)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
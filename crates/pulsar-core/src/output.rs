@@ -0,0 +1,512 @@
+//! Filters of the events sent to the outputs of the [`Bus`].
+//!
+//! Every event broadcast by the bus reaches its receivers, like the rules
+//! engine, unchanged. Outputs logging the events, like the history of
+//! `pulsar export` and `pulsar monitor`, use [`Bus::get_output_receiver`]
+//! instead: their events first go through the output filters registered with
+//! [`Bus::with_output_filter`], which can drop them, like:
+//!
+//! - [`Sample`]: sampling, keeping one event every N of a payload type
+//! - [`DnsAggregate`]: DNS queries and responses counted by domain and sent
+//!   as a periodic `DnsSummary`
+//!
+//! Events with a threat are never dropped.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    bus::Bus,
+    event::{DomainCount, Payload, PayloadDiscriminant},
+    heartbeat::daemon_event,
+    pdk::{ConfigError, Event, ModuleConfig},
+};
+
+/// Distinct domains counted in an interval. The questions of further domains
+/// are counted only in the total, so that queries of random subdomains can't
+/// exhaust the memory.
+pub const MAX_DOMAINS: usize = 10_000;
+
+const DEFAULT_DNS_SUMMARY_INTERVAL: u64 = 300;
+const DEFAULT_DNS_SUMMARY_TOP_K: usize = 50;
+
+/// Stage of the [`Bus`] selecting the events sent to the outputs.
+pub trait OutputFilter: Send + Sync + 'static {
+    /// Return `false` to keep the event from the outputs.
+    fn keep(&self, event: &Event) -> bool;
+}
+
+impl<T: OutputFilter> OutputFilter for Arc<T> {
+    fn keep(&self, event: &Event) -> bool {
+        T::keep(self, event)
+    }
+}
+
+/// Output filters configured in the general section of the daemon
+/// configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputConfig {
+    /// Payload types of which only one event every N is sent
+    pub sample_rates: HashMap<PayloadDiscriminant, u64>,
+    /// Send DNS summaries to the outputs instead of every query and response
+    pub dns_aggregate: Option<DnsAggregateConfig>,
+}
+
+/// Interval and size of the DNS summaries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsAggregateConfig {
+    pub interval: Duration,
+    /// Domains listed in a summary, the others are only counted
+    pub top_k: usize,
+    /// Questions of a domain needed to list it in a summary
+    pub min_count: u64,
+}
+
+impl TryFrom<&ModuleConfig> for OutputConfig {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let sample_rates = config
+            .get_list_with_default::<SampleEntry>("sample_events", Vec::new())?
+            .into_iter()
+            .map(|entry| (entry.payload_type, entry.rate))
+            .collect();
+        let dns_aggregate = if config.with_default("dns_aggregate", false)? {
+            let interval =
+                config.with_default("dns_summary_interval", DEFAULT_DNS_SUMMARY_INTERVAL)?;
+            Some(DnsAggregateConfig {
+                interval: Duration::from_secs(interval.max(1)),
+                top_k: config.with_default("dns_summary_top_k", DEFAULT_DNS_SUMMARY_TOP_K)?,
+                min_count: config.with_default("dns_summary_min_count", 1)?,
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            sample_rates,
+            dns_aggregate,
+        })
+    }
+}
+
+impl OutputConfig {
+    /// Register the configured output filters on the bus. Must be called
+    /// within a Tokio runtime, which sends the DNS summaries.
+    pub fn register(self, mut bus: Bus) -> Bus {
+        if !self.sample_rates.is_empty() {
+            bus = bus.with_output_filter(Sample::new(self.sample_rates));
+        }
+        if let Some(config) = self.dns_aggregate {
+            let aggregate = DnsAggregate::start(bus.clone(), config);
+            bus = bus.with_output_filter(aggregate);
+        }
+        bus
+    }
+}
+
+/// Item of `sample_events`: a payload type followed by the sampling rate, like
+/// `FileOpened:10`.
+struct SampleEntry {
+    payload_type: PayloadDiscriminant,
+    rate: u64,
+}
+
+#[derive(Error, Debug)]
+#[error("{0} is not a payload type followed by `:<rate>`")]
+struct InvalidSampleEntry(String);
+
+impl FromStr for SampleEntry {
+    type Err = InvalidSampleEntry;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSampleEntry(s.to_string());
+        let (payload_type, rate) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            payload_type: PayloadDiscriminant::from_str(payload_type.trim())
+                .map_err(|_| invalid())?,
+            rate: rate
+                .trim()
+                .parse()
+                .ok()
+                .filter(|rate| *rate > 0)
+                .ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// Send to the outputs one event every `rate` of the given payload types.
+/// Threats are always sent.
+pub struct Sample {
+    rates: HashMap<PayloadDiscriminant, (u64, AtomicU64)>,
+}
+
+impl Sample {
+    pub fn new(rates: HashMap<PayloadDiscriminant, u64>) -> Self {
+        Self {
+            rates: rates
+                .into_iter()
+                .map(|(payload_type, rate)| (payload_type, (rate, AtomicU64::new(0))))
+                .collect(),
+        }
+    }
+}
+
+impl OutputFilter for Sample {
+    fn keep(&self, event: &Event) -> bool {
+        if event.header.threat.is_some() {
+            return true;
+        }
+        match self.rates.get(&PayloadDiscriminant::from(&event.payload)) {
+            Some((rate, count)) => count.fetch_add(1, Ordering::Relaxed) % rate == 0,
+            None => true,
+        }
+    }
+}
+
+/// Count the DNS queries and responses instead of sending them to the
+/// outputs, and send a `DnsSummary` of the daemon every interval when there
+/// were some.
+pub struct DnsAggregate {
+    config: DnsAggregateConfig,
+    counter: Mutex<DnsCounter>,
+}
+
+impl DnsAggregate {
+    /// Create the aggregation of the DNS events of `bus`, whose summaries are
+    /// sent by a background task until it's dropped.
+    pub fn start(bus: Bus, config: DnsAggregateConfig) -> Arc<Self> {
+        let aggregate = Arc::new(Self {
+            config,
+            counter: Mutex::new(DnsCounter::default()),
+        });
+        tokio::spawn(summary_task(bus, Arc::downgrade(&aggregate)));
+        aggregate
+    }
+
+    fn take(&self) -> Option<Payload> {
+        self.counter.lock().unwrap().take(
+            self.config.interval,
+            self.config.top_k,
+            self.config.min_count,
+        )
+    }
+}
+
+impl OutputFilter for DnsAggregate {
+    fn keep(&self, event: &Event) -> bool {
+        if event.header.threat.is_some() {
+            return true;
+        }
+        match &event.payload {
+            Payload::DnsQuery { .. } | Payload::DnsResponse { .. } => {
+                self.counter.lock().unwrap().record(&event.payload);
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+async fn summary_task(bus: Bus, aggregate: Weak<DnsAggregate>) {
+    let Some(period) = aggregate
+        .upgrade()
+        .map(|aggregate| aggregate.config.interval)
+    else {
+        return;
+    };
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(aggregate) = aggregate.upgrade() else {
+            return;
+        };
+        // Summaries are not about a single process: they're reported by the
+        // daemon itself
+        if let Some(summary) = aggregate.take() {
+            bus.send_output(daemon_event(summary));
+        }
+    }
+}
+
+/// Questions of the DNS queries and responses counted by domain, to log a
+/// summary every interval instead of every event.
+#[derive(Debug, Default)]
+pub struct DnsCounter {
+    domains: HashMap<String, u64>,
+    queries: u64,
+    responses: u64,
+}
+
+impl DnsCounter {
+    /// Count the questions of a `DnsQuery` by domain, and the ones of a
+    /// `DnsResponse` in the total of responses. Other payloads are ignored.
+    pub fn record(&mut self, payload: &Payload) {
+        match payload {
+            Payload::DnsQuery { questions } => {
+                for question in questions {
+                    self.queries += 1;
+                    if let Some(count) = self.domains.get_mut(&question.name) {
+                        *count += 1;
+                    } else if self.domains.len() < MAX_DOMAINS {
+                        self.domains.insert(question.name.clone(), 1);
+                    }
+                }
+            }
+            Payload::DnsResponse { questions, .. } => self.responses += questions.len() as u64,
+            _ => {}
+        }
+    }
+
+    /// Reset the counters, returning a `DnsSummary` with the `top_k` most
+    /// queried domains having at least `min_count` questions, or `None` if
+    /// there were no queries nor responses.
+    pub fn take(&mut self, interval: Duration, top_k: usize, min_count: u64) -> Option<Payload> {
+        if self.queries == 0 && self.responses == 0 {
+            return None;
+        }
+        let counter = std::mem::take(self);
+        let mut domains: Vec<DomainCount> = counter
+            .domains
+            .into_iter()
+            .filter(|(_, count)| *count >= min_count)
+            .map(|(name, count)| DomainCount { name, count })
+            .collect();
+        domains.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        domains.truncate(top_k);
+        let listed: u64 = domains.iter().map(|domain| domain.count).sum();
+        Some(Payload::DnsSummary {
+            interval: interval.as_secs(),
+            queries: counter.queries,
+            responses: counter.responses,
+            domains,
+            other: counter.queries - listed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        event::{DnsQuestion, Header, Threat},
+        pdk::{policy::ThreatRouting, ModuleName, ModuleReceiver},
+    };
+
+    fn questions(names: &[&str]) -> Vec<DnsQuestion> {
+        names
+            .iter()
+            .map(|name| DnsQuestion {
+                name: name.to_string(),
+                normalized: name.to_string(),
+                dga_score: 0,
+                first_seen: false,
+                qtype: "A".to_string(),
+                qclass: "IN".to_string(),
+            })
+            .collect()
+    }
+
+    fn query(names: &[&str]) -> Payload {
+        Payload::DnsQuery {
+            questions: questions(names),
+        }
+    }
+
+    fn event(payload: Payload) -> Event {
+        Event::new(
+            Header {
+                image: "/usr/bin/curl".to_string(),
+                pid: 42,
+                parent_pid: 1,
+                ..Header::test_default()
+            },
+            payload,
+        )
+    }
+
+    #[test]
+    fn sample() {
+        let sample = Sample::new(HashMap::from([(PayloadDiscriminant::Exit, 3)]));
+        let kept = (0..9)
+            .filter(|_| sample.keep(&event(Payload::Exit { exit_code: 0 })))
+            .count();
+        assert_eq!(kept, 3);
+        assert!(sample.keep(&event(query(&["example.com"]))));
+    }
+
+    #[test]
+    fn sample_entries() {
+        let entry = SampleEntry::from_str("FileOpened:10").unwrap();
+        assert_eq!(
+            (entry.payload_type, entry.rate),
+            (PayloadDiscriminant::FileOpened, 10)
+        );
+        assert!(SampleEntry::from_str("FileOpened").is_err());
+        assert!(SampleEntry::from_str("FileOpened:0").is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let mut counter = DnsCounter::default();
+        assert!(counter.take(Duration::from_secs(60), 2, 1).is_none());
+
+        for names in [
+            &["example.com", "example.org"][..],
+            &["example.com"],
+            &["example.org"],
+            &["example.com"],
+            &["rare.example.net"],
+            &["a.example.io"],
+            &["a.example.io"],
+        ] {
+            counter.record(&query(names));
+        }
+        counter.record(&Payload::DnsResponse {
+            questions: questions(&["example.com"]),
+            answers: Vec::new(),
+        });
+
+        let Some(Payload::DnsSummary {
+            interval,
+            queries,
+            responses,
+            domains,
+            other,
+        }) = counter.take(Duration::from_secs(60), 2, 2)
+        else {
+            panic!("expected a summary");
+        };
+        assert_eq!((interval, queries, responses, other), (60, 8, 1, 3));
+        assert_eq!(
+            domains,
+            vec![
+                DomainCount {
+                    name: "example.com".to_string(),
+                    count: 3
+                },
+                DomainCount {
+                    name: "a.example.io".to_string(),
+                    count: 2
+                },
+            ]
+        );
+        assert!(counter.take(Duration::from_secs(60), 2, 1).is_none());
+    }
+
+    #[test]
+    fn max_domains() {
+        let mut counter = DnsCounter::default();
+        for index in 0..MAX_DOMAINS + 10 {
+            counter.record(&query(&[&format!("{index}.example.com")]));
+        }
+        assert_eq!(counter.domains.len(), MAX_DOMAINS);
+        let Some(Payload::DnsSummary { queries, other, .. }) =
+            counter.take(Duration::from_secs(60), 0, 1)
+        else {
+            panic!("expected a summary");
+        };
+        assert_eq!(
+            (queries, other),
+            (MAX_DOMAINS as u64 + 10, MAX_DOMAINS as u64 + 10)
+        );
+    }
+
+    #[tokio::test]
+    async fn dns_aggregate() {
+        let config = DnsAggregateConfig {
+            interval: Duration::from_secs(3600),
+            top_k: 10,
+            min_count: 1,
+        };
+        let bus = Bus::new();
+        let aggregate = DnsAggregate::start(bus.clone(), config);
+        let bus = bus.with_output_filter(aggregate.clone());
+        let mut rx = bus.get_receiver();
+        let mut output = bus.get_output_receiver();
+
+        let mut threat = event(query(&["evil.example.com"]));
+        threat.header.threat = Some(Threat {
+            id: String::new(),
+            source: "rules-engine".into(),
+            description: "DNS query".to_string(),
+            severity: Default::default(),
+            outputs: None,
+            extra: None,
+            evidence: None,
+        });
+        bus.send(event(query(&["example.com"]))).unwrap();
+        bus.send(event(Payload::DnsResponse {
+            questions: questions(&["example.com"]),
+            answers: Vec::new(),
+        }))
+        .unwrap();
+        bus.send(event(Payload::Exit { exit_code: 0 })).unwrap();
+        bus.send(threat).unwrap();
+
+        // The receivers, like the rules engine, get every event
+        for _ in 0..4 {
+            rx.try_recv().unwrap();
+        }
+        // The outputs only the other events and the threats
+        assert!(matches!(
+            output.try_recv().unwrap().payload(),
+            Payload::Exit { .. }
+        ));
+        assert!(output.try_recv().unwrap().header().threat.is_some());
+        assert!(output.try_recv().is_err());
+
+        let Some(Payload::DnsSummary {
+            queries,
+            responses,
+            domains,
+            ..
+        }) = aggregate.take()
+        else {
+            panic!("expected a summary");
+        };
+        assert_eq!((queries, responses), (1, 1));
+        assert_eq!(domains[0].name, "example.com");
+    }
+
+    #[tokio::test]
+    async fn output_module_receiver() {
+        let config = DnsAggregateConfig {
+            interval: Duration::from_secs(3600),
+            top_k: 10,
+            min_count: 1,
+        };
+        let bus = Bus::with_early_buffer(10);
+        let aggregate = DnsAggregate::start(bus.clone(), config);
+        let bus = bus.with_output_filter(aggregate.clone());
+
+        // Sent before the output module starts, kept by the early buffer
+        bus.send(event(query(&["example.com"]))).unwrap();
+        bus.send_output(daemon_event(aggregate.take().unwrap()));
+        let (_tx, policy) = tokio::sync::watch::channel(ModuleConfig::default());
+        let name: ModuleName = "logger".into();
+        let mut receiver = ModuleReceiver::output(&bus, &name, ThreatRouting::new(&name, policy));
+        bus.send(event(query(&["example.com"]))).unwrap();
+        bus.send(event(Payload::Exit { exit_code: 0 })).unwrap();
+
+        assert!(matches!(
+            receiver.recv().await.unwrap().payload(),
+            Payload::DnsSummary { queries: 1, .. }
+        ));
+        assert!(matches!(
+            receiver.recv().await.unwrap().payload(),
+            Payload::Exit { .. }
+        ));
+        assert!(receiver.rx.try_recv().is_err());
+    }
+}
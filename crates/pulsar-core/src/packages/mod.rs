@@ -0,0 +1,516 @@
+//! Database of the files installed by the package managers of the host.
+//!
+//! The files owned by packages are loaded from dpkg, apk and rpm, along with
+//! their digests, so that executables can be attributed to their package and
+//! checked against the hash recorded when it was installed:
+//!
+//! - dpkg: the `Version` and `Source` of the installed packages from `status`,
+//!   their files from `info/<package>.list` and the MD5 of their files from
+//!   `info/<package>.md5sums`
+//! - apk: the packages, origins, files and SHA-1 digests of
+//!   `/lib/apk/db/installed`
+//! - rpm: the output of `rpm -qa`, since the rpm database can't be read
+//!   directly
+//!
+//! The database is loaded once with [`PackageDb::shared`], and used both by the
+//! `package_db` middleware and by the modules.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, OnceLock},
+};
+
+pub mod digest;
+
+pub use digest::{Algorithm, Digest};
+
+pub const DPKG_PATH: &str = "/var/lib/dpkg";
+pub const APK_INSTALLED_PATH: &str = "/lib/apk/db/installed";
+/// Directories of the rpm database, the first one used by recent distributions.
+const RPM_DB_PATHS: &[&str] = &["/usr/lib/sysimage/rpm", "/var/lib/rpm"];
+/// Query printing a `<name> <version> <source rpm> <algorithm> <digest> <path>`
+/// line for every file, see `rpm --querytags`.
+const RPM_QUERY_FORMAT: &str = concat!(
+    "[%{=NAME} %{=VERSION}-%{=RELEASE} %{=SOURCERPM} ",
+    "%{=FILEDIGESTALGO} %{FILEDIGESTS} %{FILENAMES}\\n]"
+);
+
+static SHARED: OnceLock<Arc<PackageDb>> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Source package the package is built from, like `openssl` for
+    /// `libssl3`. Vulnerability databases like OSV use this name.
+    pub source: String,
+    /// Version of the source package, which differs from `version` for
+    /// rebuilds of the binary package only
+    pub source_version: String,
+}
+
+impl Package {
+    /// A package built from the source package with the same name and version.
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            source: name.to_string(),
+            source_version: version.to_string(),
+        }
+    }
+}
+
+/// File installed by a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFile {
+    pub package: Arc<Package>,
+    /// Digest recorded by the package manager, missing for directories,
+    /// symlinks and some configuration files
+    pub digest: Option<Digest>,
+}
+
+/// Files installed by packages, by path.
+#[derive(Debug, Default)]
+pub struct PackageDb {
+    files: HashMap<String, PackageFile>,
+}
+
+impl PackageDb {
+    /// The database of the host, loaded by the first caller, which blocks
+    /// for a few seconds, and shared by the others.
+    pub fn shared() -> Arc<PackageDb> {
+        SHARED.get_or_init(|| Arc::new(Self::load())).clone()
+    }
+
+    /// Load the databases of the package managers found on the host. Errors
+    /// reading one of them are logged and its packages are skipped.
+    pub fn load() -> Self {
+        let mut db = Self::default();
+        if Path::new(DPKG_PATH).exists() {
+            if let Err(err) = db.load_dpkg(Path::new(DPKG_PATH)) {
+                log::warn!("Error reading the dpkg database: {err}");
+            }
+        }
+        if Path::new(APK_INSTALLED_PATH).exists() {
+            match fs::read_to_string(APK_INSTALLED_PATH) {
+                Ok(installed) => db.add_apk(&installed),
+                Err(err) => log::warn!("Error reading the apk database: {err}"),
+            }
+        }
+        if RPM_DB_PATHS.iter().any(|path| Path::new(path).exists()) {
+            match query_rpm() {
+                Ok(output) => db.add_rpm(&output),
+                Err(err) => log::warn!("Error querying the rpm database: {err}"),
+            }
+        }
+        log::debug!("Loaded {} files of installed packages", db.files.len());
+        db
+    }
+
+    /// Return the package file at exactly `path`, without resolving symlinks.
+    pub fn file(&self, path: &Path) -> Option<&PackageFile> {
+        self.files.get(path.to_string_lossy().as_ref())
+    }
+
+    /// Return the package file at `path`, or at its canonical path if it's a
+    /// symlink or inside a symlinked directory, like `/bin` in distributions
+    /// with merged `/usr`. The canonical path is returned too.
+    pub fn get(&self, path: &Path) -> Option<(PathBuf, &PackageFile)> {
+        if let Some(file) = self.files.get(path.to_string_lossy().as_ref()) {
+            return Some((path.to_path_buf(), file));
+        }
+        let canonical = fs::canonicalize(path).ok()?;
+        let file = self.files.get(canonical.to_string_lossy().as_ref())?;
+        Some((canonical, file))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub(crate) fn insert(&mut self, path: String, package: &Arc<Package>, digest: Option<Digest>) {
+        self.files.insert(
+            path,
+            PackageFile {
+                package: package.clone(),
+                digest,
+            },
+        );
+    }
+
+    fn load_dpkg(&mut self, dpkg_path: &Path) -> io::Result<()> {
+        let packages = dpkg_packages(&fs::read_to_string(dpkg_path.join("status"))?);
+        for entry in fs::read_dir(dpkg_path.join("info"))? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "list") {
+                continue;
+            }
+            // Named `<package>:<arch>.list` for the packages installed for
+            // several architectures
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = stem.split(':').next().unwrap_or_default();
+            let Some(package) = packages.get(name) else {
+                continue;
+            };
+            let package = Arc::new(package.clone());
+            let md5sums = fs::read_to_string(path.with_extension("md5sums")).unwrap_or_default();
+            let mut digests = parse_md5sums(&md5sums);
+            for file in fs::read_to_string(&path)?.lines() {
+                let digest = digests.remove(file);
+                self.insert(file.to_string(), &package, digest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the packages of an apk `installed` database, where every package is
+    /// a paragraph of `<field>:<value>` lines, with a file `R` following its
+    /// directory `F` and followed by its digest `Z`. The origin `o` is the
+    /// source package.
+    fn add_apk(&mut self, installed: &str) {
+        for paragraph in installed.split("\n\n") {
+            let field = |name: &str| {
+                paragraph
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                    .unwrap_or_default()
+            };
+            let mut package = Package::new(field("P"), field("V"));
+            if package.name.is_empty() {
+                continue;
+            }
+            if !field("o").is_empty() {
+                package.source = field("o").to_string();
+            }
+            let package = Arc::new(package);
+            let mut directory = "";
+            let mut file: Option<String> = None;
+            for line in paragraph.lines() {
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                match key {
+                    "F" => directory = value,
+                    "R" => {
+                        if let Some(file) = file.take() {
+                            self.insert(file, &package, None);
+                        }
+                        file = Some(if directory.is_empty() {
+                            format!("/{value}")
+                        } else {
+                            format!("/{directory}/{value}")
+                        });
+                    }
+                    // SHA-1 in base64 with the `Q1` prefix
+                    "Z" => {
+                        if let Some(file) = file.take() {
+                            let digest =
+                                value
+                                    .strip_prefix("Q1")
+                                    .and_then(decode_base64)
+                                    .map(|value| Digest {
+                                        algorithm: Algorithm::Sha1,
+                                        value,
+                                    });
+                            self.insert(file, &package, digest);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(file) = file {
+                self.insert(file, &package, None);
+            }
+        }
+    }
+
+    /// Add the files printed by [`RPM_QUERY_FORMAT`].
+    fn add_rpm(&mut self, output: &str) {
+        let mut packages: HashMap<(&str, &str), Arc<Package>> = HashMap::new();
+        for line in output.lines() {
+            let mut fields = line.splitn(6, ' ');
+            let (
+                Some(name),
+                Some(version),
+                Some(source_rpm),
+                Some(algorithm),
+                Some(digest),
+                Some(path),
+            ) = (
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+            )
+            else {
+                continue;
+            };
+            let package = packages
+                .entry((name, version))
+                .or_insert_with(|| {
+                    let mut package = Package::new(name, version);
+                    if let Some(source) = rpm_source_name(source_rpm) {
+                        package.source = source.to_string();
+                    }
+                    Arc::new(package)
+                })
+                .clone();
+            // Values of `PGPHASHALGO`, see rpmpgp.h
+            let algorithm = match algorithm {
+                "1" => Some(Algorithm::Md5),
+                "2" => Some(Algorithm::Sha1),
+                "8" => Some(Algorithm::Sha256),
+                _ => None,
+            };
+            let digest = algorithm.and_then(|algorithm| Digest::from_hex(algorithm, digest));
+            self.insert(
+                path.to_string(),
+                &package,
+                digest.filter(|d| !d.value.is_empty()),
+            );
+        }
+    }
+}
+
+/// Installed packages of the dpkg `status` file, made of a paragraph of
+/// `<field>: <value>` lines for every package. `Source` is missing when equal
+/// to the package, and followed by its version in parentheses when different.
+fn dpkg_packages(status: &str) -> HashMap<String, Package> {
+    status
+        .split("\n\n")
+        .filter_map(|paragraph| {
+            let field = |name: &str| {
+                paragraph.lines().find_map(|line| {
+                    line.strip_prefix(name)?
+                        .strip_prefix(':')
+                        .map(|value| value.trim())
+                })
+            };
+            if !field("Status")?.ends_with(" installed") {
+                return None;
+            }
+            let name = field("Package")?;
+            let mut package = Package::new(name, field("Version")?);
+            if let Some(source) = field("Source") {
+                let (source, version) = match source.split_once(" (") {
+                    Some((source, version)) => (source, version.strip_suffix(')')),
+                    None => (source, None),
+                };
+                package.source = source.to_string();
+                if let Some(version) = version {
+                    package.source_version = version.to_string();
+                }
+            }
+            Some((name.to_string(), package))
+        })
+        .collect()
+}
+
+/// Name of the source package of an rpm, from its file name like
+/// `bash-5.2.15-1.fc38.src.rpm`. `(none)` for packages without source, like
+/// the GPG keys.
+fn rpm_source_name(source_rpm: &str) -> Option<&str> {
+    let stem = source_rpm
+        .strip_suffix(".src.rpm")
+        .or_else(|| source_rpm.strip_suffix(".nosrc.rpm"))?;
+    // Strip the release, then the version
+    let (stem, _release) = stem.rsplit_once('-')?;
+    let (name, _version) = stem.rsplit_once('-')?;
+    Some(name)
+}
+
+/// Parse the `<md5>  <path>` lines of a dpkg `md5sums` file, where the paths
+/// are relative to the root.
+fn parse_md5sums(md5sums: &str) -> HashMap<String, Digest> {
+    md5sums
+        .lines()
+        .filter_map(|line| {
+            let (md5, path) = line.split_once("  ")?;
+            let digest = Digest::from_hex(Algorithm::Md5, md5)?;
+            Some((format!("/{}", path.trim_start_matches('/')), digest))
+        })
+        .collect()
+}
+
+fn query_rpm() -> io::Result<String> {
+    let output = Command::new("rpm")
+        .args(["-qa", "--queryformat", RPM_QUERY_FORMAT])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Identifies a version of a file, changing when it's replaced or modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileVersion {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+}
+
+impl From<&fs::Metadata> for FileVersion {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(db: &PackageDb, path: &str) -> Option<(String, String, Option<String>)> {
+        let file = db.files.get(path)?;
+        Some((
+            file.package.name.clone(),
+            file.package.version.clone(),
+            file.digest.as_ref().map(|digest| digest.to_string()),
+        ))
+    }
+
+    #[test]
+    fn dpkg() {
+        let dir = std::env::temp_dir().join(format!("dpkg-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("info")).unwrap();
+        fs::write(
+            dir.join("status"),
+            "Package: coreutils\nStatus: install ok installed\nVersion: 9.1-1\n\n\
+             Package: libssl3\nStatus: install ok installed\nSource: openssl (3.0.11-1)\n\
+             Version: 3.0.11-1+b1\n\n\
+             Package: removed\nStatus: deinstall ok config-files\nVersion: 1.0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("info/coreutils.list"),
+            "/.\n/usr/bin\n/usr/bin/ls\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("info/coreutils.md5sums"),
+            "d41d8cd98f00b204e9800998ecf8427e  usr/bin/ls\n",
+        )
+        .unwrap();
+        fs::write(dir.join("info/removed.list"), "/usr/bin/removed\n").unwrap();
+        fs::write(
+            dir.join("info/libssl3:amd64.list"),
+            "/usr/lib/x86_64-linux-gnu/libssl.so.3\n",
+        )
+        .unwrap();
+
+        let mut db = PackageDb::default();
+        db.load_dpkg(&dir).unwrap();
+        assert_eq!(
+            package(&db, "/usr/bin/ls"),
+            Some((
+                "coreutils".to_string(),
+                "9.1-1".to_string(),
+                Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+            ))
+        );
+        assert_eq!(package(&db, "/usr/bin").unwrap().2, None);
+        assert_eq!(package(&db, "/usr/bin/removed"), None);
+        assert_eq!(db.files["/usr/bin/ls"].package.source, "coreutils");
+        let libssl = &db.files["/usr/lib/x86_64-linux-gnu/libssl.so.3"].package;
+        assert_eq!(
+            (libssl.source.as_str(), libssl.source_version.as_str()),
+            ("openssl", "3.0.11-1")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn apk() {
+        let mut db = PackageDb::default();
+        db.add_apk(
+            "C:Q1abc=\nP:busybox\nV:1.36.1-r5\nF:bin\nR:busybox\nZ:Q1qZk+NkcGgWq6PiVxeFDCbJzQ2J0=\n\
+             R:sh\n\nP:musl\nV:1.2.4-r2\nF:lib\nR:libc.musl-x86_64.so.1\n\n\
+             P:libcrypto3\nV:3.1.4-r5\no:openssl\nF:lib\nR:libcrypto.so.3\n",
+        );
+        assert_eq!(
+            package(&db, "/bin/busybox"),
+            Some((
+                "busybox".to_string(),
+                "1.36.1-r5".to_string(),
+                Some("a9993e364706816aba3e25717850c26c9cd0d89d".to_string())
+            ))
+        );
+        assert_eq!(package(&db, "/bin/sh").unwrap().2, None);
+        assert_eq!(
+            package(&db, "/lib/libc.musl-x86_64.so.1").unwrap().0,
+            "musl"
+        );
+        assert_eq!(db.files["/bin/busybox"].package.source, "busybox");
+        assert_eq!(db.files["/lib/libcrypto.so.3"].package.source, "openssl");
+    }
+
+    #[test]
+    fn rpm() {
+        let mut db = PackageDb::default();
+        db.add_rpm(
+            "bash 5.2.15-1.fc38 bash-5.2.15-1.fc38.src.rpm 8 ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad /usr/bin/bash\n\
+             bash 5.2.15-1.fc38 bash-5.2.15-1.fc38.src.rpm 8  /usr/share/doc/bash\n\
+             bash 5.2.15-1.fc38 bash-5.2.15-1.fc38.src.rpm 8 ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad /usr/share/with space\n\
+             openssl-libs 3.0.9-2.fc38 openssl-3.0.9-2.fc38.src.rpm 8  /usr/lib64/libssl.so.3\n\
+             gpg-pubkey 18b8e74c-62f2920f (none) 0  /\n",
+        );
+        assert_eq!(package(&db, "/usr/bin/bash").unwrap().1, "5.2.15-1.fc38");
+        assert_eq!(package(&db, "/usr/share/doc/bash").unwrap().2, None);
+        assert!(package(&db, "/usr/share/with space").is_some());
+        assert!(Arc::ptr_eq(
+            &db.files["/usr/bin/bash"].package,
+            &db.files["/usr/share/doc/bash"].package
+        ));
+        assert_eq!(db.files["/usr/bin/bash"].package.source, "bash");
+        assert_eq!(db.files["/usr/lib64/libssl.so.3"].package.source, "openssl");
+        assert_eq!(db.files["/"].package.source, "gpg-pubkey");
+    }
+}
@@ -7,8 +7,14 @@ use std::{
     str::FromStr,
 };
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::suggest::closest;
+
+/// Configuration field handled by the daemon for every module.
+const ENABLED_FIELD: &str = "enabled";
+
 /// Per module configuration
 #[derive(Debug, Clone, Default)]
 pub struct ModuleConfig {
@@ -25,6 +31,18 @@ pub enum ConfigError {
         value: String,
         err: String,
     },
+    #[error("unknown field {field}{}", .suggestion.as_ref().map(|s| format!(", did you mean {s}?")).unwrap_or_default())]
+    UnknownField {
+        field: String,
+        suggestion: Option<String>,
+    },
+    #[error("{value} is out of range for field {field}: expected value between {min} and {max}")]
+    OutOfRange {
+        field: String,
+        value: i64,
+        min: i64,
+        max: i64,
+    },
 }
 
 impl ModuleConfig {
@@ -119,3 +137,213 @@ where
         err: err.to_string(),
     })
 }
+
+/// Typed declaration of the configuration accepted by a module.
+///
+/// The schema documents every field and it's used by the daemon to validate
+/// the module configuration on startup and on every update.
+/// The `enabled` field is handled by the daemon and it's always accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSchema {
+    /// Schema version, to be increased on incompatible changes.
+    pub version: u32,
+    pub fields: Vec<ConfigField>,
+}
+
+/// Declaration of a single configuration field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigField {
+    pub name: String,
+    pub kind: ConfigKind,
+    /// Default value, documentation only.
+    pub default: Option<String>,
+    /// Inclusive range of valid values for [`ConfigKind::Integer`] fields.
+    pub range: Option<(i64, i64)>,
+    pub doc: String,
+}
+
+/// Type of a configuration field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigKind {
+    Bool,
+    Integer,
+    String,
+    Path,
+    /// Comma separated list of values.
+    List,
+    /// One of the given values.
+    Choice(Vec<String>),
+}
+
+impl ConfigSchema {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Adds a field to the schema.
+    pub fn field(mut self, field: ConfigField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Adds a list of fields to the schema, useful for fields shared between modules.
+    pub fn extend(mut self, fields: impl IntoIterator<Item = ConfigField>) -> Self {
+        self.fields.extend(fields);
+        self
+    }
+
+    /// Check that the configuration contains only known fields with valid values.
+    pub fn validate(&self, config: &ModuleConfig) -> Result<(), ConfigError> {
+        let mut keys: Vec<_> = config.iter().collect();
+        keys.sort();
+
+        for (key, value) in keys {
+            if key == ENABLED_FIELD {
+                parse::<bool>(value, key)?;
+                continue;
+            }
+
+            match self.fields.iter().find(|field| field.name == *key) {
+                Some(field) => field.validate(value)?,
+                None => {
+                    let names = self.fields.iter().map(|field| field.name.as_str());
+                    return Err(ConfigError::UnknownField {
+                        field: key.clone(),
+                        suggestion: closest(key, names).map(str::to_string),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ConfigField {
+    pub fn new(name: &str, kind: ConfigKind, doc: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            default: None,
+            range: None,
+            doc: doc.to_string(),
+        }
+    }
+
+    /// Set the documented default value.
+    pub fn default_value<T: Display>(mut self, default: T) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+
+    /// Set the inclusive range of valid values.
+    pub fn range(mut self, min: i64, max: i64) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    fn validate(&self, value: &str) -> Result<(), ConfigError> {
+        match &self.kind {
+            ConfigKind::Bool => {
+                parse::<bool>(value, &self.name)?;
+            }
+            ConfigKind::Integer => {
+                let number = parse::<i64>(value, &self.name)?;
+                if let Some((min, max)) = self.range {
+                    if number < min || number > max {
+                        return Err(ConfigError::OutOfRange {
+                            field: self.name.clone(),
+                            value: number,
+                            min,
+                            max,
+                        });
+                    }
+                }
+            }
+            ConfigKind::Choice(choices) => {
+                if !choices.iter().any(|choice| choice == value) {
+                    return Err(ConfigError::InvalidValue {
+                        field: self.name.clone(),
+                        value: value.to_string(),
+                        err: format!("expected one of {}", choices.join(", ")),
+                    });
+                }
+            }
+            ConfigKind::String | ConfigKind::Path | ConfigKind::List => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ConfigSchema {
+        ConfigSchema::new(1)
+            .field(ConfigField::new(
+                "rules_path",
+                ConfigKind::Path,
+                "Rules folder",
+            ))
+            .field(
+                ConfigField::new("interval", ConfigKind::Integer, "Interval in seconds")
+                    .default_value(300)
+                    .range(0, 3600),
+            )
+            .field(ConfigField::new(
+                "mode",
+                ConfigKind::Choice(vec!["fast".to_string(), "slow".to_string()]),
+                "Mode",
+            ))
+    }
+
+    fn config(values: &[(&str, &str)]) -> ModuleConfig {
+        let mut config = ModuleConfig::default();
+        for (key, value) in values {
+            config.insert(key.to_string(), value.to_string());
+        }
+        config
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = config(&[
+            ("enabled", "true"),
+            ("rules_path", "/tmp"),
+            ("interval", "10"),
+            ("mode", "fast"),
+        ]);
+        assert!(schema().validate(&config).is_ok());
+    }
+
+    #[test]
+    fn unknown_field() {
+        let error = schema()
+            .validate(&config(&[("rule_path", "/tmp")]))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown field rule_path, did you mean rules_path?"
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(matches!(
+            schema().validate(&config(&[("interval", "7200")])),
+            Err(ConfigError::OutOfRange { value: 7200, .. })
+        ));
+        assert!(matches!(
+            schema().validate(&config(&[("interval", "ten")])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            schema().validate(&config(&[("mode", "medium")])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::{mpsc, oneshot};
 
-use super::ModuleConfig;
+use super::{ConfigError, ConfigSchema, ModuleConfig};
 
 /// Error happening during daemon administration.
 #[derive(Error, Debug)]
@@ -16,6 +16,10 @@ pub enum PulsarDaemonError {
     StopError(String),
     #[error("error updating the configuration")]
     ConfigurationUpdateError(#[from] anyhow::Error),
+    #[error("invalid configuration: {0}")]
+    InvalidConfiguration(ConfigError),
+    #[error("module {module} depends on {dependency}, which is not running")]
+    DependencyNotRunning { module: String, dependency: String },
 }
 
 /// Handle to a running PulsarDaemon.
@@ -136,6 +140,19 @@ impl PulsarDaemonHandle {
         recv.await.expect("Actor task has been killed")
     }
 
+    /// Notify the daemon that the system boot is completed, starting the
+    /// modules deferred in early boot mode.
+    pub async fn boot_complete(&self) {
+        let (send, recv) = oneshot::channel();
+        let msg = PulsarDaemonCommand::BootComplete { tx_reply: send };
+
+        // Ignore send errors. If this send fails, so does the
+        // recv.await below. There's no reason to check the
+        // failure twice.
+        let _ = self.tx_cmd.send(msg).await;
+        recv.await.expect("Actor task has been killed")
+    }
+
     pub async fn get_configurations(&self) -> Vec<(String, ModuleConfig)> {
         let (send, recv) = oneshot::channel();
         let msg = PulsarDaemonCommand::Configs { tx_reply: send };
@@ -203,6 +220,9 @@ pub enum PulsarDaemonCommand {
     Configs {
         tx_reply: oneshot::Sender<Vec<(String, ModuleConfig)>>,
     },
+    BootComplete {
+        tx_reply: oneshot::Sender<()>,
+    },
 }
 
 /// Overview of loaded module.
@@ -211,4 +231,9 @@ pub struct ModuleOverview {
     pub name: String,
     pub version: Version,
     pub status: ModuleStatus,
+    #[serde(default)]
+    pub config_schema: Option<ConfigSchema>,
+    /// The module loads eBPF probes
+    #[serde(default)]
+    pub uses_ebpf: bool,
 }
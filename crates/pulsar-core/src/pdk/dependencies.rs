@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use super::{ModuleName, TaskLauncher};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DependencyError {
+    #[error("module {module} depends on {dependency}, which is not loaded")]
+    Missing {
+        module: ModuleName,
+        dependency: ModuleName,
+    },
+    #[error("circular dependency between modules: {}", .0.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(", "))]
+    Cycle(Vec<ModuleName>),
+}
+
+/// Sort the modules so that every module comes after its dependencies.
+///
+/// Modules without a dependency relation keep their original order.
+pub fn startup_order(
+    modules: Vec<Box<dyn TaskLauncher>>,
+) -> Result<Vec<Box<dyn TaskLauncher>>, DependencyError> {
+    let names: HashSet<ModuleName> = modules.iter().map(|m| m.name().clone()).collect();
+    for module in &modules {
+        if let Some(dependency) = module
+            .details()
+            .dependencies
+            .iter()
+            .find(|dependency| !names.contains(*dependency))
+        {
+            return Err(DependencyError::Missing {
+                module: module.name().clone(),
+                dependency: dependency.clone(),
+            });
+        }
+    }
+
+    let mut started: HashSet<ModuleName> = HashSet::new();
+    let mut pending = modules;
+    let mut sorted = Vec::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|module| {
+            module
+                .details()
+                .dependencies
+                .iter()
+                .all(|dependency| started.contains(dependency))
+        });
+
+        match ready {
+            Some(index) => {
+                let module = pending.remove(index);
+                started.insert(module.name().clone());
+                sorted.push(module);
+            }
+            None => {
+                return Err(DependencyError::Cycle(
+                    pending.iter().map(|module| module.name().clone()).collect(),
+                ))
+            }
+        }
+    }
+
+    Ok(sorted)
+}
+
+/// The modules in `names` and the ones depending on them, directly or not.
+///
+/// `modules` must be in [`startup_order`]. Used in early boot mode, where the
+/// dependents of a deferred module are deferred too, to start after it.
+pub fn with_dependents(
+    modules: &[Box<dyn TaskLauncher>],
+    names: &HashSet<ModuleName>,
+) -> HashSet<ModuleName> {
+    let mut selected = names.clone();
+    for module in modules {
+        if module
+            .details()
+            .dependencies
+            .iter()
+            .any(|dependency| selected.contains(dependency))
+        {
+            selected.insert(module.name().clone());
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::*;
+    use crate::pdk::{CleanExit, ModuleContext, ModuleError, PulsarModule, ShutdownSignal};
+
+    /// Module doing nothing until it's stopped.
+    async fn task(
+        _: ModuleContext,
+        mut shutdown: ShutdownSignal,
+    ) -> Result<CleanExit, ModuleError> {
+        shutdown.recv().await
+    }
+
+    fn module(name: &'static str, dependencies: &[&'static str]) -> Box<dyn TaskLauncher> {
+        let module = dependencies.iter().fold(
+            PulsarModule::new(name, Version::new(0, 1, 0), true, task),
+            |module, dependency| module.depends_on(*dependency),
+        );
+        Box::new(module)
+    }
+
+    fn names(modules: &[Box<dyn TaskLauncher>]) -> Vec<&str> {
+        modules.iter().map(|m| &**m.name()).collect()
+    }
+
+    #[test]
+    fn dependencies_first() {
+        let modules = vec![
+            module("rules-engine", &["enrichment"]),
+            module("logger", &[]),
+            module("enrichment", &["process-monitor"]),
+            module("process-monitor", &[]),
+        ];
+        assert_eq!(
+            names(&startup_order(modules).unwrap()),
+            vec!["logger", "process-monitor", "enrichment", "rules-engine"]
+        );
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let modules = vec![module("enrichment", &["process-monitor"])];
+        assert_eq!(
+            startup_order(modules).err(),
+            Some(DependencyError::Missing {
+                module: "enrichment".into(),
+                dependency: "process-monitor".into()
+            })
+        );
+    }
+
+    #[test]
+    fn dependents() {
+        let modules = startup_order(vec![
+            module("rules-engine", &["enrichment"]),
+            module("logger", &[]),
+            module("enrichment", &["process-monitor"]),
+            module("process-monitor", &[]),
+            module("threat-response", &["rules-engine"]),
+        ])
+        .unwrap();
+        let mut deferred: Vec<_> = with_dependents(&modules, &HashSet::from(["enrichment".into()]))
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        deferred.sort();
+        assert_eq!(
+            deferred,
+            vec!["enrichment", "rules-engine", "threat-response"]
+        );
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let modules = vec![module("a", &["b"]), module("b", &["a"]), module("c", &[])];
+        assert_eq!(
+            startup_order(modules).err(),
+            Some(DependencyError::Cycle(vec!["a".into(), "b".into()]))
+        );
+    }
+}
@@ -34,6 +34,7 @@
 //!     PulsarModule::new(
 //!         "my-module",
 //!         Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+//!         true,
 //!         my_module_task,
 //!     )
 //! }
@@ -61,15 +62,20 @@
 
 mod config;
 mod daemon;
+mod dependencies;
 mod module;
 mod module_context;
+pub mod policy;
 pub mod process_tracker;
+pub mod secrets;
+pub mod tls;
 
 pub use crate::bus::BusError;
 pub use crate::event::Event;
 pub use crate::event::Payload;
 pub use config::*;
 pub use daemon::*;
+pub use dependencies::*;
 pub use module::*;
 pub use module_context::*;
 pub use semver::Version;
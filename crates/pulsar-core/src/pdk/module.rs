@@ -1,11 +1,16 @@
-use std::{borrow::Cow, fmt, future::Future, ops::Deref, sync::Arc, time::UNIX_EPOCH};
+use std::{
+    borrow::Cow, collections::VecDeque, fmt, future::Future, ops::Deref, sync::Arc,
+    time::UNIX_EPOCH,
+};
 
 use crate::{
-    bus::{Bus, BusError},
-    event::{Event, Header, Payload, Threat, Value},
+    acknowledgments::threat_id,
+    bus::{Bus, BusError, ReceiverPosition},
+    event::{next_event_id, Event, Header, Payload, PayloadDiscriminant, Severity, Threat, Value},
+    host::host_info,
 };
 use anyhow::Result;
-use bpf_common::{program::BpfEvent, time::Timestamp, Pid};
+use bpf_common::{program::BpfEvent, storm::EventStorm, time::Timestamp, Pid};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{
@@ -15,8 +20,9 @@ use tokio::sync::{
 use validatron::Validatron;
 
 use super::{
-    process_tracker::{ProcessInfo, ProcessTrackerHandle},
-    CleanExit, ModuleContext, ShutdownSignal,
+    policy::ThreatRouting,
+    process_tracker::{exec_chain_hash, ProcessInfo, ProcessTrackerHandle},
+    CleanExit, ConfigSchema, ModuleContext, ShutdownSignal,
 };
 
 pub type PulsarModuleTask = dyn Future<Output = Result<CleanExit, ModuleError>> + Send;
@@ -52,6 +58,10 @@ impl PulsarModule {
             info: ModuleDetails {
                 version,
                 enabled_by_default,
+                config_schema: None,
+                dependencies: Vec::new(),
+                uses_ebpf: false,
+                acts_on_host: false,
             },
             task_start_fn: Box::new(move |ctx, shutdown| {
                 let module = task_start_fn(ctx, shutdown);
@@ -59,6 +69,37 @@ impl PulsarModule {
             }),
         }
     }
+
+    /// Declare the configuration accepted by the module.
+    pub fn with_config_schema(mut self, config_schema: ConfigSchema) -> Self {
+        self.info.config_schema = Some(config_schema);
+        self
+    }
+
+    /// Declare a module which must be running for this module to work.
+    ///
+    /// The daemon starts dependencies first and refuses to start with the
+    /// dependency missing or disabled.
+    pub fn depends_on<N: Into<ModuleName>>(mut self, module: N) -> Self {
+        self.info.dependencies.push(module.into());
+        self
+    }
+
+    /// Declare that the module loads eBPF probes, so that it's not started
+    /// when replaying captured events.
+    pub fn uses_ebpf(mut self) -> Self {
+        self.info.uses_ebpf = true;
+        self
+    }
+
+    /// Declare that the module changes the host, like killing processes or
+    /// adding firewall rules, so that it's not started when replaying
+    /// captured events: their processes and addresses are not the ones of
+    /// the host running the replay.
+    pub fn acts_on_host(mut self) -> Self {
+        self.info.acts_on_host = true;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Hash)]
@@ -95,14 +136,9 @@ impl Validatron for ModuleName {
         Self::class_builder().primitive(
             Box::new(|s| Ok(ModuleName(Cow::Owned(s.to_string())))),
             Box::new(|op| match op {
-                validatron::Operator::String(op) => match op {
-                    validatron::StringOperator::StartsWith => {
-                        Ok(Box::new(|a, b| a.0.as_ref().starts_with(b.0.as_ref())))
-                    }
-                    validatron::StringOperator::EndsWith => {
-                        Ok(Box::new(|a, b| a.0.as_ref().ends_with(b.0.as_ref())))
-                    }
-                },
+                validatron::Operator::String(op) => {
+                    Ok(Box::new(move |a, b| op.apply(a.0.as_ref(), b.0.as_ref())))
+                }
                 validatron::Operator::Relational(op) => Ok(Box::new(move |a, b| op.apply(a, b))),
                 _ => Err(validatron::ValidatronError::OperatorNotAllowedOnType(
                     op,
@@ -132,6 +168,10 @@ impl TaskLauncher for PulsarModule {
 pub struct ModuleDetails {
     pub version: Version,
     pub enabled_by_default: bool,
+    pub config_schema: Option<ConfigSchema>,
+    pub dependencies: Vec<ModuleName>,
+    pub uses_ebpf: bool,
+    pub acts_on_host: bool,
     // pub author: String,
 }
 
@@ -174,15 +214,47 @@ pub enum ModuleSignal {
 }
 
 impl ModuleSender {
+    /// Constructs a sender of events from `module_name`, usually taken from
+    /// [`ModuleContext::get_sender`].
+    pub fn new(
+        tx: Bus,
+        module_name: ModuleName,
+        process_tracker: ProcessTrackerHandle,
+        signal_sender: SignalSender,
+    ) -> Self {
+        Self {
+            tx,
+            module_name,
+            process_tracker,
+            signal_sender,
+        }
+    }
+
     /// Send an event to the [`Bus`].
     pub fn send(&self, process: Pid, timestamp: Timestamp, payload: Payload) {
         self.send_internal(process, timestamp, payload, None)
     }
 
+    /// Like [`ModuleSender::send`], but the event is sent by the returned
+    /// future instead of a new task: the events sent by awaiting it one after
+    /// the other reach the [`Bus`] in the same order. The lookup of a process
+    /// unknown to the tracker can take up to 100ms.
+    pub async fn send_ordered(&self, process: Pid, timestamp: Timestamp, payload: Payload) {
+        let event = self.event(process, timestamp, payload, None).await;
+        let _ = self.tx.send(event);
+    }
+
     /// Send an event which was caused by another event to the [`Bus`].
-    /// The new event shares the source headers, but has a new payload.
+    /// The new event shares the source headers, but has a new payload and
+    /// references the source with `parent_event_id`. The threat of the source
+    /// event, if any, is not inherited.
     pub fn send_derived(&self, source: &Event, payload: Payload) {
-        let header = source.header.clone();
+        let header = Header {
+            id: next_event_id(),
+            parent_event_id: Some(source.header.id),
+            threat: None,
+            ..source.header.clone()
+        };
         let _ = self.tx.send(Event { header, payload });
     }
 
@@ -192,32 +264,81 @@ impl ModuleSender {
         process: Pid,
         timestamp: Timestamp,
         description: String,
+        severity: Severity,
         extra: Option<Value>,
     ) {
         let threat = Threat {
+            id: String::new(),
             source: self.module_name.clone(),
             description,
+            severity,
+            outputs: None,
             extra,
+            evidence: None,
         };
         self.send_internal(process, timestamp, Payload::Empty, Some(threat))
     }
 
+    /// Like [`ModuleSender::send_threat`], with a payload describing what
+    /// happened, like the connection to a honeypot. Its `id` and `source` are
+    /// set by the sender.
+    pub fn send_threat_payload(
+        &self,
+        process: Pid,
+        timestamp: Timestamp,
+        payload: Payload,
+        mut threat: Threat,
+    ) {
+        threat.source = self.module_name.clone();
+        self.send_internal(process, timestamp, payload, Some(threat))
+    }
+
     /// Send a threat event which was caused by another event to the [`Bus`].
-    /// The new event shares the source headers and the payload.
+    /// The new event shares the source headers and the payload, and references
+    /// the source with `parent_event_id`.
     pub fn send_threat_derived(
         &self,
         source_event: &Event,
         description: String,
+        severity: Severity,
+        extra: Option<Value>,
+    ) {
+        self.send_threat_derived_to(source_event, description, severity, None, extra)
+    }
+
+    /// Like [`ModuleSender::send_threat_derived`], with the `outputs` handling
+    /// the threat instead of the ones of its severity in the escalation policy.
+    pub fn send_threat_derived_to(
+        &self,
+        source_event: &Event,
+        description: String,
+        severity: Severity,
+        outputs: Option<Vec<String>>,
         extra: Option<Value>,
     ) {
         let threat = Threat {
+            id: String::new(),
             source: self.module_name.clone(),
             description,
+            severity,
+            outputs,
             extra,
+            evidence: None,
         };
+        self.send_derived_threat(source_event, threat)
+    }
+
+    /// Send a threat built by the caller, like one with [`crate::event::Evidence`],
+    /// which was caused by another event to the [`Bus`]. Its `id` and `source`
+    /// are set by the sender, see [`ModuleSender::send_threat_derived`].
+    pub fn send_derived_threat(&self, source_event: &Event, mut threat: Threat) {
+        threat.source = self.module_name.clone();
+        threat.id = threat_id(&threat, &source_event.header.image);
 
         let _ = self.tx.send(Event {
             header: Header {
+                id: next_event_id(),
+                parent_event_id: Some(source_event.header.id),
                 threat: Some(threat),
                 ..source_event.header.clone()
             },
@@ -233,45 +354,71 @@ impl ModuleSender {
         payload: Payload,
         threat: Option<Threat>,
     ) {
-        let tx = self.tx.clone();
-        let process_tracker = self.process_tracker.clone();
-        let module_name = self.module_name.clone();
+        let sender = self.clone();
         tokio::spawn(async move {
-            let mut header = Header {
-                source: module_name.clone(),
-                threat,
-                pid: process.as_raw(),
-                timestamp: timestamp.into(),
-                image: String::new(),
-                parent_pid: 0,
-                fork_time: UNIX_EPOCH,
-            };
-            match process_tracker.get(process, timestamp).await {
-                Ok(ProcessInfo {
-                    image,
-                    ppid,
-                    fork_time,
-                    argv: _,
-                    namespaces: _,
-                }) => {
-                    header.image = image;
-                    header.parent_pid = ppid.as_raw();
-                    header.fork_time = fork_time.into();
-                }
-                Err(e) => {
-                    // warning: check if this actually happens or not
-                    log::error!(
-                        target: &module_name,
-                        "Process not found in tracker {process}: {e}"
-                    );
-                }
-            }
-            // get details from process tracker
-            let event = Event { header, payload };
-            tx.send(event)
+            let event = sender.event(process, timestamp, payload, threat).await;
+            let _ = sender.tx.send(event);
         });
     }
 
+    /// Build the event of a [`Payload`], with the details of its process
+    /// from the process tracker.
+    async fn event(
+        &self,
+        process: Pid,
+        timestamp: Timestamp,
+        payload: Payload,
+        threat: Option<Threat>,
+    ) -> Event {
+        let mut header = Header {
+            id: next_event_id(),
+            parent_event_id: None,
+            source: self.module_name.clone(),
+            threat,
+            pid: process.as_raw(),
+            timestamp: timestamp.into(),
+            raw_timestamp: timestamp.raw(),
+            image: String::new(),
+            parent_pid: 0,
+            fork_time: UNIX_EPOCH,
+            exec_chain: Vec::new(),
+            exec_chain_hash: String::new(),
+            is_interactive: false,
+            sandboxed_runtime: String::new(),
+            host: host_info(),
+            coalesced: None,
+        };
+        match self.process_tracker.get(process, timestamp).await {
+            Ok(ProcessInfo {
+                image,
+                ppid,
+                fork_time,
+                argv: _,
+                namespaces: _,
+                exec_chain,
+                is_interactive,
+            }) => {
+                header.is_interactive = is_interactive;
+                header.image = image;
+                header.parent_pid = ppid.as_raw();
+                header.fork_time = fork_time.into();
+                header.exec_chain_hash = exec_chain_hash(&exec_chain);
+                header.exec_chain = exec_chain;
+            }
+            Err(e) => {
+                // warning: check if this actually happens or not
+                log::error!(
+                    target: &self.module_name,
+                    "Process not found in tracker {process}: {e}"
+                );
+            }
+        }
+        if let Some(threat) = &mut header.threat {
+            threat.id = threat_id(threat, &header.image);
+        }
+        Event { header, payload }
+    }
+
     pub fn raise_error(&self, err: ModuleError) {
         // We don't want to make raise_error async, so we use try_send and ignore
         // the error. Since errors are fatal, it's not a problem to lose one when
@@ -316,6 +463,32 @@ impl<T: IntoPayload> bpf_common::BpfSender<T> for ModuleSender {
             }
         }
     }
+
+    fn send_storm(&mut self, data: BpfEvent<T>, storm: EventStorm) {
+        let pid = data.pid;
+        let timestamp = data.timestamp;
+        let payload = match IntoPayload::try_into_payload(data) {
+            Ok(payload) => payload,
+            Err(e) => return self.raise_error(Box::new(e)),
+        };
+        let payload_type = format!("{:?}", PayloadDiscriminant::from(&payload));
+        log::warn!(
+            target: &self.module_name,
+            "Event storm of pid {pid}: {payload_type} events muted for {:?}",
+            storm.muted_for
+        );
+        ModuleSender::send(self, pid, timestamp, payload);
+        ModuleSender::send(
+            self,
+            pid,
+            timestamp,
+            Payload::EventStorm {
+                payload_type,
+                events: storm.events,
+                muted_secs: storm.muted_for.as_secs(),
+            },
+        );
+    }
 }
 
 /// Used to receive events from outside of a module.
@@ -324,13 +497,60 @@ impl<T: IntoPayload> bpf_common::BpfSender<T> for ModuleSender {
 #[derive(Debug)]
 pub struct ModuleReceiver {
     pub(crate) rx: broadcast::Receiver<Arc<Event>>,
+    /// Events sent before the receiver was created, kept by the [`Bus`] early buffer
+    pub(crate) backlog: VecDeque<Arc<Event>>,
     pub(crate) module_name: ModuleName,
+    /// Progress checked by the watchdog
+    pub(crate) position: Arc<ReceiverPosition>,
+    /// Threats routing of output modules, see [`ModuleContext::get_output_receiver`]
+    pub(crate) routing: Option<ThreatRouting>,
 }
 
 impl ModuleReceiver {
+    /// Receiver of all the events of the bus.
+    pub(crate) fn new(bus: &Bus, module_name: &ModuleName) -> Self {
+        let (backlog, rx) = bus.get_receiver_with_backlog();
+        Self {
+            rx,
+            backlog,
+            module_name: module_name.to_owned(),
+            position: bus.track_receiver(module_name),
+            routing: None,
+        }
+    }
+
+    /// Receiver of the events sent to the outputs, with their threats routing.
+    pub(crate) fn output(bus: &Bus, module_name: &ModuleName, routing: ThreatRouting) -> Self {
+        let (backlog, rx) = bus.get_output_receiver_with_backlog();
+        Self {
+            rx,
+            backlog,
+            module_name: module_name.to_owned(),
+            position: bus.track_output_receiver(module_name),
+            routing: Some(routing),
+        }
+    }
+
     /// Receive an [`Event`] from the [`Bus`].
-    pub fn recv(&mut self) -> impl Future<Output = Result<Arc<Event>, BusError>> + '_ {
-        receive_from_broadcast(&mut self.rx, &self.module_name)
+    pub async fn recv(&mut self) -> Result<Arc<Event>, BusError> {
+        loop {
+            let event = match self.backlog.pop_front() {
+                Some(event) => {
+                    self.position.received_early();
+                    event
+                }
+                None => {
+                    receive_tracked(&mut self.rx, &self.module_name, Some(&self.position)).await?
+                }
+            };
+            let routed = match &mut self.routing {
+                Some(routing) => routing.allows(&event),
+                None => true,
+            };
+            if routed {
+                return Ok(event);
+            }
+        }
     }
 }
 
@@ -338,6 +558,15 @@ impl ModuleReceiver {
 pub async fn receive_from_broadcast(
     rx: &mut broadcast::Receiver<Arc<Event>>,
     module_name: &str,
+) -> Result<Arc<Event>, BusError> {
+    receive_tracked(rx, module_name, None).await
+}
+
+/// Like [`receive_from_broadcast`], recording the progress of the receiver.
+async fn receive_tracked(
+    rx: &mut broadcast::Receiver<Arc<Event>>,
+    module_name: &str,
+    position: Option<&ReceiverPosition>,
 ) -> Result<Arc<Event>, BusError> {
     let mut lost: u64 = 0;
     loop {
@@ -349,9 +578,18 @@ pub async fn receive_from_broadcast(
                         "brodcast channel lagged {lost} messages",
                     );
                 }
+                if let Some(position) = position {
+                    position.received();
+                }
                 return Ok(value);
             }
-            Err(RecvError::Lagged(lagged)) => lost += lagged,
+            Err(RecvError::Lagged(lagged)) => {
+                crate::bus::count_lost_events(lagged);
+                if let Some(position) = position {
+                    position.lagged(lagged);
+                }
+                lost += lagged
+            }
             Err(RecvError::Closed) => return Err(BusError::Stopped),
         }
     }
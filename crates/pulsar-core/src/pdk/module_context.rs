@@ -6,7 +6,11 @@ use tokio::sync::{broadcast, watch};
 
 use crate::{
     bus::Bus,
-    pdk::{ModuleConfig, ModuleReceiver, ModuleSender, PulsarDaemonHandle, SignalSender},
+    pdk::{
+        policy::ThreatRouting, Event, ModuleConfig, ModuleReceiver, ModuleSender,
+        PulsarDaemonHandle, SignalSender,
+    },
+    tap::EventTap,
 };
 
 use super::{process_tracker::ProcessTrackerHandle, ConfigError, ModuleError, ModuleName};
@@ -21,10 +25,12 @@ pub struct ModuleContext {
     daemon_handle: PulsarDaemonHandle,
     process_tracker: ProcessTrackerHandle,
     bpf_context: BpfContext,
+    policy: watch::Receiver<ModuleConfig>,
 }
 
 impl ModuleContext {
     /// Constructs a new `ModuleContext< B: Bus>`
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cfg: watch::Receiver<ModuleConfig>,
         bus: Bus,
@@ -33,6 +39,7 @@ impl ModuleContext {
         daemon_handle: PulsarDaemonHandle,
         process_tracker: ProcessTrackerHandle,
         bpf_context: BpfContext,
+        policy: watch::Receiver<ModuleConfig>,
     ) -> Self {
         Self {
             cfg,
@@ -42,6 +49,7 @@ impl ModuleContext {
             daemon_handle,
             process_tracker,
             bpf_context,
+            policy,
         }
     }
 }
@@ -86,12 +94,12 @@ impl ShutdownSender {
 impl ModuleContext {
     /// Get an instance of [`ModuleSender`] to send [`crate::event::Payload`] objects to the [`Bus`].
     pub fn get_sender(&self) -> ModuleSender {
-        ModuleSender {
-            tx: self.bus.get_sender(),
-            module_name: self.module_name.to_owned(),
-            process_tracker: self.process_tracker.clone(),
-            signal_sender: self.signal_sender.clone(),
-        }
+        ModuleSender::new(
+            self.bus.get_sender(),
+            self.module_name.to_owned(),
+            self.process_tracker.clone(),
+            self.signal_sender.clone(),
+        )
     }
 
     pub fn get_process_tracker(&self) -> ProcessTrackerHandle {
@@ -100,10 +108,25 @@ impl ModuleContext {
 
     /// Get an instance of [`ModuleReceiver`] to receive [`crate::event::Event`] objects from the [`Bus`].
     pub fn get_receiver(&self) -> ModuleReceiver {
-        ModuleReceiver {
-            rx: self.bus.get_receiver(),
-            module_name: self.module_name.to_owned(),
-        }
+        ModuleReceiver::new(&self.bus, &self.module_name)
+    }
+
+    /// Get a [`ModuleReceiver`] for an output module: it receives the events
+    /// which went through the output filters of the [`Bus`], see [`crate::output`],
+    /// and the threats which the [`super::policy::EscalationPolicy`] doesn't route
+    /// to it, or which are acknowledged, are skipped.
+    pub fn get_output_receiver(&self) -> ModuleReceiver {
+        ModuleReceiver::output(
+            &self.bus,
+            &self.module_name,
+            ThreatRouting::new(&self.module_name, self.policy.clone()),
+        )
+    }
+
+    /// Attach an [`EventTap`] to the [`Bus`], observing the events matching `filter`
+    /// without a receiver.
+    pub fn tap(&self, filter: impl Fn(&Event) -> bool + Send + Sync + 'static) -> EventTap {
+        EventTap::new(&self.bus, filter)
     }
 
     /// Get an instance of the [`PulsarDaemonHandle`] to perform administration operations on modules.
@@ -0,0 +1,203 @@
+//! Escalation policy routing the threats to the output modules by severity.
+//!
+//! The policy is read from the `[policy]` section of the configuration, with
+//! the list of outputs notified for every severity:
+//!
+//! ```ini
+//! [policy]
+//! low=logger
+//! high=logger,smtp-notifier,desktop-notifier
+//! critical=logger,smtp-notifier,threat-response
+//! ```
+//!
+//! Severities not listed are handled by every output, so that an empty
+//! policy keeps notifying all the threats everywhere. Threats with their own
+//! [`Threat::outputs`], set by rules with `outputs`, bypass the policy.
+//! No output handles the threats acknowledged with [`crate::acknowledgments`].
+//!
+//! The routing is done by the receivers of the output modules, taken with
+//! [`super::ModuleContext::get_output_receiver`], which skip the threats not
+//! routed to them. The policy is reloaded when it's changed with
+//! `pulsar config --set policy.<severity>=<outputs>`.
+
+use std::{collections::HashMap, str::FromStr};
+
+use tokio::sync::watch;
+
+use crate::{
+    acknowledgments,
+    event::{Event, Severity, Threat},
+    suggest::closest,
+};
+
+use super::{ConfigError, ModuleConfig};
+
+/// Configuration section of the escalation policy.
+pub const POLICY_SECTION: &str = "policy";
+
+const SEVERITIES: [&str; 4] = ["low", "medium", "high", "critical"];
+
+#[derive(Debug, Clone, Default)]
+pub struct EscalationPolicy {
+    routes: HashMap<Severity, Vec<String>>,
+}
+
+impl EscalationPolicy {
+    /// Outputs notified for a severity, `None` if not restricted.
+    pub fn outputs(&self, severity: Severity) -> Option<&[String]> {
+        self.routes.get(&severity).map(Vec::as_slice)
+    }
+
+    /// Check if the `output` module should handle the threat.
+    pub fn allows(&self, output: &str, threat: &Threat) -> bool {
+        if acknowledgments::is_acknowledged(&threat.id) {
+            return false;
+        }
+        let outputs = match &threat.outputs {
+            Some(outputs) => Some(outputs.as_slice()),
+            None => self.outputs(threat.severity),
+        };
+        match outputs {
+            Some(outputs) => outputs.iter().any(|name| name == output),
+            None => true,
+        }
+    }
+}
+
+/// Routing of the threats to an output module, following the changes of the
+/// policy configuration.
+#[derive(Debug)]
+pub(crate) struct ThreatRouting {
+    output: String,
+    rx: watch::Receiver<ModuleConfig>,
+    policy: EscalationPolicy,
+}
+
+impl ThreatRouting {
+    pub(crate) fn new(output: &str, mut rx: watch::Receiver<ModuleConfig>) -> Self {
+        // Validated by the daemon on startup and on every change
+        let policy = EscalationPolicy::try_from(&*rx.borrow_and_update()).unwrap_or_default();
+        Self {
+            output: output.to_string(),
+            rx,
+            policy,
+        }
+    }
+
+    /// Check if the output should handle the event: all the events which are
+    /// not threats are handled.
+    pub(crate) fn allows(&mut self, event: &Event) -> bool {
+        let Some(threat) = &event.header().threat else {
+            return true;
+        };
+        if self.rx.has_changed().unwrap_or(false) {
+            match EscalationPolicy::try_from(&*self.rx.borrow_and_update()) {
+                Ok(policy) => self.policy = policy,
+                Err(err) => log::warn!("Invalid escalation policy, keeping the previous: {err}"),
+            }
+        }
+        self.policy.allows(&self.output, threat)
+    }
+}
+
+impl TryFrom<&ModuleConfig> for EscalationPolicy {
+    type Error = ConfigError;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self, Self::Error> {
+        let mut routes = HashMap::new();
+        for (key, _) in config.iter() {
+            let severity = Severity::from_str(key).map_err(|_| ConfigError::UnknownField {
+                field: key.clone(),
+                suggestion: closest(key, SEVERITIES.into_iter()).map(str::to_string),
+            })?;
+            routes.insert(severity, config.get_list(key)?);
+        }
+        Ok(Self { routes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::event::{Header, Payload};
+
+    fn threat(severity: Severity) -> Threat {
+        Threat {
+            id: String::new(),
+            source: "rules-engine".into(),
+            description: "test".to_string(),
+            severity,
+            outputs: None,
+            extra: None,
+            evidence: None,
+        }
+    }
+
+    #[test]
+    fn routing() {
+        let mut config = ModuleConfig::default();
+        config.insert("low".to_string(), "logger".to_string());
+        config.insert("high".to_string(), "logger, smtp-notifier".to_string());
+        config.insert("critical".to_string(), "".to_string());
+        let policy = EscalationPolicy::try_from(&config).unwrap();
+
+        assert!(policy.allows("logger", &threat(Severity::Low)));
+        assert!(!policy.allows("smtp-notifier", &threat(Severity::Low)));
+        assert!(policy.allows("smtp-notifier", &threat(Severity::High)));
+        assert!(!policy.allows("threat-response", &threat(Severity::High)));
+        assert!(!policy.allows("logger", &threat(Severity::Critical)));
+        // not restricted
+        assert!(policy.allows("threat-response", &threat(Severity::Medium)));
+        assert!(EscalationPolicy::default().allows("logger", &threat(Severity::Low)));
+
+        // overridden by the threat
+        let paging = Threat {
+            outputs: Some(vec!["smtp-notifier".to_string()]),
+            ..threat(Severity::Low)
+        };
+        assert!(policy.allows("smtp-notifier", &paging));
+        assert!(!policy.allows("logger", &paging));
+        assert!(!EscalationPolicy::default().allows("logger", &paging));
+
+        config.insert("urgent".to_string(), "logger".to_string());
+        assert!(EscalationPolicy::try_from(&config).is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let (tx, rx) = watch::channel(ModuleConfig::default());
+        let mut routing = ThreatRouting::new("smtp-notifier", rx);
+        let event = |threat| {
+            let header = Header {
+                pid: 1,
+                threat,
+                source: "rules-engine".into(),
+                ..Header::test_default()
+            };
+            Event::new(header, Payload::Exit { exit_code: 0 })
+        };
+        assert!(routing.allows(&event(Some(threat(Severity::Low)))));
+        let mut config = ModuleConfig::default();
+        config.insert("low".to_string(), "logger".to_string());
+        tx.send_replace(config);
+        assert!(!routing.allows(&event(Some(threat(Severity::Low)))));
+        assert!(routing.allows(&event(Some(threat(Severity::High)))));
+        // Events without threats are always handled
+        assert!(routing.allows(&event(None)));
+    }
+
+    #[test]
+    fn acknowledged() {
+        let benign = Threat {
+            id: "c0ffee0000000001".to_string(),
+            ..threat(Severity::Critical)
+        };
+        let policy = EscalationPolicy::default();
+        assert!(policy.allows("logger", &benign));
+        acknowledgments::acknowledge(&benign.id, std::time::Duration::from_secs(60), None);
+        assert!(!policy.allows("logger", &benign));
+        assert!(policy.allows("logger", &threat(Severity::Critical)));
+        acknowledgments::unacknowledge(&benign.id);
+    }
+}
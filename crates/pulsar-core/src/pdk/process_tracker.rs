@@ -1,4 +1,7 @@
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+};
 
 use bpf_common::{time::Timestamp, Pid};
 use thiserror::Error;
@@ -41,6 +44,7 @@ pub enum TrackerUpdate {
         image: String,
         argv: Vec<String>,
         namespaces: Namespaces,
+        terminal: TerminalInfo,
     },
     SetNewParent {
         pid: Pid,
@@ -81,6 +85,98 @@ pub struct ProcessInfo {
     pub fork_time: Timestamp,
     pub argv: Vec<String>,
     pub namespaces: Namespaces,
+    /// Images executed by the process and its ancestors, oldest first.
+    pub exec_chain: Vec<String>,
+    /// See [`is_interactive`].
+    pub is_interactive: bool,
+}
+
+/// Terminal details of a process, collected on exec.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalInfo {
+    /// The process has a controlling terminal.
+    pub has_tty: bool,
+    /// Type of the file open as standard input.
+    pub stdin: StdinKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StdinKind {
+    #[default]
+    Unknown,
+    /// Character device, usually a terminal.
+    CharDevice,
+    Pipe,
+    Socket,
+    File,
+}
+
+impl StdinKind {
+    /// Get the kind of file from the `st_mode` of its inode.
+    pub fn from_mode(mode: u32) -> Self {
+        const S_IFMT: u32 = 0o170000;
+        match mode & S_IFMT {
+            0o020000 => StdinKind::CharDevice,
+            0o010000 => StdinKind::Pipe,
+            0o140000 => StdinKind::Socket,
+            0o100000 => StdinKind::File,
+            _ => StdinKind::Unknown,
+        }
+    }
+}
+
+/// Programs starting interactive sessions: login programs, multiplexers and
+/// terminal emulators.
+const INTERACTIVE_PARENTS: &[&str] = &[
+    "sshd",
+    "login",
+    "su",
+    "sudo",
+    "tmux: server",
+    "tmux",
+    "screen",
+    "xterm",
+    "gnome-terminal-server",
+    "konsole",
+    "xfce4-terminal",
+    "alacritty",
+    "kitty",
+    "wezterm-gui",
+    "foot",
+];
+
+/// Heuristic to detect processes run by a user in a terminal.
+///
+/// The process must have a controlling terminal and either read its standard
+/// input from a character device or be started by a login program or terminal
+/// emulator.
+pub fn is_interactive(terminal: TerminalInfo, parent_image: &str) -> bool {
+    if !terminal.has_tty {
+        return false;
+    }
+    if terminal.stdin == StdinKind::CharDevice {
+        return true;
+    }
+    let parent_name = parent_image.rsplit('/').next().unwrap_or_default();
+    INTERACTIVE_PARENTS.contains(&parent_name)
+}
+
+/// Fingerprint of an exec chain, stable across runs and hosts.
+///
+/// It's the FNV-1a hash of the images, formatted as hexadecimal.
+pub fn exec_chain_hash(exec_chain: &[String]) -> String {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET;
+    for image in exec_chain {
+        // Terminate each image with a null byte to avoid ambiguity between chains
+        for byte in image.bytes().chain(iter::once(0)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    format!("{hash:016x}")
 }
 
 impl ProcessTrackerHandle {
@@ -142,6 +238,7 @@ struct ProcessData {
     >,
     argv: Vec<String>,
     namespaces: Namespaces,
+    terminal: TerminalInfo,
 }
 
 /// Cleanup timeout in nanoseconds. This is how long an exited process
@@ -150,6 +247,8 @@ const CLEANUP_TIMEOUT: u64 = 5_000_000_000; // 5 seconds
 /// How long to consider a process still alive after it exited. Some eBPF probes
 /// might be processed after sched_process_exit, like on_tcp_set_state.
 const EXIT_THRESHOLD: u64 = 5_000_000; // 5 millis
+/// Maximum number of ancestors visited when building the exec chain.
+const MAX_EXEC_CHAIN_DEPTH: usize = 64;
 
 impl ProcessTracker {
     fn new(rx: mpsc::UnboundedReceiver<TrackerRequest>) -> Self {
@@ -166,6 +265,7 @@ impl ProcessTracker {
                 exec_changes: BTreeMap::new(),
                 argv: Vec::new(),
                 namespaces: Namespaces::default(),
+                terminal: TerminalInfo::default(),
             },
         );
         Self {
@@ -264,6 +364,12 @@ impl ProcessTracker {
                             .map(|parent| parent.argv.clone())
                             .unwrap_or_default(),
                         namespaces,
+                        // A forked process shares terminal and file descriptors with its parent
+                        terminal: self
+                            .data
+                            .get(&ppid)
+                            .map(|parent| parent.terminal)
+                            .unwrap_or_default(),
                     },
                 );
                 if let Some(pending_updates) = self.pending_updates.remove(&pid) {
@@ -278,10 +384,12 @@ impl ProcessTracker {
                 ref mut image,
                 ref mut argv,
                 namespaces: _,
+                terminal,
             } => {
                 if let Some(p) = self.data.get_mut(&pid) {
                     p.exec_changes.insert(timestamp, std::mem::take(image));
-                    p.argv = std::mem::take(argv)
+                    p.argv = std::mem::take(argv);
+                    p.terminal = terminal;
                 } else {
                     // if exec arrived before the fork, we save the event as pending
                     log::debug!("(exec) Process {pid} not found in process tree, saving for later");
@@ -331,9 +439,41 @@ impl ProcessTracker {
             fork_time: process.fork_time,
             argv: process.argv.clone(),
             namespaces: process.namespaces,
+            exec_chain: self.get_exec_chain(pid, ts),
+            is_interactive: is_interactive(process.terminal, &self.get_image(process.ppid, ts)),
         })
     }
 
+    /// Get the images executed by a process and its ancestors up to a certain
+    /// point of time. Images repeated because of forks are reported once.
+    fn get_exec_chain(&self, mut pid: Pid, mut ts: Timestamp) -> Vec<String> {
+        let mut chain: Vec<String> = Vec::new();
+        for _ in 0..MAX_EXEC_CHAIN_DEPTH {
+            let Some(process) = self.data.get(&pid) else {
+                break;
+            };
+            // Walk the images backwards, the chain is reversed at the end
+            let images = process
+                .exec_changes
+                .range(..=ts)
+                .rev()
+                .map(|(_timestamp, image)| image)
+                .chain(iter::once(&process.original_image));
+            for image in images {
+                if !image.is_empty() && chain.last() != Some(image) {
+                    chain.push(image.clone());
+                }
+            }
+            if process.ppid == pid || process.ppid == Pid::from_raw(0) {
+                break;
+            }
+            ts = process.fork_time;
+            pid = process.ppid;
+        }
+        chain.reverse();
+        chain
+    }
+
     /// get image name at a certain point of time
     fn get_image(&self, pid: Pid, ts: Timestamp) -> String {
         match self.data.get(&pid) {
@@ -472,6 +612,7 @@ mod tests {
             timestamp: 15.into(),
             argv: Vec::new(),
             namespaces: NAMESPACES_1,
+            terminal: TerminalInfo::default(),
         });
         process_tracker.update(TrackerUpdate::Exit {
             pid: PID_2,
@@ -490,6 +631,8 @@ mod tests {
                 fork_time: 10.into(),
                 argv: Vec::new(),
                 namespaces: NAMESPACES_1,
+                exec_chain: Vec::new(),
+                is_interactive: false,
             }
         );
         assert_eq!(
@@ -500,6 +643,8 @@ mod tests {
                 fork_time: 10.into(),
                 argv: Vec::new(),
                 namespaces: NAMESPACES_1,
+                exec_chain: vec!["/bin/after_exec".to_string()],
+                is_interactive: false,
             }
         );
         assert_eq!(
@@ -524,6 +669,7 @@ mod tests {
             timestamp: 15.into(),
             argv: Vec::new(),
             namespaces: NAMESPACES_1,
+            terminal: TerminalInfo::default(),
         });
         process_tracker.update(TrackerUpdate::Fork {
             ppid: PID_1,
@@ -543,6 +689,8 @@ mod tests {
                 fork_time: 10.into(),
                 argv: Vec::new(),
                 namespaces: NAMESPACES_1,
+                exec_chain: Vec::new(),
+                is_interactive: false,
             })
         );
         assert_eq!(
@@ -553,6 +701,8 @@ mod tests {
                 fork_time: 10.into(),
                 argv: Vec::new(),
                 namespaces: NAMESPACES_1,
+                exec_chain: vec!["/bin/after_exec".to_string()],
+                is_interactive: false,
             })
         );
         time::sleep(time::Duration::from_millis(1)).await;
@@ -563,4 +713,135 @@ mod tests {
             Err(TrackerError::ProcessExited)
         );
     }
+
+    #[tokio::test]
+    async fn exec_chain() {
+        const PID_3: Pid = Pid::from_raw(44);
+        let process_tracker = start_process_tracker();
+        let exec = |pid, timestamp: u64, image: &str| TrackerUpdate::Exec {
+            pid,
+            image: image.to_string(),
+            timestamp: timestamp.into(),
+            argv: Vec::new(),
+            namespaces: NAMESPACES_1,
+            terminal: TerminalInfo::default(),
+        };
+        process_tracker.update(TrackerUpdate::Fork {
+            ppid: Pid::from_raw(1),
+            pid: PID_1,
+            timestamp: 10.into(),
+            namespaces: NAMESPACES_1,
+        });
+        process_tracker.update(exec(PID_1, 11, "/usr/sbin/sshd"));
+        process_tracker.update(TrackerUpdate::Fork {
+            ppid: PID_1,
+            pid: PID_2,
+            timestamp: 20.into(),
+            namespaces: NAMESPACES_1,
+        });
+        process_tracker.update(exec(PID_2, 21, "/bin/bash"));
+        process_tracker.update(exec(PID_2, 22, "/usr/bin/python"));
+        process_tracker.update(TrackerUpdate::Fork {
+            ppid: PID_2,
+            pid: PID_3,
+            timestamp: 30.into(),
+            namespaces: NAMESPACES_1,
+        });
+        process_tracker.update(exec(PID_3, 31, "/usr/bin/curl"));
+        // Images executed by the parent after the fork are not part of the chain
+        process_tracker.update(exec(PID_2, 40, "/usr/bin/sleep"));
+
+        let chain = |images: &[&str]| images.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        let info = process_tracker.get(PID_3, 50.into()).await.unwrap();
+        assert_eq!(
+            info.exec_chain,
+            chain(&[
+                "/usr/sbin/sshd",
+                "/bin/bash",
+                "/usr/bin/python",
+                "/usr/bin/curl"
+            ])
+        );
+        let info = process_tracker.get(PID_3, 30.into()).await.unwrap();
+        assert_eq!(
+            info.exec_chain,
+            chain(&["/usr/sbin/sshd", "/bin/bash", "/usr/bin/python"])
+        );
+
+        assert_eq!(
+            exec_chain_hash(&chain(&["/bin/bash", "/usr/bin/python"])),
+            exec_chain_hash(&chain(&["/bin/bash", "/usr/bin/python"]))
+        );
+        assert_ne!(
+            exec_chain_hash(&chain(&["/bin/bash", "/usr/bin/python"])),
+            exec_chain_hash(&chain(&["/bin/bash/usr/bin/python"]))
+        );
+    }
+
+    #[tokio::test]
+    async fn interactive_process() {
+        let process_tracker = start_process_tracker();
+        let terminal = TerminalInfo {
+            has_tty: true,
+            stdin: StdinKind::CharDevice,
+        };
+        process_tracker.update(TrackerUpdate::Fork {
+            ppid: Pid::from_raw(1),
+            pid: PID_1,
+            timestamp: 10.into(),
+            namespaces: NAMESPACES_1,
+        });
+        process_tracker.update(TrackerUpdate::Exec {
+            pid: PID_1,
+            image: "/bin/bash".to_string(),
+            timestamp: 11.into(),
+            argv: Vec::new(),
+            namespaces: NAMESPACES_1,
+            terminal,
+        });
+        // The child inherits the terminal of the parent
+        process_tracker.update(TrackerUpdate::Fork {
+            ppid: PID_1,
+            pid: PID_2,
+            timestamp: 20.into(),
+            namespaces: NAMESPACES_1,
+        });
+        assert!(
+            process_tracker
+                .get(PID_1, 15.into())
+                .await
+                .unwrap()
+                .is_interactive
+        );
+        assert!(
+            process_tracker
+                .get(PID_2, 25.into())
+                .await
+                .unwrap()
+                .is_interactive
+        );
+    }
+
+    #[test]
+    fn interactive_heuristic() {
+        let terminal = |has_tty, stdin| TerminalInfo { has_tty, stdin };
+        assert!(is_interactive(
+            terminal(true, StdinKind::CharDevice),
+            "/bin/bash"
+        ));
+        assert!(is_interactive(
+            terminal(true, StdinKind::Pipe),
+            "/usr/sbin/sshd"
+        ));
+        assert!(!is_interactive(
+            terminal(true, StdinKind::Pipe),
+            "/bin/bash"
+        ));
+        assert!(!is_interactive(
+            terminal(false, StdinKind::CharDevice),
+            "/usr/sbin/sshd"
+        ));
+        assert_eq!(StdinKind::from_mode(0o020620), StdinKind::CharDevice);
+        assert_eq!(StdinKind::from_mode(0o010600), StdinKind::Pipe);
+    }
 }
@@ -0,0 +1,221 @@
+//! Interpolation of environment variables and secrets in configuration values,
+//! so that credentials don't need to be stored in the configuration file.
+//!
+//! - `${NAME}` and `${env:NAME}` are replaced with the environment variable `NAME`
+//! - `${file:/path}` with the content of a file, without the trailing newline
+//! - `${creds:NAME}` with the systemd credential `NAME`, see `LoadCredential=`
+//!   in systemd.exec(5)
+//! - `$$` with a single `$`
+//!
+//! Other sources can be added by implementing [`SecretProvider`].
+
+use std::{env, fmt, fs, path::Path};
+
+use thiserror::Error;
+
+/// Describes an error resolving a configuration value.
+#[derive(Error, Debug, Clone)]
+pub enum SecretError {
+    #[error("unterminated reference in '{0}'")]
+    Unterminated(String),
+    #[error("unknown secret provider '{0}'")]
+    UnknownProvider(String),
+    #[error("can't resolve '${{{reference}}}': {reason}")]
+    Unresolved { reference: String, reason: String },
+}
+
+/// Source of the values referenced with `${<scheme>:<key>}`.
+pub trait SecretProvider: Send + Sync {
+    fn scheme(&self) -> &str;
+
+    /// Return the value of `key`, or the reason why it's not available.
+    fn resolve(&self, key: &str) -> Result<String, String>;
+}
+
+/// Environment variables of the daemon.
+pub struct EnvProvider;
+
+impl SecretProvider for EnvProvider {
+    fn scheme(&self) -> &str {
+        "env"
+    }
+
+    fn resolve(&self, key: &str) -> Result<String, String> {
+        env::var(key).map_err(|err| err.to_string())
+    }
+}
+
+/// Content of files, like the secrets mounted by container orchestrators.
+pub struct FileProvider;
+
+impl SecretProvider for FileProvider {
+    fn scheme(&self) -> &str {
+        "file"
+    }
+
+    fn resolve(&self, key: &str) -> Result<String, String> {
+        read_secret(Path::new(key))
+    }
+}
+
+/// Credentials passed by systemd in `$CREDENTIALS_DIRECTORY`.
+pub struct CredsProvider;
+
+impl SecretProvider for CredsProvider {
+    fn scheme(&self) -> &str {
+        "creds"
+    }
+
+    fn resolve(&self, key: &str) -> Result<String, String> {
+        if key.is_empty() || key.contains('/') {
+            return Err("invalid credential name".to_string());
+        }
+        let dir = env::var_os("CREDENTIALS_DIRECTORY")
+            .ok_or_else(|| "CREDENTIALS_DIRECTORY is not set".to_string())?;
+        read_secret(&Path::new(&dir).join(key))
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String, String> {
+    let value = fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(value.strip_suffix('\n').unwrap_or(&value).to_string())
+}
+
+/// Resolves the references in configuration values with a set of providers.
+pub struct Interpolator {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl fmt::Debug for Interpolator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.providers.iter().map(|provider| provider.scheme()))
+            .finish()
+    }
+}
+
+impl Default for Interpolator {
+    fn default() -> Self {
+        Self::empty()
+            .with_provider(EnvProvider)
+            .with_provider(FileProvider)
+            .with_provider(CredsProvider)
+    }
+}
+
+impl Interpolator {
+    /// Interpolator without providers, references are errors.
+    pub fn empty() -> Self {
+        Self {
+            providers: Vec::new(),
+        }
+    }
+
+    /// Add a provider, replacing the one with the same scheme if any.
+    pub fn with_provider(mut self, provider: impl SecretProvider + 'static) -> Self {
+        self.providers
+            .retain(|existing| existing.scheme() != provider.scheme());
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// Replace all the references in `value`.
+    pub fn interpolate(&self, value: &str) -> Result<String, SecretError> {
+        let mut result = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("$$") {
+                result.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("${") {
+                let end = after
+                    .find('}')
+                    .ok_or_else(|| SecretError::Unterminated(value.to_string()))?;
+                result.push_str(&self.resolve(&after[..end])?);
+                rest = &after[end + 1..];
+            } else {
+                result.push('$');
+                rest = &rest[1..];
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    fn resolve(&self, reference: &str) -> Result<String, SecretError> {
+        let (scheme, key) = reference.split_once(':').unwrap_or(("env", reference));
+        let provider = self
+            .providers
+            .iter()
+            .find(|provider| provider.scheme() == scheme)
+            .ok_or_else(|| SecretError::UnknownProvider(scheme.to_string()))?;
+        provider
+            .resolve(key)
+            .map_err(|reason| SecretError::Unresolved {
+                reference: reference.to_string(),
+                reason,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Vault;
+
+    impl SecretProvider for Vault {
+        fn scheme(&self) -> &str {
+            "vault"
+        }
+
+        fn resolve(&self, key: &str) -> Result<String, String> {
+            match key {
+                "kafka/password" => Ok("s3cret".to_string()),
+                _ => Err("not found".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn interpolate() {
+        env::set_var("PULSAR_TEST_TOKEN", "abc");
+        let file = env::temp_dir().join("pulsar_secret_test");
+        fs::write(&file, "from-file\n").unwrap();
+        let interpolator = Interpolator::default().with_provider(Vault);
+
+        for (value, expected) in [
+            ("plain", "plain".to_string()),
+            ("Bearer ${PULSAR_TEST_TOKEN}", "Bearer abc".to_string()),
+            ("${env:PULSAR_TEST_TOKEN}-x", "abc-x".to_string()),
+            (
+                &format!("${{file:{}}}", file.display()),
+                "from-file".to_string(),
+            ),
+            ("${vault:kafka/password}", "s3cret".to_string()),
+            ("cost: $$5, $ left", "cost: $5, $ left".to_string()),
+        ] {
+            assert_eq!(interpolator.interpolate(value).unwrap(), expected);
+        }
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(
+            interpolator.interpolate("${PULSAR_TEST_TOKEN"),
+            Err(SecretError::Unterminated(_))
+        ));
+        assert!(matches!(
+            interpolator.interpolate("${aws:key}"),
+            Err(SecretError::UnknownProvider(_))
+        ));
+        assert!(matches!(
+            interpolator.interpolate("${vault:missing}"),
+            Err(SecretError::Unresolved { .. })
+        ));
+        assert!(matches!(
+            interpolator.interpolate("${creds:../etc/shadow}"),
+            Err(SecretError::Unresolved { .. })
+        ));
+    }
+}
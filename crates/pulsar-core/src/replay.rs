@@ -0,0 +1,175 @@
+//! Replay of captured events through the bus, without the eBPF probes.
+//!
+//! The input has one JSON event per line, like the files written by
+//! `pulsar export --format jsonl`. Events are sent in order with their
+//! original headers, and process events update the process tracker as the
+//! process-monitor would, so that rules, enrichment and outputs see the same
+//! events every time.
+
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{
+    bus::{Bus, BusError},
+    event::Payload,
+    pdk::{
+        process_tracker::{ProcessTrackerHandle, TerminalInfo, TrackerUpdate},
+        Event,
+    },
+    Pid, Timestamp,
+};
+
+/// Events queued on the bus after which the replay waits for the receivers,
+/// so that they never lag behind and lose events.
+const MAX_PENDING: usize = 500;
+
+/// Time without events on the bus after which the replay is considered
+/// completed, to include the events sent by modules in reaction to it.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("reading events: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("invalid event at line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error(transparent)]
+    Bus(#[from] BusError),
+}
+
+/// Send the events read from `reader` on the bus, returning how many were
+/// sent once the modules have handled them.
+pub async fn replay(
+    bus: &Bus,
+    process_tracker: &ProcessTrackerHandle,
+    reader: impl AsyncBufRead + Unpin,
+) -> Result<usize, ReplayError> {
+    let mut lines = reader.lines();
+    let mut line = 0;
+    let mut count = 0;
+    while let Some(content) = lines.next_line().await? {
+        line += 1;
+        if content.trim().is_empty() {
+            continue;
+        }
+        let event: Event =
+            serde_json::from_str(&content).map_err(|source| ReplayError::Parse { line, source })?;
+        if let Some(update) = tracker_update(&event) {
+            process_tracker.update(update);
+        }
+        while bus.pending() >= MAX_PENDING {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        bus.send(event)?;
+        count += 1;
+    }
+    settle(bus).await;
+    Ok(count)
+}
+
+/// Wait until the bus stays empty for [`SETTLE_TIME`].
+async fn settle(bus: &Bus) {
+    let mut idle = Duration::ZERO;
+    while idle < SETTLE_TIME {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if bus.pending() == 0 {
+            idle += POLL_INTERVAL;
+        } else {
+            idle = Duration::ZERO;
+        }
+    }
+}
+
+fn tracker_update(event: &Event) -> Option<TrackerUpdate> {
+    let header = event.header();
+    let pid = Pid::from_raw(header.pid);
+    let timestamp = Timestamp::from(header.raw_timestamp);
+    Some(match event.payload() {
+        Payload::Fork { ppid, namespaces } => TrackerUpdate::Fork {
+            pid,
+            timestamp,
+            ppid: Pid::from_raw(*ppid),
+            namespaces: *namespaces,
+        },
+        Payload::Exec {
+            filename,
+            argv,
+            namespaces,
+            ..
+        } => TrackerUpdate::Exec {
+            pid,
+            timestamp,
+            image: filename.clone(),
+            argv: argv.clone().into(),
+            namespaces: *namespaces,
+            terminal: TerminalInfo::default(),
+        },
+        Payload::ChangeParent { ppid } => TrackerUpdate::SetNewParent {
+            pid,
+            ppid: Pid::from_raw(*ppid),
+        },
+        Payload::Exit { .. } => TrackerUpdate::Exit { pid, timestamp },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::{event::Header, pdk::process_tracker::start_process_tracker};
+
+    fn event(id: u64) -> String {
+        let event = Event::new(
+            Header {
+                id,
+                image: "/bin/sh".to_string(),
+                pid: 42,
+                parent_pid: 1,
+                source: "process-monitor".into(),
+                timestamp: UNIX_EPOCH + Duration::from_secs(id),
+                raw_timestamp: id,
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 0 },
+        );
+        serde_json::to_string(&event).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_events() {
+        let total = 2 * MAX_PENDING as u64;
+        let bus = Bus::new();
+        let mut rx = bus.get_receiver();
+        let process_tracker = start_process_tracker();
+        let input: String = (0..total).map(|id| event(id) + "\n\n").collect();
+
+        // A slow receiver must not lose events
+        let receiver = tokio::spawn(async move {
+            let mut ids = Vec::new();
+            while ids.len() < total as usize {
+                ids.push(rx.recv().await.unwrap().header().id);
+                tokio::task::yield_now().await;
+            }
+            ids
+        });
+        let count = replay(&bus, &process_tracker, input.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(count, total as usize);
+        assert_eq!(receiver.await.unwrap(), (0..total).collect::<Vec<_>>());
+
+        let error = replay(&bus, &process_tracker, "\n{}\n".as_bytes())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ReplayError::Parse { line: 2, .. }));
+    }
+}
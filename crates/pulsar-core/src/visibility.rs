@@ -0,0 +1,197 @@
+//! Health notes for the containers the probes can't see into.
+//!
+//! With gVisor the system calls of the application are served by a user-mode
+//! kernel, and with Kata Containers the application runs in a virtual machine:
+//! the host eBPF probes only see the processes of the sandbox, like `runsc` or
+//! `qemu`. Their events are marked with `header.sandboxed_runtime`, and a
+//! `DegradedVisibility` event is sent for the first one of every container, so
+//! that analysts don't mistake the activity of the sandbox for the one of the
+//! application, or its silence for a quiet workload.
+
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    bus::{self, Bus},
+    container,
+    event::{next_event_id, Event, Header, Payload},
+    heartbeat::DAEMON_SOURCE,
+};
+
+/// Sandboxes remembered, forgotten all at once when full.
+const MAX_SANDBOXES: usize = 10_000;
+
+const GVISOR_REASON: &str = "system calls are served by the gVisor user-mode kernel: \
+    events show the runsc sandbox and gofer, not the processes of the application";
+const VM_REASON: &str = "the container runs in a virtual machine: \
+    events show the hypervisor and the runtime shim, not the processes of the application";
+
+/// Send a `DegradedVisibility` event for every sandboxed container seen on the
+/// bus, until the bus is stopped.
+pub fn start(bus: Bus) {
+    let mut rx = bus.get_receiver();
+    let mut notes = VisibilityNotes::default();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) if !event.header().sandboxed_runtime.is_empty() => {
+                    let container_id = notes.container_id(event.header().pid, |pid| {
+                        container::of_process(pid).map(|c| c.id)
+                    });
+                    if matches!(event.payload(), Payload::Exit { .. }) {
+                        notes.forget(event.header().pid);
+                    }
+                    if let Some(note) = notes.note(&event, container_id) {
+                        let _ = bus.send(note);
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(lost)) => bus::count_lost_events(lost),
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+#[derive(Default)]
+struct VisibilityNotes {
+    seen: HashSet<String>,
+    /// Container of the sandbox processes, looked up once per process.
+    containers: HashMap<i32, Option<String>>,
+}
+
+impl VisibilityNotes {
+    /// Container of a process, from the cache or from `lookup`.
+    fn container_id(
+        &mut self,
+        pid: i32,
+        lookup: impl FnOnce(i32) -> Option<String>,
+    ) -> Option<String> {
+        if self.containers.len() >= MAX_SANDBOXES && !self.containers.contains_key(&pid) {
+            self.containers.clear();
+        }
+        self.containers
+            .entry(pid)
+            .or_insert_with(|| lookup(pid))
+            .clone()
+    }
+
+    /// Forget an exited process, its pid may be reused.
+    fn forget(&mut self, pid: i32) {
+        self.containers.remove(&pid);
+    }
+
+    /// Note for the first event of a sandbox, identified by its container or,
+    /// when not found, by the exec chain of the process.
+    fn note(&mut self, event: &Event, container_id: Option<String>) -> Option<Event> {
+        let header = event.header();
+        if header.sandboxed_runtime.is_empty()
+            || matches!(event.payload(), Payload::DegradedVisibility { .. })
+        {
+            return None;
+        }
+        let key = container_id
+            .clone()
+            .unwrap_or_else(|| header.exec_chain_hash.clone());
+        if self.seen.len() >= MAX_SANDBOXES {
+            self.seen.clear();
+        }
+        if !self.seen.insert(key) {
+            return None;
+        }
+        let reason = match header.sandboxed_runtime.as_str() {
+            "gvisor" => GVISOR_REASON,
+            _ => VM_REASON,
+        };
+        Some(Event::new(
+            Header {
+                id: next_event_id(),
+                parent_event_id: Some(header.id),
+                threat: None,
+                source: DAEMON_SOURCE.into(),
+                coalesced: None,
+                ..header.clone()
+            },
+            Payload::DegradedVisibility {
+                runtime: header.sandboxed_runtime.clone(),
+                container_id: container_id.unwrap_or_default(),
+                reason: reason.to_string(),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn event(pid: i32, sandboxed_runtime: &str, exec_chain_hash: &str) -> Event {
+        Event::new(
+            Header {
+                id: pid as u64,
+                image: "/usr/local/bin/runsc".to_string(),
+                pid,
+                parent_pid: 1,
+                source: "process-monitor".into(),
+                exec_chain_hash: exec_chain_hash.to_string(),
+                sandboxed_runtime: sandboxed_runtime.to_string(),
+                ..Header::test_default()
+            },
+            Payload::Exit { exit_code: 0 },
+        )
+    }
+
+    #[test]
+    fn notes() {
+        let mut notes = VisibilityNotes::default();
+        let container = || Some("4f9d2a7c".to_string());
+
+        let note = notes.note(&event(10, "gvisor", "a1"), container()).unwrap();
+        assert_eq!(note.header().parent_event_id, Some(10));
+        assert_eq!(&*note.header().source, DAEMON_SOURCE);
+        let Payload::DegradedVisibility {
+            runtime,
+            container_id,
+            ..
+        } = note.payload()
+        else {
+            panic!("not a DegradedVisibility event");
+        };
+        assert_eq!(
+            (runtime.as_str(), container_id.as_str()),
+            ("gvisor", "4f9d2a7c")
+        );
+        // Once per container, including the note itself
+        assert!(notes
+            .note(&event(11, "gvisor", "b2"), container())
+            .is_none());
+        assert!(notes.note(&note, container()).is_none());
+
+        // Without a container, once per exec chain
+        assert!(notes.note(&event(12, "kata", "c3"), None).is_some());
+        assert!(notes.note(&event(13, "kata", "c3"), None).is_none());
+        assert!(notes.note(&event(14, "", "d4"), None).is_none());
+    }
+
+    #[test]
+    fn container_lookups() {
+        let mut notes = VisibilityNotes::default();
+        let mut lookups = 0;
+        let mut lookup = |pid| {
+            lookups += 1;
+            Some(format!("container-{pid}"))
+        };
+
+        for _ in 0..3 {
+            assert_eq!(
+                notes.container_id(10, &mut lookup).as_deref(),
+                Some("container-10")
+            );
+        }
+        notes.forget(10);
+        notes.container_id(10, &mut lookup);
+        assert_eq!(lookups, 2);
+    }
+}
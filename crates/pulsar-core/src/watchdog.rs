@@ -0,0 +1,344 @@
+//! Integration with the service manager, see sd_notify(3).
+//!
+//! The daemon tells systemd when it's ready, reloading its configuration or
+//! stopping, and pings the watchdog enabled with `WatchdogSec=`. The pings stop
+//! when the Tokio runtime is blocked, and the watchdog is triggered right away
+//! when the bus is stuck for the stall timeout:
+//!
+//! - sends started and none completed, like a sender deadlocked on a lock of a
+//!   middleware or of the coalescing
+//! - a module with events waiting in its receiver and none received, like a
+//!   consumer deadlocked or in an endless loop
+//!
+//! systemd then restarts the daemon according to `Restart=`.
+
+use std::{
+    collections::HashMap,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use nix::time::{clock_gettime, ClockId};
+
+use crate::bus::{self, ReceiverProgress, SendProgress};
+
+/// Connection to the notification socket of the service manager. Messages are
+/// dropped when the daemon isn't started by systemd with `Type=notify`.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    socket: Option<Arc<UnixDatagram>>,
+}
+
+impl Notifier {
+    /// Connect to the socket in `NOTIFY_SOCKET`, if set.
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+            return Self::default();
+        };
+        match Self::connect(&path) {
+            Ok(notifier) => notifier,
+            Err(err) => {
+                log::warn!("Error connecting to the systemd notification socket {path}: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Connect to a socket path, or to an abstract socket if it starts with `@`.
+    pub fn connect(path: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        match path.strip_prefix('@') {
+            Some(name) => socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+            None => socket.connect(path)?,
+        }
+        // A full socket must not block the daemon, the message is lost
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: Some(Arc::new(socket)),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Send a newline separated list of assignments, like `READY=1`.
+    pub fn notify(&self, state: &str) {
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send(state.as_bytes()) {
+                log::warn!("Error notifying systemd of {state:?}: {err}");
+            }
+        }
+    }
+
+    /// Startup completed, or reload completed after [`Notifier::reloading`].
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    /// The configuration is being reloaded. The timestamp lets systemd match
+    /// the notification with the reload it requested.
+    pub fn reloading(&self) {
+        let usec = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map(|now| Duration::from(now).as_micros())
+            .unwrap_or_default();
+        self.notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"));
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+}
+
+/// Interval between the pings of the watchdog, half of its timeout, if it's
+/// enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok();
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    parse_watchdog_interval(usec.as_deref(), pid.as_deref(), std::process::id())
+}
+
+/// `WATCHDOG_PID` is missing with older systemd versions, and names another
+/// process when the variables are inherited from the parent.
+fn parse_watchdog_interval(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Detects a bus with sends in flight and none completed for `timeout`. An
+/// idle bus is never stalled.
+#[derive(Debug)]
+pub struct StallDetector {
+    timeout: Duration,
+    last: SendProgress,
+    last_progress: Instant,
+}
+
+impl StallDetector {
+    pub fn new(timeout: Duration, progress: SendProgress, now: Instant) -> Self {
+        Self {
+            timeout,
+            last: progress,
+            last_progress: now,
+        }
+    }
+
+    /// Record the progress at `now`, returning if the bus is stalled.
+    /// Disabled with a zero timeout.
+    pub fn is_stalled(&mut self, progress: SendProgress, now: Instant) -> bool {
+        if progress.completed != self.last.completed || progress.in_flight() == 0 {
+            self.last_progress = now;
+        }
+        self.last = progress;
+        !self.timeout.is_zero() && now.duration_since(self.last_progress) >= self.timeout
+    }
+}
+
+/// Detects the receivers of the modules with pending events and none received
+/// for `timeout`. An idle receiver is never stalled.
+#[derive(Debug)]
+pub struct ReceiverStallDetector {
+    timeout: Duration,
+    /// Events received by every receiver, and when they last changed
+    last: HashMap<usize, (u64, Instant)>,
+}
+
+impl ReceiverStallDetector {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Record the progress of the receivers at `now`, returning the first
+    /// stalled one. Disabled with a zero timeout.
+    pub fn stalled<'a>(
+        &mut self,
+        receivers: &'a [ReceiverProgress],
+        now: Instant,
+    ) -> Option<&'a ReceiverProgress> {
+        let mut stalled = None;
+        let mut last = HashMap::with_capacity(receivers.len());
+        for receiver in receivers {
+            let last_progress = match self.last.get(&receiver.id) {
+                Some((received, since))
+                    if *received == receiver.received && receiver.pending > 0 =>
+                {
+                    *since
+                }
+                _ => now,
+            };
+            if !self.timeout.is_zero()
+                && now.duration_since(last_progress) >= self.timeout
+                && stalled.is_none()
+            {
+                stalled = Some(receiver);
+            }
+            last.insert(receiver.id, (receiver.received, last_progress));
+        }
+        // Dropped receivers are forgotten
+        self.last = last;
+        stalled
+    }
+}
+
+/// Ping the watchdog every `interval` while the bus and its consumers make
+/// progress, or trigger it when stalled for `stall_timeout`.
+pub fn start(notifier: Notifier, interval: Duration, stall_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        let mut detector = StallDetector::new(stall_timeout, bus::send_progress(), Instant::now());
+        let mut receivers = ReceiverStallDetector::new(stall_timeout);
+        loop {
+            ticks.tick().await;
+            let progress = bus::send_progress();
+            if detector.is_stalled(progress, Instant::now()) {
+                log::error!(
+                    "Bus stalled: {} events being sent and none completed in {}s, triggering the watchdog",
+                    progress.in_flight(),
+                    stall_timeout.as_secs()
+                );
+                notifier.notify("WATCHDOG=trigger");
+                return;
+            }
+            let progress = bus::receiver_progress();
+            if let Some(receiver) = receivers.stalled(&progress, Instant::now()) {
+                log::error!(
+                    "Module {} stalled: {} events waiting and none received in {}s, triggering the watchdog",
+                    receiver.name,
+                    receiver.pending,
+                    stall_timeout.as_secs()
+                );
+                notifier.notify("WATCHDOG=trigger");
+                return;
+            }
+            notifier.notify("WATCHDOG=1");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_interval() {
+        let interval = parse_watchdog_interval(Some("30000000"), Some("42"), 42);
+        assert_eq!(interval, Some(Duration::from_secs(15)));
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        // Inherited from the parent
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("1"), 42),
+            None
+        );
+        assert_eq!(parse_watchdog_interval(None, None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn stalled_bus() {
+        let progress = |started, completed| SendProgress { started, completed };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut detector = StallDetector::new(Duration::from_secs(30), progress(10, 10), start);
+        // Idle
+        assert!(!detector.is_stalled(progress(10, 10), at(60)));
+        // Busy and progressing
+        assert!(!detector.is_stalled(progress(20, 15), at(80)));
+        assert!(!detector.is_stalled(progress(30, 25), at(100)));
+        // Nothing completed since 100s
+        assert!(!detector.is_stalled(progress(31, 25), at(120)));
+        assert!(detector.is_stalled(progress(31, 25), at(130)));
+        // Unblocked
+        assert!(!detector.is_stalled(progress(31, 31), at(140)));
+
+        let mut disabled = StallDetector::new(Duration::ZERO, progress(1, 0), start);
+        assert!(!disabled.is_stalled(progress(1, 0), at(3600)));
+    }
+
+    #[test]
+    fn stalled_receiver() {
+        let receiver = |id, received, pending| ReceiverProgress {
+            id,
+            name: format!("module-{id}"),
+            received,
+            pending,
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut detector = ReceiverStallDetector::new(Duration::from_secs(30));
+        assert!(detector
+            .stalled(&[receiver(1, 10, 0), receiver(2, 10, 5)], start)
+            .is_none());
+        // Idle, and receiving
+        assert!(detector
+            .stalled(&[receiver(1, 10, 0), receiver(2, 20, 5)], at(60))
+            .is_none());
+        // Nothing received since 60s
+        assert!(detector
+            .stalled(&[receiver(1, 10, 0), receiver(2, 20, 8)], at(80))
+            .is_none());
+        let progress = [receiver(1, 10, 0), receiver(2, 20, 9)];
+        assert_eq!(detector.stalled(&progress, at(90)), Some(&progress[1]));
+        // Unblocked
+        assert!(detector
+            .stalled(&[receiver(1, 10, 0), receiver(2, 29, 0)], at(100))
+            .is_none());
+        // A new receiver starts from now
+        assert!(detector
+            .stalled(&[receiver(1, 10, 0), receiver(3, 0, 1)], at(200))
+            .is_none());
+
+        let mut disabled = ReceiverStallDetector::new(Duration::ZERO);
+        assert!(disabled.stalled(&[receiver(1, 0, 1)], start).is_none());
+        assert!(disabled.stalled(&[receiver(1, 0, 1)], at(3600)).is_none());
+    }
+
+    #[test]
+    fn notify() {
+        let path = std::env::temp_dir().join(format!("pulsar-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+        notifier.ready();
+        notifier.reloading();
+        let mut buf = [0; 128];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = systemd.recv(&mut buf).unwrap();
+        let reloading = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(reloading.starts_with("RELOADING=1\nMONOTONIC_USEC="));
+        std::fs::remove_file(&path).unwrap();
+
+        let name = format!("@pulsar-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(&name[1..]).unwrap();
+        let systemd = UnixDatagram::bind_addr(&addr).unwrap();
+        Notifier::connect(&name).unwrap().stopping();
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+
+        // Not started by systemd
+        let notifier = Notifier::default();
+        assert!(!notifier.is_enabled());
+        notifier.ready();
+    }
+}
@@ -44,6 +44,9 @@ use syn::{
 ///
 /// Incase of tuple structs (`struct MyStruct(i32)`) the name of the field is going to be the index (`0`, `1`, etc.).
 ///
+/// Fields can be excluded with `#[validatron(skip)]`. Fields behind a smart pointer, like `Arc<T>`,
+/// are exposed as the pointed type with `#[validatron(deref)]`, which generates `Box::new(|t| &*t.a)`.
+///
 /// Using it on an `enum` type is basically the same:
 ///
 /// ```ignore
@@ -165,8 +168,14 @@ fn process_struct_fields(fields: &Fields) -> Result<TokenStream2, Error> {
 
                     let field_name = field_ident.to_string();
 
-                    quote! {
-                       .add_field(#field_name, Box::new(|x| &x.#field_ident))
+                    if is_deref(&field.attrs) {
+                        quote! {
+                           .add_field(#field_name, Box::new(|x| &*x.#field_ident))
+                        }
+                    } else {
+                        quote! {
+                           .add_field(#field_name, Box::new(|x| &x.#field_ident))
+                        }
                     }
                 })
                 .collect();
@@ -179,13 +188,19 @@ fn process_struct_fields(fields: &Fields) -> Result<TokenStream2, Error> {
                 .iter()
                 .enumerate()
                 .filter(|(_index, field)| !is_skip(&field.attrs))
-                .map(|(index, _field)| {
+                .map(|(index, field)| {
                     let field_ident = Index::from(index);
 
                     let field_name = index.to_string();
 
-                    quote! {
-                       .add_field(#field_name, Box::new(|x| &x.#field_ident))
+                    if is_deref(&field.attrs) {
+                        quote! {
+                           .add_field(#field_name, Box::new(|x| &*x.#field_ident))
+                        }
+                    } else {
+                        quote! {
+                           .add_field(#field_name, Box::new(|x| &x.#field_ident))
+                        }
                     }
                 })
                 .collect();
@@ -234,7 +249,7 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
                     let variant_add_field_lines: Vec<_> = fields_named
                         .named
                         .iter()
-                        .filter(|field| !is_skip(&field.attrs) )
+                        .filter(|field| !is_skip(&field.attrs))
                         .map(|field| {
                             // Safe because we are processing named fields
                             let field_ident = match field.ident {
@@ -244,12 +259,18 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
 
                             let field_name = field_ident.to_string();
 
+                            let value = if is_deref(&field.attrs) {
+                                quote!(&**#field_ident)
+                            } else {
+                                quote!(#field_ident)
+                            };
+
                             quote! {
                             .add_variant_field(
                                     #variant_name,
                                     #field_name,
                                     Box::new(|t| match &t {
-                                        Self::#variant_ident { #field_ident, .. } => Some(#field_ident),
+                                        Self::#variant_ident { #field_ident, .. } => Some(#value),
                                         _ => None,
                                     }),
                                 )
@@ -268,19 +289,19 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
                         .unnamed
                         .iter()
                         .enumerate()
-                        .filter(|(_index,field)| !is_skip(&field.attrs) )
-                        .map(|(index, _field)| {
+                        .filter(|(_index, field)| !is_skip(&field.attrs))
+                        .map(|(index, field)| {
                             let field_name = index.to_string();
 
-                            let underscore_before =
-                                (0..index).map(|_|{
-                                    quote!(_,)
-                                });
+                            let value = if is_deref(&field.attrs) {
+                                quote!(&**x)
+                            } else {
+                                quote!(x)
+                            };
+
+                            let underscore_before = (0..index).map(|_| quote!(_,));
 
-                            let underscore_after =
-                                (index+1..fields_num).map(|_|{
-                                    quote!(_,)
-                                });
+                            let underscore_after = (index + 1..fields_num).map(|_| quote!(_,));
 
                             quote! {
                             .add_variant_field(
@@ -291,7 +312,7 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
                                             #(#underscore_before)*
                                             x,
                                             #(#underscore_after)*
-                                        ) => Some(x),
+                                        ) => Some(#value),
                                         _ => None,
                                     }),
                                 )
@@ -305,7 +326,7 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
                 }
                 syn::Fields::Unit => Err(Error::new(
                     variant.fields.span(),
-                    "Unit fields not supported, try to skip it {:?}"
+                    "Unit fields not supported, try to skip it {:?}",
                 )),
             };
 
@@ -319,13 +340,21 @@ fn process_enum_fields(variants: &Punctuated<Variant, Comma>) -> Result<TokenStr
 }
 
 fn is_skip(attrs: &Vec<Attribute>) -> bool {
+    has_attribute(attrs, "skip")
+}
+
+fn is_deref(attrs: &Vec<Attribute>) -> bool {
+    has_attribute(attrs, "deref")
+}
+
+fn has_attribute(attrs: &Vec<Attribute>, name: &str) -> bool {
     for attr in attrs {
-        let mut skip = false;
+        let mut found = false;
 
         if attr.path().is_ident("validatron") {
             attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("skip") {
-                    skip = true;
+                if meta.path.is_ident("skip") || meta.path.is_ident("deref") {
+                    found |= meta.path.is_ident(name);
                     Ok(())
                 } else {
                     Err(meta.error("unsupported attribute"))
@@ -334,7 +363,7 @@ fn is_skip(attrs: &Vec<Attribute>) -> bool {
             .unwrap();
         }
 
-        if skip {
+        if found {
             return true;
         }
     }
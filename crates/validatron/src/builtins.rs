@@ -1,7 +1,8 @@
 //! This module contains implementation for Rust primitive types and few types other types of the standard library
 
 use crate::{
-    MultiOperator, Operator, RelationalOperator, Validatron, ValidatronClass, ValidatronError,
+    operators::fold_case, MultiOperator, Operator, RelationalOperator, Validatron, ValidatronClass,
+    ValidatronError,
 };
 
 use std::{net::IpAddr, str::FromStr};
@@ -44,9 +45,26 @@ impl Validatron for String {
             Box::new(|op| match op {
                 Operator::String(op) => Ok(Box::new(move |a, b| op.apply(a, b))),
                 Operator::Relational(op) => Ok(Box::new(move |a, b| op.apply(a, b))),
-                Operator::Multi(op) => match op {
-                    MultiOperator::Contains => Ok(Box::new(move |a, b| a.contains(b))),
+                Operator::Multi(MultiOperator::Contains) => Ok(Box::new(move |a, b| a.contains(b))),
+                Operator::CaseInsensitive(op) => match *op {
+                    Operator::String(op) => {
+                        Ok(Box::new(move |a, b| op.apply(fold_case(a), fold_case(b))))
+                    }
+                    Operator::Relational(op) => {
+                        Ok(Box::new(move |a, b| op.apply(fold_case(a), fold_case(b))))
+                    }
+                    Operator::Multi(MultiOperator::Contains) => {
+                        Ok(Box::new(move |a, b| fold_case(a).contains(&*fold_case(b))))
+                    }
+                    op => Err(ValidatronError::OperatorNotAllowedOnType(
+                        Operator::CaseInsensitive(Box::new(op)),
+                        "String".to_string(),
+                    )),
                 },
+                op => Err(ValidatronError::OperatorNotAllowedOnType(
+                    op,
+                    "String".to_string(),
+                )),
             }),
         )
     }
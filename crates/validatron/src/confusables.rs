@@ -0,0 +1,77 @@
+//! Mapping of characters which look alike, used by
+//! [StringOperator::LooksLike](crate::StringOperator::LooksLike).
+//!
+//! This is a small subset of the confusables of Unicode Technical Standard #39,
+//! covering the Cyrillic, Greek and accented Latin letters, digits and letter
+//! pairs most used to imitate ASCII domain names.
+
+/// Returns the string with the characters replaced by the ASCII letter they
+/// look like, so that strings looking alike have the same skeleton.
+pub(crate) fn skeleton(value: &str) -> String {
+    let skeleton: String = value
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(prototype)
+        .collect();
+    skeleton.replace("rn", "m").replace("vv", "w")
+}
+
+/// Whether `value` looks like `other` without being the same string, ignoring case.
+pub(crate) fn looks_like(value: &str, other: &str) -> bool {
+    value.to_lowercase() != other.to_lowercase() && skeleton(value) == skeleton(other)
+}
+
+fn prototype(c: char) -> char {
+    match c {
+        'а' | 'ɑ' | 'α' | 'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ь' | 'ƅ' => 'b',
+        'с' | 'ϲ' | 'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ԁ' | 'ď' | 'đ' => 'd',
+        'е' | 'ё' | 'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ɡ' | 'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'һ' | 'ĥ' | 'ħ' => 'h',
+        'і' | 'ї' | 'ı' | 'ɩ' | 'ι' | 'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' => {
+            'i'
+        }
+        'ј' | 'ȷ' | 'ĵ' => 'j',
+        'к' | 'κ' | 'ķ' => 'k',
+        '1' | 'ӏ' | 'ℓ' | 'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ո' | 'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        '0' | 'о' | 'ο' | 'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ѕ' | 'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'τ' | 'ţ' | 'ť' | 'ŧ' => 't',
+        'υ' | 'ս' | 'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ν' | 'ѵ' => 'v',
+        'ԝ' | 'ŵ' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'ү' | 'γ' | 'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skeletons() {
+        // Cyrillic а
+        assert_eq!(skeleton("pаypal.com"), "paypal.com");
+        assert_eq!(skeleton("PAYPA1.COM"), "paypal.com");
+        assert_eq!(skeleton("rnicrosoft.com"), "microsoft.com");
+        assert_eq!(skeleton("gооglе.com"), "google.com");
+    }
+
+    #[test]
+    fn look_alike() {
+        assert!(looks_like("pаypal.com", "paypal.com"));
+        assert!(looks_like("examp1e.com", "example.com"));
+        assert!(!looks_like("paypal.com", "paypal.com"));
+        assert!(!looks_like("PayPal.com", "paypal.com"));
+        assert!(!looks_like("paypai.com", "paypal.com"));
+    }
+}
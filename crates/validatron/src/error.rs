@@ -22,4 +22,6 @@ pub enum ValidatronError {
     DifferentFieldsType,
     #[error("Collection value not primitive")]
     CollectionValueNotPrimitive,
+    #[error("Field of type {0} can't be compared, select one of its fields")]
+    FieldNotComparable(String),
 }
@@ -87,6 +87,7 @@ use serde::{Deserialize, Serialize};
 
 mod builtins;
 mod compiler;
+mod confusables;
 mod error;
 mod operators;
 mod reflection;
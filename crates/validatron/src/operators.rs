@@ -1,10 +1,10 @@
 //! This module contains operators available on [super::Primitive] types.
 
-use std::fmt;
+use std::{borrow::Cow, fmt};
 
 use serde::{Deserialize, Serialize};
 
-use crate::ValidatronError;
+use crate::{confusables, Quantifier, ValidatronError};
 
 /// An operator closure take two &T and returns whether the supplied arguments
 /// satisfy the Operator this closure implements.
@@ -24,6 +24,40 @@ pub enum Operator {
     Relational(RelationalOperator),
     String(StringOperator),
     Multi(MultiOperator),
+    /// The inner operator ignoring the case of strings, see
+    /// [`Operator::case_insensitive`]. Other types compare as usual.
+    CaseInsensitive(Box<Operator>),
+}
+
+impl Operator {
+    /// Ignore the case when comparing strings. Quantifiers apply it to the
+    /// items, and `Contains` on a collection of strings compares its items
+    /// ignoring the case.
+    ///
+    /// The strings are lowercased before comparing them. Values given in
+    /// rules are lowercased once, when compiling them for a `String` field;
+    /// the other types ignore this operator and parse their values as is.
+    pub fn case_insensitive(self) -> Self {
+        match self {
+            Operator::Multi(MultiOperator::Any(op)) => {
+                Operator::Multi(MultiOperator::Any(Box::new(op.case_insensitive())))
+            }
+            Operator::Multi(MultiOperator::All(op)) => {
+                Operator::Multi(MultiOperator::All(Box::new(op.case_insensitive())))
+            }
+            Operator::CaseInsensitive(_) => self,
+            op => Operator::CaseInsensitive(Box::new(op)),
+        }
+    }
+}
+
+/// Lowercase a string, without allocating if it has no uppercase letters.
+pub(crate) fn fold_case(value: &str) -> Cow<'_, str> {
+    if value.chars().any(char::is_uppercase) {
+        Cow::Owned(value.to_lowercase())
+    } else {
+        Cow::Borrowed(value)
+    }
 }
 
 impl fmt::Display for Operator {
@@ -37,6 +71,9 @@ impl fmt::Display for Operator {
 pub enum StringOperator {
     StartsWith,
     EndsWith,
+    /// Looks like the value without being equal to it, like `pаypal.com` with
+    /// a Cyrillic `а` and `paypal.com`
+    LooksLike,
     // Regex,
 }
 
@@ -45,6 +82,7 @@ impl StringOperator {
         match self {
             StringOperator::StartsWith => first.as_ref().starts_with(second.as_ref()),
             StringOperator::EndsWith => first.as_ref().ends_with(second.as_ref()),
+            StringOperator::LooksLike => confusables::looks_like(first.as_ref(), second.as_ref()),
         }
     }
 }
@@ -83,12 +121,28 @@ impl fmt::Display for RelationalOperator {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum MultiOperator {
     Contains,
+    /// At least one item of the collection satisfies the inner operator
+    Any(Box<Operator>),
+    /// Every item of the collection satisfies the inner operator
+    All(Box<Operator>),
+}
+
+impl MultiOperator {
+    /// Operator checking `op` on the items of a collection.
+    pub fn quantified(quantifier: Quantifier, op: Operator) -> Self {
+        match quantifier {
+            Quantifier::Any => MultiOperator::Any(Box::new(op)),
+            Quantifier::All => MultiOperator::All(Box::new(op)),
+        }
+    }
 }
 
 impl fmt::Display for MultiOperator {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             MultiOperator::Contains => write!(f, "contains"),
+            MultiOperator::Any(op) => write!(f, "any {op}"),
+            MultiOperator::All(op) => write!(f, "all {op}"),
         }
     }
 }
@@ -119,6 +119,17 @@ impl Enum {
             .remove(variant_name)
             .and_then(|mut field_map| field_map.remove(field_name))
     }
+
+    /// Returns the names of all the fields of the given variant.
+    pub fn variant_field_names<'a>(
+        &'a self,
+        variant_name: &str,
+    ) -> impl Iterator<Item = &'static str> + 'a {
+        self.variants
+            .get(variant_name)
+            .into_iter()
+            .flat_map(|field_map| field_map.keys().copied())
+    }
 }
 
 /// Enum attribute representation.
@@ -3,9 +3,14 @@ use std::{
     marker::PhantomData,
 };
 
-use crate::{Validatron, ValidatronClass, ValidatronClassKind, ValidatronError};
+use crate::{
+    Operator, RelationalOperator, Validatron, ValidatronClass, ValidatronClassKind, ValidatronError,
+};
 
-// The only operator currently supported on collections is MultiOperator::Contains.
+// Collections support MultiOperator::Contains, checking if an item is equal to the value,
+// and MultiOperator::Any and MultiOperator::All, checking if one or every item satisfies an
+// operator of the item type. Items which are not primitives are checked with a condition on
+// their fields, see Collection::quantifier_fn_items_unchecked.
 //
 // These closure types work over dyn Any to simplify code, but expect to be called with
 // the correct type.
@@ -13,12 +18,46 @@ use crate::{Validatron, ValidatronClass, ValidatronClassKind, ValidatronError};
 // When unsure about input correctness, the normal version must be called, which will return None
 // when the input type is wrong.
 //
-// Check if collection contains const value
+// Check if an item of the collection satisfies the operator with a const value
 type DynContainsFn = Box<dyn for<'c> Fn(&'c dyn Any) -> Option<bool> + Send + Sync>;
 type DynContainsFnUnchecked = Box<dyn for<'c> Fn(&'c dyn Any) -> bool + Send + Sync>;
-// Check if collection (second argument) contains the first argument
+// Check if an item of the collection (first argument) satisfies the operator with the second
+// argument
 type DynContainsMulti = Box<dyn Fn(&dyn Any, &dyn Any) -> Option<bool> + Send + Sync>;
 type DynContainsMultiUnchecked = Box<dyn Fn(&dyn Any, &dyn Any) -> bool + Send + Sync>;
+// Check if an item of the collection satisfies a condition
+type DynItemFnUnchecked = Box<dyn Fn(&dyn Any) -> bool + Send + Sync>;
+
+/// How many items of a collection must satisfy a condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+    Any,
+    All,
+}
+
+impl Quantifier {
+    fn apply<I>(self, mut items: impl Iterator<Item = I>, check: impl FnMut(I) -> bool) -> bool {
+        match self {
+            Quantifier::Any => items.any(check),
+            Quantifier::All => items.all(check),
+        }
+    }
+
+    /// Like [Quantifier::apply], returning `None` as soon as a check does.
+    fn try_apply<I>(
+        self,
+        items: impl Iterator<Item = I>,
+        mut check: impl FnMut(I) -> Option<bool>,
+    ) -> Option<bool> {
+        let all = self == Quantifier::All;
+        for item in items {
+            if check(item)? != all {
+                return Some(!all);
+            }
+        }
+        Some(all)
+    }
+}
 
 pub struct CollectionClassBuilder(());
 
@@ -57,7 +96,26 @@ impl Collection {
     }
 
     pub fn contains_fn_any_value(&self, value: &str) -> Result<DynContainsFn, ValidatronError> {
-        self.inner.contains_fn_any_value(value)
+        self.inner
+            .quantifier_fn_any_value(Quantifier::Any, equals(), value)
+    }
+
+    pub fn any_fn_any_value(
+        &self,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFn, ValidatronError> {
+        self.inner
+            .quantifier_fn_any_value(Quantifier::Any, op, value)
+    }
+
+    pub fn quantifier_fn_any_value(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFn, ValidatronError> {
+        self.inner.quantifier_fn_any_value(quantifier, op, value)
     }
 
     /// # Safety
@@ -68,11 +126,52 @@ impl Collection {
         &self,
         value: &str,
     ) -> Result<DynContainsFnUnchecked, ValidatronError> {
-        self.inner.contains_fn_any_value_unchecked(value)
+        self.inner
+            .quantifier_fn_any_value_unchecked(Quantifier::Any, equals(), value)
+    }
+
+    /// # Safety
+    ///
+    /// The `unsafe` is related to the returned function. That function accepts values as [Any],
+    /// but must be called with values of the right type, because it doesn't perform checks.
+    pub unsafe fn any_fn_any_value_unchecked(
+        &self,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFnUnchecked, ValidatronError> {
+        self.inner
+            .quantifier_fn_any_value_unchecked(Quantifier::Any, op, value)
+    }
+
+    /// # Safety
+    ///
+    /// The `unsafe` is related to the returned function. That function accepts values as [Any],
+    /// but must be called with values of the right type, because it doesn't perform checks.
+    pub unsafe fn quantifier_fn_any_value_unchecked(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFnUnchecked, ValidatronError> {
+        self.inner
+            .quantifier_fn_any_value_unchecked(quantifier, op, value)
     }
 
     pub fn contains_fn_any_multi(&self) -> Result<DynContainsMulti, ValidatronError> {
-        self.inner.contains_fn_any_multi()
+        self.inner
+            .quantifier_fn_any_multi(Quantifier::Any, equals())
+    }
+
+    pub fn any_fn_any_multi(&self, op: Operator) -> Result<DynContainsMulti, ValidatronError> {
+        self.inner.quantifier_fn_any_multi(Quantifier::Any, op)
+    }
+
+    pub fn quantifier_fn_any_multi(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+    ) -> Result<DynContainsMulti, ValidatronError> {
+        self.inner.quantifier_fn_any_multi(quantifier, op)
     }
 
     /// # Safety
@@ -82,25 +181,91 @@ impl Collection {
     pub unsafe fn contains_fn_any_multi_unchecked(
         &self,
     ) -> Result<DynContainsMultiUnchecked, ValidatronError> {
-        self.inner.contains_fn_any_multi_unchecked()
+        self.inner
+            .quantifier_fn_any_multi_unchecked(Quantifier::Any, equals())
     }
+
+    /// # Safety
+    ///
+    /// The `unsafe` is related to the returned function. That function accepts values as [Any],
+    /// but must be called with values of the right type, because it doesn't perform checks.
+    pub unsafe fn any_fn_any_multi_unchecked(
+        &self,
+        op: Operator,
+    ) -> Result<DynContainsMultiUnchecked, ValidatronError> {
+        self.inner
+            .quantifier_fn_any_multi_unchecked(Quantifier::Any, op)
+    }
+
+    /// # Safety
+    ///
+    /// The `unsafe` is related to the returned function. That function accepts values as [Any],
+    /// but must be called with values of the right type, because it doesn't perform checks.
+    pub unsafe fn quantifier_fn_any_multi_unchecked(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+    ) -> Result<DynContainsMultiUnchecked, ValidatronError> {
+        self.inner.quantifier_fn_any_multi_unchecked(quantifier, op)
+    }
+
+    /// Check the items of the collection with `item_fn`, a condition on the item type,
+    /// like one on the fields of a struct.
+    ///
+    /// # Safety
+    ///
+    /// The `unsafe` is related to the returned function. That function accepts values as [Any],
+    /// but must be called with values of the right type, because it doesn't perform checks.
+    /// The same applies to `item_fn`, which is called with the items of the collection.
+    pub unsafe fn quantifier_fn_items_unchecked(
+        &self,
+        quantifier: Quantifier,
+        item_fn: DynItemFnUnchecked,
+    ) -> DynContainsFnUnchecked {
+        self.inner
+            .quantifier_fn_items_unchecked(quantifier, item_fn)
+    }
+}
+
+/// Operator of [MultiOperator::Contains](crate::MultiOperator::Contains) on the items.
+fn equals() -> Operator {
+    Operator::Relational(RelationalOperator::Equals)
 }
 
 trait CollectionTypeDyn {
     fn get_value_class(&self) -> ValidatronClass;
 
-    fn contains_fn_any_value(&self, value: &str) -> Result<DynContainsFn, ValidatronError>;
+    fn quantifier_fn_any_value(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFn, ValidatronError>;
 
-    unsafe fn contains_fn_any_value_unchecked(
+    unsafe fn quantifier_fn_any_value_unchecked(
         &self,
+        quantifier: Quantifier,
+        op: Operator,
         value: &str,
     ) -> Result<DynContainsFnUnchecked, ValidatronError>;
 
-    fn contains_fn_any_multi(&self) -> Result<DynContainsMulti, ValidatronError>;
+    fn quantifier_fn_any_multi(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+    ) -> Result<DynContainsMulti, ValidatronError>;
 
-    unsafe fn contains_fn_any_multi_unchecked(
+    unsafe fn quantifier_fn_any_multi_unchecked(
         &self,
+        quantifier: Quantifier,
+        op: Operator,
     ) -> Result<DynContainsMultiUnchecked, ValidatronError>;
+
+    unsafe fn quantifier_fn_items_unchecked(
+        &self,
+        quantifier: Quantifier,
+        item_fn: DynItemFnUnchecked,
+    ) -> DynContainsFnUnchecked;
 }
 
 struct CollectionType<T, U>
@@ -123,87 +288,92 @@ where
         U::get_class()
     }
 
-    fn contains_fn_any_value(&self, value: &str) -> Result<DynContainsFn, ValidatronError> {
+    fn quantifier_fn_any_value(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+        value: &str,
+    ) -> Result<DynContainsFn, ValidatronError> {
         let ValidatronClassKind::Primitive(primitive) = U::get_class().into_kind() else {
             return Err(ValidatronError::CollectionValueNotPrimitive);
         };
 
-        let cmp = primitive.compare_fn_any_value(
-            crate::Operator::Relational(crate::RelationalOperator::Equals),
-            value,
-        )?;
+        let cmp = primitive.compare_fn_any_value(op, value)?;
 
         Ok(Box::new(move |source| {
-            source.downcast_ref::<T>().and_then(|source| {
-                for item in source.into_iter() {
-                    if cmp(item)? {
-                        return Some(true);
-                    }
-                }
-                Some(false)
-            })
+            source
+                .downcast_ref::<T>()
+                .and_then(|source| quantifier.try_apply(source.into_iter(), |item| cmp(item)))
         }))
     }
 
-    unsafe fn contains_fn_any_value_unchecked(
+    unsafe fn quantifier_fn_any_value_unchecked(
         &self,
+        quantifier: Quantifier,
+        op: Operator,
         value: &str,
     ) -> Result<DynContainsFnUnchecked, ValidatronError> {
         let ValidatronClassKind::Primitive(primitive) = U::get_class().into_kind() else {
             return Err(ValidatronError::CollectionValueNotPrimitive);
         };
 
-        let cmp = primitive.compare_fn_any_value_unchecked(
-            crate::Operator::Relational(crate::RelationalOperator::Equals),
-            value,
-        )?;
+        let cmp = primitive.compare_fn_any_value_unchecked(op, value)?;
 
         Ok(Box::new(move |source| {
             let source = &*(source as *const dyn Any as *const T);
 
-            source.into_iter().any(|item| cmp(item))
+            quantifier.apply(source.into_iter(), |item| cmp(item))
         }))
     }
 
-    fn contains_fn_any_multi(&self) -> Result<DynContainsMulti, ValidatronError> {
+    fn quantifier_fn_any_multi(
+        &self,
+        quantifier: Quantifier,
+        op: Operator,
+    ) -> Result<DynContainsMulti, ValidatronError> {
         let ValidatronClassKind::Primitive(primitive) = U::get_class().into_kind() else {
             return Err(ValidatronError::CollectionValueNotPrimitive);
         };
 
-        let cmp = primitive.compare_fn_any_multi(crate::Operator::Relational(
-            crate::RelationalOperator::Equals,
-        ))?;
+        let cmp = primitive.compare_fn_any_multi(op)?;
 
         Ok(Box::new(move |collection, second| {
             collection.downcast_ref::<T>().and_then(|collection| {
                 second.downcast_ref::<U>().and_then(|second| {
-                    for item in collection.into_iter() {
-                        if cmp(item, second)? {
-                            return Some(true);
-                        }
-                    }
-                    Some(false)
+                    quantifier.try_apply(collection.into_iter(), |item| cmp(item, second))
                 })
             })
         }))
     }
 
-    unsafe fn contains_fn_any_multi_unchecked(
+    unsafe fn quantifier_fn_any_multi_unchecked(
         &self,
+        quantifier: Quantifier,
+        op: Operator,
     ) -> Result<DynContainsMultiUnchecked, ValidatronError> {
         let ValidatronClassKind::Primitive(primitive) = U::get_class().into_kind() else {
             return Err(ValidatronError::CollectionValueNotPrimitive);
         };
 
-        let cmp = primitive.compare_fn_any_multi_unchecked(crate::Operator::Relational(
-            crate::RelationalOperator::Equals,
-        ))?;
+        let cmp = primitive.compare_fn_any_multi_unchecked(op)?;
 
         Ok(Box::new(move |collection, second| {
             let collection = &*(collection as *const dyn Any as *const T);
             let second = &*(second as *const dyn Any as *const U);
 
-            collection.into_iter().any(|item| cmp(item, second))
+            quantifier.apply(collection.into_iter(), |item| cmp(item, second))
         }))
     }
+
+    unsafe fn quantifier_fn_items_unchecked(
+        &self,
+        quantifier: Quantifier,
+        item_fn: DynItemFnUnchecked,
+    ) -> DynContainsFnUnchecked {
+        Box::new(move |source| {
+            let source = &*(source as *const dyn Any as *const T);
+
+            quantifier.apply(source.into_iter(), |item: &U| item_fn(item))
+        })
+    }
 }
@@ -1,6 +1,9 @@
-use std::any::{type_name, Any, TypeId};
+use std::{
+    any::{type_name, Any, TypeId},
+    borrow::Cow,
+};
 
-use crate::{HandleOperatorFn, Operator, Validatron, ValidatronError};
+use crate::{operators::fold_case, HandleOperatorFn, Operator, Validatron, ValidatronError};
 
 // These closure are variations over OperatorFn<T>, since they all
 // implementat a particular operator over two values.
@@ -123,7 +126,8 @@ where
         op: Operator,
         value: &str,
     ) -> Result<DynOperatorConst, ValidatronError> {
-        let value = (self.parse_fn)(value)?;
+        let op = case_sensitive_unless_string::<T>(op);
+        let value = (self.parse_fn)(&fold_value(&op, value))?;
 
         let compare_fn = (self.handle_op_fn)(op)?;
 
@@ -139,7 +143,8 @@ where
         op: Operator,
         value: &str,
     ) -> Result<DynOperatorConstUnchecked, ValidatronError> {
-        let value = (self.parse_fn)(value)?;
+        let op = case_sensitive_unless_string::<T>(op);
+        let value = (self.parse_fn)(&fold_value(&op, value))?;
 
         let compare_fn = (self.handle_op_fn)(op)?;
 
@@ -150,7 +155,7 @@ where
     }
 
     fn compare_fn_any_multi(&self, op: Operator) -> Result<DynOperatorMulti, ValidatronError> {
-        let compare_fn = (self.handle_op_fn)(op)?;
+        let compare_fn = (self.handle_op_fn)(case_sensitive_unless_string::<T>(op))?;
 
         Ok(Box::new(move |first, second| {
             if let Some(first) = first.downcast_ref() {
@@ -167,7 +172,7 @@ where
         &self,
         op: Operator,
     ) -> Result<DynOperatorMultiUnchecked, ValidatronError> {
-        let compare_fn = (self.handle_op_fn)(op)?;
+        let compare_fn = (self.handle_op_fn)(case_sensitive_unless_string::<T>(op))?;
 
         Ok(Box::new(move |first, second| {
             let first = &*(first as *const dyn Any as *const T);
@@ -181,3 +186,21 @@ where
         TypeId::of::<T>()
     }
 }
+
+/// Only strings can be compared ignoring the case, the other types use the
+/// inner operator.
+fn case_sensitive_unless_string<T: 'static>(op: Operator) -> Operator {
+    match op {
+        Operator::CaseInsensitive(op) if TypeId::of::<T>() != TypeId::of::<String>() => *op,
+        op => op,
+    }
+}
+
+/// Lowercase once the value compared with a string ignoring the case, so that
+/// only the other side is folded on every comparison.
+fn fold_value<'a>(op: &Operator, value: &'a str) -> Cow<'a, str> {
+    match op {
+        Operator::CaseInsensitive(_) => fold_case(value),
+        _ => Cow::Borrowed(value),
+    }
+}
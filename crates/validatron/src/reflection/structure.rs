@@ -97,6 +97,11 @@ impl Struct {
     pub fn get_field_owned(mut self, field_name: &str) -> Option<Attribute> {
         self.fields.remove(field_name)
     }
+
+    /// Returns the names of all the fields of the struct.
+    pub fn field_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.fields.keys().copied()
+    }
 }
 
 /// Struct attribute representation.
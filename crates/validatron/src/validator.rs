@@ -1,8 +1,8 @@
 use std::{any::Any, collections::VecDeque};
 
 use crate::{
-    Field, Match, MultiOperator, Operator, Validatron, ValidatronClass, ValidatronClassKind,
-    ValidatronError,
+    Field, Match, MultiOperator, Operator, Quantifier, RelationalOperator, Validatron,
+    ValidatronClass, ValidatronClassKind, ValidatronError,
 };
 
 /// Represents a valid rule for a type `T`.
@@ -27,6 +27,8 @@ struct ValidField<T: Validatron> {
 // Generic extractor function: given a T, extracts something
 type ExtractorFn<T> = Box<dyn Fn(&T) -> Option<&dyn Any> + Send + Sync>;
 type AnyExtractorFn = Box<dyn Fn(&dyn Any) -> Option<&dyn Any> + Send + Sync>;
+// Condition on a value given as Any, like an item of a collection
+type AnyRuleFn = Box<dyn Fn(&dyn Any) -> bool + Send + Sync>;
 
 /// Represents the chain of access functions starting from the top of a type `T`.
 enum ExtractorFrom<T: Validatron> {
@@ -58,6 +60,19 @@ pub fn get_valid_rule<T: Validatron + 'static>(
     op: Operator,
     value: Match,
 ) -> Result<ValidRule<T>, ValidatronError> {
+    let quantified = match &op {
+        Operator::Multi(MultiOperator::Any(op)) => Some((Quantifier::Any, op)),
+        Operator::Multi(MultiOperator::All(op)) => Some((Quantifier::All, op)),
+        _ => None,
+    };
+    if let Some((quantifier, item_op)) = quantified {
+        // The items of a collection in the middle of the path are checked one
+        // by one, like the `name` of every DNS question in `questions.name`
+        if let Some(len) = collection_path_len(T::get_class(), &field_path) {
+            return get_valid_items_rule(field_path, len, quantifier, (**item_op).clone(), value);
+        }
+    }
+
     let class = T::get_class();
 
     let first_field =
@@ -113,6 +128,17 @@ pub fn get_valid_rule<T: Validatron + 'static>(
         },
 
         ValidatronClassKind::Collection(collection) => {
+            // Compare the items with the value ignoring the case
+            let op = match op {
+                Operator::CaseInsensitive(op)
+                    if *op == Operator::Multi(MultiOperator::Contains) =>
+                {
+                    Operator::Multi(MultiOperator::Any(Box::new(
+                        Operator::Relational(RelationalOperator::Equals).case_insensitive(),
+                    )))
+                }
+                op => op,
+            };
             let Operator::Multi(op) = op else {
                 return Err(ValidatronError::OperatorNotAllowedOnType(
                     op,
@@ -126,6 +152,16 @@ pub fn get_valid_rule<T: Validatron + 'static>(
                         MultiOperator::Contains => unsafe {
                             collection.contains_fn_any_value_unchecked(&value)
                         },
+                        MultiOperator::Any(op) => unsafe {
+                            collection.any_fn_any_value_unchecked(*op, &value)
+                        },
+                        MultiOperator::All(op) => unsafe {
+                            collection.quantifier_fn_any_value_unchecked(
+                                Quantifier::All,
+                                *op,
+                                &value,
+                            )
+                        },
                     }?;
 
                     let extractor_fn = first_field.extractor.into_extract_fn();
@@ -159,7 +195,17 @@ pub fn get_valid_rule<T: Validatron + 'static>(
                     if collection_value_primitive.field_type_id()
                         == second_field_primitive.field_type_id()
                     {
-                        let compare_fn = unsafe { collection.contains_fn_any_multi_unchecked() }?;
+                        let compare_fn = match op {
+                            MultiOperator::Contains => unsafe {
+                                collection.contains_fn_any_multi_unchecked()
+                            },
+                            MultiOperator::Any(op) => unsafe {
+                                collection.any_fn_any_multi_unchecked(*op)
+                            },
+                            MultiOperator::All(op) => unsafe {
+                                collection.quantifier_fn_any_multi_unchecked(Quantifier::All, *op)
+                            },
+                        }?;
 
                         let first_extractor_fn = first_field.extractor.into_extract_fn();
                         let second_extractor_fn = second_field.extractor.into_extract_fn();
@@ -178,7 +224,12 @@ pub fn get_valid_rule<T: Validatron + 'static>(
                 }
             }
         }
-        _ => unreachable!("Expected only primitive and collection types"),
+        ValidatronClassKind::Struct(_) => {
+            Err(ValidatronError::FieldNotComparable("struct".to_string()))
+        }
+        ValidatronClassKind::Enum(_) => {
+            Err(ValidatronError::FieldNotComparable("enum".to_string()))
+        }
     };
 
     vr
@@ -249,11 +300,137 @@ fn get_valid_field_from_class<T: Validatron + 'static>(
     }
 }
 
+/// Length of the part of `field_path` leading to a collection, when it's
+/// followed by fields of its items. `None` for invalid paths, which are
+/// reported by the usual validation.
+fn collection_path_len(mut class: ValidatronClass, field_path: &[Field]) -> Option<usize> {
+    for (index, field) in field_path.iter().enumerate() {
+        class = match (class.into_kind(), field) {
+            (ValidatronClassKind::Collection(_), _) => return Some(index),
+            (ValidatronClassKind::Struct(ztruct), Field::Simple { field_name }) => {
+                ztruct.get_field_owned(field_name)?.get_class()
+            }
+            (
+                ValidatronClassKind::Enum(enumz),
+                Field::Adt {
+                    variant_name,
+                    field_name,
+                },
+            ) => enumz
+                .get_variant_field_owned(variant_name, field_name)?
+                .get_class(),
+            _ => return None,
+        };
+    }
+    None
+}
+
+/// Validate a condition on the items of the collection at `len` fields of
+/// `field_path`, where the rest of the path selects a field of the items.
+fn get_valid_items_rule<T: Validatron + 'static>(
+    mut field_path: Vec<Field>,
+    len: usize,
+    quantifier: Quantifier,
+    op: Operator,
+    value: Match,
+) -> Result<ValidRule<T>, ValidatronError> {
+    let item_path = field_path.split_off(len);
+    let collection_field =
+        get_valid_field_from_class::<T>(T::get_class(), field_path.into(), ExtractorFrom::None)?;
+    let ValidatronClassKind::Collection(collection) = collection_field.class.into_kind() else {
+        return Err(ValidatronError::FieldTypeError("collection".to_string()));
+    };
+    let Match::Value(value) = value else {
+        return Err(ValidatronError::ComparingFieldNotPrimitive);
+    };
+
+    let item_fn = get_valid_item_fn(collection.get_value_class(), item_path.into(), op, &value)?;
+    let quantifier_fn = unsafe { collection.quantifier_fn_items_unchecked(quantifier, item_fn) };
+    let extractor_fn = collection_field.extractor.into_extract_fn();
+
+    Ok(ValidRule {
+        rule_fn: Box::new(move |t| match extractor_fn(t) {
+            Some(collection) => quantifier_fn(collection),
+            None => false,
+        }),
+    })
+}
+
+/// Validate a condition on a field of the items of a collection, given the
+/// class of the items.
+fn get_valid_item_fn(
+    class: ValidatronClass,
+    mut field_path: VecDeque<Field>,
+    op: Operator,
+    value: &str,
+) -> Result<AnyRuleFn, ValidatronError> {
+    let Some(current_field) = field_path.pop_front() else {
+        return match class.into_kind() {
+            ValidatronClassKind::Primitive(primitive) => unsafe {
+                primitive.compare_fn_any_value_unchecked(op, value)
+            },
+            ValidatronClassKind::Collection(_) => Err(ValidatronError::OperatorNotAllowedOnType(
+                op,
+                "Collection".to_string(),
+            )),
+            ValidatronClassKind::Struct(_) => {
+                Err(ValidatronError::FieldNotComparable("struct".to_string()))
+            }
+            ValidatronClassKind::Enum(_) => {
+                Err(ValidatronError::FieldNotComparable("enum".to_string()))
+            }
+        };
+    };
+
+    match (class.into_kind(), current_field) {
+        (ValidatronClassKind::Struct(ztruct), Field::Simple { field_name }) => {
+            let Some(attribute) = ztruct.get_field_owned(&field_name) else {
+                return Err(ValidatronError::AttributeNotFound(field_name));
+            };
+            let inner_fn = get_valid_item_fn(attribute.get_class(), field_path, op, value)?;
+            let extractor_fn = unsafe { attribute.into_extractor_fn_unchecked() };
+            Ok(Box::new(move |item| inner_fn(extractor_fn(item))))
+        }
+        (
+            ValidatronClassKind::Enum(enumz),
+            Field::Adt {
+                variant_name,
+                field_name,
+            },
+        ) => {
+            let Some(variant) = enumz.get_variant_field_owned(&variant_name, &field_name) else {
+                return Err(ValidatronError::VariantAttributeNotFound(
+                    variant_name,
+                    field_name,
+                ));
+            };
+            let inner_fn = get_valid_item_fn(variant.get_class(), field_path, op, value)?;
+            let extractor_fn = unsafe { variant.into_extractor_fn_unchecked() };
+            Ok(Box::new(move |item| match extractor_fn(item) {
+                Some(field) => inner_fn(field),
+                None => false,
+            }))
+        }
+        (ValidatronClassKind::Struct(_), _) => {
+            Err(ValidatronError::FieldTypeError("struct".to_string()))
+        }
+        (ValidatronClassKind::Enum(_), _) => {
+            Err(ValidatronError::FieldTypeError("adt".to_string()))
+        }
+        (ValidatronClassKind::Primitive(_), _) => {
+            Err(ValidatronError::NoMoreFieldsError("primitive".to_string()))
+        }
+        (ValidatronClassKind::Collection(_), _) => {
+            Err(ValidatronError::NoMoreFieldsError("collection".to_string()))
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         validator::get_valid_rule, Field, Match, MultiOperator, Operator, RelationalOperator,
-        Validatron, ValidatronClass,
+        StringOperator, Validatron, ValidatronClass, ValidatronError,
     };
 
     #[test]
@@ -270,6 +447,30 @@ mod test {
         assert!(rule.is_match(&test))
     }
 
+    #[test]
+    fn test_struct_not_comparable() {
+        struct Wrapper {
+            i: i32,
+        }
+
+        impl Validatron for Wrapper {
+            fn get_class() -> ValidatronClass {
+                Self::class_builder()
+                    .struct_class_builder()
+                    .add_field("i", Box::new(|x| &x.i))
+                    .build()
+            }
+        }
+
+        let rule = get_valid_rule::<Wrapper>(
+            vec![],
+            Operator::Relational(RelationalOperator::Equals),
+            Match::Value("42".to_string()),
+        );
+
+        assert!(matches!(rule, Err(ValidatronError::FieldNotComparable(_))));
+    }
+
     #[test]
     fn test_primitive_in_struct() {
         struct Wrapper {
@@ -577,4 +778,136 @@ mod test {
 
         assert!(rule.is_match(&test))
     }
+
+    #[test]
+    fn test_vec_all() {
+        let rule = get_valid_rule::<Vec<i32>>(
+            vec![],
+            Operator::Multi(MultiOperator::All(Box::new(Operator::Relational(
+                RelationalOperator::Less,
+            )))),
+            Match::Value("60".to_string()),
+        )
+        .unwrap();
+
+        assert!(rule.is_match(&vec![10, 59]));
+        assert!(!rule.is_match(&vec![10, 300]));
+        assert!(rule.is_match(&vec![]));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let rule = |op: Operator, value: &str| {
+            get_valid_rule::<String>(
+                vec![],
+                op.case_insensitive(),
+                Match::Value(value.to_string()),
+            )
+            .unwrap()
+        };
+        let equals = rule(
+            Operator::Relational(RelationalOperator::Equals),
+            "/usr/bin/nc",
+        );
+        assert!(equals.is_match(&"/usr/bin/NC".to_string()));
+        assert!(!equals.is_match(&"/usr/bin/ncat".to_string()));
+        let ends_with = rule(Operator::String(StringOperator::EndsWith), ".exe");
+        assert!(ends_with.is_match(&"SETUP.EXE".to_string()));
+
+        let contains = get_valid_rule::<Vec<String>>(
+            vec![],
+            Operator::Multi(MultiOperator::Contains).case_insensitive(),
+            Match::Value("/usr/bin/nc".to_string()),
+        )
+        .unwrap();
+        assert!(contains.is_match(&vec!["/bin/sh".to_string(), "/usr/bin/NC".to_string()]));
+        assert!(!contains.is_match(&vec!["/usr/bin/ncat".to_string()]));
+
+        // Other types ignore the case
+        let greater = get_valid_rule::<i32>(
+            vec![],
+            Operator::Relational(RelationalOperator::Greater).case_insensitive(),
+            Match::Value("3".to_string()),
+        )
+        .unwrap();
+        assert!(greater.is_match(&4));
+    }
+
+    #[test]
+    fn test_vec_of_structs() {
+        struct Answer {
+            ttl: u32,
+        }
+
+        impl Validatron for Answer {
+            fn get_class() -> ValidatronClass {
+                Self::class_builder()
+                    .struct_class_builder()
+                    .add_field("ttl", Box::new(|x| &x.ttl))
+                    .build()
+            }
+        }
+
+        struct Response {
+            answers: Vec<Answer>,
+        }
+
+        impl Validatron for Response {
+            fn get_class() -> ValidatronClass {
+                Self::class_builder()
+                    .struct_class_builder()
+                    .add_field("answers", Box::new(|x| &x.answers))
+                    .build()
+            }
+        }
+
+        let field_path = || {
+            vec![
+                Field::Simple {
+                    field_name: "answers".to_string(),
+                },
+                Field::Simple {
+                    field_name: "ttl".to_string(),
+                },
+            ]
+        };
+        let less = || Box::new(Operator::Relational(RelationalOperator::Less));
+        let any = get_valid_rule::<Response>(
+            field_path(),
+            Operator::Multi(MultiOperator::Any(less())),
+            Match::Value("60".to_string()),
+        )
+        .unwrap();
+        let all = get_valid_rule::<Response>(
+            field_path(),
+            Operator::Multi(MultiOperator::All(less())),
+            Match::Value("60".to_string()),
+        )
+        .unwrap();
+
+        let test = Response {
+            answers: vec![Answer { ttl: 30 }, Answer { ttl: 300 }],
+        };
+        assert!(any.is_match(&test));
+        assert!(!all.is_match(&test));
+
+        // Fields of the items need a quantifier
+        let rule = get_valid_rule::<Response>(
+            field_path(),
+            Operator::Relational(RelationalOperator::Less),
+            Match::Value("60".to_string()),
+        );
+        assert!(matches!(rule, Err(ValidatronError::NoMoreFieldsError(_))));
+
+        let mut path = field_path();
+        path[1] = Field::Simple {
+            field_name: "name".to_string(),
+        };
+        let rule = get_valid_rule::<Response>(
+            path,
+            Operator::Multi(MultiOperator::Any(less())),
+            Match::Value("60".to_string()),
+        );
+        assert!(matches!(rule, Err(ValidatronError::AttributeNotFound(_))));
+    }
 }
@@ -32,7 +32,10 @@ async fn main() -> Result<()> {
 
 async fn spawn_pulsar() -> Result<ModuleContext> {
     let options = cli::PulsarExecOpts {
-        mode: cli::Mode::PulsarDaemon(cli::pulsard::PulsarDaemonOpts { config_file: None }),
+        mode: cli::Mode::PulsarDaemon(cli::pulsard::PulsarDaemonOpts {
+            config_file: None,
+            early_boot: false,
+        }),
         override_log_level: log::Level::Info,
     };
 
@@ -1,9 +1,12 @@
 //! A showcase of what a Pulsar modules can do
 use std::{collections::HashMap, sync::Arc};
 
-use pulsar_core::pdk::{
-    CleanExit, ConfigError, Event, ModuleConfig, ModuleContext, ModuleError, Payload, PulsarModule,
-    ShutdownSignal, Version,
+use pulsar_core::{
+    event::Severity,
+    pdk::{
+        CleanExit, ConfigError, Event, ModuleConfig, ModuleContext, ModuleError, Payload,
+        PulsarModule, ShutdownSignal, Version,
+    },
 };
 
 const MODULE_NAME: &str = "my-custom-module";
@@ -58,6 +61,7 @@ async fn module_task(
                             sender.send_threat_derived(
                                 &event,
                                 desc,
+                                Severity::High,
                                 Some(extra.into())
                             );
                         }
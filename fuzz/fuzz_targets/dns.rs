@@ -0,0 +1,12 @@
+//! Extract DNS events from arbitrary network messages.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(payload) = network_monitor::dns::parse_dns(data) {
+        // Formatting reads every parsed field
+        let _ = payload.to_string();
+    }
+});
@@ -0,0 +1,19 @@
+//! Parse and compile arbitrary rule conditions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rules_engine::{CustomPayloads, RuleEngine, RuleFormat};
+
+fuzz_target!(|condition: &str| {
+    let rules = serde_json::json!([
+        { "name": "exec", "type": "Exec", "condition": condition },
+        { "name": "connect", "type": "Connect", "condition": condition },
+        { "name": "dns", "type": "DnsQuery", "condition": condition },
+    ]);
+    let _ = RuleEngine::from_str(
+        &rules.to_string(),
+        RuleFormat::Json,
+        &CustomPayloads::default(),
+    );
+});
@@ -0,0 +1,12 @@
+//! Load arbitrary rule files, in all the supported formats.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rules_engine::{CustomPayloads, RuleEngine, RuleFormat};
+
+fuzz_target!(|body: &str| {
+    for format in [RuleFormat::Yaml, RuleFormat::Json, RuleFormat::Toml] {
+        let _ = RuleEngine::from_str(body, format, &CustomPayloads::default());
+    }
+});
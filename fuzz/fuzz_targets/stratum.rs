@@ -0,0 +1,9 @@
+//! Classify arbitrary sent data as stratum requests.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = network_monitor::stratum::parse_request(data);
+});
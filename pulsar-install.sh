@@ -102,6 +102,9 @@ main() {
 
     # Download basic rules
     ensure downloader "https://raw.githubusercontent.com/Exein-io/pulsar/main/rules/basic-rules.yaml" "${_dir}/basic-rules.yaml"
+    ensure downloader "https://raw.githubusercontent.com/Exein-io/pulsar/main/rules/miners.yaml" "${_dir}/miners.yaml"
+    ensure downloader "https://raw.githubusercontent.com/Exein-io/pulsar/main/rules/sysctl.yaml" "${_dir}/sysctl.yaml"
+    ensure downloader "https://raw.githubusercontent.com/Exein-io/pulsar/main/rules/packages.yaml" "${_dir}/packages.yaml"
 
     printf '%s\n' 'info: installing files' 1>&2
 
@@ -139,6 +142,9 @@ main() {
 
     # Install basic rules
     ensure $_install -m 644 "${_dir}/basic-rules.yaml" ${_pulsar_rules_dir}
+    ensure $_install -m 644 "${_dir}/miners.yaml" ${_pulsar_rules_dir}
+    ensure $_install -m 644 "${_dir}/sysctl.yaml" ${_pulsar_rules_dir}
+    ensure $_install -m 644 "${_dir}/packages.yaml" ${_pulsar_rules_dir}
 
     printf '%s\n' 'info: cleaning' 1>&2
     ignore rm -rf "$_dir"
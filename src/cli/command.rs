@@ -0,0 +1,40 @@
+//! Definition of the `pulsar-exec` commands, shared with the build script, which
+//! generates the shell completion scripts and the JSON help from it.
+
+use clap::{Arg, ArgAction, Command, CommandFactory};
+
+use super::{pulsar, pulsard};
+
+/// Definition of the `pulsar-exec` launcher, with `pulsard` and `pulsar` as
+/// subcommands.
+pub fn launcher(version: &'static str, daemon: Command, cli: Command) -> Command {
+    Command::new("pulsar-exec")
+        .version(version)
+        .about("Pulsar executables launcher")
+        .propagate_version(true)
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .disable_help_subcommand(true)
+        .subcommand(daemon)
+        .subcommand(cli)
+}
+
+/// Definition of the `pulsard` daemon.
+pub fn pulsard_command() -> Command {
+    with_verbosity_flag(pulsard::PulsarDaemonOpts::command())
+}
+
+/// Definition of the `pulsar` CLI, as run by the `pulsar` script.
+pub fn pulsar_command() -> Command {
+    with_verbosity_flag(pulsar::PulsarCliOpts::command())
+}
+
+fn with_verbosity_flag(app: Command) -> Command {
+    app.arg(
+        Arg::new("v")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .help("Pass many times for a more verbose output. Passing `-v` adds debug logs, `-vv` enables trace logging"),
+    )
+}
@@ -0,0 +1,88 @@
+//! Machine-readable help, printed with `--help --format json` in place of the
+//! usual help text, for tools and scripts wrapping the CLI. The description of
+//! the commands is generated by the build script, in `command_json`.
+
+use std::ffi::OsString;
+
+use serde_json::Value;
+
+/// Description of `pulsar-exec` and all its subcommands.
+const HELP: &str = include_str!(concat!(env!("OUT_DIR"), "/help.json"));
+
+/// The help of the (sub)command selected by `args`, if it was requested in
+/// JSON.
+pub fn requested(args: &[OsString]) -> Option<Value> {
+    let args: Vec<&str> = args.iter().skip(1).filter_map(|arg| arg.to_str()).collect();
+    let help = args.contains(&"--help") || args.contains(&"-h");
+    let json = args.contains(&"--format=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--format" && pair[1] == "json");
+    if !help || !json {
+        return None;
+    }
+
+    let help: Value = serde_json::from_str(HELP).expect("invalid help.json");
+    let mut command = &help;
+    for arg in args.iter().filter(|arg| !arg.starts_with('-')) {
+        match subcommand(command, arg) {
+            Some(subcommand) => command = subcommand,
+            None => break,
+        }
+    }
+    Some(command.clone())
+}
+
+fn subcommand<'a>(command: &'a Value, name: &str) -> Option<&'a Value> {
+    command["subcommands"]
+        .as_array()?
+        .iter()
+        .find(|subcommand| subcommand["name"] == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn requested_help() {
+        let help = requested(&args(&[
+            "pulsar-exec",
+            "pulsar",
+            "threats",
+            "ack",
+            "--help",
+            "--format",
+            "json",
+        ]))
+        .unwrap();
+        assert_eq!(help["name"], "ack");
+        let ttl = help["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|arg| arg["name"] == "ttl")
+            .unwrap();
+        assert_eq!(ttl["long"], "ttl");
+        assert_eq!(ttl["default"][0], "3600");
+        assert_eq!(ttl["takes_value"], true);
+
+        // Unknown subcommands stop at their parent
+        let help = requested(&args(&[
+            "pulsar-exec",
+            "pulsar",
+            "nope",
+            "--help",
+            "--format=json",
+        ]));
+        assert_eq!(help.unwrap()["name"], "pulsar");
+
+        // Plain help is printed by clap
+        assert!(requested(&args(&["pulsar-exec", "pulsar", "--help"])).is_none());
+        assert!(requested(&args(&["pulsar-exec", "pulsar", "--format", "json"])).is_none());
+    }
+}
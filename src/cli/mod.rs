@@ -1,7 +1,9 @@
 use std::{env, ffi::OsString};
 
-use clap::{Arg, ArgAction, Command, CommandFactory, FromArgMatches};
+use clap::{Command, FromArgMatches};
 
+mod command;
+mod help_json;
 pub mod pulsar;
 pub mod pulsard;
 
@@ -26,7 +28,12 @@ where
     I: Iterator<Item = T>,
     T: Into<OsString> + Clone,
 {
-    try_parse_from(args).unwrap_or_else(|e| e.exit())
+    let args: Vec<OsString> = args.map(Into::into).collect();
+    if let Some(help) = help_json::requested(&args) {
+        println!("{help:#}");
+        std::process::exit(0);
+    }
+    try_parse_from(args.into_iter()).unwrap_or_else(|e| e.exit())
 }
 
 pub fn try_parse_from<I, T>(args: I) -> Result<PulsarExecOpts, clap::Error>
@@ -34,30 +41,7 @@ where
     I: Iterator<Item = T>,
     T: Into<OsString> + Clone,
 {
-    // We wand different help templates depending on how the executable is invoked
-    let template_kind = match env::var("MASK_LAUNCHER") {
-        Ok(v) if v == "1" => HelpTemplate::MaskedSubcommand,
-        _ => HelpTemplate::RawSubcommand,
-    };
-
-    let template = help_template("pulsar-exec", HelpTemplate::RawExecutable, true, true);
-    let daemon_template = help_template(pulsard::NAME, template_kind, true, false);
-    let cli_template = help_template(pulsar::NAME, template_kind, true, true);
-
-    let daemon_app = pulsard::PulsarDaemonOpts::command().help_template(daemon_template);
-    let cli_app = pulsar::PulsarCliOpts::command().help_template(cli_template);
-
-    let matches = Command::new("pulsar-exec")
-        .version(crate::version())
-        .about("Pulsar executables launcher")
-        .propagate_version(true)
-        .subcommand_required(true)
-        .arg_required_else_help(true)
-        .disable_help_subcommand(true)
-        .help_template(template)
-        .subcommand(with_verbosity_flag(daemon_app))
-        .subcommand(with_verbosity_flag(cli_app))
-        .try_get_matches_from(args)?;
+    let matches = command().try_get_matches_from(args)?;
 
     let override_log_level;
     let mode = match matches.subcommand() {
@@ -83,14 +67,25 @@ where
     })
 }
 
-fn with_verbosity_flag(app: Command) -> Command {
-    app.arg(
-        Arg::new("v")
-            .short('v')
-            .long("verbose")
-            .action(ArgAction::Count)
-            .help("Pass many times for a more verbose output. Passing `-v` adds debug logs, `-vv` enables trace logging"),
+/// Definition of the `pulsar-exec` launcher, with `pulsard` and `pulsar` as
+/// subcommands.
+fn command() -> Command {
+    // We wand different help templates depending on how the executable is invoked
+    let template_kind = match env::var("MASK_LAUNCHER") {
+        Ok(v) if v == "1" => HelpTemplate::MaskedSubcommand,
+        _ => HelpTemplate::RawSubcommand,
+    };
+
+    let template = help_template("pulsar-exec", HelpTemplate::RawExecutable, true, true);
+    let daemon_template = help_template(pulsard::NAME, template_kind, true, false);
+    let cli_template = help_template(pulsar::NAME, template_kind, true, true);
+
+    command::launcher(
+        crate::version(),
+        command::pulsard_command().help_template(daemon_template),
+        command::pulsar_command().help_template(cli_template),
     )
+    .help_template(template)
 }
 
 fn log_level_from_verbosity_flag_count(num: u8) -> log::Level {
@@ -1,5 +1,5 @@
 use anyhow::{ensure, Result};
-use clap::{ArgGroup, Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 
 pub const NAME: &str = "pulsar";
 
@@ -18,7 +18,12 @@ pub struct PulsarCliOpts {
 #[derive(Debug, Clone, Subcommand)]
 pub enum Commands {
     /// Modules status
-    Status,
+    Status {
+        /// Show the invocations and run time of every eBPF program instead,
+        /// collected when the daemon runs with `probe_stats=true`
+        #[clap(long)]
+        probe_stats: bool,
+    },
 
     /// Start a module
     Start { module_name: String },
@@ -34,6 +39,52 @@ pub enum Commands {
 
     /// Start event monitor
     Monitor(Monitor),
+
+    /// Notify the daemon started with `--early-boot` that the boot is completed
+    BootComplete,
+
+    /// Install the systemd units, the bpffs mount and the configuration skeleton
+    Install(Install),
+
+    /// Print or install the shell completion scripts
+    Completions(Completions),
+
+    /// Export the events of an incident kept by the daemon
+    Export(Export),
+
+    /// Check the kernel support of the probes and explain why modules failed
+    Doctor,
+
+    /// List the sockets listening on the host with their process and container
+    Listeners {
+        #[clap(long, value_enum, default_value_t = ListenersFormat::Table)]
+        format: ListenersFormat,
+    },
+
+    /// Send synthetic events on the bus of the daemon to measure its throughput
+    Loadgen(Loadgen),
+
+    /// Acknowledge recurring threats, silencing them on this host for a while
+    Threats(Threats),
+
+    /// Inspect the rules loaded by the rules-engine module
+    Rules(Rules),
+
+    /// Manage the files quarantined by the threat-response module
+    #[cfg(feature = "threat-response")]
+    Quarantine(Quarantine),
+
+    /// Manage the allowlist of the exec-allowlist module
+    #[cfg(feature = "exec-allowlist")]
+    Baseline(Baseline),
+
+    /// Generate network policies from the traffic learned by the network-policy module
+    #[cfg(feature = "network-policy")]
+    Policy(Policy),
+
+    /// Check the hash chain of the threats log written by the logger module
+    #[cfg(feature = "logger")]
+    VerifyLog(VerifyLog),
 }
 
 // THIS "SHIM" STRUCT IS MANDATORY
@@ -41,7 +92,7 @@ pub enum Commands {
 #[clap(group(
     ArgGroup::new("config_variant")
         .required(true)
-        .args(&["all", "module", "set"]),
+        .args(&["all", "module", "set", "schema"]),
 ))]
 pub struct Config {
     /// Print configuration for all modules
@@ -55,6 +106,10 @@ pub struct Config {
     /// Set/Update configuration for a specified module: syntax is 'MODULE.KEY=VALUE'
     #[clap(long, short, value_parser=parse_mc_key_value, value_name = "MODULE.KEY=VALUE")]
     pub set: Option<ModuleConfigKV>,
+
+    /// Print the configuration schema of all modules
+    #[clap(long)]
+    pub schema: bool,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -71,6 +126,232 @@ pub struct Monitor {
     pub all: bool,
 }
 
+#[derive(Parser, Debug, Clone)]
+pub struct Install {
+    /// Directory under which the files are installed, to prepare a system image
+    #[clap(long, default_value = "/")]
+    pub root: std::path::PathBuf,
+
+    /// Path of the pulsar-exec executable run by the units
+    #[clap(long, default_value = "/usr/bin/pulsar-exec")]
+    pub exec: std::path::PathBuf,
+
+    /// Start the daemon during early boot, see `pulsard --early-boot`
+    #[clap(long)]
+    pub early_boot: bool,
+
+    /// Overwrite the existing files
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Completions {
+    /// Shell of the script printed on the standard output
+    #[clap(value_enum, required_unless_present = "install")]
+    pub shell: Option<clap_complete::Shell>,
+
+    /// Install the scripts of bash, zsh and fish in their system directories
+    #[clap(long, conflicts_with = "shell")]
+    pub install: bool,
+
+    /// Directory under which the scripts are installed, to prepare a system image
+    #[clap(long, default_value = "/")]
+    pub root: std::path::PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Export {
+    /// Export the events of this process and its descendants
+    #[clap(long)]
+    pub pid: Option<i32>,
+
+    /// Start time, RFC 3339 or relative to now, like `30m`, `2h` or `1d`
+    #[clap(long)]
+    pub since: Option<String>,
+
+    /// End time, RFC 3339 or relative to now
+    #[clap(long)]
+    pub until: Option<String>,
+
+    #[clap(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+    pub format: ExportFormat,
+
+    /// Output file, by default the standard output
+    #[clap(long, short)]
+    pub output: Option<std::path::PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Loadgen {
+    /// Events per second
+    #[clap(long, default_value_t = 10_000)]
+    pub rate: u64,
+
+    /// Seconds of load
+    #[clap(long, default_value_t = 10)]
+    pub duration: u64,
+
+    /// Relative weight of Exec events
+    #[clap(long, default_value_t = 1)]
+    pub exec: u32,
+
+    /// Relative weight of Connect events
+    #[clap(long, default_value_t = 2)]
+    pub connect: u32,
+
+    /// Relative weight of Send events
+    #[clap(long, default_value_t = 7)]
+    pub send: u32,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Threats {
+    #[clap(subcommand)]
+    pub command: ThreatsCommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ThreatsCommand {
+    /// List the acknowledged threats
+    List,
+
+    /// Keep the threats with this id, shown in their `threat.id`, from the output modules
+    Ack {
+        id: String,
+
+        /// Seconds for which the threat is silenced
+        #[clap(long, default_value_t = 3600)]
+        ttl: u64,
+
+        /// Why the threat is acknowledged
+        #[clap(long)]
+        reason: Option<String>,
+    },
+
+    /// Notify again the threats with this id
+    Unack { id: String },
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Rules {
+    #[clap(subcommand)]
+    pub command: RulesCommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum RulesCommand {
+    /// Show the evaluations, matches and evaluation time of every rule, slowest first
+    Stats,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON event per line
+    Jsonl,
+    /// HTTP Archive with an entry for every network connection
+    Har,
+    /// Human readable report with process tree, threats, network flows and files
+    Timeline,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenersFormat {
+    Table,
+    /// JSON array, for network policy generators and asset inventories
+    Json,
+}
+
+#[cfg(feature = "threat-response")]
+#[derive(Parser, Debug, Clone)]
+pub struct Quarantine {
+    /// Quarantine folder, by default the `quarantine_path` of the running daemon
+    #[clap(long)]
+    pub path: Option<std::path::PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: QuarantineCommand,
+}
+
+#[cfg(feature = "threat-response")]
+#[derive(Debug, Clone, Subcommand)]
+pub enum QuarantineCommand {
+    /// List the quarantined files
+    List,
+
+    /// Move a quarantined file back to its original path
+    Restore { id: String },
+}
+
+#[cfg(feature = "exec-allowlist")]
+#[derive(Parser, Debug, Clone)]
+pub struct Baseline {
+    #[clap(subcommand)]
+    pub command: BaselineCommand,
+}
+
+#[cfg(feature = "exec-allowlist")]
+#[derive(Debug, Clone, Subcommand)]
+pub enum BaselineCommand {
+    /// Write the hashes of the executables found in the given directories to the allowlist
+    Record {
+        /// Allowlist file, by default the `allowlist_path` of the running daemon
+        #[clap(long, short)]
+        output: Option<std::path::PathBuf>,
+
+        /// Directories to record, by default the `scoped_paths` of the running daemon
+        paths: Vec<std::path::PathBuf>,
+    },
+}
+
+#[cfg(feature = "network-policy")]
+#[derive(Parser, Debug, Clone)]
+pub struct Policy {
+    /// Learned profile, by default the `profile_path` of the running daemon
+    #[clap(long)]
+    pub profile: Option<std::path::PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: PolicyCommand,
+}
+
+#[cfg(feature = "network-policy")]
+#[derive(Debug, Clone, Subcommand)]
+pub enum PolicyCommand {
+    /// Print a policy allowing the traffic learned
+    Generate {
+        #[clap(long, value_enum, default_value_t = PolicyFormat::Rules)]
+        format: PolicyFormat,
+
+        /// Name of the Kubernetes NetworkPolicy
+        #[clap(long, default_value = "pulsar-learned")]
+        name: String,
+    },
+}
+
+#[cfg(feature = "network-policy")]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyFormat {
+    /// Rules of the rules-engine module raising threats for the unexpected connections
+    Rules,
+    /// Kubernetes NetworkPolicy
+    NetworkPolicy,
+    /// nftables ruleset dropping the unexpected connections
+    Nftables,
+}
+
+#[cfg(feature = "logger")]
+#[derive(Parser, Debug, Clone)]
+pub struct VerifyLog {
+    /// Log written to the `chain_path` of the logger module
+    pub path: std::path::PathBuf,
+
+    /// Hash of a head anchored to the journal or a remote endpoint, which must
+    /// be in the log
+    #[clap(long)]
+    pub anchor: Option<String>,
+}
+
 fn parse_mc_key_value(input: &str) -> Result<ModuleConfigKV> {
     // split 'module_name.config_name=config_value'
     let parts: Vec<&str> = input.split('=').filter(|s| !s.is_empty()).collect();
@@ -8,4 +8,14 @@ pub const NAME: &str = "pulsard";
 pub struct PulsarDaemonOpts {
     #[clap(long)]
     pub config_file: Option<String>,
+
+    /// Start during early boot: modules which need a fully booted system are deferred
+    /// until `pulsar boot-complete` is run, receiving the events buffered in the meantime
+    #[clap(long)]
+    pub early_boot: bool,
+
+    /// Run the modules on the events of a JSON lines file, like the ones written by
+    /// `pulsar export`, instead of the eBPF probes, and exit when they are handled
+    #[clap(long, value_name = "FILE", conflicts_with = "early_boot")]
+    pub replay: Option<std::path::PathBuf>,
 }
@@ -46,7 +46,9 @@
 //! - `default`: Enables core and extra.
 //! - `core`: Enables all the monitoring features listed below, specifically
 //!           `  logger`, `process-monitor`, `network-monitor` and `file-system-monitor`.
-//! - `extra`: Enables the rule-engine feature.
+//! - `extra`: Enables the rule-engine, notifiers, proc-metrics, threat-response, exec-allowlist,
+//!            dns-exfiltration, network-policy, canary, honeypot, vulnerabilities and
+//!            self-protection features.
 //! - `logger`: Enables the event logger to print threat events in the console.
 //! - `process-monitor`: Enables a monitor on processes lifecycle and manages the list
 //!                      of interesting applications. It's considered a core module and
@@ -55,6 +57,15 @@
 //!                      dns events.
 //! - `file-system-monitor`: Enables a monitor on file system events, example file open, delete, ecc.
 //! - `rules-engine`: Enables the rule engine module to process events and detect threats.
+//! - `proc-metrics`: Enables a sampler of CPU, memory and IO usage of processes.
+//! - `threat-response`: Enables the playbooks of response actions run on threats.
+//! - `exec-allowlist`: Enables threats for executables missing from an allowlist of hashes.
+//! - `dns-exfiltration`: Enables threats for processes tunneling data in DNS queries.
+//! - `network-policy`: Enables the learning of the network traffic to generate network policies.
+//! - `canary`: Enables threats for accesses to canary files and credentials.
+//! - `honeypot`: Enables threats for connections to decoy listening ports.
+//! - `vulnerabilities`: Enables events for running packages with known vulnerabilities.
+//! - `self-protection`: Enables threats for attempts to kill, trace or modify the daemon.
 
 use std::env;
 
@@ -91,17 +102,51 @@ pub fn modules() -> Vec<Box<dyn TaskLauncher>> {
         #[cfg(feature = "logger")]
         logger::module(),
         #[cfg(feature = "rules-engine")]
-        rules_engine::module(),
+        rules_engine::module_with_custom_payloads(custom_payloads()),
         #[cfg(feature = "desktop-notifier")]
         desktop_notifier::module(),
         #[cfg(feature = "smtp-notifier")]
         smtp_notifier::module(),
+        #[cfg(feature = "proc-metrics")]
+        proc_metrics::module(),
+        #[cfg(feature = "threat-response")]
+        threat_response::module(),
+        #[cfg(feature = "exec-allowlist")]
+        exec_allowlist::module(),
+        #[cfg(feature = "dns-exfiltration")]
+        dns_exfiltration::module(),
+        #[cfg(feature = "network-policy")]
+        network_policy::module(),
+        #[cfg(feature = "canary")]
+        canary::module(),
+        #[cfg(feature = "honeypot")]
+        honeypot::module(),
+        #[cfg(feature = "vulnerabilities")]
+        vulnerabilities::module(),
+        #[cfg(feature = "self-protection")]
+        self_protection::pulsar::module(),
     ]
     .into_iter()
     .map(|x| Box::new(x) as Box<dyn TaskLauncher>)
     .collect()
 }
 
+/// Payloads of the modules which can be matched by rules.
+#[cfg(feature = "rules-engine")]
+fn custom_payloads() -> rules_engine::CustomPayloads {
+    use rules_engine::CustomPayloads;
+
+    let registrations: &[fn(CustomPayloads) -> CustomPayloads] = &[
+        #[cfg(feature = "vulnerabilities")]
+        CustomPayloads::register::<vulnerabilities::report::VulnerablePackage>,
+    ];
+    registrations
+        .iter()
+        .fold(CustomPayloads::default(), |payloads, register| {
+            register(payloads)
+        })
+}
+
 /// Init logger. We log from info level and above, hide timestamp
 /// and module path.
 /// If RUST_LOG is set, we assume the user wants to debug something
@@ -122,13 +167,21 @@ pub fn init_logger(override_log_level: log::Level) {
     }
 }
 
+/// Steps to run before starting the async runtime, like sandboxing the daemon.
+pub fn prepare_pulsar_exec(options: &PulsarExecOpts) -> Result<()> {
+    match &options.mode {
+        cli::Mode::PulsarCli(_) => Ok(()),
+        cli::Mode::PulsarDaemon(options) => pulsard::pulsar_daemon_prepare(options),
+    }
+}
+
 /// Main pulsar entrypoint
 pub async fn run_pulsar_exec(
     options: &PulsarExecOpts,
     modules: Vec<Box<dyn TaskLauncher>>,
 ) -> Result<()> {
     match &options.mode {
-        cli::Mode::PulsarCli(options) => pulsar::pulsar_cli_run(options).await,
+        cli::Mode::PulsarCli(options) => pulsar::pulsar_cli_run(options, &modules).await,
         cli::Mode::PulsarDaemon(options) => pulsard::pulsar_daemon_run(options, modules).await,
     }
 }
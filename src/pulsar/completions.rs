@@ -0,0 +1,61 @@
+//! `pulsar completions` prints or installs the shell completion scripts,
+//! generated from the definition of the CLI by the build script, so they
+//! always match the installed executable.
+
+use std::{fmt::Write as _, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use clap_complete::Shell;
+
+use crate::cli::pulsar::Completions;
+
+/// Script generated by the build script, named after the shell conventions.
+macro_rules! generated {
+    ($file:literal) => {
+        include_str!(concat!(env!("OUT_DIR"), "/completions/", $file))
+    };
+}
+
+/// Shells with a system directory for completions, with the script path
+/// relative to the installation root.
+const INSTALL_PATHS: [(Shell, &str); 3] = [
+    (Shell::Bash, "usr/share/bash-completion/completions/pulsar"),
+    (Shell::Zsh, "usr/share/zsh/site-functions/_pulsar"),
+    (
+        Shell::Fish,
+        "usr/share/fish/vendor_completions.d/pulsar.fish",
+    ),
+];
+
+pub fn completions(options: &Completions) -> Result<String> {
+    match options.shell {
+        Some(shell) => script(shell),
+        None => install(&options.root),
+    }
+}
+
+fn script(shell: Shell) -> Result<String> {
+    let script = match shell {
+        Shell::Bash => generated!("pulsar.bash"),
+        Shell::Elvish => generated!("pulsar.elv"),
+        Shell::Fish => generated!("pulsar.fish"),
+        Shell::PowerShell => generated!("_pulsar.ps1"),
+        Shell::Zsh => generated!("_pulsar"),
+        _ => bail!("no completion script for {shell}"),
+    };
+    Ok(script.to_string())
+}
+
+fn install(root: &Path) -> Result<String> {
+    let mut report = String::new();
+    for (shell, path) in INSTALL_PATHS {
+        let path = root.join(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("error creating {}", dir.display()))?;
+        }
+        fs::write(&path, script(shell)?)
+            .with_context(|| format!("error writing {}", path.display()))?;
+        writeln!(report, "Written {}", path.display())?;
+    }
+    Ok(report.trim_end().to_string())
+}
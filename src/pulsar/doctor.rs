@@ -0,0 +1,64 @@
+//! `pulsar doctor` checks the kernel features needed by the probes and
+//! reports the modules that failed, with the verifier log and the likely
+//! missing kernel configuration of the probes that didn't load.
+
+use std::fmt::Write as _;
+
+use anyhow::Result;
+use bpf_common::feature_autodetect::{diagnostics::KernelFeatures, kernel_version::KernelVersion};
+use engine_api::client::EngineApiClient;
+use pulsar_core::pdk::ModuleStatus;
+
+/// Build the report. The kernel checks don't need the daemon, so a client
+/// error is reported instead of returned.
+pub async fn doctor(engine_api_client: Result<EngineApiClient>) -> Result<String> {
+    let mut report = String::new();
+    let check = |ok: bool| if ok { "ok" } else { "missing" };
+
+    match KernelVersion::autodetect() {
+        Ok(version) => writeln!(report, "Kernel version: {version}")?,
+        Err(err) => writeln!(report, "Kernel version: unknown ({err})")?,
+    }
+    let features = KernelFeatures::autodetect();
+    writeln!(
+        report,
+        "BTF (CONFIG_DEBUG_INFO_BTF): {}",
+        check(features.btf)
+    )?;
+    writeln!(
+        report,
+        "eBPF LSM (CONFIG_BPF_LSM, `bpf` in `lsm=`): {}",
+        check(features.bpf_lsm)
+    )?;
+
+    let modules = match engine_api_client {
+        Ok(client) => client.list_modules().await,
+        Err(err) => Err(err),
+    };
+    let mut modules = match modules {
+        Ok(modules) => modules,
+        Err(err) => {
+            write!(report, "Daemon: not reachable ({err})")?;
+            return Ok(report);
+        }
+    };
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    let failed: Vec<_> = modules
+        .iter()
+        .filter_map(|module| match &module.status {
+            ModuleStatus::Failed(err) => Some((&module.name, err)),
+            _ => None,
+        })
+        .collect();
+    if failed.is_empty() {
+        let running = modules
+            .iter()
+            .filter(|module| matches!(module.status, ModuleStatus::Running(_)))
+            .count();
+        write!(report, "Modules: {running} running, none failed")?;
+    }
+    for (name, err) in failed {
+        write!(report, "\nModule {name} failed:\n{err}\n")?;
+    }
+    Ok(report.trim_end().to_string())
+}
@@ -0,0 +1,298 @@
+//! `pulsar export`: self-contained incident file with the events kept in the
+//! history of the daemon.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use engine_api::client::EngineApiClient;
+use pulsar_core::{
+    history::EventFilter,
+    pdk::{Event, Payload},
+};
+use serde_json::json;
+
+use super::term_print::{TermPrintable, TermPrinted};
+use crate::cli::pulsar::{Export, ExportFormat};
+
+pub async fn export(engine_api_client: &EngineApiClient, options: &Export) -> Result<TermPrinted> {
+    let now = SystemTime::now();
+    let filter = EventFilter {
+        pid: options.pid,
+        since: options
+            .since
+            .as_deref()
+            .map(|since| parse_time(since, now))
+            .transpose()?,
+        until: options
+            .until
+            .as_deref()
+            .map(|until| parse_time(until, now))
+            .transpose()?,
+    };
+    let events = engine_api_client.get_events(&filter).await?;
+
+    match &options.output {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("error creating {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            write(&events, &filter, options.format, &mut writer)?;
+            writer.flush()?;
+            format!("Exported {} events to {}", events.len(), path.display()).term_print()
+        }
+        None => {
+            let mut writer = io::stdout().lock();
+            write(&events, &filter, options.format, &mut writer)?;
+            writer.flush()?;
+            Ok(TermPrinted)
+        }
+    }
+}
+
+/// Parse an RFC 3339 time or a duration before `now`, like `90s`, `30m`,
+/// `2h` or `1d`, returning the milliseconds since the Unix epoch.
+fn parse_time(input: &str, now: SystemTime) -> Result<u64> {
+    let time = match DateTime::parse_from_rfc3339(input) {
+        Ok(time) => SystemTime::from(time),
+        Err(_) => {
+            let split = input.len() - input.chars().last().map_or(0, char::len_utf8);
+            let (amount, unit) = input.split_at(split);
+            let Ok(amount) = amount.parse::<u64>() else {
+                bail!("invalid time '{input}': expected RFC 3339 or a duration like 30m");
+            };
+            let seconds = match unit {
+                "s" => amount,
+                "m" => amount * 60,
+                "h" => amount * 3600,
+                "d" => amount * 86400,
+                _ => bail!("invalid duration unit in '{input}': expected s, m, h or d"),
+            };
+            now.checked_sub(Duration::from_secs(seconds))
+                .with_context(|| format!("invalid time '{input}'"))?
+        }
+    };
+    Ok(time.duration_since(UNIX_EPOCH)?.as_millis() as u64)
+}
+
+fn write(
+    events: &[Event],
+    filter: &EventFilter,
+    format: ExportFormat,
+    writer: &mut impl Write,
+) -> Result<()> {
+    match format {
+        ExportFormat::Jsonl => {
+            for event in events {
+                serde_json::to_writer(&mut *writer, event)?;
+                writeln!(writer)?;
+            }
+        }
+        ExportFormat::Har => {
+            serde_json::to_writer_pretty(&mut *writer, &har(events))?;
+            writeln!(writer)?;
+        }
+        ExportFormat::Timeline => timeline(events, filter, writer)?,
+    }
+    Ok(())
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// HTTP Archive with a `CONNECT` entry for every outgoing and accepted
+/// connection, the whole event is kept in the `_event` field.
+fn har(events: &[Event]) -> serde_json::Value {
+    let entries: Vec<_> = events
+        .iter()
+        .filter_map(|event| {
+            let (direction, host, is_tcp) = match event.payload() {
+                Payload::Connect {
+                    destination,
+                    is_tcp,
+                } => ("outbound", destination, *is_tcp),
+                Payload::Accept { source, .. } => ("inbound", source, true),
+                _ => return None,
+            };
+            let scheme = if is_tcp { "tcp" } else { "udp" };
+            Some(json!({
+                "startedDateTime": format_time(event.header().timestamp),
+                "time": 0,
+                "request": {
+                    "method": "CONNECT",
+                    "url": format!("{scheme}://{host}"),
+                    "httpVersion": "",
+                    "cookies": [],
+                    "headers": [],
+                    "queryString": [],
+                    "headersSize": -1,
+                    "bodySize": -1,
+                },
+                "response": {
+                    "status": 0,
+                    "statusText": "",
+                    "httpVersion": "",
+                    "cookies": [],
+                    "headers": [],
+                    "content": { "size": 0, "mimeType": "" },
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": -1,
+                },
+                "cache": {},
+                "timings": { "send": 0, "wait": 0, "receive": 0 },
+                "serverIPAddress": host.ip.to_string(),
+                "_direction": direction,
+                "_process": format!("{} ({})", event.header().image, event.header().pid),
+                "_event": event,
+            }))
+        })
+        .collect();
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "pulsar", "version": crate::version() },
+            "entries": entries,
+        }
+    })
+}
+
+fn timeline(events: &[Event], filter: &EventFilter, writer: &mut impl Write) -> Result<()> {
+    let millis = |millis: u64| format_time(UNIX_EPOCH + Duration::from_millis(millis));
+    writeln!(writer, "# Pulsar incident export")?;
+    writeln!(writer)?;
+    if let Some(event) = events.first() {
+        let host = &event.header().host;
+        let name = host.node_name.as_ref().unwrap_or(&host.hostname);
+        writeln!(writer, "Host: {name} ({})", host.machine_id)?;
+    }
+    if let Some(pid) = filter.pid {
+        writeln!(writer, "Process: {pid} and descendants")?;
+    }
+    if let Some(since) = filter.since {
+        writeln!(writer, "Since: {}", millis(since))?;
+    }
+    if let Some(until) = filter.until {
+        writeln!(writer, "Until: {}", millis(until))?;
+    }
+    writeln!(writer, "Events: {}", events.len())?;
+
+    writeln!(writer, "\n## Process tree\n")?;
+    // Last image and parent seen for every process
+    let mut processes: BTreeMap<i32, (i32, &str)> = BTreeMap::new();
+    for event in events {
+        let header = event.header();
+        processes.insert(header.pid, (header.parent_pid, &header.image));
+    }
+    for (pid, (parent_pid, _)) in &processes {
+        if !processes.contains_key(parent_pid) {
+            print_tree(writer, &processes, *pid, 0)?;
+        }
+    }
+
+    writeln!(writer, "\n## Threats\n")?;
+    for event in events {
+        if let Some(threat) = &event.header().threat {
+            writeln!(
+                writer,
+                "{} {} [{} - {}] {}",
+                line_prefix(event),
+                threat.severity,
+                threat.source,
+                threat.description,
+                event.payload()
+            )?;
+        }
+    }
+
+    writeln!(writer, "\n## Network flows\n")?;
+    for event in events.iter().filter(|event| is_network(event.payload())) {
+        writeln!(writer, "{} {}", line_prefix(event), event.payload())?;
+    }
+
+    writeln!(writer, "\n## Files\n")?;
+    for event in events.iter().filter(|event| is_file(event.payload())) {
+        writeln!(writer, "{} {}", line_prefix(event), event.payload())?;
+    }
+
+    writeln!(writer, "\n## Timeline\n")?;
+    for event in events {
+        writeln!(
+            writer,
+            "{} [{}] {}",
+            line_prefix(event),
+            event.header().source,
+            event.payload()
+        )?;
+    }
+    Ok(())
+}
+
+fn line_prefix(event: &Event) -> String {
+    let header = event.header();
+    format!(
+        "{} {} ({})",
+        format_time(header.timestamp),
+        header.image,
+        header.pid
+    )
+}
+
+fn print_tree(
+    writer: &mut impl Write,
+    processes: &BTreeMap<i32, (i32, &str)>,
+    pid: i32,
+    depth: usize,
+) -> Result<()> {
+    let (_, image) = processes[&pid];
+    writeln!(writer, "{:indent$}{pid} {image}", "", indent = depth * 2)?;
+    for (child, (parent_pid, _)) in processes {
+        if *parent_pid == pid && *child != pid {
+            print_tree(writer, processes, *child, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_network(payload: &Payload) -> bool {
+    matches!(
+        payload,
+        Payload::Bind { .. }
+            | Payload::Listen { .. }
+            | Payload::Connect { .. }
+            | Payload::Accept { .. }
+            | Payload::Close { .. }
+            | Payload::Receive { .. }
+            | Payload::Send { .. }
+            | Payload::DnsQuery { .. }
+            | Payload::DnsResponse { .. }
+            | Payload::StratumRequest { .. }
+            | Payload::IdleConnection { .. }
+            | Payload::FirewallChange { .. }
+    )
+}
+
+fn is_file(payload: &Payload) -> bool {
+    matches!(
+        payload,
+        Payload::FileCreated { .. }
+            | Payload::FileDeleted { .. }
+            | Payload::DirCreated { .. }
+            | Payload::DirDeleted { .. }
+            | Payload::FileOpened { .. }
+            | Payload::FileLink { .. }
+            | Payload::FileRename { .. }
+            | Payload::XattrChanged { .. }
+            | Payload::FileCapabilitiesChanged { .. }
+            | Payload::SysctlChanged { .. }
+            | Payload::FifoCreated { .. }
+            | Payload::PipeTransfer { .. }
+            | Payload::ElfOpened { .. }
+    )
+}
@@ -0,0 +1,200 @@
+//! `pulsar install` writes the files needed to run the daemon as a systemd
+//! service: a hardened unit started with socket activation, the bpffs mount
+//! and a configuration skeleton with the defaults of every module.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use pulsar_core::pdk::TaskLauncher;
+
+use crate::cli::pulsar::Install;
+
+const SYSTEMD_DIR: &str = "etc/systemd/system";
+const PULSAR_DIR: &str = "var/lib/pulsar";
+
+const SOCKET_UNIT: &str = include_str!("../../scripts/systemd/pulsard.socket");
+const BOOT_COMPLETE_UNIT: &str = include_str!("../../scripts/systemd/pulsar-boot-complete.service");
+
+/// Mount the BPF file system, where the probes pin their maps and links.
+const BPFFS_MOUNT_UNIT: &str = "\
+[Unit]
+Description=BPF file system
+DefaultDependencies=no
+ConditionPathIsMountPoint=!/sys/fs/bpf
+Before=sysinit.target
+
+[Mount]
+What=bpffs
+Where=/sys/fs/bpf
+Type=bpf
+Options=rw,nosuid,nodev,noexec,relatime,mode=700
+";
+
+/// Capabilities needed to load the probes, on kernels without CAP_BPF too,
+/// and to run the response actions.
+const CAPABILITIES: &[&str] = &[
+    "CAP_BPF",
+    "CAP_PERFMON",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_PTRACE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FOWNER",
+    "CAP_CHOWN",
+    "CAP_KILL",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+];
+
+struct InstallFile {
+    /// Path relative to the installation root
+    path: PathBuf,
+    mode: u32,
+    content: String,
+}
+
+impl InstallFile {
+    fn new(dir: &str, name: &str, mode: u32, content: String) -> Self {
+        Self {
+            path: Path::new(dir).join(name),
+            mode,
+            content,
+        }
+    }
+}
+
+pub fn install(options: &Install, modules: &[Box<dyn TaskLauncher>]) -> Result<String> {
+    let exec = options.exec.display().to_string();
+    let mut files = vec![
+        InstallFile::new(SYSTEMD_DIR, "pulsard.service", 0o644, service_unit(options)),
+        InstallFile::new(
+            SYSTEMD_DIR,
+            "pulsard.socket",
+            0o644,
+            SOCKET_UNIT.to_string(),
+        ),
+        InstallFile::new(
+            SYSTEMD_DIR,
+            "sys-fs-bpf.mount",
+            0o644,
+            BPFFS_MOUNT_UNIT.to_string(),
+        ),
+        // The configuration can contain credentials, like the SMTP password
+        InstallFile::new(PULSAR_DIR, "pulsar.ini", 0o600, config_skeleton(modules)?),
+    ];
+    if options.early_boot {
+        files.push(InstallFile::new(
+            SYSTEMD_DIR,
+            "pulsar-boot-complete.service",
+            0o644,
+            BOOT_COMPLETE_UNIT.replace("/usr/bin/pulsar-exec", &exec),
+        ));
+    }
+
+    let mut report = String::new();
+    for dir in [SYSTEMD_DIR, PULSAR_DIR, "var/lib/pulsar/rules"] {
+        let dir = options.root.join(dir);
+        fs::create_dir_all(&dir).with_context(|| format!("error creating {}", dir.display()))?;
+    }
+    for file in files {
+        let path = options.root.join(&file.path);
+        if path.exists() && !options.force {
+            writeln!(report, "Kept existing {}", path.display())?;
+            continue;
+        }
+        fs::write(&path, &file.content)
+            .and_then(|_| fs::set_permissions(&path, fs::Permissions::from_mode(file.mode)))
+            .with_context(|| format!("error writing {}", path.display()))?;
+        writeln!(report, "Written {}", path.display())?;
+    }
+    write!(
+        report,
+        "\nStart the daemon with:\n\n  systemctl daemon-reload\n  systemctl enable --now pulsard.socket pulsard.service"
+    )?;
+    Ok(report)
+}
+
+fn service_unit(options: &Install) -> String {
+    let exec = options.exec.display();
+    let (dependencies, exec_args, install) = if options.early_boot {
+        (
+            "# Start as early as possible to monitor the boot\n\
+             DefaultDependencies=no\n\
+             Before=sysinit.target shutdown.target\n\
+             Conflicts=shutdown.target\n",
+            " --early-boot",
+            "WantedBy=sysinit.target\nAlso=pulsard.socket pulsar-boot-complete.service",
+        )
+    } else {
+        ("", "", "WantedBy=multi-user.target\nAlso=pulsard.socket")
+    };
+    format!(
+        "\
+[Unit]
+Description=Pulsar runtime security daemon
+Documentation=https://github.com/Exein-io/pulsar
+{dependencies}Requires=pulsard.socket sys-fs-bpf.mount
+After=pulsard.socket sys-fs-bpf.mount
+RequiresMountsFor=/var/lib/pulsar
+
+[Service]
+Type=notify
+ExecStart={exec} pulsard{exec_args}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+WatchdogSec=60
+# Hardening, see systemd.exec(5)
+CapabilityBoundingSet={capabilities}
+NoNewPrivileges=yes
+ProtectSystem=strict
+ReadWritePaths=/var/lib/pulsar /sys/fs/bpf
+ProtectHome=read-only
+PrivateTmp=yes
+ProtectKernelModules=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+LimitMEMLOCK=infinity
+
+[Install]
+{install}
+",
+        capabilities = CAPABILITIES.join(" "),
+    )
+}
+
+/// Configuration with a section for every module, listing the default values
+/// commented out.
+fn config_skeleton(modules: &[Box<dyn TaskLauncher>]) -> Result<String> {
+    let mut config = String::from(
+        "# Pulsar configuration, generated by `pulsar install`.\n\
+         # Uncomment a setting to override its default value.\n",
+    );
+    for module in modules {
+        let details = module.details();
+        writeln!(config, "\n[{}]", module.name())?;
+        writeln!(config, "# enabled={}", details.enabled_by_default)?;
+        for field in details
+            .config_schema
+            .iter()
+            .flat_map(|schema| &schema.fields)
+        {
+            writeln!(config, "# {}", field.doc)?;
+            writeln!(
+                config,
+                "# {}={}",
+                field.name,
+                field.default.as_deref().unwrap_or_default()
+            )?;
+        }
+    }
+    Ok(config)
+}
@@ -1,27 +1,67 @@
+use std::time::Duration;
+
 use anyhow::{Context, Result};
-use engine_api::client::EngineApiClient;
+use engine_api::{client::EngineApiClient, dto::AcknowledgeRequest};
 use futures_util::StreamExt;
+use pulsar_core::{loadgen::LoadgenConfig, pdk::TaskLauncher};
 
+mod completions;
+mod doctor;
+mod export;
+mod install;
 mod term_print;
 
 use crate::{
-    cli::pulsar::{Commands, Config, ModuleConfigKV, Monitor, PulsarCliOpts},
+    cli::pulsar::{
+        Commands, Config, ListenersFormat, ModuleConfigKV, Monitor, PulsarCliOpts, RulesCommand,
+    },
     pulsar::term_print::TermPrintable,
 };
 
-pub async fn pulsar_cli_run(options: &PulsarCliOpts) -> Result<()> {
+pub async fn pulsar_cli_run(
+    options: &PulsarCliOpts,
+    modules: &[Box<dyn TaskLauncher>],
+) -> Result<()> {
     log::trace!("Pulsar CLI Options: {:?}", options);
 
+    // Runs before the daemon is installed, so it doesn't need the API socket
+    if let Commands::Install(install) = &options.command {
+        install::install(install, modules)?.term_print()?;
+        return Ok(());
+    }
+    if let Commands::Completions(completions) = &options.command {
+        completions::completions(completions)?.term_print()?;
+        return Ok(());
+    }
+    // Logs are usually verified on a copy, away from the host
+    #[cfg(feature = "logger")]
+    if let Commands::VerifyLog(verify) = &options.command {
+        verify_log(verify)?.term_print()?;
+        return Ok(());
+    }
+
     let engine_api_client = if let Some(api_server) = &options.api_server {
-        EngineApiClient::unix(api_server.clone())?
+        EngineApiClient::unix(api_server.clone())
     } else {
-        EngineApiClient::new()?
+        EngineApiClient::new()
     };
 
+    // Checks the kernel even when the daemon is not running
+    if let Commands::Doctor = &options.command {
+        doctor::doctor(engine_api_client).await?.term_print()?;
+        return Ok(());
+    }
+    let engine_api_client = engine_api_client?;
+
     log::trace!("Command received: {:?}", options.command);
 
     match &options.command {
-        Commands::Status => engine_api_client.list_modules().await?.term_print(),
+        Commands::Status { probe_stats: false } => {
+            engine_api_client.list_modules().await?.term_print()
+        }
+        Commands::Status { probe_stats: true } => {
+            engine_api_client.probe_stats().await?.term_print()
+        }
         Commands::Start { module_name } => {
             engine_api_client.start(module_name).await?;
             "Module started".to_string().term_print()
@@ -34,9 +74,14 @@ pub async fn pulsar_cli_run(options: &PulsarCliOpts) -> Result<()> {
             engine_api_client.stop(module_name).await?;
             "Module stopped".to_string().term_print()
         }
-        Commands::Config(Config { all, module, set }) => match (all, module, set) {
-            (true, _, _) => engine_api_client.get_configs().await?.term_print(),
-            (_, Some(module), _) => engine_api_client
+        Commands::Config(Config {
+            all,
+            module,
+            set,
+            schema,
+        }) => match (all, module, set, schema) {
+            (true, _, _, _) => engine_api_client.get_configs().await?.term_print(),
+            (_, Some(module), _, _) => engine_api_client
                 .get_module_config(module)
                 .await?
                 .term_print(),
@@ -48,14 +93,48 @@ pub async fn pulsar_cli_run(options: &PulsarCliOpts) -> Result<()> {
                     key,
                     value,
                 }),
+                _,
             ) => {
                 engine_api_client
                     .set_module_config(module_name, key.clone(), value.clone())
                     .await?;
                 "Configuration updated".to_string().term_print()
             }
+            (_, _, _, true) => engine_api_client
+                .list_modules()
+                .await?
+                .into_iter()
+                .filter_map(|module| module.config_schema.map(|schema| (module.name, schema)))
+                .collect::<Vec<_>>()
+                .term_print(),
             _ => unreachable!(),
         },
+        Commands::Install(_) | Commands::Completions(_) | Commands::Doctor => unreachable!(),
+        #[cfg(feature = "logger")]
+        Commands::VerifyLog(_) => unreachable!(),
+        Commands::Export(options) => export::export(&engine_api_client, options).await,
+        Commands::Listeners { format } => {
+            let listeners = engine_api_client.listeners().await?;
+            match format {
+                ListenersFormat::Table => listeners.term_print(),
+                ListenersFormat::Json => serde_json::to_string_pretty(&listeners)?.term_print(),
+            }
+        }
+        Commands::Loadgen(options) => loadgen(&engine_api_client, options).await,
+        Commands::Threats(options) => threats(&engine_api_client, options).await,
+        Commands::Rules(options) => match options.command {
+            RulesCommand::Stats => engine_api_client.rules_stats().await?.term_print(),
+        },
+        Commands::BootComplete => {
+            engine_api_client.boot_complete().await?;
+            "Boot completed".to_string().term_print()
+        }
+        #[cfg(feature = "threat-response")]
+        Commands::Quarantine(options) => quarantine(&engine_api_client, options).await,
+        #[cfg(feature = "exec-allowlist")]
+        Commands::Baseline(options) => baseline(&engine_api_client, options).await,
+        #[cfg(feature = "network-policy")]
+        Commands::Policy(options) => policy(&engine_api_client, options).await,
         Commands::Monitor(Monitor { all }) => {
             let mut stream = engine_api_client.event_monitor().await?;
 
@@ -76,3 +155,191 @@ pub async fn pulsar_cli_run(options: &PulsarCliOpts) -> Result<()> {
 
     Ok(())
 }
+
+async fn loadgen(
+    engine_api_client: &EngineApiClient,
+    options: &crate::cli::pulsar::Loadgen,
+) -> Result<term_print::TermPrinted> {
+    let config = LoadgenConfig {
+        rate: options.rate,
+        duration: Duration::from_secs(options.duration),
+        exec: options.exec,
+        connect: options.connect,
+        send: options.send,
+    };
+    let report = engine_api_client.loadgen(&config).await?;
+    format!(
+        "Sent {} events in {:.1}s ({}/s)\nLost by lagging modules: {}\nMax events waiting on the bus: {}",
+        report.sent,
+        report.elapsed.as_secs_f64(),
+        report.rate(),
+        report.lost,
+        report.max_pending
+    )
+    .term_print()
+}
+
+async fn threats(
+    engine_api_client: &EngineApiClient,
+    options: &crate::cli::pulsar::Threats,
+) -> Result<term_print::TermPrinted> {
+    use crate::cli::pulsar::ThreatsCommand;
+
+    match &options.command {
+        ThreatsCommand::List => engine_api_client
+            .threat_acknowledgments()
+            .await?
+            .term_print(),
+        ThreatsCommand::Ack { id, ttl, reason } => {
+            engine_api_client
+                .acknowledge_threat(&AcknowledgeRequest {
+                    id: id.clone(),
+                    ttl_secs: *ttl,
+                    reason: reason.clone(),
+                })
+                .await?;
+            format!("Threat {id} acknowledged for {ttl}s").term_print()
+        }
+        ThreatsCommand::Unack { id } => {
+            engine_api_client.unacknowledge_threat(id).await?;
+            format!("Threat {id} no longer acknowledged").term_print()
+        }
+    }
+}
+
+#[cfg(feature = "logger")]
+fn verify_log(options: &crate::cli::pulsar::VerifyLog) -> Result<String> {
+    let file = std::fs::File::open(&options.path)
+        .with_context(|| format!("error opening {}", options.path.display()))?;
+    let head = logger::chain::verify(std::io::BufReader::new(file), options.anchor.as_deref())
+        .with_context(|| format!("{} failed verification", options.path.display()))?;
+    Ok(format!("{} records verified, head {}", head.seq, head.hash))
+}
+
+/// Manage the quarantine locally, in the folder configured in the daemon unless
+/// overridden.
+#[cfg(feature = "threat-response")]
+async fn quarantine(
+    engine_api_client: &EngineApiClient,
+    options: &crate::cli::pulsar::Quarantine,
+) -> Result<term_print::TermPrinted> {
+    use crate::cli::pulsar::QuarantineCommand;
+    use threat_response::quarantine::{Quarantine, DEFAULT_QUARANTINE_PATH};
+
+    let path = match &options.path {
+        Some(path) => path.clone(),
+        None => engine_api_client
+            .get_module_config("threat-response")
+            .await?
+            .into_iter()
+            .find(|cfg| cfg.key == "quarantine_path")
+            .map(|cfg| cfg.value.into())
+            .unwrap_or_else(|| DEFAULT_QUARANTINE_PATH.into()),
+    };
+    let quarantine = Quarantine::new(path);
+
+    match &options.command {
+        QuarantineCommand::List => quarantine.list()?.term_print(),
+        QuarantineCommand::Restore { id } => {
+            let record = quarantine.restore(id)?;
+            format!("Restored {}", record.original_path.display()).term_print()
+        }
+    }
+}
+
+/// Record the allowlist locally, using the daemon configuration for the
+/// options not given.
+#[cfg(feature = "exec-allowlist")]
+async fn baseline(
+    engine_api_client: &EngineApiClient,
+    options: &crate::cli::pulsar::Baseline,
+) -> Result<term_print::TermPrinted> {
+    use std::{fs::File, io::BufWriter, path::PathBuf};
+
+    use crate::cli::pulsar::BaselineCommand;
+    use exec_allowlist::{DEFAULT_ALLOWLIST_PATH, DEFAULT_BASELINE_PATHS};
+
+    let BaselineCommand::Record { output, paths } = &options.command;
+    let config = if output.is_none() || paths.is_empty() {
+        engine_api_client
+            .get_module_config("exec-allowlist")
+            .await?
+    } else {
+        Vec::new()
+    };
+    let config_value = |key: &str| {
+        config
+            .iter()
+            .find(|cfg| cfg.key == key)
+            .map(|cfg| cfg.value.clone())
+    };
+
+    let output = output.clone().unwrap_or_else(|| {
+        config_value("allowlist_path")
+            .unwrap_or_else(|| DEFAULT_ALLOWLIST_PATH.to_string())
+            .into()
+    });
+    let mut paths: Vec<PathBuf> = if paths.is_empty() {
+        config_value("scoped_paths")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.trim().into())
+            .collect()
+    } else {
+        paths.clone()
+    };
+    // Don't walk pseudo filesystems when every path is in scope
+    if paths.is_empty() || paths.iter().any(|path| path.as_os_str() == "/") {
+        paths = DEFAULT_BASELINE_PATHS.iter().map(PathBuf::from).collect();
+    }
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("error creating {}", parent.display()))?;
+    }
+    let mut writer = BufWriter::new(
+        File::create(&output).with_context(|| format!("error creating {}", output.display()))?,
+    );
+    let count = exec_allowlist::allowlist::record(&paths, &mut writer)?;
+    std::io::Write::flush(&mut writer)?;
+    format!("Recorded {count} executables to {}", output.display()).term_print()
+}
+
+/// Generate a policy from the profile learned by the daemon, or the one given.
+#[cfg(feature = "network-policy")]
+async fn policy(
+    engine_api_client: &EngineApiClient,
+    options: &crate::cli::pulsar::Policy,
+) -> Result<term_print::TermPrinted> {
+    use crate::cli::pulsar::{PolicyCommand, PolicyFormat};
+    use network_policy::{
+        generate,
+        profile::{Profile, DEFAULT_PROFILE_PATH},
+    };
+
+    let path = match &options.profile {
+        Some(path) => path.clone(),
+        None => engine_api_client
+            .get_module_config("network-policy")
+            .await?
+            .into_iter()
+            .find(|cfg| cfg.key == "profile_path")
+            .map(|cfg| cfg.value.into())
+            .unwrap_or_else(|| DEFAULT_PROFILE_PATH.into()),
+    };
+    let profile = Profile::load(&path)?.with_context(|| {
+        format!(
+            "no profile in {}, enable the network-policy module to learn the traffic",
+            path.display()
+        )
+    })?;
+
+    let PolicyCommand::Generate { format, name } = &options.command;
+    match format {
+        PolicyFormat::Rules => generate::rules(&profile),
+        PolicyFormat::NetworkPolicy => generate::network_policy(&profile, name),
+        PolicyFormat::Nftables => generate::nftables(&profile),
+    }
+    .term_print()
+}
@@ -1,8 +1,16 @@
+use std::time::SystemTime;
+
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
-use engine_api::dto::{ConfigKV, ModuleConfigKVs};
+use engine_api::dto::{ConfigKV, ModuleConfigKVs, ProbeStats};
 
-use pulsar_core::pdk::{ModuleOverview, ModuleStatus};
+use pulsar_core::{
+    acknowledgments::Acknowledgment,
+    listeners::Listener,
+    pdk::{ConfigKind, ConfigSchema, ModuleOverview, ModuleStatus},
+    rule_stats::RuleProfile,
+};
 
 pub struct TermPrinted;
 
@@ -126,6 +134,263 @@ impl TermPrintable for Vec<ModuleConfigKVs> {
     }
 }
 
+impl TermPrintable for Vec<(String, ConfigSchema)> {
+    fn term_print(&self) -> Result<TermPrinted> {
+        let sorted = {
+            let mut tmp = self.clone();
+            tmp.sort_by(|a, b| a.0.cmp(&b.0));
+            tmp
+        };
+
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("MODULE").add_attribute(Attribute::Bold),
+            Cell::new("KEY").add_attribute(Attribute::Bold),
+            Cell::new("TYPE").add_attribute(Attribute::Bold),
+            Cell::new("DEFAULT").add_attribute(Attribute::Bold),
+            Cell::new("DESCRIPTION").add_attribute(Attribute::Bold),
+        ]);
+
+        for (module, schema) in sorted {
+            for field in schema.fields {
+                let kind = match field.kind {
+                    ConfigKind::Bool => "bool".to_string(),
+                    ConfigKind::Integer => match field.range {
+                        Some((min, max)) => format!("integer [{min}, {max}]"),
+                        None => "integer".to_string(),
+                    },
+                    ConfigKind::String => "string".to_string(),
+                    ConfigKind::Path => "path".to_string(),
+                    ConfigKind::List => "list".to_string(),
+                    ConfigKind::Choice(choices) => choices.join(" | "),
+                };
+
+                table.add_row(vec![
+                    Cell::new(format!("{module} (v{})", schema.version))
+                        .fg(Color::Blue)
+                        .add_attribute(Attribute::Bold),
+                    Cell::new(field.name)
+                        .fg(Color::Cyan)
+                        .add_attribute(Attribute::Bold),
+                    Cell::new(kind),
+                    Cell::new(field.default.unwrap_or_default()),
+                    Cell::new(field.doc),
+                ]);
+            }
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
+impl TermPrintable for ProbeStats {
+    fn term_print(&self) -> Result<TermPrinted> {
+        if !self.enabled {
+            println!(
+                "Run time statistics are disabled, set probe_stats=true in the [pulsar] section"
+            );
+        }
+
+        // Most expensive first
+        let sorted = {
+            let mut tmp = self.programs.clone();
+            tmp.sort_by(|a, b| b.run_time_ns.cmp(&a.run_time_ns));
+            tmp
+        };
+
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("PROBE").add_attribute(Attribute::Bold),
+            Cell::new("PROGRAM").add_attribute(Attribute::Bold),
+            Cell::new("RUNS").add_attribute(Attribute::Bold),
+            Cell::new("TOTAL (ms)").add_attribute(Attribute::Bold),
+            Cell::new("AVERAGE (ns)").add_attribute(Attribute::Bold),
+        ]);
+
+        for stats in sorted {
+            table.add_row(vec![
+                Cell::new(stats.probe)
+                    .fg(Color::Blue)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(stats.program)
+                    .fg(Color::Cyan)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(stats.run_count),
+                Cell::new(stats.run_time_ns / 1_000_000),
+                Cell::new(stats.run_time_ns.checked_div(stats.run_count).unwrap_or(0)),
+            ]);
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
+impl TermPrintable for Vec<RuleProfile> {
+    fn term_print(&self) -> Result<TermPrinted> {
+        if self.is_empty() {
+            println!("No rules loaded, is the rules-engine module running?");
+            return Ok(TermPrinted);
+        }
+
+        // Most expensive first
+        let sorted = {
+            let mut tmp = self.clone();
+            tmp.sort_by(|a, b| b.eval_time_ns.cmp(&a.eval_time_ns));
+            tmp
+        };
+
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("RULE").add_attribute(Attribute::Bold),
+            Cell::new("MODE").add_attribute(Attribute::Bold),
+            Cell::new("EVALUATIONS").add_attribute(Attribute::Bold),
+            Cell::new("MATCHES").add_attribute(Attribute::Bold),
+            Cell::new("MISSES").add_attribute(Attribute::Bold),
+            Cell::new("TOTAL (ms)").add_attribute(Attribute::Bold),
+            Cell::new("AVERAGE (ns)").add_attribute(Attribute::Bold),
+        ]);
+
+        for stats in sorted {
+            table.add_row(vec![
+                Cell::new(stats.name)
+                    .fg(Color::Blue)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(stats.mode),
+                Cell::new(stats.evaluations),
+                Cell::new(stats.matches),
+                Cell::new(stats.evaluations.saturating_sub(stats.matches)),
+                Cell::new(stats.eval_time_ns / 1_000_000),
+                Cell::new(
+                    stats
+                        .eval_time_ns
+                        .checked_div(stats.evaluations)
+                        .unwrap_or(0),
+                ),
+            ]);
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
+impl TermPrintable for Vec<Acknowledgment> {
+    fn term_print(&self) -> Result<TermPrinted> {
+        // Expiring first
+        let sorted = {
+            let mut tmp = self.clone();
+            tmp.sort_by(|a, b| a.until.cmp(&b.until));
+            tmp
+        };
+
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("THREAT").add_attribute(Attribute::Bold),
+            Cell::new("UNTIL").add_attribute(Attribute::Bold),
+            Cell::new("REMAINING (s)").add_attribute(Attribute::Bold),
+            Cell::new("REASON").add_attribute(Attribute::Bold),
+        ]);
+
+        let now = SystemTime::now();
+        for acknowledgment in sorted {
+            let remaining = acknowledgment
+                .until
+                .duration_since(now)
+                .unwrap_or_default()
+                .as_secs();
+            table.add_row(vec![
+                Cell::new(acknowledgment.id)
+                    .fg(Color::Cyan)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(DateTime::<Utc>::from(acknowledgment.until).format("%Y-%m-%dT%TZ")),
+                Cell::new(remaining),
+                Cell::new(acknowledgment.reason.unwrap_or_default()),
+            ]);
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
+impl TermPrintable for Vec<Listener> {
+    fn term_print(&self) -> Result<TermPrinted> {
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("PROTO").add_attribute(Attribute::Bold),
+            Cell::new("ADDRESS").add_attribute(Attribute::Bold),
+            Cell::new("PID").add_attribute(Attribute::Bold),
+            Cell::new("IMAGE").add_attribute(Attribute::Bold),
+            Cell::new("CONTAINER").add_attribute(Attribute::Bold),
+            Cell::new("SINCE").add_attribute(Attribute::Bold),
+        ]);
+
+        for listener in self {
+            let container = listener
+                .container
+                .as_ref()
+                .map(|container| {
+                    format!(
+                        "{} ({})",
+                        container.id.get(..12).unwrap_or(&container.id),
+                        container.runtime
+                    )
+                })
+                .unwrap_or_default();
+            table.add_row(vec![
+                Cell::new(if listener.is_tcp { "tcp" } else { "udp" }),
+                Cell::new(&listener.address)
+                    .fg(Color::Cyan)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(listener.pid),
+                Cell::new(&listener.image),
+                Cell::new(container),
+                Cell::new(DateTime::<Utc>::from(listener.since).format("%Y-%m-%dT%TZ")),
+            ]);
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
+#[cfg(feature = "threat-response")]
+impl TermPrintable for Vec<threat_response::quarantine::QuarantineRecord> {
+    fn term_print(&self) -> Result<TermPrinted> {
+        let mut table = table();
+
+        table.set_header(vec![
+            Cell::new("ID").add_attribute(Attribute::Bold),
+            Cell::new("ORIGINAL PATH").add_attribute(Attribute::Bold),
+            Cell::new("SIZE").add_attribute(Attribute::Bold),
+            Cell::new("QUARANTINED AT").add_attribute(Attribute::Bold),
+            Cell::new("THREAT").add_attribute(Attribute::Bold),
+        ]);
+
+        for record in self {
+            table.add_row(vec![
+                Cell::new(&record.id)
+                    .fg(Color::Cyan)
+                    .add_attribute(Attribute::Bold),
+                Cell::new(record.original_path.display()),
+                Cell::new(record.size),
+                Cell::new(record.quarantined_at.format("%Y-%m-%d %H:%M:%S")),
+                Cell::new(&record.threat),
+            ]);
+        }
+
+        println!("{table}");
+        Ok(TermPrinted)
+    }
+}
+
 fn table() -> Table {
     let mut table = Table::new();
     table.set_content_arrangement(ContentArrangement::Dynamic);
@@ -6,7 +6,7 @@ use std::{
 };
 
 use anyhow::{bail, Context, Result};
-use pulsar_core::pdk::ModuleConfig;
+use pulsar_core::pdk::{secrets::Interpolator, ModuleConfig};
 use tokio::sync::watch;
 
 const DEFAULT_CONFIG_FILE: &str = "/var/lib/pulsar/pulsar.ini";
@@ -14,6 +14,9 @@ const DEFAULT_CONFIG_FILE: &str = "/var/lib/pulsar/pulsar.ini";
 /// Global Pulsar configuration manager. Contains configuration for all the modules.
 ///
 /// It is backed by an `INI` file from which parses the data on its creation.
+/// Values can reference environment variables and secrets, see
+/// [`pulsar_core::pdk::secrets`]: modules receive the resolved values, while
+/// the raw ones are shown to the users.
 #[derive(Debug, Clone)]
 pub struct PulsarConfig {
     inner: Arc<Mutex<PulsarConfigInternal>>,
@@ -23,6 +26,9 @@ pub struct PulsarConfig {
 struct PulsarConfigInternal {
     config_file: PathBuf,
     configs: HashMap<String, watch::Sender<ModuleConfig>>,
+    /// Configurations as written in the file, without resolved secrets
+    raw_configs: HashMap<String, ModuleConfig>,
+    interpolator: Interpolator,
 }
 
 impl PulsarConfig {
@@ -47,7 +53,9 @@ impl PulsarConfig {
     }
 
     fn from_config_file(config_file: PathBuf) -> Result<Self> {
+        let interpolator = Interpolator::default();
         let mut configs: HashMap<String, ModuleConfig> = HashMap::new();
+        let mut raw_configs: HashMap<String, ModuleConfig> = HashMap::new();
 
         let conf = ini::Ini::load_from_file(&config_file)
             .with_context(|| format!("Error loading configuration from {config_file:?}"))?;
@@ -55,9 +63,14 @@ impl PulsarConfig {
         for (section, prop) in &conf {
             if let Some(section) = section {
                 let mod_config = configs.entry(section.to_string()).or_default();
+                let raw_config = raw_configs.entry(section.to_string()).or_default();
                 for (key, value) in prop.iter() {
                     log::debug!("{}.{}={}", section, key, value);
-                    mod_config.insert(key.to_string(), value.to_string());
+                    let resolved = interpolator
+                        .interpolate(value)
+                        .with_context(|| format!("Error resolving {section}.{key}"))?;
+                    mod_config.insert(key.to_string(), resolved);
+                    raw_config.insert(key.to_string(), value.to_string());
                 }
             }
         }
@@ -74,6 +87,8 @@ impl PulsarConfig {
             inner: Arc::new(Mutex::new(PulsarConfigInternal {
                 config_file,
                 configs,
+                raw_configs,
+                interpolator,
             })),
         })
     }
@@ -102,6 +117,33 @@ impl PulsarConfig {
             .map(|watch_sender| watch_sender.borrow().clone())
     }
 
+    /// Get module configuration as written in the file, with unresolved secrets.
+    pub fn get_raw_module_config(&self, module: &str) -> Option<ModuleConfig> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .configs
+            .contains_key(module)
+            .then(|| inner.raw_configs.get(module).cloned().unwrap_or_default())
+    }
+
+    /// Get all configurations as written in the file, with unresolved secrets.
+    pub fn get_raw_configs(&self) -> Vec<(String, ModuleConfig)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .configs
+            .keys()
+            .map(|module| {
+                let config = inner.raw_configs.get(module).cloned().unwrap_or_default();
+                (module.clone(), config)
+            })
+            .collect()
+    }
+
+    /// Resolve the environment variables and secrets referenced by a value.
+    pub fn resolve(&self, value: &str) -> Result<String> {
+        Ok(self.inner.lock().unwrap().interpolator.interpolate(value)?)
+    }
+
     /// Get all configurations. This is intended to be used when a single access is enough.
     pub fn get_configs(&self) -> Vec<(String, ModuleConfig)> {
         self.inner
@@ -116,6 +158,12 @@ impl PulsarConfig {
     /// Update module configuration. It takes a key and value.
     pub fn update_config(&self, module: &str, key: &str, value: &str) -> Result<()> {
         let mut update_ctx = self.inner.lock().unwrap();
+        let resolved = update_ctx.interpolator.interpolate(value)?;
+        update_ctx
+            .raw_configs
+            .entry(module.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
 
         // Get or create the watch sender
         let sender_mod_config = update_ctx
@@ -130,7 +178,7 @@ impl PulsarConfig {
         let mut mod_config = sender_mod_config.borrow().clone();
 
         // Update the temporary variable
-        mod_config.insert(key.to_string(), value.to_string());
+        mod_config.insert(key.to_string(), resolved);
 
         // Send the updated value to the watch channel
         sender_mod_config.send_replace(mod_config);
@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use anyhow::{bail, Result};
 use bpf_common::{
-    feature_autodetect::lsm::lsm_supported,
+    feature_autodetect::{fentry::fentry_supported, lsm::lsm_supported},
+    instance::duplicate_programs,
     program::{BpfContext, BpfLogLevel, Pinning, PERF_PAGES_DEFAULT},
+    storm::StormConfig,
 };
 
 use pulsar_core::{
     bus::Bus,
     pdk::{
-        process_tracker::start_process_tracker, ModuleConfig, ModuleDetails, ModuleOverview,
+        policy::{EscalationPolicy, POLICY_SECTION},
+        process_tracker::ProcessTrackerHandle,
+        startup_order, with_dependents, ModuleConfig, ModuleDetails, ModuleName, ModuleOverview,
         ModuleStatus, PulsarDaemonCommand, PulsarDaemonError, PulsarDaemonHandle, TaskLauncher,
     },
 };
@@ -19,6 +27,22 @@ use crate::pulsard::{config::PulsarConfig, GENERAL_CONFIG};
 
 use super::module_manager::{create_module_manager, ModuleManagerHandle};
 
+/// Modules started only at the end of the boot in early boot mode: they send
+/// events outside of the machine or need the full file system.
+const DEFAULT_DEFERRED_MODULES: [&str; 3] = ["rules-engine", "desktop-notifier", "smtp-notifier"];
+
+/// Time given to the deferred modules to subscribe to the bus before the early
+/// buffer is released.
+const EARLY_BUFFER_GRACE: Duration = Duration::from_secs(5);
+
+/// Time after which the probes left attached by the previous daemon instance,
+/// and not taken over by the started modules, are detached.
+const CHECKPOINT_RELEASE_DELAY: Duration = Duration::from_secs(30);
+
+/// Interval between the checks for copies of our probes loaded by another
+/// process, after the release of the checkpoint.
+const DUPLICATE_PROGRAMS_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Main component of Pulsar framework. It's implemented with the actor pattern and its entrypoint is its [`PulsarDaemonHandle`]
 ///
 /// Contains references to all loaded modules. Each module is wrapped inside a [`super::ModuleManager`] actor to manage its lifecycle.
@@ -29,6 +53,10 @@ use super::module_manager::{create_module_manager, ModuleManagerHandle};
 pub struct PulsarDaemon {
     modules: HashMap<String, (ModuleDetails, ModuleManagerHandle)>,
     config: PulsarConfig,
+    bus: Bus,
+    /// Enabled modules waiting for the boot to complete, in startup order
+    deferred_modules: Vec<String>,
+    boot_completed: AtomicBool,
     rx_cmd: mpsc::Receiver<PulsarDaemonCommand>,
     rx_modules_cmd: mpsc::Receiver<PulsarDaemonCommand>,
     #[cfg(debug_assertions)]
@@ -42,7 +70,10 @@ impl PulsarDaemon {
         bus: Bus,
         modules: Vec<Box<dyn TaskLauncher>>,
         config: PulsarConfig,
+        early_boot: bool,
         rx_cmd: mpsc::Receiver<PulsarDaemonCommand>,
+        process_tracker: ProcessTrackerHandle,
+        pinning: Pinning,
     ) -> anyhow::Result<Self> {
         let (tx_modules_cmd, rx_modules_cmd) = mpsc::channel(8);
 
@@ -54,7 +85,6 @@ impl PulsarDaemon {
         };
 
         let mut m = HashMap::new();
-        let process_tracker = start_process_tracker();
 
         let general_config = config.get_module_config(GENERAL_CONFIG).unwrap_or_default();
         let perf_pages = general_config.with_default("perf_pages", PERF_PAGES_DEFAULT)?;
@@ -80,29 +110,106 @@ impl PulsarDaemon {
                 "'lsm_support' has invalid value {x:?}. The valid values are 'true', 'false' and 'autodetect'"
             ),
         };
-        let bpf_context =
-            BpfContext::new(Pinning::Enabled, perf_pages, bpf_log_level, lsm_supported)?;
+        let fentry_supported = match general_config
+            .with_default("fentry_support", "autodetect".to_string())?
+            .as_str()
+        {
+            "true" => true,
+            "false" => false,
+            "autodetect" => tokio::task::spawn_blocking(fentry_supported)
+                .await
+                .unwrap(),
+            x => anyhow::bail!(
+                "'fentry_support' has invalid value {x:?}. The valid values are 'true', 'false' and 'autodetect'"
+            ),
+        };
+        let mut bpf_context = BpfContext::new(pinning, perf_pages, bpf_log_level, lsm_supported)?
+            .with_fentry_support(fentry_supported);
+        // Opt-in: muting events blinds the detections of the muted process
+        let storm_defaults = StormConfig::default();
+        let storm_threshold = general_config.with_default("storm_threshold", 0)?;
+        if storm_threshold > 0 {
+            bpf_context = bpf_context.with_storm_control(StormConfig {
+                threshold: storm_threshold,
+                mute_duration: Duration::from_secs(
+                    general_config
+                        .with_default("storm_mute_secs", storm_defaults.mute_duration.as_secs())?,
+                ),
+            });
+        }
         #[cfg(debug_assertions)]
         let trace_pipe_handle = bpf_common::trace_pipe::start().await;
 
+        let modules = startup_order(modules)?;
+
+        // The modules depending on a deferred module are deferred too: they
+        // are started after it, in startup order, when the boot is completed.
+        let deferred = if early_boot {
+            let deferred = general_config
+                .get_list_with_default(
+                    "deferred_modules",
+                    DEFAULT_DEFERRED_MODULES.map(String::from).to_vec(),
+                )?
+                .into_iter()
+                .map(ModuleName::from)
+                .collect();
+            with_dependents(&modules, &deferred)
+        } else {
+            HashSet::new()
+        };
+        let mut deferred_modules = Vec::new();
+
+        let config_policy = config.get_watched_module_config(POLICY_SECTION);
+        if let Err(err) = EscalationPolicy::try_from(&*config_policy.borrow()) {
+            bail!("Invalid escalation policy: {err}");
+        }
+
+        let enabled_modules: HashSet<_> = modules
+            .iter()
+            .filter(|module| {
+                let module_config = config.get_module_config(module.name()).unwrap_or_default();
+                is_enabled(&module_config, module.details())
+            })
+            .map(|module| module.name().clone())
+            .collect();
+        for module in modules
+            .iter()
+            .filter(|module| enabled_modules.contains(module.name()))
+        {
+            for dependency in &module.details().dependencies {
+                if !enabled_modules.contains(dependency) {
+                    bail!(
+                        "Module {} depends on {dependency}, which is disabled",
+                        module.name()
+                    );
+                }
+            }
+        }
+
         for task_launcher in modules {
             let module_name = task_launcher.name().to_owned();
             let module_details = task_launcher.details().to_owned();
 
             let config = config.get_watched_module_config(&module_name);
-            let is_enabled = config
-                .borrow()
-                .with_default("enabled", module_details.enabled_by_default)
-                .unwrap_or(false);
+            if let Some(config_schema) = &module_details.config_schema {
+                if let Err(err) = config_schema.validate(&config.borrow()) {
+                    bail!("Invalid configuration for module {module_name}: {err}");
+                }
+            }
+            let is_enabled = enabled_modules.contains(&module_name);
             let module_handle = create_module_manager(
                 bus.clone(),
                 daemon_handle.clone(),
                 process_tracker.clone(),
                 task_launcher,
                 config,
+                config_policy.clone(),
                 bpf_context.clone(),
             );
-            if is_enabled {
+            if is_enabled && deferred.contains(&module_name) {
+                log::info!("Module {module_name} will start after boot");
+                deferred_modules.push(module_name.to_string());
+            } else if is_enabled {
                 log::info!("Starting module {module_name}");
                 module_handle.start().await;
             }
@@ -122,9 +229,43 @@ impl PulsarDaemon {
 
         log::debug!("Daemon started");
 
+        tokio::spawn(async move {
+            tokio::time::sleep(CHECKPOINT_RELEASE_DELAY).await;
+            bpf_context.release_checkpoint();
+            // The programs of the previous instance are released, copies of
+            // ours now belong to a daemon which didn't take the instance lock.
+            // It can start at any time, each new copy is reported once.
+            let mut interval = tokio::time::interval(DUPLICATE_PROGRAMS_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut reported: Vec<String> = Vec::new();
+            loop {
+                interval.tick().await;
+                match duplicate_programs() {
+                    Ok(programs) => {
+                        let new: Vec<_> = programs
+                            .iter()
+                            .filter(|program| !reported.contains(program))
+                            .cloned()
+                            .collect();
+                        if !new.is_empty() {
+                            log::error!(
+                                "Probes {} are also loaded by another process: events may be reported twice",
+                                new.join(", ")
+                            );
+                        }
+                        reported = programs;
+                    }
+                    Err(err) => log::warn!("Error listing the loaded eBPF programs: {err}"),
+                }
+            }
+        });
+
         Ok(Self {
             modules: m,
             config,
+            bus,
+            deferred_modules,
+            boot_completed: AtomicBool::new(!early_boot),
             rx_cmd,
             rx_modules_cmd,
             #[cfg(debug_assertions)]
@@ -181,6 +322,10 @@ impl PulsarDaemon {
             PulsarDaemonCommand::Configs { tx_reply } => {
                 let _ = tx_reply.send(self.get_configs());
             }
+            PulsarDaemonCommand::BootComplete { tx_reply } => {
+                self.boot_complete().await;
+                let _ = tx_reply.send(());
+            }
         }
     }
 
@@ -198,13 +343,26 @@ impl PulsarDaemon {
         Ok(module_handle.status().await)
     }
 
-    /// Start a module.
+    /// Start a module. All its dependencies must be running.
     async fn start(&self, module_name: &str) -> Result<(), PulsarDaemonError> {
-        let (_, module_handle) = self
+        let (module_details, module_handle) = self
             .modules
             .get(module_name)
             .ok_or_else(|| PulsarDaemonError::ModuleNotFound(module_name.to_string()))?;
 
+        for dependency in &module_details.dependencies {
+            let running = match self.modules.get(&**dependency) {
+                Some((_, handle)) => matches!(handle.status().await, ModuleStatus::Running(_)),
+                None => false,
+            };
+            if !running {
+                return Err(PulsarDaemonError::DependencyNotRunning {
+                    module: module_name.to_string(),
+                    dependency: dependency.to_string(),
+                });
+            }
+        }
+
         #[allow(clippy::unit_arg)]
         Ok(module_handle.start().await)
     }
@@ -237,6 +395,26 @@ impl PulsarDaemon {
             .map_err(PulsarDaemonError::StopError)
     }
 
+    /// Start the modules deferred in early boot mode and release the events
+    /// buffered for them. Nothing is done if the boot was already completed.
+    async fn boot_complete(&self) {
+        if self.boot_completed.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        log::info!("Boot completed");
+        for module_name in &self.deferred_modules {
+            log::info!("Starting module {module_name}");
+            if let Err(err) = self.start(module_name).await {
+                log::error!("Error starting module {module_name}: {err}");
+            }
+        }
+        let bus = self.bus.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(EARLY_BUFFER_GRACE).await;
+            bus.release_early_buffer();
+        });
+    }
+
     /// Get loaded module list.
     async fn modules(&self) -> Vec<ModuleOverview> {
         let mut v = Vec::new();
@@ -245,27 +423,31 @@ impl PulsarDaemon {
                 name: name.clone(),
                 version: details.version.clone(),
                 status: handle.status().await,
+                config_schema: details.config_schema.clone(),
+                uses_ebpf: details.uses_ebpf,
             })
         }
         v
     }
 
-    /// Get module configuration.
+    /// Get module configuration, with unresolved secrets.
     fn get_module_config(&self, module_name: &str) -> Result<ModuleConfig, PulsarDaemonError> {
         if !self.contains_module(module_name) {
             return Err(PulsarDaemonError::ModuleNotFound(module_name.to_string()));
         }
 
-        self.config.get_module_config(module_name).ok_or_else(|| {
-            log::error!("Module found in task manager but configuration not found");
+        self.config
+            .get_raw_module_config(module_name)
+            .ok_or_else(|| {
+                log::error!("Module found in task manager but configuration not found");
 
-            PulsarDaemonError::ModuleNotFound(module_name.to_string())
-        })
+                PulsarDaemonError::ModuleNotFound(module_name.to_string())
+            })
     }
 
-    /// Get all configurations.
+    /// Get all configurations, with unresolved secrets.
     fn get_configs(&self) -> Vec<(String, ModuleConfig)> {
-        self.config.get_configs()
+        self.config.get_raw_configs()
     }
 
     /// Update module configuration. It takes a key and value.
@@ -275,29 +457,78 @@ impl PulsarDaemon {
         key: &str,
         value: &str,
     ) -> Result<(), PulsarDaemonError> {
-        if !self.contains_module(module_name) {
+        // The escalation policy is reloaded by the receivers of the outputs
+        if module_name == POLICY_SECTION {
+            let mut new_config = self
+                .config
+                .get_module_config(module_name)
+                .unwrap_or_default();
+            new_config.insert(key.to_string(), self.config.resolve(value)?);
+            EscalationPolicy::try_from(&new_config)
+                .map_err(PulsarDaemonError::InvalidConfiguration)?;
+        } else if !self.contains_module(module_name) {
             return Err(PulsarDaemonError::ModuleNotFound(module_name.to_string()));
         }
 
+        let config_schema = self
+            .modules
+            .get(module_name)
+            .and_then(|(details, _)| details.config_schema.as_ref());
+        if let Some(config_schema) = config_schema {
+            let mut new_config = self
+                .config
+                .get_module_config(module_name)
+                .unwrap_or_default();
+            new_config.insert(key.to_string(), self.config.resolve(value)?);
+            config_schema
+                .validate(&new_config)
+                .map_err(PulsarDaemonError::InvalidConfiguration)?;
+        }
+
         self.config
             .update_config(module_name, key, value)
             .map_err(PulsarDaemonError::ConfigurationUpdateError)
     }
 }
 
+/// Check if the module is enabled in its configuration.
+fn is_enabled(config: &ModuleConfig, details: &ModuleDetails) -> bool {
+    config
+        .with_default("enabled", details.enabled_by_default)
+        .unwrap_or(false)
+}
+
 /// Create and start a [`PulsarDaemon`] actor to manage the underlying Pulsar modules.
 ///
 /// Returns the [`PulsarDaemonHandle`] that can be used to interact with the [`PulsarDaemon`] actor.
+///
+/// In `early_boot` mode the deferred modules are started only when the boot is
+/// completed, see [`PulsarDaemonHandle::boot_complete`].
+///
+/// Without `pinning`, the maps and links of the probes are not shared with
+/// other daemon instances.
 pub async fn start_daemon(
     bus: Bus,
     modules: Vec<Box<dyn TaskLauncher>>,
     config: PulsarConfig,
+    early_boot: bool,
+    process_tracker: ProcessTrackerHandle,
+    pinning: Pinning,
 ) -> anyhow::Result<PulsarDaemonHandle> {
     let (tx_cmd, rx_cmd) = mpsc::channel(8);
 
     let daemon_handle = PulsarDaemonHandle { tx_cmd };
 
-    let daemon = PulsarDaemon::new(bus, modules, config, rx_cmd).await?;
+    let daemon = PulsarDaemon::new(
+        bus,
+        modules,
+        config,
+        early_boot,
+        rx_cmd,
+        process_tracker,
+        pinning,
+    )
+    .await?;
 
     tokio::spawn(run_daemon_actor(daemon));
 
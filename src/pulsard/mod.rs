@@ -1,8 +1,23 @@
+use std::time::{Duration, Instant};
+
 use anyhow::{ensure, Result};
-use bpf_common::bpf_fs;
+use bpf_common::{bpf_fs, instance::InstanceLock, program::Pinning, program_stats};
 use engine_api::server::{self, EngineAPIContext};
 use nix::unistd::geteuid;
-use pulsar_core::{bus::Bus, pdk::TaskLauncher};
+use pulsar_core::{
+    bus::Bus,
+    coalesce::CoalesceConfig,
+    heartbeat,
+    history::EventHistory,
+    host::{init_host_info, HostInfo},
+    inventory,
+    listeners::ListenerTable,
+    middleware::{MiddlewareConfig, SandboxedRuntime},
+    output::OutputConfig,
+    pdk::{process_tracker::start_process_tracker, tls, TaskLauncher},
+    shutdown, visibility,
+    watchdog::{self, Notifier},
+};
 use tokio::signal::unix::{signal, SignalKind};
 
 use crate::cli::pulsard::PulsarDaemonOpts;
@@ -10,6 +25,8 @@ use crate::cli::pulsard::PulsarDaemonOpts;
 mod config;
 mod daemon;
 mod module_manager;
+mod replay;
+mod sandbox;
 
 use daemon::start_daemon;
 
@@ -20,61 +37,251 @@ pub use module_manager::{ModuleManager, ModuleManagerHandle};
 /// General configuration section for settings shared by all modules.
 const GENERAL_CONFIG: &str = "pulsar";
 
+/// Default number of events kept for the deferred modules in early boot mode.
+const DEFAULT_EARLY_BUFFER_SIZE: usize = 100_000;
+
+/// Default seconds after which the boot is considered completed in early boot
+/// mode, if not notified before.
+const DEFAULT_EARLY_BOOT_TIMEOUT: u64 = 300;
+
+/// Default number of events kept for `pulsar export`.
+const DEFAULT_HISTORY_SIZE: usize = 50_000;
+
+/// Default seconds between heartbeat events.
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 60;
+
+/// Default seconds between host inventory events.
+const DEFAULT_INVENTORY_INTERVAL: u64 = 24 * 60 * 60;
+
+/// Default seconds given to the modules to process the queued events on exit.
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+
+/// Default seconds after which a bus with no send completing is considered
+/// deadlocked, triggering the systemd watchdog.
+const DEFAULT_BUS_STALL_TIMEOUT: u64 = 30;
+
+/// Time given to the modules to reload their certificates on `SIGHUP`,
+/// before the reload is reported as completed anyway.
+const TLS_RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Steps to run before starting the async runtime: the Landlock sandbox only
+/// applies to the threads spawned after it's enforced.
+pub fn pulsar_daemon_prepare(options: &PulsarDaemonOpts) -> Result<()> {
+    // Replaying events doesn't load probes and needs no privileges
+    if options.replay.is_some() {
+        return Ok(());
+    }
+
+    ensure!(geteuid().is_root(), "You must run this as root user!!!");
+
+    // The sandbox doesn't allow mounting file systems
+    bpf_fs::check_or_mount_bpf_fs()?;
+
+    let config = load_config(options)?;
+    let general_config = config.get_module_config(GENERAL_CONFIG).unwrap_or_default();
+    sandbox::restrict_filesystem(&sandbox::SandboxConfig::try_from(&general_config)?)
+}
+
+fn load_config(options: &PulsarDaemonOpts) -> Result<PulsarConfig> {
+    if let Some(custom_file) = &options.config_file {
+        PulsarConfig::with_custom_file(custom_file)
+    } else {
+        PulsarConfig::new()
+    }
+}
+
 pub async fn pulsar_daemon_run(
     options: &PulsarDaemonOpts,
     modules: Vec<Box<dyn TaskLauncher>>,
 ) -> Result<()> {
     log::trace!("Pulsar Daemon Options: {:?}", options);
+    let started = Instant::now();
+
+    if let Some(replay_file) = &options.replay {
+        return replay::run_replay(load_config(options)?, modules, replay_file).await;
+    }
 
     ensure!(geteuid().is_root(), "You must run this as root user!!!");
 
     bpf_fs::check_or_mount_bpf_fs()?;
 
+    // Held until exit: a second instance would report every event twice
+    let _instance_lock = InstanceLock::acquire()?;
+
     bpf_common::bump_memlock_rlimit()?;
 
-    let config = if let Some(custom_file) = &options.config_file {
-        PulsarConfig::with_custom_file(custom_file)?
+    // Messages to systemd, dropped when not started with Type=notify
+    let notifier = Notifier::from_env();
+
+    let config = load_config(options)?;
+
+    let general_config = config.get_module_config(GENERAL_CONFIG).unwrap_or_default();
+
+    // Must be set before the first event is sent
+    let node_name = general_config.get_raw("node_name").map(String::from);
+    let host_info = HostInfo::detect(node_name, crate::version());
+    log::info!(
+        "Host {} ({})",
+        host_info.node_name.as_ref().unwrap_or(&host_info.hostname),
+        host_info.machine_id
+    );
+    if init_host_info(host_info).is_err() {
+        log::warn!("Host identity already set, ignoring node_name");
+    }
+
+    // Initialize bus. In early boot mode, events are buffered until the
+    // deferred modules are started.
+    let bus = if options.early_boot {
+        Bus::with_early_buffer(
+            general_config.with_default("early_buffer_size", DEFAULT_EARLY_BUFFER_SIZE)?,
+        )
+    } else {
+        Bus::new()
+    }
+    .with_middleware(SandboxedRuntime);
+    let bus = MiddlewareConfig::try_from(&general_config)?
+        .register(bus)
+        .with_coalescing(CoalesceConfig::try_from(&general_config)?);
+    let bus = OutputConfig::try_from(&general_config)?.register(bus);
+
+    // Subscribe before the modules start, to keep their first events
+    let history =
+        EventHistory::new(general_config.with_default("history_size", DEFAULT_HISTORY_SIZE)?);
+    history.record_bus(&bus);
+    let listeners = ListenerTable::new();
+    listeners.record_bus(&bus);
+    visibility::start(bus.clone());
+
+    // The kernel collects the run time of the probes while the returned file
+    // descriptor is open, it's kept until exit.
+    let _probe_stats = if general_config.with_default("probe_stats", false)? {
+        match program_stats::enable_stats() {
+            Ok(fd) => Some(fd),
+            Err(err) => {
+                log::warn!("Error enabling the run time statistics of the probes: {err}");
+                None
+            }
+        }
     } else {
-        PulsarConfig::new()?
+        None
     };
 
-    // Initialize bus
-    let bus = Bus::new();
+    let pulsar_daemon = start_daemon(
+        bus.clone(),
+        modules,
+        config.clone(),
+        options.early_boot,
+        start_process_tracker(),
+        Pinning::Enabled,
+    )
+    .await?;
 
-    let pulsar_daemon = start_daemon(bus.clone(), modules, config.clone()).await?;
+    if options.early_boot {
+        let timeout =
+            general_config.with_default("early_boot_timeout", DEFAULT_EARLY_BOOT_TIMEOUT)?;
+        let pulsar_daemon = pulsar_daemon.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout)).await;
+            pulsar_daemon.boot_complete().await;
+        });
+    }
+
+    let heartbeat_interval =
+        general_config.with_default("heartbeat_interval", DEFAULT_HEARTBEAT_INTERVAL)?;
+    if heartbeat_interval > 0 {
+        heartbeat::start(
+            bus.clone(),
+            pulsar_daemon.clone(),
+            Duration::from_secs(heartbeat_interval),
+        );
+    }
+
+    inventory::start(
+        bus.clone(),
+        pulsar_daemon.clone(),
+        Duration::from_secs(
+            general_config.with_default("inventory_interval", DEFAULT_INVENTORY_INTERVAL)?,
+        ),
+    );
 
     let server_handle = {
         let pulsar_daemon = pulsar_daemon.clone();
 
-        let general_config = config.get_module_config(GENERAL_CONFIG).unwrap_or_default();
         let custom_socket_path = general_config.get_raw("api_socket_path");
 
-        server::run_api_server(EngineAPIContext { bus, pulsar_daemon }, custom_socket_path)?
+        server::run_api_server(
+            EngineAPIContext {
+                bus: bus.clone(),
+                pulsar_daemon,
+                history,
+                listeners,
+            },
+            custom_socket_path,
+        )?
     };
 
+    // The probes are attached and the API socket is bound
+    sandbox::restrict_syscalls(&sandbox::SandboxConfig::try_from(&general_config)?)?;
+
+    notifier.ready();
+    if let Some(interval) = watchdog::watchdog_interval() {
+        let stall_timeout =
+            general_config.with_default("bus_stall_timeout", DEFAULT_BUS_STALL_TIMEOUT)?;
+        watchdog::start(
+            notifier.clone(),
+            interval,
+            Duration::from_secs(stall_timeout),
+        );
+    }
+
     let mut sig_int = signal(SignalKind::interrupt())?;
     let mut sig_term = signal(SignalKind::terminate())?;
     let mut sig_hup = signal(SignalKind::hangup())?;
+    let mut sig_usr1 = signal(SignalKind::user_defined1())?;
 
-    tokio::select! {
-        _ = sig_int.recv() => log::trace!("SIGINT received"),
-        _ = sig_term.recv() => log::trace!("SIGTERM received"),
-        _ = sig_hup.recv() => log::trace!("SIGHUP received"),
+    loop {
+        tokio::select! {
+            _ = sig_int.recv() => log::trace!("SIGINT received"),
+            _ = sig_term.recv() => log::trace!("SIGTERM received"),
+            _ = sig_hup.recv() => {
+                log::info!("SIGHUP received, reloading TLS certificates");
+                notifier.reloading();
+                let notifier = notifier.clone();
+                tokio::spawn(async move {
+                    if tokio::time::timeout(TLS_RELOAD_TIMEOUT, tls::request_reload())
+                        .await
+                        .is_err()
+                    {
+                        log::warn!("TLS certificates not reloaded by every module in time");
+                    }
+                    notifier.ready();
+                });
+                continue;
+            }
+            _ = sig_usr1.recv() => {
+                // Used on upgrades: keep the probes attached until the new
+                // daemon instance takes them over.
+                log::info!("SIGUSR1 received, exiting with probes attached");
+                bpf_common::program::request_checkpoint();
+            }
+        }
+        break;
     }
+    notifier.stopping();
 
     log::info!("Terminating the Engine Api Server...");
     server_handle.stop().await;
 
     log::info!("Terminating Pulsar Daemon...");
-    for module in pulsar_daemon.modules().await {
-        if let Err(err) = pulsar_daemon.stop(module.name.clone()).await {
-            log::warn!(
-                "Module {} didn't respond to shutdown signal. Forcing shutdown.\n{:?}",
-                module.name,
-                err
-            )
-        }
-    }
+    let shutdown_timeout =
+        general_config.with_default("shutdown_timeout", DEFAULT_SHUTDOWN_TIMEOUT)?;
+    shutdown::shutdown(
+        &bus,
+        &pulsar_daemon,
+        started,
+        Duration::from_secs(shutdown_timeout),
+    )
+    .await;
 
     Ok(())
 }
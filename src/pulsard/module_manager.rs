@@ -37,6 +37,7 @@ pub struct ModuleManager {
     bus: Bus,
     task_launcher: Box<dyn TaskLauncher>,
     config: watch::Receiver<ModuleConfig>,
+    policy: watch::Receiver<ModuleConfig>,
     status: ModuleStatus,
     running_task: Option<(ShutdownSender, JoinHandle<()>)>,
     bpf_context: BpfContext,
@@ -44,11 +45,13 @@ pub struct ModuleManager {
 
 impl ModuleManager {
     /// Construct a new [`ModuleManager`].
+    #[allow(clippy::too_many_arguments)]
     fn new(
         rx_cmd: mpsc::Receiver<ModuleManagerCommand>,
         task_launcher: Box<dyn TaskLauncher>,
         bus: Bus,
         config: watch::Receiver<ModuleConfig>,
+        policy: watch::Receiver<ModuleConfig>,
         daemon_handle: PulsarDaemonHandle,
         process_tracker: ProcessTrackerHandle,
         bpf_context: BpfContext,
@@ -61,6 +64,7 @@ impl ModuleManager {
             task_launcher,
             bus,
             config,
+            policy,
             status: ModuleStatus::Created,
             running_task: None,
             daemon_handle,
@@ -130,6 +134,7 @@ impl ModuleManager {
                     self.daemon_handle.clone(),
                     self.process_tracker.clone(),
                     self.bpf_context.clone(),
+                    self.policy.clone(),
                 );
                 let (tx_shutdown, rx_shutdown) = ShutdownSignal::new();
 
@@ -279,6 +284,7 @@ pub fn create_module_manager(
     process_tracker: ProcessTrackerHandle,
     task_launcher: Box<dyn TaskLauncher>,
     config: watch::Receiver<ModuleConfig>,
+    policy: watch::Receiver<ModuleConfig>,
     bpf_context: BpfContext,
 ) -> ModuleManagerHandle {
     // Create command channel used in the ModuleManagerHandle to send commands to the running ModuleManager actor
@@ -290,6 +296,7 @@ pub fn create_module_manager(
         task_launcher,
         bus,
         config,
+        policy,
         daemon_handle,
         process_tracker,
         bpf_context,
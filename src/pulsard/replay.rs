@@ -0,0 +1,75 @@
+//! `pulsard --replay`: run the modules on captured events instead of the eBPF
+//! probes, to test rules and outputs in CI or while writing rules.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use bpf_common::program::Pinning;
+use pulsar_core::{
+    bus::Bus,
+    pdk::{process_tracker::start_process_tracker, ModuleName, TaskLauncher},
+    replay::replay,
+    shutdown,
+};
+use tokio::{fs::File, io::BufReader};
+
+use super::{daemon::start_daemon, PulsarConfig, DEFAULT_SHUTDOWN_TIMEOUT};
+
+pub async fn run_replay(
+    config: PulsarConfig,
+    modules: Vec<Box<dyn TaskLauncher>>,
+    path: &Path,
+) -> Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("error opening {}", path.display()))?;
+
+    let bus = Bus::new();
+    let process_tracker = start_process_tracker();
+    let pulsar_daemon = start_daemon(
+        bus.clone(),
+        replayable(modules),
+        config,
+        false,
+        process_tracker.clone(),
+        // Don't touch the probes of a running daemon
+        Pinning::Disabled,
+    )
+    .await?;
+
+    let count = replay(&bus, &process_tracker, BufReader::new(file))
+        .await
+        .with_context(|| format!("error replaying {}", path.display()))?;
+    log::info!("Replayed {count} events from {}", path.display());
+
+    // The threats of the last events are still queued
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT);
+    shutdown::drain(&bus, deadline).await;
+    shutdown::stop_modules(&pulsar_daemon, &pulsar_daemon.modules().await).await;
+    Ok(())
+}
+
+/// Remove the modules loading eBPF probes or acting on the host, like
+/// threat-response, and the ones depending on them.
+fn replayable(mut modules: Vec<Box<dyn TaskLauncher>>) -> Vec<Box<dyn TaskLauncher>> {
+    let mut skipped: HashSet<ModuleName> = HashSet::new();
+    loop {
+        let (kept, removed): (Vec<_>, Vec<_>) = modules.into_iter().partition(|module| {
+            let details = module.details();
+            !details.uses_ebpf
+                && !details.acts_on_host
+                && details
+                    .dependencies
+                    .iter()
+                    .all(|dependency| !skipped.contains(dependency))
+        });
+        if removed.is_empty() {
+            return kept;
+        }
+        for module in removed {
+            log::info!("Module {} is not started when replaying", module.name());
+            skipped.insert(module.name().clone());
+        }
+        modules = kept;
+    }
+}
@@ -0,0 +1,337 @@
+//! Self-sandboxing of the daemon.
+//!
+//! A Landlock ruleset limits the paths pulsard can modify. Landlock applies to
+//! the calling thread and the threads it spawns afterwards, so it's enforced
+//! before the async runtime is started, see [`super::pulsar_daemon_prepare`].
+//!
+//! Once the probes are attached, a seccomp filter denies the system calls
+//! which are never used by the daemon, like loading kernel modules or tracing
+//! other processes. The filter is synchronized to all the threads.
+
+use std::{
+    ffi::CString,
+    io,
+    mem::size_of,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use pulsar_core::pdk::ModuleConfig;
+
+/// Paths where the daemon can create, modify and remove files.
+const DEFAULT_WRITABLE_PATHS: [&str; 7] = [
+    "/var/lib/pulsar",
+    "/sys/fs/bpf",
+    "/sys/kernel/tracing",
+    "/sys/kernel/debug/tracing",
+    "/run",
+    "/tmp",
+    "/dev/null",
+];
+
+pub(crate) struct SandboxConfig {
+    enabled: bool,
+    writable_paths: Vec<PathBuf>,
+    allow_exec: bool,
+}
+
+impl TryFrom<&ModuleConfig> for SandboxConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &ModuleConfig) -> Result<Self> {
+        Ok(Self {
+            enabled: config.with_default("sandbox", true)?,
+            writable_paths: config.get_list_with_default(
+                "sandbox_writable_paths",
+                DEFAULT_WRITABLE_PATHS.map(PathBuf::from).to_vec(),
+            )?,
+            // Notifiers and response actions run external commands
+            allow_exec: config.with_default("sandbox_allow_exec", true)?,
+        })
+    }
+}
+
+// Landlock file system access rights, see linux/landlock.h
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+/// Rights of the first Landlock ABI, from `EXECUTE` to `MAKE_SYM`
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+/// Rights which apply to files, the others only to directories
+const ACCESS_FILE: u64 =
+    ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+// Landlock syscalls have the same number on all architectures
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Allow reading and executing every file, but modifying only the files under
+/// `writable_paths`.
+pub(crate) fn restrict_filesystem(config: &SandboxConfig) -> Result<()> {
+    if !config.enabled {
+        log::warn!("Sandbox disabled");
+        return Ok(());
+    }
+    let abi = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        log::warn!(
+            "Landlock not supported, file system access is not restricted: {}",
+            io::Error::last_os_error()
+        );
+        return Ok(());
+    }
+    let mut handled = ACCESS_FS_V1;
+    if abi >= 2 {
+        // Without this right moving files between directories fails with
+        // EXDEV, which the quarantine handles by copying them.
+        handled |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            &attr as *const RulesetAttr,
+            size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error()).context("error creating Landlock ruleset");
+    }
+    let ruleset = ruleset as libc::c_int;
+    let result = add_rules(ruleset, handled, config).and_then(|_| {
+        set_no_new_privs()?;
+        if unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset, 0) } < 0 {
+            return Err(io::Error::last_os_error()).context("error enforcing Landlock ruleset");
+        }
+        Ok(())
+    });
+    unsafe { libc::close(ruleset) };
+    result?;
+    log::info!("File system access restricted with Landlock ABI v{abi}");
+    Ok(())
+}
+
+fn add_rules(ruleset: libc::c_int, handled: u64, config: &SandboxConfig) -> Result<()> {
+    // The quarantine removes files from anywhere
+    let read_only = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+    add_rule(ruleset, Path::new("/"), read_only | ACCESS_FS_REMOVE_FILE)?;
+    for path in &config.writable_paths {
+        add_rule(ruleset, path, handled)?;
+    }
+    Ok(())
+}
+
+fn add_rule(ruleset: libc::c_int, path: &Path, mut access: u64) -> Result<()> {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        log::debug!("Skipping missing sandbox path {}", path.display());
+        return Ok(());
+    };
+    if !metadata.is_dir() {
+        access &= ACCESS_FILE;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("error opening {}", path.display()));
+    }
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    let error = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if ret < 0 {
+        return Err(error).with_context(|| format!("error adding {} to sandbox", path.display()));
+    }
+    Ok(())
+}
+
+fn set_no_new_privs() -> Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error()).context("error setting no_new_privs");
+    }
+    Ok(())
+}
+
+// Seccomp filter, see linux/seccomp.h and linux/filter.h
+const SECCOMP_SET_MODE_FILTER: libc::c_ulong = 1;
+const SECCOMP_FILTER_FLAG_TSYNC: libc::c_ulong = 1;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+/// Offsets of `nr` and `arch` in `struct seccomp_data`
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: u32 = 0x4000_0003;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: u32 = 0x4000_0028;
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: u32 = 0xc000_00f3;
+
+/// System calls of the new mount API, with the same number on all architectures
+const MOUNT_API_SYSCALLS: [libc::c_long; 5] = [428, 429, 430, 431, 432];
+
+/// System calls never used by the daemon once the probes are attached.
+fn denied_syscalls(config: &SandboxConfig) -> Vec<libc::c_long> {
+    let mut syscalls = vec![
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_writev,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_acct,
+        libc::SYS_userfaultfd,
+        libc::SYS_personality,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_keyctl,
+        libc::SYS_open_by_handle_at,
+    ];
+    syscalls.extend(MOUNT_API_SYSCALLS);
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    syscalls.extend([libc::SYS_iopl, libc::SYS_ioperm]);
+    if !config.allow_exec {
+        syscalls.extend([libc::SYS_execve, libc::SYS_execveat]);
+    }
+    syscalls
+}
+
+#[repr(C)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+/// Deny the system calls returned by [`denied_syscalls`] in all the threads.
+pub(crate) fn restrict_syscalls(config: &SandboxConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let denied = denied_syscalls(config);
+    let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let mut filter = vec![
+        // System calls of other ABIs, like 32 bit ones, are never used
+        stmt(BPF_LD_W_ABS, DATA_ARCH),
+        jump(BPF_JEQ_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET_K, deny),
+        stmt(BPF_LD_W_ABS, DATA_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    {
+        // x32 system calls share the x86_64 architecture
+        const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+        filter.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1));
+        filter.push(stmt(BPF_RET_K, deny));
+    }
+    for (index, nr) in denied.iter().enumerate() {
+        // Jump to the deny statement after the last comparison
+        let to_deny = (denied.len() - index) as u8;
+        filter.push(jump(BPF_JEQ_K, *nr as u32, to_deny, 0));
+    }
+    filter.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+    filter.push(stmt(BPF_RET_K, deny));
+
+    let prog = SockFprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+    set_no_new_privs()?;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const SockFprog,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error()).context("error installing seccomp filter");
+    }
+    if ret > 0 {
+        bail!("error installing seccomp filter: thread {ret} can't be synchronized");
+    }
+    log::info!("{} system calls denied with seccomp", denied.len());
+    Ok(())
+}
@@ -10,5 +10,6 @@ pub fn modules() -> Vec<bpf_common::test_runner::TestSuite> {
         file_system_monitor::test_suite::tests(),
         network_monitor::test_suite::tests(),
         process_monitor::test_suite::tests(),
+        self_protection::test_suite::tests(),
     ]
 }